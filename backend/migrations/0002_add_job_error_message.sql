@@ -0,0 +1,3 @@
+-- Store job failure reasons in a dedicated column instead of parameters.error
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS error_message TEXT;
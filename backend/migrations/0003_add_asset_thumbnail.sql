@@ -0,0 +1,3 @@
+-- Storage location of the gallery thumbnail generated after upload (images only)
+
+ALTER TABLE media_assets ADD COLUMN IF NOT EXISTS thumbnail_location TEXT;
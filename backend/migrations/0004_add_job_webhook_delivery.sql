@@ -0,0 +1,5 @@
+-- Outcome of the completion webhook for jobs submitted with a webhook_url
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS webhook_status TEXT;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS webhook_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS webhook_error TEXT;
@@ -0,0 +1,3 @@
+-- Workers claim queued jobs by priority, then age; index exactly that
+
+CREATE INDEX IF NOT EXISTS idx_jobs_claim ON jobs(priority DESC, created_at ASC) WHERE status = 'queued';
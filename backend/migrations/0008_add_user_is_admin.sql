@@ -0,0 +1,4 @@
+-- Admins can manage tiers and see jobs across all users. Granted by hand:
+--   UPDATE users SET is_admin = TRUE WHERE email = '...';
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,13 @@
+-- Whether a job processes images or videos, so daily quotas can be counted
+-- per kind. Existing rows take the kind of their first asset.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS media_kind TEXT;
+
+UPDATE jobs SET media_kind = CASE
+    WHEN lower(a.format) IN ('mp4', 'mov', 'avi', 'webm') THEN 'video'
+    ELSE 'image'
+  END
+FROM media_assets a
+WHERE jobs.media_kind IS NULL
+  AND jsonb_array_length(jobs.media_asset_ids) > 0
+  AND a.id = (jobs.media_asset_ids->>0)::uuid;
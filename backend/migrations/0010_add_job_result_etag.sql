@@ -0,0 +1,4 @@
+-- Content hash of a job's result, served as the download's ETag so clients
+-- can revalidate without fetching the file again. Older results have none.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS result_etag TEXT;
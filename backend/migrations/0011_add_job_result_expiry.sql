@@ -0,0 +1,11 @@
+-- When a completed job's result file is deleted. Set at completion from the
+-- owner's tier; pro users may push it back within their tier's limit.
+-- Existing results keep the 72 hours the old global retention gave them.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS result_expires_at TIMESTAMPTZ;
+
+UPDATE jobs SET result_expires_at = completed_at + INTERVAL '72 hours'
+WHERE status = 'completed' AND completed_at IS NOT NULL AND result_expires_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_jobs_result_expires_at ON jobs(result_expires_at)
+WHERE result_location IS NOT NULL;
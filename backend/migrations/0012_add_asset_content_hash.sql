@@ -0,0 +1,7 @@
+-- SHA-256 of an upload's bytes, so re-uploading the same file can reuse the
+-- existing asset. Lookups are always scoped to the uploading user.
+
+ALTER TABLE media_assets ADD COLUMN IF NOT EXISTS content_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_media_assets_content_hash ON media_assets(user_id, content_hash)
+WHERE content_hash IS NOT NULL;
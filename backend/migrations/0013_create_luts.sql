@@ -0,0 +1,13 @@
+-- LUTs uploaded by users. Requests refer to them by id, which is checked
+-- against the owner, instead of passing storage locations around.
+
+CREATE TABLE IF NOT EXISTS luts (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  location TEXT NOT NULL,
+  size_bytes BIGINT NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_luts_user_id ON luts(user_id);
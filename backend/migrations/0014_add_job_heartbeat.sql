@@ -0,0 +1,8 @@
+-- Refreshed by the worker process running a job. Jobs whose heartbeat goes
+-- stale belonged to a worker that died and are requeued by the others, so
+-- several worker processes can share the table.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS heartbeat_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_jobs_processing_heartbeat ON jobs(heartbeat_at)
+WHERE status = 'processing';
@@ -0,0 +1,18 @@
+-- Accounts must confirm their email address before submitting jobs. Accounts
+-- created before this existed were activated on registration and stay so.
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE users ALTER COLUMN email_verified SET DEFAULT FALSE;
+
+-- Outstanding links sent by email, one per user. Only a hash of the token is
+-- kept; `email` is the address it was sent to, so a link stops working once
+-- the account's address changes.
+CREATE TABLE IF NOT EXISTS verification_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_verification_tokens_user_id ON verification_tokens(user_id);
@@ -0,0 +1,10 @@
+-- Stored objects whose rows are already gone but which have not been deleted
+-- from storage yet. Filled when an account is deleted; the cleanup sweep
+-- retries whatever the request itself could not remove.
+
+CREATE TABLE IF NOT EXISTS pending_deletions (
+    location TEXT PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_deletions_created_at ON pending_deletions(created_at);
@@ -0,0 +1,8 @@
+-- Results of POST /api/analyze, one per asset, so repeat calls are served
+-- without decoding the image again
+
+CREATE TABLE IF NOT EXISTS asset_analyses (
+    asset_id UUID PRIMARY KEY REFERENCES media_assets(id) ON DELETE CASCADE,
+    analysis JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,15 @@
+-- Every file a completed job produced, in order. Output 0 is the primary
+-- one, which jobs.result_location also names.
+
+CREATE TABLE IF NOT EXISTS job_outputs (
+    job_id UUID NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+    output_index INTEGER NOT NULL,
+    location TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    content_type TEXT NOT NULL,
+    etag TEXT NOT NULL,
+    PRIMARY KEY (job_id, output_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_outputs_location ON job_outputs(location);
@@ -0,0 +1,6 @@
+-- Deleting an asset only marks it. It can be restored until the window
+-- passes; then the cleanup sweep removes its files and row.
+
+ALTER TABLE media_assets ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_media_assets_deleted_at ON media_assets(deleted_at) WHERE deleted_at IS NOT NULL;
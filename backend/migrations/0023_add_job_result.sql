@@ -0,0 +1,4 @@
+-- Metadata of a completed job's primary output (dimensions, size, format,
+-- processing time), so clients need not download it to learn them.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS result JSONB;
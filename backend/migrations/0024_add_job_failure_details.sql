@@ -0,0 +1,17 @@
+-- Failed jobs record when they failed and a machine-readable error code
+-- alongside `error_message`. Rows from before `error_message` existed kept
+-- their error in `parameters.error`; move it over so it is the only place
+-- errors are read from.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS error_code TEXT;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS failed_at TIMESTAMPTZ;
+
+UPDATE jobs
+SET error_message = CASE
+        WHEN length(parameters->>'error') > 2048 THEN left(parameters->>'error', 2047) || '…'
+        ELSE parameters->>'error'
+    END
+WHERE error_message IS NULL AND jsonb_typeof(parameters) = 'object' AND parameters ? 'error';
+
+UPDATE jobs SET parameters = parameters - 'error'
+WHERE jsonb_typeof(parameters) = 'object' AND parameters ? 'error';
@@ -0,0 +1,11 @@
+-- When a queued job was last announced to the workers. A job still queued
+-- long after it fell due most likely lost its wake-up (say, to a crash) and
+-- is announced again.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS enqueued_at TIMESTAMPTZ;
+UPDATE jobs SET enqueued_at = created_at WHERE enqueued_at IS NULL;
+ALTER TABLE jobs ALTER COLUMN enqueued_at SET DEFAULT NOW();
+ALTER TABLE jobs ALTER COLUMN enqueued_at SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_jobs_queued_enqueued_at ON jobs(enqueued_at)
+WHERE status = 'queued';
@@ -0,0 +1,27 @@
+-- What usage statistics are counted from. Asset rows go once they expire,
+-- and a job's outputs and result metadata once its result does, so uploads
+-- are recorded on their own and jobs keep the totals.
+
+CREATE TABLE IF NOT EXISTS asset_uploads (
+  asset_id UUID PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  size_bytes BIGINT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_asset_uploads_user_id_created_at ON asset_uploads(user_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_asset_uploads_created_at ON asset_uploads(created_at);
+
+INSERT INTO asset_uploads (asset_id, user_id, size_bytes, created_at)
+SELECT id, user_id, size_bytes, COALESCE(created_at, NOW()) FROM media_assets WHERE user_id IS NOT NULL
+ON CONFLICT DO NOTHING;
+
+-- Total size of every output, and the time spent processing
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS output_bytes BIGINT;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS duration_ms BIGINT;
+
+UPDATE jobs j SET output_bytes = o.total
+FROM (SELECT job_id, SUM(size_bytes)::BIGINT AS total FROM job_outputs GROUP BY job_id) o
+WHERE o.job_id = j.id AND j.output_bytes IS NULL;
+UPDATE jobs SET duration_ms = (result->>'duration_ms')::BIGINT
+WHERE duration_ms IS NULL AND result ? 'duration_ms';
@@ -0,0 +1,44 @@
+-- Organizations share assets, jobs and LUTs among their members. Resources
+-- stay personal unless created while acting for an organization, which
+-- records it in their `organization_id`.
+
+CREATE TABLE IF NOT EXISTS organizations (
+  id UUID PRIMARY KEY,
+  name TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS organization_members (
+  organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  role TEXT NOT NULL CHECK (role IN ('owner', 'member')),
+  joined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_organization_members_user_id ON organization_members(user_id);
+
+-- Outstanding invitations, one per organization and address. Only a hash of
+-- the emailed token is kept; accepting deletes it.
+CREATE TABLE IF NOT EXISTS organization_invites (
+  token_hash TEXT PRIMARY KEY,
+  organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+  email TEXT NOT NULL,
+  invited_by UUID REFERENCES users(id) ON DELETE SET NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_organization_invites_email
+  ON organization_invites(organization_id, LOWER(email));
+
+ALTER TABLE media_assets ADD COLUMN IF NOT EXISTS organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL;
+ALTER TABLE luts ADD COLUMN IF NOT EXISTS organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_media_assets_organization_id ON media_assets(organization_id)
+  WHERE organization_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_jobs_organization_id_created_at ON jobs(organization_id, created_at)
+  WHERE organization_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_luts_organization_id ON luts(organization_id)
+  WHERE organization_id IS NOT NULL;
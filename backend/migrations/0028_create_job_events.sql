@@ -0,0 +1,13 @@
+-- A job's history: when it was queued, started, how far it got, and when
+-- and why it was retried or failed. Rows are only ever appended, and are
+-- pruned by the cleanup sweep once the job is past retention.
+
+CREATE TABLE IF NOT EXISTS job_events (
+  id BIGSERIAL PRIMARY KEY,
+  job_id UUID NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+  event_type TEXT NOT NULL,
+  detail JSONB,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_events_job_id_id ON job_events(job_id, id);
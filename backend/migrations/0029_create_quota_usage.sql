@@ -0,0 +1,33 @@
+-- Assets counted against the daily quotas, per user and per organization,
+-- UTC day and media kind. A submission reserves its assets with a single
+-- conditional upsert, so concurrent requests cannot both take the last ones.
+
+CREATE TABLE IF NOT EXISTS quota_usage (
+  -- A user's or an organization's id
+  subject_id UUID NOT NULL,
+  day DATE NOT NULL,
+  media_kind TEXT NOT NULL,
+  used BIGINT NOT NULL DEFAULT 0,
+  PRIMARY KEY (subject_id, day, media_kind)
+);
+
+CREATE INDEX IF NOT EXISTS idx_quota_usage_day ON quota_usage(day);
+
+-- The day a job's assets were counted on; cleared when they are refunded
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS quota_day DATE;
+
+UPDATE jobs SET quota_day = (created_at AT TIME ZONE 'UTC')::date WHERE quota_day IS NULL;
+
+INSERT INTO quota_usage (subject_id, day, media_kind, used)
+SELECT user_id, quota_day, media_kind, SUM(jsonb_array_length(media_asset_ids))::BIGINT
+FROM jobs
+WHERE media_kind IS NOT NULL AND quota_day >= (NOW() AT TIME ZONE 'UTC')::date
+GROUP BY user_id, quota_day, media_kind
+ON CONFLICT DO NOTHING;
+
+INSERT INTO quota_usage (subject_id, day, media_kind, used)
+SELECT organization_id, quota_day, media_kind, SUM(jsonb_array_length(media_asset_ids))::BIGINT
+FROM jobs
+WHERE media_kind IS NOT NULL AND organization_id IS NOT NULL AND quota_day >= (NOW() AT TIME ZONE 'UTC')::date
+GROUP BY organization_id, quota_day, media_kind
+ON CONFLICT DO NOTHING;
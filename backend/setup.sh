@@ -54,16 +54,65 @@ DATABASE_URL=postgresql://$DB_USER:$DB_PASSWORD@$DB_HOST:$DB_PORT/$DB_NAME
 # Redis Configuration (optional for MVP)
 REDIS_URL=redis://localhost:6379
 
-# JWT Secret (CHANGE THIS IN PRODUCTION!)
+# JWT Secret (CHANGE THIS IN PRODUCTION!). Signs download links, and access
+# tokens unless JWT_ALGORITHM is RS256 or EdDSA.
 JWT_SECRET=$(openssl rand -base64 32)
+# JWT_ALGORITHM=HS256
+# For RS256 or EdDSA, PEM files; only this server needs the private key:
+# JWT_PRIVATE_KEY_PATH=/etc/mediaforge/jwt.pem
+# JWT_PUBLIC_KEY_PATH=/etc/mediaforge/jwt.pub.pem
+# Rotating: keep the old key here until the tokens it signed have expired
+# (7 days). Comma-separated.
+# JWT_PREVIOUS_PUBLIC_KEY_PATHS=/etc/mediaforge/jwt-2025.pub.pem
+# JWT_PREVIOUS_SECRETS=
+
+# Webhook signing secret (shared with webhook receivers)
+WEBHOOK_SECRET=$(openssl rand -hex 32)
 
 # Server Configuration
 HOST=127.0.0.1
 PORT=8080
-
-# Storage Configuration
+# Base of links in emails; defaults to http://HOST:PORT
+# PUBLIC_URL=https://media.example.com
+
+# Email verification links. Without SMTP_HOST they are written to the log.
+# SMTP_HOST=smtp.example.com
+# SMTP_PORT=587
+# SMTP_TLS=starttls
+# SMTP_USERNAME=
+# SMTP_PASSWORD=
+# SMTP_FROM=MediaForge <noreply@example.com>
+EMAIL_VERIFICATION_TTL_HOURS=48
+# Page that takes ?token= and posts it to /api/auth/reset-password;
+# defaults to PUBLIC_URL/reset-password
+# PASSWORD_RESET_URL=https://media.example.com/reset-password
+PASSWORD_RESET_TTL_MINUTES=60
+# Page that takes ?token= and posts it to /api/organizations/invites/accept;
+# defaults to PUBLIC_URL/accept-invite
+# ORGANIZATION_INVITE_URL=https://media.example.com/accept-invite
+ORGANIZATION_INVITE_TTL_HOURS=168
+
+# Malware scanning of uploads through clamd (off when unset). Uploads that
+# cannot be scanned are refused unless SCAN_FAIL_OPEN=true
+# CLAMD_ADDRESS=localhost:3310
+SCAN_TIMEOUT_SECONDS=30
+SCAN_FAIL_OPEN=false
+
+# Request and job traces, exported over OTLP/HTTP (off when no endpoint is
+# set). A job's spans in the worker continue the trace of the request that
+# created it. OTEL_TRACES_SAMPLER_ARG is the share of new traces kept, 0 to 1
+# OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4318
+# OTEL_SERVICE_NAME=mediaforge-api
+OTEL_TRACES_SAMPLER_ARG=1.0
+
+# Storage Configuration. New files go to STORAGE_MODE; files already in the
+# other backend stay readable while it is configured (S3_* in local mode, or
+# an existing LOCAL_STORAGE_PATH in s3 mode)
 STORAGE_MODE=local
 LOCAL_STORAGE_PATH=./data/uploads
+# Lifetime of the result download URLs in job status and /api/download/{id}/url,
+# at most 604800 (7 days)
+DOWNLOAD_URL_TTL_SECONDS=3600
 
 # Quota Configuration
 FREE_TIER_IMAGE_DAILY=10
@@ -71,13 +120,64 @@ FREE_TIER_VIDEO_DAILY=3
 FREE_TIER_CONCURRENT=1
 PRO_TIER_VIDEO_DAILY=50
 PRO_TIER_CONCURRENT=5
+FREE_TIER_STORAGE_QUOTA_BYTES=524288000
+PRO_TIER_STORAGE_QUOTA_BYTES=10737418240
+FREE_TIER_RESULT_RETENTION_HOURS=24
+PRO_TIER_RESULT_RETENTION_HOURS=168
+PRO_TIER_MAX_RESULT_RETENTION_HOURS=720
+# Daily assets pooled across an organization's members; 0 keeps each
+# member's own limit
+ORG_IMAGE_DAILY=0
+ORG_VIDEO_DAILY=0
+# Give the assets of jobs that fail for good back to the day's quota
+QUOTA_REFUND_FAILED_JOBS=false
 
 # Processing Configuration
 MAX_IMAGE_SIZE_MB=10
+# Pixels an image may decode to, every frame of a GIF counted
+MAX_IMAGE_PIXELS=100000000
 MAX_VIDEO_SIZE_MB=100
 MAX_VIDEO_DURATION_SECONDS=30
+LUT_MAX_SIZE_MB=1
 MODEL_PATH=./models/u2net.onnx
+# ffmpeg for video jobs, with ffprobe in the same directory. Without it video
+# requests are refused and only images are processed.
+FFMPEG_PATH=ffmpeg
 TEMP_DIR=./data/temp
+WORKER_CONCURRENCY=2
+# embedded runs jobs in the API process; external leaves them to the worker
+# binary (cargo run --bin worker) and needs Redis
+WORKER_MODE=embedded
+# Jobs running longer are failed; JOB_TIMEOUT_SECONDS_<TYPE> overrides it per job type
+JOB_TIMEOUT_SECONDS=600
+JOB_TIMEOUT_SECONDS_REMOVE_BG=1800
+# Due jobs no worker has picked up for this long are announced to the workers again
+QUEUED_STALE_AFTER_SECONDS=300
+# Time allowed for /api/upload/from-url to download a file
+URL_FETCH_TIMEOUT_SECONDS=30
+# Larger images are analyzed by a queued job rather than within /api/analyze
+ANALYZE_SYNC_MAX_MB=2
+
+# Cleanup of expired assets, expired results and stale temp files
+CLEANUP_INTERVAL_SECONDS=3600
+TEMP_FILE_MAX_AGE_HOURS=6
+# Resumable uploads idle for longer are discarded
+UPLOAD_SESSION_TTL_HOURS=24
+# Idempotency-Key headers are remembered, and their responses replayed, this long
+IDEMPOTENCY_KEY_TTL_HOURS=24
+# Deleted assets can be restored for this long, then are removed for good
+ASSET_RESTORE_WINDOW_HOURS=168
+
+# Auth Rate Limits (attempts per window)
+LOGIN_RATE_LIMIT=5
+REGISTER_RATE_LIMIT=5
+RESEND_VERIFICATION_RATE_LIMIT=2
+FORGOT_PASSWORD_RATE_LIMIT=3
+AUTH_RATE_LIMIT_WINDOW_SECONDS=60
+TRUST_X_FORWARDED_FOR=false
+# Processing and upload requests per user: a burst, then a sustained rate
+REQUEST_RATE_LIMIT_BURST=30
+REQUEST_RATE_LIMIT_PER_MINUTE=60
 
 # Logging
 RUST_LOG=info,media_processor_server=debug
@@ -102,4 +202,10 @@ echo "1. Review and update .env file with your settings"
 echo "2. Run 'cargo build' to compile the project"
 echo "3. Run 'cargo run' to start the server"
 echo ""
+echo "For U²-Net background removal, place u2net.onnx in ./models, build with"
+echo "'--features onnx' and point ORT_DYLIB_PATH at libonnxruntime."
+echo ""
+echo "To accept HEIC uploads, build with '--features heic' and install heif-convert"
+echo "(libheif-examples) or ImageMagick with HEIC support."
+echo ""
 echo "The migrations will run automatically on first startup."
\ No newline at end of file
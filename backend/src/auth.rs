@@ -1,13 +1,27 @@
 use axum::{
     extract::{Request, State},
-    http::{header, StatusCode},
+    http::{header, HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 use chrono::{Duration, Utc};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{db, error::{AppError, Msg}, jwt_keys::KeyRing, AppState};
+
+/// How long an issued JWT stays valid
+pub const TOKEN_TTL_DAYS: i64 = 7;
+
+/// Plaintext API keys look like `mf_<key id>_<secret>`
+const API_KEY_PREFIX: &str = "mf_";
+
+/// Names the organization a request acts for, overriding the token's
+const ORGANIZATION_HEADER: &str = "x-organization-id";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -16,6 +30,9 @@ pub struct Claims {
     pub tier: String,
     pub exp: i64,
     pub iat: i64,
+    /// Organization the token acts for, see `/api/auth/switch-organization`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -23,12 +40,22 @@ pub struct AuthUser {
     pub id: Uuid,
     pub email: String,
     pub tier: String,
+    /// The organization the request acts for, from `X-Organization-Id` or
+    /// the token. Membership has been checked.
+    pub organization_id: Option<Uuid>,
+}
+
+impl AuthUser {
+    /// What the request reaches, and whose scope it creates resources in
+    pub fn owner(&self) -> db::Owner {
+        db::Owner { user_id: self.id, organization_id: self.organization_id }
+    }
 }
 
 impl Claims {
     pub fn new(user_id: Uuid, email: String, tier: String) -> Self {
         let now = Utc::now();
-        let exp = now + Duration::days(7); // 7 day expiry
+        let exp = now + Duration::days(TOKEN_TTL_DAYS);
 
         Self {
             sub: user_id.to_string(),
@@ -36,25 +63,16 @@ impl Claims {
             tier,
             iat: now.timestamp(),
             exp: exp.timestamp(),
+            org: None,
         }
     }
 
-    pub fn to_token(&self, secret: &str) -> Result<String, jsonwebtoken::errors::Error> {
-        encode(
-            &Header::default(),
-            self,
-            &EncodingKey::from_secret(secret.as_bytes()),
-        )
+    pub fn to_token(&self, keys: &KeyRing) -> Result<String, jsonwebtoken::errors::Error> {
+        keys.sign(self)
     }
 
-    pub fn from_token(token: &str, secret: &str) -> Result<Self, jsonwebtoken::errors::Error> {
-        let token_data = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(secret.as_bytes()),
-            &Validation::default(),
-        )?;
-
-        Ok(token_data.claims)
+    pub fn from_token(token: &str, keys: &KeyRing) -> Result<Self, jsonwebtoken::errors::Error> {
+        keys.verify(token)
     }
 }
 
@@ -68,61 +86,256 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::Bcryp
     bcrypt::verify(password, hash)
 }
 
-/// Middleware to extract and validate JWT from Authorization header
+/// A freshly generated API key. `key` is the only copy of the plaintext.
+pub struct GeneratedApiKey {
+    pub id: Uuid,
+    pub key: String,
+    pub hash: String,
+}
+
+pub fn generate_api_key() -> GeneratedApiKey {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let secret = hex::encode(secret);
+    let id = Uuid::new_v4();
+
+    GeneratedApiKey {
+        id,
+        key: format!("{}{}_{}", API_KEY_PREFIX, id.simple(), secret),
+        hash: hash_api_secret(&secret),
+    }
+}
+
+/// A random single-use token for a link sent by email, and the hash to store
+/// in its place
+pub fn generate_email_token() -> (String, String) {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let token = hex::encode(secret);
+    let hash = hash_email_token(&token);
+    (token, hash)
+}
+
+pub fn hash_email_token(token: &str) -> String {
+    hash_api_secret(token)
+}
+
+/// Split a plaintext key into its id and secret
+fn parse_api_key(key: &str) -> Option<(Uuid, &str)> {
+    let (id, secret) = key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+    Some((Uuid::parse_str(id).ok()?, secret))
+}
+
+fn hash_api_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn api_secret_matches(secret: &str, stored_hash: &str) -> bool {
+    hash_api_secret(secret)
+        .as_bytes()
+        .ct_eq(stored_hash.as_bytes())
+        .into()
+}
+
+/// Credentials presented with a request
+#[derive(Debug, PartialEq)]
+enum Credentials<'a> {
+    Jwt(&'a str),
+    ApiKey(&'a str),
+}
+
+/// `Authorization: Bearer <jwt>`, `Authorization: ApiKey <key>` or `X-Api-Key: <key>`
+fn credentials(headers: &HeaderMap) -> Option<Credentials<'_>> {
+    if let Some(value) = headers.get(header::AUTHORIZATION).and_then(|h| h.to_str().ok()) {
+        if let Some(token) = value.strip_prefix("Bearer ") {
+            return Some(Credentials::Jwt(token));
+        }
+        if let Some(key) = value.strip_prefix("ApiKey ") {
+            return Some(Credentials::ApiKey(key));
+        }
+        return None;
+    }
+
+    headers
+        .get("x-api-key")
+        .and_then(|h| h.to_str().ok())
+        .map(Credentials::ApiKey)
+}
+
+/// The organization named by `X-Organization-Id`. A value that isn't an ID
+/// is refused rather than ignored, so the request can't silently run in the
+/// personal scope.
+fn organization_header(headers: &HeaderMap) -> Result<Option<Uuid>, StatusCode> {
+    headers
+        .get(ORGANIZATION_HEADER)
+        .map(|value| {
+            let value = value.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+            Uuid::parse_str(value.trim()).map_err(|_| StatusCode::BAD_REQUEST)
+        })
+        .transpose()
+}
+
+/// Look up an API key and build the same user the JWT path would. The tier
+/// comes from the database, so quota checks see the account's current tier.
+async fn authenticate_api_key(db_pool: &sqlx::PgPool, key: &str) -> Result<AuthUser, StatusCode> {
+    let (id, secret) = parse_api_key(key.trim()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let (api_key, user) = db::ApiKey::find_active(db_pool, id)
+        .await
+        .map_err(|e| {
+            tracing::error!("API key lookup failed: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !api_secret_matches(secret, &api_key.key_hash) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // Recording use is bookkeeping; don't make the request wait for it
+    let db_pool = db_pool.clone();
+    tokio::spawn(async move {
+        if let Err(e) = db::ApiKey::touch(&db_pool, api_key.id).await {
+            tracing::warn!("Failed to record use of API key {}: {:?}", api_key.id, e);
+        }
+    });
+
+    Ok(AuthUser {
+        id: user.id,
+        email: user.email,
+        tier: user.subscription_tier,
+        organization_id: None,
+    })
+}
+
+/// Middleware to authenticate requests by JWT or API key
 pub async fn auth_middleware(
-    State(jwt_secret): State<String>,
+    State(state): State<AppState>,
     mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    let auth_header = request
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let header_organization = organization_header(request.headers())?;
+    let mut user = match credentials(request.headers()).ok_or(StatusCode::UNAUTHORIZED)? {
+        Credentials::Jwt(token) => {
+            let claims = Claims::from_token(token, &state.keys)
+                .map_err(|_| StatusCode::UNAUTHORIZED)?;
+            let id = Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?;
 
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or(StatusCode::UNAUTHORIZED)?;
-
-    let claims = Claims::from_token(token, &jwt_secret)
-        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+            // Tokens outlive a deleted account; this primary key lookup is
+            // what shuts it out at once
+            let exists = db::User::exists(&state.db, id).await.map_err(|e| {
+                tracing::error!("User lookup failed: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            if !exists {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
 
-    let user = AuthUser {
-        id: Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?,
-        email: claims.email,
-        tier: claims.tier,
+            AuthUser {
+                id,
+                email: claims.email,
+                tier: claims.tier,
+                organization_id: claims.org.as_deref().and_then(|org| Uuid::parse_str(org).ok()),
+            }
+        }
+        Credentials::ApiKey(key) => authenticate_api_key(&state.db, key).await?,
     };
 
+    // Checked on every request, so leaving an organization takes effect at
+    // once. A token's organization the user is no longer in is dropped
+    // rather than refused, so the token can still switch to another.
+    if let Some(organization_id) = header_organization.or(user.organization_id) {
+        let member = db::Organization::membership(&state.db, organization_id, user.id)
+            .await
+            .map_err(|e| {
+                tracing::error!("Membership lookup failed: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .is_some();
+        if !member && header_organization.is_some() {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        user.organization_id = member.then_some(organization_id);
+    }
+
     // Insert user into request extensions
+    tracing::Span::current().record("user_id", tracing::field::display(user.id));
     request.extensions_mut().insert(user);
 
     Ok(next.run(request).await)
 }
 
 // Request/Response types
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RegisterRequest {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangeEmailRequest {
+    pub password: String,
+    pub new_email: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ResetPasswordRequest {
+    /// Token from the emailed link
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct AuthResponse {
     pub token: String,
     pub user: UserInfo,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserInfo {
     pub id: String,
     pub email: String,
     pub tier: String,
+    /// Jobs are refused until the emailed link has been followed
+    pub email_verified: bool,
+    /// The organization the token acts for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SwitchOrganizationRequest {
+    /// An organization the caller is a member of, or null for the personal
+    /// scope
+    pub organization_id: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VerifyEmailQuery {
+    /// Token from the emailed link
+    pub token: String,
 }
 
 // Axum extractor for authenticated user
@@ -143,4 +356,79 @@ where
             .cloned()
             .ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))
     }
-}
\ No newline at end of file
+}
+
+/// An authenticated user whose account is flagged admin. The flag is read
+/// from the database on each request, so granting or revoking it applies at once.
+pub struct AdminUser(pub AuthUser);
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or_else(|| AppError::Unauthorized(Msg::Unauthorized.into()))?;
+
+        match db::User::find_by_id(&state.db, user.id).await? {
+            Some(account) if account.is_admin => Ok(Self(user)),
+            _ => Err(AppError::Forbidden(Msg::AdminRequired.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_key_round_trip() {
+        let generated = generate_api_key();
+        let (id, secret) = parse_api_key(&generated.key).unwrap();
+        assert_eq!(id, generated.id);
+        assert!(api_secret_matches(secret, &generated.hash));
+        assert!(!api_secret_matches("not the secret", &generated.hash));
+
+        // Every key is unique
+        assert_ne!(generate_api_key().key, generated.key);
+
+        let (token, hash) = generate_email_token();
+        assert_eq!(hash_email_token(&token), hash);
+        assert_ne!(generate_email_token().0, token);
+
+        assert!(parse_api_key("mf_not-a-uuid_abc").is_none());
+        assert!(parse_api_key(&generated.key.replacen("mf_", "xx_", 1)).is_none());
+    }
+
+    #[test]
+    fn test_organization_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(organization_header(&headers), Ok(None));
+
+        let id = Uuid::new_v4();
+        headers.insert(ORGANIZATION_HEADER, format!(" {} ", id).parse().unwrap());
+        assert_eq!(organization_header(&headers), Ok(Some(id)));
+        headers.insert(ORGANIZATION_HEADER, "acme".parse().unwrap());
+        assert_eq!(organization_header(&headers), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_credentials_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(credentials(&headers), None);
+
+        headers.insert("x-api-key", "mf_key".parse().unwrap());
+        assert_eq!(credentials(&headers), Some(Credentials::ApiKey("mf_key")));
+
+        // Authorization takes precedence over X-Api-Key
+        headers.insert(header::AUTHORIZATION, "Bearer jwt".parse().unwrap());
+        assert_eq!(credentials(&headers), Some(Credentials::Jwt("jwt")));
+        headers.insert(header::AUTHORIZATION, "ApiKey mf_other".parse().unwrap());
+        assert_eq!(credentials(&headers), Some(Credentials::ApiKey("mf_other")));
+        headers.insert(header::AUTHORIZATION, "Basic dXNlcg==".parse().unwrap());
+        assert_eq!(credentials(&headers), None);
+    }
+}
@@ -0,0 +1,35 @@
+// backend/src/bin/worker.rs
+// Runs only the job consumer, for scaling processing apart from the API. Jobs
+// are announced on the Redis list, so it needs REDIS_URL; run the API with
+// WORKER_MODE=external. Any number of these may run beside each other and
+// beside embedded workers: jobs are claimed from the database one at a time.
+
+use anyhow::Context;
+use media_processor_server::{config, init_tracing, shutdown_signal, start_external_worker, Resources};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+
+    let config = config::Config::from_env()
+        .context("Failed to load configuration from environment")?;
+    let exporter = init_tracing(&config.tracing, "mediaforge-worker")?;
+
+    tracing::info!("🚀 MediaForge Worker Starting...");
+    tracing::info!("✓ Configuration loaded successfully");
+
+    // The in-process channel is only fed by this process's own enqueues
+    let (resources, _) = Resources::connect(&config).await?;
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_signal(shutdown.clone()));
+
+    let worker = start_external_worker(&config, &resources, shutdown)?;
+    worker.await.context("Worker task failed")?;
+    tracing::info!("👋 MediaForge worker stopped");
+    if let Some(exporter) = exporter {
+        exporter.shutdown().await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+// backend/src/body_limit.rs
+// Router-level request body caps, and the JSON error for bodies that exceed them
+
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::config::ProcessingConfig;
+use crate::error::{AppError, Msg};
+
+/// JSON bodies. The largest legitimate one, a full batch or pipeline, is a few KB.
+pub const JSON_BODY_LIMIT: usize = 16 * 1024;
+
+/// Room for multipart boundaries and part headers on top of the file itself
+const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+/// Cap for `/api/upload`: the larger per-kind limit. The per-kind limit is
+/// enforced by the handler once it has sniffed the content.
+pub fn upload_limit(config: &ProcessingConfig) -> usize {
+    let max_mb = config.max_image_size_mb.max(config.max_video_size_mb);
+    mb(max_mb) + MULTIPART_OVERHEAD
+}
+
+/// Cap for `/api/lut`
+pub fn lut_limit(config: &ProcessingConfig) -> usize {
+    mb(config.lut_max_size_mb) + MULTIPART_OVERHEAD
+}
+
+fn mb(megabytes: u64) -> usize {
+    usize::try_from(megabytes.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX)
+}
+
+/// Body limit rejections come from tower-http and axum's extractors as plain
+/// text; give them the same JSON shape as every other error
+pub async fn payload_too_large_as_json(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_json {
+        return response;
+    }
+
+    AppError::PayloadTooLarge(Msg::RequestTooLarge.into()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::DefaultBodyLimit, routing::post, Json, Router};
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tower::{ServiceBuilder, ServiceExt};
+    use tower_http::limit::RequestBodyLimitLayer;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/json", post(|Json(_): Json<serde_json::Value>| async { "ok" }))
+            .route(
+                "/upload",
+                post(|_: axum::body::Bytes| async { "ok" })
+                    .layer(
+                        ServiceBuilder::new()
+                            .layer(DefaultBodyLimit::max(4 * 1024))
+                            .layer(RequestBodyLimitLayer::new(4 * 1024)),
+                    ),
+            )
+            .layer(DefaultBodyLimit::max(1024))
+            .layer(axum::middleware::from_fn(payload_too_large_as_json))
+    }
+
+    /// 100 chunks of 1 KB, counting how many are pulled
+    fn counted_body(pulled: Arc<AtomicUsize>) -> Body {
+        Body::from_stream(futures_util::stream::iter(0..100).map(move |_| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, std::io::Error>(bytes::Bytes::from(vec![b' '; 1024]))
+        }))
+    }
+
+    async fn error_code(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["error"]["code"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_json_body_stops_at_the_limit() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let request = Request::post("/json")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(counted_body(pulled.clone()))
+            .unwrap();
+
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error_code(response).await, "PAYLOAD_TOO_LARGE");
+        assert!(pulled.load(Ordering::SeqCst) <= 2, "read {} KB", pulled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_declared_length_over_the_limit_is_refused_unread() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let request = Request::post("/upload")
+            .header(header::CONTENT_LENGTH, 100 * 1024)
+            .body(counted_body(pulled.clone()))
+            .unwrap();
+
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error_code(response).await, "PAYLOAD_TOO_LARGE");
+        assert_eq!(pulled.load(Ordering::SeqCst), 0);
+
+        // The route's own limit replaces the router-wide one
+        let request = Request::post("/upload").body(Body::from(vec![0u8; 2048])).unwrap();
+        assert_eq!(app().oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_limits_leave_room_for_multipart_framing() {
+        let config = ProcessingConfig {
+            max_image_size_mb: 50,
+            max_image_pixels: 100_000_000,
+            max_video_size_mb: 500,
+            max_video_duration_seconds: 300,
+            lut_max_size_mb: 1,
+            model_path: String::new(),
+            ffmpeg_path: String::new(),
+            temp_dir: String::new(),
+            worker_concurrency: 1,
+            worker_mode: "embedded".to_string(),
+            cleanup_interval_seconds: 3600,
+            temp_file_max_age_hours: 6,
+            job_timeout_seconds: 600,
+            queued_stale_after_seconds: 300,
+            url_fetch_timeout_seconds: 30,
+            upload_session_ttl_hours: 24,
+            idempotency_key_ttl_hours: 24,
+            asset_restore_window_hours: 168,
+            analyze_sync_max_mb: 2,
+            job_type_timeout_seconds: Default::default(),
+        };
+        assert_eq!(upload_limit(&config), 500 * 1024 * 1024 + MULTIPART_OVERHEAD);
+        assert_eq!(lut_limit(&config), 1024 * 1024 + MULTIPART_OVERHEAD);
+    }
+}
@@ -1,16 +1,76 @@
+use anyhow::{bail, ensure, Context};
+use lettre::message::Mailbox;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::db;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub database_url: String,
     pub redis_url: String,
+    /// Signs result download links, and access tokens when `jwt` is HMAC
     pub jwt_secret: String,
+    /// Keys access tokens are signed and verified with
+    pub jwt: JwtConfig,
+    /// Shared secret for signing webhook deliveries; webhooks are disabled without it
+    pub webhook_secret: Option<String>,
     pub host: String,
     pub port: u16,
+    /// Where clients reach the API, for links sent by email
+    pub public_url: String,
     pub storage: StorageConfig,
     pub quotas: QuotaConfig,
     pub processing: ProcessingConfig,
+    pub rate_limits: RateLimitConfig,
+    pub mail: MailConfig,
+    pub scan: ScanConfig,
+    pub tracing: TracingConfig,
+}
+
+/// `JWT_ALGORITHM` and the keys that go with it. Rotating moves the current
+/// key into `previous`, where it keeps verifying the tokens it signed until
+/// they expire or it is removed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtConfig {
+    /// Signs new tokens
+    pub current: JwtKey,
+    /// Only verify: `JWT_PREVIOUS_SECRETS`, then `JWT_PREVIOUS_PUBLIC_KEY_PATHS`
+    pub previous: Vec<JwtKey>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum JwtKey {
+    /// HS256 with a shared secret
+    Hmac(String),
+    /// RS256. Anyone with the public key can verify.
+    Rsa(KeyPair),
+    /// EdDSA over Ed25519
+    Ed25519(KeyPair),
+}
+
+/// PEM files as read at startup. Previous keys have no private half.
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyPair {
+    pub private_pem: Option<Pem>,
+    pub public_pem: Pem,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Pem {
+    /// Where it was read from, to name it in errors
+    pub path: String,
+    pub contents: String,
+}
+
+impl std::fmt::Debug for Pem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pem").field("path", &self.path).finish_non_exhaustive()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -19,8 +79,21 @@ pub struct StorageConfig {
     pub local_path: String,
     pub s3_endpoint: Option<String>,
     pub s3_bucket: Option<String>,
+    pub s3_region: String,
     pub s3_access_key: Option<String>,
     pub s3_secret_key: Option<String>,
+    /// How long the download URLs handed out for results stay valid
+    pub download_url_ttl_seconds: u64,
+}
+
+/// The longest `DOWNLOAD_URL_TTL_SECONDS`: seven days, as long as S3 lets a
+/// presigned URL last
+pub const MAX_DOWNLOAD_URL_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+impl StorageConfig {
+    pub fn download_url_ttl(&self) -> Duration {
+        Duration::from_secs(self.download_url_ttl_seconds)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -30,75 +103,673 @@ pub struct QuotaConfig {
     pub free_tier_concurrent: u32,
     pub pro_tier_video_daily: u32,
     pub pro_tier_concurrent: u32,
+    /// Total size of unexpired uploads a user may keep
+    pub free_tier_storage_quota_bytes: u64,
+    pub pro_tier_storage_quota_bytes: u64,
+    /// How long a completed job's result is kept before it is deleted
+    pub free_tier_result_retention_hours: u64,
+    pub pro_tier_result_retention_hours: u64,
+    /// Furthest past completion a pro user may extend a result's retention
+    pub pro_tier_max_result_retention_hours: u64,
+    /// Daily assets shared by everyone submitting for an organization. 0
+    /// leaves members to their own tier's limit.
+    pub org_image_daily: u32,
+    pub org_video_daily: u32,
+    /// Give the assets of jobs that fail for good back to the day's quota
+    pub refund_failed_jobs: bool,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ProcessingConfig {
     pub max_image_size_mb: u64,
+    /// Pixels an image may decode to, counting every frame of a GIF. A small
+    /// file can declare a canvas that would take gigabytes to decode.
+    pub max_image_pixels: u64,
     pub max_video_size_mb: u64,
     pub max_video_duration_seconds: u32,
     pub lut_max_size_mb: u64,
     pub model_path: String,
+    /// ffmpeg to run for video work. ffprobe is expected beside it, or on
+    /// the PATH when this is a bare command name.
+    pub ffmpeg_path: String,
     pub temp_dir: String,
+    /// Jobs processed in parallel
+    pub worker_concurrency: usize,
+    /// `embedded` runs the workers inside the API process; `external` leaves
+    /// them to the `worker` binary and only pushes jobs to Redis
+    pub worker_mode: String,
+    /// How often expired assets, expired results and stale temp files are swept
+    pub cleanup_interval_seconds: u64,
+    /// Files in `temp_dir` older than this are treated as orphaned
+    pub temp_file_max_age_hours: u64,
+    /// Longest a job may run before it is failed
+    pub job_timeout_seconds: u64,
+    /// A due job still queued this long after it was announced to the
+    /// workers is announced again
+    pub queued_stale_after_seconds: u64,
+    /// Total time allowed for `/api/upload/from-url` to fetch a file,
+    /// redirects and body included
+    pub url_fetch_timeout_seconds: u64,
+    /// Resumable uploads nothing has been sent to for this long are discarded
+    pub upload_session_ttl_hours: u64,
+    /// How long an `Idempotency-Key` is remembered, and its response replayed
+    pub idempotency_key_ttl_hours: u64,
+    /// How long a deleted asset can be restored before the cleanup sweep
+    /// removes it for good
+    pub asset_restore_window_hours: u64,
+    /// Images up to this size are analyzed within the `/api/analyze` request;
+    /// larger ones are queued as `analyze` jobs. 0 queues every analysis.
+    pub analyze_sync_max_mb: u64,
+    /// `job_timeout_seconds` overrides by job type, from
+    /// `JOB_TIMEOUT_SECONDS_<TYPE>`; background removal on video runs the
+    /// model on every frame and needs far longer than an image
+    pub job_type_timeout_seconds: BTreeMap<String, u64>,
+}
+
+impl ProcessingConfig {
+    /// Whether the API process runs the workers itself
+    pub fn embedded_worker(&self) -> bool {
+        self.worker_mode == "embedded"
+    }
+
+    /// Time allowed for a job of `job_type`
+    pub fn job_timeout(&self, job_type: &str) -> Duration {
+        let seconds = self.job_type_timeout_seconds.get(job_type).copied();
+        Duration::from_secs(seconds.unwrap_or(self.job_timeout_seconds))
+    }
+
+    /// How long a due job may wait unclaimed before it is announced again
+    pub fn queued_stale_after(&self) -> Duration {
+        Duration::from_secs(self.queued_stale_after_seconds)
+    }
+
+    /// How long after deletion an asset can still be restored
+    pub fn asset_restore_window(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.asset_restore_window_hours as i64)
+    }
+}
+
+/// Outgoing email. Without `smtp_host` messages are only logged, which is
+/// enough to follow verification links in development.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailConfig {
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    /// `starttls`, `tls` (TLS from the start, usually port 465) or `none`
+    pub smtp_tls: String,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// Sender, e.g. `MediaForge <noreply@example.com>`; required with `smtp_host`
+    pub smtp_from: Option<String>,
+    /// How long an emailed verification link works
+    pub verification_ttl_hours: u64,
+    /// Page that takes a reset token as `?token=` and posts it, with the new
+    /// password, to `/api/auth/reset-password`
+    pub password_reset_url: String,
+    /// How long an emailed password reset link works
+    pub password_reset_ttl_minutes: u64,
+    /// Page that takes an invitation token as `?token=` and posts it, signed
+    /// in, to `/api/organizations/invites/accept`
+    pub organization_invite_url: String,
+    /// How long an emailed organization invitation works
+    pub organization_invite_ttl_hours: u64,
+}
+
+/// Malware scanning of uploads. Without `clamd_address` uploads are not scanned.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScanConfig {
+    /// `host:port` of a clamd listening on TCP
+    pub clamd_address: Option<String>,
+    pub timeout_seconds: u64,
+    /// Accept uploads that could not be scanned, because clamd was down or
+    /// slow, instead of refusing them
+    pub fail_open: bool,
+}
+
+/// Span export to an OpenTelemetry collector, read from the standard `OTEL_*`
+/// variables. Without an endpoint spans are only logged.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TracingConfig {
+    /// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` as given, or `OTEL_EXPORTER_OTLP_ENDPOINT`
+    /// with `/v1/traces` appended; OTLP over HTTP with protobuf
+    pub otlp_endpoint: Option<String>,
+    /// `OTEL_SERVICE_NAME`; each binary names itself otherwise
+    pub service_name: Option<String>,
+    /// Share of new traces recorded, from 0 to 1 (`OTEL_TRACES_SAMPLER_ARG`).
+    /// Requests whose caller sampled its trace are always recorded.
+    pub sample_ratio: f64,
+}
+
+/// Throttling for the unauthenticated auth endpoints, and for processing and
+/// upload requests
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Login attempts allowed per client IP and email in each window
+    pub login_attempts: u32,
+    /// Registrations allowed per client IP in each window
+    pub register_attempts: u32,
+    /// Verification emails a user may ask for again in each window
+    pub resend_verification_attempts: u32,
+    /// Password reset requests allowed per client IP, and per email, in each window
+    pub forgot_password_attempts: u32,
+    pub window_seconds: u64,
+    /// Processing and upload requests a user may make at once before being
+    /// held to `requests_per_minute`
+    pub request_burst: u32,
+    /// Sustained processing and upload requests allowed per user
+    pub requests_per_minute: u32,
+    /// Take the client IP from the last `X-Forwarded-For` entry. Only enable
+    /// behind a proxy that sets it, or clients can pick their own identity.
+    pub trust_forwarded_for: bool,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, anyhow::Error> {
         dotenv::dotenv().ok();
+        Self::from_vars(|name| env::var(name).ok())
+    }
 
-        Ok(Config {
-            database_url: env::var("DATABASE_URL")?,
-            redis_url: env::var("REDIS_URL")
-                .unwrap_or_else(|_| "redis://localhost:6379".to_string()),
-            jwt_secret: env::var("JWT_SECRET")?,
-            host: env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-            port: env::var("PORT")
-                .unwrap_or_else(|_| "8080".to_string())
-                .parse()?,
+    /// Load from `var`, which looks up an environment variable by name, and
+    /// check the result
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, anyhow::Error> {
+        let vars = Vars(&var);
+        let host = vars.string("HOST", "127.0.0.1");
+        let port = vars.parse("PORT", 8080)?;
+        let public_url = vars
+            .string("PUBLIC_URL", &format!("http://{}:{}", host, port))
+            .trim_end_matches('/')
+            .to_string();
+        let config = Config {
+            database_url: vars.required("DATABASE_URL")?,
+            // Set but empty disables Redis, so this one is not `vars.string`
+            redis_url: var("REDIS_URL").unwrap_or_else(|| "redis://localhost:6379".to_string()),
+            jwt_secret: vars.required("JWT_SECRET")?,
+            jwt: jwt_config(&vars)?,
+            webhook_secret: vars.optional("WEBHOOK_SECRET"),
+            host,
+            port,
             storage: StorageConfig {
-                mode: env::var("STORAGE_MODE").unwrap_or_else(|_| "local".to_string()),
-                local_path: env::var("LOCAL_STORAGE_PATH")
-                    .unwrap_or_else(|_| "./data/uploads".to_string()),
-                s3_endpoint: env::var("S3_ENDPOINT").ok(),
-                s3_bucket: env::var("S3_BUCKET").ok(),
-                s3_access_key: env::var("S3_ACCESS_KEY").ok(),
-                s3_secret_key: env::var("S3_SECRET_KEY").ok(),
+                mode: vars.string("STORAGE_MODE", "local").to_lowercase(),
+                local_path: vars.string("LOCAL_STORAGE_PATH", "./data/uploads"),
+                s3_endpoint: vars.optional("S3_ENDPOINT"),
+                s3_bucket: vars.optional("S3_BUCKET"),
+                s3_region: vars.string("S3_REGION", "us-east-1"),
+                s3_access_key: vars.optional("S3_ACCESS_KEY"),
+                s3_secret_key: vars.optional("S3_SECRET_KEY"),
+                download_url_ttl_seconds: vars.parse("DOWNLOAD_URL_TTL_SECONDS", 3600)?,
             },
             quotas: QuotaConfig {
-                free_tier_image_daily: env::var("FREE_TIER_IMAGE_DAILY")
-                    .unwrap_or_else(|_| "10".to_string())
-                    .parse()?,
-                free_tier_video_daily: env::var("FREE_TIER_VIDEO_DAILY")
-                    .unwrap_or_else(|_| "3".to_string())
-                    .parse()?,
-                free_tier_concurrent: env::var("FREE_TIER_CONCURRENT")
-                    .unwrap_or_else(|_| "1".to_string())
-                    .parse()?,
-                pro_tier_video_daily: env::var("PRO_TIER_VIDEO_DAILY")
-                    .unwrap_or_else(|_| "50".to_string())
-                    .parse()?,
-                pro_tier_concurrent: env::var("PRO_TIER_CONCURRENT")
-                    .unwrap_or_else(|_| "5".to_string())
-                    .parse()?,
+                free_tier_image_daily: vars.parse("FREE_TIER_IMAGE_DAILY", 10)?,
+                free_tier_video_daily: vars.parse("FREE_TIER_VIDEO_DAILY", 3)?,
+                free_tier_concurrent: vars.parse("FREE_TIER_CONCURRENT", 1)?,
+                pro_tier_video_daily: vars.parse("PRO_TIER_VIDEO_DAILY", 50)?,
+                pro_tier_concurrent: vars.parse("PRO_TIER_CONCURRENT", 5)?,
+                free_tier_storage_quota_bytes: vars.parse("FREE_TIER_STORAGE_QUOTA_BYTES", 524_288_000)?,
+                pro_tier_storage_quota_bytes: vars.parse("PRO_TIER_STORAGE_QUOTA_BYTES", 10_737_418_240)?,
+                free_tier_result_retention_hours: vars.parse("FREE_TIER_RESULT_RETENTION_HOURS", 24)?,
+                pro_tier_result_retention_hours: vars.parse("PRO_TIER_RESULT_RETENTION_HOURS", 168)?,
+                pro_tier_max_result_retention_hours: vars.parse("PRO_TIER_MAX_RESULT_RETENTION_HOURS", 720)?,
+                org_image_daily: vars.parse("ORG_IMAGE_DAILY", 0)?,
+                org_video_daily: vars.parse("ORG_VIDEO_DAILY", 0)?,
+                refund_failed_jobs: vars.flag("QUOTA_REFUND_FAILED_JOBS"),
             },
             processing: ProcessingConfig {
-                max_image_size_mb: env::var("MAX_IMAGE_SIZE_MB")
-                    .unwrap_or_else(|_| "5".to_string())
-                    .parse()?,
-                max_video_size_mb: env::var("MAX_VIDEO_SIZE_MB")
-                    .unwrap_or_else(|_| "50".to_string())
-                    .parse()?,
-                max_video_duration_seconds: env::var("MAX_VIDEO_DURATION_SECONDS")
-                    .unwrap_or_else(|_| "30".to_string())
-                    .parse()?,
-                lut_max_size_mb: env::var("LUT_MAX_SIZE_MB")
-                    .unwrap_or_else(|_| "1".to_string())
-                    .parse()?,
-                model_path: env::var("MODEL_PATH")
-                    .unwrap_or_else(|_| "./models/u2net.onnx".to_string()),
-                temp_dir: env::var("TEMP_DIR")
-                    .unwrap_or_else(|_| "./data/temp".to_string()),
+                max_image_size_mb: vars.parse("MAX_IMAGE_SIZE_MB", 5)?,
+                max_image_pixels: vars.parse("MAX_IMAGE_PIXELS", 100_000_000)?,
+                max_video_size_mb: vars.parse("MAX_VIDEO_SIZE_MB", 50)?,
+                max_video_duration_seconds: vars.parse("MAX_VIDEO_DURATION_SECONDS", 30)?,
+                lut_max_size_mb: vars.parse("LUT_MAX_SIZE_MB", 1)?,
+                model_path: vars.string("MODEL_PATH", "./models/u2net.onnx"),
+                ffmpeg_path: vars.string("FFMPEG_PATH", "ffmpeg"),
+                temp_dir: vars.string("TEMP_DIR", "./data/temp"),
+                worker_concurrency: vars.parse("WORKER_CONCURRENCY", 2)?,
+                worker_mode: vars.string("WORKER_MODE", "embedded").to_lowercase(),
+                cleanup_interval_seconds: vars.parse("CLEANUP_INTERVAL_SECONDS", 3600)?,
+                temp_file_max_age_hours: vars.parse("TEMP_FILE_MAX_AGE_HOURS", 6)?,
+                job_timeout_seconds: vars.parse("JOB_TIMEOUT_SECONDS", 600)?,
+                queued_stale_after_seconds: vars.parse("QUEUED_STALE_AFTER_SECONDS", 300)?,
+                url_fetch_timeout_seconds: vars.parse("URL_FETCH_TIMEOUT_SECONDS", 30)?,
+                upload_session_ttl_hours: vars.parse("UPLOAD_SESSION_TTL_HOURS", 24)?,
+                idempotency_key_ttl_hours: vars.parse("IDEMPOTENCY_KEY_TTL_HOURS", 24)?,
+                asset_restore_window_hours: vars.parse("ASSET_RESTORE_WINDOW_HOURS", 168)?,
+                analyze_sync_max_mb: vars.parse("ANALYZE_SYNC_MAX_MB", 2)?,
+                job_type_timeout_seconds: job_type_timeouts(&vars)?,
+            },
+            rate_limits: RateLimitConfig {
+                login_attempts: vars.parse("LOGIN_RATE_LIMIT", 5)?,
+                register_attempts: vars.parse("REGISTER_RATE_LIMIT", 5)?,
+                resend_verification_attempts: vars.parse("RESEND_VERIFICATION_RATE_LIMIT", 2)?,
+                forgot_password_attempts: vars.parse("FORGOT_PASSWORD_RATE_LIMIT", 3)?,
+                window_seconds: vars.parse("AUTH_RATE_LIMIT_WINDOW_SECONDS", 60)?,
+                request_burst: vars.parse("REQUEST_RATE_LIMIT_BURST", 30)?,
+                requests_per_minute: vars.parse("REQUEST_RATE_LIMIT_PER_MINUTE", 60)?,
+                trust_forwarded_for: vars.flag("TRUST_X_FORWARDED_FOR"),
             },
-        })
+            mail: MailConfig {
+                smtp_host: vars.optional("SMTP_HOST"),
+                smtp_port: vars.parse("SMTP_PORT", 587)?,
+                smtp_tls: vars.string("SMTP_TLS", "starttls").to_lowercase(),
+                smtp_username: vars.optional("SMTP_USERNAME"),
+                smtp_password: vars.optional("SMTP_PASSWORD"),
+                smtp_from: vars.optional("SMTP_FROM"),
+                verification_ttl_hours: vars.parse("EMAIL_VERIFICATION_TTL_HOURS", 48)?,
+                password_reset_url: vars.string("PASSWORD_RESET_URL", &format!("{}/reset-password", public_url)),
+                password_reset_ttl_minutes: vars.parse("PASSWORD_RESET_TTL_MINUTES", 60)?,
+                organization_invite_url: vars
+                    .string("ORGANIZATION_INVITE_URL", &format!("{}/accept-invite", public_url)),
+                organization_invite_ttl_hours: vars.parse("ORGANIZATION_INVITE_TTL_HOURS", 168)?,
+            },
+            scan: ScanConfig {
+                clamd_address: vars.optional("CLAMD_ADDRESS"),
+                timeout_seconds: vars.parse("SCAN_TIMEOUT_SECONDS", 30)?,
+                fail_open: vars.flag("SCAN_FAIL_OPEN"),
+            },
+            tracing: tracing_config(&vars)?,
+            public_url,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reject values that parse but would only fail, or misbehave, once
+    /// requests arrive
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        ensure!(self.port != 0, "PORT must not be 0");
+        ensure!(!self.jwt_secret.trim().is_empty(), "JWT_SECRET must not be empty");
+        // A bad PEM would otherwise only show when the first login fails
+        crate::jwt_keys::KeyRing::new(&self.jwt)?;
+
+        match self.storage.mode.as_str() {
+            "local" => ensure!(!self.storage.local_path.is_empty(), "LOCAL_STORAGE_PATH must not be empty"),
+            "s3" => {
+                ensure!(self.storage.s3_bucket.is_some(), "S3_BUCKET is required when STORAGE_MODE=s3");
+                ensure!(self.storage.s3_endpoint.is_some(), "S3_ENDPOINT is required when STORAGE_MODE=s3");
+            }
+            mode => bail!("STORAGE_MODE must be 'local' or 's3', got '{}'", mode),
+        }
+
+        let processing = &self.processing;
+        let quotas = &self.quotas;
+        let positive = [
+            ("MAX_IMAGE_SIZE_MB", processing.max_image_size_mb),
+            ("MAX_IMAGE_PIXELS", processing.max_image_pixels),
+            ("MAX_VIDEO_SIZE_MB", processing.max_video_size_mb),
+            ("MAX_VIDEO_DURATION_SECONDS", processing.max_video_duration_seconds.into()),
+            ("LUT_MAX_SIZE_MB", processing.lut_max_size_mb),
+            ("WORKER_CONCURRENCY", processing.worker_concurrency as u64),
+            ("CLEANUP_INTERVAL_SECONDS", processing.cleanup_interval_seconds),
+            ("TEMP_FILE_MAX_AGE_HOURS", processing.temp_file_max_age_hours),
+            ("JOB_TIMEOUT_SECONDS", processing.job_timeout_seconds),
+            ("QUEUED_STALE_AFTER_SECONDS", processing.queued_stale_after_seconds),
+            ("URL_FETCH_TIMEOUT_SECONDS", processing.url_fetch_timeout_seconds),
+            ("UPLOAD_SESSION_TTL_HOURS", processing.upload_session_ttl_hours),
+            ("IDEMPOTENCY_KEY_TTL_HOURS", processing.idempotency_key_ttl_hours),
+            ("ASSET_RESTORE_WINDOW_HOURS", processing.asset_restore_window_hours),
+            ("DOWNLOAD_URL_TTL_SECONDS", self.storage.download_url_ttl_seconds),
+            ("FREE_TIER_STORAGE_QUOTA_BYTES", quotas.free_tier_storage_quota_bytes),
+            ("PRO_TIER_STORAGE_QUOTA_BYTES", quotas.pro_tier_storage_quota_bytes),
+            ("FREE_TIER_RESULT_RETENTION_HOURS", quotas.free_tier_result_retention_hours),
+            ("PRO_TIER_RESULT_RETENTION_HOURS", quotas.pro_tier_result_retention_hours),
+            ("LOGIN_RATE_LIMIT", self.rate_limits.login_attempts.into()),
+            ("REGISTER_RATE_LIMIT", self.rate_limits.register_attempts.into()),
+            ("RESEND_VERIFICATION_RATE_LIMIT", self.rate_limits.resend_verification_attempts.into()),
+            ("FORGOT_PASSWORD_RATE_LIMIT", self.rate_limits.forgot_password_attempts.into()),
+            ("EMAIL_VERIFICATION_TTL_HOURS", self.mail.verification_ttl_hours),
+            ("PASSWORD_RESET_TTL_MINUTES", self.mail.password_reset_ttl_minutes),
+            ("ORGANIZATION_INVITE_TTL_HOURS", self.mail.organization_invite_ttl_hours),
+            ("AUTH_RATE_LIMIT_WINDOW_SECONDS", self.rate_limits.window_seconds),
+            ("REQUEST_RATE_LIMIT_BURST", self.rate_limits.request_burst.into()),
+            ("REQUEST_RATE_LIMIT_PER_MINUTE", self.rate_limits.requests_per_minute.into()),
+            ("SCAN_TIMEOUT_SECONDS", self.scan.timeout_seconds),
+        ];
+        for (name, value) in positive {
+            ensure!(value > 0, "{} must be greater than 0", name);
+        }
+        ensure!(
+            self.storage.download_url_ttl_seconds <= MAX_DOWNLOAD_URL_TTL_SECONDS,
+            "DOWNLOAD_URL_TTL_SECONDS must be at most {} (7 days)",
+            MAX_DOWNLOAD_URL_TTL_SECONDS
+        );
+        for (job_type, seconds) in &processing.job_type_timeout_seconds {
+            ensure!(*seconds > 0, "{} must be greater than 0", job_timeout_var(job_type));
+        }
+        ensure!(
+            quotas.pro_tier_max_result_retention_hours >= quotas.pro_tier_result_retention_hours,
+            "PRO_TIER_MAX_RESULT_RETENTION_HOURS must be at least PRO_TIER_RESULT_RETENTION_HOURS"
+        );
+        ensure!(
+            self.public_url.starts_with("http://") || self.public_url.starts_with("https://"),
+            "PUBLIC_URL must start with http:// or https://"
+        );
+
+        let mail = &self.mail;
+        if mail.smtp_host.is_some() {
+            ensure!(mail.smtp_from.is_some(), "SMTP_FROM is required when SMTP_HOST is set");
+        }
+        if let Some(from) = &mail.smtp_from {
+            ensure!(from.parse::<Mailbox>().is_ok(), "SMTP_FROM is not a valid address: '{}'", from);
+        }
+        ensure!(
+            mail.smtp_username.is_some() == mail.smtp_password.is_some(),
+            "SMTP_USERNAME and SMTP_PASSWORD must be set together"
+        );
+        ensure!(
+            matches!(mail.smtp_tls.as_str(), "starttls" | "tls" | "none"),
+            "SMTP_TLS must be 'starttls', 'tls' or 'none', got '{}'",
+            mail.smtp_tls
+        );
+        if let Some(address) = &self.scan.clamd_address {
+            ensure!(
+                address.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()),
+                "CLAMD_ADDRESS must be host:port, got '{}'",
+                address
+            );
+        }
+
+        let tracing = &self.tracing;
+        ensure!(
+            (0.0..=1.0).contains(&tracing.sample_ratio),
+            "OTEL_TRACES_SAMPLER_ARG must be between 0 and 1, got {}",
+            tracing.sample_ratio
+        );
+        if let Some(endpoint) = &tracing.otlp_endpoint {
+            ensure!(
+                endpoint.parse::<axum::http::Uri>().is_ok_and(|uri| uri.scheme().is_some()),
+                "The OTLP endpoint must be an http:// or https:// URL, got '{}'",
+                endpoint
+            );
+        }
+
+        match processing.worker_mode.as_str() {
+            "embedded" => {}
+            // A separate worker process only hears about new jobs through Redis
+            "external" => ensure!(!self.redis_url.is_empty(), "REDIS_URL is required when WORKER_MODE=external"),
+            mode => bail!("WORKER_MODE must be 'embedded' or 'external', got '{}'", mode),
+        }
+        Ok(())
+    }
+}
+
+fn jwt_config(vars: &Vars) -> Result<JwtConfig, anyhow::Error> {
+    let algorithm = vars.string("JWT_ALGORITHM", "HS256");
+    let asymmetric: Option<fn(KeyPair) -> JwtKey> = match algorithm.to_uppercase().as_str() {
+        "HS256" => None,
+        "RS256" => Some(JwtKey::Rsa),
+        "EDDSA" => Some(JwtKey::Ed25519),
+        _ => bail!("JWT_ALGORITHM must be 'HS256', 'RS256' or 'EdDSA', got '{}'", algorithm),
+    };
+
+    // Secrets retired from HS256 keep working after a move to RS256 or EdDSA
+    let mut previous: Vec<JwtKey> = list(vars, "JWT_PREVIOUS_SECRETS").into_iter().map(JwtKey::Hmac).collect();
+    let current = match asymmetric {
+        None => {
+            ensure!(
+                vars.optional("JWT_PREVIOUS_PUBLIC_KEY_PATHS").is_none(),
+                "JWT_PREVIOUS_PUBLIC_KEY_PATHS needs JWT_ALGORITHM 'RS256' or 'EdDSA'"
+            );
+            JwtKey::Hmac(vars.required("JWT_SECRET")?)
+        }
+        Some(key) => {
+            for path in list(vars, "JWT_PREVIOUS_PUBLIC_KEY_PATHS") {
+                let public_pem = read_pem("JWT_PREVIOUS_PUBLIC_KEY_PATHS", &path)?;
+                previous.push(key(KeyPair { private_pem: None, public_pem }));
+            }
+            let private_path = vars.required("JWT_PRIVATE_KEY_PATH")?;
+            let public_path = vars.required("JWT_PUBLIC_KEY_PATH")?;
+            key(KeyPair {
+                private_pem: Some(read_pem("JWT_PRIVATE_KEY_PATH", &private_path)?),
+                public_pem: read_pem("JWT_PUBLIC_KEY_PATH", &public_path)?,
+            })
+        }
+    };
+    Ok(JwtConfig { current, previous })
+}
+
+fn tracing_config(vars: &Vars) -> Result<TracingConfig, anyhow::Error> {
+    let protocol = vars.string("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL", &vars.string("OTEL_EXPORTER_OTLP_PROTOCOL", ""));
+    ensure!(
+        matches!(protocol.as_str(), "" | "http/protobuf"),
+        "Only the 'http/protobuf' OTLP protocol is supported, got '{}'",
+        protocol
+    );
+    Ok(TracingConfig {
+        otlp_endpoint: vars.optional("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").or_else(|| {
+            vars.optional("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+        }),
+        service_name: vars.optional("OTEL_SERVICE_NAME"),
+        sample_ratio: vars.parse("OTEL_TRACES_SAMPLER_ARG", 1.0)?,
+    })
+}
+
+/// A comma-separated variable's non-empty entries
+fn list(vars: &Vars, name: &str) -> Vec<String> {
+    vars.optional(name)
+        .map(|value| value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn read_pem(name: &str, path: &str) -> Result<Pem, anyhow::Error> {
+    let contents = std::fs::read_to_string(path).with_context(|| format!("{}: cannot read '{}'", name, path))?;
+    Ok(Pem { path: path.to_string(), contents })
+}
+
+/// The job types given their own timeout
+fn job_type_timeouts(vars: &Vars) -> Result<BTreeMap<String, u64>, anyhow::Error> {
+    let mut timeouts = BTreeMap::new();
+    for job_type in db::JOB_TYPES {
+        let name = job_timeout_var(job_type);
+        if vars.optional(&name).is_some() {
+            timeouts.insert(job_type.to_string(), vars.parse(&name, 0)?);
+        }
+    }
+    Ok(timeouts)
+}
+
+fn job_timeout_var(job_type: &str) -> String {
+    format!("JOB_TIMEOUT_SECONDS_{}", job_type.to_uppercase())
+}
+
+/// Typed lookups with defaults, naming the variable in every error
+struct Vars<'a>(&'a dyn Fn(&str) -> Option<String>);
+
+impl Vars<'_> {
+    fn required(&self, name: &str) -> Result<String, anyhow::Error> {
+        self.optional(name).with_context(|| format!("{} must be set", name))
+    }
+
+    /// Unset and empty are the same
+    fn optional(&self, name: &str) -> Option<String> {
+        (self.0)(name).filter(|v| !v.is_empty())
     }
-}
\ No newline at end of file
+
+    fn string(&self, name: &str, default: &str) -> String {
+        self.optional(name).unwrap_or_else(|| default.to_string())
+    }
+
+    fn parse<T>(&self, name: &str, default: T) -> Result<T, anyhow::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.optional(name) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|e| anyhow::anyhow!("{} has an invalid value '{}': {}", name, value, e)),
+            None => Ok(default),
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.optional(name).is_some_and(|v| v == "true" || v == "1")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config, anyhow::Error> {
+        let vars: HashMap<String, String> = [("DATABASE_URL", "postgres://localhost/mf"), ("JWT_SECRET", "secret")]
+            .iter()
+            .chain(vars)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_vars(|name| vars.get(name).cloned())
+    }
+
+    fn error(vars: &[(&str, &str)]) -> String {
+        load(vars).expect_err("configuration should be rejected").to_string()
+    }
+
+    #[test]
+    fn test_defaults_and_overrides() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.redis_url, "redis://localhost:6379");
+        assert_eq!(config.storage.mode, "local");
+        assert_eq!(config.storage.download_url_ttl(), Duration::from_secs(3600));
+        assert_eq!(config.processing.lut_max_size_mb, 1);
+        assert_eq!(config.processing.max_image_pixels, 100_000_000);
+        assert_eq!(config.processing.worker_concurrency, 2);
+        assert_eq!(config.processing.asset_restore_window_hours, 168);
+        assert_eq!(config.processing.queued_stale_after(), Duration::from_secs(300));
+        assert!(config.processing.embedded_worker());
+        assert_eq!(config.processing.job_timeout("remove_bg"), Duration::from_secs(600));
+        assert_eq!(config.webhook_secret, None);
+        assert!(!config.rate_limits.trust_forwarded_for);
+        assert_eq!(config.rate_limits.request_burst, 30);
+        assert_eq!(config.rate_limits.requests_per_minute, 60);
+        assert_eq!(config.public_url, "http://127.0.0.1:8080");
+        assert_eq!(config.mail.smtp_host, None);
+        assert_eq!(config.scan.clamd_address, None);
+        assert!(!config.scan.fail_open);
+        assert_eq!(config.tracing.otlp_endpoint, None);
+        assert_eq!(config.tracing.sample_ratio, 1.0);
+
+        let config = load(&[
+            ("PORT", "9000"),
+            ("LUT_MAX_SIZE_MB", "4"),
+            ("STORAGE_MODE", "S3"),
+            ("S3_BUCKET", "media"),
+            ("S3_ENDPOINT", "http://minio:9000"),
+            ("WEBHOOK_SECRET", ""),
+            ("REDIS_URL", ""),
+            ("TRUST_X_FORWARDED_FOR", "1"),
+            ("JOB_TIMEOUT_SECONDS", "120"),
+            ("JOB_TIMEOUT_SECONDS_REMOVE_BG", "1800"),
+            ("PUBLIC_URL", "https://media.example.com/"),
+            ("SMTP_HOST", "smtp.example.com"),
+            ("SMTP_FROM", "MediaForge <noreply@example.com>"),
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318/"),
+            ("OTEL_TRACES_SAMPLER_ARG", "0.25"),
+        ])
+        .unwrap();
+        assert_eq!(config.tracing.otlp_endpoint.as_deref(), Some("http://collector:4318/v1/traces"));
+        assert_eq!(config.tracing.sample_ratio, 0.25);
+        assert_eq!(config.redis_url, "");
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.public_url, "https://media.example.com");
+        assert_eq!(config.mail.password_reset_url, "https://media.example.com/reset-password");
+        assert_eq!(config.mail.organization_invite_url, "https://media.example.com/accept-invite");
+        assert_eq!(config.mail.smtp_port, 587);
+        assert_eq!(config.processing.lut_max_size_mb, 4);
+        assert_eq!(config.processing.job_timeout("remove_bg"), Duration::from_secs(1800));
+        assert_eq!(config.processing.job_timeout("convert"), Duration::from_secs(120));
+        assert_eq!(config.storage.mode, "s3");
+        assert_eq!(config.webhook_secret, None);
+        assert!(config.rate_limits.trust_forwarded_for);
+    }
+
+    #[test]
+    fn test_nonsensical_values_are_rejected_by_name() {
+        let missing = Config::from_vars(|_| None).err().unwrap();
+        assert_eq!(missing.to_string(), "DATABASE_URL must be set");
+
+        assert_eq!(error(&[("JWT_SECRET", "  ")]), "JWT_SECRET must not be empty");
+        assert_eq!(error(&[("PORT", "0")]), "PORT must not be 0");
+        assert_eq!(
+            error(&[("PORT", "http")]),
+            "PORT has an invalid value 'http': invalid digit found in string"
+        );
+        assert_eq!(error(&[("LUT_MAX_SIZE_MB", "0")]), "LUT_MAX_SIZE_MB must be greater than 0");
+        assert_eq!(error(&[("WORKER_CONCURRENCY", "0")]), "WORKER_CONCURRENCY must be greater than 0");
+        assert_eq!(
+            error(&[("REQUEST_RATE_LIMIT_PER_MINUTE", "0")]),
+            "REQUEST_RATE_LIMIT_PER_MINUTE must be greater than 0"
+        );
+        assert_eq!(
+            error(&[("JOB_TIMEOUT_SECONDS_PIPELINE", "0")]),
+            "JOB_TIMEOUT_SECONDS_PIPELINE must be greater than 0"
+        );
+        assert_eq!(error(&[("STORAGE_MODE", "ftp")]), "STORAGE_MODE must be 'local' or 's3', got 'ftp'");
+        assert_eq!(error(&[("STORAGE_MODE", "s3")]), "S3_BUCKET is required when STORAGE_MODE=s3");
+        assert!(error(&[("PRO_TIER_MAX_RESULT_RETENTION_HOURS", "24")]).starts_with("PRO_TIER_MAX_RESULT_RETENTION_HOURS"));
+        assert_eq!(
+            error(&[("WORKER_MODE", "external"), ("REDIS_URL", "")]),
+            "REDIS_URL is required when WORKER_MODE=external"
+        );
+        assert_eq!(error(&[("SMTP_HOST", "smtp.example.com")]), "SMTP_FROM is required when SMTP_HOST is set");
+        assert_eq!(
+            error(&[("SMTP_USERNAME", "mailer")]),
+            "SMTP_USERNAME and SMTP_PASSWORD must be set together"
+        );
+        assert_eq!(error(&[("PUBLIC_URL", "media.example.com")]), "PUBLIC_URL must start with http:// or https://");
+        assert_eq!(
+            error(&[("DOWNLOAD_URL_TTL_SECONDS", "604801")]),
+            "DOWNLOAD_URL_TTL_SECONDS must be at most 604800 (7 days)"
+        );
+        assert!(load(&[("DOWNLOAD_URL_TTL_SECONDS", "604800")]).is_ok());
+        assert_eq!(error(&[("WORKER_MODE", "off")]), "WORKER_MODE must be 'embedded' or 'external', got 'off'");
+        assert_eq!(error(&[("CLAMD_ADDRESS", "clamav")]), "CLAMD_ADDRESS must be host:port, got 'clamav'");
+        assert_eq!(error(&[("SCAN_TIMEOUT_SECONDS", "0")]), "SCAN_TIMEOUT_SECONDS must be greater than 0");
+        assert_eq!(
+            error(&[("OTEL_TRACES_SAMPLER_ARG", "2")]),
+            "OTEL_TRACES_SAMPLER_ARG must be between 0 and 1, got 2"
+        );
+        assert_eq!(
+            error(&[("OTEL_EXPORTER_OTLP_PROTOCOL", "grpc")]),
+            "Only the 'http/protobuf' OTLP protocol is supported, got 'grpc'"
+        );
+        assert!(error(&[("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT", "collector")]).starts_with("The OTLP endpoint"));
+    }
+
+    #[test]
+    fn test_jwt_keys_are_read_from_files() {
+        let fixture = |name: &str| format!("{}/tests/fixtures/jwt/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let config = load(&[("JWT_PREVIOUS_SECRETS", "old-1, old-2,")]).unwrap();
+        assert!(matches!(&config.jwt.current, JwtKey::Hmac(secret) if secret == "secret"));
+        assert_eq!(config.jwt.previous.len(), 2);
+
+        let (private, public) = (fixture("rsa_current.pem"), fixture("rsa_current.pub.pem"));
+        let previous = fixture("rsa_previous.pub.pem");
+        let rsa = [
+            ("JWT_ALGORITHM", "rs256"),
+            ("JWT_PRIVATE_KEY_PATH", private.as_str()),
+            ("JWT_PUBLIC_KEY_PATH", public.as_str()),
+            ("JWT_PREVIOUS_PUBLIC_KEY_PATHS", previous.as_str()),
+        ];
+        let config = load(&rsa).unwrap();
+        let JwtKey::Rsa(pair) = &config.jwt.current else { panic!("expected an RSA key") };
+        assert_eq!(pair.private_pem.as_ref().unwrap().path, private);
+        assert!(matches!(&config.jwt.previous[..], [JwtKey::Rsa(KeyPair { private_pem: None, .. })]));
+
+        assert_eq!(
+            error(&[("JWT_ALGORITHM", "HS512")]),
+            "JWT_ALGORITHM must be 'HS256', 'RS256' or 'EdDSA', got 'HS512'"
+        );
+        assert_eq!(error(&[("JWT_ALGORITHM", "EdDSA")]), "JWT_PRIVATE_KEY_PATH must be set");
+        assert_eq!(
+            error(&[("JWT_PREVIOUS_PUBLIC_KEY_PATHS", previous.as_str())]),
+            "JWT_PREVIOUS_PUBLIC_KEY_PATHS needs JWT_ALGORITHM 'RS256' or 'EdDSA'"
+        );
+        let missing = error(&[("JWT_ALGORITHM", "RS256"), ("JWT_PRIVATE_KEY_PATH", "/missing.pem"), rsa[2]]);
+        assert_eq!(missing, "JWT_PRIVATE_KEY_PATH: cannot read '/missing.pem'");
+        // An Ed25519 key where an RSA one belongs
+        let ed25519 = fixture("ed25519.pub.pem");
+        let wrong = error(&[rsa[0], rsa[1], ("JWT_PUBLIC_KEY_PATH", ed25519.as_str())]);
+        assert!(wrong.starts_with(&format!("'{}' is not an RSA public key in PEM format", ed25519)), "{}", wrong);
+    }
+}
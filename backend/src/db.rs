@@ -1,5 +1,7 @@
-use chrono::{DateTime, Utc};
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::postgres::{PgPool, PgPoolOptions, Postgres};
+use sqlx::QueryBuilder;
+use std::collections::HashMap;
 use std::time::Duration;
 use uuid::Uuid;
 
@@ -25,7 +27,7 @@ pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
     sqlx::migrate!("./migrations")
         .run(pool)
         .await
-        .map_err(|e| sqlx::Error::from(e))?;
+        .map_err(sqlx::Error::from)?;
 
     Ok(())
 }
@@ -43,6 +45,101 @@ pub struct User {
     pub daily_quota: i32,
     pub concurrent_jobs_allowed: i32,
     pub created_at: DateTime<Utc>,
+    pub is_admin: bool,
+    /// The address has been confirmed through an emailed link. Jobs are
+    /// refused until it is.
+    pub email_verified: bool,
+}
+
+/// Whose resources a request reaches: the user's own, and those created in
+/// the scope of the organization they act for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub user_id: Uuid,
+    /// Set while acting for an organization; what is created then is shared
+    /// with its members
+    pub organization_id: Option<Uuid>,
+}
+
+impl Owner {
+    /// Whether a resource created by `user_id`, in the scope of
+    /// `organization_id` if set, is within reach
+    pub fn reaches(&self, user_id: Uuid, organization_id: Option<Uuid>) -> bool {
+        user_id == self.user_id || organization_id.is_some_and(|id| self.organization_id == Some(id))
+    }
+}
+
+impl From<Uuid> for Owner {
+    /// Only the user's own resources
+    fn from(user_id: Uuid) -> Self {
+        Self { user_id, organization_id: None }
+    }
+}
+
+/// A team whose members share what they create in its scope
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An organization as one of its members sees it
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Membership {
+    pub organization_id: Uuid,
+    pub name: String,
+    /// `owner` or `member`; only owners invite
+    pub role: String,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// One member of an organization, see `Organization::members`
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct OrganizationMember {
+    pub user_id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// Filters accepted by `User::list`. All fields are optional and combine with AND.
+#[derive(Debug, Clone, Default)]
+pub struct UserFilter {
+    /// Case-insensitive match on the start of the email
+    pub email_prefix: Option<String>,
+    pub tier: Option<String>,
+}
+
+impl UserFilter {
+    fn push_where<'a>(&'a self, qb: &mut QueryBuilder<'a, Postgres>) {
+        let mut first = true;
+        if let Some(prefix) = &self.email_prefix {
+            push_clause(qb, &mut first, "email ILIKE ")
+                .push_bind(format!("{}%", escape_like(prefix)));
+        }
+        if let Some(tier) = &self.tier {
+            push_clause(qb, &mut first, "subscription_tier = ").push_bind(tier);
+        }
+    }
+}
+
+/// Start the next condition with `WHERE` or `AND` as appropriate
+fn push_clause<'q, 'a>(
+    qb: &'q mut QueryBuilder<'a, Postgres>,
+    first: &mut bool,
+    clause: &str,
+) -> &'q mut QueryBuilder<'a, Postgres> {
+    qb.push(if std::mem::take(first) { " WHERE " } else { " AND " })
+        .push(clause)
+}
+
+/// Escape LIKE wildcards so user input matches literally
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
 }
 
 #[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
@@ -59,8 +156,19 @@ pub struct MediaAsset {
     pub result_location: Option<String>,
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    pub thumbnail_location: Option<String>,
+    /// Hex SHA-256 of the uploaded bytes
+    pub content_hash: Option<String>,
+    /// Set when the owner deleted the asset; it can be restored until the
+    /// restore window passes
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Set when uploaded for an organization, whose members share it
+    pub organization_id: Option<Uuid>,
 }
 
+/// Every `Job::job_type`
+pub const JOB_TYPES: &[&str] = &["convert", "remove_bg", "color_grade", "pipeline", "analyze", "lut_generate"];
+
 #[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
 pub struct Job {
     pub id: Uuid,
@@ -74,6 +182,244 @@ pub struct Job {
     pub result_location: Option<String>,
     pub created_at: DateTime<Utc>,
     pub completed_at: Option<DateTime<Utc>>,
+    /// Why the job failed, or why its last attempt did while a retry waits
+    pub error_message: Option<String>,
+    /// Machine-readable kind of `error_message`, e.g. `decode_failed`
+    pub error_code: Option<String>,
+    pub failed_at: Option<DateTime<Utc>>,
+    /// `delivered` or `failed` once the completion webhook has been attempted
+    pub webhook_status: Option<String>,
+    pub webhook_attempts: i32,
+    pub webhook_error: Option<String>,
+    /// Runs started so far, counted when the job is claimed
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// Earliest time the job may be claimed: as scheduled when submitted,
+    /// or after a retry's back-off
+    pub run_after: Option<DateTime<Utc>>,
+    /// `image` or `video`, whichever the job's assets are. Daily quotas are
+    /// counted per kind.
+    pub media_kind: Option<String>,
+    /// Hex SHA-256 of the result, set alongside `result_location`
+    pub result_etag: Option<String>,
+    /// When the cleanup sweep deletes the result, set at completion from the
+    /// owner's tier
+    pub result_expires_at: Option<DateTime<Utc>>,
+    /// Last sign of life from the worker running the job
+    pub heartbeat_at: Option<DateTime<Utc>>,
+    /// What the primary output turned out to be, set alongside
+    /// `result_location`
+    pub result: Option<sqlx::types::Json<JobResult>>,
+    /// When the job was last announced to the workers: on submission, on
+    /// going back to `queued`, and when found stalled
+    pub enqueued_at: DateTime<Utc>,
+    /// Total size of the outputs and the time spent processing, set at
+    /// completion and kept after the result expires
+    pub output_bytes: Option<i64>,
+    pub duration_ms: Option<i64>,
+    /// Set when submitted for an organization, whose members share it
+    pub organization_id: Option<Uuid>,
+    /// The UTC day the job's assets were counted against the daily quota
+    /// on; cleared once they are refunded
+    pub quota_day: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// Hex SHA-256 of the key's secret part
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+/// A LUT in a user's library, or in an organization's
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct LutFile {
+    pub id: Uuid,
+    /// The uploaded file name
+    pub name: String,
+    pub location: String,
+    pub size_bytes: i64,
+    pub created_at: DateTime<Utc>,
+    /// Set when added for an organization, whose members share it
+    pub organization_id: Option<Uuid>,
+}
+
+/// A resumable upload in progress
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Upload {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub filename: String,
+    /// Declared total size
+    pub size_bytes: i64,
+    /// Bytes stored so far; the next chunk must start here
+    pub received_bytes: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A stored object left to delete after its row went, see
+/// `User::delete_account`
+/// A file a completed job produced. Output 0 is the primary one, which
+/// `Job::result_location` also names.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct JobOutput {
+    pub job_id: Uuid,
+    pub output_index: i32,
+    pub location: String,
+    /// Name to download it under
+    pub filename: String,
+    pub size_bytes: i64,
+    pub content_type: String,
+    /// Hex SHA-256 of the file
+    pub etag: String,
+}
+
+/// Metadata of a completed job's primary output
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JobResult {
+    pub location: String,
+    /// Unset for outputs that are not images or videos, such as LUTs
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub size_bytes: i64,
+    /// File extension of the output, e.g. `png` or `mp4`
+    pub format: String,
+    /// Time spent processing, from picking the job up to its outputs being stored
+    pub duration_ms: i64,
+}
+
+/// Something that happened to a job, see `JobEvent::record`
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct JobEvent {
+    pub id: i64,
+    pub job_id: Uuid,
+    /// `queued`, `started`, `progress`, `retry_scheduled`, `worker_lost`,
+    /// `reannounced`, `completed` or `failed`
+    pub event_type: String,
+    pub detail: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Assets counted against the daily quota of a user or an organization on
+/// one UTC day
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct QuotaUsage {
+    /// The user's or the organization's id
+    pub subject_id: Uuid,
+    pub day: NaiveDate,
+    pub media_kind: String,
+    pub used: i64,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PendingDeletion {
+    pub location: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An `Idempotency-Key` a user has sent, see `crate::idempotency`
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct IdempotencyKey {
+    pub request_hash: String,
+    /// The response to replay; `None` while the first request is in flight
+    pub response_status: Option<i16>,
+    pub response_content_type: Option<String>,
+    pub response_body: Option<Vec<u8>>,
+}
+
+/// A stored object and the account whose row refers to it
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredObject {
+    pub location: String,
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Jobs submitted over a period: of one `job_type`, or of every type when it
+/// is unset. See `Job::usage`.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct JobUsage {
+    pub job_type: Option<String>,
+    pub jobs: i64,
+    pub completed: i64,
+    pub failed: i64,
+    /// Total size of what the completed ones produced
+    pub output_bytes: i64,
+    /// Mean processing time of the completed ones; `None` without any
+    pub avg_duration_ms: Option<i64>,
+}
+
+/// Jobs submitted on one UTC day
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct DailyJobUsage {
+    pub day: NaiveDate,
+    pub jobs: i64,
+    pub completed: i64,
+    pub failed: i64,
+    pub output_bytes: i64,
+}
+
+/// Uploads on one UTC day, or over a whole period when `day` is unset. See
+/// `MediaAsset::upload_usage`.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct UploadUsage {
+    pub day: Option<NaiveDate>,
+    pub uploads: i64,
+    pub uploaded_bytes: i64,
+}
+
+/// One account's jobs and uploads over a period, see `Job::usage_by_user`
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct UserUsage {
+    pub user_id: Uuid,
+    pub email: String,
+    pub jobs: i64,
+    pub completed: i64,
+    pub failed: i64,
+    pub output_bytes: i64,
+    pub uploads: i64,
+    pub uploaded_bytes: i64,
+}
+
+/// Filters accepted by `Job::list_for_user` and `Job::list_all`. All fields are optional and combine with AND.
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    pub status: Option<String>,
+    pub job_type: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl JobFilter {
+    /// Append the WHERE clause, scoped to what `owner` reaches when given
+    fn push_where<'a>(&'a self, qb: &mut QueryBuilder<'a, Postgres>, owner: Option<Owner>) {
+        let mut first = true;
+        if let Some(owner) = owner {
+            push_clause(qb, &mut first, "(user_id = ")
+                .push_bind(owner.user_id)
+                .push(" OR organization_id = ")
+                .push_bind(owner.organization_id)
+                .push(")");
+        }
+        if let Some(status) = &self.status {
+            push_clause(qb, &mut first, "status = ").push_bind(status);
+        }
+        if let Some(job_type) = &self.job_type {
+            push_clause(qb, &mut first, "job_type = ").push_bind(job_type);
+        }
+        if let Some(since) = self.since {
+            push_clause(qb, &mut first, "created_at >= ").push_bind(since);
+        }
+        if let Some(until) = self.until {
+            push_clause(qb, &mut first, "created_at < ").push_bind(until);
+        }
+    }
 }
 
 // ============================================================================
@@ -82,6 +428,7 @@ pub struct Job {
 
 impl User {
     /// Create a new user
+    #[tracing::instrument(name = "User::create", skip_all)]
     pub async fn create(
         pool: &PgPool,
         email: &str,
@@ -111,6 +458,7 @@ impl User {
     }
 
     /// Find user by email
+    #[tracing::instrument(name = "User::find_by_email", skip_all)]
     pub async fn find_by_email(pool: &PgPool, email: &str) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
             .bind(email)
@@ -119,6 +467,7 @@ impl User {
     }
 
     /// Find user by ID
+    #[tracing::instrument(name = "User::find_by_id", skip_all)]
     pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
             .bind(id)
@@ -126,32 +475,235 @@ impl User {
             .await
     }
 
-    /// Update user subscription tier
+    /// Whether the account still exists
+    #[tracing::instrument(name = "User::exists", skip_all)]
+    pub async fn exists(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Replace the user's password hash
+    #[tracing::instrument(name = "User::update_password", skip_all)]
+    pub async fn update_password(
+        pool: &PgPool,
+        user_id: Uuid,
+        password_hash: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+            .bind(password_hash)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Change the user's email, which then needs verifying. Fails with a
+    /// unique violation if another account already uses it.
+    #[tracing::instrument(name = "User::update_email", skip_all)]
+    pub async fn update_email(pool: &PgPool, user_id: Uuid, email: &str) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "UPDATE users SET email = $1, email_verified = FALSE WHERE id = $2 RETURNING *"
+        )
+            .bind(email)
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Store the hash of a verification token sent to `email`, replacing any
+    /// earlier token so only the latest link works
+    #[tracing::instrument(name = "User::store_verification_token", skip_all)]
+    pub async fn store_verification_token(
+        pool: &PgPool,
+        user_id: Uuid,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM verification_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO verification_tokens (token_hash, user_id, email, expires_at) VALUES ($1, $2, $3, $4)"
+        )
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(email)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    /// Use up the verification token with `token_hash` and mark its user
+    /// verified. `None` if the token is unknown, expired, or was sent to an
+    /// address the account no longer has.
+    #[tracing::instrument(name = "User::verify_email", skip_all)]
+    pub async fn verify_email(pool: &PgPool, token_hash: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            r#"
+            WITH token AS (
+                DELETE FROM verification_tokens WHERE token_hash = $1 RETURNING user_id, email, expires_at
+            )
+            UPDATE users SET email_verified = TRUE
+            FROM token
+            WHERE users.id = token.user_id AND users.email = token.email AND token.expires_at > NOW()
+            RETURNING users.*
+            "#
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Store the hash of a password reset token, replacing any earlier one
+    #[tracing::instrument(name = "User::store_password_reset", skip_all)]
+    pub async fn store_password_reset(
+        pool: &PgPool,
+        user_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM password_resets WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO password_resets (token_hash, user_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(token_hash)
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    /// Use up the reset token with `token_hash` and set its user's password
+    /// hash. `None` if the token is unknown, already used or expired.
+    #[tracing::instrument(name = "User::reset_password", skip_all)]
+    pub async fn reset_password(
+        pool: &PgPool,
+        token_hash: &str,
+        password_hash: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            r#"
+            WITH token AS (
+                DELETE FROM password_resets WHERE token_hash = $1 RETURNING user_id, expires_at
+            )
+            UPDATE users SET password_hash = $2
+            FROM token
+            WHERE users.id = token.user_id AND token.expires_at > NOW()
+            RETURNING users.*
+            "#
+        )
+        .bind(token_hash)
+        .bind(password_hash)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Delete the user and, through the foreign keys, everything they own:
+    /// assets, jobs (so queued ones never run), API keys, LUTs and email
+    /// tokens. The storage locations of their files are moved to
+    /// `pending_deletions` in the same transaction and returned, for the
+    /// caller to delete. `None` if there is no such user.
+    #[tracing::instrument(name = "User::delete_account", skip_all)]
+    pub async fn delete_account(pool: &PgPool, user_id: Uuid) -> Result<Option<Vec<String>>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        // Holding the row blocks uploads and jobs racing the deletion: their
+        // foreign key checks wait, then fail
+        let locked = sqlx::query("SELECT 1 FROM users WHERE id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if locked.is_none() {
+            return Ok(None);
+        }
+
+        let locations = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT location FROM (
+                SELECT result_location FROM media_assets WHERE user_id = $1
+                UNION SELECT thumbnail_location FROM media_assets WHERE user_id = $1
+                UNION SELECT result_location FROM jobs WHERE user_id = $1
+                UNION SELECT o.location FROM job_outputs o JOIN jobs j ON j.id = o.job_id WHERE j.user_id = $1
+                UNION SELECT location FROM luts WHERE user_id = $1
+            ) AS owned (location)
+            WHERE location IS NOT NULL
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        sqlx::query("INSERT INTO pending_deletions (location) SELECT UNNEST($1::TEXT[]) ON CONFLICT DO NOTHING")
+            .bind(&locations)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(Some(locations))
+    }
+
+    /// List users (oldest first) with optional filters, returning the page and total count
+    #[tracing::instrument(name = "User::list", skip_all)]
+    pub async fn list(
+        pool: &PgPool,
+        filter: &UserFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Self>, i64), sqlx::Error> {
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT * FROM users");
+        filter.push_where(&mut qb);
+        qb.push(" ORDER BY created_at ASC, id LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+        let users = qb.build_query_as::<User>().fetch_all(pool).await?;
+
+        let mut count_qb = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users");
+        filter.push_where(&mut count_qb);
+        let total = count_qb.build_query_scalar::<i64>().fetch_one(pool).await?;
+
+        Ok((users, total))
+    }
+
+    /// Update user subscription tier, along with the quotas that go with it.
+    /// Returns the updated user, or `None` if there is no such user.
+    #[tracing::instrument(name = "User::update_tier", skip_all)]
     pub async fn update_tier(
         pool: &PgPool,
         user_id: Uuid,
         tier: &str,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<Option<Self>, sqlx::Error> {
         let (daily_quota, concurrent_jobs) = match tier {
             "pro" => (999999, 5),
             _ => (10, 1),
         };
 
-        sqlx::query(
+        sqlx::query_as::<_, User>(
             r#"
             UPDATE users 
             SET subscription_tier = $1, daily_quota = $2, concurrent_jobs_allowed = $3
             WHERE id = $4
+            RETURNING *
             "#
         )
         .bind(tier)
         .bind(daily_quota)
         .bind(concurrent_jobs)
         .bind(user_id)
-        .execute(pool)
-        .await?;
-
-        Ok(())
+        .fetch_optional(pool)
+        .await
     }
 }
 
@@ -159,36 +711,101 @@ impl User {
 // MediaAsset Repository
 // ============================================================================
 
+/// How long an upload is kept before the cleanup sweep removes it
+const ASSET_TTL_HOURS: i64 = 24;
+
 impl MediaAsset {
-    /// Create a new media asset
+    /// Create a new media asset, recording the upload for usage statistics
+    #[tracing::instrument(name = "MediaAsset::create", skip_all)]
     pub async fn create(
         pool: &PgPool,
-        user_id: Uuid,
+        owner: Owner,
         filename: &str,
         format: &str,
         size_bytes: i64,
+        content_hash: Option<&str>,
     ) -> Result<Self, sqlx::Error> {
         sqlx::query_as::<_, MediaAsset>(
             r#"
-            INSERT INTO media_assets 
-            (id, user_id, original_filename, format, size_bytes, status, created_at, expires_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            RETURNING *
+            WITH asset AS (
+                INSERT INTO media_assets
+                (id, user_id, original_filename, format, size_bytes, status, created_at, expires_at, content_hash,
+                 organization_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                RETURNING *
+            ), upload AS (
+                INSERT INTO asset_uploads (asset_id, user_id, size_bytes, created_at)
+                SELECT id, user_id, size_bytes, created_at FROM asset
+            )
+            SELECT * FROM asset
             "#
         )
         .bind(Uuid::new_v4())
-        .bind(user_id)
+        .bind(owner.user_id)
         .bind(filename)
         .bind(format)
         .bind(size_bytes)
         .bind("uploaded")
         .bind(Utc::now())
-        .bind(Utc::now() + chrono::Duration::hours(24))
+        .bind(Utc::now() + chrono::Duration::hours(ASSET_TTL_HOURS))
+        .bind(content_hash)
+        .bind(owner.organization_id)
         .fetch_one(pool)
         .await
     }
 
+    /// Original filenames of whichever of `ids` still exist
+    #[tracing::instrument(name = "MediaAsset::filenames", skip_all)]
+    pub async fn filenames(pool: &PgPool, ids: &[Uuid]) -> Result<HashMap<Uuid, String>, sqlx::Error> {
+        let rows: Vec<(Uuid, String)> =
+            sqlx::query_as("SELECT id, original_filename FROM media_assets WHERE id = ANY($1)")
+                .bind(ids)
+                .fetch_all(pool)
+                .await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    /// The user's newest unexpired upload with these exact bytes. Never
+    /// looks at other users' assets, nor across scopes: a personal upload is
+    /// not handed back for an organization, or the other way round.
+    #[tracing::instrument(name = "MediaAsset::find_by_hash", skip_all)]
+    pub async fn find_by_hash(
+        pool: &PgPool,
+        owner: Owner,
+        content_hash: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, MediaAsset>(
+            r#"
+            SELECT * FROM media_assets
+            WHERE user_id = $1 AND content_hash = $2 AND status = 'uploaded'
+              AND organization_id IS NOT DISTINCT FROM $3
+              AND result_location IS NOT NULL AND deleted_at IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW())
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#
+        )
+        .bind(owner.user_id)
+        .bind(content_hash)
+        .bind(owner.organization_id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Give an asset at least the lifetime of a fresh upload
+    #[tracing::instrument(name = "MediaAsset::renew", skip_all)]
+    pub async fn renew(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, MediaAsset>(
+            "UPDATE media_assets SET expires_at = GREATEST(expires_at, $1) WHERE id = $2 RETURNING *"
+        )
+        .bind(Utc::now() + chrono::Duration::hours(ASSET_TTL_HOURS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
     /// Update asset status and result location
+    #[tracing::instrument(name = "MediaAsset::update_status", skip_all)]
     pub async fn update_status(
         pool: &PgPool,
         id: Uuid,
@@ -207,7 +824,42 @@ impl MediaAsset {
         Ok(())
     }
 
+    /// Record probed dimensions / duration for an asset
+    #[tracing::instrument(name = "MediaAsset::update_metadata", skip_all)]
+    pub async fn update_metadata(
+        pool: &PgPool,
+        id: Uuid,
+        width: Option<i32>,
+        height: Option<i32>,
+        duration_seconds: Option<i32>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE media_assets SET width = $1, height = $2, duration_seconds = $3 WHERE id = $4"
+        )
+        .bind(width)
+        .bind(height)
+        .bind(duration_seconds)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record the thumbnail location. Returns false when the asset no longer exists.
+    #[tracing::instrument(name = "MediaAsset::set_thumbnail", skip_all)]
+    pub async fn set_thumbnail(pool: &PgPool, id: Uuid, location: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE media_assets SET thumbnail_location = $1 WHERE id = $2")
+            .bind(location)
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     /// Find asset by ID
+    #[tracing::instrument(name = "MediaAsset::find_by_id", skip_all)]
     pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, MediaAsset>("SELECT * FROM media_assets WHERE id = $1")
             .bind(id)
@@ -215,87 +867,348 @@ impl MediaAsset {
             .await
     }
 
-    /// Get user's assets
+    /// Get the assets `owner` reaches that are not deleted, newest first,
+    /// optionally filtered by status
+    #[tracing::instrument(name = "MediaAsset::find_by_user", skip_all)]
     pub async fn find_by_user(
         pool: &PgPool,
-        user_id: Uuid,
+        owner: Owner,
+        status: Option<&str>,
         limit: i64,
+        offset: i64,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, MediaAsset>(
-            "SELECT * FROM media_assets WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2"
+            r#"
+            SELECT * FROM media_assets
+            WHERE (user_id = $1 OR organization_id = $5) AND deleted_at IS NULL
+              AND ($2::text IS NULL OR status = $2)
+            ORDER BY created_at DESC
+            LIMIT $3 OFFSET $4
+            "#
         )
-        .bind(user_id)
+        .bind(owner.user_id)
+        .bind(status)
         .bind(limit)
+        .bind(offset)
+        .bind(owner.organization_id)
         .fetch_all(pool)
         .await
     }
 
-    /// Delete expired assets
-    pub async fn delete_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
-        let result = sqlx::query(
-            "DELETE FROM media_assets WHERE expires_at < $1"
-        )
-        .bind(Utc::now())
-        .execute(pool)
-        .await?;
-
-        Ok(result.rows_affected())
-    }
-}
-
-// ============================================================================
-// Job Repository
-// ============================================================================
-
-impl Job {
-    /// Create a new job
-    pub async fn create(
+    /// Count the assets matching the same filter as `find_by_user`
+    #[tracing::instrument(name = "MediaAsset::count_by_user", skip_all)]
+    pub async fn count_by_user(
         pool: &PgPool,
-        user_id: Uuid,
-        asset_ids: Vec<Uuid>,
-        job_type: &str,
-        parameters: serde_json::Value,
-        priority: i32,
-    ) -> Result<Self, sqlx::Error> {
-        sqlx::query_as::<_, Job>(
+        owner: Owner,
+        status: Option<&str>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
             r#"
-            INSERT INTO jobs 
-            (id, user_id, media_asset_ids, job_type, parameters, status, progress_percent, priority)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            RETURNING *
+            SELECT COUNT(*) FROM media_assets
+            WHERE (user_id = $1 OR organization_id = $3) AND deleted_at IS NULL
+              AND ($2::text IS NULL OR status = $2)
             "#
         )
-        .bind(Uuid::new_v4())
-        .bind(user_id)
-        .bind(serde_json::to_value(asset_ids).unwrap())
-        .bind(job_type)
-        .bind(parameters)
-        .bind("queued")
-        .bind(0)
-        .bind(priority)
+        .bind(owner.user_id)
+        .bind(status)
+        .bind(owner.organization_id)
         .fetch_one(pool)
         .await
     }
 
-    /// Find job by ID
-    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
-        sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1")
-            .bind(id)
-            .fetch_optional(pool)
-            .await
+    /// Mark an asset deleted, keeping its row and files for a restore.
+    /// `None` if it is gone or already deleted.
+    #[tracing::instrument(name = "MediaAsset::soft_delete", skip_all)]
+    pub async fn soft_delete(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, MediaAsset>(
+            "UPDATE media_assets SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL RETURNING *"
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
     }
 
-    /// Update job progress
-    pub async fn update_progress(
+    /// Undo `soft_delete` for an asset deleted after `deleted_since`. `None`
+    /// if it is not deleted, or was deleted before then.
+    #[tracing::instrument(name = "MediaAsset::restore", skip_all)]
+    pub async fn restore(pool: &PgPool, id: Uuid, deleted_since: DateTime<Utc>) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, MediaAsset>(
+            "UPDATE media_assets SET deleted_at = NULL WHERE id = $1 AND deleted_at > $2 RETURNING *"
+        )
+        .bind(id)
+        .bind(deleted_since)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Delete a single asset row
+    #[tracing::instrument(name = "MediaAsset::delete", skip_all)]
+    pub async fn delete(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM media_assets WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Total size of the user's assets that have neither expired nor been
+    /// deleted. Deleted ones stop counting at once, though their files stay
+    /// until the restore window passes.
+    #[tracing::instrument(name = "MediaAsset::storage_used", skip_all)]
+    pub async fn storage_used(pool: &PgPool, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COALESCE(SUM(size_bytes), 0)::BIGINT FROM media_assets
+            WHERE user_id = $1 AND deleted_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())
+            "#
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Uploads in `[from, to)`, all of them or the user's: a row per UTC day,
+    /// days without any included, then the total. Counted from the record
+    /// of each upload, which outlives the asset.
+    #[tracing::instrument(name = "MediaAsset::upload_usage", skip_all)]
+    pub async fn upload_usage(
+        pool: &PgPool,
+        user_id: Option<Uuid>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<UploadUsage>, sqlx::Error> {
+        sqlx::query_as::<_, UploadUsage>(
+            r#"
+            WITH daily AS (
+                SELECT date_trunc('day', created_at AT TIME ZONE 'UTC') AS day,
+                       COUNT(*) AS uploads, SUM(size_bytes) AS uploaded_bytes
+                FROM asset_uploads
+                WHERE ($1::UUID IS NULL OR user_id = $1) AND created_at >= $2 AND created_at < $3
+                GROUP BY 1
+            )
+            SELECT days.day::DATE AS day, COALESCE(SUM(d.uploads), 0)::BIGINT AS uploads,
+                   COALESCE(SUM(d.uploaded_bytes), 0)::BIGINT AS uploaded_bytes
+            FROM generate_series(
+                date_trunc('day', $2 AT TIME ZONE 'UTC'),
+                date_trunc('day', ($3 - INTERVAL '1 microsecond') AT TIME ZONE 'UTC'),
+                INTERVAL '1 day'
+            ) AS days (day)
+            LEFT JOIN daily d ON d.day = days.day
+            GROUP BY GROUPING SETS ((days.day), ())
+            ORDER BY days.day NULLS LAST
+            "#
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// The cached result of analyzing the asset, if it has been
+    #[tracing::instrument(name = "MediaAsset::analysis", skip_all)]
+    pub async fn analysis(pool: &PgPool, id: Uuid) -> Result<Option<serde_json::Value>, sqlx::Error> {
+        sqlx::query_scalar("SELECT analysis FROM asset_analyses WHERE asset_id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Cache an analysis, replacing any earlier one. Returns false when the
+    /// asset no longer exists.
+    #[tracing::instrument(name = "MediaAsset::store_analysis", skip_all)]
+    pub async fn store_analysis(pool: &PgPool, id: Uuid, analysis: &serde_json::Value) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO asset_analyses (asset_id, analysis)
+            SELECT id, $2 FROM media_assets WHERE id = $1
+            ON CONFLICT (asset_id) DO UPDATE SET analysis = EXCLUDED.analysis, created_at = NOW()
+            "#
+        )
+        .bind(id)
+        .bind(analysis)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Assets that have expired, or were deleted before `deleted_before`,
+    /// that no queued or processing job still needs, oldest first
+    #[tracing::instrument(name = "MediaAsset::find_expired", skip_all)]
+    pub async fn find_expired(
+        pool: &PgPool,
+        limit: i64,
+        deleted_before: DateTime<Utc>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, MediaAsset>(
+            r#"
+            SELECT * FROM media_assets a
+            WHERE (a.expires_at < NOW() OR a.deleted_at < $2)
+              AND NOT EXISTS (
+                SELECT 1 FROM jobs j
+                WHERE j.media_asset_ids ? a.id::text AND j.status IN ('queued', 'processing')
+              )
+            ORDER BY LEAST(a.expires_at, a.deleted_at)
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .bind(deleted_before)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+// ============================================================================
+// Job Repository
+// ============================================================================
+
+/// Longest error message stored on a job; longer ones are cut short
+pub const MAX_ERROR_MESSAGE_CHARS: usize = 2048;
+
+/// `error` as stored: at most `MAX_ERROR_MESSAGE_CHARS`, ending in an
+/// ellipsis if it was cut
+fn truncate_error(error: &str) -> std::borrow::Cow<'_, str> {
+    match error.char_indices().nth(MAX_ERROR_MESSAGE_CHARS) {
+        None => error.into(),
+        Some(_) => {
+            let (cut, _) = error.char_indices().nth(MAX_ERROR_MESSAGE_CHARS - 1).unwrap();
+            format!("{}…", &error[..cut]).into()
+        }
+    }
+}
+
+/// Error recorded on a job whose worker stopped sending heartbeats
+const WORKER_LOST_MESSAGE: &str = "The worker running the job stopped responding";
+
+impl Job {
+
+    /// ID of the HTTP request that created the job, for correlating logs
+    pub fn request_id(&self) -> Option<&str> {
+        self.parameters.get("request_id").and_then(|v| v.as_str())
+    }
+
+    /// W3C trace context of the request's span, when spans were exported
+    pub fn trace_context(&self) -> Option<&serde_json::Value> {
+        self.parameters.get("trace_context")
+    }
+
+    /// Whether the result is past its expiry, whether or not the sweep has
+    /// deleted it yet
+    pub fn result_expired(&self, now: DateTime<Utc>) -> bool {
+        self.result_expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Who the job runs for: its submitter, in the scope it was submitted in
+    pub fn owner(&self) -> Owner {
+        Owner { user_id: self.user_id, organization_id: self.organization_id }
+    }
+
+    /// Create a new job, not to be claimed before `run_after` if given
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(name = "Job::create", skip_all)]
+    pub async fn create(
+        pool: &PgPool,
+        owner: Owner,
+        asset_ids: Vec<Uuid>,
+        job_type: &str,
+        media_kind: &str,
+        parameters: serde_json::Value,
+        priority: i32,
+        run_after: Option<DateTime<Utc>>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            r#"
+            INSERT INTO jobs 
+            (id, user_id, organization_id, media_asset_ids, job_type, media_kind, parameters, status, progress_percent,
+             priority, run_after, quota_day)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, (NOW() AT TIME ZONE 'UTC')::date)
+            RETURNING *
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(owner.user_id)
+        .bind(owner.organization_id)
+        .bind(serde_json::to_value(asset_ids).unwrap())
+        .bind(job_type)
+        .bind(media_kind)
+        .bind(parameters)
+        .bind("queued")
+        .bind(0)
+        .bind(priority)
+        .bind(run_after)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Find job by ID
+    #[tracing::instrument(name = "Job::find_by_id", skip_all)]
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// List the jobs `owner` reaches (newest first) with optional filters,
+    /// returning the page and total count
+    #[tracing::instrument(name = "Job::list_for_user", skip_all)]
+    pub async fn list_for_user(
+        pool: &PgPool,
+        owner: Owner,
+        filter: &JobFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Self>, i64), sqlx::Error> {
+        Self::list(pool, Some(owner), filter, limit, offset).await
+    }
+
+    /// List jobs across all users (newest first), for support
+    #[tracing::instrument(name = "Job::list_all", skip_all)]
+    pub async fn list_all(
+        pool: &PgPool,
+        filter: &JobFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Self>, i64), sqlx::Error> {
+        Self::list(pool, None, filter, limit, offset).await
+    }
+
+    async fn list(
+        pool: &PgPool,
+        owner: Option<Owner>,
+        filter: &JobFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Self>, i64), sqlx::Error> {
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT * FROM jobs");
+        filter.push_where(&mut qb, owner);
+        qb.push(" ORDER BY created_at DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+        let jobs = qb.build_query_as::<Job>().fetch_all(pool).await?;
+
+        let mut count_qb = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM jobs");
+        filter.push_where(&mut count_qb, owner);
+        let total = count_qb.build_query_scalar::<i64>().fetch_one(pool).await?;
+
+        Ok((jobs, total))
+    }
+
+    /// Record a running job's progress. Jobs no longer `processing` keep
+    /// what they have, so a late update can't overwrite a finished job's 100.
+    #[tracing::instrument(name = "Job::update_progress", skip_all)]
+    pub async fn update_progress(
         pool: &PgPool,
         id: Uuid,
-        status: &str,
         progress: i32,
     ) -> Result<(), sqlx::Error> {
         sqlx::query(
-            "UPDATE jobs SET status = $1, progress_percent = $2 WHERE id = $3"
+            "UPDATE jobs SET progress_percent = $1 WHERE id = $2 AND status = 'processing'"
         )
-        .bind(status)
         .bind(progress)
         .bind(id)
         .execute(pool)
@@ -304,21 +1217,132 @@ impl Job {
         Ok(())
     }
 
-    /// Mark job as completed
+    /// Mark job as completed with `outputs`, recorded in order; the first is
+    /// the primary result. `false` if the job is gone, its owner having
+    /// deleted their account meanwhile.
+    #[tracing::instrument(name = "Job::complete", skip_all)]
     pub async fn complete(
         pool: &PgPool,
         id: Uuid,
-        result_location: &str,
-    ) -> Result<(), sqlx::Error> {
-        sqlx::query(
+        result: &JobResult,
+        outputs: &[JobOutput],
+        retention: chrono::Duration,
+    ) -> Result<bool, sqlx::Error> {
+        let primary = outputs.first().expect("a completed job has at least one output");
+        let completed_at = Utc::now();
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query(
             r#"
             UPDATE jobs 
-            SET status = 'completed', progress_percent = 100, result_location = $1, completed_at = $2
+            SET status = 'completed', progress_percent = 100, result_location = $1, result_etag = $4,
+                completed_at = $2, result_expires_at = $5, result = $6, error_message = NULL, error_code = NULL,
+                output_bytes = $7, duration_ms = $8
             WHERE id = $3
             "#
         )
-        .bind(result_location)
-        .bind(Utc::now())
+        .bind(&primary.location)
+        .bind(completed_at)
+        .bind(id)
+        .bind(&primary.etag)
+        .bind(completed_at + retention)
+        .bind(sqlx::types::Json(result))
+        .bind(outputs.iter().map(|o| o.size_bytes).sum::<i64>())
+        .bind(result.duration_ms)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("DELETE FROM job_outputs WHERE job_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO job_outputs (job_id, output_index, location, filename, size_bytes, content_type, etag)
+            SELECT $1, * FROM UNNEST($2::INT[], $3::TEXT[], $4::TEXT[], $5::BIGINT[], $6::TEXT[], $7::TEXT[])
+            "#
+        )
+        .bind(id)
+        .bind((0..outputs.len() as i32).collect::<Vec<_>>())
+        .bind(outputs.iter().map(|o| o.location.clone()).collect::<Vec<_>>())
+        .bind(outputs.iter().map(|o| o.filename.clone()).collect::<Vec<_>>())
+        .bind(outputs.iter().map(|o| o.size_bytes).collect::<Vec<_>>())
+        .bind(outputs.iter().map(|o| o.content_type.clone()).collect::<Vec<_>>())
+        .bind(outputs.iter().map(|o| o.etag.clone()).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Completed jobs whose result has expired but is still stored
+    #[tracing::instrument(name = "Job::find_expired_results", skip_all)]
+    pub async fn find_expired_results(pool: &PgPool, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            r#"
+            SELECT * FROM jobs
+            WHERE status = 'completed' AND result_location IS NOT NULL AND result_expires_at < NOW()
+            ORDER BY result_expires_at
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Move a result's expiry, unless it has already expired
+    #[tracing::instrument(name = "Job::extend_result", skip_all)]
+    pub async fn extend_result(
+        pool: &PgPool,
+        id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs SET result_expires_at = $1
+            WHERE id = $2 AND result_location IS NOT NULL AND result_expires_at > NOW()
+            RETURNING *
+            "#
+        )
+        .bind(expires_at)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Forget a job's result file once it has been deleted from storage
+    #[tracing::instrument(name = "Job::clear_result", skip_all)]
+    pub async fn clear_result(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("UPDATE jobs SET result_location = NULL, result_etag = NULL, result = NULL WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM job_outputs WHERE job_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    /// Set a single top-level key in the job's parameters
+    #[tracing::instrument(name = "Job::set_parameter", skip_all)]
+    pub async fn set_parameter(
+        pool: &PgPool,
+        id: Uuid,
+        key: &str,
+        value: serde_json::Value,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE jobs SET parameters = jsonb_set(COALESCE(parameters, '{}'::jsonb), ARRAY[$1], $2) WHERE id = $3"
+        )
+        .bind(key)
+        .bind(value)
         .bind(id)
         .execute(pool)
         .await?;
@@ -326,72 +1350,1705 @@ impl Job {
         Ok(())
     }
 
-    /// Mark job as failed
-    pub async fn fail(pool: &PgPool, id: Uuid, error: &str) -> Result<(), sqlx::Error> {
+    /// Mark job as failed, with `error` for people and `code` for clients
+    #[tracing::instrument(name = "Job::fail", skip_all)]
+    pub async fn fail(pool: &PgPool, id: Uuid, error: &str, code: &str) -> Result<(), sqlx::Error> {
         sqlx::query(
-            "UPDATE jobs SET status = 'failed', parameters = jsonb_set(parameters, '{error}', $1) WHERE id = $2"
+            "UPDATE jobs SET status = 'failed', error_message = $1, error_code = $3, failed_at = NOW() WHERE id = $2"
         )
-        .bind(serde_json::to_value(error).unwrap())
+        .bind(truncate_error(error))
         .bind(id)
+        .bind(code)
         .execute(pool)
         .await?;
 
         Ok(())
     }
 
-    /// Get count of user's jobs today
-    pub async fn get_user_jobs_today(
+    /// Put a job that failed transiently back to `queued`, claimable again
+    /// once `delay` has passed. The error is kept so status checks show why.
+    #[tracing::instrument(name = "Job::requeue", skip_all)]
+    pub async fn requeue(
         pool: &PgPool,
-        user_id: Uuid,
-        job_type: Option<&str>,
-    ) -> Result<i64, sqlx::Error> {
-        let today_start = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        id: Uuid,
+        delay: std::time::Duration,
+        error: &str,
+        code: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = 'queued', progress_percent = 0, error_message = $1, error_code = $4,
+                run_after = NOW() + make_interval(secs => $2), enqueued_at = NOW()
+            WHERE id = $3
+            "#
+        )
+        .bind(truncate_error(error))
+        .bind(delay.as_secs_f64())
+        .bind(id)
+        .bind(code)
+        .execute(pool)
+        .await?;
 
-        let count = if let Some(jt) = job_type {
-            sqlx::query_scalar::<_, i64>(
-                "SELECT COUNT(*) FROM jobs WHERE user_id = $1 AND job_type = $2 AND created_at >= $3"
-            )
-            .bind(user_id)
-            .bind(jt)
-            .bind(today_start)
-            .fetch_one(pool)
-            .await?
-        } else {
-            sqlx::query_scalar::<_, i64>(
-                "SELECT COUNT(*) FROM jobs WHERE user_id = $1 AND created_at >= $2"
+        Ok(())
+    }
+
+    /// Reset a failed job for a manual retry, with a fresh set of attempts.
+    /// A job whose quota was refunded is counted again today. Returns `None`
+    /// if the job is not (or no longer) failed.
+    #[tracing::instrument(name = "Job::retry", skip_all)]
+    pub async fn retry(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs
+            SET status = 'queued', progress_percent = 0, attempts = 0, run_after = NULL, enqueued_at = NOW(),
+                error_message = NULL, error_code = NULL, failed_at = NULL, result_location = NULL,
+                result_etag = NULL, result_expires_at = NULL, result = NULL, completed_at = NULL,
+                quota_day = COALESCE(quota_day, (NOW() AT TIME ZONE 'UTC')::date)
+            WHERE id = $1 AND status = 'failed'
+            RETURNING *
+            "#
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Give a failed job's assets back to the daily quota of the day they
+    /// were counted on. Returns `false` if the job is not failed or its quota
+    /// was already refunded.
+    #[tracing::instrument(name = "Job::refund_quota", skip_all)]
+    pub async fn refund_quota(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+        let refunded: Option<NaiveDate> = sqlx::query_scalar(
+            r#"
+            WITH job AS (
+                UPDATE jobs j SET quota_day = NULL
+                FROM (SELECT id, quota_day FROM jobs WHERE id = $1 FOR UPDATE) old
+                WHERE j.id = old.id AND j.status = 'failed' AND j.quota_day IS NOT NULL
+                RETURNING j.user_id, j.organization_id, j.media_kind, old.quota_day,
+                          jsonb_array_length(j.media_asset_ids)::BIGINT AS assets
+            ), refund AS (
+                UPDATE quota_usage q SET used = GREATEST(q.used - job.assets, 0)
+                FROM job
+                WHERE q.day = job.quota_day AND q.media_kind = job.media_kind
+                  AND q.subject_id IN (job.user_id, job.organization_id)
             )
-            .bind(user_id)
-            .bind(today_start)
-            .fetch_one(pool)
-            .await?
-        };
+            SELECT quota_day FROM job
+            "#
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
 
-        Ok(count)
+        Ok(refunded.is_some())
     }
 
-    /// Get user's active jobs count
-    pub async fn get_active_jobs_count(
-        pool: &PgPool,
-        user_id: Uuid,
-    ) -> Result<i64, sqlx::Error> {
-        sqlx::query_scalar::<_, i64>(
-            "SELECT COUNT(*) FROM jobs WHERE user_id = $1 AND status IN ('queued', 'processing')"
+    /// Mark `ids` as still being worked on
+    #[tracing::instrument(name = "Job::heartbeat", skip_all)]
+    pub async fn heartbeat(pool: &PgPool, ids: &[Uuid]) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE jobs SET heartbeat_at = NOW() WHERE id = ANY($1) AND status = 'processing'")
+            .bind(ids)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Put `processing` jobs whose worker has not been heard from for
+    /// `stale_after` back to `queued`, or fail them with `worker_lost` once
+    /// they have used up their attempts, so a job that keeps taking its
+    /// worker down is not run forever
+    #[tracing::instrument(name = "Job::reset_stale", skip_all)]
+    pub async fn reset_stale(pool: &PgPool, stale_after: Duration) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs SET progress_percent = 0, enqueued_at = NOW(),
+                status = CASE WHEN attempts >= max_attempts THEN 'failed' ELSE 'queued' END,
+                error_message = $2,
+                error_code = 'worker_lost',
+                failed_at = CASE WHEN attempts >= max_attempts THEN NOW() END
+            WHERE status = 'processing'
+              AND (heartbeat_at IS NULL OR heartbeat_at < NOW() - make_interval(secs => $1))
+            RETURNING *
+            "#
         )
-        .bind(user_id)
-        .fetch_one(pool)
+        .bind(stale_after.as_secs_f64())
+        .bind(WORKER_LOST_MESSAGE)
+        .fetch_all(pool)
         .await
     }
 
-    /// Get pending jobs (for worker)
-    pub async fn get_pending_jobs(
+    /// Due `queued` jobs that have waited `stale_after` since they were last
+    /// announced without a worker claiming them, only `id` when given. Their
+    /// `enqueued_at` is moved to now, so each is handed out to one caller for
+    /// announcing again.
+    #[tracing::instrument(name = "Job::take_stalled", skip_all)]
+    pub async fn take_stalled(
         pool: &PgPool,
-        limit: i64,
+        stale_after: Duration,
+        id: Option<Uuid>,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, Job>(
-            "SELECT * FROM jobs WHERE status = 'queued' ORDER BY priority DESC, created_at ASC LIMIT $1"
+            r#"
+            UPDATE jobs SET enqueued_at = NOW()
+            WHERE status = 'queued'
+              AND ($2::uuid IS NULL OR id = $2)
+              AND GREATEST(enqueued_at, COALESCE(run_after, enqueued_at)) < NOW() - make_interval(secs => $1)
+            RETURNING *
+            "#
         )
-        .bind(limit)
+        .bind(stale_after.as_secs_f64())
+        .bind(id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Record the outcome of the completion webhook
+    #[tracing::instrument(name = "Job::record_webhook", skip_all)]
+    pub async fn record_webhook(
+        pool: &PgPool,
+        id: Uuid,
+        delivered: bool,
+        attempts: i32,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE jobs SET webhook_status = $1, webhook_attempts = $2, webhook_error = $3 WHERE id = $4"
+        )
+        .bind(if delivered { "delivered" } else { "failed" })
+        .bind(attempts)
+        .bind(error)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Jobs submitted in `[from, to)`, all of them or the user's: a row per
+    /// job type that has any, then the total
+    #[tracing::instrument(name = "Job::usage", skip_all)]
+    pub async fn usage(
+        pool: &PgPool,
+        user_id: Option<Uuid>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<JobUsage>, sqlx::Error> {
+        sqlx::query_as::<_, JobUsage>(
+            r#"
+            SELECT job_type, COUNT(*) AS jobs,
+                   COUNT(*) FILTER (WHERE status = 'completed') AS completed,
+                   COUNT(*) FILTER (WHERE status = 'failed') AS failed,
+                   COALESCE(SUM(output_bytes), 0)::BIGINT AS output_bytes,
+                   ROUND(AVG(duration_ms))::BIGINT AS avg_duration_ms
+            FROM jobs
+            WHERE ($1::UUID IS NULL OR user_id = $1) AND created_at >= $2 AND created_at < $3
+            GROUP BY GROUPING SETS ((job_type), ())
+            ORDER BY job_type NULLS LAST
+            "#
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
         .fetch_all(pool)
         .await
     }
-}
\ No newline at end of file
+
+    /// Jobs submitted in `[from, to)`, all of them or the user's, per UTC
+    /// day. Days without any are included.
+    #[tracing::instrument(name = "Job::daily_usage", skip_all)]
+    pub async fn daily_usage(
+        pool: &PgPool,
+        user_id: Option<Uuid>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DailyJobUsage>, sqlx::Error> {
+        sqlx::query_as::<_, DailyJobUsage>(
+            r#"
+            WITH daily AS (
+                SELECT date_trunc('day', created_at AT TIME ZONE 'UTC') AS day, COUNT(*) AS jobs,
+                       COUNT(*) FILTER (WHERE status = 'completed') AS completed,
+                       COUNT(*) FILTER (WHERE status = 'failed') AS failed,
+                       SUM(output_bytes) AS output_bytes
+                FROM jobs
+                WHERE ($1::UUID IS NULL OR user_id = $1) AND created_at >= $2 AND created_at < $3
+                GROUP BY 1
+            )
+            SELECT days.day::DATE AS day, COALESCE(d.jobs, 0) AS jobs, COALESCE(d.completed, 0) AS completed,
+                   COALESCE(d.failed, 0) AS failed, COALESCE(d.output_bytes, 0)::BIGINT AS output_bytes
+            FROM generate_series(
+                date_trunc('day', $2 AT TIME ZONE 'UTC'),
+                date_trunc('day', ($3 - INTERVAL '1 microsecond') AT TIME ZONE 'UTC'),
+                INTERVAL '1 day'
+            ) AS days (day)
+            LEFT JOIN daily d ON d.day = days.day
+            ORDER BY days.day
+            "#
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Jobs and uploads in `[from, to)` per account that had any, most jobs
+    /// first, at most `limit` accounts
+    #[tracing::instrument(name = "Job::usage_by_user", skip_all)]
+    pub async fn usage_by_user(
+        pool: &PgPool,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<UserUsage>, sqlx::Error> {
+        sqlx::query_as::<_, UserUsage>(
+            r#"
+            WITH job_usage AS (
+                SELECT user_id, COUNT(*) AS jobs,
+                       COUNT(*) FILTER (WHERE status = 'completed') AS completed,
+                       COUNT(*) FILTER (WHERE status = 'failed') AS failed,
+                       SUM(output_bytes) AS output_bytes
+                FROM jobs
+                WHERE created_at >= $1 AND created_at < $2
+                GROUP BY user_id
+            ), upload_usage AS (
+                SELECT user_id, COUNT(*) AS uploads, SUM(size_bytes) AS uploaded_bytes
+                FROM asset_uploads
+                WHERE created_at >= $1 AND created_at < $2
+                GROUP BY user_id
+            )
+            SELECT u.id AS user_id, u.email, COALESCE(j.jobs, 0) AS jobs, COALESCE(j.completed, 0) AS completed,
+                   COALESCE(j.failed, 0) AS failed, COALESCE(j.output_bytes, 0)::BIGINT AS output_bytes,
+                   COALESCE(a.uploads, 0) AS uploads, COALESCE(a.uploaded_bytes, 0)::BIGINT AS uploaded_bytes
+            FROM job_usage j
+            FULL JOIN upload_usage a ON a.user_id = j.user_id
+            JOIN users u ON u.id = COALESCE(j.user_id, a.user_id)
+            ORDER BY jobs DESC, uploaded_bytes DESC, u.email
+            LIMIT $3
+            "#
+        )
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Get user's active jobs count
+    #[tracing::instrument(name = "Job::get_active_jobs_count", skip_all)]
+    pub async fn get_active_jobs_count(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM jobs WHERE user_id = $1 AND status IN ('queued', 'processing')"
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Count queued/processing jobs that use the given LUT, directly or in a
+    /// pipeline step
+    #[tracing::instrument(name = "Job::count_active_for_lut", skip_all)]
+    pub async fn count_active_for_lut(pool: &PgPool, lut_id: Uuid) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM jobs
+            WHERE status IN ('queued', 'processing')
+              AND (parameters @> jsonb_build_object('lut_id', $1::text)
+                   OR parameters->'operations' @> jsonb_build_array(jsonb_build_object('lut_id', $1::text)))
+            "#
+        )
+        .bind(lut_id.to_string())
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Count queued/processing jobs that reference the given asset
+    #[tracing::instrument(name = "Job::count_active_for_asset", skip_all)]
+    pub async fn count_active_for_asset(
+        pool: &PgPool,
+        asset_id: Uuid,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM jobs WHERE media_asset_ids ? $1 AND status IN ('queued', 'processing')"
+        )
+        .bind(asset_id.to_string())
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Atomically move the next queued job (highest priority, then oldest)
+    /// to `processing`, counting the attempt, and return it. Jobs scheduled
+    /// for later and retries still waiting out their delay are skipped; idle
+    /// workers poll, so they are picked up soon after they fall due. `SKIP LOCKED` lets concurrent
+    /// workers claim different jobs instead of waiting on each other.
+    #[tracing::instrument(name = "Job::claim_next", skip_all)]
+    pub async fn claim_next(pool: &PgPool) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs SET status = 'processing', progress_percent = 0, attempts = attempts + 1,
+                heartbeat_at = NOW()
+            WHERE id = (
+                SELECT id FROM jobs
+                WHERE status = 'queued' AND (run_after IS NULL OR run_after <= NOW())
+                ORDER BY priority DESC, created_at ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#
+        )
+        .fetch_optional(pool)
+        .await
+    }
+}
+// ============================================================================
+// ApiKey Repository
+// ============================================================================
+
+impl ApiKey {
+    /// Store a new key. The id is part of the plaintext key, so the caller picks it.
+    #[tracing::instrument(name = "ApiKey::create", skip_all)]
+    pub async fn create(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: Uuid,
+        name: &str,
+        key_hash: &str,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, ApiKey>(
+            "INSERT INTO api_keys (id, user_id, name, key_hash) VALUES ($1, $2, $3, $4) RETURNING *"
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .bind(key_hash)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Find an unrevoked key along with the user it belongs to
+    #[tracing::instrument(name = "ApiKey::find_active", skip_all)]
+    pub async fn find_active(pool: &PgPool, id: Uuid) -> Result<Option<(Self, User)>, sqlx::Error> {
+        let Some(key) = sqlx::query_as::<_, ApiKey>(
+            "SELECT * FROM api_keys WHERE id = $1 AND NOT revoked"
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let user = User::find_by_id(pool, key.user_id).await?;
+        Ok(user.map(|user| (key, user)))
+    }
+
+    /// A user's keys, newest first, including revoked ones
+    #[tracing::instrument(name = "ApiKey::list_for_user", skip_all)]
+    pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKey>(
+            "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC"
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Revoke one of the user's keys. Returns false if they have no such key.
+    #[tracing::instrument(name = "ApiKey::revoke", skip_all)]
+    pub async fn revoke(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE api_keys SET revoked = TRUE WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(name = "ApiKey::touch", skip_all)]
+    pub async fn touch(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE api_keys SET last_used_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+// ============================================================================
+// LUT Repository
+// ============================================================================
+
+impl LutFile {
+    #[tracing::instrument(name = "LutFile::create", skip_all)]
+    pub async fn create(
+        pool: &PgPool,
+        owner: Owner,
+        name: &str,
+        location: &str,
+        size_bytes: i64,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, LutFile>(
+            r#"
+            INSERT INTO luts (id, user_id, name, location, size_bytes, organization_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(owner.user_id)
+        .bind(name)
+        .bind(location)
+        .bind(size_bytes)
+        .bind(owner.organization_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    #[tracing::instrument(name = "LutFile::find_by_id", skip_all)]
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, LutFile>("SELECT * FROM luts WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// One of the LUTs `owner` reaches; other users' LUTs are not found
+    #[tracing::instrument(name = "LutFile::find_for_user", skip_all)]
+    pub async fn find_for_user(pool: &PgPool, id: Uuid, owner: Owner) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, LutFile>("SELECT * FROM luts WHERE id = $1 AND (user_id = $2 OR organization_id = $3)")
+            .bind(id)
+            .bind(owner.user_id)
+            .bind(owner.organization_id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// The user's LUT stored at `location`, for requests that still pass
+    /// `lut_location`
+    #[tracing::instrument(name = "LutFile::find_by_location", skip_all)]
+    pub async fn find_by_location(
+        pool: &PgPool,
+        user_id: Uuid,
+        location: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, LutFile>("SELECT * FROM luts WHERE user_id = $1 AND location = $2")
+            .bind(user_id)
+            .bind(location)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// The LUTs `owner` reaches, newest first
+    #[tracing::instrument(name = "LutFile::list_for_user", skip_all)]
+    pub async fn list_for_user(pool: &PgPool, owner: Owner) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, LutFile>(
+            "SELECT * FROM luts WHERE user_id = $1 OR organization_id = $2 ORDER BY created_at DESC"
+        )
+        .bind(owner.user_id)
+        .bind(owner.organization_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    #[tracing::instrument(name = "LutFile::delete", skip_all)]
+    pub async fn delete(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM luts WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Organization Repository
+// ============================================================================
+
+impl Organization {
+    /// Create an organization with `owner_id` as its first owner
+    #[tracing::instrument(name = "Organization::create", skip_all)]
+    pub async fn create(pool: &PgPool, name: &str, owner_id: Uuid) -> Result<Membership, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let organization = sqlx::query_as::<_, Organization>(
+            "INSERT INTO organizations (id, name) VALUES ($1, $2) RETURNING *"
+        )
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .fetch_one(&mut *tx)
+        .await?;
+        let joined_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            r#"
+            INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, 'owner')
+            RETURNING joined_at
+            "#
+        )
+        .bind(organization.id)
+        .bind(owner_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Membership {
+            organization_id: organization.id,
+            name: organization.name,
+            role: "owner".to_string(),
+            joined_at,
+        })
+    }
+
+    /// The user's membership of the organization, `None` if they aren't in it
+    #[tracing::instrument(name = "Organization::membership", skip_all)]
+    pub async fn membership(
+        pool: &PgPool,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Membership>, sqlx::Error> {
+        sqlx::query_as::<_, Membership>(
+            r#"
+            SELECT m.organization_id, o.name, m.role, m.joined_at
+            FROM organization_members m JOIN organizations o ON o.id = m.organization_id
+            WHERE m.organization_id = $1 AND m.user_id = $2
+            "#
+        )
+        .bind(organization_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Every organization the user is in, in the order they joined
+    #[tracing::instrument(name = "Organization::memberships", skip_all)]
+    pub async fn memberships(pool: &PgPool, user_id: Uuid) -> Result<Vec<Membership>, sqlx::Error> {
+        sqlx::query_as::<_, Membership>(
+            r#"
+            SELECT m.organization_id, o.name, m.role, m.joined_at
+            FROM organization_members m JOIN organizations o ON o.id = m.organization_id
+            WHERE m.user_id = $1
+            ORDER BY m.joined_at, o.name
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// The organization's members, owners first
+    #[tracing::instrument(name = "Organization::members", skip_all)]
+    pub async fn members(pool: &PgPool, organization_id: Uuid) -> Result<Vec<OrganizationMember>, sqlx::Error> {
+        sqlx::query_as::<_, OrganizationMember>(
+            r#"
+            SELECT m.user_id, u.email, m.role, m.joined_at
+            FROM organization_members m JOIN users u ON u.id = m.user_id
+            WHERE m.organization_id = $1
+            ORDER BY m.role = 'owner' DESC, m.joined_at, u.email
+            "#
+        )
+        .bind(organization_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Store an invitation for `email`, replacing any earlier one to the
+    /// same address
+    #[tracing::instrument(name = "Organization::store_invite", skip_all)]
+    pub async fn store_invite(
+        pool: &PgPool,
+        organization_id: Uuid,
+        email: &str,
+        token_hash: &str,
+        invited_by: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM organization_invites WHERE organization_id = $1 AND LOWER(email) = LOWER($2)")
+            .bind(organization_id)
+            .bind(email)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO organization_invites (token_hash, organization_id, email, invited_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#
+        )
+        .bind(token_hash)
+        .bind(organization_id)
+        .bind(email)
+        .bind(invited_by)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    /// Use up the invitation with `token_hash` and add the user as a member.
+    /// The invitation must be addressed to `email`. `None` if it is unknown,
+    /// already used, expired or for another address.
+    #[tracing::instrument(name = "Organization::accept_invite", skip_all)]
+    pub async fn accept_invite(
+        pool: &PgPool,
+        token_hash: &str,
+        user_id: Uuid,
+        email: &str,
+    ) -> Result<Option<Membership>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let organization_id = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            DELETE FROM organization_invites
+            WHERE token_hash = $1 AND expires_at > NOW() AND LOWER(email) = LOWER($2)
+            RETURNING organization_id
+            "#
+        )
+        .bind(token_hash)
+        .bind(email)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(organization_id) = organization_id else {
+            return Ok(None);
+        };
+
+        // Already being a member is fine; the role stays as it was
+        sqlx::query(
+            r#"
+            INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, 'member')
+            ON CONFLICT DO NOTHING
+            "#
+        )
+        .bind(organization_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Self::membership(pool, organization_id, user_id).await
+    }
+}
+
+// ============================================================================
+// Upload Repository
+// ============================================================================
+
+impl Upload {
+    #[tracing::instrument(name = "Upload::create", skip_all)]
+    pub async fn create(pool: &PgPool, user_id: Uuid, filename: &str, size_bytes: i64) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Upload>(
+            "INSERT INTO uploads (id, user_id, filename, size_bytes) VALUES ($1, $2, $3, $4) RETURNING *"
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(filename)
+        .bind(size_bytes)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// The user's upload with this id; `None` for other users' uploads too
+    #[tracing::instrument(name = "Upload::find_for_user", skip_all)]
+    pub async fn find_for_user(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Upload>("SELECT * FROM uploads WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    #[tracing::instrument(name = "Upload::count_for_user", skip_all)]
+    pub async fn count_for_user(pool: &PgPool, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM uploads WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Record that `received_bytes` are stored. `None` if the upload is gone.
+    #[tracing::instrument(name = "Upload::set_received", skip_all)]
+    pub async fn set_received(pool: &PgPool, id: Uuid, received_bytes: i64) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Upload>(
+            "UPDATE uploads SET received_bytes = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+        )
+        .bind(received_bytes)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Returns false if the upload was already gone
+    #[tracing::instrument(name = "Upload::delete", skip_all)]
+    pub async fn delete(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM uploads WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete uploads nothing has been sent to for `idle`, returning their ids
+    #[tracing::instrument(name = "Upload::delete_idle", skip_all)]
+    pub async fn delete_idle(pool: &PgPool, idle: Duration, limit: i64) -> Result<Vec<Uuid>, sqlx::Error> {
+        sqlx::query_scalar::<_, Uuid>(
+            r#"
+            DELETE FROM uploads WHERE id IN (
+                SELECT id FROM uploads
+                WHERE updated_at < NOW() - make_interval(secs => $1)
+                ORDER BY updated_at
+                LIMIT $2
+            )
+            RETURNING id
+            "#
+        )
+        .bind(idle.as_secs_f64())
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+// ============================================================================
+// Idempotency Key Repository
+// ============================================================================
+
+impl IdempotencyKey {
+    /// Record `key` as in flight for this request, unless a live row holds
+    /// it. Expired rows, and rows whose request has been in flight for
+    /// `stale_after` and so presumably died, are taken over. Returns whether
+    /// the key is now this request's. Concurrent claims wait on the primary
+    /// key, so only one of them wins.
+    #[tracing::instrument(name = "IdempotencyKey::claim", skip_all)]
+    pub async fn claim(
+        pool: &PgPool,
+        user_id: Uuid,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+        stale_after: Duration,
+    ) -> Result<bool, sqlx::Error> {
+        let claimed = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            INSERT INTO idempotency_keys (user_id, key, request_hash, expires_at)
+            VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+            ON CONFLICT (user_id, key) DO UPDATE SET
+                request_hash = EXCLUDED.request_hash,
+                response_status = NULL,
+                response_content_type = NULL,
+                response_body = NULL,
+                created_at = NOW(),
+                expires_at = EXCLUDED.expires_at
+            WHERE idempotency_keys.expires_at < NOW()
+               OR (idempotency_keys.response_status IS NULL
+                   AND idempotency_keys.created_at < NOW() - make_interval(secs => $5))
+            RETURNING user_id
+            "#
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(request_hash)
+        .bind(ttl.as_secs_f64())
+        .bind(stale_after.as_secs_f64())
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(claimed.is_some())
+    }
+
+    #[tracing::instrument(name = "IdempotencyKey::find", skip_all)]
+    pub async fn find(pool: &PgPool, user_id: Uuid, key: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, IdempotencyKey>(
+            "SELECT request_hash, response_status, response_content_type, response_body \
+             FROM idempotency_keys WHERE user_id = $1 AND key = $2"
+        )
+        .bind(user_id)
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Store the response a claimed key's request produced
+    #[tracing::instrument(name = "IdempotencyKey::complete", skip_all)]
+    pub async fn complete(
+        pool: &PgPool,
+        user_id: Uuid,
+        key: &str,
+        status: i16,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE idempotency_keys SET response_status = $3, response_content_type = $4, response_body = $5 \
+             WHERE user_id = $1 AND key = $2"
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(status)
+        .bind(content_type)
+        .bind(body)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Give up a claimed key whose request failed, so it can be retried
+    #[tracing::instrument(name = "IdempotencyKey::release", skip_all)]
+    pub async fn release(pool: &PgPool, user_id: Uuid, key: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2 AND response_status IS NULL")
+            .bind(user_id)
+            .bind(key)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete up to `limit` expired keys, returning how many went
+    #[tracing::instrument(name = "IdempotencyKey::delete_expired", skip_all)]
+    pub async fn delete_expired(pool: &PgPool, limit: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM idempotency_keys WHERE (user_id, key) IN (
+                SELECT user_id, key FROM idempotency_keys
+                WHERE expires_at < NOW()
+                ORDER BY expires_at
+                LIMIT $1
+            )
+            "#
+        )
+        .bind(limit)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+// ============================================================================
+// Pending Deletion Repository
+// ============================================================================
+
+impl JobOutput {
+    /// A job's outputs, primary first
+    #[tracing::instrument(name = "JobOutput::list", skip_all)]
+    pub async fn list(pool: &PgPool, job_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        Self::list_for_jobs(pool, &[job_id]).await
+    }
+
+    /// The outputs of each of `job_ids`, grouped by job and in order within one
+    #[tracing::instrument(name = "JobOutput::list_for_jobs", skip_all)]
+    pub async fn list_for_jobs(pool: &PgPool, job_ids: &[Uuid]) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, JobOutput>(
+            "SELECT * FROM job_outputs WHERE job_id = ANY($1) ORDER BY job_id, output_index"
+        )
+        .bind(job_ids)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+impl JobEvent {
+    /// Append an event to a job's history. The history is for people looking
+    /// into a job, so failing to write it is logged and otherwise ignored.
+    #[tracing::instrument(name = "JobEvent::record", skip_all)]
+    pub async fn record(pool: &PgPool, job_id: Uuid, event_type: &str, detail: Option<serde_json::Value>) {
+        let inserted = sqlx::query("INSERT INTO job_events (job_id, event_type, detail) VALUES ($1, $2, $3)")
+            .bind(job_id)
+            .bind(event_type)
+            .bind(detail)
+            .execute(pool)
+            .await;
+        if let Err(e) = inserted {
+            tracing::warn!("Failed to record {} event for job {}: {:?}", event_type, job_id, e);
+        }
+    }
+
+    /// A page of a job's events, oldest first, and how many there are in all
+    #[tracing::instrument(name = "JobEvent::list", skip_all)]
+    pub async fn list(pool: &PgPool, job_id: Uuid, limit: i64, offset: i64) -> Result<(Vec<Self>, i64), sqlx::Error> {
+        let events = sqlx::query_as::<_, JobEvent>(
+            "SELECT * FROM job_events WHERE job_id = $1 ORDER BY id LIMIT $2 OFFSET $3"
+        )
+        .bind(job_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM job_events WHERE job_id = $1")
+            .bind(job_id)
+            .fetch_one(pool)
+            .await?;
+
+        Ok((events, total))
+    }
+
+    /// Delete up to `limit` events of jobs past retention: completed jobs
+    /// whose result has expired, and jobs that failed before `failed_before`.
+    /// Returns how many went.
+    #[tracing::instrument(name = "JobEvent::delete_expired", skip_all)]
+    pub async fn delete_expired(pool: &PgPool, failed_before: DateTime<Utc>, limit: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM job_events WHERE id IN (
+                SELECT e.id FROM job_events e JOIN jobs j ON j.id = e.job_id
+                WHERE (j.status = 'completed' AND j.result_expires_at < NOW())
+                   OR (j.status = 'failed' AND j.failed_at < $1)
+                LIMIT $2
+            )
+            "#
+        )
+        .bind(failed_before)
+        .bind(limit)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl QuotaUsage {
+    /// Today's count for a user or an organization
+    #[tracing::instrument(name = "QuotaUsage::today", skip_all)]
+    pub async fn today(pool: &PgPool, subject_id: Uuid, media_kind: &str) -> Result<i64, sqlx::Error> {
+        let used: Option<i64> = sqlx::query_scalar(
+            "SELECT used FROM quota_usage WHERE subject_id = $1 AND day = (NOW() AT TIME ZONE 'UTC')::date \
+             AND media_kind = $2",
+        )
+        .bind(subject_id)
+        .bind(media_kind)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(used.unwrap_or(0))
+    }
+
+    /// Count `assets` more today for the owner, and for their organization
+    /// when acting for one, unless that takes the count past `limit`: the
+    /// organization's when `pooled`, else the user's. Both counts move or
+    /// neither does, and concurrent reservations cannot both take the last
+    /// of a limit. Returns whether the assets were counted.
+    #[tracing::instrument(name = "QuotaUsage::reserve", skip_all)]
+    pub async fn reserve(
+        pool: &PgPool,
+        owner: Owner,
+        media_kind: &str,
+        assets: i64,
+        limit: Option<i64>,
+        pooled: bool,
+    ) -> Result<bool, sqlx::Error> {
+        // A first reservation of the day inserts its row unchecked
+        if limit.is_some_and(|limit| assets > limit) {
+            return Ok(false);
+        }
+        let subjects = [(Some(owner.user_id), !pooled), (owner.organization_id, pooled)];
+
+        let mut tx = pool.begin().await?;
+        // The user's row is always taken first, so two reservations cannot
+        // each wait on the other's
+        for (subject_id, checked) in subjects {
+            let Some(subject_id) = subject_id else { continue };
+            let used: Option<i64> = sqlx::query_scalar(
+                r#"
+                INSERT INTO quota_usage (subject_id, day, media_kind, used)
+                VALUES ($1, (NOW() AT TIME ZONE 'UTC')::date, $2, $3)
+                ON CONFLICT (subject_id, day, media_kind) DO UPDATE SET used = quota_usage.used + EXCLUDED.used
+                WHERE $4::BIGINT IS NULL OR quota_usage.used + EXCLUDED.used <= $4
+                RETURNING used
+                "#
+            )
+            .bind(subject_id)
+            .bind(media_kind)
+            .bind(assets)
+            .bind(limit.filter(|_| checked))
+            .fetch_optional(&mut *tx)
+            .await?;
+            if used.is_none() {
+                return Ok(false);
+            }
+        }
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Take back a reservation made today for a job that was never created
+    #[tracing::instrument(name = "QuotaUsage::release", skip_all)]
+    pub async fn release(pool: &PgPool, owner: Owner, media_kind: &str, assets: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE quota_usage SET used = GREATEST(used - $3, 0)
+            WHERE subject_id IN ($1, $2) AND day = (NOW() AT TIME ZONE 'UTC')::date AND media_kind = $4
+            "#
+        )
+        .bind(owner.user_id)
+        .bind(owner.organization_id)
+        .bind(assets)
+        .bind(media_kind)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete counts for days before `day`, which no quota looks at again
+    #[tracing::instrument(name = "QuotaUsage::delete_before", skip_all)]
+    pub async fn delete_before(pool: &PgPool, day: NaiveDate) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM quota_usage WHERE day < $1")
+            .bind(day)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl PendingDeletion {
+    /// The oldest objects still to delete
+    #[tracing::instrument(name = "PendingDeletion::list", skip_all)]
+    pub async fn list(pool: &PgPool, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, PendingDeletion>("SELECT * FROM pending_deletions ORDER BY created_at LIMIT $1")
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Forget `locations`, which have been deleted from storage
+    #[tracing::instrument(name = "PendingDeletion::clear", skip_all)]
+    pub async fn clear(pool: &PgPool, locations: &[String]) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM pending_deletions WHERE location = ANY($1)")
+            .bind(locations)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl StoredObject {
+    /// Uploads, thumbnails, job results and outputs, and LUTs in location order, starting
+    /// after `after`. A location held by several rows is listed once, with
+    /// its oldest row.
+    #[tracing::instrument(name = "StoredObject::list_after", skip_all)]
+    pub async fn list_after(pool: &PgPool, after: &str, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, StoredObject>(
+            r#"
+            SELECT DISTINCT ON (location) location, user_id, created_at FROM (
+                SELECT result_location, user_id, COALESCE(created_at, NOW()) FROM media_assets
+                UNION ALL SELECT thumbnail_location, user_id, COALESCE(created_at, NOW()) FROM media_assets
+                UNION ALL SELECT result_location, user_id, COALESCE(created_at, NOW()) FROM jobs
+                UNION ALL SELECT o.location, j.user_id, COALESCE(j.created_at, NOW())
+                    FROM job_outputs o JOIN jobs j ON j.id = o.job_id
+                UNION ALL SELECT location, user_id, created_at FROM luts
+            ) AS owned (location, user_id, created_at)
+            WHERE location > $1
+            ORDER BY location, created_at
+            LIMIT $2
+            "#
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Point every row that refers to `from` at `to` instead, including
+    /// background images of queued jobs and of their pipeline steps
+    #[tracing::instrument(name = "StoredObject::relocate", skip_all)]
+    pub async fn relocate(pool: &PgPool, from: &str, to: &str) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        for statement in [
+            "UPDATE media_assets SET result_location = $2 WHERE result_location = $1",
+            "UPDATE media_assets SET thumbnail_location = $2 WHERE thumbnail_location = $1",
+            "UPDATE jobs SET result_location = $2 WHERE result_location = $1",
+            "UPDATE job_outputs SET location = $2 WHERE location = $1",
+            "UPDATE luts SET location = $2 WHERE location = $1",
+            "UPDATE jobs SET parameters = jsonb_set(parameters, '{background_location}', to_jsonb($2::TEXT)) \
+             WHERE parameters->>'background_location' = $1",
+            "UPDATE jobs SET parameters = jsonb_set(parameters, '{operations}', ( \
+                 SELECT jsonb_agg(CASE WHEN step->>'background_location' = $1 \
+                     THEN jsonb_set(step, '{background_location}', to_jsonb($2::TEXT)) ELSE step END ORDER BY i) \
+                 FROM jsonb_array_elements(parameters->'operations') WITH ORDINALITY AS steps (step, i))) \
+             WHERE parameters->'operations' @> jsonb_build_array(jsonb_build_object('background_location', $1::TEXT))",
+        ] {
+            sqlx::query(statement).bind(from).bind(to).execute(&mut *tx).await?;
+        }
+        tx.commit().await
+    }
+}
+
+/// Setup for the tests that need a database (feature = "db-tests")
+#[cfg(all(test, feature = "db-tests"))]
+mod test_support {
+    use super::*;
+
+    /// A pool on the test database, migrated
+    pub async fn pool() -> PgPool {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+        pool
+    }
+
+    /// A new free-tier user, under an address no other test uses
+    pub async fn user(pool: &PgPool) -> User {
+        User::create(pool, &format!("{}@db.test", Uuid::new_v4()), "hash", "free").await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_filter_combines_clauses() {
+        let filter = JobFilter {
+            status: Some("failed".to_string()),
+            job_type: Some("convert".to_string()),
+            since: Some(Utc::now() - chrono::Duration::days(1)),
+            until: Some(Utc::now()),
+        };
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT * FROM jobs");
+        filter.push_where(&mut qb, Some(Uuid::new_v4().into()));
+
+        assert_eq!(
+            qb.sql(),
+            "SELECT * FROM jobs WHERE (user_id = $1 OR organization_id = $2) AND status = $3 AND job_type = $4 \
+             AND created_at >= $5 AND created_at < $6"
+        );
+    }
+
+    #[test]
+    fn test_job_filter_empty_only_scopes_user() {
+        let filter = JobFilter::default();
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM jobs");
+        filter.push_where(&mut qb, Some(Uuid::new_v4().into()));
+
+        assert_eq!(qb.sql(), "SELECT COUNT(*) FROM jobs WHERE (user_id = $1 OR organization_id = $2)");
+    }
+
+    #[test]
+    fn test_job_filter_without_user_spans_all_jobs() {
+        let filter = JobFilter {
+            status: Some("failed".to_string()),
+            ..Default::default()
+        };
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT * FROM jobs");
+        filter.push_where(&mut qb, None);
+        assert_eq!(qb.sql(), "SELECT * FROM jobs WHERE status = $1");
+
+        let empty = JobFilter::default();
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT * FROM jobs");
+        empty.push_where(&mut qb, None);
+        assert_eq!(qb.sql(), "SELECT * FROM jobs");
+    }
+
+    #[test]
+    fn test_user_filter() {
+        let filter = UserFilter {
+            email_prefix: Some("ann".to_string()),
+            tier: Some("pro".to_string()),
+        };
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT * FROM users");
+        filter.push_where(&mut qb);
+        assert_eq!(
+            qb.sql(),
+            "SELECT * FROM users WHERE email ILIKE $1 AND subscription_tier = $2"
+        );
+
+        assert_eq!(escape_like("a_b%c\\"), "a\\_b\\%c\\\\");
+    }
+
+    /// Pro jobs are claimed ahead of free-tier jobs submitted before them,
+    /// and jobs scheduled for later wait whatever their priority. Claims any
+    /// other queued jobs in the database along the way.
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_claim_next_prefers_higher_priority() {
+        let pool = test_support::pool().await;
+        let user = test_support::user(&pool).await;
+        let free = Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+            .await
+            .unwrap();
+        let pro = Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 10, None)
+            .await
+            .unwrap();
+        let later = Utc::now() + chrono::Duration::hours(1);
+        let scheduled =
+            Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 15, Some(later))
+                .await
+                .unwrap();
+
+        let mut claimed = Vec::new();
+        while let Some(job) = Job::claim_next(&pool).await.unwrap() {
+            if [free.id, pro.id, scheduled.id].contains(&job.id) {
+                claimed.push(job.id);
+            }
+        }
+        assert_eq!(claimed, [pro.id, free.id]);
+        assert_eq!(Job::find_by_id(&pool, scheduled.id).await.unwrap().unwrap().status, "queued");
+        User::delete_account(&pool, user.id).await.unwrap();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_reset_stale_requeues_only_jobs_without_a_recent_heartbeat() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let user = User::create(&pool, &format!("{}@stale.test", Uuid::new_v4()), "hash", "free").await.unwrap();
+        let mut ids = Vec::new();
+        for heartbeat in ["NOW()", "NOW() - INTERVAL '10 minutes'"] {
+            let job = Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+                .await
+                .unwrap();
+            sqlx::query(&format!("UPDATE jobs SET status = 'processing', heartbeat_at = {} WHERE id = $1", heartbeat))
+                .bind(job.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            ids.push(job.id);
+        }
+        let (alive, dead) = (ids[0], ids[1]);
+        let status = |id| {
+            let pool = &pool;
+            async move { Job::find_by_id(pool, id).await.unwrap().unwrap().status }
+        };
+
+        let reset: Vec<_> = Job::reset_stale(&pool, Duration::from_secs(120)).await.unwrap().iter().map(|j| j.id).collect();
+        assert!(reset.contains(&dead) && !reset.contains(&alive), "{:?}", reset);
+        assert_eq!(status(dead).await, "queued");
+        assert_eq!(status(alive).await, "processing");
+
+        // A heartbeat keeps a long-running job from being taken away
+        sqlx::query("UPDATE jobs SET heartbeat_at = NOW() - INTERVAL '10 minutes' WHERE id = $1")
+            .bind(alive)
+            .execute(&pool)
+            .await
+            .unwrap();
+        Job::heartbeat(&pool, &[alive]).await.unwrap();
+        Job::reset_stale(&pool, Duration::from_secs(120)).await.unwrap();
+        assert_eq!(status(alive).await, "processing");
+
+        // A job that has used up its attempts fails rather than running again
+        sqlx::query("UPDATE jobs SET status = 'processing', attempts = max_attempts, heartbeat_at = NULL WHERE id = $1")
+            .bind(dead)
+            .execute(&pool)
+            .await
+            .unwrap();
+        Job::reset_stale(&pool, Duration::from_secs(120)).await.unwrap();
+        let lost = Job::find_by_id(&pool, dead).await.unwrap().unwrap();
+        assert_eq!(lost.status, "failed");
+        assert_eq!(lost.error_code.as_deref(), Some("worker_lost"));
+        assert!(lost.failed_at.is_some());
+        User::delete_account(&pool, user.id).await.unwrap();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_take_stalled_hands_out_long_queued_jobs_once() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let user = User::create(&pool, &format!("{}@stalled.test", Uuid::new_v4()), "hash", "free").await.unwrap();
+        let mut ids = Vec::new();
+        // Announced long ago; announced just now; long ago but only just due
+        for (enqueued, run_after) in [
+            ("NOW() - INTERVAL '10 minutes'", "NULL"),
+            ("NOW()", "NULL"),
+            ("NOW() - INTERVAL '10 minutes'", "NOW() - INTERVAL '1 second'"),
+        ] {
+            let job = Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+                .await
+                .unwrap();
+            sqlx::query(&format!("UPDATE jobs SET enqueued_at = {}, run_after = {} WHERE id = $1", enqueued, run_after))
+                .bind(job.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            ids.push(job.id);
+        }
+        let stale_after = Duration::from_secs(300);
+
+        assert!(Job::take_stalled(&pool, stale_after, Some(ids[1])).await.unwrap().is_empty());
+        let stalled: Vec<_> = Job::take_stalled(&pool, stale_after, None).await.unwrap().iter().map(|j| j.id).collect();
+        assert!(stalled.contains(&ids[0]), "{:?}", stalled);
+        assert!(!stalled.contains(&ids[1]) && !stalled.contains(&ids[2]), "{:?}", stalled);
+        // Announcing it again restarts the wait
+        assert!(Job::take_stalled(&pool, stale_after, Some(ids[0])).await.unwrap().is_empty());
+        User::delete_account(&pool, user.id).await.unwrap();
+    }
+
+    #[test]
+    fn test_long_errors_are_cut_short() {
+        assert_eq!(truncate_error("Asset not found"), "Asset not found");
+        let exact = "é".repeat(MAX_ERROR_MESSAGE_CHARS);
+        assert_eq!(truncate_error(&exact), exact);
+
+        let long = format!("{}x", exact);
+        let cut = truncate_error(&long);
+        assert_eq!(cut.chars().count(), MAX_ERROR_MESSAGE_CHARS);
+        assert!(cut.ends_with("é…"), "{}", &cut[cut.len() - 8..]);
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_failures_record_their_code_and_time_until_retried() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let user = User::create(&pool, &format!("{}@failures.test", Uuid::new_v4()), "hash", "free").await.unwrap();
+        // Null parameters once made the error vanish
+        let job = Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::Value::Null, 0, None)
+            .await
+            .unwrap();
+
+        Job::requeue(&pool, job.id, Duration::from_secs(30), "Failed to save result", "storage_error").await.unwrap();
+        let queued = Job::find_by_id(&pool, job.id).await.unwrap().unwrap();
+        assert_eq!(queued.error_code.as_deref(), Some("storage_error"));
+        assert!(queued.failed_at.is_none());
+
+        Job::fail(&pool, job.id, &"ffmpeg says no. ".repeat(500), "decode_failed").await.unwrap();
+        let failed = Job::find_by_id(&pool, job.id).await.unwrap().unwrap();
+        assert_eq!(failed.status, "failed");
+        let error = failed.error_message.unwrap();
+        assert!(error.starts_with("ffmpeg says no.") && error.ends_with('…'), "{}", error);
+        assert_eq!(error.chars().count(), MAX_ERROR_MESSAGE_CHARS);
+        assert_eq!(failed.error_code.as_deref(), Some("decode_failed"));
+        assert!(failed.failed_at.is_some());
+
+        let retried = Job::retry(&pool, job.id).await.unwrap().unwrap();
+        assert_eq!((retried.error_message, retried.error_code, retried.failed_at), (None, None, None));
+        User::delete_account(&pool, user.id).await.unwrap();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_storage_used_skips_expired_and_deleted_assets() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let email = format!("{}@storage.test", Uuid::new_v4());
+        let user = User::create(&pool, &email, "hash", "free").await.unwrap();
+        let kept = MediaAsset::create(&pool, user.id.into(), "a.png", "png", 100, None).await.unwrap();
+        let deleted = MediaAsset::create(&pool, user.id.into(), "b.png", "png", 20, None).await.unwrap();
+        let expired = MediaAsset::create(&pool, user.id.into(), "c.png", "png", 3, None).await.unwrap();
+        sqlx::query("UPDATE media_assets SET expires_at = NOW() - INTERVAL '1 minute' WHERE id = $1")
+            .bind(expired.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(MediaAsset::storage_used(&pool, user.id).await.unwrap(), 120);
+
+        MediaAsset::delete(&pool, deleted.id).await.unwrap();
+        assert_eq!(MediaAsset::storage_used(&pool, user.id).await.unwrap(), kept.size_bytes);
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_find_by_hash_is_scoped_to_the_user() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let hash = hex::encode(Uuid::new_v4().as_bytes()).repeat(2);
+        let owner = User::create(&pool, &format!("{}@hash.test", Uuid::new_v4()), "hash", "free").await.unwrap();
+        let other = User::create(&pool, &format!("{}@hash.test", Uuid::new_v4()), "hash", "free").await.unwrap();
+        let asset = MediaAsset::create(&pool, owner.id.into(), "a.png", "png", 10, Some(&hash)).await.unwrap();
+        MediaAsset::update_status(&pool, asset.id, "uploaded", Some("a.png")).await.unwrap();
+
+        let found = MediaAsset::find_by_hash(&pool, owner.id.into(), &hash).await.unwrap().unwrap();
+        assert_eq!(found.id, asset.id);
+        assert!(MediaAsset::find_by_hash(&pool, other.id.into(), &hash).await.unwrap().is_none());
+
+        // Expired assets are about to be swept, so they are not reused
+        sqlx::query("UPDATE media_assets SET expires_at = NOW() - INTERVAL '1 minute' WHERE id = $1")
+            .bind(asset.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(MediaAsset::find_by_hash(&pool, owner.id.into(), &hash).await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_luts_are_scoped_to_the_user_and_counted_by_active_jobs() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let owner = User::create(&pool, &format!("{}@lut.test", Uuid::new_v4()), "lut", "free").await.unwrap();
+        let other = User::create(&pool, &format!("{}@lut.test", Uuid::new_v4()), "lut", "free").await.unwrap();
+        let location = format!("{}_warm.cube", Uuid::new_v4());
+        let lut = LutFile::create(&pool, owner.id.into(), "warm.cube", &location, 42).await.unwrap();
+
+        assert!(LutFile::find_for_user(&pool, lut.id, owner.id.into()).await.unwrap().is_some());
+        assert!(LutFile::find_for_user(&pool, lut.id, other.id.into()).await.unwrap().is_none());
+        assert!(LutFile::find_by_location(&pool, owner.id, &location).await.unwrap().is_some());
+        assert!(LutFile::find_by_location(&pool, other.id, &location).await.unwrap().is_none());
+
+        // Direct and pipeline references both count
+        let lut_id = lut.id.to_string();
+        let graded = serde_json::json!({"lut_id": lut_id});
+        Job::create(&pool, owner.id.into(), vec![], "color_grade", "image", graded, 0, None).await.unwrap();
+        let pipeline = serde_json::json!({"operations": [{"type": "remove_bg"}, {"type": "convert", "lut_id": lut_id}]});
+        let job = Job::create(&pool, owner.id.into(), vec![], "pipeline", "image", pipeline, 0, None).await.unwrap();
+        assert_eq!(Job::count_active_for_lut(&pool, lut.id).await.unwrap(), 2);
+
+        Job::fail(&pool, job.id, "done with it", "processing_failed").await.unwrap();
+        assert_eq!(Job::count_active_for_lut(&pool, lut.id).await.unwrap(), 1);
+
+        LutFile::delete(&pool, lut.id).await.unwrap();
+        assert!(LutFile::find_by_id(&pool, lut.id).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_owner_reaches_its_own_and_its_organization_resources() {
+        let (user, other, org) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let personal = Owner::from(user);
+        let acting = Owner { user_id: user, organization_id: Some(org) };
+
+        assert!(personal.reaches(user, None) && personal.reaches(user, Some(org)));
+        assert!(!personal.reaches(other, Some(org)));
+        assert!(acting.reaches(other, Some(org)));
+        assert!(!acting.reaches(other, None) && !acting.reaches(other, Some(Uuid::new_v4())));
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_invited_members_share_what_is_created_for_the_organization() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let owner = User::create(&pool, &format!("{}@org.test", Uuid::new_v4()), "org", "free").await.unwrap();
+        let member = User::create(&pool, &format!("{}@org.test", Uuid::new_v4()), "org", "free").await.unwrap();
+        let org = Organization::create(&pool, "Studio", owner.id).await.unwrap();
+        assert_eq!(org.role, "owner");
+        let org_id = org.organization_id;
+
+        // Only the invited address can accept, and only once
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        let (first, second) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        Organization::store_invite(&pool, org_id, &member.email.to_uppercase(), &first, owner.id, expires_at)
+            .await
+            .unwrap();
+        assert!(Organization::accept_invite(&pool, &first, owner.id, &owner.email).await.unwrap().is_none());
+        let joined = Organization::accept_invite(&pool, &first, member.id, &member.email).await.unwrap().unwrap();
+        assert_eq!((joined.organization_id, joined.role.as_str()), (org_id, "member"));
+        assert!(Organization::accept_invite(&pool, &first, member.id, &member.email).await.unwrap().is_none());
+        let members = Organization::members(&pool, org_id).await.unwrap();
+        assert_eq!(members.iter().map(|m| m.user_id).collect::<Vec<_>>(), [owner.id, member.id]);
+        assert_eq!(Organization::memberships(&pool, member.id).await.unwrap().len(), 1);
+
+        // An expired invitation is refused
+        let expired = Utc::now() - chrono::Duration::minutes(1);
+        Organization::store_invite(&pool, org_id, &member.email, &second, owner.id, expired).await.unwrap();
+        assert!(Organization::accept_invite(&pool, &second, member.id, &member.email).await.unwrap().is_none());
+
+        let for_org = Owner { user_id: owner.id, organization_id: Some(org_id) };
+        let as_member = Owner { user_id: member.id, organization_id: Some(org_id) };
+        let shared = MediaAsset::create(&pool, for_org, "shared.png", "png", 10, None).await.unwrap();
+        let personal = MediaAsset::create(&pool, owner.id.into(), "personal.png", "png", 10, None).await.unwrap();
+        let lut = LutFile::create(&pool, for_org, "team.cube", &format!("{}_team.cube", Uuid::new_v4()), 42)
+            .await
+            .unwrap();
+
+        let assets: Vec<_> =
+            MediaAsset::find_by_user(&pool, as_member, None, 10, 0).await.unwrap().iter().map(|a| a.id).collect();
+        assert_eq!(assets, [shared.id]);
+        assert_eq!(MediaAsset::count_by_user(&pool, member.id.into(), None).await.unwrap(), 0);
+        assert_eq!(MediaAsset::count_by_user(&pool, for_org, None).await.unwrap(), 2);
+        assert!(LutFile::find_for_user(&pool, lut.id, as_member).await.unwrap().is_some());
+        assert!(LutFile::find_for_user(&pool, lut.id, member.id.into()).await.unwrap().is_none());
+        assert!(!as_member.reaches(personal.user_id, personal.organization_id));
+
+        User::delete_account(&pool, member.id).await.unwrap();
+        User::delete_account(&pool, owner.id).await.unwrap();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_job_outputs_are_recorded_cleared_and_deleted_with_the_account() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let user = User::create(&pool, &format!("{}@outputs.test", Uuid::new_v4()), "hash", "free").await.unwrap();
+        let output = |job_id, name: &str| JobOutput {
+            job_id,
+            output_index: 0,
+            location: format!("local://{}_{}", Uuid::new_v4(), name),
+            filename: name.to_string(),
+            size_bytes: 5,
+            content_type: "image/png".to_string(),
+            etag: name.to_string(),
+        };
+        let result = |output: &JobOutput| JobResult {
+            location: output.location.clone(),
+            width: Some(2),
+            height: Some(1),
+            size_bytes: output.size_bytes,
+            format: "png".to_string(),
+            duration_ms: 40,
+        };
+        let job = Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+            .await
+            .unwrap();
+        let outputs = vec![output(job.id, "a.png"), output(job.id, "b.png")];
+        assert!(Job::complete(&pool, job.id, &result(&outputs[0]), &outputs, chrono::Duration::hours(1)).await.unwrap());
+
+        let completed = Job::find_by_id(&pool, job.id).await.unwrap().unwrap();
+        assert_eq!(completed.result_location.as_deref(), Some(outputs[0].location.as_str()));
+        assert_eq!(completed.result_etag.as_deref(), Some("a.png"));
+        assert_eq!(completed.result.map(|r| r.0), Some(result(&outputs[0])));
+        let listed = JobOutput::list(&pool, job.id).await.unwrap();
+        let listed: Vec<_> = listed.iter().map(|o| (o.output_index, o.filename.as_str())).collect();
+        assert_eq!(listed, [(0, "a.png"), (1, "b.png")]);
+
+        let other = Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+            .await
+            .unwrap();
+        let kept = output(other.id, "c.png");
+        Job::complete(&pool, other.id, &result(&kept), std::slice::from_ref(&kept), chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        Job::clear_result(&pool, job.id).await.unwrap();
+        assert!(JobOutput::list(&pool, job.id).await.unwrap().is_empty());
+        let cleared = Job::find_by_id(&pool, job.id).await.unwrap().unwrap();
+        assert!(cleared.result.is_none());
+        // What statistics count is kept
+        assert_eq!((cleared.output_bytes, cleared.duration_ms), (Some(10), Some(40)));
+
+        let mut locations = User::delete_account(&pool, user.id).await.unwrap().unwrap();
+        locations.sort();
+        assert_eq!(locations, [kept.location.as_str()]);
+        PendingDeletion::clear(&pool, &locations).await.unwrap();
+
+        // A job that went with its account is not completed
+        assert!(!Job::complete(&pool, other.id, &result(&kept), &[kept], chrono::Duration::hours(1)).await.unwrap());
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_usage_is_aggregated_per_type_day_and_user() {
+        use chrono::TimeZone;
+
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = create_pool(&url).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let user = User::create(&pool, &format!("{}@usage.test", Uuid::new_v4()), "hash", "free").await.unwrap();
+        let at = |d, h, m| Utc.with_ymd_and_hms(2001, 3, d, h, m, 0).unwrap();
+        let job_at = |job_type: &'static str, created_at: DateTime<Utc>| {
+            let pool = pool.clone();
+            async move {
+                let job = Job::create(&pool, user.id.into(), vec![], job_type, "image", serde_json::json!({}), 0, None)
+                    .await
+                    .unwrap();
+                sqlx::query("UPDATE jobs SET created_at = $2 WHERE id = $1")
+                    .bind(job.id)
+                    .bind(created_at)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                job.id
+            }
+        };
+        // Completed, then failed on the first day; queued on the third; one
+        // the day before the period
+        let completed = job_at("convert", at(1, 13, 0)).await;
+        let outputs: Vec<_> = ["a.png", "b.png"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| JobOutput {
+                job_id: completed,
+                output_index: i as i32,
+                location: format!("local://{}_{}", Uuid::new_v4(), name),
+                filename: name.to_string(),
+                size_bytes: 5,
+                content_type: "image/png".to_string(),
+                etag: name.to_string(),
+            })
+            .collect();
+        let result = JobResult {
+            location: outputs[0].location.clone(),
+            width: Some(2),
+            height: Some(1),
+            size_bytes: 5,
+            format: "png".to_string(),
+            duration_ms: 40,
+        };
+        Job::complete(&pool, completed, &result, &outputs, chrono::Duration::hours(1)).await.unwrap();
+        // Its result expiring does not change what it produced
+        Job::clear_result(&pool, completed).await.unwrap();
+        let failed = job_at("convert", at(1, 23, 30)).await;
+        Job::fail(&pool, failed, "Decode failed", "decode_failed").await.unwrap();
+        job_at("remove_bg", at(3, 0, 0)).await;
+        job_at("convert", at(1, 11, 59)).await;
+        // Uploads count after their assets are gone
+        for size in [6, 7] {
+            let asset = MediaAsset::create(&pool, user.id.into(), "a.png", "png", size, None).await.unwrap();
+            MediaAsset::delete(&pool, asset.id).await.unwrap();
+        }
+        sqlx::query("UPDATE asset_uploads SET created_at = $2 WHERE user_id = $1")
+            .bind(user.id)
+            .bind(at(2, 10, 0))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let (from, to) = (at(1, 12, 0), at(3, 12, 0));
+        let usage = Job::usage(&pool, Some(user.id), from, to).await.unwrap();
+        let usage: Vec<_> = usage
+            .iter()
+            .map(|u| (u.job_type.as_deref(), u.jobs, u.completed, u.failed, u.output_bytes, u.avg_duration_ms))
+            .collect();
+        assert_eq!(
+            usage,
+            [
+                (Some("convert"), 2, 1, 1, 10, Some(40)),
+                (Some("remove_bg"), 1, 0, 0, 0, None),
+                (None, 3, 1, 1, 10, Some(40)),
+            ]
+        );
+
+        let daily = Job::daily_usage(&pool, Some(user.id), from, to).await.unwrap();
+        let daily: Vec<_> = daily.iter().map(|d| (d.day.to_string(), d.jobs, d.failed, d.output_bytes)).collect();
+        assert_eq!(
+            daily,
+            [
+                ("2001-03-01".to_string(), 2, 1, 10),
+                ("2001-03-02".to_string(), 0, 0, 0),
+                ("2001-03-03".to_string(), 1, 0, 0),
+            ]
+        );
+
+        let uploads = MediaAsset::upload_usage(&pool, Some(user.id), from, to).await.unwrap();
+        let uploads: Vec<_> =
+            uploads.iter().map(|u| (u.day.map(|d| d.to_string()), u.uploads, u.uploaded_bytes)).collect();
+        assert_eq!(
+            uploads,
+            [
+                (Some("2001-03-01".to_string()), 0, 0),
+                (Some("2001-03-02".to_string()), 2, 13),
+                (Some("2001-03-03".to_string()), 0, 0),
+                (None, 2, 13),
+            ]
+        );
+
+        let by_user = Job::usage_by_user(&pool, from, to, 1000).await.unwrap();
+        let mine = by_user.iter().find(|u| u.user_id == user.id).unwrap();
+        assert_eq!((mine.jobs, mine.completed, mine.failed, mine.output_bytes), (3, 1, 1, 10));
+        assert_eq!((mine.uploads, mine.uploaded_bytes), (2, 13));
+        User::delete_account(&pool, user.id).await.unwrap();
+    }
+}
@@ -0,0 +1,622 @@
+// backend/src/error/catalog.rs
+// Every message the API shows people, in each language it speaks. Errors carry
+// a `Msg` and the values it mentions; the text is picked when the response is
+// written, from the request's `Accept-Language`.
+
+use axum::{
+    extract::Request,
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::services::{download_token, formats, url_fetch, webhook};
+
+/// A language messages are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    /// Used when the request asks for nothing we speak
+    #[default]
+    En,
+    Es,
+}
+
+impl Locale {
+    /// The language tag for `Content-Language`
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Es => "es",
+        }
+    }
+
+    /// Matches on the primary subtag, so `es-MX` is Spanish
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split('-').next().unwrap_or_default();
+        [Self::En, Self::Es]
+            .into_iter()
+            .find(|locale| primary.eq_ignore_ascii_case(locale.tag()))
+    }
+
+    /// The supported language an `Accept-Language` header weighs highest,
+    /// the first listed on a tie, or English
+    pub fn negotiate(accept_language: Option<&str>) -> Self {
+        let mut best: Option<(Self, f32)> = None;
+        for range in accept_language.unwrap_or_default().split(',') {
+            let mut parts = range.split(';');
+            let Some(locale) = parts.next().and_then(|tag| Self::from_tag(tag.trim())) else {
+                continue;
+            };
+            let weight = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(q) => q.trim().parse().unwrap_or(0.0),
+                None => 1.0,
+            };
+            if weight > 0.0 && best.is_none_or(|(_, top)| weight > top) {
+                best = Some((locale, weight));
+            }
+        }
+        best.map(|(locale, _)| locale).unwrap_or_default()
+    }
+}
+
+tokio::task_local! {
+    static LOCALE: Locale;
+}
+
+/// The language of the request being handled, English outside of one
+pub fn current() -> Locale {
+    LOCALE.try_with(|locale| *locale).unwrap_or_default()
+}
+
+/// Handle the request in the language its `Accept-Language` asks for
+pub async fn negotiate_locale(request: Request, next: Next) -> Response {
+    let accept_language = request.headers().get(header::ACCEPT_LANGUAGE).and_then(|value| value.to_str().ok());
+    let locale = Locale::negotiate(accept_language);
+    LOCALE.scope(locale, next.run(request)).await
+}
+
+macro_rules! catalog {
+    ($($id:ident: $en:literal | $es:literal,)*) => {
+        /// A message in the catalog. Its serialized name is the `reason` of an
+        /// error response, stable like `code`, so clients may key their own
+        /// translations on it.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+        #[serde(rename_all = "snake_case")]
+        pub enum Msg {
+            $($id,)*
+        }
+
+        impl Msg {
+            /// The text, with `{name}` where a detail goes. `{name:bytes}`
+            /// writes a byte count in KB, MB, ...
+            fn template(self, locale: Locale) -> &'static str {
+                match (self, locale) {
+                    $(
+                        (Self::$id, Locale::En) => $en,
+                        (Self::$id, Locale::Es) => $es,
+                    )*
+                }
+            }
+        }
+
+        #[cfg(test)]
+        const ALL: &[Msg] = &[$(Msg::$id,)*];
+    };
+}
+
+catalog! {
+    // Generic
+    Internal: "Internal server error" | "Error interno del servidor",
+    DatabaseError: "A database error occurred" | "Se produjo un error de base de datos",
+    IoError: "An IO error occurred" | "Se produjo un error de E/S",
+    DatabaseBusy: "The database is busy; try again later" | "La base de datos está ocupada; inténtalo más tarde",
+    ResourceNotFound: "Resource not found" | "Recurso no encontrado",
+    FileNotFound: "File not found" | "Archivo no encontrado",
+    RequestTooLarge: "Request body is too large" | "El cuerpo de la solicitud es demasiado grande",
+    InvalidMultipart: "Invalid multipart data: {reason}" | "Datos multipart no válidos: {reason}",
+    ProcessingFailed: "{reason}" | "El procesamiento falló: {reason}",
+    ValidationFailed: "Request validation failed" | "La validación de la solicitud falló",
+    AccessDenied: "Access denied" | "Acceso denegado",
+    TooManyRequests: "Too many requests. Try again in {retry_after_seconds} seconds."
+        | "Demasiadas solicitudes. Inténtalo de nuevo en {retry_after_seconds} segundos.",
+    QueueUnavailable: "Job queue is unavailable" | "La cola de trabajos no está disponible",
+
+    // Accounts
+    Unauthorized: "Unauthorized" | "No autorizado",
+    AdminRequired: "Admin access required" | "Se requiere acceso de administrador",
+    InvalidCredentials: "Invalid credentials" | "Credenciales no válidas",
+    AccountGone: "Account no longer exists" | "La cuenta ya no existe",
+    TooManyAttempts: "Too many attempts. Try again in {retry_after_seconds} seconds."
+        | "Demasiados intentos. Inténtalo de nuevo en {retry_after_seconds} segundos.",
+    VerificationLinkInvalid: "This verification link is invalid or has expired"
+        | "Este enlace de verificación no es válido o ha caducado",
+    EmailAlreadyVerified: "Email is already verified" | "El correo ya está verificado",
+    ResetLinkInvalid: "This reset link is invalid or has expired"
+        | "Este enlace de restablecimiento no es válido o ha caducado",
+    CurrentPasswordIncorrect: "Current password is incorrect" | "La contraseña actual es incorrecta",
+    EmailAlreadyRegistered: "Email already registered" | "El correo ya está registrado",
+    EmailNotVerified: "Confirm your email address before submitting jobs. \
+        POST /api/auth/resend-verification sends a new link."
+        | "Confirma tu dirección de correo antes de enviar trabajos. \
+        POST /api/auth/resend-verification envía un enlace nuevo.",
+
+    // Fields
+    InvalidEmail: "Invalid email format" | "Formato de correo no válido",
+    TooShort: "Must be at least {min} characters" | "Debe tener al menos {min} caracteres",
+    OutOfRange: "Must be between {min} and {max}, got {value}" | "Debe estar entre {min} y {max}; se recibió {value}",
+    InvalidChoice: "Invalid value '{value}'; expected one of {choices}"
+        | "Valor '{value}' no válido; se esperaba uno de {choices}",
+    Required: "Must not be empty" | "No debe estar vacío",
+
+    // Ids
+    InvalidAssetId: "Invalid asset ID" | "ID de recurso no válido",
+    InvalidAssetIdValue: "Invalid asset ID '{value}'" | "ID de recurso '{value}' no válido",
+    DuplicateAssetId: "Duplicate asset ID '{value}'" | "ID de recurso '{value}' duplicado",
+    InvalidSourceAssetId: "Invalid source asset ID" | "ID de recurso de origen no válido",
+    InvalidGradedAssetId: "Invalid graded asset ID" | "ID de recurso graduado no válido",
+    InvalidUploadId: "Invalid upload ID" | "ID de subida no válido",
+    InvalidJobId: "Invalid job ID" | "ID de trabajo no válido",
+    InvalidLutId: "Invalid LUT ID" | "ID de LUT no válido",
+    InvalidKeyId: "Invalid key ID" | "ID de clave no válido",
+    InvalidOrganizationId: "Invalid organization ID" | "ID de organización no válido",
+    InvalidUserId: "Invalid user ID" | "ID de usuario no válido",
+    MustBeAssetId: "Must be an asset id" | "Debe ser un ID de recurso",
+    MustBeLutId: "Must be a LUT id" | "Debe ser un ID de LUT",
+
+    // Lookups
+    AssetNotFound: "Asset not found" | "Recurso no encontrado",
+    BackgroundAssetNotFound: "Background asset not found" | "Recurso de fondo no encontrado",
+    NoAssetWithHash: "No asset with this content" | "No hay ningún recurso con este contenido",
+    NoThumbnail: "No thumbnail for this asset" | "Este recurso no tiene miniatura",
+    UploadNotFound: "Upload not found" | "Subida no encontrada",
+    JobNotFound: "Job not found" | "Trabajo no encontrado",
+    LutNotFound: "LUT not found" | "LUT no encontrado",
+    ResultNotFound: "Result not found" | "Resultado no encontrado",
+    OutputNotFound: "Output not found" | "Salida no encontrada",
+    ApiKeyNotFound: "API key not found" | "Clave de API no encontrada",
+    OrganizationNotFound: "Organization not found" | "Organización no encontrada",
+    UserNotFound: "User not found" | "Usuario no encontrado",
+
+    // Uploads
+    NoFile: "No file provided" | "No se proporcionó ningún archivo",
+    UrlWithoutFilename: "The URL has no file name; pass one in `filename`"
+        | "La URL no tiene nombre de archivo; indica uno en `filename`",
+    MalwareDetected: "The file was flagged by the malware scanner: {signature}"
+        | "El analizador de malware marcó el archivo: {signature}",
+    ScanUnavailable: "Uploads cannot be scanned right now; try again later"
+        | "Ahora no se pueden analizar las subidas; inténtalo más tarde",
+    FileTooLarge: "File too large: exceeds {limit_mb} MB limit"
+        | "Archivo demasiado grande: supera el límite de {limit_mb} MB",
+    UnsupportedFileType: "Unsupported file type. Supported: {supported}"
+        | "Tipo de archivo no admitido. Admitidos: {supported}",
+    UnrecognizedContent: "File content is not a supported image or video format"
+        | "El contenido del archivo no es un formato de imagen o vídeo admitido",
+    ExtensionMismatch: "File content ({content}) does not match its .{extension} extension"
+        | "El contenido del archivo ({content}) no coincide con su extensión .{extension}",
+    HeicUnsupported: "HEIC images are not supported by this server; convert to JPEG or PNG first"
+        | "Este servidor no admite imágenes HEIC; conviértelas primero a JPEG o PNG",
+    UnreadableImage: "Could not read image: {reason}" | "No se pudo leer la imagen: {reason}",
+    UnreadableVideo: "Could not read video: {reason}" | "No se pudo leer el vídeo: {reason}",
+    VideoTooLong: "Video too long: {duration}s (max {max}s)" | "Vídeo demasiado largo: {duration} s (máx. {max} s)",
+    VideoUnavailable: "Video processing is unavailable on this deployment"
+        | "El procesamiento de vídeo no está disponible en esta instalación",
+    VideoEncoderUnavailable: "Video processing on this deployment cannot encode with {encoder}"
+        | "El procesamiento de vídeo en esta instalación no puede codificar con {encoder}",
+    ImageTooManyPixels: "Image too large: decodes to {pixels} pixels (max {max})"
+        | "Imagen demasiado grande: se decodifica a {pixels} píxeles (máx. {max})",
+    TooManyOpenUploads: "At most {max} uploads may be in progress; complete or cancel one first"
+        | "Puede haber como máximo {max} subidas en curso; completa o cancela una primero",
+    UploadOffsetRequired: "Upload-Offset header is required" | "La cabecera Upload-Offset es obligatoria",
+    UploadOffsetMismatch: "Upload-Offset must be {expected}, the bytes received so far"
+        | "Upload-Offset debe ser {expected}, los bytes recibidos hasta ahora",
+    ChunkTooLong: "The chunk goes past the declared size of {size} bytes"
+        | "El fragmento supera el tamaño declarado de {size} bytes",
+    UploadPartsMissing: "Bytes received for this upload are missing; start a new upload"
+        | "Faltan bytes recibidos para esta subida; empieza una subida nueva",
+    UnreadableChunk: "The chunk could not be read: {reason}" | "No se pudo leer el fragmento: {reason}",
+    UploadIncomplete: "Upload is incomplete: {received} of {size} bytes received"
+        | "La subida está incompleta: se recibieron {received} de {size} bytes",
+    UploadBusy: "Another request is writing to this upload" | "Otra solicitud está escribiendo en esta subida",
+    ChecksumMismatch: "The file does not match the sha256 given; upload it again"
+        | "El archivo no coincide con el sha256 indicado; vuelve a subirlo",
+
+    // Fetching from a URL
+    UrlInvalid: "url is not a valid URL: {reason}" | "url no es una URL válida: {reason}",
+    UrlNotHttps: "url must use https" | "url debe usar https",
+    UrlUnresolvable: "url host could not be resolved" | "No se pudo resolver el host de url",
+    UrlPrivateAddress: "url must not point at a private, loopback or link-local address"
+        | "url no debe apuntar a una dirección privada, de loopback o de enlace local",
+    UrlTooManyRedirects: "url redirected more than {max} times" | "url redirigió más de {max} veces",
+    RemoteStatus: "the remote server responded with {status}" | "el servidor remoto respondió con {status}",
+    RemoteFileTooLarge: "the remote file exceeds the {limit_mb} MB limit"
+        | "el archivo remoto supera el límite de {limit_mb} MB",
+    FetchTimedOut: "fetching the url timed out" | "se agotó el tiempo al descargar url",
+    FetchFailed: "fetching the url failed: {reason}" | "no se pudo descargar url: {reason}",
+
+    // Assets
+    InvalidContentHash: "Invalid content hash; expected a hex SHA-256"
+        | "Hash de contenido no válido; se esperaba un SHA-256 hexadecimal",
+    AssetInUse: "Asset is used by {active} queued or processing job(s); wait for them to finish before deleting"
+        | "El recurso lo usan {active} trabajo(s) en cola o en proceso; espera a que terminen antes de borrarlo",
+    AssetNotDeleted: "Asset is not deleted" | "El recurso no está borrado",
+    AssetRestoreExpired: "The asset was deleted too long ago to be restored"
+        | "El recurso se borró hace demasiado tiempo para restaurarlo",
+    AssetWithoutContent: "The asset has no stored content" | "El recurso no tiene contenido almacenado",
+
+    // Processing
+    UnsupportedOutputFormat: "Unsupported output format '{format}'; supported formats: {supported}"
+        | "Formato de salida '{format}' no admitido; formatos admitidos: {supported}",
+    OutputFormatDisabled: "Output format '{format}' is not enabled on this server; supported formats: {supported}"
+        | "El formato de salida '{format}' no está habilitado en este servidor; formatos admitidos: {supported}",
+    FormatOnlyFor: "{format} only applies to {kind}" | "{format} solo se aplica a {kind}",
+    UnsupportedOptions: "{reason}" | "Opciones no admitidas: {reason}",
+    EmptyAssetIds: "asset_ids must not be empty" | "asset_ids no debe estar vacío",
+    BatchTooLarge: "A batch may contain at most {max} assets, got {count}"
+        | "Un lote puede contener como máximo {max} recursos; se recibieron {count}",
+    BatchMixesKinds: "A batch may not mix images and videos" | "Un lote no puede mezclar imágenes y vídeos",
+    LutOrLocation: "Give lut_id or lut_location, not both" | "Indica lut_id o lut_location, no ambos",
+    LutImagesOnly: "LUTs can only be applied to images" | "Los LUT solo se pueden aplicar a imágenes",
+    VideoCodecVideosOnly: "video_codec only applies to video assets"
+        | "video_codec solo se aplica a recursos de vídeo",
+    TargetSizeFormats: "target_size_kb only applies to {formats} output"
+        | "target_size_kb solo se aplica a la salida {formats}",
+    TargetSizeOrQuality: "target_size_kb replaces quality; set one or the other"
+        | "target_size_kb sustituye a quality; indica uno u otro",
+    TargetSizeImagesOnly: "target_size_kb only applies to image assets"
+        | "target_size_kb solo se aplica a recursos de imagen",
+    BackgroundNotImage: "The background must be an image, not a video" | "El fondo debe ser una imagen, no un vídeo",
+    BackgroundWithoutContent: "The background asset has no stored content"
+        | "El recurso de fondo no tiene contenido almacenado",
+    ColorOrBackground: "Give replace_color or background_asset_id, not both"
+        | "Indica replace_color o background_asset_id, no ambos",
+    JpegLosesAlpha: "JPEG has no alpha channel, so the removed background would be lost. \
+        Use png or webp, or give a flatten_color to fill it"
+        | "JPEG no tiene canal alfa, así que el fondo eliminado se perdería. \
+        Usa png o webp, o indica un flatten_color para rellenarlo",
+    FlattenColorTransparentJpg: "flatten_color only applies to jpg output in the transparent mode"
+        | "flatten_color solo se aplica a la salida jpg en el modo transparent",
+    TrimPaddingNeedsTrim: "trim_padding only applies with trim" | "trim_padding solo se aplica con trim",
+    ColorModeNeedsColor: "The color mode needs a replace_color" | "El modo color necesita un replace_color",
+    ReplaceColorColorMode: "replace_color only applies to the color mode"
+        | "replace_color solo se aplica al modo color",
+    ImageModeNeedsBackground: "The image mode needs a background_asset_id"
+        | "El modo image necesita un background_asset_id",
+    BackgroundImageMode: "background_asset_id only applies to the image mode"
+        | "background_asset_id solo se aplica al modo image",
+    BlurSigmaBlurMode: "blur_sigma only applies to the blur mode" | "blur_sigma solo se aplica al modo blur",
+    TrimImagesOnly: "trim only applies to images" | "trim solo se aplica a imágenes",
+    EmptyOperations: "operations must not be empty" | "operations no debe estar vacío",
+    PipelineTooLong: "A pipeline may contain at most {max} operations, got {count}"
+        | "Una cadena puede contener como máximo {max} operaciones; se recibieron {count}",
+    InvalidOperation: "{reason}" | "Operación no válida: {reason}",
+    PipelineStep: "Step {step} ({operation})" | "Paso {step} ({operation})",
+    PipelineStepAt: "Step {step}" | "Paso {step}",
+    StepOutputNotProcessable: "the previous step produces {output}, which cannot be processed further"
+        | "el paso anterior produce {output}, que no se puede seguir procesando",
+    ColorGradeImagesOnly: "color grading only applies to images" | "la gradación de color solo se aplica a imágenes",
+    AnalyzeImagesOnly: "Only images can be analyzed" | "Solo se pueden analizar imágenes",
+
+    // LUTs
+    LutFormatUnsupported: "Only .cube and .3dl LUT files are supported"
+        | "Solo se admiten archivos LUT .cube y .3dl",
+    LutTooLarge: "LUT file too large: {size_mb} MB (max {limit_mb} MB)"
+        | "Archivo LUT demasiado grande: {size_mb} MB (máx. {limit_mb} MB)",
+    InvalidLut: "Invalid LUT file: {reason}" | "Archivo LUT no válido: {reason}",
+    NoLutFile: "No LUT file provided" | "No se proporcionó ningún archivo LUT",
+    LutFromVideo: "LUTs are generated from images; '{filename}' is a video"
+        | "Los LUT se generan a partir de imágenes; '{filename}' es un vídeo",
+    LutNameInvalid: "Must be at most {max} characters, without slashes"
+        | "Debe tener como máximo {max} caracteres, sin barras",
+    LutInUse: "LUT is used by {active} queued or processing job(s); wait for them to finish before deleting"
+        | "El LUT lo usan {active} trabajo(s) en cola o en proceso; espera a que terminen antes de borrarlo",
+
+    // Jobs
+    OnlyFailedRetried: "Only failed jobs can be retried (job is {status})"
+        | "Solo se pueden reintentar trabajos fallidos (el trabajo está {status})",
+    AlreadyRetrying: "Job is already being retried" | "El trabajo ya se está reintentando",
+    ExtendNeedsPro: "Extending result retention requires a pro plan"
+        | "Ampliar la conservación de resultados requiere un plan pro",
+    ResultDoesNotExpire: "This result does not expire" | "Este resultado no caduca",
+    RetentionTooLong: "Results can be kept at most {max_hours} hours after completion"
+        | "Los resultados se pueden conservar como máximo {max_hours} horas tras completarse",
+    UnknownStatus: "Unknown status '{value}'. Supported: {supported}"
+        | "Estado '{value}' desconocido. Admitidos: {supported}",
+    UnknownJobType: "Unknown job_type '{value}'. Supported: {supported}"
+        | "job_type '{value}' desconocido. Admitidos: {supported}",
+    UnknownTier: "Unknown tier '{value}'. Supported: {supported}"
+        | "Plan '{value}' desconocido. Admitidos: {supported}",
+    SinceAfterUntil: "'since' must be before 'until'" | "'since' debe ser anterior a 'until'",
+    FromAfterTo: "'from' must be before 'to'" | "'from' debe ser anterior a 'to'",
+    PeriodTooLong: "The period may span at most {max_days} days"
+        | "El periodo puede abarcar como máximo {max_days} días",
+    OneOutputOrZip: "Pick one output or the zip, not both" | "Elige una salida o el zip, no ambos",
+    PriorityProOnly: "High priority is only available to pro accounts"
+        | "La prioridad alta solo está disponible para cuentas pro",
+    InvalidTimestamp: "Must be an RFC 3339 timestamp, e.g. 2024-05-01T22:00:00Z"
+        | "Debe ser una marca de tiempo RFC 3339, p. ej. 2024-05-01T22:00:00Z",
+    ScheduleTooFar: "Must be at most {max_days} days ahead" | "Debe estar como máximo {max_days} días en el futuro",
+    JobNotCompleted: "Job not completed" | "Trabajo no completado",
+    DownloadLinkMalformed: "download token is malformed" | "el token de descarga está mal formado",
+    DownloadLinkForged: "download token signature is invalid" | "la firma del token de descarga no es válida",
+    DownloadLinkExpired: "download token has expired" | "el token de descarga ha caducado",
+    ResultExpired: "The result has expired and is no longer available"
+        | "El resultado ha caducado y ya no está disponible",
+    WebhooksDisabled: "Webhooks are not enabled on this server" | "Los webhooks no están habilitados en este servidor",
+    WebhookUrlInvalid: "webhook_url is not a valid URL: {reason}" | "webhook_url no es una URL válida: {reason}",
+    WebhookUrlScheme: "webhook_url must use http or https" | "webhook_url debe usar http o https",
+    WebhookUrlUnresolvable: "webhook_url host could not be resolved" | "No se pudo resolver el host de webhook_url",
+    WebhookUrlPrivate: "webhook_url must not point at a private, loopback or link-local address"
+        | "webhook_url no debe apuntar a una dirección privada, de loopback o de enlace local",
+
+    // Quotas
+    DailyQuotaExceeded: "Daily quota exceeded ({used}/{limit}). Upgrade to Pro for more capacity."
+        | "Cuota diaria superada ({used}/{limit}). Pásate a Pro para tener más capacidad.",
+    DailyQuotaExceededBy: "Daily quota exceeded ({used}/{limit}, {requested} more requested). \
+        Upgrade to Pro for more capacity."
+        | "Cuota diaria superada ({used}/{limit}, {requested} más solicitados). \
+        Pásate a Pro para tener más capacidad.",
+    ConcurrentLimitExceeded: "Concurrent job limit exceeded ({used}/{limit}). Try again later."
+        | "Límite de trabajos simultáneos superado ({used}/{limit}). Inténtalo más tarde.",
+    StorageQuotaExceeded: "Storage quota exceeded ({used:bytes} of {limit:bytes} used, upload is {requested:bytes}). \
+        Delete assets you no longer need or upgrade to Pro."
+        | "Cuota de almacenamiento superada ({used:bytes} de {limit:bytes} usados, la subida ocupa \
+        {requested:bytes}). Borra los recursos que ya no necesites o pásate a Pro.",
+
+    // API keys and organizations
+    KeyNameLength: "Key name must be 1 to {max} characters"
+        | "El nombre de la clave debe tener de 1 a {max} caracteres",
+    NameTooLong: "Must be at most {max} characters" | "Debe tener como máximo {max} caracteres",
+    OwnersOnlyInvite: "Only owners can invite members" | "Solo los propietarios pueden invitar a miembros",
+    AlreadyMember: "{email} is already a member" | "{email} ya es miembro",
+    ConfirmEmailForInvites: "Confirm your email address before accepting invitations"
+        | "Confirma tu dirección de correo antes de aceptar invitaciones",
+    InvitationInvalid: "This invitation is invalid, has expired or is for another address"
+        | "Esta invitación no es válida, ha caducado o es para otra dirección",
+
+    // Idempotency
+    IdempotencyKeyInvalid: "Idempotency-Key must be 1 to {max} printable ASCII characters"
+        | "Idempotency-Key debe tener de 1 a {max} caracteres ASCII imprimibles",
+    IdempotencyInProgress: "A request with this Idempotency-Key is still in progress"
+        | "Una solicitud con esta Idempotency-Key sigue en curso",
+    IdempotencyKeyReused: "Idempotency-Key was already used with a different request"
+        | "Idempotency-Key ya se usó con una solicitud distinta",
+}
+
+impl Msg {
+    /// The message with one detail set
+    pub fn with(self, name: &'static str, value: impl Into<Value>) -> Message {
+        Message::from(self).with(name, value)
+    }
+}
+
+/// A catalog message and the values it mentions, rendered in the request's
+/// language when serialized
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    id: Msg,
+    details: Map<String, Value>,
+    /// What this message happened within, e.g. a pipeline step
+    context: Option<Box<Message>>,
+}
+
+impl From<Msg> for Message {
+    fn from(id: Msg) -> Self {
+        Self {
+            id,
+            details: Map::new(),
+            context: None,
+        }
+    }
+}
+
+impl Message {
+    pub fn id(&self) -> Msg {
+        self.id
+    }
+
+    pub fn with(mut self, name: &'static str, value: impl Into<Value>) -> Self {
+        self.details.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// The same message, prefixed with `context` and carrying its details
+    pub fn within(mut self, context: impl Into<Message>) -> Self {
+        self.context = Some(Box::new(context.into()));
+        self
+    }
+
+    /// The values the message mentions, its context's included
+    pub fn details(&self) -> Map<String, Value> {
+        let mut details = self.context.as_ref().map(|context| context.details()).unwrap_or_default();
+        details.extend(self.details.clone());
+        details
+    }
+
+    pub fn render(&self, locale: Locale) -> String {
+        let text = fill(self.id.template(locale), &self.details);
+        match &self.context {
+            Some(context) => format!("{}: {}", context.render(locale), text),
+            None => text,
+        }
+    }
+}
+
+/// English, for logs
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(Locale::En))
+    }
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.render(current()))
+    }
+}
+
+fn fill(template: &str, details: &Map<String, Value>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        text.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..start + len];
+        let (name, bytes) = match placeholder.strip_suffix(":bytes") {
+            Some(name) => (name, true),
+            None => (placeholder, false),
+        };
+        match details.get(name) {
+            Some(value) if bytes => text.push_str(&human_bytes(value.as_i64().unwrap_or_default())),
+            Some(value) => text.push_str(&plain(value)),
+            None => text.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// A detail as it reads in a sentence: strings unquoted, lists comma-separated
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(plain).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+fn human_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// Errors from the services, in catalog terms
+
+impl From<&url_fetch::FetchError> for Message {
+    fn from(err: &url_fetch::FetchError) -> Self {
+        use url_fetch::FetchError;
+        match err {
+            FetchError::Invalid(reason) => Msg::UrlInvalid.with("reason", reason.as_str()),
+            FetchError::UnsupportedScheme => Msg::UrlNotHttps.into(),
+            FetchError::Unresolvable => Msg::UrlUnresolvable.into(),
+            FetchError::PrivateAddress => Msg::UrlPrivateAddress.into(),
+            FetchError::TooManyRedirects => Msg::UrlTooManyRedirects.with("max", url_fetch::MAX_REDIRECTS),
+            FetchError::Status(status) => Msg::RemoteStatus.with("status", status.as_u16()),
+            FetchError::TooLarge(limit) => Msg::RemoteFileTooLarge.with("limit_mb", limit / (1024 * 1024)),
+            FetchError::TimedOut => Msg::FetchTimedOut.into(),
+            FetchError::Request(reason) => Msg::FetchFailed.with("reason", reason.as_str()),
+        }
+    }
+}
+
+impl From<&webhook::WebhookUrlError> for Message {
+    fn from(err: &webhook::WebhookUrlError) -> Self {
+        use webhook::WebhookUrlError;
+        match err {
+            WebhookUrlError::Invalid(reason) => Msg::WebhookUrlInvalid.with("reason", reason.as_str()),
+            WebhookUrlError::UnsupportedScheme => Msg::WebhookUrlScheme.into(),
+            WebhookUrlError::Unresolvable => Msg::WebhookUrlUnresolvable.into(),
+            WebhookUrlError::PrivateAddress => Msg::WebhookUrlPrivate.into(),
+        }
+    }
+}
+
+impl From<&formats::UnsupportedFormat> for Message {
+    fn from(err: &formats::UnsupportedFormat) -> Self {
+        let id = if err.disabled { Msg::OutputFormatDisabled } else { Msg::UnsupportedOutputFormat };
+        id.with("format", err.format.as_str()).with("supported", err.supported.as_str())
+    }
+}
+
+impl From<&download_token::TokenError> for Message {
+    fn from(err: &download_token::TokenError) -> Self {
+        use download_token::TokenError;
+        match err {
+            TokenError::Malformed => Msg::DownloadLinkMalformed.into(),
+            TokenError::BadSignature => Msg::DownloadLinkForged.into(),
+            TokenError::Expired => Msg::DownloadLinkExpired.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn placeholders(template: &str) -> BTreeSet<&str> {
+        template
+            .split('{')
+            .skip(1)
+            .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+            .collect()
+    }
+
+    #[test]
+    fn test_every_locale_mentions_the_same_details() {
+        for msg in ALL {
+            let en = msg.template(Locale::En);
+            let es = msg.template(Locale::Es);
+            assert_eq!(placeholders(en), placeholders(es), "{:?}", msg);
+            assert_ne!(en, es, "{:?} is untranslated", msg);
+        }
+    }
+
+    #[test]
+    fn test_messages_render_their_details() {
+        let message = Msg::StorageQuotaExceeded
+            .with("used", 3 * 1024 * 1024)
+            .with("limit", 4 * 1024 * 1024)
+            .with("requested", 1024 * 1024 + 1);
+        assert_eq!(
+            message.to_string(),
+            "Storage quota exceeded (3.0 MB of 4.0 MB used, upload is 1.0 MB). \
+             Delete assets you no longer need or upgrade to Pro."
+        );
+        assert!(message.render(Locale::Es).starts_with("Cuota de almacenamiento superada (3.0 MB de 4.0 MB"));
+
+        let choice = Msg::InvalidChoice.with("value", "H265").with("choices", vec!["h264", "vp9"]);
+        assert_eq!(choice.to_string(), "Invalid value 'H265'; expected one of h264, vp9");
+
+        let in_step = Message::from(Msg::ColorGradeImagesOnly).within(
+            Msg::PipelineStep.with("step", 2).with("operation", "color_grade"),
+        );
+        assert_eq!(in_step.to_string(), "Step 2 (color_grade): color grading only applies to images");
+        assert_eq!(
+            in_step.render(Locale::Es),
+            "Paso 2 (color_grade): la gradación de color solo se aplica a imágenes"
+        );
+        assert_eq!(in_step.details()["step"], 2);
+        assert_eq!(in_step.id(), Msg::ColorGradeImagesOnly);
+
+        assert_eq!(human_bytes(512), "512 bytes");
+        assert_eq!(human_bytes(10 * 1024 * 1024 * 1024), "10.0 GB");
+    }
+
+    #[test]
+    fn test_accept_language_picks_the_best_supported_locale() {
+        assert_eq!(Locale::negotiate(None), Locale::En);
+        assert_eq!(Locale::negotiate(Some("es")), Locale::Es);
+        assert_eq!(Locale::negotiate(Some("ES-mx,en;q=0.5")), Locale::Es);
+        assert_eq!(Locale::negotiate(Some("fr-CH, fr;q=0.9, es;q=0.8, en;q=0.7")), Locale::Es);
+        assert_eq!(Locale::negotiate(Some("en;q=0.4, es;q=0.6")), Locale::Es);
+        assert_eq!(Locale::negotiate(Some("es;q=0, de")), Locale::En);
+        // Unsupported languages, wildcards and garbage fall back to English
+        assert_eq!(Locale::negotiate(Some("de-DE, *;q=0.5")), Locale::En);
+        assert_eq!(Locale::negotiate(Some(";;q=,")), Locale::En);
+    }
+}
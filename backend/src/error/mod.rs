@@ -0,0 +1,422 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+use utoipa::ToSchema;
+
+pub mod catalog;
+
+pub use catalog::{Message, Msg};
+
+/// Application-wide error type with proper HTTP status mapping. Messages come
+/// from the catalog, so they can be shown in the request's language.
+#[derive(Debug)]
+pub enum AppError {
+    // Client errors (4xx)
+    BadRequest(Message),
+    Unauthorized(Message),
+    Forbidden(Message),
+    /// The account must confirm its email address first
+    EmailNotVerified(Message),
+    NotFound(Message),
+    Conflict(Message),
+    /// The resource existed but has been deleted for good
+    Gone(Message),
+    PayloadTooLarge(Message),
+    /// A tier limit was hit; `quota` is the usage that was checked
+    QuotaExceeded {
+        message: Message,
+        quota: Box<crate::services::quota::QuotaStatus>,
+    },
+    /// Too many attempts; clients may try again after `retry_after_secs`
+    RateLimited { message: Message, retry_after_secs: u64 },
+    UnprocessableEntity(Message),
+    /// The malware scanner flagged an upload
+    MalwareDetected(Message),
+    /// The request was well-formed but these fields are not acceptable
+    Validation(Vec<crate::validation::FieldError>),
+
+    // Server errors (5xx). What went wrong is logged, not shown.
+    Internal(String),
+    ServiceUnavailable(Message),
+    
+    // External errors
+    Database(sqlx::Error),
+    Io(std::io::Error),
+    ImageProcessing(Message),
+}
+
+/// The `error.code` of an error response, stable for clients to branch on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    /// Follow the link sent at registration, or ask for a new one
+    EmailNotVerified,
+    NotFound,
+    Conflict,
+    Gone,
+    PayloadTooLarge,
+    /// A tier limit or an attempt limit was hit
+    QuotaExceeded,
+    UnprocessableEntity,
+    /// The upload was refused by the malware scanner
+    MalwareDetected,
+    /// `error.errors` lists the fields at fault
+    ValidationError,
+    InternalError,
+    ServiceUnavailable,
+    DatabaseError,
+    IoError,
+    ProcessingError,
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(msg) => write!(f, "Bad Request: {}", msg),
+            Self::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            Self::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            Self::EmailNotVerified(msg) => write!(f, "Email Not Verified: {}", msg),
+            Self::NotFound(msg) => write!(f, "Not Found: {}", msg),
+            Self::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            Self::Gone(msg) => write!(f, "Gone: {}", msg),
+            Self::PayloadTooLarge(msg) => write!(f, "Payload Too Large: {}", msg),
+            Self::QuotaExceeded { message, .. } => write!(f, "Quota Exceeded: {}", message),
+            Self::RateLimited { message, .. } => write!(f, "Rate Limited: {}", message),
+            Self::UnprocessableEntity(msg) => write!(f, "Unprocessable Entity: {}", msg),
+            Self::MalwareDetected(msg) => write!(f, "Malware Detected: {}", msg),
+            Self::Validation(errors) => {
+                write!(f, "Validation Failed: ")?;
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            }
+            Self::Internal(msg) => write!(f, "Internal Server Error: {}", msg),
+            Self::ServiceUnavailable(msg) => write!(f, "Service Unavailable: {}", msg),
+            Self::Database(err) => write!(f, "Database Error: {}", err),
+            Self::Io(err) => write!(f, "IO Error: {}", err),
+            Self::ImageProcessing(msg) => write!(f, "Image Processing Error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// Conversions from other error types
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        tracing::error!("Database error: {:?}", err);
+        match err {
+            sqlx::Error::RowNotFound => Self::NotFound(Msg::ResourceNotFound.into()),
+            sqlx::Error::PoolTimedOut => Self::ServiceUnavailable(Msg::DatabaseBusy.into()),
+            _ => Self::Database(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        tracing::error!("IO error: {:?}", err);
+        Self::Io(err)
+    }
+}
+
+impl From<axum::extract::multipart::MultipartError> for AppError {
+    fn from(err: axum::extract::multipart::MultipartError) -> Self {
+        // A body cut off by the route's size limit surfaces as a multipart error
+        if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            Self::PayloadTooLarge(Msg::RequestTooLarge.into())
+        } else {
+            Self::BadRequest(Msg::InvalidMultipart.with("reason", err.body_text()))
+        }
+    }
+}
+
+impl From<image::ImageError> for AppError {
+    fn from(err: image::ImageError) -> Self {
+        tracing::error!("Image processing error: {:?}", err);
+        Self::ImageProcessing(Msg::ProcessingFailed.with("reason", err.to_string()))
+    }
+}
+
+impl From<crate::services::processing::ProcessingError> for AppError {
+    fn from(err: crate::services::processing::ProcessingError) -> Self {
+        tracing::error!("Processing error: {:?}", err);
+        Self::ImageProcessing(Msg::ProcessingFailed.with("reason", err.to_string()))
+    }
+}
+
+impl From<crate::services::storage::StorageError> for AppError {
+    fn from(err: crate::services::storage::StorageError) -> Self {
+        match err {
+            crate::services::storage::StorageError::NotFound(location) => {
+                tracing::debug!("Stored object not found: {}", location);
+                Self::NotFound(Msg::FileNotFound.into())
+            }
+            // Only a tampered database row points here; don't confirm the path
+            crate::services::storage::StorageError::OutsideStorage(location) => {
+                tracing::warn!("Refused storage location outside the base: {}", location);
+                Self::NotFound(Msg::FileNotFound.into())
+            }
+            other => {
+                tracing::error!("Storage error: {:?}", other);
+                Self::Internal(format!("Storage error: {}", other))
+            }
+        }
+    }
+}
+
+impl From<crate::services::url_fetch::FetchError> for AppError {
+    fn from(err: crate::services::url_fetch::FetchError) -> Self {
+        match err {
+            crate::services::url_fetch::FetchError::TooLarge(_) => Self::PayloadTooLarge((&err).into()),
+            other => Self::BadRequest((&other).into()),
+        }
+    }
+}
+
+impl AppError {
+    /// What the response says. Server errors say only that something failed.
+    fn message(&self) -> Message {
+        match self {
+            Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::EmailNotVerified(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::Gone(message)
+            | Self::PayloadTooLarge(message)
+            | Self::QuotaExceeded { message, .. }
+            | Self::RateLimited { message, .. }
+            | Self::UnprocessableEntity(message)
+            | Self::MalwareDetected(message)
+            | Self::ServiceUnavailable(message)
+            | Self::ImageProcessing(message) => message.clone(),
+            Self::Validation(errors) => {
+                let fields: Vec<_> = errors.iter().map(|error| error.field.clone()).collect();
+                Msg::ValidationFailed.with("fields", fields)
+            }
+            Self::Internal(_) => Msg::Internal.into(),
+            Self::Database(_) => Msg::DatabaseError.into(),
+            Self::Io(_) => Msg::IoError.into(),
+        }
+    }
+
+    fn status_and_code(&self) -> (StatusCode, ErrorCode) {
+        match self {
+            Self::BadRequest(_) => (StatusCode::BAD_REQUEST, ErrorCode::BadRequest),
+            Self::Unauthorized(_) => (StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized),
+            Self::Forbidden(_) => (StatusCode::FORBIDDEN, ErrorCode::Forbidden),
+            Self::EmailNotVerified(_) => (StatusCode::FORBIDDEN, ErrorCode::EmailNotVerified),
+            Self::NotFound(_) => (StatusCode::NOT_FOUND, ErrorCode::NotFound),
+            Self::Conflict(_) => (StatusCode::CONFLICT, ErrorCode::Conflict),
+            Self::Gone(_) => (StatusCode::GONE, ErrorCode::Gone),
+            Self::PayloadTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, ErrorCode::PayloadTooLarge),
+            Self::QuotaExceeded { .. } | Self::RateLimited { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, ErrorCode::QuotaExceeded)
+            }
+            Self::UnprocessableEntity(_) => (StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::UnprocessableEntity),
+            Self::MalwareDetected(_) => (StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::MalwareDetected),
+            Self::Validation(_) => (StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::ValidationError),
+            Self::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::InternalError),
+            Self::ServiceUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, ErrorCode::ServiceUnavailable),
+            Self::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::DatabaseError),
+            Self::Io(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::IoError),
+            Self::ImageProcessing(_) => (StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::ProcessingError),
+        }
+    }
+}
+
+// Convert AppError to HTTP response
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error_code) = self.status_and_code();
+
+        // Log error details
+        if status.is_server_error() {
+            tracing::error!("Server error: {:?}", self);
+        } else {
+            tracing::warn!("Client error: {:?}", self);
+        }
+
+        let locale = catalog::current();
+        let message = self.message();
+        let mut body = json!({
+            "error": {
+                "code": error_code,
+                "reason": message.id(),
+                "message": message.render(locale),
+                "details": message.details(),
+            }
+        });
+        if let Self::QuotaExceeded { quota, .. } = &self {
+            body["error"]["quota"] = json!(quota);
+        }
+        if let Self::Validation(errors) = &self {
+            body["error"]["errors"] = json!(errors);
+        }
+        let body = Json(body);
+
+        let mut response = (status, body).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(locale.tag()));
+        if let Self::RateLimited { retry_after_secs, .. } = &self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+        }
+        response
+    }
+}
+
+/// Convenience type alias for Results
+pub type Result<T> = std::result::Result<T, AppError>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::{code, FieldError};
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn body(response: Response) -> Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_validation_errors_render_in_the_envelope() {
+        let response = AppError::Validation(vec![
+            FieldError::new("email", code::INVALID_FORMAT, Msg::InvalidEmail),
+            FieldError::new("password", code::TOO_SHORT, Msg::TooShort.with("min", 8)),
+        ])
+        .into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.headers()[header::CONTENT_LANGUAGE], "en");
+
+        assert_eq!(
+            body(response).await,
+            json!({
+                "error": {
+                    "code": "VALIDATION_ERROR",
+                    "reason": "validation_failed",
+                    "message": "Request validation failed",
+                    "details": { "fields": ["email", "password"] },
+                    "errors": [
+                        { "field": "email", "code": "invalid_format", "message": "Invalid email format" },
+                        { "field": "password", "code": "too_short", "message": "Must be at least 8 characters" },
+                    ],
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quota_errors_give_their_numbers_as_details() {
+        let status = crate::services::quota::QuotaStatus {
+            tier: "free".to_string(),
+            images: crate::services::quota::Usage { used: 3, limit: Some(3) },
+            videos: crate::services::quota::Usage { used: 0, limit: None },
+            concurrent: crate::services::quota::Usage { used: 0, limit: Some(1) },
+            storage: crate::services::quota::Usage { used: 0, limit: None },
+            resets_at: chrono::Utc::now(),
+            organization_id: None,
+        };
+        let violation = status.check(crate::services::sniff::MediaKind::Image, 1).unwrap_err();
+        let message = violation.message();
+        let response = AppError::QuotaExceeded { message, quota: Box::new(status) }.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let body = body(response).await;
+        assert_eq!(body["error"]["code"], "QUOTA_EXCEEDED");
+        assert_eq!(body["error"]["reason"], "daily_quota_exceeded");
+        assert_eq!(body["error"]["details"], json!({ "used": 3, "limit": 3 }));
+        assert_eq!(body["error"]["quota"]["images"]["used"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_codes_and_details_stay_when_the_language_changes() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { AppError::Conflict(Msg::TooManyOpenUploads.with("max", 5)) }),
+            )
+            .layer(axum::middleware::from_fn(catalog::negotiate_locale));
+        let request = |language: &str| {
+            Request::builder().uri("/").header(header::ACCEPT_LANGUAGE, language).body(Body::empty()).unwrap()
+        };
+
+        let en = app.clone().oneshot(request("en-GB")).await.unwrap();
+        let es = app.oneshot(request("es-ES,es;q=0.9,en;q=0.5")).await.unwrap();
+        assert_eq!(en.headers()[header::CONTENT_LANGUAGE], "en");
+        assert_eq!(es.headers()[header::CONTENT_LANGUAGE], "es");
+
+        let (en, es) = (body(en).await, body(es).await);
+        assert_eq!(en["error"]["code"], "CONFLICT");
+        assert_eq!(en["error"]["reason"], "too_many_open_uploads");
+        assert_eq!(en["error"]["details"], json!({ "max": 5 }));
+        assert_eq!(en["error"]["message"], "At most 5 uploads may be in progress; complete or cancel one first");
+        assert_eq!(
+            es["error"]["message"],
+            "Puede haber como máximo 5 subidas en curso; completa o cancela una primero"
+        );
+        for key in ["code", "reason", "details"] {
+            assert_eq!(en["error"][key], es["error"][key]);
+        }
+    }
+
+    /// Clients branch on these; renaming one breaks them
+    #[test]
+    fn test_error_codes_are_stable() {
+        let codes = [
+            ErrorCode::BadRequest,
+            ErrorCode::Unauthorized,
+            ErrorCode::Forbidden,
+            ErrorCode::EmailNotVerified,
+            ErrorCode::NotFound,
+            ErrorCode::Conflict,
+            ErrorCode::Gone,
+            ErrorCode::PayloadTooLarge,
+            ErrorCode::QuotaExceeded,
+            ErrorCode::UnprocessableEntity,
+            ErrorCode::MalwareDetected,
+            ErrorCode::ValidationError,
+            ErrorCode::InternalError,
+            ErrorCode::ServiceUnavailable,
+            ErrorCode::DatabaseError,
+            ErrorCode::IoError,
+            ErrorCode::ProcessingError,
+        ];
+        assert_eq!(
+            json!(codes),
+            json!([
+                "BAD_REQUEST", "UNAUTHORIZED", "FORBIDDEN", "EMAIL_NOT_VERIFIED", "NOT_FOUND", "CONFLICT", "GONE",
+                "PAYLOAD_TOO_LARGE", "QUOTA_EXCEEDED", "UNPROCESSABLE_ENTITY", "MALWARE_DETECTED", "VALIDATION_ERROR",
+                "INTERNAL_ERROR", "SERVICE_UNAVAILABLE", "DATABASE_ERROR", "IO_ERROR", "PROCESSING_ERROR",
+            ])
+        );
+        assert_eq!(json!(Msg::DailyQuotaExceeded), "daily_quota_exceeded");
+        assert_eq!(json!(Msg::InvalidAssetId), "invalid_asset_id");
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_keep_their_cause_out_of_the_response() {
+        let response = AppError::Internal("Failed to hash password: secret detail".to_string()).into_response();
+        let body = body(response).await;
+        assert_eq!(body["error"]["code"], "INTERNAL_ERROR");
+        assert_eq!(body["error"]["message"], "Internal server error");
+    }
+}
@@ -0,0 +1,297 @@
+// backend/src/idempotency.rs
+// `Idempotency-Key` support for job-creating requests: a retry with the same
+// key and payload gets the first response back instead of a second job
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{FromRequest, Multipart, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::auth::AuthUser;
+use crate::body_limit;
+use crate::db;
+use crate::error::{AppError, Msg, Result};
+use crate::AppState;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set to `true` on responses replayed from an earlier request
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// A request in flight this long is taken to have died, and its key is
+/// handed to the next request that sends it
+const STALE_AFTER: Duration = Duration::from_secs(300);
+
+/// With an `Idempotency-Key` header, run the request only if the key is new
+/// to the user. Repeats of the request replay its response with
+/// `Idempotent-Replayed: true`; the key sent with a different request is
+/// refused with 422, and while the first is still running with 409. Only
+/// successful responses are kept, so a failed request can be retried under
+/// the same key. Goes inside the auth middleware.
+pub async fn idempotent(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    run_once(state, request, next).await.unwrap_or_else(IntoResponse::into_response)
+}
+
+async fn run_once(state: AppState, request: Request, next: Next) -> Result<Response> {
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(next.run(request).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| valid_key(key))
+        .ok_or_else(|| {
+            AppError::BadRequest(Msg::IdempotencyKeyInvalid.with("max", MAX_KEY_LENGTH))
+        })?
+        .to_string();
+    let Some(user_id) = request.extensions().get::<AuthUser>().map(|user| user.id) else {
+        return Ok(next.run(request).await);
+    };
+
+    // The body is read here once, hashed, and handed on to the handler
+    let (parts, body) = request.into_parts();
+    let too_large = || AppError::PayloadTooLarge(Msg::RequestTooLarge.into());
+    let body = if is_multipart(&parts.headers) {
+        // Uploads can be large, so they wait on disk rather than in memory
+        let path = Path::new(&state.config.processing.temp_dir).join(format!("idempotent_{}", uuid::Uuid::new_v4()));
+        // Removed on drop, however far spooling gets
+        let spooled = ReadBody::Spooled(path.clone());
+        spool(body, &path, body_limit::upload_limit(&state.config.processing)).await?;
+        spooled
+    } else {
+        ReadBody::Memory(to_bytes(body, body_limit::JSON_BODY_LIMIT).await.map_err(|_| too_large())?)
+    };
+    let request_hash = request_hash(&parts, &body).await?;
+
+    let ttl = Duration::from_secs(state.config.processing.idempotency_key_ttl_hours * 3600);
+    if !db::IdempotencyKey::claim(&state.db, user_id, &key, &request_hash, ttl, STALE_AFTER).await? {
+        return replay(&state, user_id, &key, &request_hash).await;
+    }
+
+    let response = next.run(Request::from_parts(parts, body.open().await?)).await;
+    if !response.status().is_success() {
+        if let Err(e) = db::IdempotencyKey::release(&state.db, user_id, &key).await {
+            tracing::warn!("Failed to release idempotency key: {:?}", e);
+        }
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to read response body: {}", e)))?;
+    let content_type = parts.headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let stored =
+        db::IdempotencyKey::complete(&state.db, user_id, &key, parts.status.as_u16() as i16, content_type, &body)
+            .await;
+    if let Err(e) = stored {
+        // The work is done either way; a retry will find the key in flight
+        // until it goes stale
+        tracing::warn!("Failed to store the response for an idempotency key: {:?}", e);
+    }
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+async fn replay(state: &AppState, user_id: uuid::Uuid, key: &str, request_hash: &str) -> Result<Response> {
+    let in_flight = || AppError::Conflict(Msg::IdempotencyInProgress.into());
+    let earlier = db::IdempotencyKey::find(&state.db, user_id, key).await?.ok_or_else(in_flight)?;
+    if earlier.request_hash != request_hash {
+        return Err(AppError::UnprocessableEntity(Msg::IdempotencyKeyReused.into()));
+    }
+    let (Some(status), Some(body)) = (earlier.response_status, earlier.response_body) else {
+        return Err(in_flight());
+    };
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(status as u16).unwrap_or(StatusCode::OK);
+    if let Some(content_type) = earlier.response_content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+    }
+    response.headers_mut().insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    Ok(response)
+}
+
+fn valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LENGTH && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn is_multipart(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"))
+}
+
+/// A request body read ahead of its handler: in memory, or spooled to a temp
+/// file that is removed when this is dropped
+enum ReadBody {
+    Memory(Bytes),
+    Spooled(PathBuf),
+}
+
+impl ReadBody {
+    /// The body again from the start
+    async fn open(&self) -> std::io::Result<Body> {
+        match self {
+            Self::Memory(bytes) => Ok(Body::from(bytes.clone())),
+            Self::Spooled(path) => {
+                let file = tokio::fs::File::open(path).await?;
+                Ok(Body::from_stream(tokio_util::io::ReaderStream::new(file)))
+            }
+        }
+    }
+}
+
+impl Drop for ReadBody {
+    fn drop(&mut self) {
+        if let Self::Spooled(path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Write `body` to `path` chunk by chunk, refusing it once past `limit` bytes
+async fn spool(body: Body, path: &Path, limit: usize) -> Result<()> {
+    let too_large = || AppError::PayloadTooLarge(Msg::RequestTooLarge.into());
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut stream = body.into_data_stream();
+    let mut size = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| too_large())?;
+        size += chunk.len();
+        if size > limit {
+            return Err(too_large());
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// SHA-256 of the method, path and body. A multipart body is hashed field by
+/// field, since clients pick a new boundary for every attempt; one that isn't
+/// well-formed is hashed as sent.
+async fn request_hash(parts: &Parts, body: &ReadBody) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(parts.method.as_str());
+    hasher.update(b" ");
+    hasher.update(parts.uri.path());
+    hasher.update(b"\n");
+    match multipart_hash(parts, body.open().await?).await {
+        Some(fields) => hasher.update(fields),
+        None => {
+            let mut stream = body.open().await?.into_data_stream();
+            while let Some(chunk) = stream.next().await {
+                hasher.update(chunk.map_err(|e| AppError::Internal(format!("Failed to reread the body: {}", e)))?);
+            }
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hash of each field's name, file name and content, read a chunk at a time,
+/// or `None` if the body isn't well-formed multipart
+async fn multipart_hash(parts: &Parts, body: Body) -> Option<[u8; 32]> {
+    if !is_multipart(&parts.headers) {
+        return None;
+    }
+    // The extensions carry the route's body limit
+    let mut request = Request::new(body);
+    *request.headers_mut() = parts.headers.clone();
+    *request.extensions_mut() = parts.extensions.clone();
+    let mut multipart = Multipart::from_request(request, &()).await.ok()?;
+
+    let mut hasher = Sha256::new();
+    while let Some(mut field) = multipart.next_field().await.ok()? {
+        // Each part goes in as its own digest, so adjacent parts can't run together
+        hasher.update(Sha256::digest(field.name().unwrap_or_default()));
+        hasher.update(Sha256::digest(field.file_name().unwrap_or_default()));
+        let mut content = Sha256::new();
+        while let Some(chunk) = field.chunk().await.ok()? {
+            content.update(&chunk);
+        }
+        hasher.update(content.finalize());
+    }
+    Some(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(content_type: &str) -> Parts {
+        let (parts, _) = Request::post("/api/upload")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(())
+            .unwrap()
+            .into_parts();
+        parts
+    }
+
+    fn multipart(boundary: &str, content: &str) -> Bytes {
+        Bytes::from(format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n\r\n{c}\r\n--{b}--\r\n",
+            b = boundary,
+            c = content
+        ))
+    }
+
+    async fn hash(parts: &Parts, body: &Bytes) -> String {
+        request_hash(parts, &ReadBody::Memory(body.clone())).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_multipart_hash_ignores_the_boundary() {
+        let first = hash(&parts("multipart/form-data; boundary=aaa"), &multipart("aaa", "png")).await;
+        let retry = hash(&parts("multipart/form-data; boundary=bbb"), &multipart("bbb", "png")).await;
+        let other = hash(&parts("multipart/form-data; boundary=aaa"), &multipart("aaa", "gif")).await;
+        assert_eq!(first, retry);
+        assert_ne!(first, other);
+    }
+
+    #[tokio::test]
+    async fn test_json_hash_covers_the_path_and_body() {
+        let body = Bytes::from_static(br#"{"asset_id":"a"}"#);
+        let convert = hash(&parts("application/json"), &body).await;
+        assert_eq!(convert, hash(&parts("application/json"), &body).await);
+        assert_ne!(convert, hash(&parts("application/json"), &Bytes::from_static(b"{}")).await);
+
+        let (other_path, _) = Request::post("/api/remove-bg").body(()).unwrap().into_parts();
+        assert_ne!(convert, hash(&other_path, &body).await);
+    }
+
+    #[tokio::test]
+    async fn test_spooled_body_hashes_and_replays_like_one_in_memory() {
+        let upload = multipart("aaa", &"x".repeat(100_000));
+        let parts = parts("multipart/form-data; boundary=aaa");
+        let path = std::env::temp_dir().join(format!("idempotent_test_{}", uuid::Uuid::new_v4()));
+        let spooled = ReadBody::Spooled(path.clone());
+
+        let refused = spool(Body::from(upload.clone()), &path, upload.len() - 1).await;
+        assert!(matches!(refused, Err(AppError::PayloadTooLarge(_))));
+        spool(Body::from(upload.clone()), &path, upload.len()).await.unwrap();
+        assert_eq!(request_hash(&parts, &spooled).await.unwrap(), hash(&parts, &upload).await);
+        let replayed = to_bytes(spooled.open().await.unwrap(), usize::MAX).await.unwrap();
+        assert_eq!(replayed, upload);
+
+        drop(spooled);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_valid_key() {
+        assert!(valid_key("3f1c9a7e-retry-1"));
+        assert!(!valid_key(""));
+        assert!(!valid_key("has space"));
+        assert!(!valid_key(&"k".repeat(MAX_KEY_LENGTH + 1)));
+    }
+}
@@ -0,0 +1,229 @@
+// backend/src/jwt_keys.rs
+// The keys access tokens are signed and verified with. Tokens carry the `kid`
+// of the key that signed them, so a rotated-out key can keep verifying its
+// own tokens alongside the current one.
+
+use anyhow::{anyhow, ensure};
+use jsonwebtoken::errors::{Error, ErrorKind};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::{JwtConfig, JwtKey, KeyPair, Pem};
+
+struct VerifyingKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// The current key, which signs, and every key that verifies
+pub struct KeyRing {
+    signing: EncodingKey,
+    /// The current key first, then the previous ones
+    verifying: Vec<VerifyingKey>,
+}
+
+impl KeyRing {
+    /// Parse the configured keys. Fails on a PEM that isn't the kind of key
+    /// the algorithm needs, or a private key that doesn't match its public one.
+    pub fn new(config: &JwtConfig) -> Result<Self, anyhow::Error> {
+        let current = verifying_key(&config.current)?;
+        let signing = match &config.current {
+            JwtKey::Hmac(secret) => EncodingKey::from_secret(secret.as_bytes()),
+            JwtKey::Rsa(pair) => {
+                let pem = private_pem(pair)?;
+                EncodingKey::from_rsa_pem(pem.contents.as_bytes())
+                    .map_err(|e| not_a_key(pem, "an RSA private key", e))?
+            }
+            JwtKey::Ed25519(pair) => {
+                let pem = private_pem(pair)?;
+                EncodingKey::from_ed_pem(pem.contents.as_bytes())
+                    .map_err(|e| not_a_key(pem, "an Ed25519 private key", e))?
+            }
+        };
+        let verifying = std::iter::once(Ok(current))
+            .chain(config.previous.iter().map(verifying_key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let keys = Self { signing, verifying };
+
+        if let JwtKey::Rsa(pair) | JwtKey::Ed25519(pair) = &config.current {
+            let probe = keys.sign(&serde_json::json!({ "exp": u32::MAX })).map_err(|e| anyhow!("{}", e))?;
+            ensure!(
+                keys.verify::<serde_json::Value>(&probe).is_ok(),
+                "JWT_PRIVATE_KEY_PATH '{}' does not match JWT_PUBLIC_KEY_PATH '{}'",
+                private_pem(pair)?.path,
+                pair.public_pem.path
+            );
+        }
+        Ok(keys)
+    }
+
+    /// Sign `claims` with the current key, naming it in the `kid` header
+    pub fn sign(&self, claims: &impl Serialize) -> Result<String, Error> {
+        let current = &self.verifying[0];
+        let mut header = Header::new(current.algorithm);
+        header.kid = Some(current.kid.clone());
+        encode(&header, claims, &self.signing)
+    }
+
+    /// The claims of a token signed by any of the keys that hasn't expired.
+    /// Tokens from before key ids were only ever signed with an HS256 secret.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, Error> {
+        let header = decode_header(token)?;
+        let mut result = Err(Error::from(ErrorKind::InvalidSignature));
+        let candidates = self.verifying.iter().filter(|key| match &header.kid {
+            Some(kid) => *kid == key.kid,
+            None => key.algorithm == Algorithm::HS256,
+        });
+        for key in candidates {
+            result = decode::<T>(token, &key.key, &Validation::new(key.algorithm)).map(|data| data.claims);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+}
+
+fn verifying_key(key: &JwtKey) -> Result<VerifyingKey, anyhow::Error> {
+    let (algorithm, material, key) = match key {
+        JwtKey::Hmac(secret) => (Algorithm::HS256, secret.as_str(), DecodingKey::from_secret(secret.as_bytes())),
+        JwtKey::Rsa(pair) => {
+            let pem = &pair.public_pem;
+            let key = DecodingKey::from_rsa_pem(pem.contents.as_bytes())
+                .map_err(|e| not_a_key(pem, "an RSA public key", e))?;
+            (Algorithm::RS256, pem.contents.trim(), key)
+        }
+        JwtKey::Ed25519(pair) => {
+            let pem = &pair.public_pem;
+            let key = DecodingKey::from_ed_pem(pem.contents.as_bytes())
+                .map_err(|e| not_a_key(pem, "an Ed25519 public key", e))?;
+            (Algorithm::EdDSA, pem.contents.trim(), key)
+        }
+    };
+    Ok(VerifyingKey { kid: key_id(algorithm, material), algorithm, key })
+}
+
+/// A short fingerprint of the key, the same in every process that loads it
+fn key_id(algorithm: Algorithm, material: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(format!("{:?}:", algorithm))
+        .chain_update(material)
+        .finalize();
+    hex::encode(&digest[..8])
+}
+
+fn private_pem(pair: &KeyPair) -> Result<&Pem, anyhow::Error> {
+    pair.private_pem.as_ref().ok_or_else(|| anyhow!("The signing key needs JWT_PRIVATE_KEY_PATH"))
+}
+
+fn not_a_key(pem: &Pem, kind: &str, error: Error) -> anyhow::Error {
+    anyhow!("'{}' is not {} in PEM format: {}", pem.path, kind, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn pem(name: &str) -> Pem {
+        let path = format!("{}/tests/fixtures/jwt/{}", env!("CARGO_MANIFEST_DIR"), name);
+        Pem { contents: std::fs::read_to_string(&path).unwrap(), path }
+    }
+
+    fn rsa(name: &str, private: bool) -> JwtKey {
+        JwtKey::Rsa(KeyPair {
+            private_pem: private.then(|| pem(&format!("{}.pem", name))),
+            public_pem: pem(&format!("{}.pub.pem", name)),
+        })
+    }
+
+    fn ring(current: JwtKey, previous: Vec<JwtKey>) -> KeyRing {
+        KeyRing::new(&JwtConfig { current, previous }).unwrap()
+    }
+
+    fn claims() -> Value {
+        json!({ "sub": "user", "exp": chrono::Utc::now().timestamp() + 60 })
+    }
+
+    #[test]
+    fn test_tokens_name_their_key_and_verify_with_each_algorithm() {
+        let ed25519 = JwtKey::Ed25519(KeyPair {
+            private_pem: Some(pem("ed25519.pem")),
+            public_pem: pem("ed25519.pub.pem"),
+        });
+        for (key, algorithm) in [
+            (JwtKey::Hmac("secret".to_string()), Algorithm::HS256),
+            (rsa("rsa_current", true), Algorithm::RS256),
+            (ed25519, Algorithm::EdDSA),
+        ] {
+            let keys = ring(key, vec![]);
+            let token = keys.sign(&claims()).unwrap();
+            let header = decode_header(&token).unwrap();
+            assert_eq!(header.alg, algorithm);
+            assert_eq!(header.kid.unwrap().len(), 16);
+            assert_eq!(keys.verify::<Value>(&token).unwrap()["sub"], "user");
+        }
+
+        // A public key alone verifies what the private one signed
+        let signed = ring(rsa("rsa_current", true), vec![]).sign(&claims()).unwrap();
+        let verifier = ring(JwtKey::Hmac("unrelated".to_string()), vec![rsa("rsa_current", false)]);
+        assert_eq!(verifier.verify::<Value>(&signed).unwrap()["sub"], "user");
+    }
+
+    #[test]
+    fn test_previous_keys_verify_until_removed() {
+        let old = ring(rsa("rsa_previous", true), vec![]).sign(&claims()).unwrap();
+        let rotated = ring(rsa("rsa_current", true), vec![rsa("rsa_previous", false)]);
+        assert_eq!(rotated.verify::<Value>(&old).unwrap()["sub"], "user");
+        let new = rotated.sign(&claims()).unwrap();
+        assert_ne!(decode_header(&new).unwrap().kid, decode_header(&old).unwrap().kid);
+
+        let removed = ring(rsa("rsa_current", true), vec![]);
+        assert!(removed.verify::<Value>(&old).is_err());
+        assert!(removed.verify::<Value>(&new).is_ok());
+
+        // Moving off HS256: tokens signed with the secret, before key ids
+        // existed, still verify while it is kept as a previous secret
+        let legacy = encode(&Header::default(), &claims(), &EncodingKey::from_secret(b"old-secret")).unwrap();
+        let moved = ring(rsa("rsa_current", true), vec![JwtKey::Hmac("old-secret".to_string())]);
+        assert_eq!(moved.verify::<Value>(&legacy).unwrap()["sub"], "user");
+        assert!(ring(rsa("rsa_current", true), vec![]).verify::<Value>(&legacy).is_err());
+    }
+
+    #[test]
+    fn test_expired_and_forged_tokens_are_refused() {
+        let keys = ring(JwtKey::Hmac("secret".to_string()), vec![]);
+        let expired = keys.sign(&json!({ "sub": "user", "exp": 1 })).unwrap();
+        assert_eq!(*keys.verify::<Value>(&expired).unwrap_err().kind(), ErrorKind::ExpiredSignature);
+
+        let forged = ring(JwtKey::Hmac("guess".to_string()), vec![]).sign(&claims()).unwrap();
+        assert!(keys.verify::<Value>(&forged).is_err());
+        assert!(keys.verify::<Value>("not.a.token").is_err());
+    }
+
+    #[test]
+    fn test_malformed_and_mismatched_pems_fail_at_startup() {
+        let swapped = JwtKey::Rsa(KeyPair {
+            private_pem: Some(pem("ed25519.pem")),
+            public_pem: pem("rsa_current.pub.pem"),
+        });
+        let error = KeyRing::new(&JwtConfig { current: swapped, previous: vec![] }).err().unwrap().to_string();
+        assert!(error.contains("ed25519.pem' is not an RSA private key in PEM format"), "{}", error);
+
+        let contents = "-----BEGIN PUBLIC KEY-----\nAAAA\n-----END PUBLIC KEY-----\n".to_string();
+        let garbage = Pem { path: "garbage.pem".to_string(), contents };
+        let previous = vec![JwtKey::Ed25519(KeyPair { private_pem: None, public_pem: garbage })];
+        let error = KeyRing::new(&JwtConfig { current: JwtKey::Hmac("s".to_string()), previous }).err().unwrap();
+        assert!(error.to_string().starts_with("'garbage.pem' is not an Ed25519 public key"), "{}", error);
+
+        let mismatched = JwtKey::Rsa(KeyPair {
+            private_pem: Some(pem("rsa_current.pem")),
+            public_pem: pem("rsa_previous.pub.pem"),
+        });
+        let error = KeyRing::new(&JwtConfig { current: mismatched, previous: vec![] }).err().unwrap().to_string();
+        assert!(error.contains("does not match JWT_PUBLIC_KEY_PATH"), "{}", error);
+    }
+}
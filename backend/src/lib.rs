@@ -0,0 +1,556 @@
+// backend/src/lib.rs
+// Everything but `main`: connections, application state, the router and the
+// job consumer, shared by the server and worker binaries and the integration
+// tests
+
+mod auth;
+mod body_limit;
+pub mod config;
+pub mod db;
+mod error;
+mod idempotency;
+mod jwt_keys;
+mod openapi;
+pub mod otel;
+mod request_id;
+mod request_limit;
+mod routes;
+pub mod services;
+pub mod telemetry;
+mod validation;
+
+use anyhow::Context;
+use axum::{extract::DefaultBodyLimit, middleware, routing::delete, routing::get, routing::post, Router};
+use std::sync::Arc;
+use std::time::Duration;
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceBuilder;
+use tower_http::cors::CorsLayer;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use config::Config;
+use services::queue::StatusStore;
+use services::{JobMessage, JobSource};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: sqlx::PgPool,
+    pub storage: Arc<dyn services::Storage>,
+    pub queue: Arc<services::Queue>,
+    pub mailer: Arc<dyn services::Mailer>,
+    pub scanner: Arc<dyn services::Scanner>,
+    /// Whether video work can run, and with which encoders
+    pub ffmpeg: Arc<services::ffmpeg::Ffmpeg>,
+    pub config: Arc<config::Config>,
+    /// Attempt counters for login and registration
+    pub auth_limiter: services::rate_limit::RateLimiter,
+    /// Token buckets for the processing and upload routes
+    pub request_limiter: services::rate_limit::RequestLimiter,
+    /// Renders the metrics recorded since startup
+    pub metrics: PrometheusHandle,
+    /// Cached dependency checks behind `/api/health/ready`
+    pub readiness: Arc<services::readiness::ReadinessProbe>,
+    /// Bytes received so far by resumable uploads
+    pub part_files: Arc<services::resumable::PartFiles>,
+    /// Result download URLs handed out with job status
+    pub signed_urls: Arc<services::signed_urls::SignedUrlCache>,
+    /// Signs access tokens and verifies them
+    pub keys: Arc<jwt_keys::KeyRing>,
+}
+
+impl AppState {
+    pub fn new(config: Config, resources: Resources, metrics: PrometheusHandle) -> Self {
+        Self {
+            auth_limiter: services::rate_limit::RateLimiter::new(
+                Duration::from_secs(config.rate_limits.window_seconds),
+                resources.queue.redis_connection(),
+            ),
+            request_limiter: services::rate_limit::RequestLimiter::new(
+                config.rate_limits.request_burst,
+                config.rate_limits.requests_per_minute,
+                resources.queue.redis_connection(),
+            ),
+            keys: Arc::new(jwt_keys::KeyRing::new(&config.jwt).expect("JWT keys are checked as the config loads")),
+            part_files: Arc::new(services::resumable::PartFiles::new(&config.processing.temp_dir)),
+            // Renewed once a quarter of their lifetime is left
+            signed_urls: Arc::new(services::signed_urls::SignedUrlCache::new(
+                config.storage.download_url_ttl() / 4,
+            )),
+            db: resources.db,
+            storage: resources.storage,
+            queue: resources.queue,
+            mailer: resources.mailer,
+            scanner: resources.scanner,
+            ffmpeg: resources.ffmpeg,
+            config: Arc::new(config),
+            metrics,
+            readiness: Arc::default(),
+        }
+    }
+}
+
+/// Connections the API and the worker both need
+#[derive(Clone)]
+pub struct Resources {
+    pub db: sqlx::PgPool,
+    pub storage: Arc<dyn services::Storage>,
+    pub queue: Arc<services::Queue>,
+    pub mailer: Arc<dyn services::Mailer>,
+    /// Checks uploads for malware before they are stored
+    pub scanner: Arc<dyn services::Scanner>,
+    /// The ffmpeg video work runs, and what it can encode
+    pub ffmpeg: Arc<services::ffmpeg::Ffmpeg>,
+}
+
+impl Resources {
+    /// Connect to and migrate the database, set up storage, the temp
+    /// directory, mail and upload scanning, locate ffmpeg, and open the job queue. The receiver is the
+    /// queue's in-process channel, for `start_worker`.
+    pub async fn connect(config: &Config) -> anyhow::Result<(Self, Receiver<JobMessage>)> {
+        // Create database pool with retry logic
+        let db = db::create_pool(&config.database_url)
+            .await
+            .context("Failed to create database connection pool")?;
+        tracing::info!("✓ Database connection pool created");
+
+        // Test database connection
+        sqlx::query("SELECT 1")
+            .execute(&db)
+            .await
+            .context("Failed to connect to database. Is PostgreSQL running?")?;
+        tracing::info!("✓ Database connection verified");
+
+        // Run migrations
+        db::run_migrations(&db)
+            .await
+            .context("Failed to run database migrations")?;
+        tracing::info!("✓ Database migrations completed");
+
+        // Initialize storage. Both backends are set up when configured so
+        // objects written before a change of STORAGE_MODE can still be read.
+        let local_storage = services::LocalStorage::new(&config.storage.local_path);
+        let storage: Arc<dyn services::Storage> = if config.storage.mode == "s3" {
+            let s3_storage = services::S3Storage::new(
+                config
+                    .storage
+                    .s3_bucket
+                    .as_deref()
+                    .context("S3_BUCKET required when STORAGE_MODE=s3")?,
+                config
+                    .storage
+                    .s3_endpoint
+                    .as_deref()
+                    .context("S3_ENDPOINT required when STORAGE_MODE=s3")?,
+                &config.storage.s3_region,
+                config.storage.s3_access_key.as_deref(),
+                config.storage.s3_secret_key.as_deref(),
+            )
+            .context("Failed to initialize S3 storage")?;
+            let local = std::path::Path::new(&config.storage.local_path).is_dir().then_some(local_storage);
+            Arc::new(services::StorageRouter::s3(s3_storage, local))
+        } else {
+            std::fs::create_dir_all(&config.storage.local_path)
+                .context("Failed to create local storage directory")?;
+            let s3_storage = match (&config.storage.s3_bucket, &config.storage.s3_endpoint) {
+                (Some(bucket), Some(endpoint)) => Some(
+                    services::S3Storage::new(
+                        bucket,
+                        endpoint,
+                        &config.storage.s3_region,
+                        config.storage.s3_access_key.as_deref(),
+                        config.storage.s3_secret_key.as_deref(),
+                    )
+                    .context("Failed to initialize S3 storage")?,
+                ),
+                _ => None,
+            };
+            Arc::new(services::StorageRouter::local(local_storage, s3_storage))
+        };
+        tracing::info!("✓ Storage initialized: {}", config.storage.mode);
+
+        // Create required directories
+        std::fs::create_dir_all(&config.processing.temp_dir)
+            .context("Failed to create temp directory")?;
+        tracing::info!("✓ Temporary directory created");
+
+        // Initialize job queue (pass optional redis url)
+        let redis_url_opt = if config.redis_url.is_empty() { None } else { Some(config.redis_url.as_str()) };
+        let (queue, wake) = services::Queue::new(100, redis_url_opt).await;
+
+        // Without SMTP, emails such as verification links only go to the log
+        let mailer: Arc<dyn services::Mailer> = if config.mail.smtp_host.is_some() {
+            Arc::new(services::SmtpMailer::new(&config.mail).context("Failed to set up SMTP")?)
+        } else {
+            Arc::new(services::LogMailer)
+        };
+        tracing::info!("✓ Mail: {}", config.mail.smtp_host.as_deref().unwrap_or("log only"));
+
+        let scanner: Arc<dyn services::Scanner> = match &config.scan.clamd_address {
+            Some(address) => Arc::new(services::ClamAvScanner::new(
+                address.clone(),
+                Duration::from_secs(config.scan.timeout_seconds),
+            )),
+            None => Arc::new(services::NoopScanner),
+        };
+        tracing::info!("✓ Upload scanning: {}", config.scan.clamd_address.as_deref().unwrap_or("off"));
+
+        let ffmpeg = Arc::new(services::ffmpeg::Ffmpeg::detect(&config.processing.ffmpeg_path).await);
+
+        let resources = Self {
+            db,
+            storage,
+            queue: Arc::new(queue),
+            mailer,
+            scanner,
+            ffmpeg,
+        };
+        Ok((resources, wake))
+    }
+}
+
+/// Run the job consumer in this process: start the workers and the cleanup
+/// sweep and, with Redis, poll for jobs pushed by any API instance. Once
+/// `shutdown` is cancelled the handle resolves when the workers have finished
+/// the jobs in hand.
+pub fn start_worker(
+    config: &Config,
+    resources: &Resources,
+    wake: Receiver<JobMessage>,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    if !config.redis_url.is_empty() {
+        spawn_redis_poller(resources.queue.clone(), config.redis_url.clone(), shutdown.clone());
+    }
+    spawn_consumer(
+        config,
+        resources,
+        JobSource::Local(wake),
+        resources.queue.get_statuses_handle(),
+        shutdown,
+    )
+}
+
+/// Run the job consumer apart from the API, as the `worker` binary does.
+/// Workers wait on the Redis list themselves and statuses go only to Redis
+/// and the database, where the API reads them.
+pub fn start_external_worker(
+    config: &Config,
+    resources: &Resources,
+    shutdown: CancellationToken,
+) -> anyhow::Result<JoinHandle<()>> {
+    let redis = resources
+        .queue
+        .redis_connection()
+        .context("The worker needs Redis to hear about new jobs; check REDIS_URL")?;
+    let client = redis::Client::open(config.redis_url.as_str()).context("Invalid REDIS_URL")?;
+    Ok(spawn_consumer(
+        config,
+        resources,
+        JobSource::Redis(client),
+        StatusStore::redis_only(redis),
+        shutdown,
+    ))
+}
+
+fn spawn_consumer(
+    config: &Config,
+    resources: &Resources,
+    source: JobSource,
+    statuses: StatusStore,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    // Jobs interrupted by a crash are requeued once their heartbeat goes stale;
+    // the workers sweep all queued jobs as soon as they start
+    let worker = services::start_worker(
+        source,
+        resources.storage.clone(),
+        resources.db.clone(),
+        resources.queue.clone(),
+        statuses,
+        resources.ffmpeg.clone(),
+        config.clone(),
+        shutdown.clone(),
+    );
+    tracing::info!("✓ Background worker started");
+
+    // Sweep expired assets, old results and stale temp files on an interval
+    let cleanup = services::start_cleanup(
+        resources.db.clone(),
+        resources.storage.clone(),
+        config.processing.clone(),
+        shutdown,
+    );
+    tracing::info!("✓ Cleanup task started");
+
+    tokio::spawn(async move {
+        if let Err(e) = worker.await {
+            tracing::error!("Worker task failed: {:?}", e);
+        }
+        if let Err(e) = cleanup.await {
+            tracing::error!("Cleanup task failed: {:?}", e);
+        }
+    })
+}
+
+/// Turn messages on the Redis list into wake-ups on the in-process channel,
+/// so this process's workers look for new jobs
+fn spawn_redis_poller(queue: Arc<services::Queue>, redis_url: String, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        // Use a dedicated redis client here
+        match redis::Client::open(redis_url.as_str()) {
+            Ok(client) => match client.get_multiplexed_async_connection().await {
+                Ok(mut conn) => while !shutdown.is_cancelled() {
+                    // BRPOP with 5 second timeout to allow graceful shutdown checks
+                    let res: Result<Option<(String, String)>, redis::RedisError> = redis::cmd("BRPOP")
+                        .arg(services::queue::JOB_QUEUE_KEY)
+                        .arg(5)
+                        .query_async(&mut conn)
+                        .await;
+
+                    match res {
+                        Ok(Some((_list, payload))) => {
+                            if let Ok(job) = serde_json::from_str::<JobMessage>(&payload) {
+                                // Wake a local worker (best-effort; the job stays queued in the DB)
+                                if let Err(e) = queue.forward_to_local(job).await {
+                                    tracing::error!("Failed to forward job from redis to local channel: {:?}", e);
+                                }
+                            } else {
+                                tracing::warn!("Failed to deserialize job payload from redis");
+                            }
+                        }
+                        Ok(None) => {
+                            // timeout, continue
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::error!("Redis BRPOP error: {:?}", e);
+                            // On error, back off briefly
+                            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                        }
+                    }
+                },
+                Err(e) => tracing::error!("Failed to get async redis connection: {:?}", e),
+            },
+            Err(e) => tracing::error!("Failed to create redis client: {:?}", e),
+        }
+    });
+}
+
+/// Log to stdout, filtered by `RUST_LOG`, and export spans when an OTLP
+/// endpoint is configured. The exporter, if any, should be shut down on exit
+/// to send the spans still queued.
+pub fn init_tracing(
+    config: &config::TracingConfig,
+    service_name: &str,
+) -> Result<Option<otel::Exporter>, anyhow::Error> {
+    let exporter = otel::Exporter::new(config, service_name).context("Failed to set up span export")?;
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "info,media_processor_server=debug".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .with(exporter.as_ref().map(otel::Exporter::layer))
+        .init();
+    Ok(exporter)
+}
+
+/// Resolve on Ctrl-C or SIGTERM and cancel `token`
+pub async fn shutdown_signal(token: CancellationToken) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutdown signal received, finishing in-flight work...");
+    token.cancel();
+}
+
+/// Every route with its middleware. Serve it with connect info
+/// (`into_make_service_with_connect_info::<SocketAddr>`): the auth rate
+/// limits key on the client address.
+pub fn build_router(state: AppState) -> Router {
+    let upload_limit = body_limit::upload_limit(&state.config.processing);
+    let lut_limit = body_limit::lut_limit(&state.config.processing);
+    Router::new()
+        // Protected routes
+        .route("/api/auth/change-password", post(routes::change_password))
+        .route("/api/auth/change-email", post(routes::change_email))
+        .route("/api/auth/resend-verification", post(routes::resend_verification))
+        .route("/api/auth/account", delete(routes::delete_account))
+        .route("/api/auth/switch-organization", post(routes::switch_organization))
+        .route(
+            "/api/upload/:upload_id",
+            get(routes::get_upload)
+                .patch(routes::upload_chunk)
+                .delete(routes::cancel_upload)
+                .layer(RequestBodyLimitLayer::new(upload_limit)),
+        )
+        .route("/api/assets", get(routes::list_assets))
+        .route("/api/assets/by-hash/:hash", get(routes::find_asset_by_hash))
+        .route("/api/assets/:asset_id", get(routes::get_asset).delete(routes::delete_asset))
+        .route("/api/assets/:asset_id/thumbnail", get(routes::get_asset_thumbnail))
+        .route("/api/assets/:asset_id/restore", post(routes::restore_asset))
+        .route("/api/luts", get(routes::list_luts))
+        .route("/api/luts/:lut_id", delete(routes::delete_lut))
+        // Compatibility: OpenAPI/contract tests expect /api/status/{jobId}
+        .route("/api/status/:job_id", get(routes::get_status))
+        .route("/api/jobs/:job_id", get(routes::get_job_status))
+        .route("/api/jobs/:job_id/extend", post(routes::extend_result))
+        .route("/api/jobs/:job_id/events", get(routes::list_job_events))
+        .route("/api/jobs", get(routes::list_user_jobs))
+        .route("/api/quota", get(routes::get_quota))
+        .route("/api/stats", get(routes::get_stats))
+        .route("/api/download/:job_id", get(routes::download_result))
+        .route("/api/download/:job_id/url", get(routes::download_url))
+        .route(
+            "/api/organizations",
+            post(routes::create_organization).get(routes::list_organizations),
+        )
+        .route("/api/organizations/invites/accept", post(routes::accept_invite))
+        .route("/api/organizations/:org_id/members", get(routes::list_organization_members))
+        .route("/api/organizations/:org_id/invites", post(routes::invite_member))
+        .route("/api/keys", post(routes::create_api_key).get(routes::list_api_keys))
+        .route("/api/keys/:key_id", delete(routes::revoke_api_key))
+        .route("/api/admin/users", get(routes::admin_list_users))
+        .route("/api/admin/users/:user_id/tier", post(routes::admin_update_tier))
+        .route("/api/admin/jobs", get(routes::admin_list_jobs))
+        .route("/api/admin/stats", get(routes::admin_stats))
+        .route("/api/admin/storage/relocate", post(routes::admin_relocate_storage))
+        // Processing and uploads, throttled per user
+        .merge(throttled_routes(state.clone(), upload_limit, lut_limit))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::auth_middleware,
+        ))
+        // Public routes. `layer` only wraps routes added before it, so these
+        // must come after the auth middleware.
+        .route("/api/health", get(routes::health))
+        .route("/api/health/ready", get(routes::ready))
+        .route("/api/capabilities", get(routes::get_capabilities))
+        .route("/api/auth/register", post(routes::register))
+        .route("/api/auth/login", post(routes::login))
+        .route("/api/auth/verify", get(routes::verify_email))
+        .route("/api/auth/forgot-password", post(routes::forgot_password))
+        .route("/api/auth/reset-password", post(routes::reset_password))
+        // Authorized by the signed token in the path
+        .route("/api/files/:token", get(routes::download_file))
+        .route("/metrics", get(routes::prometheus_metrics))
+        .route("/api/openapi.json", get(openapi::openapi_json))
+        .route("/api/docs", get(openapi::docs))
+        // Request metrics for every route above, keyed by route template
+        .route_layer(middleware::from_fn(telemetry::track_http))
+        // Everything else takes small JSON bodies; the upload and LUT routes
+        // set their own limits above, which take precedence
+        .layer(DefaultBodyLimit::max(body_limit::JSON_BODY_LIMIT))
+        .layer(middleware::from_fn(body_limit::payload_too_large_as_json))
+        // Errors are written in the language the request asks for
+        .layer(middleware::from_fn(error::catalog::negotiate_locale))
+        // Add state
+        .with_state(state)
+        // CORS
+        .layer(
+            CorsLayer::permissive()
+                .allow_origin(tower_http::cors::Any)
+                .allow_methods([
+                    hyper::Method::GET,
+                    hyper::Method::POST,
+                    hyper::Method::DELETE,
+                    hyper::Method::OPTIONS,
+                ])
+                .allow_headers(tower_http::cors::Any),
+        )
+        // Outermost, so the ID and access log cover every request. Incoming
+        // `X-Request-Id` values are kept; responses echo the ID back.
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(request_id::request_span)
+                        .on_response(request_id::record_response),
+                )
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        )
+}
+
+/// Routes that start work or take in files, throttled per user by token
+/// bucket (`REQUEST_RATE_LIMIT_*`). Chunks of a resumable upload and its
+/// status are left out: one upload sends many, and starting and completing
+/// it are limited already.
+fn throttled_routes(state: AppState, upload_limit: usize, lut_limit: usize) -> Router<AppState> {
+    // Job-creating routes honor `Idempotency-Key`
+    let idempotent = middleware::from_fn_with_state(state.clone(), idempotency::idempotent);
+    Router::new()
+        .route(
+            "/api/upload",
+            post(routes::upload)
+                .layer(
+                    ServiceBuilder::new()
+                        .layer(DefaultBodyLimit::max(upload_limit))
+                        .layer(idempotent.clone())
+                        .layer(RequestBodyLimitLayer::new(upload_limit)),
+                ),
+        )
+        .route(
+            "/api/upload/from-url",
+            post(routes::upload_from_url).layer(idempotent.clone()),
+        )
+        .route("/api/upload/init", post(routes::init_upload))
+        .route("/api/upload/:upload_id/complete", post(routes::complete_upload))
+        .route(
+            "/api/convert",
+            post(routes::convert).layer(idempotent.clone()),
+        )
+        .route("/api/convert/batch", post(routes::convert_batch))
+        .route(
+            "/api/remove-bg",
+            post(routes::remove_bg).layer(idempotent.clone()),
+        )
+        .route(
+            "/api/lut",
+            post(routes::upload_lut)
+                .layer(
+                    ServiceBuilder::new()
+                        .layer(DefaultBodyLimit::max(lut_limit))
+                        .layer(RequestBodyLimitLayer::new(lut_limit)),
+                ),
+        )
+        .route("/api/luts/generate", post(routes::generate_lut))
+        .route(
+            "/api/color-grade",
+            post(routes::color_grade).layer(idempotent),
+        )
+        .route("/api/process", post(routes::process))
+        .route("/api/analyze", post(routes::analyze))
+        .route("/api/jobs/:job_id/retry", post(routes::retry_job))
+        .route_layer(middleware::from_fn_with_state(state, request_limit::limit_requests))
+}
@@ -0,0 +1,390 @@
+// backend/src/openapi.rs
+// OpenAPI description of the HTTP API, served at /api/openapi.json with a
+// Swagger UI at /api/docs
+
+use axum::{response::Html, Json};
+use serde::Serialize;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+use crate::error::ErrorCode;
+use crate::services::quota::QuotaStatus;
+use crate::validation::FieldError;
+use crate::{auth, routes};
+
+/// Body of every error response
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    /// Which catalog message this is, e.g. `daily_quota_exceeded`. Stable like
+    /// `code`, for clients that translate messages themselves.
+    pub reason: String,
+    /// For people, in the language `Accept-Language` asks for (`en` or `es`,
+    /// English otherwise); may change without notice
+    pub message: String,
+    /// The values the message mentions, e.g. `limit` and `used` for a quota
+    #[schema(value_type = Object)]
+    pub details: serde_json::Map<String, serde_json::Value>,
+    /// `QUOTA_EXCEEDED` for a tier limit: the usage that was checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<QuotaStatus>,
+    /// `VALIDATION_ERROR` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+}
+
+/// `multipart/form-data` upload of a single file. Only describes the form;
+/// handlers read the parts themselves.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct FileUpload {
+    /// The first part with a filename is used; its extension must match the content
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+/// Security scheme names used by `security(...)` on protected operations
+const BEARER: &str = "bearer";
+const API_KEY: &str = "api_key";
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            BEARER,
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some(
+                        "Token from /api/auth/login or /api/auth/register. API keys are also \
+                         accepted here as `Authorization: ApiKey <key>`.",
+                    ))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            API_KEY,
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-API-Key",
+                "Key from POST /api/keys",
+            ))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "MediaForge API", description = "Media upload, conversion, background removal and color grading"),
+    paths(
+        routes::health,
+        routes::ready,
+        routes::prometheus_metrics,
+        routes::get_capabilities,
+        routes::register,
+        routes::login,
+        routes::change_password,
+        routes::change_email,
+        routes::delete_account,
+        routes::verify_email,
+        routes::resend_verification,
+        routes::switch_organization,
+        routes::forgot_password,
+        routes::reset_password,
+        routes::upload,
+        routes::upload_from_url,
+        routes::init_upload,
+        routes::get_upload,
+        routes::upload_chunk,
+        routes::complete_upload,
+        routes::cancel_upload,
+        routes::list_assets,
+        routes::find_asset_by_hash,
+        routes::get_asset,
+        routes::delete_asset,
+        routes::restore_asset,
+        routes::get_asset_thumbnail,
+        routes::convert,
+        routes::convert_batch,
+        routes::remove_bg,
+        routes::color_grade,
+        routes::process,
+        routes::analyze,
+        routes::upload_lut,
+        routes::list_luts,
+        routes::generate_lut,
+        routes::delete_lut,
+        routes::get_job_status,
+        routes::get_status,
+        routes::retry_job,
+        routes::extend_result,
+        routes::list_user_jobs,
+        routes::list_job_events,
+        routes::get_quota,
+        routes::get_stats,
+        routes::download_result,
+        routes::download_url,
+        routes::download_file,
+        routes::create_organization,
+        routes::list_organizations,
+        routes::list_organization_members,
+        routes::invite_member,
+        routes::accept_invite,
+        routes::create_api_key,
+        routes::list_api_keys,
+        routes::revoke_api_key,
+        routes::admin_list_users,
+        routes::admin_update_tier,
+        routes::admin_list_jobs,
+        routes::admin_stats,
+        routes::admin_relocate_storage,
+        openapi_json,
+        docs,
+    ),
+    components(schemas(
+        ErrorResponse,
+        ErrorBody,
+        ErrorCode,
+        FieldError,
+        FileUpload,
+        auth::RegisterRequest,
+        auth::LoginRequest,
+        auth::ChangePasswordRequest,
+        auth::ChangeEmailRequest,
+        auth::DeleteAccountRequest,
+        auth::ForgotPasswordRequest,
+        auth::ResetPasswordRequest,
+        auth::SwitchOrganizationRequest,
+        auth::AuthResponse,
+        auth::UserInfo,
+        routes::HealthResponse,
+        routes::CapabilitiesResponse,
+        routes::MediaFormats,
+        routes::ForgotPasswordResponse,
+        routes::UploadResponse,
+        routes::UploadFromUrlRequest,
+        routes::InitUploadRequest,
+        routes::CompleteUploadRequest,
+        routes::UploadSessionResponse,
+        routes::AssetResponse,
+        routes::AssetListResponse,
+        routes::LutReference,
+        routes::ConversionParams,
+        routes::ConvertRequest,
+        routes::BatchConvertRequest,
+        routes::JobResponse,
+        routes::JobSchedule,
+        routes::RemoveBgParams,
+        routes::RemoveBgRequest,
+        routes::ColorGradeParams,
+        routes::ColorGradeRequest,
+        routes::Operation,
+        routes::ProcessRequest,
+        routes::AnalyzeRequest,
+        routes::AnalyzeResponse,
+        routes::LutResponse,
+        routes::UploadedLutResponse,
+        routes::GenerateLutRequest,
+        routes::JobStatusResponse,
+        routes::JobOutputResponse,
+        routes::JobResultResponse,
+        routes::ImageSize,
+        routes::ExtendResultRequest,
+        routes::JobListResponse,
+        routes::JobEventResponse,
+        routes::JobEventListResponse,
+        routes::DownloadUrlResponse,
+        routes::CreateOrganizationRequest,
+        routes::InviteMemberRequest,
+        routes::AcceptInviteRequest,
+        routes::OrganizationResponse,
+        routes::OrganizationMemberResponse,
+        routes::InviteResponse,
+        routes::CreateApiKeyRequest,
+        routes::ApiKeyResponse,
+        routes::CreatedApiKeyResponse,
+        routes::AdminUserResponse,
+        routes::UserListResponse,
+        routes::UpdateTierRequest,
+        routes::UpdateTierResponse,
+        routes::AdminJobResponse,
+        routes::AdminJobListResponse,
+        routes::AdminStatsResponse,
+        crate::services::relocation::RelocationSummary,
+        crate::services::quota::QuotaStatus,
+        crate::services::quota::Usage,
+        crate::services::stats::UsageStats,
+        crate::services::stats::JobTypeStats,
+        crate::services::stats::DailyStats,
+        crate::services::stats::UserStats,
+        crate::services::lut::LutInfo,
+        crate::services::lut::LutMetadata,
+        crate::services::analysis::ImageAnalysis,
+        crate::services::analysis::Histogram,
+        crate::services::analysis::DominantColor,
+        crate::services::readiness::Readiness,
+        crate::services::readiness::CheckResult,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "health", description = "Liveness, readiness and metrics"),
+        (name = "auth", description = "Accounts and tokens"),
+        (name = "assets", description = "Uploaded media"),
+        (name = "processing", description = "Submitting jobs"),
+        (name = "luts", description = "The caller's LUT library"),
+        (name = "jobs", description = "Job status and results"),
+        (name = "organizations", description = "Sharing assets, LUTs and quota with other accounts"),
+        (name = "api_keys", description = "Long-lived credentials for scripts"),
+        (name = "admin", description = "Admin accounts only"),
+        (name = "docs", description = "This description"),
+    )
+)]
+pub struct ApiDoc;
+
+/// This OpenAPI description
+#[utoipa::path(get, path = "/api/openapi.json", tag = "docs", responses(
+    (status = 200, description = "OpenAPI 3 document", content_type = "application/json"),
+))]
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI for this description. The UI's assets load from a CDN.
+#[utoipa::path(get, path = "/api/docs", tag = "docs", responses(
+    (status = 200, description = "HTML page", content_type = "text/html"),
+))]
+pub async fn docs() -> Html<&'static str> {
+    Html(SWAGGER_UI)
+}
+
+const SWAGGER_UI: &str = r##"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>MediaForge API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utoipa::openapi::PathItemType;
+
+    /// `(method, path, public)` of every route `build_router` registers, read from its
+    /// source. Routes added after the auth middleware layer are public, but
+    /// for those of `throttled_routes`, which is merged in before it.
+    fn registered_routes() -> Vec<(String, String, bool)> {
+        let source = include_str!("lib.rs");
+        let auth_layer = source.find("auth::auth_middleware").expect("the router installs the auth middleware");
+        let throttled = source.find("fn throttled_routes").expect("the router merges in the throttled routes");
+
+        let mut routes = Vec::new();
+        let starts: Vec<_> = source.match_indices(".route(").map(|(i, _)| i).collect();
+        for (n, &start) in starts.iter().enumerate() {
+            let rest = source[start + ".route(".len()..].trim_start().strip_prefix('"').unwrap();
+            let path = &rest[..rest.find('"').unwrap()];
+            // Everything up to the next route or layer belongs to this one
+            let end = [".layer(middleware", ".route_layer("]
+                .iter()
+                .filter_map(|layer| source[start..].find(layer).map(|i| start + i))
+                .fold(starts.get(n + 1).copied().unwrap_or(source.len()), usize::min);
+            let handlers = &source[start..end];
+
+            let path = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(name) => format!("{{{}}}", name),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for method in ["get", "post", "delete", "put", "patch"] {
+                let called = handlers.match_indices(&format!("{}(", method)).any(|(i, _)| {
+                    let before = handlers[..i].chars().last();
+                    !before.is_some_and(|c| c.is_alphanumeric() || c == '_')
+                });
+                if called {
+                    routes.push((method.to_string(), path.clone(), start > auth_layer && start < throttled));
+                }
+            }
+        }
+        routes
+    }
+
+    fn operation<'a>(
+        spec: &'a utoipa::openapi::OpenApi,
+        method: &str,
+        path: &str,
+    ) -> Option<&'a utoipa::openapi::path::Operation> {
+        let method = match method {
+            "get" => PathItemType::Get,
+            "post" => PathItemType::Post,
+            "delete" => PathItemType::Delete,
+            "put" => PathItemType::Put,
+            "patch" => PathItemType::Patch,
+            other => panic!("unexpected method {}", other),
+        };
+        spec.paths.paths.get(path)?.operations.get(&method)
+    }
+
+    #[test]
+    fn test_spec_documents_every_registered_route() {
+        let spec = ApiDoc::openapi();
+        let routes = registered_routes();
+        assert!(routes.len() > 30, "found only {:?}", routes);
+
+        for (method, path, public) in &routes {
+            let operation = operation(&spec, method, path)
+                .unwrap_or_else(|| panic!("{} {} is registered but not documented", method, path));
+            let secured = operation.security.as_ref().is_some_and(|s| !s.is_empty());
+            assert_eq!(secured, !public, "{} {} security", method, path);
+            if !public {
+                assert!(operation.responses.responses.contains_key("401"), "{} {} lacks 401", method, path);
+            }
+        }
+
+        // And nothing is documented that isn't served
+        for (path, item) in &spec.paths.paths {
+            for method in item.operations.keys() {
+                let method = serde_json::to_value(method).unwrap();
+                assert!(
+                    routes.iter().any(|(m, p, _)| method == m.as_str() && p == path),
+                    "{} {} is documented but not registered",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_spec_serializes_with_error_codes_and_auth() {
+        let spec: serde_json::Value = serde_json::from_str(&ApiDoc::openapi().to_json().unwrap()).unwrap();
+
+        let codes = &spec["components"]["schemas"]["ErrorCode"]["enum"];
+        for code in ["BAD_REQUEST", "EMAIL_NOT_VERIFIED", "VALIDATION_ERROR", "QUOTA_EXCEEDED", "IO_ERROR", "PROCESSING_ERROR"] {
+            assert!(codes.as_array().unwrap().contains(&code.into()), "{} missing from {}", code, codes);
+        }
+        assert_eq!(spec["components"]["securitySchemes"]["bearer"]["scheme"], "bearer");
+        assert_eq!(spec["components"]["securitySchemes"]["api_key"]["name"], "X-API-Key");
+
+        let upload = &spec["paths"]["/api/upload"]["post"]["requestBody"]["content"];
+        assert!(upload.get("multipart/form-data").is_some(), "{}", upload);
+    }
+}
@@ -0,0 +1,167 @@
+// backend/src/otel.rs
+// OpenTelemetry span export over OTLP, and the W3C trace context that ties the
+// worker's spans for a job to the request that created it
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::config::TracingConfig;
+
+/// How long shutdown waits for the last spans to be sent
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends this process's spans to the collector. Finished spans are queued and
+/// exported in batches by a background task, so a slow or unreachable
+/// collector costs dropped spans, never a request waiting on it.
+pub struct Exporter {
+    provider: TracerProvider,
+}
+
+impl Exporter {
+    /// `None` without an OTLP endpoint. `service_name` is used unless
+    /// `OTEL_SERVICE_NAME` overrides it.
+    pub fn new(config: &TracingConfig, service_name: &str) -> Result<Option<Self>, anyhow::Error> {
+        let Some(endpoint) = &config.otlp_endpoint else {
+            return Ok(None);
+        };
+        let exporter = SpanExporter::builder().with_http().with_endpoint(endpoint).build()?;
+        let service_name = config.service_name.as_deref().unwrap_or(service_name).to_string();
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            // A trace started upstream is kept or dropped whole, as its caller decided
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+            .with_resource(Resource::new([KeyValue::new("service.name", service_name)]))
+            .build();
+        Ok(Some(Self { provider }))
+    }
+
+    /// Turns `tracing` spans into OpenTelemetry ones
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer(env!("CARGO_PKG_NAME")))
+    }
+
+    /// Send what is still queued, giving up after `FLUSH_TIMEOUT`
+    pub async fn shutdown(self) {
+        let provider = self.provider;
+        // Shutting down blocks this thread until the background task has exported
+        let flush = tokio::task::spawn_blocking(move || provider.shutdown());
+        match tokio::time::timeout(FLUSH_TIMEOUT, flush).await {
+            Ok(Ok(Ok(()))) => {}
+            Ok(Ok(Err(e))) => tracing::warn!("Failed to flush spans: {}", e),
+            Ok(Err(e)) => tracing::warn!("Failed to flush spans: {}", e),
+            Err(_) => tracing::warn!("Gave up flushing spans after {:?}", FLUSH_TIMEOUT),
+        }
+    }
+}
+
+/// Continue the trace named by a request's `traceparent` header, if any
+pub fn set_remote_parent(span: &tracing::Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    set_parent(span, context);
+}
+
+/// The current span's trace context in W3C form, for a job to carry to the
+/// worker. `None` when spans are not being exported.
+pub fn current_trace_context() -> Option<serde_json::Value> {
+    let mut carrier = HashMap::<String, String>::new();
+    TraceContextPropagator::new().inject_context(&tracing::Span::current().context(), &mut carrier);
+    (!carrier.is_empty()).then(|| serde_json::json!(carrier))
+}
+
+/// Make `span` a child of the span that recorded `trace_context`
+pub fn set_job_parent(span: &tracing::Span, trace_context: &serde_json::Value) {
+    let carrier: HashMap<String, String> = serde_json::from_value(trace_context.clone()).unwrap_or_default();
+    set_parent(span, TraceContextPropagator::new().extract(&carrier));
+}
+
+fn set_parent(span: &tracing::Span, context: Context) {
+    use opentelemetry::trace::TraceContextExt;
+    if context.span().span_context().is_valid() {
+        span.set_parent(context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn config(endpoint: &str) -> TracingConfig {
+        TracingConfig { otlp_endpoint: Some(endpoint.to_string()), service_name: None, sample_ratio: 1.0 }
+    }
+
+    #[tokio::test]
+    async fn test_no_endpoint_means_no_exporter() {
+        let config = TracingConfig { otlp_endpoint: None, service_name: None, sample_ratio: 1.0 };
+        assert!(Exporter::new(&config, "test").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_trace_context_reaches_the_job_span() {
+        let exporter = Exporter::new(&config("http://127.0.0.1:9/v1/traces"), "test").unwrap().unwrap();
+        let subscriber = tracing_subscriber::registry().with(exporter.layer());
+        tracing::subscriber::with_default(subscriber, check_propagation);
+        // Dropping the last handle would flush on this thread, which the export needs
+        exporter.shutdown().await;
+    }
+
+    fn check_propagation() {
+        let mut headers = HeaderMap::new();
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        headers.insert("traceparent", traceparent.parse().unwrap());
+        let request = tracing::info_span!("request");
+        set_remote_parent(&request, &headers);
+
+        let carried = request.in_scope(current_trace_context).unwrap();
+        let carried_traceparent = carried["traceparent"].as_str().unwrap();
+        assert!(carried_traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"), "{}", carried);
+        // The request's own span, not the caller's, is the job's parent
+        assert!(!carried_traceparent.contains("00f067aa0ba902b7"));
+
+        let job = tracing::info_span!("job");
+        set_job_parent(&job, &carried);
+        let job_context = job.context();
+        assert_eq!(job_context.span().span_context().trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        // Nothing to continue from: the job starts its own trace
+        let orphan = tracing::info_span!("job");
+        set_job_parent(&orphan, &serde_json::json!({ "traceparent": "garbage" }));
+        assert_ne!(orphan.context().span().span_context().trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_collector_does_not_hold_up_spans_or_shutdown() {
+        // Port 9 (discard) refuses connections
+        let exporter = Exporter::new(&config("http://127.0.0.1:9/v1/traces"), "test").unwrap().unwrap();
+        let subscriber = tracing_subscriber::registry().with(exporter.layer());
+        let started = std::time::Instant::now();
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..1000 {
+                tracing::info_span!("request").in_scope(|| tracing::info!("handled"));
+            }
+        });
+        assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+
+        let started = std::time::Instant::now();
+        exporter.shutdown().await;
+        assert!(started.elapsed() <= FLUSH_TIMEOUT + Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,133 @@
+// backend/src/request_id.rs
+// Request IDs: assigned (or taken from `X-Request-Id`) per request, recorded on
+// the request's tracing span and carried into the jobs it creates, along with
+// the span itself
+
+use axum::{
+    body::Body,
+    extract::FromRequestParts,
+    http::{request::Parts, Method, Request, Response},
+};
+use std::convert::Infallible;
+use std::time::Duration;
+use tower_http::trace::{DefaultOnResponse, OnResponse};
+use tower_http::LatencyUnit;
+use tracing::field::Empty;
+
+/// Header a request ID is read from and echoed back in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The current request's ID, as set by `SetRequestIdLayer`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for RequestId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Only missing if the layer isn't installed, as in handler tests
+        Ok(Self(
+            header_id(parts.headers.get(REQUEST_ID_HEADER))
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        ))
+    }
+}
+
+fn header_id(value: Option<&axum::http::HeaderValue>) -> Option<String> {
+    value
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// Span covering one request. Every log line emitted while handling it,
+/// including the access log line, carries these fields. The route, user and
+/// status are filled in as they become known. When spans are exported it
+/// continues the trace of a `traceparent` header.
+pub fn request_span(request: &Request<Body>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        request_id = header_id(request.headers().get(REQUEST_ID_HEADER)).as_deref(),
+        method = %request.method(),
+        path = %request.uri().path(),
+        route = Empty,
+        user_id = Empty,
+        status = Empty,
+        otel.name = Empty,
+        otel.kind = "server",
+        otel.status_code = Empty,
+    );
+    crate::otel::set_remote_parent(&span, request.headers());
+    span
+}
+
+/// Name the request's span after the route that matched
+pub fn record_route(method: &Method, route: &str) {
+    let span = tracing::Span::current();
+    span.record("route", route);
+    span.record("otel.name", format!("{} {}", method, route));
+}
+
+/// Record the status on the request's span, then write the access log line
+pub fn record_response<B>(response: &Response<B>, latency: Duration, span: &tracing::Span) {
+    span.record("status", response.status().as_u16());
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+    DefaultOnResponse::new()
+        .level(tracing::Level::INFO)
+        .latency_unit(LatencyUnit::Millis)
+        .on_response(response, latency, span);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_extractor_reads_header_or_makes_one_up() {
+        let request = Request::builder()
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        let id = RequestId::from_request_parts(&mut parts, &()).await.unwrap();
+        assert_eq!(id, RequestId("abc-123".to_string()));
+
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+        let id = RequestId::from_request_parts(&mut parts, &()).await.unwrap();
+        assert!(uuid::Uuid::parse_str(&id.0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_request_span_joins_the_callers_trace_and_records_the_route() {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let config = crate::config::TracingConfig {
+            otlp_endpoint: Some("http://127.0.0.1:9/v1/traces".to_string()),
+            service_name: None,
+            sample_ratio: 0.0,
+        };
+        let exporter = crate::otel::Exporter::new(&config, "test").unwrap().unwrap();
+        let span_context = tracing::subscriber::with_default(tracing_subscriber::registry().with(exporter.layer()), || {
+            let request = Request::builder()
+                .uri("/api/v1/jobs/123")
+                .header("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+                .body(Body::empty())
+                .unwrap();
+            let span = request_span(&request);
+            span.in_scope(|| record_route(&Method::GET, "/api/v1/jobs/:id"));
+            span.context().span().span_context().clone()
+        });
+        exporter.shutdown().await;
+        assert_eq!(span_context.trace_id().to_string(), "0af7651916cd43dd8448eb211c80319c");
+        // Sampled by the caller, so kept even though no new traces are
+        assert!(span_context.is_sampled());
+    }
+}
@@ -0,0 +1,41 @@
+// backend/src/request_limit.rs
+// Token-bucket throttling of the processing and upload routes, per user or,
+// for requests without one, per client address
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::SocketAddr;
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, Msg};
+use crate::routes::client_ip;
+use crate::AppState;
+
+/// Take a token from the caller's bucket, or answer 429 with `Retry-After`.
+/// Goes inside the auth middleware so the user it sets is seen.
+pub async fn limit_requests(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let key = match request.extensions().get::<AuthUser>() {
+        Some(user) => format!("user:{}", user.id),
+        None => match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(peer)) => format!("ip:{}", client_ip(&state, *peer, request.headers())),
+            // Served without connect info there is nothing to key on
+            None => return next.run(request).await,
+        },
+    };
+
+    match state.request_limiter.take(&key).await {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let retry_after_secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            tracing::debug!("Request limit reached for {}", key);
+            AppError::RateLimited {
+                message: Msg::TooManyRequests.with("retry_after_seconds", retry_after_secs),
+                retry_after_secs,
+            }
+            .into_response()
+        }
+    }
+}
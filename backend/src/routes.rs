@@ -1,55 +1,225 @@
 use axum::{
-    extract::{Multipart, Path, State},
+    body::Body,
+    extract::{ConnectInfo, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use std::net::{IpAddr, SocketAddr};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::{auth, db, error::{AppError, Result}, AppState};
+use crate::{auth, db, error::{AppError, Message, Msg, Result}, AppState};
+use crate::request_id::RequestId;
+use crate::telemetry;
+use crate::validation::{code, FieldError, Validator};
+use crate::services::byte_range;
+use crate::services::conditional;
+use crate::services::download_token;
+use crate::services::signed_urls::SignedUrl;
+use crate::services::url_fetch;
+use crate::services::resumable;
+use crate::services::lut::{Lut, LutInfo};
+use crate::services::formats;
+use crate::services::probe;
+use crate::services::ffmpeg::Ffmpeg;
+use crate::services::heic;
+use crate::services::job_params::{self, JobParams};
+use crate::services::analysis::ImageAnalysis;
+use crate::services::archive;
+use crate::services::relocation::{self, RelocationSummary};
+use crate::services::scan;
+use crate::services::stats::{self, UsageStats, UserStats};
+use crate::services::processing::{self, ImageProcessor};
+use crate::services::queue::{JobStatus, Queue};
+use crate::services::video;
+use crate::services::webhook;
+use crate::services::{Storage, StorageLocation};
+use crate::services::mailer::Email;
+use crate::services::quota::{self, QuotaStatus, QuotaViolation};
+use crate::services::readiness::{Dependencies, Readiness};
+use crate::services::sniff::{self, MediaKind, SniffedType};
 
 // ============================================================================
 // Health Check
 // ============================================================================
 
-pub async fn health() -> Json<serde_json::Value> {
-    Json(json!({
-        "status": "healthy",
-        "version": env!("CARGO_PKG_VERSION"),
-        "service": "MediaForge API"
-    }))
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
+    pub status: &'static str,
+    pub version: &'static str,
+    pub service: &'static str,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "health",
+    responses(
+        (status = 200, description = "The API is up", body = HealthResponse),
+    ),
+)]
+pub async fn health() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "healthy",
+        version: env!("CARGO_PKG_VERSION"),
+        service: "MediaForge API",
+    })
+}
+
+/// Readiness probe: 200 when every dependency answers within its timeout,
+/// 503 otherwise. `/api/health` stays a cheap liveness check.
+#[utoipa::path(
+    get,
+    path = "/api/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every dependency is reachable", body = Readiness),
+        (status = 503, description = "A dependency failed its check", body = Readiness),
+    ),
+)]
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let report = state
+        .readiness
+        .check(Dependencies {
+            db: &state.db,
+            redis: state.queue.redis_connection(),
+            storage: state.storage.as_ref(),
+            temp_dir: std::path::Path::new(&state.config.processing.temp_dir),
+        })
+        .await;
+
+    let status = if report.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+// ============================================================================
+// Capabilities
+// ============================================================================
+
+/// Image inputs, by extension
+const IMAGE_INPUT_FORMATS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "heic", "heif"];
+
+/// Video inputs, by extension
+const VIDEO_INPUT_FORMATS: &[&str] = &["mp4", "mov", "avi", "webm"];
+
+#[derive(Serialize, ToSchema)]
+pub struct CapabilitiesResponse {
+    /// Whether videos can be processed at all; off when ffmpeg is not installed
+    pub video: bool,
+    pub image_formats: MediaFormats,
+    pub video_formats: MediaFormats,
+    /// Values accepted for `video_codec`
+    pub video_codecs: Vec<&'static str>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MediaFormats {
+    /// Upload extensions
+    pub input: Vec<&'static str>,
+    /// Formats `/api/convert` can produce
+    pub output: Vec<&'static str>,
+    /// Job types that accept this kind of media
+    pub operations: Vec<&'static str>,
+}
+
+/// What this deployment can process, so clients can hide what it cannot
+#[utoipa::path(
+    get,
+    path = "/api/capabilities",
+    tag = "processing",
+    responses(
+        (status = 200, description = "Supported formats and operations", body = CapabilitiesResponse),
+    ),
+)]
+pub async fn get_capabilities(State(state): State<AppState>) -> Json<CapabilitiesResponse> {
+    Json(capabilities(&state.ffmpeg, heic::available()))
+}
+
+fn capabilities(ffmpeg: &Ffmpeg, heic: bool) -> CapabilitiesResponse {
+    let image_inputs = IMAGE_INPUT_FORMATS.iter().copied().filter(|f| heic || !matches!(*f, "heic" | "heif"));
+    let video = ffmpeg.available();
+    CapabilitiesResponse {
+        video,
+        image_formats: MediaFormats {
+            input: image_inputs.collect(),
+            output: formats::IMAGE_OUTPUT_FORMATS.to_vec(),
+            operations: vec!["convert", "remove_bg", "color_grade", "analyze", "lut_generate", "pipeline"],
+        },
+        video_formats: MediaFormats {
+            input: if video { VIDEO_INPUT_FORMATS.to_vec() } else { Vec::new() },
+            output: ffmpeg.video_output_formats(),
+            operations: if video { vec!["convert", "remove_bg", "pipeline"] } else { Vec::new() },
+        },
+        video_codecs: ffmpeg.video_codecs(),
+    }
+}
+
+// ============================================================================
+// Metrics
+// ============================================================================
+
+/// Prometheus scrape endpoint. Public, like the health check; queue depth is
+/// sampled here so it is current at every scrape.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses(
+        (status = 200, description = "Prometheus text exposition", body = String, content_type = "text/plain"),
+    ),
+)]
+pub async fn prometheus_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    metrics::gauge!(telemetry::QUEUE_DEPTH).set(state.queue.depth().await as f64);
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
 }
 
 // ============================================================================
 // Authentication Routes
 // ============================================================================
 
+#[utoipa::path(
+    post,
+    path = "/api/auth/register",
+    tag = "auth",
+    request_body = auth::RegisterRequest,
+    responses(
+        (status = 200, description = "Account created", body = auth::AuthResponse),
+        (status = 409, description = "Email already registered", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+        (status = 429, description = "Too many attempts", body = ErrorResponse),
+    ),
+)]
 pub async fn register(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<auth::RegisterRequest>,
 ) -> Result<Json<auth::AuthResponse>> {
-    // Validate email format
-    if !payload.email.contains('@') || payload.email.len() < 5 {
-        return Err(AppError::BadRequest(
-            "Invalid email format".to_string(),
-        ));
-    }
+    let ip = client_ip(&state, addr, &headers);
+    throttle(&state, &format!("register:{}", ip), state.config.rate_limits.register_attempts).await?;
 
-    // Validate password strength
-    if payload.password.len() < 8 {
-        return Err(AppError::BadRequest(
-            "Password must be at least 8 characters".to_string(),
-        ));
-    }
+    Validator::new()
+        .email("email", &payload.email)
+        .min_length("password", &payload.password, MIN_PASSWORD_LENGTH)
+        .finish()?;
 
     // Check if user exists
     if db::User::find_by_email(&state.db, &payload.email)
         .await?
         .is_some()
     {
-        return Err(AppError::Conflict(
-            "Email already registered".to_string(),
-        ));
+        return Err(email_registered());
     }
 
     // Hash password
@@ -59,71 +229,534 @@ pub async fn register(
     // Create user (default to free tier)
     let user = db::User::create(&state.db, &payload.email, &password_hash, "free").await?;
 
-    // Generate JWT
-    let claims = auth::Claims::new(user.id, user.email.clone(), user.subscription_tier.clone());
-    let token = claims
-        .to_token(&state.config.jwt_secret)
-        .map_err(|e| AppError::Internal(format!("Failed to generate token: {}", e)))?;
-
     tracing::info!("User registered: {} ({})", user.email, user.id);
 
-    Ok(Json(auth::AuthResponse {
-        token,
-        user: auth::UserInfo {
-            id: user.id.to_string(),
-            email: user.email,
-            tier: user.subscription_tier,
-        },
-    }))
+    send_verification_email(&state, &user).await?;
+    auth_response(&state, user, None)
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/auth/login",
+    tag = "auth",
+    request_body = auth::LoginRequest,
+    responses(
+        (status = 200, description = "Signed in", body = auth::AuthResponse),
+        (status = 401, description = "Invalid credentials"),
+        (status = 429, description = "Too many attempts", body = ErrorResponse),
+    ),
+)]
 pub async fn login(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<auth::LoginRequest>,
 ) -> Result<Json<auth::AuthResponse>> {
+    // Failed attempts count per address and account, so one client cannot
+    // grind through passwords and a shared address does not lock out everyone
+    let limit_key = format!(
+        "login:{}:{}",
+        client_ip(&state, addr, &headers),
+        payload.email.trim().to_lowercase()
+    );
+    throttle(&state, &limit_key, state.config.rate_limits.login_attempts).await?;
+
     // Find user
     let user = db::User::find_by_email(&state.db, &payload.email)
         .await?
-        .ok_or_else(|| AppError::Unauthorized("Invalid credentials".to_string()))?;
+        .ok_or_else(|| AppError::Unauthorized(Msg::InvalidCredentials.into()))?;
 
     // Verify password
     let valid = auth::verify_password(&payload.password, &user.password_hash)
         .map_err(|e| AppError::Internal(format!("Password verification failed: {}", e)))?;
 
     if !valid {
-        return Err(AppError::Unauthorized("Invalid credentials".to_string()));
+        return Err(AppError::Unauthorized(Msg::InvalidCredentials.into()));
     }
 
-    // Generate JWT
-    let claims = auth::Claims::new(user.id, user.email.clone(), user.subscription_tier.clone());
-    let token = claims
-        .to_token(&state.config.jwt_secret)
-        .map_err(|e| AppError::Internal(format!("Failed to generate token: {}", e)))?;
+    state.auth_limiter.reset(&limit_key).await;
 
     tracing::info!("User logged in: {} ({})", user.email, user.id);
 
+    auth_response(&state, user, None)
+}
+
+/// Change the signed-in user's password. Tokens are stateless and there are
+/// no refresh tokens yet, so there is nothing to revoke here.
+#[utoipa::path(
+    post,
+    path = "/api/auth/change-password",
+    tag = "auth",
+    request_body = auth::ChangePasswordRequest,
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 401, description = "Missing credentials or wrong current password"),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn change_password(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<auth::ChangePasswordRequest>,
+) -> Result<StatusCode> {
+    let user = current_user(&state, &auth_user).await?;
+    verify_current_password(&payload.current_password, &user.password_hash)?;
+    Validator::new()
+        .min_length("new_password", &payload.new_password, MIN_PASSWORD_LENGTH)
+        .finish()?;
+
+    let password_hash = auth::hash_password(&payload.new_password)
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {}", e)))?;
+    db::User::update_password(&state.db, user.id, &password_hash).await?;
+
+    tracing::info!("Password changed for user {}", user.id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the signed-in user's email. Returns a fresh token, since the old
+/// one still carries the previous address.
+#[utoipa::path(
+    post,
+    path = "/api/auth/change-email",
+    tag = "auth",
+    request_body = auth::ChangeEmailRequest,
+    responses(
+        (status = 200, description = "Email changed; use the new token", body = auth::AuthResponse),
+        (status = 401, description = "Missing credentials or wrong password"),
+        (status = 409, description = "Email already registered", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn change_email(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<auth::ChangeEmailRequest>,
+) -> Result<Json<auth::AuthResponse>> {
+    Validator::new().email("new_email", &payload.new_email).finish()?;
+    let user = current_user(&state, &auth_user).await?;
+    verify_current_password(&payload.password, &user.password_hash)?;
+
+    if let Some(existing) = db::User::find_by_email(&state.db, &payload.new_email).await? {
+        if existing.id != user.id {
+            return Err(email_registered());
+        }
+    }
+    // The unique constraint still catches a registration racing this update
+    let user = db::User::update_email(&state.db, user.id, &payload.new_email)
+        .await
+        .map_err(email_conflict)?;
+
+    tracing::info!("Email changed for user {}: {} -> {}", user.id, auth_user.email, user.email);
+
+    // The new address has to be confirmed like a new registration
+    send_verification_email(&state, &user).await?;
+    auth_response(&state, user, None)
+}
+
+/// Tries per stored file before an account deletion leaves it to the cleanup sweep
+const ACCOUNT_FILE_DELETE_ATTEMPTS: u32 = 3;
+
+/// Delete the signed-in user's account and everything in it. Queued jobs are
+/// dropped, and the account's tokens and API keys stop working at once.
+/// Stored files that can't be deleted straight away are retried by the
+/// cleanup sweep, which the 202 response signals.
+#[utoipa::path(
+    delete,
+    path = "/api/auth/account",
+    tag = "auth",
+    request_body = auth::DeleteAccountRequest,
+    responses(
+        (status = 204, description = "Account and files deleted"),
+        (status = 202, description = "Account deleted; some files will be removed by the cleanup sweep"),
+        (status = 401, description = "Missing credentials or wrong password"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn delete_account(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<auth::DeleteAccountRequest>,
+) -> Result<StatusCode> {
+    let user = current_user(&state, &auth_user).await?;
+    verify_current_password(&payload.password, &user.password_hash)?;
+
+    let locations = db::User::delete_account(&state.db, user.id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(Msg::AccountGone.into()))?;
+
+    let mut deleted = Vec::new();
+    for location in &locations {
+        let parsed = match location.parse::<StorageLocation>() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                tracing::warn!("Not deleting {}: {}", location, e);
+                continue;
+            }
+        };
+        for attempt in 1..=ACCOUNT_FILE_DELETE_ATTEMPTS {
+            match state.storage.delete(&parsed).await {
+                Ok(()) => {
+                    deleted.push(location.clone());
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to delete {} (attempt {}): {:?}", location, attempt, e);
+                    if attempt < ACCOUNT_FILE_DELETE_ATTEMPTS {
+                        tokio::time::sleep(std::time::Duration::from_millis(100 * u64::from(attempt))).await;
+                    }
+                }
+            }
+        }
+    }
+    // Whatever isn't cleared here stays for the sweep to retry
+    if let Err(e) = db::PendingDeletion::clear(&state.db, &deleted).await {
+        tracing::warn!("Failed to clear pending deletions for user {}: {:?}", user.id, e);
+    }
+
+    let pending = locations.len() - deleted.len();
+    tracing::info!(
+        "Account {} ({}) deleted with {} stored file(s); {} left for the cleanup sweep",
+        user.id,
+        user.email,
+        locations.len(),
+        pending
+    );
+
+    Ok(if pending > 0 { StatusCode::ACCEPTED } else { StatusCode::NO_CONTENT })
+}
+
+/// Confirm an email address from the link sent at registration. Public: the
+/// token identifies the account.
+#[utoipa::path(
+    get,
+    path = "/api/auth/verify",
+    tag = "auth",
+    params(auth::VerifyEmailQuery),
+    responses(
+        (status = 200, description = "Email verified", body = auth::UserInfo),
+        (status = 400, description = "Unknown, used or expired link", body = ErrorResponse),
+    ),
+)]
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Query(query): Query<auth::VerifyEmailQuery>,
+) -> Result<Json<auth::UserInfo>> {
+    let user = db::User::verify_email(&state.db, &auth::hash_email_token(query.token.trim()))
+        .await?
+        .ok_or_else(|| {
+            AppError::BadRequest(Msg::VerificationLinkInvalid.into())
+        })?;
+
+    tracing::info!("Email verified for user {}", user.id);
+
+    Ok(Json(auth::UserInfo {
+        id: user.id.to_string(),
+        email: user.email,
+        tier: user.subscription_tier,
+        email_verified: user.email_verified,
+        organization_id: None,
+    }))
+}
+
+/// Send a fresh verification link to the signed-in user. Earlier links stop
+/// working.
+#[utoipa::path(
+    post,
+    path = "/api/auth/resend-verification",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Link sent"),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 409, description = "Email already verified", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn resend_verification(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+) -> Result<StatusCode> {
+    let user = current_user(&state, &auth_user).await?;
+    if user.email_verified {
+        return Err(AppError::Conflict(Msg::EmailAlreadyVerified.into()));
+    }
+    throttle(
+        &state,
+        &format!("resend-verification:{}", user.id),
+        state.config.rate_limits.resend_verification_attempts,
+    )
+    .await?;
+
+    send_verification_email(&state, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Email a password reset link if an account uses the address. The response
+/// is the same either way, so it can't be used to find out who has an account.
+#[utoipa::path(
+    post,
+    path = "/api/auth/forgot-password",
+    tag = "auth",
+    request_body = auth::ForgotPasswordRequest,
+    responses(
+        (status = 200, description = "A link was sent if the address has an account", body = ForgotPasswordResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<auth::ForgotPasswordRequest>,
+) -> Result<Json<ForgotPasswordResponse>> {
+    // Per address so one client can't spray requests, and per email so a
+    // victim's inbox can't be flooded from many addresses
+    let limit = state.config.rate_limits.forgot_password_attempts;
+    let email = payload.email.trim().to_lowercase();
+    throttle(&state, &format!("forgot-password:ip:{}", client_ip(&state, addr, &headers)), limit).await?;
+    throttle(&state, &format!("forgot-password:email:{}", email), limit).await?;
+
+    if let Some(user) = db::User::find_by_email(&state.db, payload.email.trim()).await? {
+        let (token, token_hash) = auth::generate_email_token();
+        let ttl_minutes = state.config.mail.password_reset_ttl_minutes;
+        let expires_at = chrono::Utc::now() + chrono::Duration::minutes(ttl_minutes as i64);
+        db::User::store_password_reset(&state.db, user.id, &token_hash, expires_at).await?;
+
+        let email = Email {
+            to: user.email.clone(),
+            subject: "Reset your MediaForge password".to_string(),
+            body: format!(
+                "Open this link to choose a new password:\n\n\
+                 {}?token={}\n\n\
+                 The link works once, for {} minutes. If you didn't ask to reset your password, \
+                 ignore this email; your password hasn't changed.\n",
+                state.config.mail.password_reset_url, token, ttl_minutes
+            ),
+        };
+        send_in_background(&state, email, user.id);
+        tracing::info!("Password reset requested for user {}", user.id);
+    }
+
+    Ok(Json(ForgotPasswordResponse {
+        message: "If an account uses that address, a reset link is on its way.",
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ForgotPasswordResponse {
+    pub message: &'static str,
+}
+
+/// Set a new password with the token from a reset email. Tokens are
+/// stateless and there are no refresh tokens yet, so there are no sessions to
+/// revoke; API keys are separate credentials and keep working.
+#[utoipa::path(
+    post,
+    path = "/api/auth/reset-password",
+    tag = "auth",
+    request_body = auth::ResetPasswordRequest,
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 400, description = "Unknown, used or expired link", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+    ),
+)]
+pub async fn reset_password(
+    State(state): State<AppState>,
+    Json(payload): Json<auth::ResetPasswordRequest>,
+) -> Result<StatusCode> {
+    // Checked first, so a rejected password doesn't use up the link
+    Validator::new()
+        .min_length("new_password", &payload.new_password, MIN_PASSWORD_LENGTH)
+        .finish()?;
+
+    let password_hash = auth::hash_password(&payload.new_password)
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {}", e)))?;
+    let user = db::User::reset_password(&state.db, &auth::hash_email_token(payload.token.trim()), &password_hash)
+        .await?
+        .ok_or_else(|| AppError::BadRequest(Msg::ResetLinkInvalid.into()))?;
+
+    tracing::info!("Password reset for user {}", user.id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Address to key rate limits on: the peer, or the address the trusted
+/// proxy recorded as the last `X-Forwarded-For` hop
+pub(crate) fn client_ip(state: &AppState, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    if state.config.rate_limits.trust_forwarded_for {
+        if let Some(ip) = forwarded_for(headers) {
+            return ip;
+        }
+    }
+    peer.ip()
+}
+
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get("x-forwarded-for")?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+async fn throttle(state: &AppState, key: &str, limit: u32) -> Result<()> {
+    state.auth_limiter.hit(key, limit).await.map_err(|retry_after| {
+        let retry_after_secs = retry_after.as_secs().max(1);
+        AppError::RateLimited {
+            message: Msg::TooManyAttempts.with("retry_after_seconds", retry_after_secs),
+            retry_after_secs,
+        }
+    })
+}
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Account changes re-check the password even though the caller holds a token
+fn verify_current_password(password: &str, password_hash: &str) -> Result<()> {
+    let valid = auth::verify_password(password, password_hash)
+        .map_err(|e| AppError::Internal(format!("Password verification failed: {}", e)))?;
+    if !valid {
+        return Err(AppError::Unauthorized(Msg::CurrentPasswordIncorrect.into()));
+    }
+    Ok(())
+}
+
+async fn current_user(state: &AppState, auth_user: &auth::AuthUser) -> Result<db::User> {
+    db::User::find_by_id(&state.db, auth_user.id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(Msg::AccountGone.into()))
+}
+
+fn email_registered() -> AppError {
+    AppError::Conflict(Msg::EmailAlreadyRegistered.into())
+}
+
+fn email_conflict(e: sqlx::Error) -> AppError {
+    match e.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => email_registered(),
+        _ => e.into(),
+    }
+}
+
+/// Issue a token for the user, acting for `organization_id` if set, and
+/// describe them to the client
+fn auth_response(
+    state: &AppState,
+    user: db::User,
+    organization_id: Option<Uuid>,
+) -> Result<Json<auth::AuthResponse>> {
+    let mut claims = auth::Claims::new(user.id, user.email.clone(), user.subscription_tier.clone());
+    claims.org = organization_id.map(|id| id.to_string());
+    let token = claims
+        .to_token(&state.keys)
+        .map_err(|e| AppError::Internal(format!("Failed to generate token: {}", e)))?;
+
     Ok(Json(auth::AuthResponse {
         token,
         user: auth::UserInfo {
             id: user.id.to_string(),
             email: user.email,
             tier: user.subscription_tier,
+            email_verified: user.email_verified,
+            organization_id: claims.org,
         },
     }))
 }
 
+/// Email `user` a link that marks their address verified. The token is
+/// stored before this returns; the email is sent in the background.
+async fn send_verification_email(state: &AppState, user: &db::User) -> Result<()> {
+    let (token, token_hash) = auth::generate_email_token();
+    let ttl_hours = state.config.mail.verification_ttl_hours;
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(ttl_hours as i64);
+    db::User::store_verification_token(&state.db, user.id, &user.email, &token_hash, expires_at).await?;
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Confirm your MediaForge email address".to_string(),
+        body: format!(
+            "Open this link to confirm your email address and start processing media:\n\n\
+             {}/api/auth/verify?token={}\n\n\
+             The link works for {} hours. If you didn't sign up for MediaForge, ignore this email.\n",
+            state.config.public_url, token, ttl_hours
+        ),
+    };
+    send_in_background(state, email, user.id);
+    Ok(())
+}
+
+/// Send `email` without making the request wait: a slow or failing mail
+/// server shouldn't hold it up. Users can ask for another email if one never
+/// arrives.
+fn send_in_background(state: &AppState, email: Email, user_id: Uuid) {
+    let mailer = state.mailer.clone();
+    tokio::spawn(async move {
+        let subject = email.subject.clone();
+        if let Err(e) = mailer.send(email).await {
+            tracing::error!("Failed to send '{}' email to user {}: {}", subject, user_id, e);
+        }
+    });
+}
+
 // ============================================================================
 // Upload Route
 // ============================================================================
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct UploadResponse {
     pub asset_id: String,
     pub filename: String,
     pub size: u64,
     pub location: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration_seconds: Option<i32>,
+    /// The caller had already uploaded these bytes; `asset_id` is that
+    /// earlier asset and nothing new was stored
+    pub deduplicated: bool,
+}
+
+impl UploadResponse {
+    fn deduplicated(asset: db::MediaAsset) -> Self {
+        Self {
+            asset_id: asset.id.to_string(),
+            filename: asset.original_filename,
+            size: asset.size_bytes.max(0) as u64,
+            location: asset.result_location.unwrap_or_default(),
+            width: asset.width,
+            height: asset.height,
+            duration_seconds: asset.duration_seconds,
+            deduplicated: true,
+        }
+    }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/upload",
+    tag = "assets",
+    request_body(content = FileUpload, content_type = "multipart/form-data"),
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries sent with the same key get the first response back, marked `Idempotent-Replayed: true`, rather than running again")),
+    responses(
+        (status = 200, description = "Stored, or matched an earlier upload", body = UploadResponse),
+        (status = 400, description = "No file, or an unsupported or mislabeled one", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 409, description = "A request with this Idempotency-Key is still in progress", body = ErrorResponse),
+        (status = 413, description = "File too large", body = ErrorResponse),
+        (status = 422, description = "Media rejected, e.g. a video over the length limit, or flagged as malware (`MALWARE_DETECTED`), or an Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 429, description = "Storage quota or request limit reached", body = ErrorResponse),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
 pub async fn upload(
     auth_user: auth::AuthUser,
     State(state): State<AppState>,
@@ -131,530 +764,5363 @@ pub async fn upload(
 ) -> Result<Json<UploadResponse>> {
     while let Some(field) = multipart
         .next_field()
-        .await
-        .map_err(|e| AppError::BadRequest(format!("Invalid multipart data: {}", e)))?
+        .await?
     {
         if let Some(file_name) = field.file_name() {
-            let file_name_owned = file_name.to_string();
-
-            // Read file data
-            let data = field
-                .bytes()
-                .await
-                .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
+            let file_name = file_name.to_string();
+            return Ok(Json(store_upload(&state, &auth_user, file_name, field).await?));
+        }
+    }
 
-            // Validate file
-            validate_file(&file_name_owned, &data, &state.config)?;
+    Err(AppError::BadRequest(Msg::NoFile.into()))
+}
 
-            // Save to storage
-            let location = state
-                .storage
-                .save_bytes(&data, &file_name_owned)
-                .map_err(|e| AppError::Internal(format!("Failed to save file: {:?}", e)))?;
-
-            // Create media asset record
-            let asset = db::MediaAsset::create(
-                &state.db,
-                auth_user.id,
-                &file_name_owned,
-                &get_file_extension(&file_name_owned),
-                data.len() as i64,
-            )
-            .await?;
-
-            // Update asset with storage location
-            db::MediaAsset::update_status(&state.db, asset.id, "uploaded", Some(&location))
-                .await?;
-
-            tracing::info!(
-                "File uploaded: {} by user {} (asset: {})",
-                file_name_owned,
-                auth_user.email,
-                asset.id
-            );
+#[derive(Deserialize, ToSchema)]
+pub struct UploadFromUrlRequest {
+    /// https URL of the image or video
+    pub url: String,
+    /// Name for the asset, which must have a supported extension. Defaults
+    /// to the last segment of the URL's path (after redirects).
+    #[serde(default)]
+    pub filename: Option<String>,
+}
 
-            return Ok(Json(UploadResponse {
-                asset_id: asset.id.to_string(),
-                filename: file_name_owned,
-                size: data.len() as u64,
-                location,
-            }));
-        }
+/// Fetch a file from a URL and store it as if it had been uploaded. Only
+/// public https hosts are fetched, each redirect is checked again, and the
+/// size limit is enforced on the bytes received whatever the server declares.
+#[utoipa::path(
+    post,
+    path = "/api/upload/from-url",
+    tag = "assets",
+    request_body = UploadFromUrlRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries sent with the same key get the first response back, marked `Idempotent-Replayed: true`, rather than running again")),
+    responses(
+        (status = 200, description = "Stored, or matched an earlier upload", body = UploadResponse),
+        (status = 400, description = "Refused or unreachable URL, or an unsupported or mislabeled file", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 409, description = "A request with this Idempotency-Key is still in progress", body = ErrorResponse),
+        (status = 413, description = "File too large", body = ErrorResponse),
+        (status = 422, description = "Media rejected, e.g. a video over the length limit, or flagged as malware (`MALWARE_DETECTED`), or an Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 429, description = "Storage quota or request limit reached", body = ErrorResponse),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn upload_from_url(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<UploadFromUrlRequest>,
+) -> Result<Json<UploadResponse>> {
+    if let Some(filename) = &payload.filename {
+        media_kind_from_filename(filename)?;
     }
 
-    Err(AppError::BadRequest("No file provided".to_string()))
+    // The real limit depends on what the content turns out to be
+    let max_bytes = max_upload_bytes(MediaKind::Image, &state.config)
+        .max(max_upload_bytes(MediaKind::Video, &state.config));
+    let timeout = std::time::Duration::from_secs(state.config.processing.url_fetch_timeout_seconds);
+    let remote = url_fetch::open(&payload.url, max_bytes, timeout).await?;
+
+    let file_name = payload
+        .filename
+        .or_else(|| remote.file_name())
+        .ok_or_else(|| AppError::BadRequest(Msg::UrlWithoutFilename.into()))?;
+    tracing::info!("Fetching {} for user {}", remote.url, auth_user.email);
+
+    let uploaded = store_upload(&state, &auth_user, file_name, remote.into_stream()).await?;
+    Ok(Json(uploaded))
 }
 
-// ============================================================================
-// Processing Routes
-// ============================================================================
+/// Check, stream and store one file, creating its asset. Shared by multipart
+/// uploads and URL fetches, so both go through the same validation.
+async fn store_upload<S, E>(
+    state: &AppState,
+    auth_user: &auth::AuthUser,
+    file_name: String,
+    body: S,
+) -> Result<UploadResponse>
+where
+    S: futures_util::Stream<Item = std::result::Result<bytes::Bytes, E>> + Unpin,
+    E: Into<AppError>,
+{
+    // Reject unsupported extensions before reading anything
+    media_kind_from_filename(&file_name)?;
+    let extension = get_file_extension(&file_name);
 
-#[derive(Deserialize)]
-pub struct ConvertRequest {
-    pub asset_id: String,
-    pub output_format: String,
-    #[serde(default)]
-    pub lut_location: Option<String>,
-    #[serde(default)]
-    pub width: Option<u32>,
-    #[serde(default)]
-    pub height: Option<u32>,
+    // A full storage quota is rejected up front; whether this file
+    // fits is checked once its size is known
+    let quota = quota::quota_status(&state.db, &state.config.quotas, auth_user.owner(), &auth_user.tier).await?;
+    quota
+        .check_storage(1)
+        .map_err(|violation| quota_exceeded(violation, quota.clone()))?;
+
+    // Stream the body to a temp file. The first chunk is sniffed to confirm the
+    // content matches the extension and to pick the size limit, which is then
+    // enforced as the body arrives.
+    let temp_path = std::path::Path::new(&state.config.processing.temp_dir)
+        .join(format!("upload_{}", Uuid::new_v4()));
+    let mut kind = MediaKind::Image;
+    let limit_for_header = |header: &[u8]| -> Result<u64> {
+        let sniffed = validate_content(header, &extension)?;
+        kind = sniffed.kind();
+        Ok(max_upload_bytes(kind, &state.config))
+    };
+    let streamed = match stream_to_file(body, &temp_path, limit_for_header).await {
+        Ok(streamed) => streamed,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(e);
+        }
+    };
+
+    finish_upload(state, auth_user, file_name, &temp_path, kind, streamed, quota).await
 }
 
-#[derive(Serialize)]
-pub struct JobResponse {
-    pub job_id: String,
-    pub status: String,
+/// Run the malware scanner over a staged upload. Content it flags is
+/// refused; when it cannot give an answer the upload is refused too, unless
+/// `SCAN_FAIL_OPEN` is set.
+async fn scan_upload(
+    state: &AppState,
+    auth_user: &auth::AuthUser,
+    file_name: &str,
+    temp_path: &std::path::Path,
+) -> Result<()> {
+    match state.scanner.scan(scan::ScanInput::File(temp_path)).await {
+        Ok(scan::ScanVerdict::Clean) => Ok(()),
+        Ok(scan::ScanVerdict::Infected { signature }) => {
+            tracing::warn!(
+                "Refused upload of {} by user {}: malware detected ({})",
+                file_name,
+                auth_user.email,
+                signature
+            );
+            Err(AppError::MalwareDetected(Msg::MalwareDetected.with("signature", signature)))
+        }
+        Err(e) if state.config.scan.fail_open => {
+            tracing::warn!("Upload of {} by user {} was not scanned: {}", file_name, auth_user.email, e);
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!("Upload of {} by user {} could not be scanned: {}", file_name, auth_user.email, e);
+            Err(AppError::ServiceUnavailable(Msg::ScanUnavailable.into()))
+        }
+    }
 }
 
-pub async fn convert(
-    auth_user: auth::AuthUser,
-    State(state): State<AppState>,
-    Json(payload): Json<ConvertRequest>,
-) -> Result<Json<JobResponse>> {
-    let asset_id = Uuid::parse_str(&payload.asset_id)
-        .map_err(|_| AppError::BadRequest("Invalid asset ID".to_string()))?;
+/// Turn a validated file staged at `temp_path` into an asset, or into a
+/// reference to the caller's earlier upload of the same bytes. The file is
+/// moved into storage or removed, whatever the outcome.
+async fn finish_upload(
+    state: &AppState,
+    auth_user: &auth::AuthUser,
+    file_name: String,
+    temp_path: &std::path::Path,
+    kind: MediaKind,
+    streamed: StreamedFile,
+    quota: QuotaStatus,
+) -> Result<UploadResponse> {
+    let size = streamed.size;
 
-    // Verify asset ownership
-    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.id).await?;
+    if let Err(e) = scan_upload(state, auth_user, &file_name, temp_path).await {
+        let _ = tokio::fs::remove_file(temp_path).await;
+        return Err(e);
+    }
 
-    // Check quota
-    check_quota(&state, &auth_user, "image").await?;
+    // Bytes the caller already uploaded reuse that asset instead of being stored again
+    let duplicate = db::MediaAsset::find_by_hash(&state.db, auth_user.owner(), &streamed.content_hash).await;
+    if !matches!(duplicate, Ok(None)) {
+        let _ = tokio::fs::remove_file(temp_path).await;
+    }
+    if let Some(existing) = duplicate? {
+        let asset = db::MediaAsset::renew(&state.db, existing.id).await?.unwrap_or(existing);
+        tracing::info!(
+            "Upload of {} by user {} matched asset {}; not stored again",
+            file_name,
+            auth_user.email,
+            asset.id
+        );
+        return Ok(UploadResponse::deduplicated(asset));
+    }
 
-    // Create job
-    let job = db::Job::create(
+    if let Err(violation) = quota.check_storage(size as i64) {
+        let _ = tokio::fs::remove_file(temp_path).await;
+        return Err(quota_exceeded(violation, quota));
+    }
+
+    // Probe dimensions / duration while the file is still local
+    let info = match probe_upload(temp_path, kind, &state.config, &state.ffmpeg).await {
+        Ok(info) => info,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(temp_path).await;
+            return Err(e);
+        }
+    };
+
+    // Move into storage
+    let location = match state.storage.save_file(temp_path, auth_user.id, &file_name).await {
+        Ok(location) => location,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(temp_path).await;
+            return Err(AppError::Internal(format!("Failed to save file: {:?}", e)));
+        }
+    };
+
+    // Create media asset record
+    let asset = db::MediaAsset::create(
         &state.db,
-        auth_user.id,
-        vec![asset_id],
-        "convert",
-        json!({
-            "output_format": payload.output_format,
-            "lut_location": payload.lut_location,
-            "width": payload.width,
-            "height": payload.height,
-        }),
-        if auth_user.tier == "pro" { 10 } else { 0 },
+        auth_user.owner(),
+        &file_name,
+        &get_file_extension(&file_name),
+        size as i64,
+        Some(&streamed.content_hash),
     )
     .await?;
 
-    // Enqueue job
-    state
-        .queue
-        .enqueue(crate::services::JobMessage {
-            job_id: job.id.to_string(),
-            user_id: auth_user.id.to_string(),
-            job_type: "convert".to_string(),
-            media_location: asset.result_location.unwrap_or_default(),
-        })
-        .await
-        .map_err(|_| AppError::ServiceUnavailable("Queue is full".to_string()))?;
+    // Update asset with storage location
+    let location = location.to_string();
+    db::MediaAsset::update_status(&state.db, asset.id, "uploaded", Some(&location))
+        .await?;
+
+    let width = info.width.map(|w| w as i32);
+    let height = info.height.map(|h| h as i32);
+    let duration_seconds = info.duration_seconds.map(|d| d.ceil() as i32);
+    db::MediaAsset::update_metadata(&state.db, asset.id, width, height, duration_seconds)
+        .await?;
 
     tracing::info!(
-        "Conversion job {} queued for user {}",
-        job.id,
-        auth_user.email
+        "File uploaded: {} by user {} (asset: {})",
+        file_name,
+        auth_user.email,
+        asset.id
     );
+    metrics::histogram!(telemetry::UPLOAD_SIZE, "kind" => kind.as_str()).record(size as f64);
 
-    Ok(Json(JobResponse {
-        job_id: job.id.to_string(),
-        status: "queued".to_string(),
-    }))
-}
+    // Thumbnails are generated off the request path
+    if kind == MediaKind::Image {
+        spawn_thumbnail(state.clone(), auth_user.id, asset.id, location.clone());
+    }
 
-#[derive(Deserialize)]
-pub struct RemoveBgRequest {
-    pub asset_id: String,
-    #[serde(default)]
-    pub replace_color: Option<[u8; 3]>,
+    Ok(UploadResponse {
+        asset_id: asset.id.to_string(),
+        filename: file_name,
+        size,
+        location,
+        width,
+        height,
+        duration_seconds,
+        deduplicated: false,
+    })
 }
 
-pub async fn remove_bg(
-    auth_user: auth::AuthUser,
-    State(state): State<AppState>,
-    Json(payload): Json<RemoveBgRequest>,
-) -> Result<Json<JobResponse>> {
-    let asset_id = Uuid::parse_str(&payload.asset_id)
-        .map_err(|_| AppError::BadRequest("Invalid asset ID".to_string()))?;
+// ============================================================================
+// Resumable Upload Routes
+// ============================================================================
 
-    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.id).await?;
+/// Header giving the byte a chunk starts at
+const UPLOAD_OFFSET: &str = "upload-offset";
+/// Unfinished resumable uploads a user may have at once
+const MAX_OPEN_UPLOADS: i64 = 5;
 
-    // Check quota for video processing
-    check_quota(&state, &auth_user, "video").await?;
+#[derive(Deserialize, ToSchema)]
+pub struct InitUploadRequest {
+    pub filename: String,
+    /// Total size of the file in bytes
+    pub size: u64,
+}
 
-    let job = db::Job::create(
-        &state.db,
-        auth_user.id,
-        vec![asset_id],
-        "remove_bg",
-        json!({
-            "replace_color": payload.replace_color,
-        }),
-        if auth_user.tier == "pro" { 10 } else { 0 },
-    )
-    .await?;
+#[derive(Deserialize, ToSchema)]
+pub struct CompleteUploadRequest {
+    /// Hex SHA-256 of the whole file. If given, the assembled file must match.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
 
-    state
-        .queue
-        .enqueue(crate::services::JobMessage {
-            job_id: job.id.to_string(),
-            user_id: auth_user.id.to_string(),
-            job_type: "remove_bg".to_string(),
-            media_location: asset.result_location.unwrap_or_default(),
-        })
-        .await
-        .map_err(|_| AppError::ServiceUnavailable("Queue is full".to_string()))?;
+#[derive(Serialize, ToSchema)]
+pub struct UploadSessionResponse {
+    pub upload_id: String,
+    pub filename: String,
+    pub size: u64,
+    /// Bytes stored so far: the `Upload-Offset` of the next chunk
+    pub received_bytes: u64,
+    /// When the upload is discarded unless more is sent
+    pub expires_at: String,
+}
+
+impl UploadSessionResponse {
+    fn new(upload: db::Upload, config: &crate::config::Config) -> Self {
+        let ttl = chrono::Duration::hours(config.processing.upload_session_ttl_hours as i64);
+        Self {
+            upload_id: upload.id.to_string(),
+            filename: upload.filename,
+            size: upload.size_bytes.max(0) as u64,
+            received_bytes: upload.received_bytes.max(0) as u64,
+            expires_at: (upload.updated_at + ttl).to_rfc3339(),
+        }
+    }
+}
+
+/// Start a resumable upload. The file is then sent in chunks with `PATCH`
+/// and turned into an asset with `complete`.
+#[utoipa::path(
+    post,
+    path = "/api/upload/init",
+    tag = "assets",
+    request_body = InitUploadRequest,
+    responses(
+        (status = 200, description = "Upload started", body = UploadSessionResponse),
+        (status = 400, description = "Unsupported file type", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 409, description = "Too many unfinished uploads", body = ErrorResponse),
+        (status = 422, description = "Size is zero or over the limit", body = ErrorResponse),
+        (status = 429, description = "Storage quota or request limit reached", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn init_upload(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<InitUploadRequest>,
+) -> Result<Json<UploadSessionResponse>> {
+    let kind = media_kind_from_filename(&payload.filename)?;
+    Validator::new()
+        .range("size", Some(payload.size), 1..=max_upload_bytes(kind, &state.config))
+        .finish()?;
+
+    let quota = quota::quota_status(&state.db, &state.config.quotas, auth_user.owner(), &auth_user.tier).await?;
+    quota
+        .check_storage(payload.size as i64)
+        .map_err(|violation| quota_exceeded(violation, quota.clone()))?;
+    if db::Upload::count_for_user(&state.db, auth_user.id).await? >= MAX_OPEN_UPLOADS {
+        return Err(AppError::Conflict(Msg::TooManyOpenUploads.with("max", MAX_OPEN_UPLOADS)));
+    }
 
+    let upload = db::Upload::create(&state.db, auth_user.id, &payload.filename, payload.size as i64).await?;
     tracing::info!(
-        "Background removal job {} queued for user {}",
-        job.id,
+        "Resumable upload {} of {} ({} bytes) started by user {}",
+        upload.id,
+        upload.filename,
+        payload.size,
         auth_user.email
     );
 
-    Ok(Json(JobResponse {
-        job_id: job.id.to_string(),
-        status: "queued".to_string(),
-    }))
+    Ok(Json(UploadSessionResponse::new(upload, &state.config)))
 }
 
-#[derive(Deserialize)]
-pub struct ColorGradeRequest {
-    pub asset_id: String,
-    #[serde(default)]
-    pub preset: Option<String>,
-    #[serde(default)]
-    pub lut_location: Option<String>,
-    #[serde(default)]
-    pub hue: Option<i32>,
-    #[serde(default)]
-    pub saturation: Option<i32>,
-    #[serde(default)]
-    pub brightness: Option<i32>,
-    #[serde(default)]
-    pub contrast: Option<i32>,
+/// How far a resumable upload has got, to resume from after a failure
+#[utoipa::path(
+    get,
+    path = "/api/upload/{upload_id}",
+    tag = "assets",
+    params(("upload_id" = Uuid, Path, description = "Upload ID")),
+    responses(
+        (status = 200, description = "The upload so far", body = UploadSessionResponse),
+        (status = 400, description = "Malformed ID", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "No such upload, or it was completed or discarded", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn get_upload(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(upload_id): Path<String>,
+) -> Result<Json<UploadSessionResponse>> {
+    let upload = find_upload(&state, parse_upload_id(&upload_id)?, auth_user.id).await?;
+    Ok(Json(UploadSessionResponse::new(upload, &state.config)))
 }
 
-pub async fn color_grade(
+/// Append a chunk to a resumable upload. `Upload-Offset` must equal the
+/// bytes received so far; a chunk that fails midway is dropped whole and can
+/// be sent again from the same offset.
+#[utoipa::path(
+    patch,
+    path = "/api/upload/{upload_id}",
+    tag = "assets",
+    params(
+        ("upload_id" = Uuid, Path, description = "Upload ID"),
+        ("Upload-Offset" = u64, Header, description = "Byte of the file the chunk starts at"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Chunk stored", body = UploadSessionResponse),
+        (status = 400, description = "Malformed ID or missing Upload-Offset", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "No such upload", body = ErrorResponse),
+        (status = 409, description = "Wrong offset, or another chunk is still being received", body = ErrorResponse),
+        (status = 413, description = "Chunk goes past the declared size", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn upload_chunk(
     auth_user: auth::AuthUser,
     State(state): State<AppState>,
-    Json(payload): Json<ColorGradeRequest>,
-) -> Result<Json<JobResponse>> {
-    let asset_id = Uuid::parse_str(&payload.asset_id)
-        .map_err(|_| AppError::BadRequest("Invalid asset ID".to_string()))?;
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<UploadSessionResponse>> {
+    let upload_id = parse_upload_id(&upload_id)?;
+    let offset = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .ok_or_else(|| AppError::BadRequest(Msg::UploadOffsetRequired.into()))?;
 
-    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.id).await?;
-
-    let job = db::Job::create(
-        &state.db,
-        auth_user.id,
-        vec![asset_id],
-        "color_grade",
-        json!({
-            "preset": payload.preset,
-            "lut_location": payload.lut_location,
-            "hue": payload.hue,
-            "saturation": payload.saturation,
-            "brightness": payload.brightness,
-            "contrast": payload.contrast,
-        }),
-        if auth_user.tier == "pro" { 10 } else { 0 },
-    )
-    .await?;
+    // Read the upload only once it is ours, so the offset can't move under us
+    let lock = state.part_files.lock(upload_id).ok_or_else(upload_busy)?;
+    let upload = find_upload(&state, upload_id, auth_user.id).await?;
+    if offset != upload.received_bytes as u64 {
+        return Err(AppError::Conflict(Msg::UploadOffsetMismatch.with("expected", upload.received_bytes)));
+    }
 
-    state
-        .queue
-        .enqueue(crate::services::JobMessage {
-            job_id: job.id.to_string(),
-            user_id: auth_user.id.to_string(),
-            job_type: "color_grade".to_string(),
-            media_location: asset.result_location.unwrap_or_default(),
-        })
+    let received = state
+        .part_files
+        .append(&lock, offset, upload.size_bytes as u64, body.into_data_stream())
         .await
-        .map_err(|_| AppError::ServiceUnavailable("Queue is full".to_string()))?;
-
-    tracing::info!(
-        "Color grading job {} queued for user {}",
-        job.id,
-        auth_user.email
-    );
+        .map_err(|e| match e {
+            resumable::AppendError::TooLong(size) => AppError::PayloadTooLarge(Msg::ChunkTooLong.with("size", size)),
+            resumable::AppendError::Missing => AppError::Conflict(Msg::UploadPartsMissing.into()),
+            resumable::AppendError::Body(reason) => AppError::BadRequest(Msg::UnreadableChunk.with("reason", reason)),
+            resumable::AppendError::Io(e) => e.into(),
+        })?;
+    let Some(upload) = db::Upload::set_received(&state.db, upload_id, received as i64).await? else {
+        // Discarded while the chunk arrived
+        state.part_files.remove(upload_id).await;
+        return Err(upload_not_found());
+    };
 
-    Ok(Json(JobResponse {
-        job_id: job.id.to_string(),
-        status: "queued".to_string(),
-    }))
+    Ok(Json(UploadSessionResponse::new(upload, &state.config)))
 }
 
-// LUT upload endpoint: Accepts a single .cube file (<= configured size) and
-// returns a temporary location or registers it for user. Minimal validation here.
-pub async fn upload_lut(
+/// Finish a resumable upload once every byte has arrived: the file is
+/// checked like a regular upload and becomes an asset. The upload is used up
+/// either way; a file that is refused has to be sent again.
+#[utoipa::path(
+    post,
+    path = "/api/upload/{upload_id}/complete",
+    tag = "assets",
+    params(("upload_id" = Uuid, Path, description = "Upload ID")),
+    request_body = CompleteUploadRequest,
+    responses(
+        (status = 200, description = "Stored, or matched an earlier upload", body = UploadResponse),
+        (status = 400, description = "Checksum mismatch, or an unsupported or mislabeled file", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "No such upload", body = ErrorResponse),
+        (status = 409, description = "Bytes are still missing, or a chunk is being received", body = ErrorResponse),
+        (status = 413, description = "File too large", body = ErrorResponse),
+        (status = 422, description = "Media rejected, e.g. a video over the length limit, or flagged as malware (`MALWARE_DETECTED`)", body = ErrorResponse),
+        (status = 429, description = "Storage quota or request limit reached", body = ErrorResponse),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn complete_upload(
     auth_user: auth::AuthUser,
     State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Result<Json<serde_json::Value>> {
-    while let Some(field) = multipart
-        .next_field()
-        .await
-        .map_err(|e| AppError::BadRequest(format!("Invalid multipart data: {}", e)))?
-    {
-        if let Some(file_name_ref) = field.file_name() {
-            let file_name = file_name_ref.to_string();
-            let lower = file_name.to_lowercase();
-            if !lower.ends_with(".cube") {
-                return Err(AppError::BadRequest("Only .cube LUT files are supported".to_string()));
+    Path(upload_id): Path<String>,
+    Json(payload): Json<CompleteUploadRequest>,
+) -> Result<Json<UploadResponse>> {
+    let upload_id = parse_upload_id(&upload_id)?;
+    let _lock = state.part_files.lock(upload_id).ok_or_else(upload_busy)?;
+    let upload = find_upload(&state, upload_id, auth_user.id).await?;
+    if upload.received_bytes != upload.size_bytes {
+        return Err(AppError::Conflict(
+            Msg::UploadIncomplete
+                .with("received", upload.received_bytes)
+                .with("size", upload.size_bytes),
+        ));
+    }
+
+    // From here the part file becomes an asset or is removed
+    db::Upload::delete(&state.db, upload_id).await?;
+    let checked = async {
+        let inspected = state.part_files.inspect(upload_id, sniff::SNIFF_LEN).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                AppError::Conflict(Msg::UploadPartsMissing.into())
+            } else {
+                e.into()
             }
+        })?;
+        if let Some(expected) = &payload.sha256 {
+            if !expected.trim().eq_ignore_ascii_case(&inspected.sha256) {
+                return Err(AppError::BadRequest(Msg::ChecksumMismatch.into()));
+            }
+        }
+        let kind = validate_content(&inspected.header, &get_file_extension(&upload.filename))?.kind();
+        let max_bytes = max_upload_bytes(kind, &state.config);
+        if upload.size_bytes as u64 > max_bytes {
+            return Err(file_too_large(max_bytes));
+        }
+        let quota = quota::quota_status(&state.db, &state.config.quotas, auth_user.owner(), &auth_user.tier).await?;
+        Ok((kind, inspected.sha256, quota))
+    }
+    .await;
+    let (kind, content_hash, quota) = match checked {
+        Ok(checked) => checked,
+        Err(e) => {
+            state.part_files.remove(upload_id).await;
+            return Err(e);
+        }
+    };
 
-            let data = field
-                .bytes()
-                .await
-                .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
+    let staged = StreamedFile {
+        size: upload.size_bytes as u64,
+        content_hash,
+    };
+    let path = state.part_files.path(upload_id);
+    let uploaded = finish_upload(&state, &auth_user, upload.filename, &path, kind, staged, quota).await?;
+    Ok(Json(uploaded))
+}
 
-            let max_bytes = state.config.processing.lut_max_size_mb * 1024 * 1024;
-            if data.len() as u64 > max_bytes {
-                return Err(AppError::PayloadTooLarge(format!(
-                    "LUT file too large: {} MB (max {} MB)",
-                    data.len() as u64 / (1024 * 1024),
-                    max_bytes / (1024 * 1024)
-                )));
-            }
+/// Abandon a resumable upload and delete what was received
+#[utoipa::path(
+    delete,
+    path = "/api/upload/{upload_id}",
+    tag = "assets",
+    params(("upload_id" = Uuid, Path, description = "Upload ID")),
+    responses(
+        (status = 204, description = "Upload discarded"),
+        (status = 400, description = "Malformed ID", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "No such upload", body = ErrorResponse),
+        (status = 409, description = "A chunk is being received", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn cancel_upload(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(upload_id): Path<String>,
+) -> Result<StatusCode> {
+    let upload_id = parse_upload_id(&upload_id)?;
+    let _lock = state.part_files.lock(upload_id).ok_or_else(upload_busy)?;
+    find_upload(&state, upload_id, auth_user.id).await?;
 
-            // Save LUT to storage (using same storage adapter)
-            let location = state
-                .storage
-                .save_bytes(&data, &file_name)
-                .map_err(|e| AppError::Internal(format!("Failed to save LUT: {:?}", e)))?;
+    db::Upload::delete(&state.db, upload_id).await?;
+    state.part_files.remove(upload_id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn parse_upload_id(upload_id: &str) -> Result<Uuid> {
+    Uuid::parse_str(upload_id).map_err(|_| AppError::BadRequest(Msg::InvalidUploadId.into()))
+}
 
-            tracing::info!("User {} uploaded LUT {}", auth_user.email, file_name);
+async fn find_upload(state: &AppState, upload_id: Uuid, user_id: Uuid) -> Result<db::Upload> {
+    db::Upload::find_for_user(&state.db, upload_id, user_id)
+        .await?
+        .ok_or_else(upload_not_found)
+}
 
-            return Ok(Json(json!({"location": location})))
-        }
-    }
+fn upload_not_found() -> AppError {
+    AppError::NotFound(Msg::UploadNotFound.into())
+}
 
-    Err(AppError::BadRequest("No LUT file provided".to_string()))
+fn upload_busy() -> AppError {
+    AppError::Conflict(Msg::UploadBusy.into())
 }
 
 // ============================================================================
-// Job Status Routes
+// Asset Routes
 // ============================================================================
 
-#[derive(Serialize)]
-pub struct JobStatusResponse {
-    pub job_id: String,
-    pub status: String,
-    pub progress: u32,
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListAssetsQuery {
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AssetResponse {
+    pub id: String,
+    pub filename: String,
+    pub format: String,
+    pub size: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// Videos only
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub result_url: Option<String>,
+    pub duration_seconds: Option<i32>,
+    pub status: String,
     pub created_at: String,
+    pub expires_at: Option<String>,
+    /// Present once a thumbnail has been generated
+    pub thumbnail_url: Option<String>,
+    /// Hex SHA-256 of the uploaded bytes
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub completed_at: Option<String>,
+    pub content_hash: Option<String>,
+    /// The organization it was uploaded for, whose members share it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<String>,
 }
 
-pub async fn get_job_status(
+impl From<db::MediaAsset> for AssetResponse {
+    fn from(asset: db::MediaAsset) -> Self {
+        Self {
+            id: asset.id.to_string(),
+            filename: asset.original_filename,
+            format: asset.format,
+            size: asset.size_bytes,
+            width: asset.width,
+            height: asset.height,
+            duration_seconds: asset.duration_seconds,
+            status: asset.status,
+            created_at: asset.created_at.to_rfc3339(),
+            expires_at: asset.expires_at.map(|t| t.to_rfc3339()),
+            thumbnail_url: asset
+                .thumbnail_location
+                .as_ref()
+                .map(|_| format!("/api/assets/{}/thumbnail", asset.id)),
+            content_hash: asset.content_hash,
+            organization_id: asset.organization_id.map(|id| id.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AssetListResponse {
+    pub assets: Vec<AssetResponse>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/assets",
+    tag = "assets",
+    params(ListAssetsQuery),
+    responses(
+        (status = 200, description = "The caller's assets, newest first", body = AssetListResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn list_assets(
     auth_user: auth::AuthUser,
     State(state): State<AppState>,
-    Path(job_id): Path<String>,
-) -> Result<Json<JobStatusResponse>> {
-    let job_uuid = Uuid::parse_str(&job_id)
-        .map_err(|_| AppError::BadRequest("Invalid job ID".to_string()))?;
-
-    let job = db::Job::find_by_id(&state.db, job_uuid)
-        .await?
-        .ok_or_else(|| AppError::NotFound("Job not found".to_string()))?;
+    Query(query): Query<ListAssetsQuery>,
+) -> Result<Json<AssetListResponse>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let status = query.status.as_deref();
 
-    // Verify ownership
-    if job.user_id != auth_user.id {
-        return Err(AppError::Forbidden("Access denied".to_string()));
-    }
+    let assets = db::MediaAsset::find_by_user(&state.db, auth_user.owner(), status, limit, offset).await?;
+    let total = db::MediaAsset::count_by_user(&state.db, auth_user.owner(), status).await?;
 
-    Ok(Json(JobStatusResponse {
-        job_id: job.id.to_string(),
-        status: job.status,
-        progress: job.progress_percent as u32,
-        result_url: job.result_location,
-        created_at: job.created_at.to_rfc3339(),
-        completed_at: job.completed_at.map(|t| t.to_rfc3339()),
+    Ok(Json(AssetListResponse {
+        assets: assets.into_iter().map(AssetResponse::from).collect(),
+        total,
+        limit,
+        offset,
     }))
 }
 
-pub async fn list_user_jobs(
+/// Look up the caller's unexpired asset with this content, so a client can
+/// skip uploading bytes it has already sent
+#[utoipa::path(
+    get,
+    path = "/api/assets/by-hash/{hash}",
+    tag = "assets",
+    params(("hash" = String, Path, description = "Hex SHA-256 of the file")),
+    responses(
+        (status = 200, description = "The caller's asset with these bytes", body = AssetResponse),
+        (status = 400, description = "Not a hex SHA-256", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "No such upload", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn find_asset_by_hash(
     auth_user: auth::AuthUser,
     State(state): State<AppState>,
-) -> Result<Json<Vec<JobStatusResponse>>> {
-    let jobs = sqlx::query_as::<_, db::Job>(
-        "SELECT * FROM jobs WHERE user_id = $1 ORDER BY created_at DESC LIMIT 50",
-    )
-    .bind(auth_user.id)
-    .fetch_all(&state.db)
-    .await?;
+    Path(hash): Path<String>,
+) -> Result<Json<AssetResponse>> {
+    let hash = hash.to_lowercase();
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(AppError::BadRequest(Msg::InvalidContentHash.into()));
+    }
 
-    let response: Vec<JobStatusResponse> = jobs
-        .into_iter()
-        .map(|job| JobStatusResponse {
-            job_id: job.id.to_string(),
-            status: job.status,
-            progress: job.progress_percent as u32,
-            result_url: job.result_location,
-            created_at: job.created_at.to_rfc3339(),
-            completed_at: job.completed_at.map(|t| t.to_rfc3339()),
-        })
-        .collect();
+    let asset = db::MediaAsset::find_by_hash(&state.db, auth_user.owner(), &hash)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::NoAssetWithHash.into()))?;
 
-    Ok(Json(response))
+    Ok(Json(AssetResponse::from(asset)))
 }
 
-pub async fn download_result(
+#[utoipa::path(
+    get,
+    path = "/api/assets/{asset_id}",
+    tag = "assets",
+    params(("asset_id" = Uuid, Path, description = "Asset ID")),
+    responses(
+        (status = 200, description = "The asset", body = AssetResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn get_asset(
     auth_user: auth::AuthUser,
     State(state): State<AppState>,
-    Path(job_id): Path<String>,
-) -> Result<impl axum::response::IntoResponse> {
-    let job_uuid = Uuid::parse_str(&job_id)
-        .map_err(|_| AppError::BadRequest("Invalid job ID".to_string()))?;
+    Path(asset_id): Path<String>,
+) -> Result<Json<AssetResponse>> {
+    let asset_id = Uuid::parse_str(&asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
 
-    let job = db::Job::find_by_id(&state.db, job_uuid)
-        .await?
-        .ok_or_else(|| AppError::NotFound("Job not found".to_string()))?;
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+    Ok(Json(AssetResponse::from(asset)))
+}
 
-    // Verify ownership
-    if job.user_id != auth_user.id {
-        return Err(AppError::Forbidden("Access denied".to_string()));
+/// Delete an asset. Its files are kept and it can be restored for
+/// `ASSET_RESTORE_WINDOW_HOURS`, but it stops counting toward the storage
+/// quota at once and no job can use it meanwhile.
+#[utoipa::path(
+    delete,
+    path = "/api/assets/{asset_id}",
+    tag = "assets",
+    params(("asset_id" = Uuid, Path, description = "Asset ID")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+        (status = 409, description = "Jobs are still using the asset", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn delete_asset(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(asset_id): Path<String>,
+) -> Result<StatusCode> {
+    let asset_id = Uuid::parse_str(&asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+
+    let active = db::Job::count_active_for_asset(&state.db, asset_id).await?;
+    if active > 0 {
+        return Err(AppError::Conflict(Msg::AssetInUse.with("active", active)));
     }
 
-    if job.status != "completed" {
-        return Err(AppError::BadRequest("Job not completed".to_string()));
+    // The cleanup sweep removes the files once the restore window passes.
+    // Losing a race with another delete leaves the asset deleted just the same.
+    db::MediaAsset::soft_delete(&state.db, asset.id).await?;
+
+    tracing::info!("Asset {} deleted by user {}", asset_id, auth_user.email);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Bring back an asset deleted within the restore window
+#[utoipa::path(
+    post,
+    path = "/api/assets/{asset_id}/restore",
+    tag = "assets",
+    params(("asset_id" = Uuid, Path, description = "Asset ID")),
+    responses(
+        (status = 200, description = "Restored", body = AssetResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+        (status = 409, description = "The asset is not deleted", body = ErrorResponse),
+        (status = 410, description = "The restore window has passed", body = ErrorResponse),
+        (status = 429, description = "Restoring it would exceed the storage quota", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn restore_asset(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(asset_id): Path<String>,
+) -> Result<Json<AssetResponse>> {
+    let asset_id = Uuid::parse_str(&asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+
+    let asset = db::MediaAsset::find_by_id(&state.db, asset_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::AssetNotFound.into()))?;
+    if !auth_user.owner().reaches(asset.user_id, asset.organization_id) {
+        return Err(AppError::Forbidden(Msg::AccessDenied.into()));
+    }
+    if asset.deleted_at.is_none() {
+        return Err(AppError::Conflict(Msg::AssetNotDeleted.into()));
     }
 
-    let result_location = job
-        .result_location
-        .ok_or_else(|| AppError::NotFound("Result not found".to_string()))?;
+    // It counts toward the quota again
+    let quota = quota::quota_status(&state.db, &state.config.quotas, auth_user.owner(), &auth_user.tier).await?;
+    quota
+        .check_storage(asset.size_bytes)
+        .map_err(|violation| quota_exceeded(violation, quota.clone()))?;
 
-    // Read file from storage
-    let file_data = tokio::fs::read(&result_location)
-        .await
-        .map_err(|_| AppError::NotFound("File not found".to_string()))?;
+    let deleted_since = chrono::Utc::now() - state.config.processing.asset_restore_window();
+    let asset = db::MediaAsset::restore(&state.db, asset_id, deleted_since)
+        .await?
+        .ok_or_else(|| AppError::Gone(Msg::AssetRestoreExpired.into()))?;
+
+    tracing::info!("Asset {} restored by user {}", asset_id, auth_user.email);
+
+    Ok(Json(AssetResponse::from(asset)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/assets/{asset_id}/thumbnail",
+    tag = "assets",
+    params(("asset_id" = Uuid, Path, description = "Asset ID")),
+    responses(
+        (status = 200, description = "JPEG thumbnail", body = Vec<u8>, content_type = "image/jpeg"),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "No such asset, or no thumbnail yet", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn get_asset_thumbnail(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(asset_id): Path<String>,
+) -> Result<impl axum::response::IntoResponse> {
+    let asset_id = Uuid::parse_str(&asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+    let location = asset
+        .thumbnail_location
+        .ok_or_else(|| AppError::NotFound(Msg::NoThumbnail.into()))?;
+
+    let data = state.storage.load_bytes(&location.parse()?).await?;
 
-    // Determine content type from filename
-    let content_type = get_content_type(&result_location);
-    let filename = result_location
-        .split('/')
-        .last()
-        .unwrap_or("result");
-
-    let disposition = format!("attachment; filename=\"{}\"", filename);
-    
     Ok((
-        axum::http::StatusCode::OK,
-        [
-            ("Content-Type", content_type.to_string()),
-            ("Content-Disposition", disposition),
-        ],
-        file_data,
+        StatusCode::OK,
+        [("Content-Type", formats::content_type(&location).to_string())],
+        data,
     ))
 }
-// ============================================================================
-// Helper Functions
-// ============================================================================
-
-async fn verify_asset_ownership(
-    db: &sqlx::PgPool,
-    asset_id: Uuid,
-    user_id: Uuid,
-) -> Result<db::MediaAsset> {
-    let asset = sqlx::query_as::<_, db::MediaAsset>("SELECT * FROM media_assets WHERE id = $1")
-        .bind(asset_id)
-        .fetch_optional(db)
-        .await?
-        .ok_or_else(|| AppError::NotFound("Asset not found".to_string()))?;
 
-    if asset.user_id != user_id {
-        return Err(AppError::Forbidden("Access denied".to_string()));
-    }
+/// Longest edge of generated gallery thumbnails
+const THUMBNAIL_MAX_EDGE: u32 = 256;
 
-    Ok(asset)
+/// Generate a thumbnail in the background. Failures are only logged: the
+/// asset is fully usable without one.
+fn spawn_thumbnail(state: AppState, owner: Uuid, asset_id: Uuid, location: String) {
+    tokio::spawn(async move {
+        if let Err(e) = generate_thumbnail(&state, owner, asset_id, &location).await {
+            tracing::warn!("Thumbnail generation failed for asset {}: {}", asset_id, e);
+        }
+    });
 }
 
-async fn check_quota(state: &AppState, user: &auth::AuthUser, job_type: &str) -> Result<()> {
-    // Use quota service for logic
-    match crate::services::quota::check_quota(&state.db, &state.config, user.id, &user.tier, job_type).await {
-        Ok(_) => (),
-        Err(e) => return Err(AppError::QuotaExceeded(format!("{} Upgrade to Pro for more capacity.", e))),
-    }
+async fn generate_thumbnail(state: &AppState, owner: Uuid, asset_id: Uuid, location: &str) -> Result<()> {
+    let data = state.storage.load_bytes(&location.parse()?).await?;
+    let max_pixels = state.config.processing.max_image_pixels;
+    let thumbnail = tokio::task::spawn_blocking(move || {
+        ImageProcessor::thumbnail(&data, THUMBNAIL_MAX_EDGE, max_pixels)
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("Thumbnail task failed: {}", e)))??;
+
+    let thumbnail_location = state
+        .storage
+        .save_bytes(&thumbnail, owner, &format!("thumb_{}.jpg", asset_id))
+        .await?;
 
-    match crate::services::quota::check_concurrent(&state.db, &state.config, user.id, &user.tier).await {
-        Ok(_) => Ok(()),
-        Err(e) => Err(AppError::QuotaExceeded(format!("{} Try again later.", e))),
+    // The asset may have been deleted while we were working
+    if !db::MediaAsset::set_thumbnail(&state.db, asset_id, &thumbnail_location.to_string()).await? {
+        state.storage.delete(&thumbnail_location).await?;
     }
+    Ok(())
 }
 
-fn validate_file(
-    filename: &str,
-    data: &[u8],
-    config: &crate::config::Config,
-) -> Result<()> {
-    let lower = filename.to_lowercase();
-    let size = data.len() as u64;
-
-    let is_image = lower.ends_with(".jpg")
-        || lower.ends_with(".jpeg")
-        || lower.ends_with(".png")
-        || lower.ends_with(".webp")
-        || lower.ends_with(".gif")
-        || lower.ends_with(".heic");
+// ============================================================================
+// Processing Routes
+// ============================================================================
 
-    let is_video = lower.ends_with(".mp4") 
-        || lower.ends_with(".mov") 
-        || lower.ends_with(".avi")
-        || lower.ends_with(".webm");
+/// How a request names a LUT from the caller's library
+#[derive(Deserialize, Default, ToSchema)]
+pub struct LutReference {
+    /// Id returned by `POST /api/lut` and listed by `GET /api/luts`
+    #[serde(default)]
+    pub lut_id: Option<String>,
+    /// Deprecated: the storage location `POST /api/lut` used to hand out.
+    /// Only accepted when it is one of the caller's LUTs.
+    #[serde(default)]
+    pub lut_location: Option<String>,
+}
 
-    if !is_image && !is_video {
-        return Err(AppError::BadRequest(
-            "Unsupported file type. Supported: JPG, PNG, WEBP, GIF, HEIC, MP4, MOV, AVI, WEBM"
-                .to_string(),
-        ));
+impl LutReference {
+    fn is_some(&self) -> bool {
+        self.lut_id.is_some() || self.lut_location.is_some()
     }
 
-    let max_size_bytes = if is_image {
-        config.processing.max_image_size_mb * 1024 * 1024
-    } else {
-        config.processing.max_video_size_mb * 1024 * 1024
-    };
+    /// The field the request used, for errors about the reference as a whole
+    fn field(&self) -> &'static str {
+        if self.lut_id.is_some() { "lut_id" } else { "lut_location" }
+    }
 
-    if size > max_size_bytes {
-        return Err(AppError::PayloadTooLarge(format!(
-            "File too large: {} MB (max {} MB)",
-            size / (1024 * 1024),
-            max_size_bytes / (1024 * 1024)
-        )));
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(id) = &self.lut_id {
+            if Uuid::parse_str(id).is_err() {
+                validator.push(FieldError::new("lut_id", code::INVALID_FORMAT, Msg::MustBeLutId));
+            }
+            if self.lut_location.is_some() {
+                validator.push(FieldError::new(
+                    "lut_location",
+                    code::NOT_APPLICABLE,
+                    Msg::LutOrLocation,
+                ));
+            }
+        }
     }
 
-    Ok(())
-}
+    /// Replace the reference with the id of the LUT it names, one of the
+    /// caller's or their organization's, so jobs only ever carry ids.
+    /// References that fail `validate` are left alone for it to report.
+    async fn resolve(&mut self, db: &sqlx::PgPool, owner: db::Owner) -> Result<()> {
+        let lut = match (self.lut_id.as_deref(), self.lut_location.as_deref()) {
+            (Some(id), None) => match Uuid::parse_str(id) {
+                Ok(id) => db::LutFile::find_for_user(db, id, owner).await?,
+                Err(_) => return Ok(()),
+            },
+            (None, Some(location)) => db::LutFile::find_by_location(db, owner.user_id, location).await?,
+            _ => return Ok(()),
+        };
+        // Someone else's LUT looks the same as a missing one
+        let lut = lut.ok_or_else(|| AppError::NotFound(Msg::LutNotFound.into()))?;
+        self.lut_id = Some(lut.id.to_string());
+        self.lut_location = None;
+        Ok(())
+    }
 
-fn get_file_extension(filename: &str) -> String {
-    filename
-        .rsplit('.')
-        .next()
-        .unwrap_or("unknown")
-        .to_lowercase()
+    /// The id a validated, resolved reference names
+    fn id(&self) -> Option<Uuid> {
+        self.lut_id.as_deref().and_then(|id| Uuid::parse_str(id).ok())
+    }
 }
 
-fn get_content_type(filename: &str) -> &'static str {
-    let lower = filename.to_lowercase();
-    
-    if lower.ends_with(".png") {
-        "image/png"
-    } else if lower.ends_with(".jpg") || lower.ends_with(".jpeg") {
-        "image/jpeg"
-    } else if lower.ends_with(".webp") {
-        "image/webp"
-    } else if lower.ends_with(".gif") {
-        "image/gif"
-    } else if lower.ends_with(".mp4") {
-        "video/mp4"
-    } else if lower.ends_with(".mov") {
-        "video/quicktime"
-    } else if lower.ends_with(".avi") {
-        "video/x-msvideo"
-    } else if lower.ends_with(".webm") {
-        "video/webm"
-    } else {
-        "application/octet-stream"
+/// Conversion options shared by single and batch requests
+#[derive(Deserialize, ToSchema)]
+pub struct ConversionParams {
+    /// AVIF output is only available when the server is built with it. It
+    /// is slow to encode, some seconds per megapixel, and limited to 4096 px
+    /// per side.
+    pub output_format: String,
+    /// Images only
+    #[serde(flatten)]
+    pub lut: LutReference,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Video only: h264, hevc, vp9, av1 or prores (default depends on the container)
+    #[serde(default)]
+    pub video_codec: Option<String>,
+    /// Images: encoder quality 1–100 (JPEG, WebP and AVIF). Video: CRF, lower is better.
+    #[serde(default)]
+    pub quality: Option<u32>,
+    /// Still JPEG, WebP and AVIF images: instead of `quality`, the size in
+    /// KiB to come in under. The highest quality that does is searched for;
+    /// if even the lowest is too large the job notes `target_size_missed`.
+    #[serde(default)]
+    pub target_size_kb: Option<u32>,
+    /// Drop EXIF/GPS and other metadata from the output. On unless turned off.
+    #[serde(default = "strip_metadata_default")]
+    pub strip_metadata: bool,
+}
+
+fn strip_metadata_default() -> bool {
+    true
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ConvertRequest {
+    pub asset_id: String,
+    #[serde(flatten)]
+    pub params: ConversionParams,
+    /// Receives a signed POST when the job completes or fails
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(flatten)]
+    pub schedule: JobSchedule,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchConvertRequest {
+    pub asset_ids: Vec<String>,
+    #[serde(flatten)]
+    pub params: ConversionParams,
+    /// Receives a signed POST when the job completes or fails
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(flatten)]
+    pub schedule: JobSchedule,
+}
+
+/// Most assets a single batch job may reference
+const MAX_BATCH_ASSETS: usize = 50;
+
+#[derive(Serialize, ToSchema)]
+pub struct JobResponse {
+    pub job_id: String,
+    pub status: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/convert",
+    tag = "processing",
+    request_body = ConvertRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries sent with the same key get the first response back, marked `Idempotent-Replayed: true`, rather than running again")),
+    responses(
+        (status = 200, description = "Job queued", body = JobResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Asset owned by another user, or email not verified", body = ErrorResponse),
+        (status = 404, description = "Asset not found", body = ErrorResponse),
+        (status = 409, description = "A request with this Idempotency-Key is still in progress", body = ErrorResponse),
+        (status = 422, description = "Invalid fields, or an Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+        (status = 503, description = "Queue unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn convert(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    Json(payload): Json<ConvertRequest>,
+) -> Result<Json<JobResponse>> {
+    let asset_id = Uuid::parse_str(&payload.asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+    let mut params = payload.params;
+    validate_video_codec(&params)?;
+    validate_webhook(&state, payload.webhook_url.as_deref()).await?;
+    let (priority, run_after) = payload.schedule.place(&auth_user.tier)?;
+
+    // Verify asset ownership
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+
+    // Reject conversions the worker could never complete
+    let kind = media_kind_from_filename(&asset.original_filename)?;
+    let output_format = validate_conversion(&params, kind)?;
+    if kind == MediaKind::Video {
+        let encoders = video_convert_options(&params, &output_format).encoders().unwrap_or_default();
+        check_video_support(&state.ffmpeg, &encoders)?;
+    }
+    params.lut.resolve(&state.db, auth_user.owner()).await?;
+
+    // Check quota
+    check_quota(&state, &auth_user, kind, 1).await?;
+
+    // Create job
+    let job = db::Job::create(
+        &state.db,
+        auth_user.owner(),
+        vec![asset_id],
+        "convert",
+        kind.as_str(),
+        job_parameters(conversion_parameters(&params, &output_format).to_value(), payload.webhook_url, &request_id),
+        priority,
+        run_after,
+    )
+    .await;
+    let job = created_or_released(&state, &auth_user, kind, 1, job).await?;
+
+    // Enqueue job
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!(
+        "Conversion job {} queued for user {}",
+        job.id,
+        auth_user.email
+    );
+
+    Ok(Json(JobResponse {
+        job_id: job.id.to_string(),
+        status: "queued".to_string(),
+    }))
+}
+
+/// Convert several assets with the same options in one job. Every output that
+/// succeeded is kept, and downloading the result without picking one gets
+/// them all in a zip; per-asset outcomes are recorded under `asset_results`
+/// in the job parameters.
+#[utoipa::path(
+    post,
+    path = "/api/convert/batch",
+    tag = "processing",
+    request_body = BatchConvertRequest,
+    responses(
+        (status = 200, description = "One job for every asset, with an output for each", body = JobResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Asset owned by another user, or email not verified", body = ErrorResponse),
+        (status = 404, description = "Asset not found", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+        (status = 503, description = "Queue unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn convert_batch(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    Json(payload): Json<BatchConvertRequest>,
+) -> Result<Json<JobResponse>> {
+    if payload.asset_ids.is_empty() {
+        return Err(AppError::BadRequest(Msg::EmptyAssetIds.into()));
+    }
+    if payload.asset_ids.len() > MAX_BATCH_ASSETS {
+        return Err(AppError::BadRequest(
+            Msg::BatchTooLarge
+                .with("max", MAX_BATCH_ASSETS)
+                .with("count", payload.asset_ids.len()),
+        ));
+    }
+
+    let mut asset_ids = Vec::with_capacity(payload.asset_ids.len());
+    for raw in &payload.asset_ids {
+        let asset_id = Uuid::parse_str(raw)
+            .map_err(|_| AppError::BadRequest(Msg::InvalidAssetIdValue.with("value", raw.as_str())))?;
+        if asset_ids.contains(&asset_id) {
+            return Err(AppError::BadRequest(Msg::DuplicateAssetId.with("value", raw.as_str())));
+        }
+        asset_ids.push(asset_id);
+    }
+
+    let mut params = payload.params;
+    validate_video_codec(&params)?;
+    validate_webhook(&state, payload.webhook_url.as_deref()).await?;
+    let (priority, run_after) = payload.schedule.place(&auth_user.tier)?;
+
+    // Every asset must belong to the caller and be convertible with these
+    // options. Image and video output formats don't overlap, so mixed
+    // batches already fail validation; the job records a single media kind.
+    let mut output_format = String::new();
+    let mut batch_kind = None;
+    for &asset_id in &asset_ids {
+        let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+        let kind = media_kind_from_filename(&asset.original_filename)?;
+        output_format = validate_conversion(&params, kind)?;
+        if batch_kind.is_some_and(|k| k != kind) {
+            return Err(AppError::UnprocessableEntity(Msg::BatchMixesKinds.into()));
+        }
+        batch_kind = Some(kind);
+    }
+    let kind = batch_kind.expect("asset_ids is not empty");
+    if kind == MediaKind::Video {
+        let encoders = video_convert_options(&params, &output_format).encoders().unwrap_or_default();
+        check_video_support(&state.ffmpeg, &encoders)?;
+    }
+    let asset_count = asset_ids.len();
+    params.lut.resolve(&state.db, auth_user.owner()).await?;
+
+    // Each asset counts against the quota individually
+    check_quota(&state, &auth_user, kind, asset_count as i64).await?;
+
+    let job = db::Job::create(
+        &state.db,
+        auth_user.owner(),
+        asset_ids,
+        "convert",
+        kind.as_str(),
+        job_parameters(conversion_parameters(&params, &output_format).to_value(), payload.webhook_url, &request_id),
+        priority,
+        run_after,
+    )
+    .await;
+    let job = created_or_released(&state, &auth_user, kind, asset_count as i64, job).await?;
+
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!(
+        "Batch conversion job {} ({} assets) queued for user {}",
+        job.id,
+        asset_count,
+        auth_user.email
+    );
+
+    Ok(Json(JobResponse {
+        job_id: job.id.to_string(),
+        status: "queued".to_string(),
+    }))
+}
+
+fn validate_video_codec(params: &ConversionParams) -> Result<()> {
+    Validator::new()
+        .one_of("video_codec", params.video_codec.as_deref(), video::VIDEO_CODECS)
+        .finish()
+}
+
+/// Size targets need a lossy output and stand in for `quality`
+fn validate_size_target(validator: &mut Validator, params: &ConversionParams, output_format: &str) {
+    if params.target_size_kb.is_none() {
+        return;
+    }
+    if !SIZE_TARGET_FORMATS.contains(&output_format) {
+        validator.push(FieldError::new(
+            "target_size_kb",
+            code::NOT_APPLICABLE,
+            Msg::TargetSizeFormats.with("formats", SIZE_TARGET_FORMATS),
+        ));
+    } else if params.quality.is_some() {
+        validator.push(FieldError::new(
+            "target_size_kb",
+            code::NOT_APPLICABLE,
+            Msg::TargetSizeOrQuality,
+        ));
+    }
+    validator.range("target_size_kb", params.target_size_kb, 1..=MAX_TARGET_SIZE_KB);
+}
+
+/// Image outputs whose size a quality search can bring down
+const SIZE_TARGET_FORMATS: &[&str] = &["jpg", "jpeg", "webp", "avif"];
+
+/// Largest size target, 100 MiB
+const MAX_TARGET_SIZE_KB: u32 = 100 * 1024;
+
+/// Check the options against an asset's kind and return the normalized output format
+fn validate_conversion(params: &ConversionParams, kind: MediaKind) -> Result<String> {
+    let mut validator = Validator::new();
+    let output_format = match formats::validate_output_format(&params.output_format, kind) {
+        Ok(format) => format,
+        Err(e) => {
+            validator.push(FieldError::new("output_format", code::INVALID_CHOICE, &e));
+            String::new()
+        }
+    };
+    params.lut.validate(&mut validator);
+    match kind {
+        MediaKind::Video => {
+            if params.lut.is_some() {
+                validator.push(FieldError::new(
+                    params.lut.field(),
+                    code::NOT_APPLICABLE,
+                    Msg::LutImagesOnly,
+                ));
+            }
+        }
+        MediaKind::Image => {
+            if params.video_codec.is_some() {
+                validator.push(FieldError::new(
+                    "video_codec",
+                    code::NOT_APPLICABLE,
+                    Msg::VideoCodecVideosOnly,
+                ));
+            }
+            validator.range("quality", params.quality, 1..=100);
+            validate_size_target(&mut validator, params, &output_format);
+            if output_format == "avif" {
+                let max_edge = processing::AVIF_MAX_EDGE;
+                validator.range("width", params.width, 1..=max_edge);
+                validator.range("height", params.height, 1..=max_edge);
+            }
+        }
+    }
+    if kind == MediaKind::Video && params.target_size_kb.is_some() {
+        validator.push(FieldError::new(
+            "target_size_kb",
+            code::NOT_APPLICABLE,
+            Msg::TargetSizeImagesOnly,
+        ));
+    }
+    validator.finish()?;
+
+    // Codec, container and quality combinations are checked together
+    if kind == MediaKind::Video {
+        video_convert_options(params, &output_format)
+            .validate()
+            .map_err(|e| AppError::UnprocessableEntity(Msg::UnsupportedOptions.with("reason", e.to_string())))?;
+    }
+    Ok(output_format)
+}
+
+fn video_convert_options(params: &ConversionParams, output_format: &str) -> video::ConvertOptions {
+    video::ConvertOptions {
+        format: output_format.to_string(),
+        codec: params.video_codec.clone(),
+        quality: params.quality,
+        width: params.width,
+        height: params.height,
+        keep_metadata: !params.strip_metadata,
+    }
+}
+
+/// Refuse video work this deployment's ffmpeg could never run, before a job
+/// is queued for it
+fn check_video_support(ffmpeg: &Ffmpeg, encoders: &[&str]) -> Result<()> {
+    if !ffmpeg.available() {
+        return Err(AppError::UnprocessableEntity(Msg::VideoUnavailable.into()));
+    }
+    match ffmpeg.missing_encoder(encoders) {
+        Some(encoder) => Err(AppError::UnprocessableEntity(Msg::VideoEncoderUnavailable.with("encoder", encoder))),
+        None => Ok(()),
+    }
+}
+
+fn conversion_parameters(params: &ConversionParams, output_format: &str) -> job_params::ConvertParams {
+    job_params::ConvertParams {
+        output_format: Some(output_format.to_string()),
+        lut_id: params.lut.id(),
+        lut_location: None,
+        width: params.width,
+        height: params.height,
+        video_codec: params.video_codec.clone(),
+        quality: params.quality,
+        target_size_kb: params.target_size_kb,
+        strip_metadata: params.strip_metadata,
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RemoveBgParams {
+    /// What goes behind the subject: `transparent`, `color` (needs
+    /// `replace_color`), `blur` or `image` (needs `background_asset_id`).
+    /// Defaults to `color` or `image` when their field is given and
+    /// `transparent` otherwise.
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    /// RGB, each channel 0–255. Wider integers so out-of-range channels get
+    /// a field error rather than a deserialization failure.
+    pub replace_color: Option<[i32; 3]>,
+    /// Gaussian sigma of the background in `blur` mode, 0.5–50 (default 12)
+    #[serde(default)]
+    pub blur_sigma: Option<f32>,
+    /// Another of the caller's uploaded images to put behind the subject.
+    /// It is scaled to cover the result and center-cropped.
+    #[serde(default)]
+    pub background_asset_id: Option<String>,
+    /// Where the background asset is stored, filled in by `resolve_background`
+    #[serde(skip)]
+    background_location: Option<String>,
+    /// Crop an image result to the subject. A result with no subject left
+    /// keeps its canvas, and the job notes `trim_empty`.
+    #[serde(default)]
+    pub trim: bool,
+    /// Space kept around the subject when trimming, as a percentage of its
+    /// width and height, 0–100 (default 0)
+    #[serde(default)]
+    pub trim_padding: Option<f32>,
+    /// Images: `png` (default), `webp` (lossy, with alpha) or `jpg`, which
+    /// has no alpha and so needs an opaque mode or `flatten_color`.
+    /// Videos: WebM keeps alpha; `mp4`, `mov` and `zip` yield a zip of PNG
+    /// frames.
+    #[serde(default)]
+    pub output_format: Option<String>,
+    /// RGB, each channel 0–255, filling the transparent background of a
+    /// `jpg` result in the `transparent` mode
+    #[serde(default)]
+    pub flatten_color: Option<[i32; 3]>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RemoveBgRequest {
+    pub asset_id: String,
+    #[serde(flatten)]
+    pub params: RemoveBgParams,
+    /// Receives a signed POST when the job completes or fails
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(flatten)]
+    pub schedule: JobSchedule,
+}
+
+const VIDEO_OUTPUT_FORMATS: &[&str] = &["webm", "mp4", "mov", "zip"];
+const IMAGE_CUT_OUT_FORMATS: &[&str] = &["png", "webp", "jpg", "jpeg"];
+const BACKGROUND_MODES: &[&str] = &["transparent", "color", "blur", "image"];
+
+impl RemoveBgParams {
+    /// Look up `background_asset_id`, which must be an image the caller
+    /// reaches, and note where it is stored. Ids that fail validation are
+    /// left for it to report.
+    async fn resolve_background(&mut self, db: &sqlx::PgPool, owner: db::Owner) -> Result<()> {
+        let Some(asset_id) = self.background_asset_id.as_deref().and_then(|id| Uuid::parse_str(id).ok()) else {
+            return Ok(());
+        };
+        let asset = verify_asset_ownership(db, asset_id, owner)
+            .await
+            .map_err(|e| match e {
+                AppError::NotFound(_) => AppError::NotFound(Msg::BackgroundAssetNotFound.into()),
+                e => e,
+            })?;
+        if media_kind_from_filename(&asset.original_filename)? != MediaKind::Image {
+            return Err(AppError::UnprocessableEntity(Msg::BackgroundNotImage.into()));
+        }
+        let location = asset
+            .result_location
+            .ok_or_else(|| AppError::UnprocessableEntity(Msg::BackgroundWithoutContent.into()))?;
+        self.background_location = Some(location);
+        Ok(())
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/remove-bg",
+    tag = "processing",
+    request_body = RemoveBgRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries sent with the same key get the first response back, marked `Idempotent-Replayed: true`, rather than running again")),
+    responses(
+        (status = 200, description = "Job queued", body = JobResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Asset owned by another user, or email not verified", body = ErrorResponse),
+        (status = 404, description = "Asset not found", body = ErrorResponse),
+        (status = 409, description = "A request with this Idempotency-Key is still in progress", body = ErrorResponse),
+        (status = 422, description = "Invalid fields, or an Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+        (status = 503, description = "Queue unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn remove_bg(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    Json(payload): Json<RemoveBgRequest>,
+) -> Result<Json<JobResponse>> {
+    let asset_id = Uuid::parse_str(&payload.asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+
+    let mut params = payload.params;
+    validate_remove_bg(&params)?;
+    validate_webhook(&state, payload.webhook_url.as_deref()).await?;
+    let (priority, run_after) = payload.schedule.place(&auth_user.tier)?;
+
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+    let kind = media_kind_from_filename(&asset.original_filename)?;
+    validate_remove_bg_for(&params, kind)?;
+    if kind == MediaKind::Video {
+        let output = video::VideoOutput::for_format(params.output_format.as_deref());
+        check_video_support(&state.ffmpeg, output.encoders())?;
+    }
+    params.resolve_background(&state.db, auth_user.owner()).await?;
+
+    check_quota(&state, &auth_user, kind, 1).await?;
+
+    let job = db::Job::create(
+        &state.db,
+        auth_user.owner(),
+        vec![asset_id],
+        "remove_bg",
+        kind.as_str(),
+        job_parameters(remove_bg_parameters(&params).to_value(), payload.webhook_url, &request_id),
+        priority,
+        run_after,
+    )
+    .await;
+    let job = created_or_released(&state, &auth_user, kind, 1, job).await?;
+
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!(
+        "Background removal job {} queued for user {}",
+        job.id,
+        auth_user.email
+    );
+
+    Ok(Json(JobResponse {
+        job_id: job.id.to_string(),
+        status: "queued".to_string(),
+    }))
+}
+
+fn validate_remove_bg(params: &RemoveBgParams) -> Result<()> {
+    if params.replace_color.is_some() && params.background_asset_id.is_some() {
+        return Err(AppError::BadRequest(Msg::ColorOrBackground.into()));
+    }
+    let jpeg = params
+        .output_format
+        .as_deref()
+        .is_some_and(|f| f.eq_ignore_ascii_case("jpg") || f.eq_ignore_ascii_case("jpeg"));
+    if jpeg && background_mode(params) == "transparent" && params.flatten_color.is_none() {
+        return Err(AppError::BadRequest(Msg::JpegLosesAlpha.into()));
+    }
+    let mut validator = Validator::new();
+    let output_formats = [IMAGE_CUT_OUT_FORMATS, VIDEO_OUTPUT_FORMATS].concat();
+    validator.one_of("output_format", params.output_format.as_deref(), &output_formats);
+    validator.one_of("mode", params.mode.as_deref(), BACKGROUND_MODES);
+    for (i, channel) in params.replace_color.iter().flatten().enumerate() {
+        validator.range(&format!("replace_color[{}]", i), Some(*channel), 0..=255);
+    }
+    for (i, channel) in params.flatten_color.iter().flatten().enumerate() {
+        validator.range(&format!("flatten_color[{}]", i), Some(*channel), 0..=255);
+    }
+    if params.flatten_color.is_some() && !(jpeg && background_mode(params) == "transparent") {
+        validator.push(FieldError::new(
+            "flatten_color",
+            code::NOT_APPLICABLE,
+            Msg::FlattenColorTransparentJpg,
+        ));
+    }
+    validator.range("blur_sigma", params.blur_sigma, 0.5..=50.0);
+    validator.range("trim_padding", params.trim_padding, 0.0..=100.0);
+    if !params.trim && params.trim_padding.is_some() {
+        validator.push(FieldError::new("trim_padding", code::NOT_APPLICABLE, Msg::TrimPaddingNeedsTrim));
+    }
+    if params.background_asset_id.as_deref().is_some_and(|id| Uuid::parse_str(id).is_err()) {
+        validator.push(FieldError::new("background_asset_id", code::INVALID_FORMAT, Msg::MustBeAssetId));
+    }
+
+    // An unknown mode has already failed; don't pile more errors onto it
+    let mode = background_mode(params);
+    if !BACKGROUND_MODES.contains(&mode.as_str()) {
+        return validator.finish();
+    }
+    if mode == "color" && params.replace_color.is_none() {
+        validator.push(FieldError::new("replace_color", code::REQUIRED, Msg::ColorModeNeedsColor));
+    }
+    if mode != "color" && params.replace_color.is_some() {
+        validator.push(FieldError::new(
+            "replace_color",
+            code::NOT_APPLICABLE,
+            Msg::ReplaceColorColorMode,
+        ));
+    }
+    if mode == "image" && params.background_asset_id.is_none() {
+        validator.push(FieldError::new(
+            "background_asset_id",
+            code::REQUIRED,
+            Msg::ImageModeNeedsBackground,
+        ));
+    }
+    if mode != "image" && params.background_asset_id.is_some() {
+        validator.push(FieldError::new(
+            "background_asset_id",
+            code::NOT_APPLICABLE,
+            Msg::BackgroundImageMode,
+        ));
+    }
+    if mode != "blur" && params.blur_sigma.is_some() {
+        validator.push(FieldError::new(
+            "blur_sigma",
+            code::NOT_APPLICABLE,
+            Msg::BlurSigmaBlurMode,
+        ));
+    }
+    validator.finish()
+}
+
+/// Checks that depend on the input, after `validate_remove_bg`. Trimming
+/// crops each result to its own subject, which video frames can't each have.
+fn validate_remove_bg_for(params: &RemoveBgParams, kind: MediaKind) -> Result<()> {
+    let mut validator = Validator::new();
+    if params.trim && kind == MediaKind::Video {
+        validator.push(FieldError::new("trim", code::NOT_APPLICABLE, Msg::TrimImagesOnly));
+    }
+    let (formats, other) = match kind {
+        MediaKind::Image => (IMAGE_CUT_OUT_FORMATS, "videos"),
+        MediaKind::Video => (VIDEO_OUTPUT_FORMATS, "images"),
+    };
+    if let Some(format) = params.output_format.as_deref() {
+        let format = format.to_lowercase();
+        if !formats.contains(&format.as_str()) {
+            validator.push(FieldError::new(
+                "output_format",
+                code::NOT_APPLICABLE,
+                Msg::FormatOnlyFor.with("format", format).with("kind", other),
+            ));
+        }
+    }
+    validator.finish()
+}
+
+/// The requested mode, lowercased, or the one implied by `replace_color`
+/// or `background_asset_id`
+fn background_mode(params: &RemoveBgParams) -> String {
+    match &params.mode {
+        Some(mode) => mode.to_lowercase(),
+        None if params.replace_color.is_some() => "color".to_string(),
+        None if params.background_asset_id.is_some() => "image".to_string(),
+        None => "transparent".to_string(),
+    }
+}
+
+/// The job's parameters from validated ones; colors are already known to be
+/// in range
+fn remove_bg_parameters(params: &RemoveBgParams) -> job_params::RemoveBgParams {
+    let mode = background_mode(params);
+    let blur_sigma = (mode == "blur").then(|| params.blur_sigma.unwrap_or(processing::DEFAULT_BLUR_SIGMA));
+    let color = |color: Option<[i32; 3]>| color.map(|channels| channels.map(|c| c as u8));
+    job_params::RemoveBgParams {
+        mode: Some(mode),
+        replace_color: color(params.replace_color),
+        blur_sigma,
+        background_asset_id: params.background_asset_id.clone(),
+        background_location: params.background_location.clone(),
+        trim: params.trim,
+        trim_padding: params.trim.then(|| params.trim_padding.unwrap_or(0.0)),
+        output_format: params.output_format.clone(),
+        flatten_color: color(params.flatten_color),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ColorGradeParams {
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(flatten)]
+    pub lut: LutReference,
+    #[serde(default)]
+    pub hue: Option<i32>,
+    #[serde(default)]
+    pub saturation: Option<i32>,
+    #[serde(default)]
+    pub brightness: Option<i32>,
+    #[serde(default)]
+    pub contrast: Option<i32>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ColorGradeRequest {
+    pub asset_id: String,
+    #[serde(flatten)]
+    pub params: ColorGradeParams,
+    /// Receives a signed POST when the job completes or fails
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(flatten)]
+    pub schedule: JobSchedule,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/color-grade",
+    tag = "processing",
+    request_body = ColorGradeRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries sent with the same key get the first response back, marked `Idempotent-Replayed: true`, rather than running again")),
+    responses(
+        (status = 200, description = "Job queued", body = JobResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Asset owned by another user, or email not verified", body = ErrorResponse),
+        (status = 404, description = "Asset not found", body = ErrorResponse),
+        (status = 409, description = "A request with this Idempotency-Key is still in progress", body = ErrorResponse),
+        (status = 422, description = "Invalid fields, or an Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+        (status = 503, description = "Queue unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn color_grade(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    Json(payload): Json<ColorGradeRequest>,
+) -> Result<Json<JobResponse>> {
+    let asset_id = Uuid::parse_str(&payload.asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+
+    let mut params = payload.params;
+    validate_color_grade(&params)?;
+    validate_webhook(&state, payload.webhook_url.as_deref()).await?;
+    let (priority, run_after) = payload.schedule.place(&auth_user.tier)?;
+
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+    let kind = media_kind_from_filename(&asset.original_filename)?;
+    params.lut.resolve(&state.db, auth_user.owner()).await?;
+
+    check_quota(&state, &auth_user, kind, 1).await?;
+
+    let job = db::Job::create(
+        &state.db,
+        auth_user.owner(),
+        vec![asset_id],
+        "color_grade",
+        kind.as_str(),
+        job_parameters(color_grade_parameters(&params).to_value(), payload.webhook_url, &request_id),
+        priority,
+        run_after,
+    )
+    .await;
+    let job = created_or_released(&state, &auth_user, kind, 1, job).await?;
+
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!(
+        "Color grading job {} queued for user {}",
+        job.id,
+        auth_user.email
+    );
+
+    Ok(Json(JobResponse {
+        job_id: job.id.to_string(),
+        status: "queued".to_string(),
+    }))
+}
+
+fn validate_color_grade(params: &ColorGradeParams) -> Result<()> {
+    let mut validator = Validator::new();
+    params.lut.validate(&mut validator);
+    validator
+        .range("hue", params.hue, -180..=180)
+        .range("saturation", params.saturation, -100..=100)
+        .range("brightness", params.brightness, -100..=100)
+        .range("contrast", params.contrast, -100..=100)
+        .finish()
+}
+
+fn color_grade_parameters(params: &ColorGradeParams) -> job_params::ColorGradeParams {
+    job_params::ColorGradeParams {
+        preset: params.preset.clone(),
+        lut_id: params.lut.id(),
+        lut_location: None,
+        hue: params.hue,
+        saturation: params.saturation,
+        brightness: params.brightness,
+        contrast: params.contrast,
+    }
+}
+
+/// One step of a processing pipeline; the payloads match the single-operation routes
+#[derive(Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Operation {
+    RemoveBg(RemoveBgParams),
+    ColorGrade(ColorGradeParams),
+    Convert(ConversionParams),
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::RemoveBg(_) => "remove_bg",
+            Self::ColorGrade(_) => "color_grade",
+            Self::Convert(_) => "convert",
+        }
+    }
+
+    /// Look up what the step refers to: a LUT or a background image
+    async fn resolve(&mut self, db: &sqlx::PgPool, owner: db::Owner) -> Result<()> {
+        match self {
+            Self::RemoveBg(params) => params.resolve_background(db, owner).await,
+            Self::ColorGrade(params) => params.lut.resolve(db, owner).await,
+            Self::Convert(params) => params.lut.resolve(db, owner).await,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ProcessRequest {
+    pub asset_id: String,
+    /// Operations as raw JSON so a bad step can be reported by position
+    #[schema(value_type = Vec<Operation>)]
+    pub operations: Vec<serde_json::Value>,
+    /// Receives a signed POST when the job completes or fails
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(flatten)]
+    pub schedule: JobSchedule,
+}
+
+/// Longest chain a pipeline job may run
+const MAX_PIPELINE_STEPS: usize = 5;
+
+/// Run several operations on one asset as a single `pipeline` job. Each step
+/// consumes the previous step's output; the last step decides the result format.
+#[utoipa::path(
+    post,
+    path = "/api/process",
+    tag = "processing",
+    request_body = ProcessRequest,
+    responses(
+        (status = 200, description = "Job queued", body = JobResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Asset owned by another user, or email not verified", body = ErrorResponse),
+        (status = 404, description = "Asset not found", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+        (status = 503, description = "Queue unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn process(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    Json(payload): Json<ProcessRequest>,
+) -> Result<Json<JobResponse>> {
+    let asset_id = Uuid::parse_str(&payload.asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+
+    if payload.operations.is_empty() {
+        return Err(AppError::BadRequest(Msg::EmptyOperations.into()));
+    }
+    if payload.operations.len() > MAX_PIPELINE_STEPS {
+        return Err(AppError::BadRequest(
+            Msg::PipelineTooLong
+                .with("max", MAX_PIPELINE_STEPS)
+                .with("count", payload.operations.len()),
+        ));
+    }
+
+    validate_webhook(&state, payload.webhook_url.as_deref()).await?;
+    let (priority, run_after) = payload.schedule.place(&auth_user.tier)?;
+
+    let mut operations = payload
+        .operations
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            serde_json::from_value::<Operation>(value).map_err(|e| {
+                let message = Msg::InvalidOperation.with("reason", e.to_string());
+                AppError::BadRequest(message.within(Msg::PipelineStepAt.with("step", i + 1)))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+    let asset_kind = media_kind_from_filename(&asset.original_filename)?;
+    if asset_kind == MediaKind::Video {
+        check_video_support(&state.ffmpeg, &[])?;
+    }
+
+    for (i, operation) in operations.iter_mut().enumerate() {
+        let step = Msg::PipelineStep.with("step", i + 1).with("operation", operation.name());
+        operation.resolve(&state.db, auth_user.owner()).await.map_err(|e| match e {
+            AppError::NotFound(m) => AppError::NotFound(m.within(step)),
+            AppError::UnprocessableEntity(m) => AppError::UnprocessableEntity(m.within(step)),
+            e => e,
+        })?;
+    }
+    let steps = pipeline_parameters(&operations, asset_kind)?;
+
+    check_quota(&state, &auth_user, asset_kind, 1).await?;
+
+    let job = db::Job::create(
+        &state.db,
+        auth_user.owner(),
+        vec![asset_id],
+        "pipeline",
+        asset_kind.as_str(),
+        job_parameters(job_params::PipelineParams { operations: steps }.to_value(), payload.webhook_url, &request_id),
+        priority,
+        run_after,
+    )
+    .await;
+    let job = created_or_released(&state, &auth_user, asset_kind, 1, job).await?;
+
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!(
+        "Pipeline job {} ({} steps) queued for user {}",
+        job.id,
+        operations.len(),
+        auth_user.email
+    );
+
+    Ok(Json(JobResponse {
+        job_id: job.id.to_string(),
+        status: "queued".to_string(),
+    }))
+}
+
+/// Validate each step against the media it will receive and build the
+/// per-step job parameters. A step's input kind follows from the previous
+/// step's output; `None` means a zip of frames, which nothing can consume.
+fn pipeline_parameters(
+    operations: &[Operation],
+    asset_kind: MediaKind,
+) -> Result<Vec<job_params::PipelineStep>> {
+    // What the previous step produced, or why it cannot be processed further
+    let mut kind: std::result::Result<MediaKind, &str> = Ok(asset_kind);
+    let mut steps = Vec::with_capacity(operations.len());
+
+    for (i, operation) in operations.iter().enumerate() {
+        let step = || Msg::PipelineStep.with("step", i + 1).with("operation", operation.name());
+        let in_step = |e: AppError| match e {
+            AppError::BadRequest(m) => AppError::BadRequest(m.within(step())),
+            AppError::UnprocessableEntity(m) => AppError::UnprocessableEntity(m.within(step())),
+            AppError::Validation(errors) => AppError::Validation(
+                errors
+                    .into_iter()
+                    .map(|error| error.within(&format!("operations[{}]", i)))
+                    .collect(),
+            ),
+            e => e,
+        };
+        let input_kind = kind.map_err(|output| {
+            in_step(AppError::UnprocessableEntity(Msg::StepOutputNotProcessable.with("output", output)))
+        })?;
+
+        let step = match operation {
+            Operation::RemoveBg(params) => {
+                validate_remove_bg(params).map_err(in_step)?;
+                validate_remove_bg_for(params, input_kind).map_err(in_step)?;
+                kind = match input_kind {
+                    MediaKind::Image => Ok(MediaKind::Image),
+                    MediaKind::Video => match video::VideoOutput::for_format(params.output_format.as_deref()) {
+                        video::VideoOutput::WebmAlpha => Ok(MediaKind::Video),
+                        video::VideoOutput::PngSequence => Err("a zip of frames"),
+                    },
+                };
+                job_params::PipelineStep::RemoveBg(remove_bg_parameters(params))
+            }
+            Operation::ColorGrade(params) => {
+                if input_kind != MediaKind::Image {
+                    return Err(in_step(AppError::UnprocessableEntity(Msg::ColorGradeImagesOnly.into())));
+                }
+                validate_color_grade(params).map_err(in_step)?;
+                job_params::PipelineStep::ColorGrade(color_grade_parameters(params))
+            }
+            Operation::Convert(params) => {
+                validate_video_codec(params).map_err(in_step)?;
+                let output_format = validate_conversion(params, input_kind).map_err(in_step)?;
+                // Video to GIF leaves an image for any following step; AVIF
+                // is written but not read
+                if output_format == "gif" {
+                    kind = Ok(MediaKind::Image);
+                } else if output_format == "avif" {
+                    kind = Err("AVIF");
+                }
+                job_params::PipelineStep::Convert(conversion_parameters(params, &output_format))
+            }
+        };
+        steps.push(step);
+    }
+
+    Ok(steps)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AnalyzeRequest {
+    pub asset_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AnalyzeResponse {
+    /// `completed`, or `queued` when the image is too large to analyze in
+    /// the request
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<ImageAnalysis>,
+    /// The queued `analyze` job. Its result is the analysis as JSON, and a
+    /// later request for the same asset returns it directly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+}
+
+/// Per-channel histograms, luminance and dominant colors of an image.
+/// Analyses are cached per asset.
+#[utoipa::path(
+    post,
+    path = "/api/analyze",
+    tag = "processing",
+    request_body = AnalyzeRequest,
+    responses(
+        (status = 200, description = "Analysis", body = AnalyzeResponse),
+        (status = 202, description = "Large image; analysis job queued", body = AnalyzeResponse),
+        (status = 400, description = "Malformed ID", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Asset owned by another user, or email not verified", body = ErrorResponse),
+        (status = 404, description = "Asset not found", body = ErrorResponse),
+        (status = 422, description = "Asset is a video or cannot be decoded", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+        (status = 503, description = "Queue unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn analyze(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    Json(payload): Json<AnalyzeRequest>,
+) -> Result<(StatusCode, Json<AnalyzeResponse>)> {
+    let asset_id = Uuid::parse_str(&payload.asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidAssetId.into()))?;
+
+    let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+    if media_kind_from_filename(&asset.original_filename)? != MediaKind::Image {
+        return Err(AppError::UnprocessableEntity(Msg::AnalyzeImagesOnly.into()));
+    }
+
+    // A cache entry from an older analysis shape is recomputed
+    let cached = db::MediaAsset::analysis(&state.db, asset_id)
+        .await?
+        .and_then(|analysis| serde_json::from_value::<ImageAnalysis>(analysis).ok());
+    if let Some(analysis) = cached {
+        return Ok((StatusCode::OK, Json(AnalyzeResponse::completed(analysis))));
+    }
+
+    let sync_max_bytes = state.config.processing.analyze_sync_max_mb * 1024 * 1024;
+    if sync_max_bytes > 0 && (asset.size_bytes as u64) <= sync_max_bytes {
+        let location = asset
+            .result_location
+            .ok_or_else(|| AppError::UnprocessableEntity(Msg::AssetWithoutContent.into()))?;
+        let data = state.storage.load_bytes(&location.parse()?).await?;
+        let max_pixels = state.config.processing.max_image_pixels;
+        let analysis = tokio::task::spawn_blocking(move || ImageProcessor::analyze(&data, max_pixels))
+            .await
+            .map_err(|e| AppError::Internal(format!("Analysis task failed: {}", e)))??;
+
+        let value = serde_json::to_value(&analysis)
+            .map_err(|e| AppError::Internal(format!("Failed to encode analysis: {}", e)))?;
+        db::MediaAsset::store_analysis(&state.db, asset_id, &value).await?;
+        return Ok((StatusCode::OK, Json(AnalyzeResponse::completed(analysis))));
+    }
+
+    check_quota(&state, &auth_user, MediaKind::Image, 1).await?;
+
+    let job = db::Job::create(
+        &state.db,
+        auth_user.owner(),
+        vec![asset_id],
+        "analyze",
+        MediaKind::Image.as_str(),
+        job_parameters(json!({}), None, &request_id),
+        job_priority(&auth_user.tier, JobPriority::Normal),
+        None,
+    )
+    .await;
+    let job = created_or_released(&state, &auth_user, MediaKind::Image, 1, job).await?;
+
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!("Analysis job {} queued for user {}", job.id, auth_user.email);
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(AnalyzeResponse {
+            status: "queued".to_string(),
+            analysis: None,
+            job_id: Some(job.id.to_string()),
+        }),
+    ))
+}
+
+impl AnalyzeResponse {
+    fn completed(analysis: ImageAnalysis) -> Self {
+        Self {
+            status: "completed".to_string(),
+            analysis: Some(analysis),
+            job_id: None,
+        }
+    }
+}
+
+// ============================================================================
+// LUT Routes
+// ============================================================================
+
+#[derive(Serialize, ToSchema)]
+pub struct LutResponse {
+    pub id: String,
+    pub name: String,
+    pub size_bytes: i64,
+    pub created_at: String,
+    /// The organization whose library it is in, shared by its members
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<String>,
+}
+
+impl From<db::LutFile> for LutResponse {
+    fn from(lut: db::LutFile) -> Self {
+        Self {
+            id: lut.id.to_string(),
+            name: lut.name,
+            size_bytes: lut.size_bytes,
+            created_at: lut.created_at.to_rfc3339(),
+            organization_id: lut.organization_id.map(|id| id.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UploadedLutResponse {
+    #[serde(flatten)]
+    pub info: LutResponse,
+    /// What the parsed file contains
+    pub metadata: LutInfo,
+    /// Deprecated: pass `lut_id` instead. Kept while clients move off `lut_location`.
+    pub location: String,
+}
+
+/// Add a single .cube or .3dl file (<= configured size) to the caller's LUT
+/// library. Requests refer to it by the returned id.
+#[utoipa::path(
+    post,
+    path = "/api/lut",
+    tag = "luts",
+    request_body(content = FileUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Stored", body = UploadedLutResponse),
+        (status = 400, description = "No file", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 413, description = "File too large", body = ErrorResponse),
+        (status = 422, description = "Not a valid .cube LUT", body = ErrorResponse),
+        (status = 429, description = "Request limit reached", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn upload_lut(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<UploadedLutResponse>> {
+    while let Some(field) = multipart
+        .next_field()
+        .await?
+    {
+        if let Some(file_name_ref) = field.file_name() {
+            let file_name = file_name_ref.to_string();
+            let lower = file_name.to_lowercase();
+            let extension = if lower.ends_with(".cube") {
+                "cube"
+            } else if lower.ends_with(".3dl") {
+                "3dl"
+            } else {
+                return Err(AppError::BadRequest(Msg::LutFormatUnsupported.into()));
+            };
+
+            let data = field.bytes().await?;
+
+            let max_bytes = state.config.processing.lut_max_size_mb * 1024 * 1024;
+            if data.len() as u64 > max_bytes {
+                return Err(AppError::PayloadTooLarge(
+                    Msg::LutTooLarge
+                        .with("size_mb", data.len() as u64 / (1024 * 1024))
+                        .with("limit_mb", max_bytes / (1024 * 1024)),
+                ));
+            }
+
+            // Reject malformed LUTs now rather than when a grading job runs
+            let (parsed, lut_metadata) = Lut::from_bytes(&data, Some(extension))
+                .map_err(|e| AppError::UnprocessableEntity(Msg::InvalidLut.with("reason", e.to_string())))?;
+            let metadata = parsed.info(lut_metadata);
+
+            // Save LUT to storage (using same storage adapter)
+            let location = state
+                .storage
+                .save_bytes(&data, auth_user.id, &file_name)
+                .await
+                .map_err(|e| AppError::Internal(format!("Failed to save LUT: {:?}", e)))?;
+
+            let lut =
+                db::LutFile::create(&state.db, auth_user.owner(), &file_name, &location.to_string(), data.len() as i64)
+                    .await;
+            let lut = match lut {
+                Ok(lut) => lut,
+                Err(e) => {
+                    // Don't leave a file nothing refers to
+                    state.storage.delete(&location).await.ok();
+                    return Err(e.into());
+                }
+            };
+
+            tracing::info!("User {} uploaded LUT {} ({})", auth_user.email, lut.id, file_name);
+
+            return Ok(Json(UploadedLutResponse {
+                info: LutResponse::from(lut),
+                metadata,
+                location: location.to_string(),
+            }));
+        }
+    }
+
+    Err(AppError::BadRequest(Msg::NoLutFile.into()))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct GenerateLutRequest {
+    /// The shot before grading
+    pub source_asset_id: String,
+    /// The same shot after grading, at the same size
+    pub graded_asset_id: String,
+    /// Library name for the LUT; `.cube` is added if missing. Defaults to the
+    /// graded image's name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Receives a signed POST when the job completes or fails
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(flatten)]
+    pub schedule: JobSchedule,
+}
+
+/// Longest LUT name accepted
+const MAX_LUT_NAME_LEN: usize = 255;
+
+/// Build a 33-point 3D LUT reproducing a grade from a before/after pair of
+/// the same shot. The job's result is the .cube file, which also joins the
+/// caller's LUT library; the job reports its id as `generated_lut_id`.
+#[utoipa::path(
+    post,
+    path = "/api/luts/generate",
+    tag = "luts",
+    request_body = GenerateLutRequest,
+    responses(
+        (status = 200, description = "Job queued", body = JobResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Asset owned by another user, or email not verified", body = ErrorResponse),
+        (status = 404, description = "Asset not found", body = ErrorResponse),
+        (status = 422, description = "Invalid fields, or an asset is a video", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+        (status = 503, description = "Queue unavailable", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn generate_lut(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    Json(payload): Json<GenerateLutRequest>,
+) -> Result<Json<JobResponse>> {
+    let source_id = Uuid::parse_str(&payload.source_asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidSourceAssetId.into()))?;
+    let graded_id = Uuid::parse_str(&payload.graded_asset_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidGradedAssetId.into()))?;
+
+    let name = payload.name.as_deref().map(str::trim);
+    validate_lut_name(name)?;
+    validate_webhook(&state, payload.webhook_url.as_deref()).await?;
+    let (priority, run_after) = payload.schedule.place(&auth_user.tier)?;
+
+    for asset_id in [source_id, graded_id] {
+        let asset = verify_asset_ownership(&state.db, asset_id, auth_user.owner()).await?;
+        if media_kind_from_filename(&asset.original_filename)? != MediaKind::Image {
+            return Err(AppError::UnprocessableEntity(
+                Msg::LutFromVideo.with("filename", asset.original_filename.as_str()),
+            ));
+        }
+    }
+
+    // Both images count against the daily quota, as for any job
+    check_quota(&state, &auth_user, MediaKind::Image, 2).await?;
+
+    let name = name.map(|name| {
+        if name.to_lowercase().ends_with(".cube") {
+            name.to_string()
+        } else {
+            format!("{}.cube", name)
+        }
+    });
+    let job = db::Job::create(
+        &state.db,
+        auth_user.owner(),
+        vec![source_id, graded_id],
+        "lut_generate",
+        MediaKind::Image.as_str(),
+        job_parameters(
+            job_params::LutGenerateParams { name, generated_lut_id: None }.to_value(),
+            payload.webhook_url,
+            &request_id,
+        ),
+        priority,
+        run_after,
+    )
+    .await;
+    let job = created_or_released(&state, &auth_user, MediaKind::Image, 2, job).await?;
+
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!("LUT generation job {} queued for user {}", job.id, auth_user.email);
+
+    Ok(Json(JobResponse {
+        job_id: job.id.to_string(),
+        status: "queued".to_string(),
+    }))
+}
+
+fn validate_lut_name(name: Option<&str>) -> Result<()> {
+    let mut validator = Validator::new();
+    if let Some(name) = name {
+        if name.is_empty() {
+            validator.min_length("name", name, 1);
+        } else if name.chars().count() > MAX_LUT_NAME_LEN || name.contains(['/', '\\']) {
+            validator.push(FieldError::new(
+                "name",
+                code::INVALID_FORMAT,
+                Msg::LutNameInvalid.with("max", MAX_LUT_NAME_LEN),
+            ));
+        }
+    }
+    validator.finish()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/luts",
+    tag = "luts",
+    responses(
+        (status = 200, description = "The caller's LUTs", body = Vec<LutResponse>),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn list_luts(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<LutResponse>>> {
+    let luts = db::LutFile::list_for_user(&state.db, auth_user.owner()).await?;
+    Ok(Json(luts.into_iter().map(LutResponse::from).collect()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/luts/{lut_id}",
+    tag = "luts",
+    params(("lut_id" = Uuid, Path, description = "LUT ID")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "Not found", body = ErrorResponse),
+        (status = 409, description = "Queued jobs still use the LUT", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn delete_lut(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(lut_id): Path<String>,
+) -> Result<StatusCode> {
+    let lut_id = Uuid::parse_str(&lut_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidLutId.into()))?;
+
+    // Someone else's LUT looks the same as a missing one
+    let lut = db::LutFile::find_for_user(&state.db, lut_id, auth_user.owner())
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::LutNotFound.into()))?;
+
+    let active = db::Job::count_active_for_lut(&state.db, lut_id).await?;
+    if active > 0 {
+        return Err(AppError::Conflict(Msg::LutInUse.with("active", active)));
+    }
+
+    state.storage.delete(&lut.location.parse()?).await?;
+    db::LutFile::delete(&state.db, lut_id).await?;
+
+    tracing::info!("LUT {} deleted by user {}", lut_id, auth_user.email);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ============================================================================
+// Job Status Routes
+// ============================================================================
+
+#[derive(Serialize, ToSchema, Default)]
+pub struct JobStatusResponse {
+    pub job_id: String,
+    pub job_type: String,
+    /// The assets the job was submitted with, in order
+    pub asset_ids: Vec<String>,
+    /// Original filename of the first asset, while it still exists
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_filename: Option<String>,
+    pub status: String,
+    pub progress: u32,
+    /// Runs started so far, including automatic retries
+    pub attempts: i32,
+    pub max_attempts: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_url: Option<String>,
+    /// What the primary output at `result_url` turned out to be
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<JobResultResponse>,
+    /// When the result is deleted; downloads return 410 Gone after this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_expires_at: Option<String>,
+    /// Fetches the result without credentials, like the URL from
+    /// `/api/download/{job_id}/url`. Only on a single completed job's status;
+    /// once it has expired, poll the status again for a fresh one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url_expires_at: Option<String>,
+    pub created_at: String,
+    /// When a queued job becomes due: the `run_after` it was submitted with,
+    /// or the end of the wait before an automatic retry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduled_for: Option<String>,
+    /// Set when a queued job had waited past `QUEUED_STALE_AFTER_SECONDS`
+    /// without a worker taking it, as after a lost wake-up. It has just been
+    /// announced to the workers again; if it stays queued, no worker is
+    /// running.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stalled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Set with `error`, for clients to branch on: `decode_failed`,
+    /// `unsupported_format`, `storage_error`, `timeout`, `worker_lost` or
+    /// `processing_failed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_at: Option<String>,
+    /// Size a background removal with `trim` cropped its result to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trimmed_size: Option<ImageSize>,
+    /// Set when trimming found no subject and left the result uncropped
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub trim_empty: bool,
+    /// Set when a conversion's `target_size_kb` could not be met; the result
+    /// is encoded at the lowest quality tried
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub target_size_missed: bool,
+    /// The library LUT a `lut_generate` job created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generated_lut_id: Option<String>,
+    /// The organization it was submitted for, whose members share it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<String>,
+    /// Every file a completed job produced, primary first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<JobOutputResponse>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobOutputResponse {
+    /// Pass as `output` to `/api/download/{job_id}` to fetch just this file
+    pub index: i32,
+    pub filename: String,
+    pub size: i64,
+    pub content_type: String,
+}
+
+impl From<db::JobOutput> for JobOutputResponse {
+    fn from(output: db::JobOutput) -> Self {
+        Self {
+            index: output.output_index,
+            filename: output.filename,
+            size: output.size_bytes,
+            content_type: output.content_type,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobResultResponse {
+    /// Unset unless the output is an image or a video
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    pub size_bytes: i64,
+    /// File extension, e.g. `png` or `mp4`
+    pub format: String,
+    /// How long the worker took over the job
+    pub duration_ms: i64,
+}
+
+impl From<db::JobResult> for JobResultResponse {
+    fn from(result: db::JobResult) -> Self {
+        Self {
+            width: result.width,
+            height: result.height,
+            size_bytes: result.size_bytes,
+            format: result.format,
+            duration_ms: result.duration_ms,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ImageSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<db::Job> for JobStatusResponse {
+    fn from(job: db::Job) -> Self {
+        let failed = job.status == "failed";
+        let error = job.error_message.filter(|_| failed);
+        let error_code = job.error_code.filter(|_| failed);
+        let trimmed_size = job
+            .parameters
+            .get("trimmed_size")
+            .and_then(|v| serde_json::from_value(v.clone()).ok());
+        let trim_empty = job.parameters.get("trim_empty").and_then(|v| v.as_bool()).unwrap_or(false);
+        let target_size_missed = job
+            .parameters
+            .get("target_size_missed")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let generated_lut_id = job
+            .parameters
+            .get("generated_lut_id")
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
+        let scheduled_for = job.run_after.filter(|_| job.status == "queued").map(|t| t.to_rfc3339());
+
+        Self {
+            job_id: job.id.to_string(),
+            job_type: job.job_type,
+            asset_ids: serde_json::from_value(job.media_asset_ids).unwrap_or_default(),
+            asset_filename: None,
+            status: job.status,
+            progress: job.progress_percent as u32,
+            attempts: job.attempts,
+            max_attempts: job.max_attempts,
+            result_url: job.result_location,
+            result: job.result.map(|result| result.0.into()),
+            result_expires_at: job.result_expires_at.map(|t| t.to_rfc3339()),
+            download_url: None,
+            download_url_expires_at: None,
+            created_at: job.created_at.to_rfc3339(),
+            scheduled_for,
+            stalled: false,
+            completed_at: job.completed_at.map(|t| t.to_rfc3339()),
+            error,
+            error_code,
+            failed_at: job.failed_at.filter(|_| failed).map(|t| t.to_rfc3339()),
+            trimmed_size,
+            trim_empty,
+            target_size_missed,
+            generated_lut_id,
+            organization_id: job.organization_id.map(|id| id.to_string()),
+            outputs: Vec::new(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{job_id}",
+    tag = "jobs",
+    params(("job_id" = String, Path, description = "Job ID, or an ID only the live status map knows")),
+    responses(
+        (status = 200, description = "The job", body = JobStatusResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn get_job_status(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<JobStatusResponse>> {
+    let parsed = Uuid::parse_str(&job_id).ok();
+    let found = match parsed {
+        Some(job_uuid) => db::Job::find_by_id(&state.db, job_uuid).await?,
+        None => None,
+    };
+    // Older clients poll IDs the table never had, and a job is in the live
+    // status map a moment before its row is visible
+    let Some(job) = found else {
+        let status = state
+            .queue
+            .get_status(&job_id)
+            .await
+            .ok_or_else(|| AppError::NotFound(Msg::JobNotFound.into()))?;
+        let mut response = live_job_status(job_id, status);
+        // A UUID without a row is as likely a deleted job, whose owner can no
+        // longer be checked: its result and error stay hidden
+        if parsed.is_some() {
+            response.result_url = None;
+            response.error = None;
+        }
+        return Ok(Json(response));
+    };
+    let job_uuid = job.id;
+
+    // Verify ownership
+    if !auth_user.owner().reaches(job.user_id, job.organization_id) {
+        return Err(AppError::Forbidden(Msg::AccessDenied.into()));
+    }
+
+    // A job queued for too long most likely lost its wake-up; announce it again
+    let stalled = job.status == "queued"
+        && !state
+            .queue
+            .reannounce_stalled(&state.db, state.config.processing.queued_stale_after(), Some(job.id))
+            .await?
+            .is_empty();
+
+    let result = (job.status == "completed")
+        .then(|| StoredResult::of(job.clone()).ok())
+        .flatten();
+
+    let mut response = job_status(job, &state.queue).await;
+    response.stalled = stalled;
+    with_asset_filenames(&state.db, std::slice::from_mut(&mut response)).await?;
+    with_outputs(&state.db, std::slice::from_mut(&mut response)).await?;
+    if let Some(result) = result {
+        if let Some(signed) = status_download_url(&state, job_uuid, result, response.outputs.len()).await {
+            response.download_url = Some(signed.url);
+            response.download_url_expires_at = Some(signed.expires_at.to_rfc3339());
+        }
+    }
+    Ok(Json(response))
+}
+
+/// `/api/status/{job_id}`, the older name for `/api/jobs/{job_id}`
+#[utoipa::path(
+    get,
+    path = "/api/status/{job_id}",
+    tag = "jobs",
+    params(("job_id" = String, Path, description = "Job ID, or an ID only the live status map knows")),
+    responses(
+        (status = 200, description = "The job", body = JobStatusResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn get_status(
+    auth_user: auth::AuthUser,
+    state: State<AppState>,
+    job_id: Path<String>,
+) -> Result<Json<JobStatusResponse>> {
+    get_job_status(auth_user, state, job_id).await
+}
+
+/// Fill in `asset_filename` from each job's first asset, in one query
+async fn with_asset_filenames(db: &sqlx::PgPool, jobs: &mut [JobStatusResponse]) -> Result<()> {
+    let first_asset = |job: &JobStatusResponse| job.asset_ids.first().and_then(|id| Uuid::parse_str(id).ok());
+    let ids: Vec<Uuid> = jobs.iter().filter_map(first_asset).collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let filenames = db::MediaAsset::filenames(db, &ids).await?;
+    for job in jobs {
+        job.asset_filename = first_asset(job).and_then(|id| filenames.get(&id).cloned());
+    }
+    Ok(())
+}
+
+/// Fill in `outputs` for jobs whose result is still stored, in one query
+async fn with_outputs(db: &sqlx::PgPool, jobs: &mut [JobStatusResponse]) -> Result<()> {
+    let ids: Vec<Uuid> = jobs
+        .iter()
+        .filter(|job| job.result_url.is_some())
+        .filter_map(|job| Uuid::parse_str(&job.job_id).ok())
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut outputs: std::collections::HashMap<Uuid, Vec<JobOutputResponse>> = std::collections::HashMap::new();
+    for output in db::JobOutput::list_for_jobs(db, &ids).await? {
+        outputs.entry(output.job_id).or_default().push(output.into());
+    }
+    for job in jobs {
+        if let Some(found) = Uuid::parse_str(&job.job_id).ok().and_then(|id| outputs.remove(&id)) {
+            job.outputs = found;
+        }
+    }
+    Ok(())
+}
+
+/// A job's status with the latest progress of a running job. Workers record
+/// every change in the queue's status map but write the jobs table only every
+/// few percent; the table stays the source of truth for everything else, so a
+/// missing or stale live entry (after a restart, say) changes nothing.
+async fn job_status(job: db::Job, queue: &Queue) -> JobStatusResponse {
+    let mut response = JobStatusResponse::from(job);
+    if response.status == "processing" {
+        if let Some(JobStatus::Processing { progress }) = queue.get_status(&response.job_id).await {
+            response.progress = response.progress.max(progress.min(100));
+        }
+    }
+    response
+}
+
+/// A job known only from the live status map. Without its row there is no
+/// type, owner or history to show, and the creation time is unknown, so it
+/// reads as now.
+fn live_job_status(job_id: String, status: JobStatus) -> JobStatusResponse {
+    let response = JobStatusResponse {
+        job_id,
+        job_type: "unknown".to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        ..Default::default()
+    };
+    match status {
+        JobStatus::Queued => JobStatusResponse { status: "queued".to_string(), ..response },
+        JobStatus::Processing { progress } => JobStatusResponse {
+            status: "processing".to_string(),
+            progress: progress.min(100),
+            ..response
+        },
+        JobStatus::Completed { result_url } => JobStatusResponse {
+            status: "completed".to_string(),
+            progress: 100,
+            result_url: Some(result_url),
+            ..response
+        },
+        JobStatus::Failed { error } => JobStatusResponse {
+            status: "failed".to_string(),
+            error: Some(error),
+            ..response
+        },
+    }
+}
+
+/// Run a failed job again from scratch, with a fresh set of attempts
+#[utoipa::path(
+    post,
+    path = "/api/jobs/{job_id}/retry",
+    tag = "jobs",
+    params(("job_id" = Uuid, Path, description = "Job ID")),
+    responses(
+        (status = 200, description = "Queued again", body = JobResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+        (status = 409, description = "Only failed jobs can be retried", body = ErrorResponse),
+        (status = 429, description = "Quota or attempt limit reached", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn retry_job(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<JobResponse>> {
+    let job_uuid = Uuid::parse_str(&job_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidJobId.into()))?;
+
+    let job = db::Job::find_by_id(&state.db, job_uuid)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::JobNotFound.into()))?;
+
+    // Verify ownership
+    if !auth_user.owner().reaches(job.user_id, job.organization_id) {
+        return Err(AppError::Forbidden(Msg::AccessDenied.into()));
+    }
+
+    if job.status != "failed" {
+        return Err(AppError::Conflict(Msg::OnlyFailedRetried.with("status", job.status.as_str())));
+    }
+
+    // A retry occupies a concurrent slot like any new job, but it was
+    // already counted against the daily quota when first submitted, unless
+    // that was refunded when it failed
+    let status = quota::quota_status(&state.db, &state.config.quotas, auth_user.owner(), &auth_user.tier).await?;
+    status
+        .check_concurrent()
+        .map_err(|violation| quota_exceeded(violation, status.clone()))?;
+    let owner = db::Owner { user_id: job.user_id, organization_id: job.organization_id };
+    let recharge = match (job.quota_day, job.media_kind.as_deref().and_then(MediaKind::parse)) {
+        (None, Some(kind)) => Some((kind, job.media_asset_ids.as_array().map_or(0, Vec::len) as i64)),
+        _ => None,
+    };
+    if let Some((kind, assets)) = recharge {
+        let quotas = &state.config.quotas;
+        if let Err(violation) = quota::reserve(&state.db, quotas, owner, &auth_user.tier, kind, assets).await? {
+            return Err(quota_exceeded(violation, status));
+        }
+    }
+
+    // The status check is repeated in the update, so a concurrent retry loses cleanly
+    let Some(job) = db::Job::retry(&state.db, job_uuid).await? else {
+        if let Some((kind, assets)) = recharge {
+            quota::release(&state.db, owner, kind, assets).await;
+        }
+        return Err(AppError::Conflict(Msg::AlreadyRetrying.into()));
+    };
+
+    enqueue_job(&state, &job, &auth_user.tier).await?;
+
+    tracing::info!("Job {} retried by user {}", job.id, auth_user.email);
+
+    Ok(Json(JobResponse {
+        job_id: job.id.to_string(),
+        status: "queued".to_string(),
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ExtendResultRequest {
+    /// How much longer to keep the result
+    pub hours: u32,
+}
+
+/// Keep a completed job's result for longer. Pro only, and never past the
+/// tier's maximum retention counted from completion.
+#[utoipa::path(
+    post,
+    path = "/api/jobs/{job_id}/extend",
+    tag = "jobs",
+    params(("job_id" = Uuid, Path, description = "Job ID")),
+    request_body = ExtendResultRequest,
+    responses(
+        (status = 200, description = "New expiry", body = JobStatusResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+        (status = 409, description = "The job has no result", body = ErrorResponse),
+        (status = 410, description = "Result has expired", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn extend_result(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+    Json(payload): Json<ExtendResultRequest>,
+) -> Result<Json<JobStatusResponse>> {
+    let max = quota::max_result_retention(&state.config.quotas, &auth_user.tier).ok_or_else(|| {
+        AppError::Forbidden(Msg::ExtendNeedsPro.into())
+    })?;
+    Validator::new()
+        .range("hours", Some(i64::from(payload.hours)), 1..=max.num_hours())
+        .finish()?;
+
+    let (job_id, result) = owned_result(&state, &auth_user, &job_id).await?;
+    let (Some(completed_at), Some(expires_at)) = (result.completed_at, result.expires_at) else {
+        return Err(AppError::Conflict(Msg::ResultDoesNotExpire.into()));
+    };
+    let extended = quota::extended_expiry(
+        expires_at,
+        completed_at,
+        chrono::Duration::hours(i64::from(payload.hours)),
+        max,
+    )
+    .ok_or_else(|| {
+        AppError::Conflict(Msg::RetentionTooLong.with("max_hours", max.num_hours()))
+    })?;
+
+    // The expiry is checked again in the update, so a result that runs out
+    // meanwhile is not revived
+    let job = db::Job::extend_result(&state.db, job_id, extended)
+        .await?
+        .ok_or_else(result_gone)?;
+
+    tracing::info!(
+        "Result of job {} extended to {} by user {}",
+        job.id,
+        extended.to_rfc3339(),
+        auth_user.email
+    );
+
+    let mut response = JobStatusResponse::from(job);
+    with_asset_filenames(&state.db, std::slice::from_mut(&mut response)).await?;
+    with_outputs(&state.db, std::slice::from_mut(&mut response)).await?;
+    Ok(Json(response))
+}
+
+const JOB_STATUSES: &[&str] = &["queued", "processing", "completed", "failed"];
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListJobsQuery {
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub job_type: Option<String>,
+    #[serde(default)]
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobListResponse {
+    pub jobs: Vec<JobStatusResponse>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs",
+    tag = "jobs",
+    params(ListJobsQuery),
+    responses(
+        (status = 200, description = "The caller's jobs, newest first", body = JobListResponse),
+        (status = 400, description = "Unknown status or job type, or an empty time range", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn list_user_jobs(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<ListJobsQuery>,
+) -> Result<Json<JobListResponse>> {
+    let (filter, limit, offset) = job_filter(query)?;
+    let (jobs, total) = db::Job::list_for_user(&state.db, auth_user.owner(), &filter, limit, offset).await?;
+
+    let mut jobs: Vec<_> = jobs.into_iter().map(JobStatusResponse::from).collect();
+    with_asset_filenames(&state.db, &mut jobs).await?;
+    with_outputs(&state.db, &mut jobs).await?;
+
+    Ok(Json(JobListResponse {
+        jobs,
+        total,
+        limit,
+        offset,
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListJobEventsQuery {
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+}
+
+/// Something that happened to a job
+#[derive(Serialize, ToSchema)]
+pub struct JobEventResponse {
+    /// `queued`, `started`, `progress`, `retry_scheduled`, `worker_lost`,
+    /// `reannounced`, `completed` or `failed`
+    pub event_type: String,
+    /// Particulars of the event, such as the attempt, progress or error
+    pub detail: Option<serde_json::Value>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobEventListResponse {
+    pub events: Vec<JobEventResponse>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{job_id}/events",
+    tag = "jobs",
+    params(("job_id" = Uuid, Path, description = "Job ID"), ListJobEventsQuery),
+    responses(
+        (status = 200, description = "The job's history, oldest first", body = JobEventListResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn list_job_events(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+    Query(query): Query<ListJobEventsQuery>,
+) -> Result<Json<JobEventListResponse>> {
+    let job_uuid = Uuid::parse_str(&job_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidJobId.into()))?;
+
+    let job = db::Job::find_by_id(&state.db, job_uuid)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::JobNotFound.into()))?;
+
+    // Verify ownership
+    if !auth_user.owner().reaches(job.user_id, job.organization_id) {
+        return Err(AppError::Forbidden(Msg::AccessDenied.into()));
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let (events, total) = db::JobEvent::list(&state.db, job.id, limit, offset).await?;
+
+    let events = events
+        .into_iter()
+        .map(|event| JobEventResponse {
+            event_type: event.event_type,
+            detail: event.detail,
+            created_at: event.created_at.to_rfc3339(),
+        })
+        .collect();
+
+    Ok(Json(JobEventListResponse {
+        events,
+        total,
+        limit,
+        offset,
+    }))
+}
+
+/// Validate list filters and pagination, clamping the page size
+fn job_filter(query: ListJobsQuery) -> Result<(db::JobFilter, i64, i64)> {
+    if let Some(status) = query.status.as_deref() {
+        if !JOB_STATUSES.contains(&status) {
+            return Err(AppError::BadRequest(
+                Msg::UnknownStatus.with("value", status).with("supported", JOB_STATUSES),
+            ));
+        }
+    }
+    if let Some(job_type) = query.job_type.as_deref() {
+        if !db::JOB_TYPES.contains(&job_type) {
+            return Err(AppError::BadRequest(
+                Msg::UnknownJobType.with("value", job_type).with("supported", db::JOB_TYPES),
+            ));
+        }
+    }
+    if let (Some(since), Some(until)) = (query.since, query.until) {
+        if since >= until {
+            return Err(AppError::BadRequest(Msg::SinceAfterUntil.into()));
+        }
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let filter = db::JobFilter {
+        status: query.status,
+        job_type: query.job_type,
+        since: query.since,
+        until: query.until,
+    };
+
+    Ok((filter, limit, offset))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    /// Index of a single output, from the job's `outputs`
+    #[serde(default)]
+    pub output: Option<i32>,
+    /// `zip` for every output in one archive
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+impl DownloadQuery {
+    /// Whether a zip of every output was asked for
+    fn wants_zip(&self) -> Result<bool> {
+        let mut v = Validator::new();
+        v.one_of("format", self.format.as_deref(), &["zip"]);
+        v.range("output", self.output, 0..=i32::MAX);
+        if self.output.is_some() && self.format.is_some() {
+            v.push(FieldError::new("format", code::NOT_APPLICABLE, Msg::OneOutputOrZip));
+        }
+        v.finish()?;
+        Ok(self.format.is_some())
+    }
+}
+
+/// A job with a single output sends it; one with several sends them all in
+/// a zip unless `output` picks one
+#[utoipa::path(
+    get,
+    path = "/api/download/{job_id}",
+    tag = "jobs",
+    params(("job_id" = Uuid, Path, description = "Job ID"), DownloadQuery),
+    responses(
+        (status = 200, description = "The result file, or a zip of every output", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 206, description = "The requested byte range", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 304, description = "Unchanged since the cached copy"),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "No such job or output, or it has no result yet", body = ErrorResponse),
+        (status = 410, description = "Result has expired", body = ErrorResponse),
+        (status = 416, description = "Range not satisfiable", body = ErrorResponse),
+        (status = 422, description = "Unknown format, a negative output index, or both at once", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn download_result(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let zip = query.wants_zip()?;
+    let job = owned_completed_job(&state, &auth_user, &job_id).await?;
+    send_result(&state, job, query.output, zip, &headers).await
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DownloadUrlResponse {
+    pub url: String,
+    pub expires_at: String,
+}
+
+/// A URL for the job's result that needs no auth header: a presigned object
+/// URL with S3 storage, otherwise a signed `/api/files` link. It lasts
+/// `DOWNLOAD_URL_TTL_SECONDS`, unless the result expires first.
+#[utoipa::path(
+    get,
+    path = "/api/download/{job_id}/url",
+    tag = "jobs",
+    params(("job_id" = Uuid, Path, description = "Job ID")),
+    responses(
+        (status = 200, description = "Short-lived URL needing no credentials", body = DownloadUrlResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Owned by another user", body = ErrorResponse),
+        (status = 404, description = "No such job, or it has no result yet", body = ErrorResponse),
+        (status = 410, description = "Result has expired", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn download_url(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<DownloadUrlResponse>> {
+    let (job_id, result) = owned_result(&state, &auth_user, &job_id).await?;
+    let outputs = db::JobOutput::list(&state.db, job_id).await?.len();
+    let signed = sign_result_url(&state, job_id, &result, outputs, chrono::Utc::now()).await?;
+
+    Ok(Json(DownloadUrlResponse {
+        url: signed.url,
+        expires_at: signed.expires_at.to_rfc3339(),
+    }))
+}
+
+/// A credential-free URL for `result`, the stored result of `job_id` with
+/// `outputs` outputs
+async fn sign_result_url(
+    state: &AppState,
+    job_id: Uuid,
+    result: &StoredResult,
+    outputs: usize,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<SignedUrl> {
+    let mut expires_at = chrono::Duration::from_std(state.config.storage.download_url_ttl())
+        .ok()
+        .and_then(|ttl| now.checked_add_signed(ttl))
+        .ok_or_else(|| AppError::Internal("DOWNLOAD_URL_TTL_SECONDS is out of range".to_string()))?;
+    // The link must not outlive the result it points to
+    if let Some(result_expires_at) = result.expires_at {
+        expires_at = expires_at.min(result_expires_at);
+    }
+    let ttl = (expires_at - now).to_std().unwrap_or_default();
+
+    // A presigned URL names one object, which would leave out the rest of a
+    // multi-output result
+    let presigned = match outputs {
+        0 | 1 => state.storage.presigned_url(&result.location.parse()?, ttl).await?,
+        _ => None,
+    };
+    let url = match presigned {
+        Some(url) => url,
+        None => format!(
+            "/api/files/{}",
+            download_token::issue(&state.config.jwt_secret, job_id, expires_at)
+        ),
+    };
+    Ok(SignedUrl { url, expires_at })
+}
+
+/// `sign_result_url` for job status, reusing the URL handed out to earlier
+/// polls while enough of it is left. One that can't be signed is left out
+/// rather than failing the status.
+async fn status_download_url(
+    state: &AppState,
+    job_id: Uuid,
+    result: StoredResult,
+    outputs: usize,
+) -> Option<SignedUrl> {
+    let now = chrono::Utc::now();
+    if let Some(signed) = state.signed_urls.get(job_id, &result.location, result.expires_at, now) {
+        return Some(signed);
+    }
+    match sign_result_url(state, job_id, &result, outputs, now).await {
+        Ok(signed) => {
+            state.signed_urls.insert(job_id, result.location, result.expires_at, signed.clone(), now);
+            Some(signed)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to sign a download URL for job {}: {:?}", job_id, e);
+            None
+        }
+    }
+}
+
+/// Redeem a token from `download_url`. Public: the token is the credential.
+#[utoipa::path(
+    get,
+    path = "/api/files/{token}",
+    tag = "jobs",
+    params(("token" = String, Path, description = "Token from /api/download/{job_id}/url")),
+    responses(
+        (status = 200, description = "The result file, or a zip of every output", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 206, description = "The requested byte range", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 304, description = "Unchanged since the cached copy"),
+        (status = 403, description = "Invalid or expired token", body = ErrorResponse),
+        (status = 404, description = "The result is gone", body = ErrorResponse),
+        (status = 416, description = "Range not satisfiable", body = ErrorResponse),
+    ),
+)]
+pub async fn download_file(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let job_id = download_token::verify(&state.config.jwt_secret, &token, chrono::Utc::now())
+        .map_err(|e| AppError::Forbidden(Message::from(&e)))?;
+
+    // The result may have been replaced by a retry or expired since the token was issued
+    let job = db::Job::find_by_id(&state.db, job_id)
+        .await?
+        .filter(|job| job.status == "completed")
+        .ok_or_else(|| AppError::NotFound(Msg::ResultNotFound.into()))?;
+
+    send_result(&state, job, None, false, &headers).await
+}
+
+/// Where a completed job's result is stored, with the validators that
+/// conditional requests for it are checked against
+struct StoredResult {
+    location: String,
+    /// Quoted `ETag`; results saved before hashing was added have none
+    etag: Option<String>,
+    /// Name to send it under; without one, the stored file's own name
+    filename: Option<String>,
+    completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl StoredResult {
+    /// The stored result of a completed job, or 410 Gone once it has expired
+    fn of(job: db::Job) -> Result<Self> {
+        if job.result_expired(chrono::Utc::now()) {
+            return Err(result_gone());
+        }
+        let location = job
+            .result_location
+            .ok_or_else(|| AppError::NotFound(Msg::ResultNotFound.into()))?;
+
+        Ok(Self {
+            location,
+            etag: job.result_etag.as_deref().map(conditional::etag),
+            filename: None,
+            completed_at: job.completed_at,
+            expires_at: job.result_expires_at,
+        })
+    }
+
+    /// One of the job's outputs in place of its primary result
+    fn output(self, output: db::JobOutput) -> Self {
+        Self {
+            location: output.location,
+            etag: Some(conditional::etag(&output.etag)),
+            filename: Some(output.filename),
+            ..self
+        }
+    }
+}
+
+fn result_gone() -> AppError {
+    AppError::Gone(Msg::ResultExpired.into())
+}
+
+/// The caller's completed job and its stored result
+async fn owned_result(
+    state: &AppState,
+    auth_user: &auth::AuthUser,
+    job_id: &str,
+) -> Result<(Uuid, StoredResult)> {
+    let job = owned_completed_job(state, auth_user, job_id).await?;
+    Ok((job.id, StoredResult::of(job)?))
+}
+
+/// The caller's job, once it has completed
+async fn owned_completed_job(state: &AppState, auth_user: &auth::AuthUser, job_id: &str) -> Result<db::Job> {
+    let job_uuid = Uuid::parse_str(job_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidJobId.into()))?;
+
+    let job = db::Job::find_by_id(&state.db, job_uuid)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::JobNotFound.into()))?;
+
+    // Verify ownership
+    if !auth_user.owner().reaches(job.user_id, job.organization_id) {
+        return Err(AppError::Forbidden(Msg::AccessDenied.into()));
+    }
+
+    if job.status != "completed" {
+        return Err(AppError::BadRequest(Msg::JobNotCompleted.into()));
+    }
+
+    Ok(job)
+}
+
+/// Send output `output` of a completed job, or with `zip` all of them in
+/// one archive. With neither, a single output is sent as it is and several
+/// are zipped. Jobs completed before outputs were recorded have just their
+/// primary result, as output 0.
+async fn send_result(
+    state: &AppState,
+    job: db::Job,
+    output: Option<i32>,
+    zip: bool,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let job_id = job.id;
+    let result = StoredResult::of(job)?;
+    let mut outputs = db::JobOutput::list(&state.db, job_id).await?;
+
+    if zip || (output.is_none() && outputs.len() > 1) {
+        return zip_result(state, job_id, &result, outputs, headers).await;
+    }
+    let index = output.unwrap_or(0);
+    let result = match outputs.iter().position(|o| o.output_index == index) {
+        Some(at) => result.output(outputs.swap_remove(at)),
+        None if index == 0 && outputs.is_empty() => result,
+        None => return Err(AppError::NotFound(Msg::OutputNotFound.into())),
+    };
+    stream_result(state.storage.as_ref(), &result, headers).await
+}
+
+/// Every output of a job in a zip, built in the temp dir and streamed from
+/// there so memory use stays flat however large the outputs are
+async fn zip_result(
+    state: &AppState,
+    job_id: Uuid,
+    result: &StoredResult,
+    outputs: Vec<db::JobOutput>,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    // Archives are built afresh, so only the completion time validates them
+    let validators = result_validators(None, result.completed_at);
+    if conditional::not_modified(headers, None, result.completed_at) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
+    }
+
+    let files: Vec<(String, StorageLocation)> = if outputs.is_empty() {
+        let location: StorageLocation = result.location.parse()?;
+        vec![(location.file_name().to_string(), location)]
+    } else {
+        let mut files = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            files.push((output.filename, output.location.parse()?));
+        }
+        files
+    };
+    let names = archive::unique_entry_names(files.iter().map(|(name, _)| name.as_str()));
+    let mut entries = Vec::with_capacity(files.len());
+    for (name, (_, location)) in names.into_iter().zip(files) {
+        entries.push(archive::StreamedEntry {
+            name,
+            size: state.storage.size(&location).await?,
+            content: state.storage.open_stream(&location, None).await?,
+        });
+    }
+
+    let path = std::path::Path::new(&state.config.processing.temp_dir).join(format!("download_{}.zip", Uuid::new_v4()));
+    let zipped = archive::zip_streams(entries, &path).await;
+    let opened = match zipped {
+        Ok(()) => tokio::fs::File::open(&path).await,
+        Err(e) => Err(e),
+    };
+    // The open file stays readable once unlinked
+    let _ = tokio::fs::remove_file(&path).await;
+    let file = opened.map_err(|e| AppError::Internal(format!("Failed to build archive: {}", e)))?;
+    let size = file.metadata().await.map_err(|e| AppError::Internal(e.to_string()))?.len();
+
+    Ok((
+        validators,
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, attachment_disposition(&format!("{}.zip", job_id))),
+            (header::CONTENT_LENGTH, size.to_string()),
+        ],
+        Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+/// Clients may keep a result but must revalidate before reusing it, which
+/// the ETag makes a cheap 304
+const RESULT_CACHE_CONTROL: &str = "private, no-cache";
+
+/// `Cache-Control`, with `ETag` and `Last-Modified` when there are values for them
+fn result_validators(etag: Option<&str>, completed_at: Option<chrono::DateTime<chrono::Utc>>) -> HeaderMap {
+    let mut validators = HeaderMap::new();
+    validators.insert(header::CACHE_CONTROL, header::HeaderValue::from_static(RESULT_CACHE_CONTROL));
+    if let Some(etag) = etag {
+        validators.insert(header::ETAG, etag.parse().expect("ETag is a hex digest"));
+    }
+    if let Some(completed_at) = completed_at {
+        let last_modified = conditional::http_date(completed_at).parse().expect("HTTP date is ASCII");
+        validators.insert(header::LAST_MODIFIED, last_modified);
+    }
+    validators
+}
+
+/// `Content-Disposition` for downloading a file as `filename`. The name can
+/// come from an upload, so the quoted `filename` keeps only plain ASCII, with
+/// quotes, backslashes and semicolons replaced, and the exact name goes in
+/// `filename*` (RFC 6266) percent-encoded.
+fn attachment_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | ';' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_'
+            | b'`' | b'|' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+/// Send a stored result as an attachment, streamed from storage. A `Range`
+/// header gets just that window back as 206 Partial Content, so players can seek.
+/// A client whose cached copy is current gets 304 without storage being touched.
+async fn stream_result(
+    storage: &dyn Storage,
+    result: &StoredResult,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let validators = result_validators(result.etag.as_deref(), result.completed_at);
+    if conditional::not_modified(headers, result.etag.as_deref(), result.completed_at) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
+    }
+
+    let location: StorageLocation = result.location.parse()?;
+    let size = storage.size(&location).await?;
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => match byte_range::parse(value, size) {
+            Ok(range) => range,
+            Err(byte_range::Unsatisfiable) => {
+                return Ok((
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                )
+                    .into_response())
+            }
+        },
+        None => None,
+    };
+    let stream = storage.open_stream(&location, range).await?;
+
+    // Determine content type from filename
+    let filename = result.filename.as_deref().unwrap_or(location.file_name());
+    let content_type = formats::content_type(filename);
+
+    let disposition = attachment_disposition(filename);
+
+    let (status, length) = match range {
+        Some(range) => (StatusCode::PARTIAL_CONTENT, range.size()),
+        None => (StatusCode::OK, size),
+    };
+    let mut response = (
+        status,
+        validators,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response();
+    if let Some(range) = range {
+        let content_range = range.content_range(size).parse().expect("Content-Range is ASCII");
+        response.headers_mut().insert(header::CONTENT_RANGE, content_range);
+    }
+    Ok(response)
+}
+
+// ============================================================================
+// API Key Routes
+// ============================================================================
+
+const MAX_API_KEY_NAME_LEN: usize = 100;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub name: String,
+    pub created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<String>,
+    pub revoked: bool,
+}
+
+impl From<db::ApiKey> for ApiKeyResponse {
+    fn from(key: db::ApiKey) -> Self {
+        Self {
+            id: key.id.to_string(),
+            name: key.name,
+            created_at: key.created_at.to_rfc3339(),
+            last_used_at: key.last_used_at.map(|t| t.to_rfc3339()),
+            revoked: key.revoked,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedApiKeyResponse {
+    #[serde(flatten)]
+    pub info: ApiKeyResponse,
+    /// The plaintext key. Only a hash is stored, so this is the one chance to copy it.
+    pub key: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/keys",
+    tag = "api_keys",
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 200, description = "The key; its secret is shown only this once", body = CreatedApiKeyResponse),
+        (status = 400, description = "Missing name", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn create_api_key(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreatedApiKeyResponse>> {
+    let name = payload.name.trim();
+    if name.is_empty() || name.len() > MAX_API_KEY_NAME_LEN {
+        return Err(AppError::BadRequest(Msg::KeyNameLength.with("max", MAX_API_KEY_NAME_LEN)));
+    }
+
+    let generated = auth::generate_api_key();
+    let key = db::ApiKey::create(&state.db, generated.id, auth_user.id, name, &generated.hash).await?;
+
+    tracing::info!("API key {} created for user {}", key.id, auth_user.email);
+
+    Ok(Json(CreatedApiKeyResponse {
+        info: ApiKeyResponse::from(key),
+        key: generated.key,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys",
+    tag = "api_keys",
+    responses(
+        (status = 200, description = "The caller's keys, without secrets", body = Vec<ApiKeyResponse>),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn list_api_keys(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ApiKeyResponse>>> {
+    let keys = db::ApiKey::list_for_user(&state.db, auth_user.id).await?;
+    Ok(Json(keys.into_iter().map(ApiKeyResponse::from).collect()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{key_id}",
+    tag = "api_keys",
+    params(("key_id" = Uuid, Path, description = "API key ID")),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "Not found", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn revoke_api_key(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(key_id): Path<String>,
+) -> Result<StatusCode> {
+    let key_id = Uuid::parse_str(&key_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidKeyId.into()))?;
+
+    // Someone else's key looks the same as a missing one
+    if !db::ApiKey::revoke(&state.db, key_id, auth_user.id).await? {
+        return Err(AppError::NotFound(Msg::ApiKeyNotFound.into()));
+    }
+
+    tracing::info!("API key {} revoked by user {}", key_id, auth_user.email);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ============================================================================
+// Organization Routes
+// ============================================================================
+
+const MAX_ORGANIZATION_NAME_LEN: usize = 100;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct InviteMemberRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AcceptInviteRequest {
+    /// Token from the emailed invitation
+    pub token: String,
+}
+
+/// An organization the caller is in. Send its id as `X-Organization-Id`, or
+/// switch a token to it, to upload, submit jobs and add LUTs for it.
+#[derive(Serialize, ToSchema)]
+pub struct OrganizationResponse {
+    pub id: String,
+    pub name: String,
+    /// The caller's role: `owner` or `member`. Only owners invite.
+    pub role: String,
+    pub joined_at: String,
+}
+
+impl From<db::Membership> for OrganizationResponse {
+    fn from(membership: db::Membership) -> Self {
+        Self {
+            id: membership.organization_id.to_string(),
+            name: membership.name,
+            role: membership.role,
+            joined_at: membership.joined_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OrganizationMemberResponse {
+    pub user_id: String,
+    pub email: String,
+    pub role: String,
+    pub joined_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InviteResponse {
+    pub email: String,
+    pub expires_at: String,
+}
+
+/// Create an organization with the caller as its owner
+#[utoipa::path(
+    post,
+    path = "/api/organizations",
+    tag = "organizations",
+    request_body = CreateOrganizationRequest,
+    responses(
+        (status = 200, description = "The organization", body = OrganizationResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn create_organization(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateOrganizationRequest>,
+) -> Result<Json<OrganizationResponse>> {
+    let name = payload.name.trim();
+    let mut validator = Validator::new();
+    if name.is_empty() {
+        validator.push(FieldError::new("name", code::REQUIRED, Msg::Required));
+    } else if name.chars().count() > MAX_ORGANIZATION_NAME_LEN {
+        validator.push(FieldError::new(
+            "name",
+            code::INVALID_FORMAT,
+            Msg::NameTooLong.with("max", MAX_ORGANIZATION_NAME_LEN),
+        ));
+    }
+    validator.finish()?;
+
+    let membership = db::Organization::create(&state.db, name, auth_user.id).await?;
+    tracing::info!("Organization {} created by user {}", membership.organization_id, auth_user.email);
+
+    Ok(Json(OrganizationResponse::from(membership)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/organizations",
+    tag = "organizations",
+    responses(
+        (status = 200, description = "Organizations the caller is in", body = Vec<OrganizationResponse>),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn list_organizations(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<OrganizationResponse>>> {
+    let memberships = db::Organization::memberships(&state.db, auth_user.id).await?;
+    Ok(Json(memberships.into_iter().map(OrganizationResponse::from).collect()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/organizations/{org_id}/members",
+    tag = "organizations",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    responses(
+        (status = 200, description = "Members, owners first", body = Vec<OrganizationMemberResponse>),
+        (status = 400, description = "Malformed ID", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "No such organization, or the caller isn't in it", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn list_organization_members(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+) -> Result<Json<Vec<OrganizationMemberResponse>>> {
+    let membership = organization_membership(&state, &auth_user, &org_id).await?;
+    let members = db::Organization::members(&state.db, membership.organization_id).await?;
+    Ok(Json(
+        members
+            .into_iter()
+            .map(|member| OrganizationMemberResponse {
+                user_id: member.user_id.to_string(),
+                email: member.email,
+                role: member.role,
+                joined_at: member.joined_at.to_rfc3339(),
+            })
+            .collect(),
+    ))
+}
+
+/// Email an invitation to join the organization. Owners only. A new
+/// invitation to the same address replaces the earlier one.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/{org_id}/invites",
+    tag = "organizations",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    request_body = InviteMemberRequest,
+    responses(
+        (status = 200, description = "Invitation sent", body = InviteResponse),
+        (status = 400, description = "Malformed ID", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "The caller is not an owner", body = ErrorResponse),
+        (status = 404, description = "No such organization, or the caller isn't in it", body = ErrorResponse),
+        (status = 409, description = "The address already belongs to a member", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+        (status = 429, description = "Too many invitations", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn invite_member(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<InviteMemberRequest>,
+) -> Result<Json<InviteResponse>> {
+    let membership = organization_membership(&state, &auth_user, &org_id).await?;
+    if membership.role != "owner" {
+        return Err(AppError::Forbidden(Msg::OwnersOnlyInvite.into()));
+    }
+    let email = payload.email.trim();
+    Validator::new().email("email", email).finish()?;
+
+    let members = db::Organization::members(&state.db, membership.organization_id).await?;
+    if members.iter().any(|member| member.email.eq_ignore_ascii_case(email)) {
+        return Err(AppError::Conflict(Msg::AlreadyMember.with("email", email)));
+    }
+    // Each one emails an address the caller chose
+    throttle(
+        &state,
+        &format!("organization-invite:{}", auth_user.id),
+        state.config.rate_limits.resend_verification_attempts,
+    )
+    .await?;
+
+    let (token, token_hash) = auth::generate_email_token();
+    let ttl_hours = state.config.mail.organization_invite_ttl_hours;
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(ttl_hours as i64);
+    db::Organization::store_invite(&state.db, membership.organization_id, email, &token_hash, auth_user.id, expires_at)
+        .await?;
+
+    let invitation = Email {
+        to: email.to_string(),
+        subject: format!("Join {} on MediaForge", membership.name),
+        body: format!(
+            "{} invited you to share uploads and LUTs in {} on MediaForge. Sign in as {} and open this \
+             link to accept:\n\n\
+             {}?token={}\n\n\
+             The invitation works once, for {} hours. If you weren't expecting it, ignore this email.\n",
+            auth_user.email, membership.name, email, state.config.mail.organization_invite_url, token, ttl_hours
+        ),
+    };
+    send_in_background(&state, invitation, auth_user.id);
+    tracing::info!("User {} invited {} to organization {}", auth_user.email, email, membership.organization_id);
+
+    Ok(Json(InviteResponse {
+        email: email.to_string(),
+        expires_at: expires_at.to_rfc3339(),
+    }))
+}
+
+/// Join an organization with the token from an invitation. The caller's
+/// confirmed email must be the address it was sent to.
+#[utoipa::path(
+    post,
+    path = "/api/organizations/invites/accept",
+    tag = "organizations",
+    request_body = AcceptInviteRequest,
+    responses(
+        (status = 200, description = "Joined", body = OrganizationResponse),
+        (status = 400, description = "Unknown, used or expired, or for another address", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "The caller's email is not verified", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn accept_invite(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<AcceptInviteRequest>,
+) -> Result<Json<OrganizationResponse>> {
+    // An unconfirmed address could be anyone's
+    let user = current_user(&state, &auth_user).await?;
+    if !user.email_verified {
+        return Err(AppError::Forbidden(Msg::ConfirmEmailForInvites.into()));
+    }
+
+    let token_hash = auth::hash_email_token(payload.token.trim());
+    let membership = db::Organization::accept_invite(&state.db, &token_hash, user.id, &user.email)
+        .await?
+        .ok_or_else(|| {
+            AppError::BadRequest(Msg::InvitationInvalid.into())
+        })?;
+    tracing::info!("User {} joined organization {}", user.email, membership.organization_id);
+
+    Ok(Json(OrganizationResponse::from(membership)))
+}
+
+/// A token acting for an organization the caller is in, or for nobody but
+/// the caller when `organization_id` is null. `X-Organization-Id` overrides
+/// it per request.
+#[utoipa::path(
+    post,
+    path = "/api/auth/switch-organization",
+    tag = "auth",
+    request_body = auth::SwitchOrganizationRequest,
+    responses(
+        (status = 200, description = "A token for the chosen scope", body = auth::AuthResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "No such organization, or the caller isn't in it", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn switch_organization(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<auth::SwitchOrganizationRequest>,
+) -> Result<Json<auth::AuthResponse>> {
+    if let Some(organization_id) = payload.organization_id {
+        organization_membership(&state, &auth_user, &organization_id.to_string()).await?;
+    }
+    let user = current_user(&state, &auth_user).await?;
+    auth_response(&state, user, payload.organization_id)
+}
+
+/// The caller's membership of the organization. Organizations they aren't
+/// in look the same as missing ones.
+async fn organization_membership(
+    state: &AppState,
+    auth_user: &auth::AuthUser,
+    org_id: &str,
+) -> Result<db::Membership> {
+    let org_id = Uuid::parse_str(org_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidOrganizationId.into()))?;
+    db::Organization::membership(&state.db, org_id, auth_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::OrganizationNotFound.into()))
+}
+
+// ============================================================================
+// Quota Routes
+// ============================================================================
+
+/// The caller's usage against each limit of their tier, and when the daily
+/// counts reset. Limits of `null` are unlimited.
+#[utoipa::path(
+    get,
+    path = "/api/quota",
+    tag = "jobs",
+    responses(
+        (status = 200, description = "Usage against the caller's tier limits", body = QuotaStatus),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn get_quota(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<QuotaStatus>> {
+    let status = quota::quota_status(&state.db, &state.config.quotas, auth_user.owner(), &auth_user.tier).await?;
+    Ok(Json(status))
+}
+
+/// Longest period statistics are aggregated over
+const MAX_STATS_DAYS: i64 = 365;
+
+/// Period statistics cover unless given, ending now
+const DEFAULT_STATS_DAYS: i64 = 30;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    /// Start of the period, inclusive. Defaults to 30 days before `to`.
+    #[serde(default)]
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// End of the period, exclusive. Defaults to now.
+    #[serde(default)]
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The caller's jobs and uploads over a period of at most a year: totals,
+/// a breakdown per job type, and a series per UTC day for charting
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "jobs",
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "The caller's usage over the period", body = UsageStats),
+        (status = 400, description = "An empty or inverted period, or one longer than a year", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn get_stats(
+    auth_user: auth::AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<UsageStats>> {
+    let (from, to) = stats_period(query.from, query.to, chrono::Utc::now())?;
+    let stats = stats::usage_stats(&state.db, Some(auth_user.id), from, to).await?;
+    Ok(Json(stats))
+}
+
+/// Fill in the period's defaults and check it is in order and no longer than allowed
+fn stats_period(
+    from: Option<chrono::DateTime<chrono::Utc>>,
+    to: Option<chrono::DateTime<chrono::Utc>>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<(chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>)> {
+    let to = to.unwrap_or(now);
+    let from = from.unwrap_or(to - chrono::Duration::days(DEFAULT_STATS_DAYS));
+    if from >= to {
+        return Err(AppError::BadRequest(Msg::FromAfterTo.into()));
+    }
+    if to - from > chrono::Duration::days(MAX_STATS_DAYS) {
+        return Err(AppError::BadRequest(Msg::PeriodTooLong.with("max_days", MAX_STATS_DAYS)));
+    }
+    Ok((from, to))
+}
+
+// ============================================================================
+// Admin Routes
+// ============================================================================
+
+const TIERS: &[&str] = &["free", "pro"];
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsersQuery {
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub email_prefix: Option<String>,
+    #[serde(default)]
+    pub tier: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminUserResponse {
+    pub id: String,
+    pub email: String,
+    pub tier: String,
+    pub daily_quota: i32,
+    pub concurrent_jobs_allowed: i32,
+    pub is_admin: bool,
+    pub created_at: String,
+}
+
+impl From<db::User> for AdminUserResponse {
+    fn from(user: db::User) -> Self {
+        Self {
+            id: user.id.to_string(),
+            email: user.email,
+            tier: user.subscription_tier,
+            daily_quota: user.daily_quota,
+            concurrent_jobs_allowed: user.concurrent_jobs_allowed,
+            is_admin: user.is_admin,
+            created_at: user.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserListResponse {
+    pub users: Vec<AdminUserResponse>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/users",
+    tag = "admin",
+    params(ListUsersQuery),
+    responses(
+        (status = 200, description = "Matching users", body = UserListResponse),
+        (status = 400, description = "Unknown tier", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn admin_list_users(
+    _admin: auth::AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<Json<UserListResponse>> {
+    if let Some(tier) = query.tier.as_deref() {
+        validate_tier(tier)?;
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let filter = db::UserFilter {
+        email_prefix: query.email_prefix.filter(|p| !p.is_empty()),
+        tier: query.tier,
+    };
+
+    let (users, total) = db::User::list(&state.db, &filter, limit, offset).await?;
+
+    Ok(Json(UserListResponse {
+        users: users.into_iter().map(AdminUserResponse::from).collect(),
+        total,
+        limit,
+        offset,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateTierRequest {
+    pub tier: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UpdateTierResponse {
+    pub user: AdminUserResponse,
+    /// When the change reaches the user's requests
+    pub note: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{user_id}/tier",
+    tag = "admin",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    request_body = UpdateTierRequest,
+    responses(
+        (status = 200, description = "Tier changed", body = UpdateTierResponse),
+        (status = 400, description = "Malformed ID or request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+        (status = 422, description = "Invalid fields", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn admin_update_tier(
+    admin: auth::AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<UpdateTierRequest>,
+) -> Result<Json<UpdateTierResponse>> {
+    let user_id = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::BadRequest(Msg::InvalidUserId.into()))?;
+    validate_tier(&payload.tier)?;
+
+    let user = db::User::update_tier(&state.db, user_id, &payload.tier)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::UserNotFound.into()))?;
+
+    tracing::info!(
+        "Admin {} set tier of user {} to {}",
+        admin.0.email,
+        user.id,
+        user.subscription_tier
+    );
+
+    Ok(Json(UpdateTierResponse {
+        user: AdminUserResponse::from(user),
+        note: format!(
+            "Applies immediately to API key requests and to tokens issued from now on. \
+             Tokens issued earlier keep the old tier until the user logs in again or they \
+             expire (at most {} days).",
+            auth::TOKEN_TTL_DAYS
+        ),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminJobResponse {
+    #[serde(flatten)]
+    pub job: JobStatusResponse,
+    pub user_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminJobListResponse {
+    pub jobs: Vec<AdminJobResponse>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Recent jobs across every user, newest first, with the same filters as `GET /api/jobs`
+#[utoipa::path(
+    get,
+    path = "/api/admin/jobs",
+    tag = "admin",
+    params(ListJobsQuery),
+    responses(
+        (status = 200, description = "Jobs of every user", body = AdminJobListResponse),
+        (status = 400, description = "Unknown status or job type, or an empty time range", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn admin_list_jobs(
+    _admin: auth::AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<ListJobsQuery>,
+) -> Result<Json<AdminJobListResponse>> {
+    let (filter, limit, offset) = job_filter(query)?;
+    let (jobs, total) = db::Job::list_all(&state.db, &filter, limit, offset).await?;
+
+    Ok(Json(AdminJobListResponse {
+        jobs: jobs
+            .into_iter()
+            .map(|job| AdminJobResponse {
+                user_id: job.user_id.to_string(),
+                job: JobStatusResponse::from(job),
+            })
+            .collect(),
+        total,
+        limit,
+        offset,
+    }))
+}
+
+/// Accounts listed in the per-user breakdown of `GET /api/admin/stats`
+const STATS_USER_LIMIT: i64 = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AdminStatsQuery {
+    /// Start of the period, inclusive. Defaults to 30 days before `to`.
+    #[serde(default)]
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// End of the period, exclusive. Defaults to now.
+    #[serde(default)]
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    /// Also break the period down per account
+    #[serde(default)]
+    pub by_user: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminStatsResponse {
+    #[serde(flatten)]
+    pub stats: UsageStats,
+    /// With `by_user`: the 100 accounts with the most jobs in the period,
+    /// then the most uploaded bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users: Option<Vec<UserStats>>,
+}
+
+/// Jobs and uploads of every user over a period, as `GET /api/stats` reports
+/// them for one
+#[utoipa::path(
+    get,
+    path = "/api/admin/stats",
+    tag = "admin",
+    params(AdminStatsQuery),
+    responses(
+        (status = 200, description = "Usage of every user over the period", body = AdminStatsResponse),
+        (status = 400, description = "An empty or inverted period, or one longer than a year", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn admin_stats(
+    _admin: auth::AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<AdminStatsQuery>,
+) -> Result<Json<AdminStatsResponse>> {
+    let (from, to) = stats_period(query.from, query.to, chrono::Utc::now())?;
+    let stats = stats::usage_stats(&state.db, None, from, to).await?;
+    let users = if query.by_user {
+        let usage = db::Job::usage_by_user(&state.db, from, to, STATS_USER_LIMIT).await?;
+        Some(usage.into_iter().map(UserStats::from).collect())
+    } else {
+        None
+    };
+
+    Ok(Json(AdminStatsResponse { stats, users }))
+}
+
+/// Move local files saved before the per-account layout into it and update
+/// the rows that refer to them. Safe to repeat; files already laid out and
+/// objects in S3 are left alone.
+#[utoipa::path(
+    post,
+    path = "/api/admin/storage/relocate",
+    tag = "admin",
+    responses(
+        (status = 200, description = "What was moved", body = RelocationSummary),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn admin_relocate_storage(
+    admin: auth::AdminUser,
+    State(state): State<AppState>,
+) -> Result<Json<RelocationSummary>> {
+    let local = crate::services::LocalStorage::new(&state.config.storage.local_path);
+    let summary = relocation::relocate_local_objects(&state.db, &local).await?;
+
+    tracing::info!(
+        "Admin {} relocated {} stored file(s); {} missing, {} failed",
+        admin.0.email,
+        summary.relocated,
+        summary.missing,
+        summary.failed
+    );
+
+    Ok(Json(summary))
+}
+
+fn validate_tier(tier: &str) -> Result<()> {
+    if !TIERS.contains(&tier) {
+        return Err(AppError::BadRequest(Msg::UnknownTier.with("value", tier).with("supported", TIERS)));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Helper Functions
+// ============================================================================
+
+/// The asset, if `owner` reaches it: the caller's own, or one uploaded for
+/// the organization they act for
+#[tracing::instrument(skip_all)]
+async fn verify_asset_ownership(
+    db: &sqlx::PgPool,
+    asset_id: Uuid,
+    owner: db::Owner,
+) -> Result<db::MediaAsset> {
+    // Deleted assets are out of reach until restored
+    let asset = sqlx::query_as::<_, db::MediaAsset>("SELECT * FROM media_assets WHERE id = $1 AND deleted_at IS NULL")
+        .bind(asset_id)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound(Msg::AssetNotFound.into()))?;
+
+    if !owner.reaches(asset.user_id, asset.organization_id) {
+        return Err(AppError::Forbidden(Msg::AccessDenied.into()));
+    }
+
+    Ok(asset)
+}
+
+/// Webhooks need a signing secret, an http(s) URL and a public target
+async fn validate_webhook(state: &AppState, url: Option<&str>) -> Result<()> {
+    let Some(url) = url else {
+        return Ok(());
+    };
+    if state.config.webhook_secret.is_none() {
+        return Err(AppError::UnprocessableEntity(Msg::WebhooksDisabled.into()));
+    }
+    webhook::validate_url(url)
+        .await
+        .map_err(|e| AppError::BadRequest(Message::from(&e)))?;
+    Ok(())
+}
+
+/// Add what every job records besides its own options: the completion
+/// webhook, if any, and the ID of the request that created it so the
+/// worker's logs can be matched to it. When spans are exported the trace
+/// context goes along too, so the worker's spans join the request's trace.
+fn job_parameters(
+    mut parameters: serde_json::Value,
+    webhook_url: Option<String>,
+    request_id: &RequestId,
+) -> serde_json::Value {
+    if let Some(url) = webhook_url {
+        parameters["webhook_url"] = json!(url);
+    }
+    parameters["request_id"] = json!(request_id.0);
+    if let Some(trace_context) = crate::otel::current_trace_context() {
+        parameters["trace_context"] = trace_context;
+    }
+    parameters
+}
+
+/// Wake a worker for a new or retried job and count it as enqueued
+async fn enqueue_job(state: &AppState, job: &db::Job, tier: &str) -> Result<()> {
+    state
+        .queue
+        .enqueue(&state.db, crate::services::JobMessage {
+            job_id: job.id.to_string(),
+        })
+        .await
+        .map_err(|_| AppError::ServiceUnavailable(Msg::QueueUnavailable.into()))?;
+
+    metrics::counter!(telemetry::JOBS_ENQUEUED, &telemetry::job_labels(&job.job_type, tier)).increment(1);
+    Ok(())
+}
+
+/// When and how urgently a new job runs, accepted by the requests that
+/// queue one
+#[derive(Deserialize, ToSchema)]
+pub struct JobSchedule {
+    /// `low`, `normal` (the default) or `high`, ordering the job among the
+    /// account's others. `high` is for pro accounts.
+    #[serde(default)]
+    pub priority: Option<String>,
+    /// RFC 3339 time the job is not started before, at most 7 days ahead. It
+    /// counts toward the daily quota of the day it was submitted.
+    #[serde(default)]
+    pub run_after: Option<String>,
+}
+
+/// Furthest ahead `run_after` may be
+const MAX_SCHEDULE_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl JobSchedule {
+    /// The queue priority and start time for a job of a user on `tier`
+    fn place(&self, tier: &str) -> Result<(i32, Option<chrono::DateTime<chrono::Utc>>)> {
+        let mut validator = Validator::new();
+        validator.one_of("priority", self.priority.as_deref(), &["low", "normal", "high"]);
+        let level = match self.priority.as_deref().map(str::to_lowercase).as_deref() {
+            Some("low") => JobPriority::Low,
+            Some("high") => JobPriority::High,
+            _ => JobPriority::Normal,
+        };
+        if level == JobPriority::High && tier != "pro" {
+            validator.push(FieldError::new(
+                "priority",
+                code::NOT_APPLICABLE,
+                Msg::PriorityProOnly,
+            ));
+        }
+
+        let now = chrono::Utc::now();
+        let mut run_after = None;
+        if let Some(raw) = self.run_after.as_deref() {
+            match chrono::DateTime::parse_from_rfc3339(raw) {
+                Ok(at) if at > now + chrono::Duration::days(MAX_SCHEDULE_DAYS) => {
+                    validator.push(FieldError::new(
+                        "run_after",
+                        code::OUT_OF_RANGE,
+                        Msg::ScheduleTooFar.with("max_days", MAX_SCHEDULE_DAYS),
+                    ));
+                }
+                // A time already past just means now
+                Ok(at) => run_after = Some(at.with_timezone(&chrono::Utc)).filter(|at| *at > now),
+                Err(_) => {
+                    validator.push(FieldError::new(
+                        "run_after",
+                        code::INVALID_FORMAT,
+                        Msg::InvalidTimestamp,
+                    ));
+                }
+            }
+        }
+        validator.finish()?;
+
+        Ok((job_priority(tier, level), run_after))
+    }
+}
+
+/// Queue priority for a new job. Workers claim the highest priority first,
+/// so pro jobs start ahead of free-tier jobs that are already waiting; the
+/// requested level moves a job up or down within its tier.
+fn job_priority(tier: &str, level: JobPriority) -> i32 {
+    let base = if tier == "pro" { 10 } else { 0 };
+    match level {
+        JobPriority::Low => base - 5,
+        JobPriority::Normal => base,
+        JobPriority::High => base + 5,
+    }
+}
+
+/// Checks before a job is accepted: the account's email is verified, then
+/// `enforce_quota`
+async fn check_quota(
+    state: &AppState,
+    user: &auth::AuthUser,
+    kind: MediaKind,
+    requested: i64,
+) -> Result<()> {
+    if !current_user(state, user).await?.email_verified {
+        return Err(AppError::EmailNotVerified(Msg::EmailNotVerified.into()));
+    }
+    enforce_quota(&state.db, &state.config.quotas, user, kind, requested).await
+}
+
+/// Daily quota for `kind`, then the concurrent job limit, then reserve the
+/// `requested` assets from today's quota for the job about to be created
+async fn enforce_quota(
+    db_pool: &sqlx::PgPool,
+    quotas: &crate::config::QuotaConfig,
+    user: &auth::AuthUser,
+    kind: MediaKind,
+    requested: i64,
+) -> Result<()> {
+    let status = quota::quota_status(db_pool, quotas, user.owner(), &user.tier).await?;
+    status
+        .check(kind, requested)
+        .map_err(|violation| quota_exceeded(violation, status))?;
+
+    // The status is a snapshot another request may have overtaken since
+    if let Err(violation) = quota::reserve(db_pool, quotas, user.owner(), &user.tier, kind, requested).await? {
+        let status = quota::quota_status(db_pool, quotas, user.owner(), &user.tier).await?;
+        return Err(quota_exceeded(violation, status));
+    }
+    Ok(())
+}
+
+/// The job just created for assets `check_quota` reserved, or when creating
+/// it failed, the error once the reservation is given back
+async fn created_or_released(
+    state: &AppState,
+    user: &auth::AuthUser,
+    kind: MediaKind,
+    requested: i64,
+    created: std::result::Result<db::Job, sqlx::Error>,
+) -> Result<db::Job> {
+    if created.is_err() {
+        quota::release(&state.db, user.owner(), kind, requested).await;
+    }
+    Ok(created?)
+}
+
+fn quota_exceeded(violation: QuotaViolation, status: QuotaStatus) -> AppError {
+    AppError::QuotaExceeded {
+        message: violation.message(),
+        quota: Box::new(status),
+    }
+}
+
+fn file_too_large(max_bytes: u64) -> AppError {
+    AppError::PayloadTooLarge(Msg::FileTooLarge.with("limit_mb", max_bytes / (1024 * 1024)))
+}
+
+fn media_kind_from_filename(filename: &str) -> Result<MediaKind> {
+    let lower = filename.to_lowercase();
+    let extension = lower.rsplit_once('.').map(|(_, extension)| extension);
+
+    match extension {
+        Some(e) if IMAGE_INPUT_FORMATS.contains(&e) => Ok(MediaKind::Image),
+        Some(e) if VIDEO_INPUT_FORMATS.contains(&e) => Ok(MediaKind::Video),
+        _ => Err(AppError::BadRequest(
+            Msg::UnsupportedFileType.with("supported", "JPG, PNG, WEBP, GIF, HEIC, MP4, MOV, AVI, WEBM"),
+        )),
+    }
+}
+
+fn max_upload_bytes(kind: MediaKind, config: &crate::config::Config) -> u64 {
+    match kind {
+        MediaKind::Image => config.processing.max_image_size_mb * 1024 * 1024,
+        MediaKind::Video => config.processing.max_video_size_mb * 1024 * 1024,
+    }
+}
+
+/// Read dimensions (and duration for videos) from a staged upload, rejecting
+/// images that decode to more pixels, and videos longer, than the configured
+/// maximum. Metadata that cannot be read is left empty rather than failing
+/// the upload, except for a HEIC that cannot be converted.
+async fn probe_upload(
+    path: &std::path::Path,
+    kind: MediaKind,
+    config: &crate::config::Config,
+    ffmpeg: &Ffmpeg,
+) -> Result<probe::MediaInfo> {
+    match kind {
+        MediaKind::Image => {
+            let max = config.processing.max_image_pixels;
+            // The image crate cannot read HEIC, so it is measured by converting
+            // it; one that will not convert could never be processed either
+            let pixels = if heic::is_heic(path)? {
+                let staged = path.to_path_buf();
+                let pixels = tokio::task::spawn_blocking(move || heic::decoded_pixels(&staged))
+                    .await
+                    .map_err(|e| AppError::Internal(format!("HEIC probe task failed: {}", e)))?
+                    .map_err(|e| AppError::UnprocessableEntity(Msg::UnreadableImage.with("reason", e.to_string())))?;
+                Some(pixels)
+            } else {
+                probe::decoded_pixels(path).ok()
+            };
+            if let Some(pixels) = pixels {
+                if pixels > max {
+                    return Err(AppError::UnprocessableEntity(
+                        Msg::ImageTooManyPixels.with("pixels", pixels).with("max", max),
+                    ));
+                }
+            }
+            Ok(probe::probe_image(path).unwrap_or_else(|e| {
+                tracing::warn!("Could not read image dimensions for {}: {}", path.display(), e);
+                probe::MediaInfo::default()
+            }))
+        }
+        MediaKind::Video => {
+            let info = probe::probe_video(ffmpeg, path)
+                .await
+                .map_err(|e| AppError::UnprocessableEntity(Msg::UnreadableVideo.with("reason", e.to_string())))?
+                .unwrap_or_default();
+
+            let max = config.processing.max_video_duration_seconds as f64;
+            if let Some(duration) = info.duration_seconds {
+                if duration > max {
+                    return Err(AppError::UnprocessableEntity(
+                        Msg::VideoTooLong
+                            .with("duration", (duration * 10.0).round() / 10.0)
+                            .with("max", max),
+                    ));
+                }
+            }
+            Ok(info)
+        }
+    }
+}
+
+/// Check sniffed content against the allowlist and the claimed extension, and
+/// refuse HEIC up front when this server has no way to decode it
+fn validate_content(header: &[u8], extension: &str) -> Result<SniffedType> {
+    let sniffed = sniff::sniff(header).ok_or_else(|| {
+        AppError::BadRequest(Msg::UnrecognizedContent.into())
+    })?;
+
+    if !sniffed.matches_extension(extension) {
+        return Err(AppError::BadRequest(
+            Msg::ExtensionMismatch
+                .with("content", sniffed.name())
+                .with("extension", extension),
+        ));
+    }
+
+    if sniffed == SniffedType::Heic && !heic::available() {
+        return Err(AppError::UnprocessableEntity(Msg::HeicUnsupported.into()));
+    }
+
+    Ok(sniffed)
+}
+
+/// What `stream_to_file` wrote
+struct StreamedFile {
+    size: u64,
+    /// Hex SHA-256 of the bytes, computed as they were written
+    content_hash: String,
+}
+
+/// Write a chunk stream to `path`, failing with `PayloadTooLarge` as soon as the
+/// running total exceeds the limit so oversized bodies are never fully read.
+///
+/// The limit comes from `limit_for_header`, called once with the first
+/// `SNIFF_LEN` bytes (or the whole body if it is shorter), so it can reject
+/// the content outright or choose a limit based on what it sees.
+async fn stream_to_file<S, E, F>(
+    mut stream: S,
+    path: &std::path::Path,
+    mut limit_for_header: F,
+) -> Result<StreamedFile>
+where
+    S: futures_util::Stream<Item = std::result::Result<bytes::Bytes, E>> + Unpin,
+    E: Into<AppError>,
+    F: FnMut(&[u8]) -> Result<u64>,
+{
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut size: u64 = 0;
+    let mut header: Vec<u8> = Vec::with_capacity(sniff::SNIFF_LEN);
+    let mut max_bytes: Option<u64> = None;
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(Into::into)?;
+        size += chunk.len() as u64;
+
+        if max_bytes.is_none() {
+            let take = (sniff::SNIFF_LEN - header.len()).min(chunk.len());
+            header.extend_from_slice(&chunk[..take]);
+            if header.len() == sniff::SNIFF_LEN {
+                max_bytes = Some(limit_for_header(&header)?);
+            }
+        }
+
+        if let Some(max_bytes) = max_bytes {
+            if size > max_bytes {
+                return Err(file_too_large(max_bytes));
+            }
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    // Bodies shorter than the sniff window are checked once the stream ends
+    if max_bytes.is_none() {
+        let max_bytes = limit_for_header(&header)?;
+        if size > max_bytes {
+            return Err(file_too_large(max_bytes));
+        }
+    }
+
+    Ok(StreamedFile {
+        size,
+        content_hash: hex::encode(hasher.finalize()),
+    })
+}
+
+fn get_file_extension(filename: &str) -> String {
+    filename
+        .rsplit('.')
+        .next()
+        .unwrap_or("unknown")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_job_schedule_places_jobs_by_tier_and_level() {
+        let schedule = |priority: Option<&str>, run_after: Option<String>| JobSchedule {
+            priority: priority.map(str::to_string),
+            run_after,
+        };
+        assert_eq!(schedule(None, None).place("free").unwrap(), (0, None));
+        assert_eq!(schedule(Some("low"), None).place("pro").unwrap(), (5, None));
+        assert_eq!(schedule(Some("HIGH"), None).place("pro").unwrap(), (15, None));
+
+        let refused = |result: Result<_>| match result {
+            Err(AppError::Validation(errors)) => errors.iter().map(|e| (e.field.clone(), e.code)).collect::<Vec<_>>(),
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        };
+        assert_eq!(refused(schedule(Some("high"), None).place("free")), [("priority".to_string(), code::NOT_APPLICABLE)]);
+        assert_eq!(refused(schedule(Some("urgent"), None).place("pro")), [("priority".to_string(), code::INVALID_CHOICE)]);
+
+        let tomorrow = chrono::Utc::now() + chrono::Duration::days(1);
+        let (_, run_after) = schedule(None, Some(tomorrow.to_rfc3339())).place("free").unwrap();
+        assert_eq!(run_after.map(|t| t.timestamp()), Some(tomorrow.timestamp()));
+        // Times already past run now
+        let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+        assert_eq!(schedule(None, Some(yesterday.to_rfc3339())).place("free").unwrap().1, None);
+
+        let next_month = chrono::Utc::now() + chrono::Duration::days(30);
+        assert_eq!(
+            refused(schedule(None, Some(next_month.to_rfc3339())).place("pro")),
+            [("run_after".to_string(), code::OUT_OF_RANGE)]
+        );
+        assert_eq!(
+            refused(schedule(None, Some("tomorrow night".to_string())).place("pro")),
+            [("run_after".to_string(), code::INVALID_FORMAT)]
+        );
+    }
+
+    #[test]
+    fn test_verify_current_password() {
+        // Low cost keeps the test fast; verification reads the cost from the hash
+        let hash = bcrypt::hash("correct horse", 4).unwrap();
+        assert!(verify_current_password("correct horse", &hash).is_ok());
+        assert!(matches!(
+            verify_current_password("wrong horse", &hash),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_stats_period_defaults_and_limits() {
+        let now = chrono::Utc::now();
+        let (from, to) = stats_period(None, None, now).unwrap();
+        assert_eq!((to, to - from), (now, chrono::Duration::days(30)));
+        let earlier = now - chrono::Duration::days(100);
+        assert_eq!(stats_period(None, Some(earlier), now).unwrap().0, earlier - chrono::Duration::days(30));
+        assert_eq!(stats_period(Some(earlier), None, now).unwrap(), (earlier, now));
+
+        let year_ago = now - chrono::Duration::days(365);
+        assert!(stats_period(Some(year_ago), None, now).is_ok());
+        let too_long = stats_period(Some(year_ago - chrono::Duration::seconds(1)), None, now).unwrap_err();
+        assert!(matches!(too_long, AppError::BadRequest(_)));
+        assert!(matches!(stats_period(Some(now), Some(now), now), Err(AppError::BadRequest(_))));
+        assert!(matches!(stats_period(Some(now), Some(earlier), now), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_forwarded_for_takes_last_hop() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(forwarded_for(&headers), None);
+
+        headers.insert("x-forwarded-for", "203.0.113.9, 198.51.100.7".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), Some("198.51.100.7".parse().unwrap()));
+
+        headers.insert("x-forwarded-for", "not-an-ip".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), None);
+    }
+
+    /// `(field, code)` of each error in a validation failure
+    fn field_errors<T: std::fmt::Debug>(result: Result<T>) -> Vec<(String, &'static str)> {
+        match result {
+            Err(AppError::Validation(errors)) => errors.into_iter().map(|e| (e.field, e.code)).collect(),
+            other => panic!("expected validation errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_color_grade_and_remove_bg_ranges() {
+        let params: ColorGradeParams = serde_json::from_value(json!({
+            "hue": -180, "saturation": 101, "brightness": -100, "contrast": -101,
+        }))
+        .unwrap();
+        assert_eq!(
+            field_errors(validate_color_grade(&params)),
+            [("saturation".to_string(), code::OUT_OF_RANGE), ("contrast".to_string(), code::OUT_OF_RANGE)]
+        );
+
+        let params: RemoveBgParams = serde_json::from_value(json!({ "replace_color": [0, 256, -1] })).unwrap();
+        assert_eq!(
+            field_errors(validate_remove_bg(&params)),
+            [("replace_color[1]".to_string(), code::OUT_OF_RANGE), ("replace_color[2]".to_string(), code::OUT_OF_RANGE)]
+        );
+        let params: RemoveBgParams = serde_json::from_value(json!({ "replace_color": [0, 255, 128] })).unwrap();
+        assert!(validate_remove_bg(&params).is_ok());
+        assert_eq!(remove_bg_parameters(&params).replace_color, Some([0, 255, 128]));
+        assert_eq!(remove_bg_parameters(&params).mode.as_deref(), Some("color"));
+    }
+
+    #[test]
+    fn test_remove_bg_modes() {
+        let params: RemoveBgParams = serde_json::from_value(json!({ "mode": "Blur" })).unwrap();
+        assert!(validate_remove_bg(&params).is_ok());
+        let parameters = remove_bg_parameters(&params);
+        assert_eq!(parameters.mode.as_deref(), Some("blur"));
+        assert_eq!(parameters.blur_sigma, Some(processing::DEFAULT_BLUR_SIGMA));
+
+        let params: RemoveBgParams = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(remove_bg_parameters(&params).mode.as_deref(), Some("transparent"));
+        assert_eq!(remove_bg_parameters(&params).blur_sigma, None);
+
+        let params: RemoveBgParams =
+            serde_json::from_value(json!({ "mode": "color", "blur_sigma": 80.0 })).unwrap();
+        assert_eq!(
+            field_errors(validate_remove_bg(&params)),
+            [
+                ("blur_sigma".to_string(), code::OUT_OF_RANGE),
+                ("replace_color".to_string(), code::REQUIRED),
+                ("blur_sigma".to_string(), code::NOT_APPLICABLE),
+            ]
+        );
+        let params: RemoveBgParams =
+            serde_json::from_value(json!({ "mode": "sepia", "replace_color": [0, 0, 0] })).unwrap();
+        assert_eq!(
+            field_errors(validate_remove_bg(&params)),
+            [("mode".to_string(), code::INVALID_CHOICE)]
+        );
+
+        let background = Uuid::new_v4().to_string();
+        let params: RemoveBgParams = serde_json::from_value(json!({ "background_asset_id": background })).unwrap();
+        assert!(validate_remove_bg(&params).is_ok());
+        assert_eq!(remove_bg_parameters(&params).mode.as_deref(), Some("image"));
+        let params: RemoveBgParams =
+            serde_json::from_value(json!({ "background_asset_id": background, "replace_color": [0, 0, 0] })).unwrap();
+        assert!(matches!(validate_remove_bg(&params), Err(AppError::BadRequest(_))));
+        let params: RemoveBgParams =
+            serde_json::from_value(json!({ "mode": "image", "blur_sigma": 2.0 })).unwrap();
+        assert_eq!(
+            field_errors(validate_remove_bg(&params)),
+            [("background_asset_id".to_string(), code::REQUIRED), ("blur_sigma".to_string(), code::NOT_APPLICABLE)]
+        );
+    }
+
+    #[test]
+    fn test_remove_bg_trim_options() {
+        let params: RemoveBgParams = serde_json::from_value(json!({ "trim": true })).unwrap();
+        assert!(validate_remove_bg(&params).is_ok());
+        assert!(validate_remove_bg_for(&params, MediaKind::Image).is_ok());
+        assert_eq!(field_errors(validate_remove_bg_for(&params, MediaKind::Video)), [("trim".to_string(), code::NOT_APPLICABLE)]);
+        assert_eq!(remove_bg_parameters(&params).trim_padding, Some(0.0));
+
+        let params: RemoveBgParams = serde_json::from_value(json!({ "trim": true, "trim_padding": 12.5 })).unwrap();
+        assert_eq!(remove_bg_parameters(&params).trim_padding, Some(12.5));
+
+        let params: RemoveBgParams = serde_json::from_value(json!({ "trim_padding": 120 })).unwrap();
+        assert_eq!(
+            field_errors(validate_remove_bg(&params)),
+            [("trim_padding".to_string(), code::OUT_OF_RANGE), ("trim_padding".to_string(), code::NOT_APPLICABLE)]
+        );
+    }
+
+    #[test]
+    fn test_remove_bg_output_formats() {
+        let params: RemoveBgParams = serde_json::from_value(json!({ "output_format": "WEBP" })).unwrap();
+        assert!(validate_remove_bg(&params).is_ok());
+        assert!(validate_remove_bg_for(&params, MediaKind::Image).is_ok());
+        assert_eq!(
+            field_errors(validate_remove_bg_for(&params, MediaKind::Video)),
+            [("output_format".to_string(), code::NOT_APPLICABLE)]
+        );
+        let params: RemoveBgParams = serde_json::from_value(json!({ "output_format": "zip" })).unwrap();
+        assert_eq!(
+            field_errors(validate_remove_bg_for(&params, MediaKind::Image)),
+            [("output_format".to_string(), code::NOT_APPLICABLE)]
+        );
+
+        // JPEG can't carry the transparency away
+        let params: RemoveBgParams = serde_json::from_value(json!({ "output_format": "jpg" })).unwrap();
+        assert!(matches!(validate_remove_bg(&params), Err(AppError::BadRequest(_))));
+        let params: RemoveBgParams =
+            serde_json::from_value(json!({ "output_format": "jpeg", "flatten_color": [255, 255, 255] })).unwrap();
+        assert!(validate_remove_bg(&params).is_ok());
+        assert_eq!(remove_bg_parameters(&params).flatten_color, Some([255, 255, 255]));
+        let params: RemoveBgParams =
+            serde_json::from_value(json!({ "output_format": "jpg", "mode": "blur" })).unwrap();
+        assert!(validate_remove_bg(&params).is_ok());
+
+        let params: RemoveBgParams =
+            serde_json::from_value(json!({ "output_format": "png", "flatten_color": [0, 0, 300] })).unwrap();
+        assert_eq!(
+            field_errors(validate_remove_bg(&params)),
+            [("flatten_color[2]".to_string(), code::OUT_OF_RANGE), ("flatten_color".to_string(), code::NOT_APPLICABLE)]
+        );
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_update_email_to_registered_address_conflicts() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let taken = format!("{}@email.test", Uuid::new_v4());
+        db::User::create(&pool, &taken, "hash", "free").await.unwrap();
+        let user = db::User::create(&pool, &format!("{}@email.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+
+        let result = db::User::update_email(&pool, user.id, &taken).await.map_err(email_conflict);
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+
+        let fresh = format!("{}@email.test", Uuid::new_v4());
+        let updated = db::User::update_email(&pool, user.id, &fresh).await.unwrap();
+        assert_eq!(updated.email, fresh);
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_free_tier_daily_image_quota_is_enforced() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let quotas = crate::config::QuotaConfig {
+            free_tier_image_daily: 3,
+            free_tier_video_daily: 1,
+            free_tier_concurrent: 100,
+            pro_tier_video_daily: 100,
+            pro_tier_concurrent: 100,
+            free_tier_storage_quota_bytes: 1024,
+            pro_tier_storage_quota_bytes: 1024,
+            free_tier_result_retention_hours: 24,
+            pro_tier_result_retention_hours: 168,
+            pro_tier_max_result_retention_hours: 720,
+            org_image_daily: 0,
+            org_video_daily: 0,
+            refund_failed_jobs: false,
+        };
+        let user = db::User::create(&pool, &format!("{}@quota.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let auth_user = auth::AuthUser {
+            id: user.id,
+            email: user.email.clone(),
+            tier: user.subscription_tier.clone(),
+            organization_id: None,
+        };
+
+        for _ in 0..3 {
+            enforce_quota(&pool, &quotas, &auth_user, MediaKind::Image, 1).await.unwrap();
+            db::Job::create(&pool, user.id.into(), vec![Uuid::new_v4()], "convert", "image", json!({}), 0, None)
+                .await
+                .unwrap();
+        }
+
+        let result = enforce_quota(&pool, &quotas, &auth_user, MediaKind::Image, 1).await;
+        assert!(matches!(result, Err(AppError::QuotaExceeded { message, quota })
+            if message.details()["used"] == 3 && message.details()["limit"] == 3 && quota.images.used == 3));
+        // Videos have their own daily budget
+        enforce_quota(&pool, &quotas, &auth_user, MediaKind::Video, 1).await.unwrap();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_job_status_shows_live_progress_of_a_running_job() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let user = db::User::create(&pool, &format!("{}@status.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let job = db::Job::create(&pool, user.id.into(), vec![], "convert", "image", json!({}), 0, None)
+            .await
+            .unwrap();
+        let (queue, _rx) = Queue::new(8, None).await;
+        let current = || async { db::Job::find_by_id(&pool, job.id).await.unwrap().unwrap() };
+
+        // Nothing live yet: the row as it is
+        let status = job_status(current().await, &queue).await;
+        assert_eq!((status.status.as_str(), status.progress), ("queued", 0));
+
+        // Mid-flight, the worker has written 45% to the table and is on 47%
+        sqlx::query("UPDATE jobs SET status = 'processing', progress_percent = 45, heartbeat_at = NOW() WHERE id = $1")
+            .bind(job.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let statuses = queue.get_statuses_handle();
+        statuses.set(&job.id.to_string(), JobStatus::Processing { progress: 47 }).await;
+        let status = job_status(current().await, &queue).await;
+        assert_eq!((status.status.as_str(), status.progress), ("processing", 47));
+
+        // The table decides once the job has finished
+        db::Job::fail(&pool, job.id, "boom", "processing_failed").await.unwrap();
+        let status = job_status(current().await, &queue).await;
+        assert_eq!((status.status.as_str(), status.progress), ("failed", 45));
+    }
+
+    #[test]
+    fn test_live_job_status_reads_the_status_map_entry() {
+        let status = live_job_status("legacy-7".to_string(), JobStatus::Processing { progress: 140 });
+        assert_eq!((status.job_id.as_str(), status.status.as_str(), status.progress), ("legacy-7", "processing", 100));
+        assert!(chrono::DateTime::parse_from_rfc3339(&status.created_at).is_ok());
+
+        let completed = live_job_status("a".to_string(), JobStatus::Completed { result_url: "out.png".to_string() });
+        assert_eq!((completed.status.as_str(), completed.result_url.as_deref()), ("completed", Some("out.png")));
+        let failed = live_job_status("b".to_string(), JobStatus::Failed { error: "boom".to_string() });
+        assert_eq!((failed.status.as_str(), failed.error.as_deref()), ("failed", Some("boom")));
+        assert_eq!(failed.error_code, None);
+        assert_eq!(live_job_status("c".to_string(), JobStatus::Queued).status, "queued");
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_job_responses_name_their_assets() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let user = db::User::create(&pool, &format!("{}@assets.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let first = db::MediaAsset::create(&pool, user.id.into(), "holiday.png", "png", 6, None).await.unwrap();
+        let second = db::MediaAsset::create(&pool, user.id.into(), "beach.png", "png", 6, None).await.unwrap();
+        let mut jobs = Vec::new();
+        for asset_ids in [vec![first.id, second.id], vec![Uuid::new_v4()], vec![]] {
+            let job = db::Job::create(&pool, user.id.into(), asset_ids, "convert", "image", json!({}), 0, None)
+                .await
+                .unwrap();
+            jobs.push(JobStatusResponse::from(job));
+        }
+
+        with_asset_filenames(&pool, &mut jobs).await.unwrap();
+        assert_eq!(jobs[0].job_type, "convert");
+        assert_eq!(jobs[0].asset_ids, [first.id.to_string(), second.id.to_string()]);
+        assert_eq!(jobs[0].asset_filename.as_deref(), Some("holiday.png"));
+        // Deleted assets and jobs without any have no name
+        assert_eq!(jobs[1].asset_filename, None);
+        assert!(jobs[2].asset_ids.is_empty() && jobs[2].asset_filename.is_none());
+
+        let fields = serde_json::to_value(&jobs[0]).unwrap();
+        assert_eq!(fields["asset_ids"][1], json!(second.id.to_string()));
+        assert_eq!(fields["asset_filename"], "holiday.png");
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_file_rejects_oversized_body_early() {
+        let path = std::env::temp_dir().join(format!("upload_test_{}", Uuid::new_v4()));
+        let pulled = AtomicUsize::new(0);
+
+        // 100 chunks of 1 KB against a 4 KB limit
+        let chunks = futures_util::stream::iter(0..100)
+            .map(|_| {
+                pulled.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, std::io::Error>(bytes::Bytes::from(vec![0u8; 1024]))
+            });
+
+        let result = stream_to_file(Box::pin(chunks), &path, |_: &[u8]| Ok(4 * 1024)).await;
+        assert!(matches!(result, Err(AppError::PayloadTooLarge(_))));
+        // Stopped right after crossing the limit instead of draining the body
+        assert_eq!(pulled.load(Ordering::SeqCst), 5);
+        assert!(std::fs::metadata(&path).unwrap().len() <= 4 * 1024);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_file_accepts_body_within_limit() {
+        let path = std::env::temp_dir().join(format!("upload_test_{}", Uuid::new_v4()));
+        let chunks = futures_util::stream::iter(vec![
+            Ok::<_, std::io::Error>(bytes::Bytes::from_static(b"hello ")),
+            Ok(bytes::Bytes::from_static(b"world")),
+        ]);
+
+        let streamed = stream_to_file(chunks, &path, |_: &[u8]| Ok(1024)).await.unwrap();
+        assert_eq!(streamed.size, 11);
+        // Hashed across chunk boundaries, over the file bytes alone
+        assert_eq!(
+            streamed.content_hash,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_file_rejects_disguised_executable() {
+        let path = std::env::temp_dir().join(format!("upload_test_{}", Uuid::new_v4()));
+        let mut body = b"MZ\x90\x00".to_vec();
+        body.resize(4096, 0);
+        let chunks = futures_util::stream::iter(vec![Ok::<_, std::io::Error>(bytes::Bytes::from(body))]);
+
+        let result = stream_to_file(chunks, &path, |h: &[u8]| {
+            validate_content(h, "png").map(|_| 1024 * 1024)
+        })
+        .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_download_revalidates_with_etag() {
+        let base = std::env::temp_dir().join(format!("download_test_{}", Uuid::new_v4()));
+        let storage = crate::services::LocalStorage::new(&base);
+        let location = storage.save_bytes(b"result bytes", Uuid::new_v4(), "result.png").await.unwrap();
+        let result = StoredResult {
+            location: location.to_string(),
+            etag: Some(conditional::etag("abc123")),
+            filename: None,
+            completed_at: Some(chrono::Utc::now()),
+            expires_at: None,
+        };
+
+        let first = stream_result(&storage, &result, &HeaderMap::new()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers()[header::CACHE_CONTROL], RESULT_CACHE_CONTROL);
+        assert!(first.headers().contains_key(header::LAST_MODIFIED));
+        let etag = first.headers()[header::ETAG].clone();
+        assert_eq!(etag, "\"abc123\"");
+
+        // The 304 is answered from the job row alone, so a missing file doesn't matter
+        std::fs::remove_dir_all(&base).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let second = stream_result(&storage, &result, &headers).await.unwrap();
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(second.headers()[header::ETAG], etag);
+        let body = axum::body::to_bytes(second.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn test_attachment_disposition_escapes_the_filename() {
+        assert_eq!(
+            attachment_disposition("say \"hi\"; x.png"),
+            "attachment; filename=\"say _hi__ x.png\"; filename*=UTF-8''say%20%22hi%22%3B%20x.png"
+        );
+        assert_eq!(
+            attachment_disposition("café\r\n.jpg"),
+            "attachment; filename=\"caf___.jpg\"; filename*=UTF-8''caf%C3%A9%0D%0A.jpg"
+        );
+        assert!(header::HeaderValue::from_str(&attachment_disposition("a\u{0}b\u{7f}.png")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_legacy_result_paths_still_download() {
+        let base = std::env::temp_dir().join(format!("download_test_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&base).unwrap();
+        // Written before locations had a scheme: the row holds the full path
+        let legacy = base.join("0000_result.png");
+        std::fs::write(&legacy, b"old result").unwrap();
+        let storage = crate::services::StorageRouter::local(crate::services::LocalStorage::new(&base), None);
+        let result = StoredResult {
+            location: legacy.to_string_lossy().to_string(),
+            etag: None,
+            filename: None,
+            completed_at: None,
+            expires_at: None,
+        };
+
+        let response = stream_result(&storage, &result, &HeaderMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"0000_result.png\"; filename*=UTF-8''0000_result.png"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"old result");
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn test_job_parameters_record_webhook_and_request_id() {
+        let request_id = RequestId("req-1".to_string());
+        let params = job_parameters(json!({ "output_format": "png" }), None, &request_id);
+        assert_eq!(params, json!({ "output_format": "png", "request_id": "req-1" }));
+
+        let params = job_parameters(json!({}), Some("https://hook.test/".to_string()), &request_id);
+        assert_eq!(params["webhook_url"], "https://hook.test/");
+        assert_eq!(params["request_id"], "req-1");
+    }
+
+    #[test]
+    fn test_validate_content_checks_extension() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(validate_content(png, "png").unwrap(), SniffedType::Png);
+        assert!(matches!(validate_content(png, "jpg"), Err(AppError::BadRequest(_))));
+
+        // HEIC is only accepted when it can be decoded
+        let heic = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic";
+        match validate_content(heic, "heic") {
+            Ok(sniffed) => {
+                assert!(heic::available());
+                assert_eq!(sniffed, SniffedType::Heic);
+            }
+            Err(e) => {
+                assert!(!heic::available());
+                assert!(matches!(e, AppError::UnprocessableEntity(_)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_media_kind_from_filename() {
+        assert_eq!(media_kind_from_filename("photo.JPG").unwrap(), MediaKind::Image);
+        assert_eq!(media_kind_from_filename("clip.webm").unwrap(), MediaKind::Video);
+        assert!(media_kind_from_filename("malware.exe").is_err());
+        assert!(media_kind_from_filename("png").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_without_ffmpeg_or_heic() {
+        let ffmpeg = Ffmpeg::detect("/nonexistent/ffmpeg").await;
+        let report = capabilities(&ffmpeg, false);
+        assert!(!report.video);
+        assert_eq!(report.image_formats.input, ["jpg", "jpeg", "png", "webp", "gif"]);
+        assert!(report.image_formats.operations.contains(&"color_grade"));
+        assert!(report.video_formats.input.is_empty() && report.video_formats.output.is_empty());
+        assert!(report.video_formats.operations.is_empty() && report.video_codecs.is_empty());
+        assert!(check_video_support(&ffmpeg, &[]).is_err());
+
+        assert!(capabilities(&ffmpeg, true).image_formats.input.contains(&"heic"));
+    }
+
+    #[test]
+    fn test_batch_request_shares_conversion_params() {
+        let payload: BatchConvertRequest = serde_json::from_value(json!({
+            "asset_ids": ["a", "b"],
+            "output_format": "JPG",
+            "quality": 70,
+        }))
+        .unwrap();
+        assert_eq!(payload.asset_ids.len(), 2);
+        assert_eq!(payload.params.quality, Some(70));
+        assert_eq!(validate_conversion(&payload.params, MediaKind::Image).unwrap(), "jpg");
+        // Same options must be valid for every asset kind in the batch
+        assert_eq!(
+            field_errors(validate_conversion(&payload.params, MediaKind::Video)),
+            [("output_format".to_string(), code::INVALID_CHOICE)]
+        );
+
+        let params: ConversionParams = serde_json::from_value(json!({
+            "output_format": "png", "video_codec": "h264", "quality": 0,
+        }))
+        .unwrap();
+        assert_eq!(
+            field_errors(validate_conversion(&params, MediaKind::Image)),
+            [("video_codec".to_string(), code::NOT_APPLICABLE), ("quality".to_string(), code::OUT_OF_RANGE)]
+        );
+    }
+
+    #[test]
+    fn test_size_targets_and_avif_are_validated() {
+        let params = |value: serde_json::Value| serde_json::from_value::<ConversionParams>(value).unwrap();
+        let target = params(json!({ "output_format": "webp", "target_size_kb": 150 }));
+        assert_eq!(validate_conversion(&target, MediaKind::Image).unwrap(), "webp");
+        assert_eq!(conversion_parameters(&target, "webp").target_size_kb, Some(150));
+
+        let not_lossy = params(json!({ "output_format": "png", "target_size_kb": 150 }));
+        let with_quality = params(json!({ "output_format": "jpg", "target_size_kb": 150, "quality": 80 }));
+        let zero = params(json!({ "output_format": "jpg", "target_size_kb": 0 }));
+        let video = params(json!({ "output_format": "mp4", "target_size_kb": 150 }));
+        for (params, kind, code) in [
+            (not_lossy, MediaKind::Image, code::NOT_APPLICABLE),
+            (with_quality, MediaKind::Image, code::NOT_APPLICABLE),
+            (zero, MediaKind::Image, code::OUT_OF_RANGE),
+            (video, MediaKind::Video, code::NOT_APPLICABLE),
+        ] {
+            assert_eq!(field_errors(validate_conversion(&params, kind)), [("target_size_kb".to_string(), code)]);
+        }
+
+        let avif = params(json!({ "output_format": "avif", "width": 8000, "height": 600 }));
+        let errors = field_errors(validate_conversion(&avif, MediaKind::Image));
+        if cfg!(feature = "avif") {
+            assert_eq!(errors, [("width".to_string(), code::OUT_OF_RANGE)]);
+        } else {
+            // Refused outright when the server is built without it
+            assert_eq!(errors, [("output_format".to_string(), code::INVALID_CHOICE)]);
+        }
+    }
+
+    #[test]
+    fn test_lut_references_are_validated() {
+        let lut_id = Uuid::new_v4().to_string();
+        let params: ColorGradeParams = serde_json::from_value(json!({ "lut_id": lut_id })).unwrap();
+        assert!(validate_color_grade(&params).is_ok());
+        assert_eq!(color_grade_parameters(&params).lut_id.map(|id| id.to_string()), Some(lut_id.clone()));
+
+        let params: ColorGradeParams = serde_json::from_value(json!({
+            "lut_id": "warm.cube", "lut_location": "luts/warm.cube",
+        }))
+        .unwrap();
+        assert_eq!(
+            field_errors(validate_color_grade(&params)),
+            [("lut_id".to_string(), code::INVALID_FORMAT), ("lut_location".to_string(), code::NOT_APPLICABLE)]
+        );
+
+        let params: ConversionParams = serde_json::from_value(json!({ "output_format": "mp4", "lut_id": lut_id })).unwrap();
+        assert_eq!(
+            field_errors(validate_conversion(&params, MediaKind::Video)),
+            [("lut_id".to_string(), code::NOT_APPLICABLE)]
+        );
+
+        // Pipeline steps carry the id through to the worker
+        let ops = operations(json!([{ "type": "convert", "output_format": "jpg", "lut_id": lut_id }]));
+        let steps = job_params::PipelineParams { operations: pipeline_parameters(&ops, MediaKind::Image).unwrap() };
+        assert_eq!(steps.to_value()["operations"][0]["lut_id"], json!(lut_id));
+    }
+
+    fn operations(value: serde_json::Value) -> Vec<Operation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_pipeline_parameters() {
+        let ops = operations(json!([
+            { "type": "remove_bg" },
+            { "type": "color_grade", "preset": "warm" },
+            { "type": "convert", "output_format": "WEBP", "quality": 80 },
+        ]));
+        let steps = pipeline_parameters(&ops, MediaKind::Image).unwrap();
+        assert_eq!(steps.len(), 3);
+        let stored = job_params::PipelineParams { operations: steps }.to_value();
+        assert_eq!(stored["operations"][1]["type"], "color_grade");
+        assert_eq!(stored["operations"][1]["preset"], "warm");
+        assert_eq!(stored["operations"][2]["output_format"], "webp");
+
+        assert!(serde_json::from_value::<Operation>(json!({ "type": "sharpen" })).is_err());
+    }
+
+    #[test]
+    fn test_pipeline_rejects_steps_that_cannot_take_the_previous_output() {
+        // Color grading works on images only
+        let ops = operations(json!([{ "type": "color_grade" }]));
+        let err = pipeline_parameters(&ops, MediaKind::Video).err().unwrap();
+        assert!(matches!(err, AppError::UnprocessableEntity(m) if m.to_string().starts_with("Step 1 (color_grade)")));
+
+        // Field errors are reported under the step's position
+        let ops = operations(json!([
+            { "type": "remove_bg" },
+            { "type": "color_grade", "hue": 270 },
+        ]));
+        assert_eq!(
+            field_errors(pipeline_parameters(&ops, MediaKind::Image)),
+            [("operations[1].hue".to_string(), code::OUT_OF_RANGE)]
+        );
+
+        // So does AVIF, which nothing reads back
+        let ops = operations(json!([
+            { "type": "convert", "output_format": "avif" },
+            { "type": "color_grade", "preset": "warm" },
+        ]));
+        if cfg!(feature = "avif") {
+            let err = pipeline_parameters(&ops, MediaKind::Image).err().unwrap();
+            assert!(matches!(err, AppError::UnprocessableEntity(m) if m.details()["output"] == "AVIF"));
+        }
+
+        // A zip of frames ends the chain
+        let ops = operations(json!([
+            { "type": "remove_bg", "output_format": "zip" },
+            { "type": "convert", "output_format": "mp4" },
+        ]));
+        assert!(matches!(
+            pipeline_parameters(&ops, MediaKind::Video),
+            Err(AppError::UnprocessableEntity(_))
+        ));
+
+        // Video to GIF hands an image to the next step
+        let ops = operations(json!([
+            { "type": "convert", "output_format": "gif" },
+            { "type": "convert", "output_format": "png" },
+        ]));
+        assert!(pipeline_parameters(&ops, MediaKind::Video).is_ok());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,239 @@
+// backend/src/services/analysis.rs
+// Histograms, luminance and dominant colors of an image, for /api/analyze
+
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Dominant colors reported
+pub const DOMINANT_COLORS: usize = 5;
+
+/// Pixels looked at when finding dominant colors. Larger images are sampled
+/// at an even stride; the histograms always count every pixel.
+const COLOR_SAMPLES: usize = 1 << 16;
+
+/// What `ImageProcessor::analyze` found. Fully transparent pixels are left
+/// out, so a cut-out describes its subject rather than the empty canvas.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ImageAnalysis {
+    pub width: u32,
+    pub height: u32,
+    pub histogram: Histogram,
+    /// Rec. 709 luminance, 0–255
+    pub mean_luminance: f32,
+    pub median_luminance: u8,
+    /// Most common first
+    pub dominant_colors: Vec<DominantColor>,
+}
+
+/// Pixel counts for each of the 256 levels of a channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Histogram {
+    pub red: Vec<u64>,
+    pub green: Vec<u64>,
+    pub blue: Vec<u64>,
+    pub luminance: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DominantColor {
+    /// `#rrggbb`
+    pub hex: String,
+    pub rgb: [u8; 3],
+    /// Fraction of the image, 0–1
+    pub share: f32,
+}
+
+pub fn analyze(img: &RgbaImage) -> ImageAnalysis {
+    let mut red = vec![0u64; 256];
+    let mut green = vec![0u64; 256];
+    let mut blue = vec![0u64; 256];
+    let mut luminance = vec![0u64; 256];
+    let mut luminance_sum = 0u64;
+    for pixel in img.pixels().filter(|p| p[3] > 0) {
+        red[pixel[0] as usize] += 1;
+        green[pixel[1] as usize] += 1;
+        blue[pixel[2] as usize] += 1;
+        let luma = luma(pixel);
+        luminance[luma as usize] += 1;
+        luminance_sum += luma as u64;
+    }
+
+    let counted: u64 = luminance.iter().sum();
+    let mean_luminance = if counted == 0 { 0.0 } else { luminance_sum as f32 / counted as f32 };
+
+    ImageAnalysis {
+        width: img.width(),
+        height: img.height(),
+        median_luminance: median(&luminance, counted),
+        mean_luminance,
+        dominant_colors: dominant_colors(img),
+        histogram: Histogram { red, green, blue, luminance },
+    }
+}
+
+fn luma(pixel: &Rgba<u8>) -> u8 {
+    (0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32).round() as u8
+}
+
+/// The level below which half of the `total` counts fall
+fn median(histogram: &[u64], total: u64) -> u8 {
+    let mut seen = 0;
+    for (level, count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen * 2 >= total && total > 0 {
+            return level as u8;
+        }
+    }
+    0
+}
+
+/// Median cut: split the box of sampled colors with the widest spread,
+/// weighted by how many pixels it holds, until there are
+/// `DOMINANT_COLORS` boxes or nothing left to split. Each box's mean is
+/// one color.
+fn dominant_colors(img: &RgbaImage) -> Vec<DominantColor> {
+    let opaque = img.pixels().filter(|p| p[3] > 0).count();
+    let stride = opaque.div_ceil(COLOR_SAMPLES).max(1);
+    let samples: Vec<[u8; 3]> = img
+        .pixels()
+        .filter(|p| p[3] > 0)
+        .step_by(stride)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let total = samples.len();
+
+    let mut boxes = vec![samples];
+    while boxes.len() < DOMINANT_COLORS {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| (i, widest_channel(colors)))
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(i, (_, range))| *range as usize * boxes[*i].len());
+        let Some((i, (channel, _))) = widest else {
+            break;
+        };
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|c| c[channel]);
+        let at = split_point(&colors, channel);
+        let upper = colors.split_off(at);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.sort_by_key(|colors| std::cmp::Reverse(colors.len()));
+    boxes
+        .iter()
+        .map(|colors| {
+            let mean = |c: usize| {
+                (colors.iter().map(|color| color[c] as u64).sum::<u64>() as f64 / colors.len() as f64).round() as u8
+            };
+            let rgb = [mean(0), mean(1), mean(2)];
+            DominantColor {
+                hex: format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]),
+                rgb,
+                share: colors.len() as f32 / total as f32,
+            }
+        })
+        .collect()
+}
+
+/// The channel whose values spread furthest, and how far
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (min, max) = colors
+                .iter()
+                .fold((u8::MAX, u8::MIN), |(min, max), color| (min.min(color[c]), max.max(color[c])));
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+/// Where to split colors sorted by `channel`: at the median, moved to the
+/// nearest change of value so one flat color never ends up in two boxes.
+/// The colors must not all share a value on `channel`.
+fn split_point(colors: &[[u8; 3]], channel: usize) -> usize {
+    let value = colors[colors.len() / 2][channel];
+    let above = colors.partition_point(|c| c[channel] <= value);
+    if above < colors.len() {
+        above
+    } else {
+        colors.partition_point(|c| c[channel] < value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vertical bands of flat color, `(color, width)` from left to right
+    fn bands(bands: &[([u8; 3], u32)], height: u32) -> RgbaImage {
+        let width = bands.iter().map(|(_, w)| w).sum();
+        RgbaImage::from_fn(width, height, |x, _| {
+            let mut left = 0;
+            for (color, w) in bands {
+                if x < left + w {
+                    return Rgba([color[0], color[1], color[2], 255]);
+                }
+                left += w;
+            }
+            unreachable!()
+        })
+    }
+
+    #[test]
+    fn test_dominant_colors_of_flat_regions() {
+        let red = [220, 20, 60];
+        let teal = [0, 128, 128];
+        let cream = [250, 240, 200];
+        let img = bands(&[(red, 50), (teal, 30), (cream, 20)], 10);
+
+        let colors = dominant_colors(&img);
+        let found: Vec<_> = colors.iter().map(|c| (c.rgb, (c.share * 100.0).round() as u32)).collect();
+        assert_eq!(found, [(red, 50), (teal, 30), (cream, 20)]);
+        assert_eq!(colors[1].hex, "#008080");
+    }
+
+    #[test]
+    fn test_dominant_colors_stop_at_five() {
+        let img = bands(
+            &[
+                ([0, 0, 0], 40),
+                ([255, 0, 0], 30),
+                ([0, 255, 0], 20),
+                ([0, 0, 255], 15),
+                ([255, 255, 0], 10),
+                ([255, 255, 255], 2),
+            ],
+            4,
+        );
+        let colors = dominant_colors(&img);
+        assert_eq!(colors.len(), DOMINANT_COLORS);
+        assert_eq!(colors[0].rgb, [0, 0, 0]);
+        let share: f32 = colors.iter().map(|c| c.share).sum();
+        assert!((share - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_histograms_and_luminance_skip_transparent_pixels() {
+        let mut img = bands(&[([0, 0, 0], 3), ([255, 255, 255], 1)], 1);
+        img.put_pixel(0, 0, Rgba([255, 255, 255, 0]));
+
+        let analysis = analyze(&img);
+        assert_eq!(analysis.histogram.red[0], 2);
+        assert_eq!(analysis.histogram.red[255], 1);
+        assert_eq!(analysis.histogram.luminance.iter().sum::<u64>(), 3);
+        assert_eq!(analysis.median_luminance, 0);
+        assert!((analysis.mean_luminance - 85.0).abs() < 0.01);
+
+        let empty = analyze(&RgbaImage::new(2, 2));
+        assert!(empty.dominant_colors.is_empty());
+        assert_eq!(empty.mean_luminance, 0.0);
+    }
+}
@@ -0,0 +1,256 @@
+// backend/src/services/animation.rs
+// Animated GIF input: frames are decoded, transformed and encoded one at a
+// time, so a long animation never has to be held in memory whole
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::iter::Peekable;
+use std::path::Path;
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::{AnimationDecoder, DynamicImage, Frame, Frames, ImageDecoder, ImageFormat, ImageReader, RgbaImage};
+
+use super::probe;
+use super::processing::{self, OutputEncoding, ProcessingError};
+
+/// 1 (best palette) to 30 (fastest). image's default of 1 takes seconds per
+/// frame on large canvases.
+const GIF_ENCODE_SPEED: i32 = 10;
+
+/// Raw frame data an animated WebP may buffer. libwebp only assembles the
+/// file once it has every frame, so unlike GIF these can't be streamed.
+const MAX_WEBP_FRAME_BYTES: usize = 256 * 1024 * 1024;
+
+/// A GIF with more than one frame, positioned at its first frame
+pub struct Animation {
+    first: Frame,
+    rest: Peekable<Frames<'static>>,
+    repeat: Repeat,
+}
+
+/// Whether `format` can hold an animation
+pub fn is_animated_format(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Gif | ImageFormat::WebP)
+}
+
+/// Whether `path` is an animated GIF
+pub fn is_animated(path: &Path, max_pixels: u64) -> Result<bool, ProcessingError> {
+    Ok(Animation::open(path, max_pixels)?.is_some())
+}
+
+impl Animation {
+    /// Open `path` as an animation. `None` for anything but a GIF with more
+    /// than one frame. Only the first two frames are decoded, but every frame
+    /// counts towards `max_pixels`.
+    pub fn open(path: &Path, max_pixels: u64) -> Result<Option<Self>, ProcessingError> {
+        if ImageReader::open(path)?.with_guessed_format()?.format() != Some(ImageFormat::Gif) {
+            return Ok(None);
+        }
+        processing::check_pixels(probe::decoded_pixels(path)?, max_pixels)?;
+
+        // image doesn't expose the loop count, so read it from the header
+        let header = gif::DecodeOptions::new()
+            .read_info(BufReader::new(File::open(path)?))
+            .map_err(|e| ProcessingError::DecodeFailed(e.to_string()))?;
+        let repeat = match header.repeat() {
+            gif::Repeat::Infinite => Repeat::Infinite,
+            gif::Repeat::Finite(n) => Repeat::Finite(n),
+        };
+
+        let mut decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+        decoder.set_limits(processing::decode_limits(max_pixels))?;
+        let mut frames = decoder.into_frames().peekable();
+        let first = match frames.next() {
+            Some(frame) => frame?,
+            None => return Ok(None),
+        };
+        if frames.peek().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self { first, rest: frames, repeat }))
+    }
+
+    /// The first frame, for outputs that can't animate
+    pub fn into_first_frame(self) -> DynamicImage {
+        DynamicImage::ImageRgba8(self.first.into_buffer())
+    }
+
+    /// Write every frame, passed through `map`, as an animated GIF or WebP
+    /// keeping each frame's delay and the loop count
+    pub fn encode(
+        self,
+        output_path: &Path,
+        encoding: OutputEncoding,
+        mut map: impl FnMut(RgbaImage) -> RgbaImage,
+    ) -> Result<(), ProcessingError> {
+        let repeat = self.repeat;
+        let frames = std::iter::once(Ok(self.first)).chain(self.rest).map(|frame| {
+            frame.map(|frame| {
+                let delay = frame.delay();
+                Frame::from_parts(map(frame.into_buffer()), 0, 0, delay)
+            })
+        });
+
+        match encoding.format {
+            ImageFormat::Gif => {
+                let output = BufWriter::new(File::create(output_path)?);
+                let mut encoder = GifEncoder::new_with_speed(output, GIF_ENCODE_SPEED);
+                encoder.set_repeat(repeat)?;
+                for frame in frames {
+                    encoder.encode_frame(frame?)?;
+                }
+                Ok(())
+            }
+            ImageFormat::WebP => encode_webp(frames, repeat, output_path, encoding.quality),
+            format => Err(ProcessingError::Unsupported(format!("animated {:?} output", format))),
+        }
+    }
+}
+
+fn encode_webp(
+    frames: impl Iterator<Item = image::ImageResult<Frame>>,
+    repeat: Repeat,
+    output_path: &Path,
+    quality: Option<u8>,
+) -> Result<(), ProcessingError> {
+    // Frames paired with the time they start, in ms
+    let mut buffered: Vec<(RgbaImage, i32)> = Vec::new();
+    let mut buffered_bytes = 0;
+    let mut start_ms = 0;
+    for frame in frames {
+        let frame = frame?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let image = frame.into_buffer();
+
+        buffered_bytes += image.as_raw().len();
+        if buffered_bytes > MAX_WEBP_FRAME_BYTES {
+            return Err(ProcessingError::Unsupported(
+                "animation too long for animated WebP output; convert it to GIF instead".to_string(),
+            ));
+        }
+        buffered.push((image, start_ms));
+        start_ms += (numer / denom.max(1)) as i32;
+    }
+
+    let Some((width, height)) = buffered.first().map(|(image, _)| image.dimensions()) else {
+        return Err(ProcessingError::Unsupported("animation without frames".to_string()));
+    };
+    let mut config = webp::WebPConfig::new()
+        .map_err(|_| ProcessingError::Unsupported("libwebp configuration".to_string()))?;
+    match quality {
+        Some(quality) => config.quality = quality.clamp(1, 100) as f32,
+        None => config.lossless = 1,
+    }
+
+    let mut encoder = webp::AnimEncoder::new(width, height, &config);
+    encoder.set_loop_count(match repeat {
+        Repeat::Infinite => 0,
+        // GIFs without a loop count play once
+        Repeat::Finite(n) => i32::from(n.max(1)),
+    });
+    for (image, start_ms) in &buffered {
+        encoder.add_frame(webp::AnimFrame::from_rgba(image.as_raw(), width, height, *start_ms));
+    }
+    let encoded = encoder
+        .try_encode()
+        .map_err(|e| ProcessingError::Unsupported(format!("animated WebP encoding failed: {:?}", e)))?;
+    std::fs::write(output_path, &*encoded)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Delay, Rgba};
+
+    const MAX_PIXELS: u64 = 100_000_000;
+
+    /// Three 8x8 frames, red, green then blue, 100ms each, looping twice
+    fn write_gif(path: &Path) {
+        let mut encoder = GifEncoder::new(File::create(path).unwrap());
+        encoder.set_repeat(Repeat::Finite(2)).unwrap();
+        for color in [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]] {
+            let frame = RgbaImage::from_pixel(8, 8, Rgba(color));
+            let delay = Delay::from_numer_denom_ms(100, 1);
+            encoder.encode_frame(Frame::from_parts(frame, 0, 0, delay)).unwrap();
+        }
+    }
+
+    fn gif_frames(path: &Path) -> Vec<Frame> {
+        let decoder = GifDecoder::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        decoder.into_frames().collect_frames().unwrap()
+    }
+
+    #[test]
+    fn test_reencodes_every_frame_with_delays_and_loop_count() {
+        let dir = std::env::temp_dir().join(format!("animation_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.gif");
+        write_gif(&input);
+
+        let output = dir.join("out.gif");
+        let animation = Animation::open(&input, MAX_PIXELS).unwrap().unwrap();
+        let mut mapped = 0;
+        animation
+            .encode(&output, OutputEncoding::new(ImageFormat::Gif), |frame| {
+                mapped += 1;
+                frame
+            })
+            .unwrap();
+        assert_eq!(mapped, 3);
+
+        let frames = gif_frames(&output);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].buffer().get_pixel(4, 4)[1], 255);
+        assert!(frames.iter().all(|frame| frame.delay().numer_denom_ms() == (100, 1)));
+        let header = gif::DecodeOptions::new().read_info(File::open(&output).unwrap()).unwrap();
+        assert_eq!(header.repeat(), gif::Repeat::Finite(2));
+
+        let webp = dir.join("out.webp");
+        Animation::open(&input, MAX_PIXELS)
+            .unwrap()
+            .unwrap()
+            .encode(&webp, OutputEncoding::new(ImageFormat::WebP), |frame| frame)
+            .unwrap();
+        let decoder = image::codecs::webp::WebPDecoder::new(BufReader::new(File::open(&webp).unwrap())).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[2].buffer().get_pixel(4, 4)[2] > 250);
+        assert!(frames.iter().all(|frame| frame.delay().numer_denom_ms() == (100, 1)));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_every_frame_counts_towards_the_pixel_limit() {
+        let dir = std::env::temp_dir().join(format!("animation_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.gif");
+        write_gif(&input);
+        assert_eq!(probe::decoded_pixels(&input).unwrap(), 3 * 8 * 8);
+
+        assert!(Animation::open(&input, 3 * 8 * 8).unwrap().is_some());
+        let err = Animation::open(&input, 8 * 8).err().unwrap();
+        assert!(matches!(err, ProcessingError::TooManyPixels { pixels: 192, max: 64 }), "{}", err);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_still_images_are_not_animations() {
+        let dir = std::env::temp_dir().join(format!("animation_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let still = dir.join("still.gif");
+        RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255])).save(&still).unwrap();
+        assert!(!is_animated(&still, MAX_PIXELS).unwrap());
+
+        let png = dir.join("still.png");
+        RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255])).save(&png).unwrap();
+        assert!(!is_animated(&png, MAX_PIXELS).unwrap());
+
+        let animated = dir.join("animated.gif");
+        write_gif(&animated);
+        assert!(is_animated(&animated, MAX_PIXELS).unwrap());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,189 @@
+// backend/src/services/archive.rs
+// Zip bundles for jobs that produce more than one output file, and of all a
+// job's outputs for download
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+
+use super::storage::ByteStream;
+
+/// Chunks in flight between the readers and the zip writer. With storage
+/// chunks of at most a few hundred KiB this bounds what an archive being
+/// built holds in memory, however large its entries.
+const ZIP_CHANNEL_CHUNKS: usize = 8;
+
+/// Write `(entry name, file on disk)` pairs into a zip. Media outputs are
+/// already compressed, so entries are stored rather than deflated.
+pub fn zip_files(entries: &[(String, PathBuf)], output: &Path) -> std::io::Result<()> {
+    let file = std::fs::File::create(output)?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+
+    for (name, path) in entries {
+        zip.start_file(name.as_str(), options).map_err(std::io::Error::other)?;
+        zip.write_all(&std::fs::read(path)?)?;
+    }
+
+    zip.finish().map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+/// An entry of `zip_streams`, read as it is written
+pub struct StreamedEntry {
+    pub name: String,
+    /// Bytes the stream holds; entries of 4 GiB or more are written as Zip64
+    pub size: u64,
+    pub content: ByteStream,
+}
+
+enum ZipPart {
+    Start { name: String, size: u64 },
+    Chunk(Bytes),
+}
+
+/// Write `entries` into a zip at `output`, stored like `zip_files`. The
+/// streams are read on this task and written on a blocking one, a chunk at a
+/// time, so nothing larger than a few chunks is ever in memory.
+pub async fn zip_streams(entries: Vec<StreamedEntry>, output: &Path) -> std::io::Result<()> {
+    let file = std::fs::File::create(output)?;
+    let (parts, mut received) = tokio::sync::mpsc::channel(ZIP_CHANNEL_CHUNKS);
+    let writer = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        let mut zip = zip::ZipWriter::new(std::io::BufWriter::new(file));
+        while let Some(part) = received.blocking_recv() {
+            match part {
+                ZipPart::Start { name, size } => {
+                    let options = zip::write::SimpleFileOptions::default()
+                        .compression_method(zip::CompressionMethod::Stored)
+                        .large_file(size >= u32::MAX as u64);
+                    zip.start_file(name, options).map_err(std::io::Error::other)?;
+                }
+                ZipPart::Chunk(bytes) => zip.write_all(&bytes)?,
+            }
+        }
+        zip.finish().map_err(std::io::Error::other)?.flush()
+    });
+
+    // A send only fails once the writer has stopped, and it says why
+    let read = async {
+        for entry in entries {
+            let StreamedEntry { name, size, mut content } = entry;
+            if parts.send(ZipPart::Start { name, size }).await.is_err() {
+                return Ok(());
+            }
+            while let Some(chunk) = content.next().await {
+                if parts.send(ZipPart::Chunk(chunk?)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+    drop(parts);
+
+    let written = writer.await.map_err(std::io::Error::other)?;
+    read.and(written)
+}
+
+/// Make entry names unique by suffixing repeats: `a.png`, `a (2).png`, ...
+/// Path separators are dropped so names cannot escape the archive root.
+pub fn unique_entry_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let name = Path::new(name.trim())
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .filter(|n| !n.is_empty())
+                .unwrap_or_else(|| "output".to_string());
+            let (stem, ext) = match name.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+                _ => (name.clone(), String::new()),
+            };
+
+            let mut candidate = name;
+            let mut n = 2;
+            while !seen.insert(candidate.to_lowercase()) {
+                candidate = format!("{} ({}){}", stem, n, ext);
+                n += 1;
+            }
+            candidate
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_unique_entry_names() {
+        let names = unique_entry_names(["a.png", "b.png", "A.png", "a.png", "../../x.png", ""]);
+        assert_eq!(names, ["a.png", "b.png", "A (2).png", "a (3).png", "x.png", "output"]);
+    }
+
+    #[test]
+    fn test_zip_files_round_trip() {
+        let dir = std::env::temp_dir().join(format!("archive_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("1.bin");
+        let second = dir.join("2.bin");
+        std::fs::write(&first, b"first").unwrap();
+        std::fs::write(&second, b"second").unwrap();
+
+        let output = dir.join("out.zip");
+        zip_files(
+            &[("one.bin".to_string(), first), ("two.bin".to_string(), second)],
+            &output,
+        )
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut contents = String::new();
+        archive.by_name("two.bin").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "second");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_zip_streams_writes_entries_chunk_by_chunk() {
+        let dir = std::env::temp_dir().join(format!("archive_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stream = |chunks: Vec<&'static [u8]>| -> ByteStream {
+            Box::pin(futures_util::stream::iter(chunks.into_iter().map(|c| Ok(Bytes::from_static(c)))))
+        };
+        // More chunks than the channel holds
+        let many = vec![&b"x"[..]; ZIP_CHANNEL_CHUNKS * 4];
+
+        let output = dir.join("out.zip");
+        let entries = vec![
+            StreamedEntry { name: "one.bin".to_string(), size: 11, content: stream(vec![b"first", b" part"]) },
+            StreamedEntry { name: "many.bin".to_string(), size: many.len() as u64, content: stream(many.clone()) },
+            StreamedEntry { name: "empty.bin".to_string(), size: 0, content: stream(vec![]) },
+        ];
+        zip_streams(entries, &output).await.unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut contents = String::new();
+        archive.by_name("one.bin").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "first part");
+        assert_eq!(archive.by_name("many.bin").unwrap().size(), many.len() as u64);
+
+        // A failed read fails the archive
+        let failing: ByteStream = Box::pin(futures_util::stream::iter([Err(std::io::Error::other("gone"))]));
+        let entries = vec![StreamedEntry { name: "bad.bin".to_string(), size: 1, content: failing }];
+        assert!(zip_streams(entries, &output).await.is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
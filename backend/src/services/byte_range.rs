@@ -0,0 +1,113 @@
+// backend/src/services/byte_range.rs
+// HTTP `Range` header parsing for partial result downloads
+
+/// Inclusive window of an object's bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Number of bytes in the window
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// `Content-Range` value for this window of an object of `total` bytes
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// The requested range starts past the end of the object
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unsatisfiable;
+
+/// Parse a `Range` header against an object of `size` bytes. `Ok(None)` means
+/// send the whole object, as for a header that is not a `bytes` range or is
+/// malformed. Only the first range of a multi-range request is honoured.
+pub fn parse(header: &str, size: u64) -> Result<Option<ByteRange>, Unsatisfiable> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let first = spec.split(',').next().unwrap_or_default().trim();
+    let Some((start, end)) = first.split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    // `bytes=-N` asks for the last N bytes
+    if start.is_empty() {
+        let Ok(suffix) = end.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || size == 0 {
+            return Err(Unsatisfiable);
+        }
+        return Ok(Some(ByteRange {
+            start: size.saturating_sub(suffix),
+            end: size - 1,
+        }));
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return Ok(None);
+    };
+    let end = if end.is_empty() {
+        None
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return Ok(None),
+        }
+    };
+    if start >= size {
+        return Err(Unsatisfiable);
+    }
+
+    Ok(Some(ByteRange {
+        start,
+        // An end past the object is clamped to its last byte
+        end: end.map_or(size - 1, |end| end.min(size - 1)),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> Option<ByteRange> {
+        Some(ByteRange { start, end })
+    }
+
+    #[test]
+    fn test_parse_bounded_and_open_ended() {
+        assert_eq!(parse("bytes=0-99", 1000), Ok(range(0, 99)));
+        assert_eq!(parse("bytes=100-", 1000), Ok(range(100, 999)));
+        assert_eq!(parse("bytes=900-5000", 1000), Ok(range(900, 999)));
+        // Multi-range requests are collapsed to the first range
+        assert_eq!(parse("bytes=10-19, 50-59", 1000), Ok(range(10, 19)));
+        assert_eq!(range(100, 999).unwrap().content_range(1000), "bytes 100-999/1000");
+    }
+
+    #[test]
+    fn test_parse_suffix() {
+        assert_eq!(parse("bytes=-500", 1000), Ok(range(500, 999)));
+        assert_eq!(parse("bytes=-5000", 1000), Ok(range(0, 999)));
+        assert_eq!(parse("bytes=-0", 1000), Err(Unsatisfiable));
+    }
+
+    #[test]
+    fn test_parse_out_of_bounds_and_malformed() {
+        assert_eq!(parse("bytes=1000-", 1000), Err(Unsatisfiable));
+        assert_eq!(parse("bytes=2000-3000", 1000), Err(Unsatisfiable));
+        assert_eq!(parse("bytes=0-", 0), Err(Unsatisfiable));
+
+        // Headers that can't be understood are ignored rather than rejected
+        assert_eq!(parse("items=0-10", 1000), Ok(None));
+        assert_eq!(parse("bytes=20-10", 1000), Ok(None));
+        assert_eq!(parse("bytes=abc", 1000), Ok(None));
+        assert_eq!(parse("bytes=x-10", 1000), Ok(None));
+    }
+}
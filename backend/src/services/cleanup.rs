@@ -0,0 +1,648 @@
+// backend/src/services/cleanup.rs
+// Periodic sweep of expired assets, deleted assets past their restore window,
+// expired job results and the history of jobs past retention, files left by
+// deleted accounts, abandoned resumable uploads, expired idempotency keys,
+// past days' quota counts and orphaned temp files
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::ProcessingConfig;
+use crate::db;
+use super::resumable::PART_DIR;
+use super::{Storage, StorageLocation};
+
+/// Rows taken from each table per sweep; anything left waits for the next run
+const SWEEP_BATCH: i64 = 500;
+
+/// How long a failed job's history is kept after it failed. A completed job's
+/// goes with its result.
+const FAILED_JOB_EVENT_RETENTION_DAYS: i64 = 7;
+
+/// Days of quota counts kept before today, for refunding jobs that fail
+/// after midnight
+const QUOTA_USAGE_RETENTION_DAYS: u64 = 1;
+
+/// What one sweep removed. `bytes_freed` leaves out job results saved before
+/// outputs were recorded with their size.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SweepSummary {
+    pub assets: u64,
+    pub results: u64,
+    /// History entries of jobs past retention
+    pub job_events: u64,
+    /// Files of deleted accounts
+    pub leftovers: u64,
+    /// Resumable uploads left unfinished
+    pub abandoned_uploads: u64,
+    pub idempotency_keys: u64,
+    /// Daily quota counts of past days
+    pub quota_usage: u64,
+    pub temp_files: u64,
+    pub bytes_freed: u64,
+    /// Deletions that failed and will be retried by the next sweep
+    pub failures: u64,
+}
+
+/// Sweep now and then every `cleanup_interval_seconds` until `shutdown` is
+/// cancelled. A sweep in progress is allowed to finish.
+pub fn start_cleanup(
+    db_pool: sqlx::PgPool,
+    storage: Arc<dyn Storage>,
+    config: ProcessingConfig,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(config.cleanup_interval_seconds.max(1));
+        loop {
+            let summary = sweep(&db_pool, storage.as_ref(), &config).await;
+            if summary == SweepSummary::default() {
+                tracing::debug!("Cleanup sweep found nothing to remove");
+            } else {
+                tracing::info!(
+                    "Cleanup sweep removed {} asset(s), {} job result(s), {} job event(s), {} deleted account file(s), {} abandoned upload(s), {} idempotency key(s), {} quota count(s) and {} temp file(s), freeing {} bytes; {} deletion(s) failed",
+                    summary.assets,
+                    summary.results,
+                    summary.job_events,
+                    summary.leftovers,
+                    summary.abandoned_uploads,
+                    summary.idempotency_keys,
+                    summary.quota_usage,
+                    summary.temp_files,
+                    summary.bytes_freed,
+                    summary.failures
+                );
+            }
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+        tracing::info!("Cleanup task stopped after shutdown");
+    })
+}
+
+/// Remove expired assets, deleted ones that can no longer be restored,
+/// expired result files, the history of jobs past retention, deleted
+/// accounts' files, abandoned uploads, expired idempotency keys, past days'
+/// quota counts and stale temp files.
+/// Failures are logged and counted rather than ending the sweep.
+pub async fn sweep(
+    db_pool: &sqlx::PgPool,
+    storage: &dyn Storage,
+    config: &ProcessingConfig,
+) -> SweepSummary {
+    let mut summary = SweepSummary::default();
+    sweep_assets(db_pool, storage, config.asset_restore_window(), &mut summary).await;
+    sweep_results(db_pool, storage, &mut summary).await;
+    sweep_job_events(db_pool, &mut summary).await;
+    sweep_pending_deletions(db_pool, storage, &mut summary).await;
+    let upload_ttl = Duration::from_secs(config.upload_session_ttl_hours * 3600);
+    sweep_uploads(db_pool, upload_ttl, &mut summary).await;
+    sweep_idempotency_keys(db_pool, &mut summary).await;
+    sweep_quota_usage(db_pool, &mut summary).await;
+    // Part files idle as long as an abandoned upload, including any whose
+    // row went with a deleted account
+    let part_dir = Path::new(&config.temp_dir).join(PART_DIR);
+    if part_dir.exists() {
+        sweep_temp_dir(&part_dir, upload_ttl, &mut summary).await;
+    }
+    sweep_temp_dir(
+        Path::new(&config.temp_dir),
+        Duration::from_secs(config.temp_file_max_age_hours * 3600),
+        &mut summary,
+    )
+    .await;
+    summary
+}
+
+/// Delete the stored files of expired assets and of assets deleted more than
+/// `restore_window` ago, then their rows. A row whose files could not all be
+/// deleted is kept so the next sweep tries again.
+async fn sweep_assets(
+    db_pool: &sqlx::PgPool,
+    storage: &dyn Storage,
+    restore_window: chrono::Duration,
+    summary: &mut SweepSummary,
+) {
+    let deleted_before = chrono::Utc::now() - restore_window;
+    let assets = match db::MediaAsset::find_expired(db_pool, SWEEP_BATCH, deleted_before).await {
+        Ok(assets) => assets,
+        Err(e) => {
+            tracing::error!("Failed to list expired assets: {:?}", e);
+            summary.failures += 1;
+            return;
+        }
+    };
+
+    for asset in assets {
+        let locations = [&asset.result_location, &asset.thumbnail_location];
+        if !delete_objects(storage, locations.into_iter().flatten()).await {
+            summary.failures += 1;
+            continue;
+        }
+
+        match db::MediaAsset::delete(db_pool, asset.id).await {
+            Ok(()) => {
+                summary.assets += 1;
+                summary.bytes_freed += asset.size_bytes.max(0) as u64;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to delete expired asset {}: {:?}", asset.id, e);
+                summary.failures += 1;
+            }
+        }
+    }
+}
+
+/// Delete result files past their `result_expires_at`. The job rows stay for
+/// history, without a result location.
+async fn sweep_results(db_pool: &sqlx::PgPool, storage: &dyn Storage, summary: &mut SweepSummary) {
+    let jobs = match db::Job::find_expired_results(db_pool, SWEEP_BATCH).await {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            tracing::error!("Failed to list expired job results: {:?}", e);
+            summary.failures += 1;
+            return;
+        }
+    };
+
+    for job in jobs {
+        let outputs = match db::JobOutput::list(db_pool, job.id).await {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                tracing::warn!("Failed to list outputs of job {}: {:?}", job.id, e);
+                summary.failures += 1;
+                continue;
+            }
+        };
+        // Results saved before outputs were recorded are only in `result_location`
+        let locations: Vec<&String> = if outputs.is_empty() {
+            job.result_location.iter().collect()
+        } else {
+            outputs.iter().map(|o| &o.location).collect()
+        };
+        if !delete_objects(storage, locations).await {
+            summary.failures += 1;
+            continue;
+        }
+
+        match db::Job::clear_result(db_pool, job.id).await {
+            Ok(()) => {
+                summary.results += 1;
+                summary.bytes_freed += outputs.iter().map(|o| o.size_bytes.max(0) as u64).sum::<u64>();
+            }
+            Err(e) => {
+                tracing::warn!("Failed to clear result of job {}: {:?}", job.id, e);
+                summary.failures += 1;
+            }
+        }
+    }
+}
+
+/// Retry the stored files of deleted accounts that could not be removed at
+/// the time
+async fn sweep_pending_deletions(db_pool: &sqlx::PgPool, storage: &dyn Storage, summary: &mut SweepSummary) {
+    let pending = match db::PendingDeletion::list(db_pool, SWEEP_BATCH).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            tracing::error!("Failed to list pending deletions: {:?}", e);
+            summary.failures += 1;
+            return;
+        }
+    };
+
+    let mut deleted = Vec::new();
+    for object in pending {
+        if delete_objects(storage, [&object.location]).await {
+            deleted.push(object.location);
+        } else {
+            summary.failures += 1;
+        }
+    }
+    if deleted.is_empty() {
+        return;
+    }
+    match db::PendingDeletion::clear(db_pool, &deleted).await {
+        Ok(()) => summary.leftovers += deleted.len() as u64,
+        Err(e) => {
+            tracing::warn!("Failed to clear {} pending deletion(s): {:?}", deleted.len(), e);
+            summary.failures += 1;
+        }
+    }
+}
+
+/// Forget resumable uploads nothing has been sent to for `idle`. Their part
+/// files are as old, so the part file sweep that follows removes them.
+async fn sweep_uploads(db_pool: &sqlx::PgPool, idle: Duration, summary: &mut SweepSummary) {
+    match db::Upload::delete_idle(db_pool, idle, SWEEP_BATCH).await {
+        Ok(ids) => summary.abandoned_uploads += ids.len() as u64,
+        Err(e) => {
+            tracing::error!("Failed to delete abandoned uploads: {:?}", e);
+            summary.failures += 1;
+        }
+    }
+}
+
+async fn sweep_job_events(db_pool: &sqlx::PgPool, summary: &mut SweepSummary) {
+    let failed_before = chrono::Utc::now() - chrono::Duration::days(FAILED_JOB_EVENT_RETENTION_DAYS);
+    match db::JobEvent::delete_expired(db_pool, failed_before, SWEEP_BATCH).await {
+        Ok(deleted) => summary.job_events += deleted,
+        Err(e) => {
+            tracing::error!("Failed to delete expired job events: {:?}", e);
+            summary.failures += 1;
+        }
+    }
+}
+
+async fn sweep_idempotency_keys(db_pool: &sqlx::PgPool, summary: &mut SweepSummary) {
+    match db::IdempotencyKey::delete_expired(db_pool, SWEEP_BATCH).await {
+        Ok(deleted) => summary.idempotency_keys += deleted,
+        Err(e) => {
+            tracing::error!("Failed to delete expired idempotency keys: {:?}", e);
+            summary.failures += 1;
+        }
+    }
+}
+
+async fn sweep_quota_usage(db_pool: &sqlx::PgPool, summary: &mut SweepSummary) {
+    let before = chrono::Utc::now().date_naive() - chrono::Days::new(QUOTA_USAGE_RETENTION_DAYS);
+    match db::QuotaUsage::delete_before(db_pool, before).await {
+        Ok(deleted) => summary.quota_usage += deleted,
+        Err(e) => {
+            tracing::error!("Failed to delete past quota counts: {:?}", e);
+            summary.failures += 1;
+        }
+    }
+}
+
+/// Whether every object was deleted (or was already gone)
+async fn delete_objects<'a>(
+    storage: &dyn Storage,
+    locations: impl IntoIterator<Item = &'a String>,
+) -> bool {
+    let mut deleted = true;
+    for location in locations {
+        let result = match location.parse::<StorageLocation>() {
+            Ok(parsed) => storage.delete(&parsed).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to delete stored object {}: {:?}", location, e);
+            deleted = false;
+        }
+    }
+    deleted
+}
+
+/// Remove entries in `temp_dir` last modified more than `max_age` ago. Jobs
+/// and uploads clean up after themselves, so these were left by a crash.
+/// The part file directory is swept on its own schedule.
+async fn sweep_temp_dir(temp_dir: &Path, max_age: Duration, summary: &mut SweepSummary) {
+    let mut entries = match tokio::fs::read_dir(temp_dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("Failed to read temp dir {}: {:?}", temp_dir.display(), e);
+            summary.failures += 1;
+            return;
+        }
+    };
+
+    let now = SystemTime::now();
+    loop {
+        let entry = match entries.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Failed to read temp dir {}: {:?}", temp_dir.display(), e);
+                summary.failures += 1;
+                break;
+            }
+        };
+        if entry.file_name() == PART_DIR {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        let stale = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age > max_age);
+        if !stale {
+            continue;
+        }
+
+        let path = entry.path();
+        let removed = if metadata.is_dir() {
+            tokio::fs::remove_dir_all(&path).await
+        } else {
+            tokio::fs::remove_file(&path).await
+        };
+        match removed {
+            Ok(()) => {
+                summary.temp_files += 1;
+                if metadata.is_file() {
+                    summary.bytes_freed += metadata.len();
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to remove stale temp file {}: {:?}", path.display(), e);
+                summary.failures += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the file behind a `local://` location under `base` exists
+    #[cfg(feature = "db-tests")]
+    fn stored(base: &Path, location: &str) -> bool {
+        base.join(location.strip_prefix("local://").unwrap()).exists()
+    }
+
+    #[tokio::test]
+    async fn test_sweep_temp_dir_removes_only_stale_entries() {
+        let dir = std::env::temp_dir().join(format!("cleanup_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("stale_frames")).unwrap();
+        std::fs::write(dir.join("stale.bin"), b"12345").unwrap();
+        std::fs::write(dir.join("fresh.bin"), b"fresh").unwrap();
+
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 3600);
+        for stale in ["stale.bin", "stale_frames"] {
+            std::fs::File::open(dir.join(stale))
+                .unwrap()
+                .set_modified(two_hours_ago)
+                .unwrap();
+        }
+
+        let mut summary = SweepSummary::default();
+        sweep_temp_dir(&dir, Duration::from_secs(3600), &mut summary).await;
+
+        assert_eq!(summary.temp_files, 2);
+        assert_eq!(summary.bytes_freed, 5);
+        assert!(!dir.join("stale.bin").exists());
+        assert!(!dir.join("stale_frames").exists());
+        assert!(dir.join("fresh.bin").exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_sweep_assets_deletes_files_then_rows() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let base = std::env::temp_dir().join(format!("cleanup_storage_{}", uuid::Uuid::new_v4()));
+        let storage = super::super::LocalStorage::new(&base);
+        let user = db::User::create(&pool, &format!("{}@cleanup.test", uuid::Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let location = storage.save_bytes(b"upload", user.id, "a.png").await.unwrap().to_string();
+        let asset = db::MediaAsset::create(&pool, user.id.into(), "a.png", "png", 6, None).await.unwrap();
+        db::MediaAsset::update_status(&pool, asset.id, "uploaded", Some(&location)).await.unwrap();
+        sqlx::query("UPDATE media_assets SET expires_at = NOW() - INTERVAL '1 hour' WHERE id = $1")
+            .bind(asset.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut summary = SweepSummary::default();
+        sweep_assets(&pool, &storage, chrono::Duration::days(7), &mut summary).await;
+
+        assert!(summary.assets >= 1);
+        assert!(!stored(&base, &location));
+        assert!(db::MediaAsset::find_by_id(&pool, asset.id).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_sweep_assets_keeps_deleted_assets_through_the_restore_window() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let base = std::env::temp_dir().join(format!("cleanup_storage_{}", uuid::Uuid::new_v4()));
+        let storage = super::super::LocalStorage::new(&base);
+        let user = db::User::create(&pool, &format!("{}@cleanup.test", uuid::Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let mut deleted = Vec::new();
+        for age in ["1 hour", "8 days"] {
+            let location = storage.save_bytes(b"upload", user.id, "a.png").await.unwrap().to_string();
+            let asset = db::MediaAsset::create(&pool, user.id.into(), "a.png", "png", 6, None).await.unwrap();
+            db::MediaAsset::update_status(&pool, asset.id, "uploaded", Some(&location)).await.unwrap();
+            db::MediaAsset::soft_delete(&pool, asset.id).await.unwrap().unwrap();
+            sqlx::query("UPDATE media_assets SET deleted_at = NOW() - $2::INTERVAL WHERE id = $1")
+                .bind(asset.id)
+                .bind(age)
+                .execute(&pool)
+                .await
+                .unwrap();
+            deleted.push((asset.id, location));
+        }
+        let [(recent, recent_location), (old, old_location)] = <[_; 2]>::try_from(deleted).unwrap();
+        assert_eq!(db::MediaAsset::storage_used(&pool, user.id).await.unwrap(), 0);
+
+        let mut summary = SweepSummary::default();
+        sweep_assets(&pool, &storage, chrono::Duration::days(7), &mut summary).await;
+
+        assert!(summary.assets >= 1);
+        assert!(!stored(&base, &old_location));
+        assert!(db::MediaAsset::find_by_id(&pool, old).await.unwrap().is_none());
+        assert!(stored(&base, &recent_location));
+        let since = chrono::Utc::now() - chrono::Duration::days(7);
+        let restored = db::MediaAsset::restore(&pool, recent, since).await.unwrap().unwrap();
+        assert!(restored.deleted_at.is_none());
+
+        db::User::delete_account(&pool, user.id).await.unwrap();
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_sweep_job_events_keeps_only_jobs_within_retention() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let user = db::User::create(&pool, &format!("{}@cleanup.test", uuid::Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let mut jobs = Vec::new();
+        for _ in 0..5 {
+            let job = db::Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+                .await
+                .unwrap();
+            db::JobEvent::record(&pool, job.id, "queued", None).await;
+            jobs.push(job.id);
+        }
+        let [expired_result, kept_result, old_failure, recent_failure, queued] = <[_; 5]>::try_from(jobs).unwrap();
+        let retentions = [(expired_result, chrono::Duration::seconds(-1)), (kept_result, chrono::Duration::hours(1))];
+        for (id, retention) in retentions {
+            let output = db::JobOutput {
+                job_id: id,
+                output_index: 0,
+                location: format!("local://{}/result.png", id),
+                filename: "result.png".to_string(),
+                size_bytes: 6,
+                content_type: "image/png".to_string(),
+                etag: "etag".to_string(),
+            };
+            let result = db::JobResult {
+                location: output.location.clone(),
+                width: None,
+                height: None,
+                size_bytes: 6,
+                format: "png".to_string(),
+                duration_ms: 10,
+            };
+            db::Job::complete(&pool, id, &result, &[output], retention).await.unwrap();
+        }
+        for id in [old_failure, recent_failure] {
+            db::Job::fail(&pool, id, "Asset not found", "processing_failed").await.unwrap();
+        }
+        sqlx::query("UPDATE jobs SET failed_at = NOW() - INTERVAL '8 days' WHERE id = $1")
+            .bind(old_failure)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // Other tests' jobs may fill a batch; sweep until nothing is left
+        loop {
+            let mut summary = SweepSummary::default();
+            sweep_job_events(&pool, &mut summary).await;
+            assert_eq!(summary.failures, 0);
+            if summary.job_events == 0 {
+                break;
+            }
+        }
+
+        let mut remaining = Vec::new();
+        for id in [expired_result, kept_result, old_failure, recent_failure, queued] {
+            remaining.push(db::JobEvent::list(&pool, id, 10, 0).await.unwrap().1);
+        }
+        assert_eq!(remaining, [0, 1, 0, 1, 1]);
+        db::User::delete_account(&pool, user.id).await.unwrap();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_sweep_results_deletes_only_expired_results() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let base = std::env::temp_dir().join(format!("cleanup_storage_{}", uuid::Uuid::new_v4()));
+        let storage = super::super::LocalStorage::new(&base);
+        let user = db::User::create(&pool, &format!("{}@cleanup.test", uuid::Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+
+        let mut jobs = Vec::new();
+        for retention in [chrono::Duration::seconds(-1), chrono::Duration::hours(24)] {
+            let job = db::Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+                .await
+                .unwrap();
+            let mut outputs = Vec::new();
+            for (index, name) in ["result.png", "extra.png"].into_iter().enumerate() {
+                let location = storage.save_bytes(b"result", user.id, name).await.unwrap().to_string();
+                outputs.push(db::JobOutput {
+                    job_id: job.id,
+                    output_index: index as i32,
+                    location,
+                    filename: name.to_string(),
+                    size_bytes: 6,
+                    content_type: "image/png".to_string(),
+                    etag: "etag".to_string(),
+                });
+            }
+            let result = db::JobResult {
+                location: outputs[0].location.clone(),
+                width: Some(1),
+                height: Some(1),
+                size_bytes: 6,
+                format: "png".to_string(),
+                duration_ms: 10,
+            };
+            db::Job::complete(&pool, job.id, &result, &outputs, retention).await.unwrap();
+            jobs.push((job.id, outputs.into_iter().map(|o| o.location).collect::<Vec<_>>()));
+        }
+        let [(expired, expired_locations), (kept, kept_locations)] = <[_; 2]>::try_from(jobs).unwrap();
+
+        let mut summary = SweepSummary::default();
+        sweep_results(&pool, &storage, &mut summary).await;
+
+        assert!(summary.results >= 1 && summary.bytes_freed >= 12);
+        assert!(expired_locations.iter().all(|location| !stored(&base, location)));
+        assert!(kept_locations.iter().all(|location| stored(&base, location)));
+        assert!(db::JobOutput::list(&pool, expired).await.unwrap().is_empty());
+        let expired = db::Job::find_by_id(&pool, expired).await.unwrap().unwrap();
+        assert!(expired.result_location.is_none());
+        assert!(expired.result_expired(chrono::Utc::now()));
+
+        // An expired result cannot be extended back to life
+        let later = chrono::Utc::now() + chrono::Duration::days(1);
+        assert!(db::Job::extend_result(&pool, expired.id, later).await.unwrap().is_none());
+        let kept = db::Job::extend_result(&pool, kept, later).await.unwrap().unwrap();
+        assert_eq!(kept.result_expires_at.map(|t| t.timestamp()), Some(later.timestamp()));
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_sweep_retries_deleted_accounts_files() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let base = std::env::temp_dir().join(format!("cleanup_storage_{}", uuid::Uuid::new_v4()));
+        let storage = super::super::LocalStorage::new(&base);
+        let user = db::User::create(&pool, &format!("{}@cleanup.test", uuid::Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let upload = storage.save_bytes(b"upload", user.id, "a.png").await.unwrap().to_string();
+        let asset = db::MediaAsset::create(&pool, user.id.into(), "a.png", "png", 6, None).await.unwrap();
+        db::MediaAsset::update_status(&pool, asset.id, "uploaded", Some(&upload)).await.unwrap();
+        let lut = storage.save_bytes(b"LUT_3D_SIZE 2", user.id, "grade.cube").await.unwrap().to_string();
+        db::LutFile::create(&pool, user.id.into(), "grade.cube", &lut, 13).await.unwrap();
+
+        // As if the request deleting the account had failed to reach storage
+        let mut locations = db::User::delete_account(&pool, user.id).await.unwrap().unwrap();
+        locations.sort();
+        let mut expected = vec![upload.clone(), lut.clone()];
+        expected.sort();
+        assert_eq!(locations, expected);
+        assert!(db::User::find_by_id(&pool, user.id).await.unwrap().is_none());
+        assert!(db::User::delete_account(&pool, user.id).await.unwrap().is_none());
+
+        let mut summary = SweepSummary::default();
+        sweep_pending_deletions(&pool, &storage, &mut summary).await;
+
+        assert!(summary.leftovers >= 2);
+        assert!(!stored(&base, &upload) && !stored(&base, &lut));
+        let pending: Vec<String> = db::PendingDeletion::list(&pool, i64::MAX)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.location)
+            .collect();
+        assert!(!pending.contains(&upload) && !pending.contains(&lut));
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+}
@@ -0,0 +1,87 @@
+// backend/src/services/conditional.rs
+// Conditional GET (`If-None-Match` / `If-Modified-Since`) for result downloads
+
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Utc};
+
+/// Quoted strong `ETag` value for a stored content hash
+pub fn etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// IMF-fixdate, the format of `Last-Modified` and `If-Modified-Since`
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether the client's cached copy is current, so a 304 can be sent instead
+/// of the body. `If-None-Match` takes precedence: `If-Modified-Since` is only
+/// consulted when it is absent.
+pub fn not_modified(
+    headers: &HeaderMap,
+    etag: Option<&str>,
+    last_modified: Option<DateTime<Utc>>,
+) -> bool {
+    if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+        let (Ok(value), Some(etag)) = (value.to_str(), etag) else {
+            return false;
+        };
+        return value.split(',').map(str::trim).any(|candidate| {
+            // Weak comparison, as GET allows
+            candidate == "*" || candidate.trim_start_matches("W/") == etag
+        });
+    }
+
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+    match (since, last_modified) {
+        // HTTP dates have whole-second precision
+        (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let tag = etag("abc123");
+        assert_eq!(tag, "\"abc123\"");
+
+        assert!(not_modified(&headers(header::IF_NONE_MATCH, "\"abc123\""), Some(&tag), None));
+        assert!(not_modified(&headers(header::IF_NONE_MATCH, "\"x\", W/\"abc123\""), Some(&tag), None));
+        assert!(not_modified(&headers(header::IF_NONE_MATCH, "*"), Some(&tag), None));
+        assert!(!not_modified(&headers(header::IF_NONE_MATCH, "\"other\""), Some(&tag), None));
+        // Nothing to compare against for results stored before hashing
+        assert!(!not_modified(&headers(header::IF_NONE_MATCH, "\"abc123\""), None, None));
+        assert!(!not_modified(&HeaderMap::new(), Some(&tag), None));
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let modified = DateTime::parse_from_rfc3339("2024-03-05T10:00:00.750+00:00").unwrap().to_utc();
+        assert_eq!(http_date(modified), "Tue, 05 Mar 2024 10:00:00 GMT");
+
+        let same = headers(header::IF_MODIFIED_SINCE, "Tue, 05 Mar 2024 10:00:00 GMT");
+        assert!(not_modified(&same, None, Some(modified)));
+        let earlier = headers(header::IF_MODIFIED_SINCE, "Tue, 05 Mar 2024 09:59:59 GMT");
+        assert!(!not_modified(&earlier, None, Some(modified)));
+        let garbage = headers(header::IF_MODIFIED_SINCE, "yesterday");
+        assert!(!not_modified(&garbage, None, Some(modified)));
+
+        // A non-matching If-None-Match wins over a satisfied If-Modified-Since
+        let mut both = same.clone();
+        both.insert(header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        assert!(!not_modified(&both, Some("\"abc123\""), Some(modified)));
+    }
+}
@@ -0,0 +1,81 @@
+// backend/src/services/download_token.rs
+// Short-lived signed tokens that let a client fetch a job result without auth headers
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum TokenError {
+    #[error("download token is malformed")]
+    Malformed,
+    #[error("download token signature is invalid")]
+    BadSignature,
+    #[error("download token has expired")]
+    Expired,
+}
+
+/// `<job id>.<expiry unix seconds>.<hex HMAC of both>`, safe to put in a path
+pub fn issue(secret: &str, job_id: Uuid, expires_at: DateTime<Utc>) -> String {
+    let payload = format!("{}.{}", job_id.simple(), expires_at.timestamp());
+    let signature = hex::encode(mac(secret, &payload).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+/// The job a token grants access to, if its signature holds and it has not expired
+pub fn verify(secret: &str, token: &str, now: DateTime<Utc>) -> Result<Uuid, TokenError> {
+    let (payload, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+    let signature = hex::decode(signature).map_err(|_| TokenError::Malformed)?;
+    mac(secret, payload)
+        .verify_slice(&signature)
+        .map_err(|_| TokenError::BadSignature)?;
+
+    let (job_id, expires_at) = payload.split_once('.').ok_or(TokenError::Malformed)?;
+    let job_id = Uuid::parse_str(job_id).map_err(|_| TokenError::Malformed)?;
+    let expires_at: i64 = expires_at.parse().map_err(|_| TokenError::Malformed)?;
+    if now.timestamp() >= expires_at {
+        return Err(TokenError::Expired);
+    }
+
+    Ok(job_id)
+}
+
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_round_trip_and_expiry() {
+        let job_id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = issue("secret", job_id, now + Duration::minutes(5));
+
+        assert_eq!(verify("secret", &token, now), Ok(job_id));
+        assert_eq!(verify("secret", &token, now + Duration::minutes(5)), Err(TokenError::Expired));
+        assert_eq!(verify("other", &token, now), Err(TokenError::BadSignature));
+    }
+
+    #[test]
+    fn test_tampered_tokens_are_rejected() {
+        let now = Utc::now();
+        let token = issue("secret", Uuid::new_v4(), now + Duration::minutes(5));
+
+        // Pushing the expiry out invalidates the signature
+        let (job_id, rest) = token.split_once('.').unwrap();
+        let signature = rest.rsplit_once('.').unwrap().1;
+        let extended = format!("{}.{}.{}", job_id, now.timestamp() + 3600 * 24, signature);
+        assert_eq!(verify("secret", &extended, now), Err(TokenError::BadSignature));
+
+        assert_eq!(verify("secret", "not-a-token", now), Err(TokenError::Malformed));
+        assert_eq!(verify("secret", "a.b.zz", now), Err(TokenError::Malformed));
+    }
+}
@@ -0,0 +1,246 @@
+// backend/src/services/ffmpeg.rs
+// Locating ffmpeg and ffprobe at startup and what the installed build can
+// encode. Without them video requests are refused up front rather than queued
+// as jobs bound to fail.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use super::processing::ProcessingError;
+use super::video::{self, ConvertOptions};
+
+/// What the located ffmpeg reported about itself
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// As `ffmpeg -version` gives it, e.g. `6.1.1`
+    pub version: String,
+    /// Names from `ffmpeg -encoders`, e.g. `libx264`
+    pub encoders: BTreeSet<String>,
+}
+
+/// The ffmpeg and ffprobe video work runs, checked once
+#[derive(Debug, Clone)]
+pub struct Ffmpeg {
+    ffmpeg: PathBuf,
+    ffprobe: PathBuf,
+    /// The tool that could not be run, if either
+    capabilities: Result<Capabilities, &'static str>,
+}
+
+impl Ffmpeg {
+    /// Run `ffmpeg_path` for its version and encoders and check ffprobe runs
+    /// beside it. Logs which features are off when either is missing.
+    pub async fn detect(ffmpeg_path: &str) -> Self {
+        let ffmpeg = PathBuf::from(ffmpeg_path);
+        let ffprobe = ffprobe_beside(&ffmpeg);
+        let version = run(&ffmpeg, &["-version"]).await;
+        let encoders = run(&ffmpeg, &["-hide_banner", "-encoders"]).await;
+        let capabilities = match (version, encoders, run(&ffprobe, &["-version"]).await) {
+            (Some(version), Some(encoders), Some(_)) => Ok(Capabilities {
+                version: parse_version(&version).unwrap_or_default(),
+                encoders: parse_encoders(&encoders),
+            }),
+            (None, _, _) | (_, None, _) => Err("ffmpeg"),
+            (_, _, None) => Err("ffprobe"),
+        };
+        let detected = Self { ffmpeg, ffprobe, capabilities };
+        detected.log_status();
+        detected
+    }
+
+    fn log_status(&self) {
+        match &self.capabilities {
+            Ok(capabilities) => {
+                tracing::info!(
+                    "✓ ffmpeg {} at {} with {} encoders",
+                    capabilities.version,
+                    self.ffmpeg.display(),
+                    capabilities.encoders.len()
+                );
+                let missing: Vec<_> = video::CONVERT_FORMATS
+                    .iter()
+                    .filter(|format| !self.video_output_formats().contains(format))
+                    .collect();
+                if !missing.is_empty() {
+                    tracing::warn!("ffmpeg lacks the encoders to convert videos to {:?}; those are refused", missing);
+                }
+            }
+            Err(tool) => tracing::warn!(
+                "⚠ {} could not be run (FFMPEG_PATH={}). Video is DISABLED: video conversion, background \
+                 removal on video and video pipelines are refused, and uploaded videos are not probed for \
+                 dimensions or duration",
+                tool,
+                self.ffmpeg.display()
+            ),
+        }
+    }
+
+    /// Whether video work can run at all
+    pub fn available(&self) -> bool {
+        self.capabilities.is_ok()
+    }
+
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref().ok()
+    }
+
+    /// Fail fast when video work cannot run, before any is done
+    pub fn require(&self) -> Result<(), ProcessingError> {
+        self.capabilities.as_ref().map(|_| ()).map_err(|tool| ProcessingError::ToolMissing(tool))
+    }
+
+    /// The first of `encoders` this ffmpeg was built without
+    pub fn missing_encoder<'a>(&self, encoders: &[&'a str]) -> Option<&'a str> {
+        let available = self.capabilities().map(|c| &c.encoders);
+        encoders.iter().copied().find(|encoder| !available.is_some_and(|a| a.contains(*encoder)))
+    }
+
+    /// Containers videos can be converted to with the installed encoders
+    pub fn video_output_formats(&self) -> Vec<&'static str> {
+        video::CONVERT_FORMATS
+            .iter()
+            .copied()
+            .filter(|format| {
+                let options = ConvertOptions { format: format.to_string(), ..Default::default() };
+                self.can_run(&options)
+            })
+            .collect()
+    }
+
+    /// Video codecs that can be written to at least one container
+    pub fn video_codecs(&self) -> Vec<&'static str> {
+        video::VIDEO_CODECS
+            .iter()
+            .copied()
+            .filter(|codec| {
+                video::CONVERT_FORMATS.iter().any(|format| {
+                    let options = ConvertOptions {
+                        format: format.to_string(),
+                        codec: Some(codec.to_string()),
+                        ..Default::default()
+                    };
+                    self.can_run(&options)
+                })
+            })
+            .collect()
+    }
+
+    fn can_run(&self, options: &ConvertOptions) -> bool {
+        options.encoders().is_ok_and(|encoders| self.available() && self.missing_encoder(&encoders).is_none())
+    }
+
+    /// A command running ffmpeg
+    pub fn ffmpeg(&self) -> tokio::process::Command {
+        tokio::process::Command::new(&self.ffmpeg)
+    }
+
+    /// A command running ffprobe
+    pub fn ffprobe(&self) -> tokio::process::Command {
+        tokio::process::Command::new(&self.ffprobe)
+    }
+}
+
+/// ffprobe in the same directory as `ffmpeg`, or from the PATH when `ffmpeg`
+/// is a bare command name
+fn ffprobe_beside(ffmpeg: &Path) -> PathBuf {
+    let name = ffmpeg
+        .file_name()
+        .map(|name| name.to_string_lossy().replace("ffmpeg", "ffprobe"))
+        .filter(|name| name.contains("ffprobe"))
+        .unwrap_or_else(|| "ffprobe".to_string());
+    match ffmpeg.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.join(name),
+        _ => PathBuf::from(name),
+    }
+}
+
+/// Standard output of a successful run, `None` when the tool is missing or fails
+async fn run(tool: &Path, args: &[&str]) -> Option<String> {
+    let output = tokio::process::Command::new(tool).args(args).kill_on_drop(true).output().await.ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The version from the first line of `ffmpeg -version`
+fn parse_version(output: &str) -> Option<String> {
+    let line = output.lines().next()?;
+    let rest = line.strip_prefix("ffmpeg version ")?;
+    rest.split_whitespace().next().map(str::to_string)
+}
+
+/// Encoder names from `ffmpeg -encoders`, listed below a `------` line as
+/// ` V....D libx264  description`
+fn parse_encoders(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .skip_while(|line| line.trim() != "------")
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D gif                  GIF (Graphics Interchange Format)
+ V....D png                  PNG (Portable Network Graphics) image
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ V....D libvpx-vp9           libvpx VP9 (codec vp9)
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+
+    fn with_encoders(output: &str) -> Ffmpeg {
+        Ffmpeg {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+            capabilities: Ok(Capabilities { version: "6.1.1".to_string(), encoders: parse_encoders(output) }),
+        }
+    }
+
+    #[test]
+    fn test_parse_version_and_encoders() {
+        let version = "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers\nbuilt with gcc";
+        assert_eq!(parse_version(version).as_deref(), Some("6.1.1-3ubuntu5"));
+        assert_eq!(parse_version("avconv version 12"), None);
+
+        let encoders = parse_encoders(ENCODERS);
+        let names: Vec<_> = encoders.iter().map(String::as_str).collect();
+        assert_eq!(names, ["aac", "gif", "libvpx-vp9", "libx264", "png"]);
+        assert!(parse_encoders("no listing here").is_empty());
+    }
+
+    #[test]
+    fn test_formats_and_codecs_follow_the_installed_encoders() {
+        let ffmpeg = with_encoders(ENCODERS);
+        // WebM also needs libopus for its audio
+        assert_eq!(ffmpeg.video_output_formats(), ["mp4", "mov", "gif"]);
+        assert_eq!(ffmpeg.video_codecs(), ["h264"]);
+        assert_eq!(ffmpeg.missing_encoder(&["libx264", "libopus"]), Some("libopus"));
+        assert!(ffmpeg.require().is_ok());
+
+        let missing = Ffmpeg { capabilities: Err("ffprobe"), ..ffmpeg };
+        assert!(!missing.available());
+        assert!(missing.video_output_formats().is_empty() && missing.video_codecs().is_empty());
+        assert!(matches!(missing.require(), Err(ProcessingError::ToolMissing("ffprobe"))));
+    }
+
+    #[test]
+    fn test_ffprobe_is_looked_for_beside_ffmpeg() {
+        assert_eq!(ffprobe_beside(Path::new("ffmpeg")), PathBuf::from("ffprobe"));
+        assert_eq!(ffprobe_beside(Path::new("/opt/ffmpeg/bin/ffmpeg")), PathBuf::from("/opt/ffmpeg/bin/ffprobe"));
+        assert_eq!(ffprobe_beside(Path::new("/usr/local/bin/ff6")), PathBuf::from("/usr/local/bin/ffprobe"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_binary_disables_video() {
+        let ffmpeg = Ffmpeg::detect("/nonexistent/ffmpeg").await;
+        assert!(!ffmpeg.available());
+        assert!(matches!(ffmpeg.require(), Err(ProcessingError::ToolMissing("ffmpeg"))));
+        assert_eq!(ffmpeg.missing_encoder(&["gif"]), Some("gif"));
+    }
+}
@@ -0,0 +1,157 @@
+// backend/src/services/formats.rs
+// Output format allowlists shared by the routes and the worker
+
+use image::ImageFormat;
+
+use super::sniff::MediaKind;
+use super::video;
+
+/// Formats an image can be converted to. AVIF needs the `avif` feature.
+#[cfg(feature = "avif")]
+pub const IMAGE_OUTPUT_FORMATS: &[&str] = &["png", "jpg", "jpeg", "webp", "avif", "gif", "bmp", "tiff"];
+#[cfg(not(feature = "avif"))]
+pub const IMAGE_OUTPUT_FORMATS: &[&str] = &["png", "jpg", "jpeg", "webp", "gif", "bmp", "tiff"];
+
+/// Image output formats this build knows but was compiled without
+const DISABLED_IMAGE_FORMATS: &[&str] = if cfg!(feature = "avif") { &[] } else { &["avif"] };
+
+/// Formats a video can be converted to
+pub const VIDEO_OUTPUT_FORMATS: &[&str] = video::CONVERT_FORMATS;
+
+#[derive(Debug)]
+pub struct UnsupportedFormat {
+    pub format: String,
+    pub supported: String,
+    /// A format the server could support but was built without
+    pub disabled: bool,
+}
+
+impl std::fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.disabled {
+            write!(f, "Output format '{}' is not enabled on this server", self.format)?;
+        } else {
+            write!(f, "Unsupported output format '{}'", self.format)?;
+        }
+        write!(f, "; supported formats: {}", self.supported)
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+/// Normalize a requested output format (case, leading dot) and check it against
+/// the allowlist for the input kind. The result is safe to use as a file extension.
+pub fn validate_output_format(format: &str, kind: MediaKind) -> Result<String, UnsupportedFormat> {
+    let allowed = match kind {
+        MediaKind::Image => IMAGE_OUTPUT_FORMATS,
+        MediaKind::Video => VIDEO_OUTPUT_FORMATS,
+    };
+
+    let normalized = normalize(format);
+    if allowed.contains(&normalized.as_str()) {
+        Ok(normalized)
+    } else {
+        Err(UnsupportedFormat {
+            format: format.to_string(),
+            supported: allowed.join(", "),
+            disabled: kind == MediaKind::Image && DISABLED_IMAGE_FORMATS.contains(&normalized.as_str()),
+        })
+    }
+}
+
+/// Encoder for an image output format
+pub fn image_format(format: &str) -> Result<ImageFormat, UnsupportedFormat> {
+    match normalize(format).as_str() {
+        "png" => Ok(ImageFormat::Png),
+        "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+        "webp" => Ok(ImageFormat::WebP),
+        "gif" => Ok(ImageFormat::Gif),
+        "bmp" => Ok(ImageFormat::Bmp),
+        "tiff" => Ok(ImageFormat::Tiff),
+        #[cfg(feature = "avif")]
+        "avif" => Ok(ImageFormat::Avif),
+        normalized => Err(UnsupportedFormat {
+            format: format.to_string(),
+            supported: IMAGE_OUTPUT_FORMATS.join(", "),
+            disabled: DISABLED_IMAGE_FORMATS.contains(&normalized),
+        }),
+    }
+}
+
+fn normalize(format: &str) -> String {
+    format.trim().trim_start_matches('.').to_lowercase()
+}
+
+/// MIME type of a stored file, from its extension
+pub fn content_type(filename: &str) -> &'static str {
+    let lower = filename.to_lowercase();
+
+    if lower.ends_with(".png") {
+        "image/png"
+    } else if lower.ends_with(".jpg") || lower.ends_with(".jpeg") {
+        "image/jpeg"
+    } else if lower.ends_with(".webp") {
+        "image/webp"
+    } else if lower.ends_with(".avif") {
+        "image/avif"
+    } else if lower.ends_with(".gif") {
+        "image/gif"
+    } else if lower.ends_with(".mp4") {
+        "video/mp4"
+    } else if lower.ends_with(".mov") {
+        "video/quicktime"
+    } else if lower.ends_with(".avi") {
+        "video/x-msvideo"
+    } else if lower.ends_with(".webm") {
+        "video/webm"
+    } else if lower.ends_with(".zip") {
+        "application/zip"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_output_format() {
+        assert_eq!(validate_output_format("PNG", MediaKind::Image).unwrap(), "png");
+        assert_eq!(validate_output_format(".jpeg", MediaKind::Image).unwrap(), "jpeg");
+        assert_eq!(validate_output_format("webm", MediaKind::Video).unwrap(), "webm");
+        // Allowlists are per input kind
+        assert!(validate_output_format("mp4", MediaKind::Image).is_err());
+        assert!(validate_output_format("png", MediaKind::Video).is_err());
+    }
+
+    #[test]
+    fn test_rejects_unsafe_formats() {
+        for bad in ["exe", "../../etc/passwd", "png/../x", ""] {
+            let err = validate_output_format(bad, MediaKind::Image).unwrap_err();
+            assert!(err.to_string().contains("png, jpg, jpeg"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_image_format() {
+        assert_eq!(image_format("jpg").unwrap(), ImageFormat::Jpeg);
+        assert_eq!(image_format("TIFF").unwrap(), ImageFormat::Tiff);
+        assert!(image_format("mp4").is_err());
+    }
+
+    #[test]
+    fn test_avif_follows_the_feature() {
+        let avif = validate_output_format("AVIF", MediaKind::Image);
+        if cfg!(feature = "avif") {
+            assert_eq!(avif.unwrap(), "avif");
+            assert_eq!(content_type("out.avif"), "image/avif");
+        } else {
+            let err = avif.unwrap_err();
+            assert!(err.disabled);
+            assert!(err.to_string().starts_with("Output format 'AVIF' is not enabled"), "{}", err);
+            assert!(image_format("avif").unwrap_err().disabled);
+        }
+        assert!(!validate_output_format("avif", MediaKind::Video).unwrap_err().disabled);
+    }
+}
@@ -0,0 +1,186 @@
+// backend/src/services/heic.rs
+// HEIC decoding (feature = "heic"). The image crate has no HEVC decoder, so
+// files are converted to PNG by libheif's heif-convert, or ImageMagick when
+// that is what's installed.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use image::{DynamicImage, ImageDecoder, ImageReader};
+
+use super::processing::{self, ProcessingError};
+use super::sniff::{self, SniffedType};
+
+/// Command-line decoders in order of preference, each run as `tool input output.png`
+const DECODERS: &[&str] = &["heif-convert", "magick", "convert"];
+
+/// The first decoder found on PATH, looked up once
+fn decoder() -> Option<&'static str> {
+    static DECODER: OnceLock<Option<&'static str>> = OnceLock::new();
+    *DECODER.get_or_init(|| {
+        let found = DECODERS.iter().copied().find(|tool| installed(tool));
+        match found {
+            Some(tool) => tracing::info!("Decoding HEIC with {}", tool),
+            None => tracing::warn!("Neither heif-convert nor ImageMagick can decode HEIC; HEIC uploads will be refused"),
+        }
+        found
+    })
+}
+
+fn installed(tool: &str) -> bool {
+    if tool == "heif-convert" {
+        return Command::new(tool).arg("--version").output().is_ok();
+    }
+    // ImageMagick only reads HEIC when it was built against libheif
+    Command::new(tool)
+        .args(["-list", "format"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("HEIC"))
+}
+
+/// Whether HEIC inputs can be processed: built with the feature and a decoder installed
+pub fn available() -> bool {
+    cfg!(feature = "heic") && decoder().is_some()
+}
+
+/// Whether a file's content is HEIC, whatever its name
+pub fn is_heic(path: &Path) -> std::io::Result<bool> {
+    let mut header = Vec::with_capacity(sniff::SNIFF_LEN);
+    File::open(path)?
+        .take(sniff::SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(sniff::sniff(&header) == Some(SniffedType::Heic))
+}
+
+/// Decode a HEIC file, refusing one that decodes to more than `max_pixels`.
+/// The decoders apply the container's rotation and mirroring, so the image
+/// comes back upright.
+pub fn decode(path: &Path, max_pixels: u64) -> Result<DynamicImage, ProcessingError> {
+    with_png(path, |png| {
+        let mut reader = ImageReader::open(png)?.with_guessed_format()?;
+        reader.no_limits();
+        let mut decoder = reader.into_decoder()?;
+        let (width, height) = decoder.dimensions();
+        processing::check_pixels(u64::from(width) * u64::from(height), max_pixels)?;
+        decoder.set_limits(processing::decode_limits(max_pixels))?;
+        Ok(DynamicImage::from_decoder(decoder)?)
+    })
+}
+
+/// Pixels a HEIC file decodes to, read from the header of its conversion
+pub fn decoded_pixels(path: &Path) -> Result<u64, ProcessingError> {
+    with_png(path, |png| {
+        let mut reader = ImageReader::open(png)?.with_guessed_format()?;
+        reader.no_limits();
+        let (width, height) = reader.into_dimensions()?;
+        Ok(u64::from(width) * u64::from(height))
+    })
+}
+
+/// `decode` for HEIC held in memory, staged through a temp file
+pub fn decode_bytes(data: &[u8], max_pixels: u64) -> Result<DynamicImage, ProcessingError> {
+    let staged = std::env::temp_dir().join(format!("heic_{}.heic", uuid::Uuid::new_v4()));
+    std::fs::write(&staged, data)?;
+    let decoded = decode(&staged, max_pixels);
+    let _ = std::fs::remove_file(&staged);
+    decoded
+}
+
+/// Convert a HEIC file to a temporary PNG and read it with `read`
+fn with_png<T>(path: &Path, read: impl FnOnce(&Path) -> Result<T, ProcessingError>) -> Result<T, ProcessingError> {
+    if !cfg!(feature = "heic") {
+        return Err(ProcessingError::Unsupported(
+            "HEIC input (built without the `heic` feature)".to_string(),
+        ));
+    }
+    let tool = decoder().ok_or(ProcessingError::ToolMissing("heif-convert"))?;
+
+    let png = std::env::temp_dir().join(format!("heic_{}.png", uuid::Uuid::new_v4()));
+    let read = match Command::new(tool).arg(path).arg(&png).output() {
+        Ok(output) if output.status.success() => read(&png),
+        Ok(output) => Err(ProcessingError::DecodeFailed(format!(
+            "{} exited with {}: {}",
+            tool,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+        Err(e) => Err(e.into()),
+    };
+    let _ = std::fs::remove_file(&png);
+    read
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64x32 HEVC-coded image: red left half, blue right half
+    const SAMPLE: &[u8] = include_bytes!("../../tests/fixtures/small.heic");
+
+    #[test]
+    fn test_is_heic_sniffs_content() {
+        let dir = std::env::temp_dir().join(format!("heic_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Named like a JPEG, but the content decides
+        let path = dir.join("photo.jpg");
+        std::fs::write(&path, SAMPLE).unwrap();
+        assert!(is_heic(&path).unwrap());
+
+        std::fs::write(&path, b"\xff\xd8\xff\xe0\0\x10JFIF\0").unwrap();
+        assert!(!is_heic(&path).unwrap());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Needs heif-convert, or ImageMagick with HEIC support, on PATH
+    #[cfg(feature = "heic")]
+    #[test]
+    fn test_decodes_fixture() {
+        use super::super::processing::{ImageProcessor, OutputEncoding};
+        use image::GenericImageView;
+
+        let img = decode_bytes(SAMPLE, 64 * 32).unwrap();
+        assert_eq!(img.dimensions(), (64, 32));
+        let left = img.get_pixel(8, 16);
+        let right = img.get_pixel(56, 16);
+        assert!(left[0] > 150 && left[2] < 100, "left is {:?}", left);
+        assert!(right[2] > 150 && right[0] < 100, "right is {:?}", right);
+
+        // The processor picks HEIC out by content, not by name
+        let dir = std::env::temp_dir().join(format!("heic_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("upload");
+        std::fs::write(&input, SAMPLE).unwrap();
+        let output = dir.join("out.png");
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), u64::MAX).unwrap();
+        processor
+            .convert_format(&input, &output, OutputEncoding::new(image::ImageFormat::Png), None, None, None, &|_| {})
+            .unwrap();
+        assert_eq!(image::open(&output).unwrap().dimensions(), (64, 32));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Needs heif-convert, or ImageMagick with HEIC support, on PATH
+    #[cfg(feature = "heic")]
+    #[test]
+    fn test_refuses_heic_over_the_pixel_limit() {
+        let dir = std::env::temp_dir().join(format!("heic_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("upload.heic");
+        std::fs::write(&input, SAMPLE).unwrap();
+        assert_eq!(decoded_pixels(&input).unwrap(), 64 * 32);
+        std::fs::remove_dir_all(&dir).ok();
+
+        let refused = decode_bytes(SAMPLE, 64 * 32 - 1);
+        assert!(matches!(refused, Err(ProcessingError::TooManyPixels { pixels: 2048, max: 2047 })), "{:?}", refused);
+    }
+
+    #[cfg(not(feature = "heic"))]
+    #[test]
+    fn test_decode_needs_the_feature() {
+        assert!(!available());
+        assert!(matches!(decode_bytes(SAMPLE, u64::MAX), Err(ProcessingError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,279 @@
+// backend/src/services/job_params.rs
+// What each job type keeps in `jobs.parameters`: written by the routes when
+// a job is queued, read back by the worker
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Stored parameters that don't have the shape their job type expects
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid {job_type} parameters: {source}")]
+pub struct InvalidParams {
+    pub job_type: &'static str,
+    pub source: serde_path_to_error::Error<serde_json::Error>,
+}
+
+/// The parameters of one job type, or of a pipeline step of that type.
+/// Reading is lenient so rows outlive changes to the structs: keys a struct
+/// doesn't know (`webhook_url`, `request_id`, notes the worker adds, fields
+/// from newer versions) are ignored, and missing ones take their defaults.
+pub trait JobParams: Serialize + DeserializeOwned {
+    /// The `job_type` these belong to
+    const JOB_TYPE: &'static str;
+
+    fn from_value(parameters: &serde_json::Value) -> Result<Self, InvalidParams> {
+        serde_path_to_error::deserialize(parameters)
+            .map_err(|source| InvalidParams { job_type: Self::JOB_TYPE, source })
+    }
+
+    fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("job parameters serialize to JSON")
+    }
+}
+
+fn strip_metadata_default() -> bool {
+    true
+}
+
+/// A `convert` job, batch or single, or a pipeline step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConvertParams {
+    /// Validated and lowercased by the route. Jobs queued before it was
+    /// required convert to PNG.
+    #[serde(default)]
+    pub output_format: Option<String>,
+    #[serde(default)]
+    pub lut_id: Option<Uuid>,
+    /// Only on jobs queued before LUTs were stored per user, which are refused
+    #[serde(default, skip_serializing)]
+    pub lut_location: Option<String>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub video_codec: Option<String>,
+    /// Encoder quality for images, CRF for videos
+    #[serde(default)]
+    pub quality: Option<u32>,
+    /// Size a lossy still image is to come in under, in KiB
+    #[serde(default)]
+    pub target_size_kb: Option<u32>,
+    /// Jobs queued before the option existed strip metadata, which is what
+    /// image conversions always did
+    #[serde(default = "strip_metadata_default")]
+    pub strip_metadata: bool,
+}
+
+impl JobParams for ConvertParams {
+    const JOB_TYPE: &'static str = "convert";
+}
+
+/// A `remove_bg` job or pipeline step
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RemoveBgParams {
+    /// `transparent`, `color`, `blur` or `image`. Jobs queued before modes
+    /// existed only have `replace_color`.
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub replace_color: Option<[u8; 3]>,
+    /// Set in `blur` mode
+    #[serde(default)]
+    pub blur_sigma: Option<f32>,
+    /// The caller's image behind the subject in `image` mode, and where it
+    /// is stored
+    #[serde(default)]
+    pub background_asset_id: Option<String>,
+    #[serde(default)]
+    pub background_location: Option<String>,
+    #[serde(default)]
+    pub trim: bool,
+    /// Set when `trim` is
+    #[serde(default)]
+    pub trim_padding: Option<f32>,
+    #[serde(default)]
+    pub output_format: Option<String>,
+    /// Fills a transparent result for JPEG
+    #[serde(default)]
+    pub flatten_color: Option<[u8; 3]>,
+}
+
+impl JobParams for RemoveBgParams {
+    const JOB_TYPE: &'static str = "remove_bg";
+}
+
+/// A `color_grade` job or pipeline step: a LUT, else a preset, else the
+/// manual adjustments
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColorGradeParams {
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub lut_id: Option<Uuid>,
+    /// Only on jobs queued before LUTs were stored per user, which are refused
+    #[serde(default, skip_serializing)]
+    pub lut_location: Option<String>,
+    #[serde(default)]
+    pub hue: Option<i32>,
+    #[serde(default)]
+    pub saturation: Option<i32>,
+    #[serde(default)]
+    pub brightness: Option<i32>,
+    #[serde(default)]
+    pub contrast: Option<i32>,
+}
+
+impl JobParams for ColorGradeParams {
+    const JOB_TYPE: &'static str = "color_grade";
+}
+
+/// One step of a pipeline, tagged with its `type`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PipelineStep {
+    RemoveBg(RemoveBgParams),
+    ColorGrade(ColorGradeParams),
+    Convert(ConvertParams),
+}
+
+impl PipelineStep {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RemoveBg(_) => RemoveBgParams::JOB_TYPE,
+            Self::ColorGrade(_) => ColorGradeParams::JOB_TYPE,
+            Self::Convert(_) => ConvertParams::JOB_TYPE,
+        }
+    }
+}
+
+/// A `pipeline` job: its steps in the order they run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineParams {
+    #[serde(default)]
+    pub operations: Vec<PipelineStep>,
+}
+
+impl JobParams for PipelineParams {
+    const JOB_TYPE: &'static str = "pipeline";
+}
+
+/// A `lut_generate` job
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LutGenerateParams {
+    /// Name of the library LUT. Defaults to the graded image's name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The library LUT, once the worker has created it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_lut_id: Option<Uuid>,
+}
+
+impl JobParams for LutGenerateParams {
+    const JOB_TYPE: &'static str = "lut_generate";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rows_written_by_earlier_versions_still_read() {
+        // Before strip_metadata and LUT ids, with the keys every job carries
+        let convert = ConvertParams::from_value(&json!({
+            "output_format": "webp",
+            "width": 800,
+            "height": null,
+            "quality": 80,
+            "webhook_url": "https://hook.test/",
+            "request_id": "req-1",
+            "frames_dropped": true,
+        }))
+        .unwrap();
+        assert_eq!(convert.output_format.as_deref(), Some("webp"));
+        assert_eq!((convert.width, convert.height, convert.quality), (Some(800), None, Some(80)));
+        assert!(convert.strip_metadata);
+        assert_eq!(convert.lut_id, None);
+
+        // Before modes: only a replacement color
+        let remove_bg = RemoveBgParams::from_value(&json!({ "replace_color": [0, 128, 255] })).unwrap();
+        assert_eq!(remove_bg, RemoveBgParams { replace_color: Some([0, 128, 255]), ..Default::default() });
+
+        // LUTs by location are still recognized, so they can be refused
+        let graded = ColorGradeParams::from_value(&json!({ "lut_location": "luts/warm.cube" })).unwrap();
+        assert_eq!(graded.lut_location.as_deref(), Some("luts/warm.cube"));
+        assert_eq!(ConvertParams::from_value(&json!({})).unwrap().output_format, None);
+    }
+
+    #[test]
+    fn test_fields_from_newer_versions_are_ignored() {
+        let params = ColorGradeParams::from_value(&json!({
+            "preset": "warm",
+            "vignette": 0.4,
+            "curves": { "red": [[0, 0], [255, 255]] },
+            "trimmed_size": { "width": 10, "height": 10 },
+        }))
+        .unwrap();
+        assert_eq!(params, ColorGradeParams { preset: Some("warm".to_string()), ..Default::default() });
+
+        let pipeline = PipelineParams::from_value(&json!({
+            "operations": [{ "type": "color_grade", "hue": 10, "mask": "subject" }],
+            "asset_results": {},
+        }))
+        .unwrap();
+        assert_eq!(pipeline.operations.len(), 1);
+        assert_eq!(pipeline.operations[0].name(), "color_grade");
+    }
+
+    #[test]
+    fn test_parameters_round_trip() {
+        let steps = vec![
+            PipelineStep::RemoveBg(RemoveBgParams {
+                mode: Some("blur".to_string()),
+                blur_sigma: Some(12.0),
+                trim: true,
+                trim_padding: Some(5.0),
+                ..Default::default()
+            }),
+            PipelineStep::ColorGrade(ColorGradeParams { lut_id: Some(Uuid::new_v4()), ..Default::default() }),
+            PipelineStep::Convert(ConvertParams {
+                output_format: Some("jpg".to_string()),
+                lut_id: None,
+                lut_location: None,
+                width: Some(640),
+                height: None,
+                video_codec: None,
+                quality: None,
+                target_size_kb: Some(200),
+                strip_metadata: false,
+            }),
+        ];
+        let pipeline = PipelineParams { operations: steps };
+
+        let stored = pipeline.to_value();
+        assert_eq!(stored["operations"][0]["type"], "remove_bg");
+        assert_eq!(stored["operations"][2]["strip_metadata"], false);
+        assert_eq!(stored["operations"][2]["target_size_kb"], 200);
+        assert_eq!(PipelineParams::from_value(&stored).unwrap(), pipeline);
+
+        let generated = LutGenerateParams { name: Some("look.cube".to_string()), generated_lut_id: None };
+        assert_eq!(generated.to_value(), json!({ "name": "look.cube" }));
+        assert_eq!(LutGenerateParams::from_value(&generated.to_value()).unwrap(), generated);
+    }
+
+    #[test]
+    fn test_wrong_shapes_name_the_job_type_and_field() {
+        let error = ConvertParams::from_value(&json!({ "width": "wide" })).unwrap_err();
+        assert_eq!(error.job_type, "convert");
+        let message = error.to_string();
+        assert!(message.starts_with("Invalid convert parameters: width: invalid type"), "{}", message);
+
+        let error = RemoveBgParams::from_value(&json!({ "replace_color": [0, 256, 0] })).unwrap_err();
+        assert!(error.to_string().contains("replace_color"), "{}", error);
+
+        let error = PipelineParams::from_value(&json!({ "operations": [{ "type": "sharpen" }] })).unwrap_err();
+        assert!(error.to_string().contains("operations[0]"), "{}", error);
+    }
+}
@@ -1,8 +1,16 @@
 use std::path::Path;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use serde::Serialize;
 use thiserror::Error;
-use image::{RgbaImage, Rgba, DynamicImage};
+use utoipa::ToSchema;
+use image::{RgbImage, RgbaImage, DynamicImage};
+use rayon::prelude::*;
+
+/// Largest LUT_3D_SIZE accepted. 129 is the biggest lattice grading tools
+/// export; at 12 bytes an entry it is already 26 MB in memory.
+pub const MAX_3D_SIZE: usize = 129;
+
+/// Largest LUT_1D_SIZE accepted (a full 16-bit curve)
+pub const MAX_1D_SIZE: usize = 65536;
 
 #[derive(Debug, Error)]
 pub enum LutError {
@@ -12,122 +20,698 @@ pub enum LutError {
     Parse(String),
 }
 
-/// Minimal 3D LUT representation (cube) using nearest-neighbor lookup.
+/// Lattice size of LUTs fitted by `Lut3D::fit`, the common grading-tool default
+pub const FITTED_3D_SIZE: usize = 33;
+
+/// Share of the fitted lattice's cells that must be sampled. Below this the
+/// LUT would be mostly guesswork filled in from a few colors.
+const MIN_FIT_COVERAGE: f64 = 0.01;
+
+/// Why a before/after pair cannot be fitted
+#[derive(Debug, Error)]
+pub enum FitError {
+    #[error(
+        "The graded image is {graded_width}x{graded_height} but the source is {source_width}x{source_height}; \
+         export both at the same size without cropping"
+    )]
+    DimensionMismatch { source_width: u32, source_height: u32, graded_width: u32, graded_height: u32 },
+    #[error(
+        "The images cover too few colors to build a LUT ({sampled} of {total} lattice cells sampled, \
+         {needed} needed); use a shot with a wider range of colors and tones"
+    )]
+    InsufficientCoverage { sampled: usize, needed: usize, total: usize },
+}
+
+/// Longest part of an offending line quoted in a parse error
+const MAX_QUOTED_CHARS: usize = 60;
+
+/// A parse error at 1-based `line_no`, quoting the line's `content`
+fn parse_error(line_no: usize, content: &str, msg: impl std::fmt::Display) -> LutError {
+    let content = content.trim();
+    if content.is_empty() {
+        return LutError::Parse(format!("line {}: {}", line_no, msg));
+    }
+    let quoted: String = content.chars().take(MAX_QUOTED_CHARS).collect();
+    let cut = if quoted.len() < content.len() { "..." } else { "" };
+    LutError::Parse(format!("line {} ('{}{}'): {}", line_no, quoted, cut, msg))
+}
+
+/// Any supported LUT, loaded by `Lut::from_file`.
+pub enum Lut {
+    OneD(Lut1D),
+    ThreeD(Lut3D),
+}
+
+/// What a LUT's header declared, and what was tolerated in its table
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct LutMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Input range the table covers, per channel (DOMAIN_MIN / DOMAIN_MAX)
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    /// Table values below 0 or above 1. They are kept as written and only
+    /// the final output is clamped.
+    pub out_of_range_values: usize,
+}
+
+impl Default for LutMetadata {
+    fn default() -> Self {
+        Self { title: None, domain_min: [0.0; 3], domain_max: [1.0; 3], out_of_range_values: 0 }
+    }
+}
+
+/// What an uploaded LUT contains, reported back to the uploader
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct LutInfo {
+    /// `1d` or `3d`
+    pub kind: &'static str,
+    /// Entries per axis (LUT_1D_SIZE / LUT_3D_SIZE)
+    pub size: usize,
+    /// RGB triplets in the table: `size` for 1D, `size^3` for 3D
+    pub entries: usize,
+    #[serde(flatten)]
+    pub metadata: LutMetadata,
+}
+
+impl Lut {
+    /// Pick a parser from the file extension, falling back to the header for
+    /// files with an unknown or missing extension.
+    pub fn from_file(path: &Path) -> Result<Self, LutError> {
+        let data = std::fs::read(path)?;
+        let ext = path.extension().and_then(|e| e.to_str());
+        Self::from_bytes(&data, ext).map(|(lut, _)| lut)
+    }
+
+    /// Parse file contents as uploaded, e.g. before they are stored
+    pub fn from_bytes(data: &[u8], extension: Option<&str>) -> Result<(Self, LutMetadata), LutError> {
+        let text = std::str::from_utf8(data).map_err(|e| {
+            let valid = &data[..e.valid_up_to()];
+            let line_no = valid.iter().filter(|&&b| b == b'\n').count() + 1;
+            LutError::Parse(format!("line {}: not a text file (invalid UTF-8 at byte {})", line_no, e.valid_up_to()))
+        })?;
+        Self::parse(text, extension)
+    }
+
+    pub fn parse(text: &str, extension: Option<&str>) -> Result<(Self, LutMetadata), LutError> {
+        let three_d = |(lut, metadata)| (Lut::ThreeD(lut), metadata);
+        match extension.map(|e| e.to_lowercase()).as_deref() {
+            Some("3dl") => Lut3D::parse_3dl(text).map(three_d),
+            Some("cube") => Self::parse_cube(text),
+            _ if has_directive(text, "LUT_1D_SIZE") || has_directive(text, "LUT_3D_SIZE") => {
+                Self::parse_cube(text)
+            }
+            _ => Lut3D::parse_3dl(text).map(three_d),
+        }
+    }
+
+    /// .cube files carry either a 1D or a 3D table depending on the size directive
+    fn parse_cube(text: &str) -> Result<(Self, LutMetadata), LutError> {
+        if has_directive(text, "LUT_1D_SIZE") {
+            Lut1D::parse(text).map(|(lut, metadata)| (Lut::OneD(lut), metadata))
+        } else {
+            Lut3D::parse(text).map(|(lut, metadata)| (Lut::ThreeD(lut), metadata))
+        }
+    }
+
+    pub fn apply_to_image(&self, img: &DynamicImage) -> RgbaImage {
+        match self {
+            Lut::OneD(lut) => lut.apply_to_image(img),
+            Lut::ThreeD(lut) => lut.apply_to_image(img),
+        }
+    }
+
+    /// Apply the LUT in place to a row of RGBA pixels, leaving alpha alone
+    pub fn apply_to_row(&self, row: &mut [u8]) {
+        match self {
+            Lut::OneD(lut) => lut.apply_to_row(row),
+            Lut::ThreeD(lut) => lut.apply_to_row(row),
+        }
+    }
+
+    /// Describe the LUT, with the `metadata` its parse returned
+    pub fn info(&self, metadata: LutMetadata) -> LutInfo {
+        match self {
+            Lut::OneD(lut) => LutInfo { kind: "1d", size: lut.entries.len(), entries: lut.entries.len(), metadata },
+            Lut::ThreeD(lut) => LutInfo { kind: "3d", size: lut.size, entries: lut.entries.len(), metadata },
+        }
+    }
+}
+
+fn has_directive(text: &str, directive: &str) -> bool {
+    text.lines().any(|l| {
+        l.split_whitespace()
+            .next()
+            .is_some_and(|k| k.eq_ignore_ascii_case(directive))
+    })
+}
+
+/// Lines of a .cube file after stripping comments, keyed by 1-based line number
+enum CubeLine<'a> {
+    Directive(&'a str, Vec<&'a str>),
+    Values([f32; 3]),
+}
+
+/// Each line with content as `(line number, trimmed line, parsed line)`
+fn cube_lines(text: &str) -> impl Iterator<Item = Result<(usize, &str, CubeLine<'_>), LutError>> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line_no = i + 1;
+        let s = line.trim();
+        if s.is_empty() || s.starts_with('#') {
+            return None;
+        }
+
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        // Directives start with a keyword; everything else must be an RGB triplet
+        if parts[0].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Some(Ok((line_no, s, CubeLine::Directive(parts[0], parts[1..].to_vec()))));
+        }
+        Some(
+            parse_triplet(&parts)
+                .map(|rgb| (line_no, s, CubeLine::Values(rgb)))
+                .ok_or_else(|| parse_error(line_no, s, "expected three numbers")),
+        )
+    })
+}
+
+/// Shared header state for 1D and 3D .cube files
+struct CubeHeader<'a> {
+    /// LUT_1D_SIZE or LUT_3D_SIZE
+    size_key: &'static str,
+    size: Option<usize>,
+    title: Option<String>,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// The last DOMAIN_MIN or DOMAIN_MAX line, which a bad domain is reported at
+    domain_line: Option<(usize, &'a str)>,
+}
+
+impl<'a> CubeHeader<'a> {
+    fn new(size_key: &'static str) -> Self {
+        Self { size_key, size: None, title: None, domain_min: [0.0; 3], domain_max: [1.0; 3], domain_line: None }
+    }
+
+    /// Table entries a size calls for
+    fn entries_for(&self, size: usize) -> usize {
+        if self.size_key == "LUT_1D_SIZE" { size } else { size * size * size }
+    }
+
+    /// Consume the directive on line `line_no`, which reads `content`
+    fn directive(&mut self, line_no: usize, content: &'a str, key: &str, args: &[&str]) -> Result<(), LutError> {
+        let size_key = self.size_key;
+        let key = key.to_uppercase();
+        if key == size_key {
+            let size = args
+                .first()
+                .and_then(|v| v.parse::<usize>().ok())
+                .ok_or_else(|| parse_error(line_no, content, format!("invalid {}", size_key)))?;
+            // Checked here so an oversized table is refused before it is read
+            let max = if size_key == "LUT_1D_SIZE" { MAX_1D_SIZE } else { MAX_3D_SIZE };
+            if size > max {
+                return Err(parse_error(line_no, content, format!("{} exceeds the maximum of {}", size_key, max)));
+            }
+            if size < 2 {
+                return Err(parse_error(line_no, content, format!("{} must be at least 2", size_key)));
+            }
+            self.size = Some(size);
+        } else if key == "DOMAIN_MIN" || key == "DOMAIN_MAX" {
+            let domain = parse_triplet(args)
+                .ok_or_else(|| parse_error(line_no, content, format!("{} needs three numbers", key)))?;
+            if key == "DOMAIN_MIN" {
+                self.domain_min = domain;
+            } else {
+                self.domain_max = domain;
+            }
+            self.domain_line = Some((line_no, content));
+        } else if key == "TITLE" {
+            self.title = Some(args.join(" ").trim_matches('"').to_string());
+        } else if key == "LUT_1D_SIZE" || key == "LUT_3D_SIZE" {
+            return Err(parse_error(line_no, content, format!("unexpected {} in a {} file", key, size_key)));
+        }
+        // Ignore other directives
+        Ok(())
+    }
+
+    fn check_domain(&self) -> Result<(), LutError> {
+        if (0..3).any(|c| self.domain_max[c] <= self.domain_min[c]) {
+            let (line_no, content) = self.domain_line.unwrap_or((1, ""));
+            return Err(parse_error(line_no, content, "DOMAIN_MAX must be greater than DOMAIN_MIN"));
+        }
+        Ok(())
+    }
+}
+
+/// A .cube file as read, before its table is checked against its size
+struct CubeTable<'a> {
+    header: CubeHeader<'a>,
+    entries: Vec<[f32; 3]>,
+    out_of_range_values: usize,
+    /// The last line with content, which errors about the table as a whole
+    /// are reported at
+    last_line: (usize, &'a str),
+}
+
+impl<'a> CubeTable<'a> {
+    /// Read the header and table of a .cube file whose size directive is `size_key`
+    fn read(text: &'a str, size_key: &'static str) -> Result<Self, LutError> {
+        let mut header = CubeHeader::new(size_key);
+        let mut entries: Vec<[f32; 3]> = Vec::new();
+        let mut out_of_range_values = 0;
+        let mut last_line = (1, "");
+
+        for line in cube_lines(text) {
+            let (line_no, content, line) = line?;
+            last_line = (line_no, content);
+            match line {
+                CubeLine::Directive(key, args) => header.directive(line_no, content, key, &args)?,
+                CubeLine::Values(rgb) => {
+                    if let Some(size) = header.size.filter(|&size| entries.len() == header.entries_for(size)) {
+                        return Err(parse_error(
+                            line_no,
+                            content,
+                            format!("more entries than the {} that {} {} calls for", entries.len(), size_key, size),
+                        ));
+                    }
+                    out_of_range_values += rgb.iter().filter(|v| !(0.0..=1.0).contains(*v)).count();
+                    entries.push(rgb);
+                }
+            }
+        }
+
+        Ok(Self { header, entries, out_of_range_values, last_line })
+    }
+
+    /// Check the table holds the `entries_for(size)` entries of `size`
+    fn check_entries(&self, size: usize) -> Result<(), LutError> {
+        let expected = self.header.entries_for(size);
+        if self.entries.len() != expected {
+            let (line_no, content) = self.last_line;
+            return Err(parse_error(
+                line_no,
+                content,
+                format!("expected {} entries but found {}", expected, self.entries.len()),
+            ));
+        }
+        self.header.check_domain()
+    }
+
+    fn metadata(&self) -> LutMetadata {
+        LutMetadata {
+            title: self.header.title.clone(),
+            domain_min: self.header.domain_min,
+            domain_max: self.header.domain_max,
+            out_of_range_values: self.out_of_range_values,
+        }
+    }
+}
+
+/// Map a normalized input value into a fractional lattice position `0..=size-1`
+fn lattice_position(v: f32, min: f32, max: f32, size: usize) -> (usize, usize, f32) {
+    let t = ((v - min) / (max - min)).clamp(0.0, 1.0);
+    let pos = t * (size - 1) as f32;
+    let lo = pos.floor() as usize;
+    let hi = (lo + 1).min(size - 1);
+    (lo, hi, pos - lo as f32)
+}
+
+/// 1D LUT: an independent curve per channel, linearly interpolated.
+pub struct Lut1D {
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    entries: Vec<[f32; 3]>,
+}
+
+impl Lut1D {
+    /// Parse a .cube file with a LUT_1D_SIZE directive.
+    pub fn parse(text: &str) -> Result<(Self, LutMetadata), LutError> {
+        let table = CubeTable::read(text, "LUT_1D_SIZE")?;
+        let Some(size) = table.header.size else {
+            let (line_no, content) = table.last_line;
+            return Err(parse_error(line_no, content, "missing LUT_1D_SIZE"));
+        };
+        table.check_entries(size)?;
+
+        let metadata = table.metadata();
+        let CubeTable { header, entries, .. } = table;
+        let lut = Lut1D { domain_min: header.domain_min, domain_max: header.domain_max, entries };
+        Ok((lut, metadata))
+    }
+
+    pub fn apply_to_image(&self, img: &DynamicImage) -> RgbaImage {
+        let mut out = img.to_rgba8();
+        out.par_chunks_mut(4 * 1024).for_each(|row| self.apply_to_row(row));
+        out
+    }
+
+    pub fn apply_to_row(&self, row: &mut [u8]) {
+        for pixel in row.chunks_exact_mut(4) {
+            for (c, channel) in pixel[..3].iter_mut().enumerate() {
+                *channel = to_u8(self.sample(c, *channel as f32 / 255.0));
+            }
+        }
+    }
+
+    fn sample(&self, channel: usize, v: f32) -> f32 {
+        let (lo, hi, frac) = lattice_position(
+            v,
+            self.domain_min[channel],
+            self.domain_max[channel],
+            self.entries.len(),
+        );
+        let a = self.entries[lo][channel];
+        let b = self.entries[hi][channel];
+        a + (b - a) * frac
+    }
+}
+
+/// 3D LUT (cube) sampled with trilinear interpolation.
 pub struct Lut3D {
     size: usize,
+    title: Option<String>,
+    /// Input range covered by the lattice, per channel (DOMAIN_MIN / DOMAIN_MAX)
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
     /// Flattened RGB entries in row-major order: r fastest, then g, then b
-    entries: Vec<[u8; 3]>,
+    entries: Vec<[f32; 3]>,
 }
 
 impl Lut3D {
-    /// Load a very small subset of .cube files. Supports comments, TITLE, LUT_3D_SIZE n,
-    /// and then n^3 floating RGB values in 0..1 range.
-    pub fn from_cube(path: &Path) -> Result<Self, LutError> {
-        let f = File::open(path)?;
-        let reader = BufReader::new(f);
+    /// Parse a .cube file. Supports comments, TITLE, LUT_3D_SIZE n, DOMAIN_MIN/DOMAIN_MAX
+    /// and then n^3 floating RGB values.
+    pub fn parse(text: &str) -> Result<(Self, LutMetadata), LutError> {
+        let table = CubeTable::read(text, "LUT_3D_SIZE")?;
+
+        // If LUT_3D_SIZE directive was not present, try to infer from value count
+        let size = match table.header.size {
+            Some(s) => s,
+            None => infer_cube_size(table.entries.len()).ok_or_else(|| {
+                let (line_no, content) = table.last_line;
+                let msg = format!("missing LUT_3D_SIZE, and {} entries do not make a cube", table.entries.len());
+                parse_error(line_no, content, msg)
+            })?,
+        };
+        table.check_entries(size)?;
+
+        let metadata = table.metadata();
+        let CubeTable { header, entries, .. } = table;
+        let mut lut = Self::new(size, header.domain_min, header.domain_max, entries)?;
+        lut.title = header.title;
+        Ok((lut, metadata))
+    }
 
-        let mut size: Option<usize> = None;
-        let mut values: Vec<[f32; 3]> = Vec::new();
+    /// Parse an Autodesk .3dl file: an optional shaper line listing the input
+    /// lattice positions, followed by integer RGB triplets with blue varying
+    /// fastest. The output bit depth is inferred from the largest value.
+    /// Shaper positions are assumed to be evenly spaced.
+    pub fn parse_3dl(text: &str) -> Result<(Self, LutMetadata), LutError> {
+        let mut shaper: Option<usize> = None;
+        let mut raw: Vec<[u32; 3]> = Vec::new();
+        let mut last_line = (1, "");
 
-        for line in reader.lines() {
-            let line = line?;
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
             let s = line.trim();
-            if s.is_empty() || s.starts_with('#') {
+            // Skip comments and keyword lines such as "3DMESH" / "Mesh 4 12"
+            let keyword = s.split_whitespace().next().is_some_and(|w| w.contains(|c: char| c.is_ascii_alphabetic()));
+            if s.is_empty() || s.starts_with('#') || keyword {
                 continue;
             }
+            last_line = (line_no, s);
 
-            if s.to_uppercase().starts_with("LUT_3D_SIZE") {
-                let parts: Vec<&str> = s.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    size = parts[1].parse::<usize>().ok();
-                }
-                continue;
-            }
+            let parts: Result<Vec<u32>, _> = s.split_whitespace().map(str::parse::<u32>).collect();
+            let parts = parts.map_err(|_| parse_error(line_no, s, "expected integers"))?;
 
-            // Try parse three floats
-            let parts: Vec<&str> = s.split_whitespace().collect();
-            if parts.len() == 3 {
-                if let (Ok(r), Ok(g), Ok(b)) = (
-                    parts[0].parse::<f32>(),
-                    parts[1].parse::<f32>(),
-                    parts[2].parse::<f32>(),
-                ) {
-                    values.push([r, g, b]);
-                    continue;
+            match parts.len() {
+                3 => {
+                    if let Some(size) = shaper.filter(|&size| raw.len() == size * size * size) {
+                        let msg = format!("more entries than the {} that a {}-point shaper calls for", raw.len(), size);
+                        return Err(parse_error(line_no, s, msg));
+                    }
+                    raw.push([parts[0], parts[1], parts[2]]);
                 }
+                n if shaper.is_none() && raw.is_empty() && n >= 2 => {
+                    if n > MAX_3D_SIZE {
+                        let msg = format!("a {}-point shaper exceeds the maximum LUT size of {}", n, MAX_3D_SIZE);
+                        return Err(parse_error(line_no, s, msg));
+                    }
+                    shaper = Some(n);
+                }
+                n => return Err(parse_error(line_no, s, format!("expected three values, got {}", n))),
             }
-
-            // Ignore other directives like TITLE, DOMAIN_MIN/MAX
         }
 
-        // If LUT_3D_SIZE directive was not present, try to infer from value count
-        let size = match size {
+        let (line_no, content) = last_line;
+        let size = match shaper {
             Some(s) => s,
-            None => {
-                let expected = values.len();
-                let root = (expected as f64).cbrt().round() as usize;
-                if root * root * root == expected && expected > 0 {
-                    root
-                } else {
-                    return Err(LutError::Parse("Missing LUT_3D_SIZE and cannot infer size".into()));
-                }
-            }
+            None => infer_cube_size(raw.len()).ok_or_else(|| {
+                parse_error(line_no, content, format!("cannot infer the LUT size from {} entries", raw.len()))
+            })?,
         };
 
         let expected = size * size * size;
-        if values.len() != expected {
-            return Err(LutError::Parse(format!("Expected {} entries but found {}", expected, values.len())));
-        }
-
-        // Convert floats 0..1 to u8
-        let entries = values
-            .into_iter()
-            .map(|c| {
-                [
-                    (c[0].clamp(0.0, 1.0) * 255.0) as u8,
-                    (c[1].clamp(0.0, 1.0) * 255.0) as u8,
-                    (c[2].clamp(0.0, 1.0) * 255.0) as u8,
-                ]
+        if raw.len() != expected {
+            return Err(parse_error(line_no, content, format!("expected {} entries but found {}", expected, raw.len())));
+        }
+
+        // Round up to the next full bit depth (10-bit -> 1023, 12-bit -> 4095, ...)
+        let max_value = raw.iter().flatten().copied().max().unwrap_or(0);
+        let scale = ((max_value + 1).next_power_of_two() - 1).max(1) as f32;
+
+        // Reorder from blue-fastest to the red-fastest layout used by `entries`
+        let mut entries = vec![[0.0f32; 3]; expected];
+        for (i, rgb) in raw.iter().enumerate() {
+            let b = i % size;
+            let g = (i / size) % size;
+            let r = i / (size * size);
+            entries[Self::index(size, r, g, b)] =
+                [rgb[0] as f32 / scale, rgb[1] as f32 / scale, rgb[2] as f32 / scale];
+        }
+
+        Ok((Self::new(size, [0.0; 3], [1.0; 3], entries)?, LutMetadata::default()))
+    }
+
+    /// Callers check the size and entry count first; parse errors from here
+    /// have no line to point at
+    fn new(
+        size: usize,
+        domain_min: [f32; 3],
+        domain_max: [f32; 3],
+        entries: Vec<[f32; 3]>,
+    ) -> Result<Self, LutError> {
+        if size < 2 {
+            return Err(LutError::Parse(format!("LUT_3D_SIZE must be at least 2, got {}", size)));
+        }
+        if size > MAX_3D_SIZE {
+            return Err(LutError::Parse(format!("LUT size {} exceeds the maximum of {}", size, MAX_3D_SIZE)));
+        }
+
+        let expected = size * size * size;
+        if entries.len() != expected {
+            return Err(LutError::Parse(format!("Expected {} entries but found {}", expected, entries.len())));
+        }
+
+        Ok(Lut3D { size, title: None, domain_min, domain_max, entries })
+    }
+
+    /// Fit a `size`³ LUT taking each source pixel to the graded pixel at the
+    /// same position. Each sampled lattice point gets the mean shift of the
+    /// pixels nearest it; unsampled points take the mean shift of their
+    /// sampled neighbours, spreading outwards until the lattice is full.
+    pub fn fit(source: &RgbImage, graded: &RgbImage, size: usize) -> Result<Self, FitError> {
+        if source.dimensions() != graded.dimensions() {
+            return Err(FitError::DimensionMismatch {
+                source_width: source.width(),
+                source_height: source.height(),
+                graded_width: graded.width(),
+                graded_height: graded.height(),
+            });
+        }
+
+        let total = size * size * size;
+        let step = (size - 1) as f32 / 255.0;
+        let mut sums = vec![[0.0f64; 3]; total];
+        let mut counts = vec![0u32; total];
+        for (from, to) in source.pixels().zip(graded.pixels()) {
+            let [r, g, b] = from.0.map(|v| (v as f32 * step).round() as usize);
+            let i = Self::index(size, r, g, b);
+            for c in 0..3 {
+                sums[i][c] += (to[c] as f64 - from[c] as f64) / 255.0;
+            }
+            counts[i] += 1;
+        }
+
+        let sampled = counts.iter().filter(|&&n| n > 0).count();
+        let needed = (total as f64 * MIN_FIT_COVERAGE).ceil() as usize;
+        if sampled < needed {
+            return Err(FitError::InsufficientCoverage { sampled, needed, total });
+        }
+
+        let mut shifts: Vec<Option<[f64; 3]>> = sums
+            .iter()
+            .zip(&counts)
+            .map(|(sum, &n)| (n > 0).then(|| sum.map(|v| v / n as f64)))
+            .collect();
+
+        // Breadth-first from the sampled points, one ring per pass, so each
+        // point is filled only from points nearer the samples than itself
+        let mut frontier: Vec<usize> = (0..total)
+            .filter(|&i| shifts[i].is_none() && Self::neighbours(size, i).any(|n| shifts[n].is_some()))
+            .collect();
+        while !frontier.is_empty() {
+            let filled: Vec<(usize, [f64; 3])> = frontier
+                .iter()
+                .map(|&i| {
+                    let known: Vec<[f64; 3]> = Self::neighbours(size, i).filter_map(|n| shifts[n]).collect();
+                    let mean = [0, 1, 2].map(|c| known.iter().map(|s| s[c]).sum::<f64>() / known.len() as f64);
+                    (i, mean)
+                })
+                .collect();
+            for &(i, shift) in &filled {
+                shifts[i] = Some(shift);
+            }
+            let mut next: Vec<usize> = filled
+                .iter()
+                .flat_map(|&(i, _)| Self::neighbours(size, i))
+                .filter(|&n| shifts[n].is_none())
+                .collect();
+            next.sort_unstable();
+            next.dedup();
+            frontier = next;
+        }
+
+        let entries = (0..total)
+            .map(|i| {
+                let point = Self::point(size, i);
+                let shift = shifts[i].unwrap_or_default();
+                [0, 1, 2].map(|c| (point[c] + shift[c] as f32).clamp(0.0, 1.0))
             })
             .collect();
+        Ok(Self::new(size, [0.0; 3], [1.0; 3], entries).expect("fitted lattice has size³ entries"))
+    }
 
-        Ok(Lut3D { size, entries })
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
     }
 
-    /// Apply the LUT to an image using nearest neighbor in RGB cube.
-    pub fn apply_to_image(&self, img: &DynamicImage) -> RgbaImage {
-        let rgba = img.to_rgba8();
-        let (w, h) = rgba.dimensions();
-        let mut out = RgbaImage::new(w, h);
+    /// Serialize as a .cube file that `Lut3D::parse` reads back
+    pub fn to_cube_string(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        if let Some(title) = &self.title {
+            // The format has no escaping, so quotes cannot appear inside the title
+            let _ = writeln!(out, "TITLE \"{}\"", title.replace('"', "'"));
+        }
+        let _ = writeln!(out, "LUT_3D_SIZE {}", self.size);
+        if self.domain_min != [0.0; 3] || self.domain_max != [1.0; 3] {
+            let [r, g, b] = self.domain_min;
+            let _ = writeln!(out, "DOMAIN_MIN {} {} {}", r, g, b);
+            let [r, g, b] = self.domain_max;
+            let _ = writeln!(out, "DOMAIN_MAX {} {} {}", r, g, b);
+        }
+        for [r, g, b] in &self.entries {
+            let _ = writeln!(out, "{:.6} {:.6} {:.6}", r, g, b);
+        }
+        out
+    }
 
-        for (x, y, pixel) in rgba.enumerate_pixels() {
-            let r = pixel[0] as usize;
-            let g = pixel[1] as usize;
-            let b = pixel[2] as usize;
+    /// Apply the LUT to an image, interpolating between the eight lattice
+    /// points surrounding each pixel.
+    pub fn apply_to_image(&self, img: &DynamicImage) -> RgbaImage {
+        let mut out = img.to_rgba8();
+        out.par_chunks_mut(4 * 1024).for_each(|row| self.apply_to_row(row));
+        out
+    }
 
-            // Map 0..255 -> 0..(size-1)
-            let ri = r * (self.size - 1) / 255;
-            let gi = g * (self.size - 1) / 255;
-            let bi = b * (self.size - 1) / 255;
+    pub fn apply_to_row(&self, row: &mut [u8]) {
+        for pixel in row.chunks_exact_mut(4) {
+            let outc = self.sample([
+                pixel[0] as f32 / 255.0,
+                pixel[1] as f32 / 255.0,
+                pixel[2] as f32 / 255.0,
+            ]);
+            pixel[0] = to_u8(outc[0]);
+            pixel[1] = to_u8(outc[1]);
+            pixel[2] = to_u8(outc[2]);
+        }
+    }
 
-            let idx = Self::index(self.size, ri, gi, bi);
-            let outc = self.entries[idx];
+    /// Trilinear lookup of a normalized (0..1) RGB value.
+    fn sample(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let mut lo = [0usize; 3];
+        let mut hi = [0usize; 3];
+        let mut frac = [0.0f32; 3];
 
-            out.put_pixel(x, y, Rgba([outc[0], outc[1], outc[2], pixel[3]]));
+        for c in 0..3 {
+            (lo[c], hi[c], frac[c]) =
+                lattice_position(rgb[c], self.domain_min[c], self.domain_max[c], self.size);
         }
 
-        out
+        let at = |r: usize, g: usize, b: usize| self.entries[Self::index(self.size, r, g, b)];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+            [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+        };
+
+        // Interpolate along r, then g, then b
+        let c00 = lerp(at(lo[0], lo[1], lo[2]), at(hi[0], lo[1], lo[2]), frac[0]);
+        let c10 = lerp(at(lo[0], hi[1], lo[2]), at(hi[0], hi[1], lo[2]), frac[0]);
+        let c01 = lerp(at(lo[0], lo[1], hi[2]), at(hi[0], lo[1], hi[2]), frac[0]);
+        let c11 = lerp(at(lo[0], hi[1], hi[2]), at(hi[0], hi[1], hi[2]), frac[0]);
+        let c0 = lerp(c00, c10, frac[1]);
+        let c1 = lerp(c01, c11, frac[1]);
+        lerp(c0, c1, frac[2])
     }
 
     fn index(size: usize, r: usize, g: usize, b: usize) -> usize {
         // r fastest (innermost), then g, then b
         r + g * size + b * size * size
     }
+
+    /// Normalized input color of lattice point `i`, the inverse of `index`
+    fn point(size: usize, i: usize) -> [f32; 3] {
+        let scale = (size - 1) as f32;
+        [i % size, (i / size) % size, i / (size * size)].map(|v| v as f32 / scale)
+    }
+
+    /// Lattice points one step away from `i` along an axis
+    fn neighbours(size: usize, i: usize) -> impl Iterator<Item = usize> {
+        let stride = [1, size, size * size];
+        let at = [i % size, (i / size) % size, i / (size * size)];
+        (0..3).flat_map(move |c| {
+            let below = (at[c] > 0).then(|| i - stride[c]);
+            let above = (at[c] + 1 < size).then(|| i + stride[c]);
+            below.into_iter().chain(above)
+        })
+    }
+}
+
+/// Cube size whose n^3 lattice matches the entry count, if it is one we accept
+fn infer_cube_size(count: usize) -> Option<usize> {
+    let root = (count as f64).cbrt().round() as usize;
+    ((2..=MAX_3D_SIZE).contains(&root) && root * root * root == count).then_some(root)
+}
+
+/// Three finite numbers; `nan` and `inf` parse as floats but are refused
+fn parse_triplet(parts: &[&str]) -> Option<[f32; 3]> {
+    if parts.len() != 3 {
+        return None;
+    }
+    let r = parts[0].parse::<f32>().ok()?;
+    let g = parts[1].parse::<f32>().ok()?;
+    let b = parts[2].parse::<f32>().ok()?;
+    [r, g, b].iter().all(|v| v.is_finite()).then_some([r, g, b])
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::Rgba;
+    use std::fs::File;
     use std::io::Write;
 
     #[test]
@@ -148,7 +732,9 @@ mod tests {
         writeln!(f, "0 1 1").unwrap();
         writeln!(f, "1 1 1").unwrap();
 
-        let lut = Lut3D::from_cube(&tmp).unwrap();
+        let Lut::ThreeD(lut) = Lut::from_file(&tmp).unwrap() else {
+            panic!("expected a 3D LUT");
+        };
         assert_eq!(lut.size, 2);
         assert_eq!(lut.entries.len(), 8);
 
@@ -159,4 +745,289 @@ mod tests {
 
         let _ = std::fs::remove_file(tmp);
     }
+
+    /// Identity cube of the given size with an optional header
+    fn identity_cube(size: usize, header: &str) -> String {
+        let mut text = format!("{}\nLUT_3D_SIZE {}\n", header, size);
+        let max = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    text.push_str(&format!("{} {} {}\n", r as f32 / max, g as f32 / max, b as f32 / max));
+                }
+            }
+        }
+        text
+    }
+
+    fn apply_pixel(lut: &Lut3D, rgb: [u8; 3]) -> [u8; 3] {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([rgb[0], rgb[1], rgb[2], 255])));
+        let p = *lut.apply_to_image(&img).get_pixel(0, 0);
+        [p[0], p[1], p[2]]
+    }
+
+    #[test]
+    fn test_identity_lut_interpolates_between_corners() {
+        let lut = Lut3D::parse(&identity_cube(2, "")).unwrap().0;
+        // Nearest-neighbor would snap every channel to 0 or 255
+        assert_eq!(apply_pixel(&lut, [50, 100, 150]), [50, 100, 150]);
+        assert_eq!(apply_pixel(&lut, [0, 128, 255]), [0, 128, 255]);
+    }
+
+    #[test]
+    fn test_inverting_lut() {
+        let text = "LUT_3D_SIZE 2\n1 1 1\n0 1 1\n1 0 1\n0 0 1\n1 1 0\n0 1 0\n1 0 0\n0 0 0\n";
+        let lut = Lut3D::parse(text).unwrap().0;
+        assert_eq!(apply_pixel(&lut, [64, 128, 192]), [191, 127, 63]);
+    }
+
+    #[test]
+    fn test_domain_min_max() {
+        let lut = Lut3D::parse(&identity_cube(2, "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 0.5 0.5 0.5")).unwrap().0;
+        // 64/255 sits at 50.2% of the [0, 0.5] domain; inputs past DOMAIN_MAX clamp
+        assert_eq!(apply_pixel(&lut, [64, 0, 200]), [128, 0, 255]);
+
+        let inverted = "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 0 1 1\nLUT_3D_SIZE 2\n";
+        assert!(Lut3D::parse(inverted).is_err());
+    }
+
+    #[test]
+    fn test_smooth_ramp_has_no_steps() {
+        let lut = Lut3D::parse(&identity_cube(3, "TITLE \"ramp\"")).unwrap().0;
+        let ramp = RgbaImage::from_fn(256, 1, |x, _| Rgba([x as u8, x as u8, x as u8, 255]));
+        let out = lut.apply_to_image(&DynamicImage::ImageRgba8(ramp));
+
+        let reds: Vec<u8> = out.pixels().map(|p| p[0]).collect();
+        for (x, pair) in reds.windows(2).enumerate() {
+            assert!(pair[1] >= pair[0] && pair[1] - pair[0] <= 1, "step at {}: {:?}", x, pair);
+        }
+        assert_eq!((reds[0], reds[255]), (0, 255));
+    }
+
+    #[test]
+    fn test_1d_lut_per_channel_curves() {
+        // Red inverted, green identity, blue flat at 0.5
+        let text = "TITLE \"curves\"\nLUT_1D_SIZE 3\n1 0 0.5\n0.5 0.5 0.5\n0 1 0.5\n";
+        let (lut, metadata) = Lut::parse(text, Some("cube")).unwrap();
+        assert!(matches!(lut, Lut::OneD(_)));
+        let expected = LutMetadata { title: Some("curves".to_string()), ..LutMetadata::default() };
+        assert_eq!(lut.info(metadata), LutInfo { kind: "1d", size: 3, entries: 3, metadata: expected });
+
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([64, 128, 255, 77])));
+        assert_eq!(*lut.apply_to_image(&img).get_pixel(0, 0), Rgba([191, 128, 128, 77]));
+    }
+
+    #[test]
+    fn test_3dl_identity_reorders_blue_fastest() {
+        let mut text = String::from("# 12-bit output\n0 1023\n");
+        for r in [0, 4095] {
+            for g in [0, 4095] {
+                for b in [0, 4095] {
+                    text.push_str(&format!("{} {} {}\n", r, g, b));
+                }
+            }
+        }
+
+        let (lut, _) = Lut::parse(&text, Some("3dl")).unwrap();
+        let Lut::ThreeD(ref lut3d) = lut else {
+            panic!("expected a 3D LUT");
+        };
+        assert_eq!(lut3d.size, 2);
+        // A wrong axis order would swap red and blue here
+        assert_eq!(apply_pixel(lut3d, [50, 100, 150]), [50, 100, 150]);
+    }
+
+    #[test]
+    fn test_dispatch_by_header_without_extension() {
+        assert!(matches!(Lut::parse(&identity_cube(2, ""), None), Ok((Lut::ThreeD(_), _))));
+        assert!(matches!(Lut::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n", Some("txt")), Ok((Lut::OneD(_), _))));
+    }
+
+    #[test]
+    fn test_parse_errors_report_line_and_content() {
+        let error = |text: &str, extension| Lut::parse(text, Some(extension)).err().unwrap().to_string();
+
+        assert_eq!(
+            error("LUT_3D_SIZE 2\n0 0 0\n0 0 abc\n", "cube"),
+            "Parse error: line 3 ('0 0 abc'): expected three numbers"
+        );
+        assert_eq!(error("0 1023\n0 0 0\n0 0 x\n", "3dl"), "Parse error: line 3 ('0 0 x'): expected integers");
+        assert_eq!(
+            error("LUT_1D_SIZE 2\nDOMAIN_MIN 0 0\n", "cube"),
+            "Parse error: line 2 ('DOMAIN_MIN 0 0'): DOMAIN_MIN needs three numbers"
+        );
+        assert_eq!(
+            error("LUT_3D_SIZE 2\n0 0 nan\n", "cube"),
+            "Parse error: line 2 ('0 0 nan'): expected three numbers"
+        );
+
+        // Errors about the table as a whole point at where it ends or overflows
+        assert_eq!(
+            error("LUT_1D_SIZE 3\n0 0 0\n\n1 1 1\n# done\n", "cube"),
+            "Parse error: line 4 ('1 1 1'): expected 3 entries but found 2"
+        );
+        let mut overflowing = identity_cube(2, "");
+        overflowing.push_str("0.5 0.5 0.5\n");
+        assert_eq!(
+            error(&overflowing, "cube"),
+            "Parse error: line 11 ('0.5 0.5 0.5'): more entries than the 8 that LUT_3D_SIZE 2 calls for"
+        );
+        assert_eq!(
+            error("0 0 0\n1 1 1\n", "cube"),
+            "Parse error: line 2 ('1 1 1'): missing LUT_3D_SIZE, and 2 entries do not make a cube"
+        );
+        assert_eq!(
+            error("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 0 1 1\nLUT_3D_SIZE 2\n", "cube"),
+            "Parse error: line 3 ('LUT_3D_SIZE 2'): expected 8 entries but found 0"
+        );
+        assert_eq!(
+            error(&identity_cube(2, "DOMAIN_MAX 1 1 1\nDOMAIN_MIN 0 1 0"), "cube"),
+            "Parse error: line 2 ('DOMAIN_MIN 0 1 0'): DOMAIN_MAX must be greater than DOMAIN_MIN"
+        );
+
+        // Long lines are cut short
+        let long = format!("LUT_3D_SIZE 2\n{}\n", "1".repeat(100));
+        assert!(error(&long, "cube").contains(&format!("('{}...')", "1".repeat(60))));
+    }
+
+    #[test]
+    fn test_out_of_range_values_are_kept_and_counted() {
+        // Red overshoots a little at the top and undershoots at the bottom
+        let text = "LUT_1D_SIZE 2\n-0.02 0 0\n1.04 1 1\n";
+        let (lut, metadata) = Lut::parse(text, Some("cube")).unwrap();
+        assert_eq!(metadata.out_of_range_values, 2);
+
+        // The curve is followed as written; only the output is clamped
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(3, 1, |x, _| {
+            let v = [0, 128, 255][x as usize];
+            Rgba([v, v, v, 255])
+        }));
+        let out = lut.apply_to_image(&img);
+        let reds: Vec<u8> = out.pixels().map(|p| p[0]).collect();
+        assert_eq!(reds, [0, 131, 255]);
+    }
+
+    #[test]
+    fn test_fixtures_from_grading_tools() {
+        // DaVinci Resolve: TITLE, explicit unit domain, a few values just past 0..1
+        let (lut, metadata) =
+            Lut::parse(include_str!("../../tests/fixtures/luts/resolve_film_look.cube"), Some("cube")).unwrap();
+        assert_eq!(lut.info(metadata.clone()).size, 5);
+        assert_eq!(metadata.title.as_deref(), Some("Film Look"));
+        assert_eq!((metadata.domain_min, metadata.domain_max), ([0.0; 3], [1.0; 3]));
+        assert_eq!(metadata.out_of_range_values, 3);
+
+        // FilmConvert: a log profile over a wider input domain
+        let (lut, metadata) =
+            Lut::parse(include_str!("../../tests/fixtures/luts/filmconvert_log.cube"), Some("cube")).unwrap();
+        let Lut::ThreeD(ref lut3d) = lut else {
+            panic!("expected a 3D LUT");
+        };
+        assert_eq!(lut3d.size, 3);
+        assert_eq!(metadata.title.as_deref(), Some("FilmConvert Nitrate KD5207"));
+        assert_eq!((metadata.domain_min, metadata.domain_max), ([-0.125; 3], [1.25; 3]));
+        // Every component on the domain's edges lies outside 0..1
+        assert_eq!(metadata.out_of_range_values, 54);
+        // The lattice is an identity over its domain, so lookups land back on the input
+        assert_eq!(apply_pixel(lut3d, [30, 128, 220]), [30, 128, 220]);
+
+        // Adobe: a 1D curve with Windows line endings
+        let (lut, metadata) =
+            Lut::parse(include_str!("../../tests/fixtures/luts/adobe_curve.cube"), Some("cube")).unwrap();
+        assert!(matches!(lut, Lut::OneD(_)));
+        assert_eq!(lut.info(metadata.clone()).size, 16);
+        assert_eq!(metadata.title.as_deref(), Some("Contrast Curve"));
+
+        // Autodesk Flame: 12-bit output behind a 10-bit shaper
+        let (lut, _) = Lut::parse(include_str!("../../tests/fixtures/luts/flame_identity.3dl"), Some("3dl")).unwrap();
+        let Lut::ThreeD(ref lut3d) = lut else {
+            panic!("expected a 3D LUT");
+        };
+        assert_eq!(lut3d.size, 17);
+        assert_eq!(apply_pixel(lut3d, [30, 128, 220]), [30, 128, 220]);
+    }
+
+    #[test]
+    fn test_upload_checks_bound_the_table() {
+        let (lut, metadata) =
+            Lut::from_bytes(identity_cube(3, "TITLE \"Warm film\"").as_bytes(), Some("cube")).unwrap();
+        let metadata = lut.info(metadata);
+        assert_eq!((metadata.kind, metadata.size, metadata.entries), ("3d", 3, 27));
+        assert_eq!(metadata.metadata.title.as_deref(), Some("Warm film"));
+
+        // Refused at the directive, before any entries are read
+        let err = Lut::from_bytes(b"LUT_3D_SIZE 130\n0 0 0\n", Some("cube")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: line 1 ('LUT_3D_SIZE 130'): LUT_3D_SIZE exceeds the maximum of 129"
+        );
+
+        let err = Lut::from_bytes(b"LUT_3D_SIZE 2\n0 0 0\n0 \xff 0\n", Some("cube")).err().unwrap();
+        assert_eq!(err.to_string(), "Parse error: line 3: not a text file (invalid UTF-8 at byte 22)");
+    }
+
+    /// Source colors spread over much of the lattice
+    fn spread_source() -> RgbImage {
+        RgbImage::from_fn(256, 256, |x, y| image::Rgb([x as u8, y as u8, (x ^ y) as u8]))
+    }
+
+    fn graded(source: &RgbImage, grade: impl Fn([u8; 3]) -> [u8; 3]) -> RgbImage {
+        let mut out = source.clone();
+        out.pixels_mut().for_each(|p| p.0 = grade(p.0));
+        out
+    }
+
+    fn assert_close(got: [u8; 3], want: [u8; 3], tolerance: u8) {
+        assert!((0..3).all(|c| got[c].abs_diff(want[c]) <= tolerance), "{:?} vs {:?}", got, want);
+    }
+
+    #[test]
+    fn test_fit_recovers_a_grade() {
+        let warm = |[r, g, b]: [u8; 3]| [(r as f32 * 0.9 + 20.0) as u8, g, (b as f32 * 0.8) as u8];
+        let source = spread_source();
+        let lut = Lut3D::fit(&source, &graded(&source, warm), FITTED_3D_SIZE).unwrap();
+
+        assert_eq!(lut.size, FITTED_3D_SIZE);
+        for rgb in [[0, 0, 0], [40, 200, 224], [128, 128, 0], [255, 0, 255], [250, 10, 240]] {
+            assert_close(apply_pixel(&lut, rgb), warm(rgb), 3);
+        }
+    }
+
+    #[test]
+    fn test_fit_fills_unsampled_colors_from_neighbours() {
+        // Only the darker half of each channel appears in the pair
+        let source = RgbImage::from_fn(256, 256, |x, y| image::Rgb([x as u8 / 2, y as u8 / 2, (x ^ y) as u8 / 2]));
+        let lift = |rgb: [u8; 3]| rgb.map(|v| v.saturating_add(16));
+        let lut = Lut3D::fit(&source, &graded(&source, lift), FITTED_3D_SIZE).unwrap();
+
+        assert_close(apply_pixel(&lut, [60, 30, 90]), [76, 46, 106], 1);
+        assert_close(apply_pixel(&lut, [200, 30, 220]), [216, 46, 236], 1);
+        assert_close(apply_pixel(&lut, [255, 255, 255]), [255, 255, 255], 0);
+    }
+
+    #[test]
+    fn test_fit_refuses_unusable_pairs() {
+        let source = spread_source();
+        let cropped = image::imageops::crop_imm(&source, 0, 0, 200, 256).to_image();
+        let err = Lut3D::fit(&source, &cropped, FITTED_3D_SIZE).err().unwrap();
+        assert!(matches!(err, FitError::DimensionMismatch { graded_width: 200, .. }));
+        assert!(err.to_string().contains("200x256"), "{}", err);
+
+        let flat = RgbImage::from_pixel(64, 64, image::Rgb([90, 120, 30]));
+        let err = Lut3D::fit(&flat, &flat, FITTED_3D_SIZE).err().unwrap();
+        assert!(matches!(err, FitError::InsufficientCoverage { sampled: 1, .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_to_cube_string_round_trips() {
+        let lut = Lut3D::parse(&identity_cube(3, "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 0.5 1 1")).unwrap().0;
+        let lut = lut.with_title("Say \"cheese\"");
+        let text = lut.to_cube_string();
+        assert!(text.starts_with("TITLE \"Say 'cheese'\"\nLUT_3D_SIZE 3\n"), "{}", text);
+
+        let parsed = Lut3D::parse(&text).unwrap().0;
+        assert_eq!(parsed.title.as_deref(), Some("Say 'cheese'"));
+        assert_eq!(parsed.domain_max, [0.5, 1.0, 1.0]);
+        assert_eq!(parsed.entries, lut.entries);
+    }
 }
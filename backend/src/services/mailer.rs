@@ -0,0 +1,155 @@
+// backend/src/services/mailer.rs
+// Outgoing email: SMTP when configured, the log otherwise
+
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::MailConfig;
+
+/// A plain-text message to one recipient
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("Invalid address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("Failed to build message: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Mail is not configured: {0}")]
+    Config(String),
+}
+
+#[axum::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), MailError>;
+}
+
+/// Sends through the SMTP relay in `MailConfig`
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig) -> Result<Self, MailError> {
+        let host = config
+            .smtp_host
+            .as_deref()
+            .ok_or_else(|| MailError::Config("SMTP_HOST is not set".to_string()))?;
+        let from = config
+            .smtp_from
+            .as_deref()
+            .ok_or_else(|| MailError::Config("SMTP_FROM is not set".to_string()))?
+            .parse()?;
+
+        let mut builder = match config.smtp_tls.as_str() {
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+        }
+        .port(config.smtp_port);
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self { transport: builder.build(), from })
+    }
+}
+
+#[axum::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email.to.parse()?)
+            .subject(email.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Writes messages to the log instead of sending them, for development
+/// without a mail server
+pub struct LogMailer;
+
+#[axum::async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        tracing::info!("Email to {} (not sent, SMTP_HOST is unset): {}\n{}", email.to, email.subject, email.body);
+        Ok(())
+    }
+}
+
+/// Keeps sent messages in memory for tests to read back
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: std::sync::Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything sent so far, oldest first
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[axum::async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(email);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(host: Option<&str>, from: Option<&str>) -> MailConfig {
+        MailConfig {
+            smtp_host: host.map(str::to_string),
+            smtp_port: 2525,
+            smtp_tls: "none".to_string(),
+            smtp_username: None,
+            smtp_password: None,
+            smtp_from: from.map(str::to_string),
+            verification_ttl_hours: 48,
+            password_reset_url: "http://localhost/reset-password".to_string(),
+            password_reset_ttl_minutes: 60,
+            organization_invite_url: "http://localhost/accept-invite".to_string(),
+            organization_invite_ttl_hours: 168,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_smtp_mailer_needs_a_host_and_sender() {
+        assert!(SmtpMailer::new(&config(Some("localhost"), Some("MediaForge <noreply@example.com>"))).is_ok());
+        assert!(matches!(SmtpMailer::new(&config(None, Some("noreply@example.com"))), Err(MailError::Config(_))));
+        assert!(matches!(SmtpMailer::new(&config(Some("localhost"), Some("not an address"))), Err(MailError::Address(_))));
+    }
+
+    #[tokio::test]
+    async fn test_memory_mailer_keeps_messages_in_order() {
+        let mailer = MemoryMailer::new();
+        for to in ["a@example.com", "b@example.com"] {
+            let email = Email { to: to.to_string(), subject: "Hi".to_string(), body: "Hello".to_string() };
+            mailer.send(email).await.unwrap();
+        }
+        let sent: Vec<_> = mailer.sent().into_iter().map(|m| m.to).collect();
+        assert_eq!(sent, ["a@example.com", "b@example.com"]);
+    }
+}
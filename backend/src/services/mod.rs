@@ -3,8 +3,37 @@ pub mod queue;
 pub mod processing;
 pub mod quota;
 pub mod lut;
+pub mod sniff;
+pub mod animation;
+pub mod heic;
+pub mod formats;
+pub mod probe;
+pub mod video;
+pub mod ffmpeg;
+pub mod archive;
+pub mod webhook;
+pub mod rate_limit;
+pub mod cleanup;
+pub mod relocation;
+pub mod stats;
+pub mod download_token;
+pub mod signed_urls;
+pub mod byte_range;
+pub mod conditional;
+pub mod readiness;
+pub mod mailer;
+pub mod url_fetch;
+pub mod resumable;
+pub mod analysis;
+pub mod scan;
+pub mod job_params;
+#[cfg(feature = "onnx")]
+mod u2net;
 mod worker;
 
-pub use storage::{Storage, LocalStorage, S3Storage};
+pub use storage::{Storage, StorageLocation, StorageRouter, LocalStorage, MemoryStorage, S3Storage};
+pub use mailer::{Mailer, LogMailer, MemoryMailer, SmtpMailer};
+pub use scan::{Scanner, ClamAvScanner, NoopScanner};
 pub use queue::{Queue, JobMessage};
-pub use worker::start_worker;
\ No newline at end of file
+pub use worker::{start_worker, JobSource};
+pub use cleanup::start_cleanup;
\ No newline at end of file
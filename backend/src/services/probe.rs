@@ -0,0 +1,201 @@
+// backend/src/services/probe.rs
+// Cheap metadata probing for uploaded media (dimensions, duration)
+
+use image::error::{DecodingError, ImageError};
+use image::ImageFormat;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use super::ffmpeg::Ffmpeg;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MediaInfo {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_seconds: Option<f64>,
+    pub frame_rate: Option<f64>,
+}
+
+/// Read image dimensions from the header without decoding pixel data. They
+/// are the displayed dimensions: swapped when the Exif orientation turns the
+/// image on its side, as processing will.
+pub fn probe_image(path: &Path) -> Result<MediaInfo, image::ImageError> {
+    use image::{metadata::Orientation, ImageDecoder};
+
+    let mut decoder = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    let (width, height) = match decoder.orientation()? {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => {
+            let (width, height) = decoder.dimensions();
+            (height, width)
+        }
+        _ => decoder.dimensions(),
+    };
+
+    Ok(MediaInfo {
+        width: Some(width),
+        height: Some(height),
+        duration_seconds: None,
+        frame_rate: None,
+    })
+}
+
+/// Pixels the image decodes to: its area, or for a GIF the canvas area times
+/// the frame count, as each frame is rendered onto the whole canvas. Only
+/// headers are read, and GIF frame data is skipped without decompressing it.
+pub fn decoded_pixels(path: &Path) -> Result<u64, ImageError> {
+    let mut reader = image::ImageReader::open(path)?.with_guessed_format()?;
+    if reader.format() != Some(ImageFormat::Gif) {
+        // The default limits would refuse a huge canvas before saying how big it is
+        reader.no_limits();
+        let (width, height) = reader.into_dimensions()?;
+        return Ok(u64::from(width) * u64::from(height));
+    }
+
+    let gif_error = |e: gif::DecodingError| ImageError::Decoding(DecodingError::new(ImageFormat::Gif.into(), e));
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options.read_info(BufReader::new(File::open(path)?)).map_err(gif_error)?;
+    let canvas = u64::from(decoder.width()) * u64::from(decoder.height());
+    let mut frames = 0;
+    while decoder.next_frame_info().map_err(gif_error)?.is_some() {
+        frames += 1;
+    }
+    Ok(canvas * frames.max(1))
+}
+
+/// Probe a video with ffprobe. Returns `Ok(None)` when ffprobe is not installed
+/// so callers can degrade gracefully instead of failing the upload.
+pub async fn probe_video(ffmpeg: &Ffmpeg, path: &Path) -> Result<Option<MediaInfo>, std::io::Error> {
+    if !ffmpeg.available() {
+        return Ok(None);
+    }
+    let output = match ffmpeg
+        .ffprobe()
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height,avg_frame_rate:format=duration"])
+        .args(["-of", "json"])
+        .arg(path.as_os_str())
+        .kill_on_drop(true)
+        .output()
+        .await
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::warn!("ffprobe not found; skipping video metadata for {}", path.display());
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    parse_ffprobe_json(&output.stdout).map(Some)
+}
+
+#[derive(Deserialize)]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeStream {
+    width: Option<u32>,
+    height: Option<u32>,
+    // Rational such as "30000/1001"; "0/0" when unknown
+    avg_frame_rate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeFormat {
+    // ffprobe reports duration as a decimal string
+    duration: Option<String>,
+}
+
+fn parse_ffprobe_json(raw: &[u8]) -> Result<MediaInfo, std::io::Error> {
+    let parsed: FfprobeOutput = serde_json::from_slice(raw)
+        .map_err(|e| std::io::Error::other(format!("Invalid ffprobe output: {}", e)))?;
+
+    let stream = parsed.streams.first();
+    Ok(MediaInfo {
+        width: stream.and_then(|s| s.width),
+        height: stream.and_then(|s| s.height),
+        duration_seconds: parsed
+            .format
+            .and_then(|f| f.duration)
+            .and_then(|d| d.parse::<f64>().ok()),
+        frame_rate: stream
+            .and_then(|s| s.avg_frame_rate.as_deref())
+            .and_then(parse_rational),
+    })
+}
+
+fn parse_rational(raw: &str) -> Option<f64> {
+    let (num, den) = raw.split_once('/').unwrap_or((raw, "1"));
+    let value = num.parse::<f64>().ok()? / den.parse::<f64>().ok()?;
+    (value.is_finite() && value > 0.0).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ffprobe_json() {
+        let raw = br#"{
+            "programs": [],
+            "streams": [{"width": 1920, "height": 1080, "avg_frame_rate": "30000/1001"}],
+            "format": {"duration": "12.480000"}
+        }"#;
+        let info = parse_ffprobe_json(raw).unwrap();
+        assert_eq!(info.width, Some(1920));
+        assert_eq!(info.height, Some(1080));
+        assert_eq!(info.duration_seconds, Some(12.48));
+        assert!((info.frame_rate.unwrap() - 29.97).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_parse_rational_rejects_unknown_rate() {
+        assert_eq!(parse_rational("25/1"), Some(25.0));
+        assert_eq!(parse_rational("24"), Some(24.0));
+        assert_eq!(parse_rational("0/0"), None);
+    }
+
+    #[test]
+    fn test_parse_ffprobe_json_without_video_stream() {
+        let info = parse_ffprobe_json(br#"{"streams": [], "format": {}}"#).unwrap();
+        assert_eq!(info, MediaInfo::default());
+    }
+
+    #[test]
+    fn test_decoded_pixels_reads_only_the_header() {
+        // 31 KB, but 256 megapixels once decoded
+        let bomb = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/huge_canvas.png"));
+        assert_eq!(decoded_pixels(bomb).unwrap(), 16_000 * 16_000);
+    }
+
+    #[test]
+    fn test_probe_image_reads_header() {
+        let path = std::env::temp_dir().join(format!("probe_{}.png", uuid::Uuid::new_v4()));
+        image::DynamicImage::new_rgb8(7, 3).save(&path).unwrap();
+
+        let info = probe_image(&path).unwrap();
+        assert_eq!((info.width, info.height), (Some(7), Some(3)));
+        assert_eq!(decoded_pixels(&path).unwrap(), 21);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,8 +1,19 @@
 // backend/src/services/processing.rs
 // Self-hosted background removal and image processing
 
-use image::{DynamicImage, Rgba, RgbaImage, GenericImageView};
+use image::{DynamicImage, GrayImage, ImageFormat, Luma, Pixel, Rgba, RgbaImage};
+use image::{metadata::Orientation, ImageDecoder, ImageEncoder, ImageReader};
+use rayon::prelude::*;
+use std::io::{BufRead, Seek};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::analysis::{self, ImageAnalysis};
+use super::animation::{self, Animation};
+use super::heic;
+use super::lut::{self, FitError, Lut, Lut3D, LutError};
+use super::sniff::{self, SniffedType};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ProcessingError {
@@ -10,98 +21,439 @@ pub enum ProcessingError {
     ModelLoadFailed(String),
     #[error("Image load failed: {0}")]
     ImageLoadFailed(#[from] image::ImageError),
+    #[error("Decode failed: {0}")]
+    DecodeFailed(String),
     #[error("Inference failed: {0}")]
     InferenceFailed(String),
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
+    #[error("Unsupported conversion: {0}")]
+    Unsupported(String),
+    #[error("{0} is not installed or not on PATH")]
+    ToolMissing(&'static str),
+    #[error("Invalid LUT: {0}")]
+    InvalidLut(#[from] LutError),
+    #[error("{0}")]
+    LutFit(#[from] FitError),
+    #[error("Image too large: decodes to {pixels} pixels, more than the {max} allowed")]
+    TooManyPixels { pixels: u64, max: u64 },
+}
+
+/// Encoder settings for saved images
+#[derive(Debug, Clone, Copy)]
+pub struct OutputEncoding {
+    pub format: ImageFormat,
+    /// 1–100, honored by JPEG, WebP and AVIF; lossless formats ignore it
+    pub quality: Option<u8>,
+    /// Carry the source's Exif data (camera, GPS, ...) into the output. Only
+    /// JPEG, PNG and lossless WebP can store it; other outputs drop it anyway.
+    pub keep_exif: bool,
+    /// Size to get a still JPEG, WebP or AVIF output under, replacing
+    /// `quality` with the highest one that does
+    pub target_bytes: Option<u64>,
+}
+
+impl OutputEncoding {
+    pub fn new(format: ImageFormat) -> Self {
+        Self { format, quality: None, keep_exif: false, target_bytes: None }
+    }
+}
+
+/// What `convert_format` had to give up on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Converted {
+    /// The input was animated but the output format is a still image, so
+    /// only the first frame was kept
+    pub frames_dropped: bool,
+    /// Quality a size target settled on
+    pub quality: Option<u8>,
+    /// No quality got the output under the size target; it was encoded at
+    /// the lowest
+    pub target_size_missed: bool,
+}
+
+/// Bytes a decoder may allocate for each pixel of the budget: enough for
+/// 16-bit RGBA, the widest any supported format decodes to
+const DECODE_BYTES_PER_PIXEL: u64 = 8;
+
+/// Quality `image` uses for JPEG when none is given
+const DEFAULT_JPEG_QUALITY: u8 = 75;
+
+/// Quality of WebP background removal results
+const CUT_OUT_WEBP_QUALITY: u8 = 90;
+
+/// Quality AVIF is encoded at when none is given
+#[cfg(feature = "avif")]
+const DEFAULT_AVIF_QUALITY: u8 = 70;
+
+/// rav1e's speed, from 1 (smallest files) to 10. At 6 a 12 MP photo takes in
+/// the order of ten seconds on one core.
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 6;
+
+/// Longest side of an AVIF output. Encoding time grows with the pixel count,
+/// and larger images would hold a worker for minutes.
+pub const AVIF_MAX_EDGE: u32 = 4096;
+
+/// Qualities a size target is searched between, and the most encodes the
+/// search may take
+const TARGET_QUALITIES: std::ops::RangeInclusive<u8> = 5..=95;
+const TARGET_SEARCH_STEPS: u32 = 7;
+
+/// Receives how far a long operation has got, 0 to 100. Values only go up,
+/// though they may arrive from any of rayon's threads.
+pub type OnProgress<'a> = &'a (dyn Fn(u32) + Sync);
+
+/// Progress reported once a still image is decoded and once its pixels are
+/// done; rows fill the gap between them and saving takes it to 100
+const DECODED: u32 = 20;
+const PROCESSED: u32 = 80;
+
+/// Blur applied to the cut-out mask before compositing over a blurred
+/// background, giving its edge a few pixels of alpha gradient
+const FEATHER_SIGMA: f32 = 1.5;
+
+/// Background blur used when a job doesn't choose one
+pub const DEFAULT_BLUR_SIGMA: f32 = 12.0;
+
+/// Counts the rows of one stage, reporting each whole percent of
+/// `start..=end` as it is reached
+struct StageProgress<'a> {
+    on_progress: OnProgress<'a>,
+    start: u32,
+    end: u32,
+    total: usize,
+    done: AtomicUsize,
+    /// Last value reported, held while reporting so values never go backwards
+    reported: Mutex<u32>,
+}
+
+impl<'a> StageProgress<'a> {
+    fn new(on_progress: OnProgress<'a>, start: u32, end: u32, total: usize) -> Self {
+        Self { on_progress, start, end, total: total.max(1), done: AtomicUsize::new(0), reported: Mutex::new(start) }
+    }
+
+    /// Rows between `DECODED` and `PROCESSED`
+    fn rows(on_progress: OnProgress<'a>, img: &RgbaImage) -> Self {
+        Self::new(on_progress, DECODED, PROCESSED, img.height() as usize)
+    }
+
+    fn step(&self) {
+        let done = (self.done.fetch_add(1, Ordering::Relaxed) + 1).min(self.total);
+        self.report(self.start + ((self.end - self.start) as usize * done / self.total) as u32);
+    }
+
+    /// Report `end`, unless the last row already did
+    fn finish(&self) {
+        self.report(self.end);
+    }
+
+    fn report(&self, percent: u32) {
+        let mut reported = self.reported.lock().unwrap_or_else(|e| e.into_inner());
+        if percent > *reported {
+            *reported = percent;
+            (self.on_progress)(percent);
+        }
+    }
+}
+
+/// Run `f` over each row of `img` in parallel, counting the rows into `progress`
+fn par_rows(img: &mut RgbaImage, progress: Option<&StageProgress>, f: impl Fn(&mut [u8]) + Sync) {
+    let row_bytes = img.width() as usize * 4;
+    if row_bytes == 0 {
+        return;
+    }
+    img.par_chunks_mut(row_bytes).for_each(|row| {
+        f(row);
+        if let Some(progress) = progress {
+            progress.step();
+        }
+    });
+}
+
+/// A region of an image, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Alpha a pixel needs to count as part of the subject when trimming, so
+/// stray near-transparent pixels don't widen the box
+const TRIM_ALPHA_THRESHOLD: u8 = 16;
+
+/// What trimming did to a background removal's output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trimmed {
+    /// Trimming wasn't asked for
+    Off,
+    /// Nothing of the subject was found, so the output kept its canvas
+    /// rather than becoming 0×0
+    Empty,
+    /// The output was cropped to these bounds of the input
+    To(Bounds),
+}
+
+impl Trimmed {
+    /// Work out the crop from a cut-out, before anything is composited
+    /// behind it
+    fn plan(cut_out: &RgbaImage, padding_percent: Option<f32>) -> Self {
+        match padding_percent {
+            None => Self::Off,
+            Some(padding) => subject_bounds(cut_out, padding).map_or(Self::Empty, Self::To),
+        }
+    }
+
+    fn apply(self, img: RgbaImage) -> RgbaImage {
+        match self {
+            Self::To(b) => image::imageops::crop_imm(&img, b.x, b.y, b.width, b.height).to_image(),
+            Self::Off | Self::Empty => img,
+        }
+    }
+}
+
+/// The bounding box of the pixels above `TRIM_ALPHA_THRESHOLD`, grown on
+/// each side by `padding_percent` of its width and height and kept within
+/// the image. `None` when no pixel is.
+fn subject_bounds(cut_out: &RgbaImage, padding_percent: f32) -> Option<Bounds> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in cut_out.enumerate_pixels() {
+        if pixel[3] > TRIM_ALPHA_THRESHOLD {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if min_x > max_x {
+        return None;
+    }
+
+    let pad = |size: u32| (size as f32 * padding_percent.max(0.0) / 100.0).round() as u32;
+    let (pad_x, pad_y) = (pad(max_x - min_x + 1), pad(max_y - min_y + 1));
+    let (x, y) = (min_x.saturating_sub(pad_x), min_y.saturating_sub(pad_y));
+    let right = (max_x + pad_x).min(cut_out.width() - 1);
+    let bottom = (max_y + pad_y).min(cut_out.height() - 1);
+    Some(Bounds { x, y, width: right - x + 1, height: bottom - y + 1 })
+}
+
+/// Blend `cut_out` onto `background` of the same size, using its alpha
+fn composite_over(cut_out: &RgbaImage, background: &mut RgbaImage) {
+    for (pixel, bg_pixel) in cut_out.pixels().zip(background.pixels_mut()) {
+        let alpha = pixel[3] as f32 / 255.0;
+        for c in 0..3 {
+            bg_pixel[c] = ((pixel[c] as f32 * alpha) + (bg_pixel[c] as f32 * (1.0 - alpha))) as u8;
+        }
+    }
+}
+
+/// A decoded image, turned upright
+struct Decoded {
+    image: DynamicImage,
+    /// The source's Exif chunk, with its orientation reset since the pixels
+    /// have already been rotated
+    exif: Option<Vec<u8>>,
+}
+
+/// Refuse an image over `max_pixels` before decoding it
+pub(crate) fn check_pixels(pixels: u64, max_pixels: u64) -> Result<(), ProcessingError> {
+    if pixels > max_pixels {
+        return Err(ProcessingError::TooManyPixels { pixels, max: max_pixels });
+    }
+    Ok(())
+}
+
+/// Decoder limits for images of at most `max_pixels`, so that whatever a
+/// decoder allocates beyond what the header declared fails cleanly
+pub(crate) fn decode_limits(max_pixels: u64) -> image::Limits {
+    let mut limits = image::Limits::default();
+    limits.max_alloc = Some(max_pixels.saturating_mul(DECODE_BYTES_PER_PIXEL));
+    limits
+}
+
+/// Decode and apply the Exif orientation, so the pixels are the way viewers
+/// show the image. Phone photos are usually stored sideways with a tag
+/// saying so, which plain decoding ignores.
+fn decode_upright<R: BufRead + Seek>(reader: ImageReader<R>, max_pixels: u64) -> Result<Decoded, ProcessingError> {
+    let mut reader = reader.with_guessed_format()?;
+    // Only the header is read until the limits are set, so an oversized
+    // image is refused by its size rather than as a failed allocation
+    reader.no_limits();
+    let mut decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    check_pixels(u64::from(width) * u64::from(height), max_pixels)?;
+    decoder.set_limits(decode_limits(max_pixels))?;
+    let orientation = decoder.orientation()?;
+    let mut exif = decoder.exif_metadata()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+
+    image.apply_orientation(orientation);
+    if let Some(exif) = exif.as_mut() {
+        let _ = Orientation::remove_from_exif_chunk(exif);
+    }
+    Ok(Decoded { image, exif })
+}
+
+fn open_upright(path: &Path, max_pixels: u64) -> Result<Decoded, ProcessingError> {
+    // HEIC goes through an external decoder, which applies the orientation itself
+    if heic::is_heic(path)? {
+        return Ok(Decoded { image: heic::decode(path, max_pixels)?, exif: None });
+    }
+    decode_upright(ImageReader::open(path)?, max_pixels)
+}
+
+/// `open_upright` for an image already in memory, without its Exif
+fn decode_bytes_upright(data: &[u8], max_pixels: u64) -> Result<DynamicImage, ProcessingError> {
+    if sniff::sniff(data) == Some(SniffedType::Heic) {
+        return heic::decode_bytes(data, max_pixels);
+    }
+    Ok(decode_upright(ImageReader::new(std::io::Cursor::new(data)), max_pixels)?.image)
 }
 
 pub struct ImageProcessor {
     model_path: String,
+    /// Images decoding to more pixels than this are refused
+    max_pixels: u64,
+    /// U²-Net session, loaded on first use so that processors created for
+    /// conversions or grading never pay the model load cost.
+    #[cfg(feature = "onnx")]
+    session: std::sync::OnceLock<Result<ort::session::Session, String>>,
 }
 
 impl ImageProcessor {
-    pub fn new(model_path: String) -> Result<Self, ProcessingError> {
+    pub fn new(model_path: String, max_pixels: u64) -> Result<Self, ProcessingError> {
         // Verify model exists
         if !Path::new(&model_path).exists() {
             tracing::warn!("ML model not found at {}, using fallback processing", model_path);
+        } else if !cfg!(feature = "onnx") {
+            tracing::warn!("Built without the `onnx` feature, using fallback processing");
         }
 
-        Ok(Self { model_path })
+        Ok(Self {
+            model_path,
+            max_pixels,
+            #[cfg(feature = "onnx")]
+            session: std::sync::OnceLock::new(),
+        })
+    }
+
+    /// Downscale an encoded image so its longest edge is at most `max_edge` and
+    /// return it as JPEG. Smaller images are re-encoded but never upscaled.
+    pub fn thumbnail(data: &[u8], max_edge: u32, max_pixels: u64) -> Result<Vec<u8>, ProcessingError> {
+        let img = decode_bytes_upright(data, max_pixels)?;
+        let thumb = if img.width() > max_edge || img.height() > max_edge {
+            img.thumbnail(max_edge, max_edge)
+        } else {
+            img
+        };
+
+        let mut out = Vec::new();
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 80);
+        thumb.to_rgb8().write_with_encoder(encoder)?;
+        Ok(out)
+    }
+
+    /// Histograms, luminance and dominant colors of an encoded image
+    pub fn analyze(data: &[u8], max_pixels: u64) -> Result<ImageAnalysis, ProcessingError> {
+        let img = decode_bytes_upright(data, max_pixels)?.to_rgba8();
+        Ok(analysis::analyze(&img))
+    }
+
+    /// Pixels an image may decode to
+    pub fn max_pixels(&self) -> u64 {
+        self.max_pixels
     }
 
-    /// Remove background from an image (simplified version for MVP)
+    /// Whether background removal will run U²-Net rather than the threshold fallback
+    pub fn model_available(&self) -> bool {
+        cfg!(feature = "onnx") && Path::new(&self.model_path).exists()
+    }
+
+    /// Remove background from an image. With `trim`, the output is cropped
+    /// to the subject padded by that percentage of its size; see `Trimmed`.
     pub fn remove_background(
         &self,
         input_path: &Path,
         output_path: &Path,
-    ) -> Result<(), ProcessingError> {
-        let img = image::open(input_path)?;
+        trim: Option<f32>,
+        on_progress: OnProgress,
+    ) -> Result<Trimmed, ProcessingError> {
+        let img = open_upright(input_path, self.max_pixels)?.image;
+        on_progress(DECODED);
+        let result = self.cut_out(&img, on_progress)?;
+        let trimmed = Trimmed::plan(&result, trim);
 
-        // For MVP: Use simple threshold-based background removal
-        // In production, replace with actual ONNX model inference
-        let result = self.simple_bg_removal(&img)?;
-
-        result.save(output_path)?;
+        save_cut_out(trimmed.apply(result), output_path)?;
+        on_progress(100);
         tracing::info!("Background removed: {} -> {}", input_path.display(), output_path.display());
 
-        Ok(())
+        Ok(trimmed)
     }
 
-    /// For MVP: If input is a video, extract the first frame and run background
-    /// removal on that frame producing a single-image result. This is a
-    /// lightweight placeholder for full frame-by-frame processing.
-    pub fn remove_background_from_video(&self, input_path: &Path, output_path: &Path) -> Result<(), ProcessingError> {
-        // Create a temp file for extracted frame
-        let frame_path = std::env::temp_dir().join(format!("frame_{}.png", uuid::Uuid::new_v4()));
-
-        // Call ffmpeg to extract the first frame
-        let status = std::process::Command::new("ffmpeg")
-            .arg("-y")
-            .arg("-i")
-            .arg(input_path.as_os_str())
-            .arg("-frames:v")
-            .arg("1")
-            .arg(frame_path.as_os_str())
-            .status();
-
-        match status {
-            Ok(s) if s.success() => {
-                // Run image background removal on the extracted frame
-                let res = self.remove_background(&frame_path, output_path);
-                // cleanup
-                let _ = std::fs::remove_file(&frame_path);
-                res
-            }
-            Ok(s) => Err(ProcessingError::IoError(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("ffmpeg failed with code: {}", s),
-            ))),
-            Err(e) => Err(ProcessingError::IoError(e)),
+    /// The image with its background made transparent, by the model when it
+    /// is available. Progress ends at `PROCESSED`.
+    fn cut_out(&self, img: &DynamicImage, on_progress: OnProgress) -> Result<RgbaImage, ProcessingError> {
+        if self.model_available() {
+            let cut_out = self.model_bg_removal(img)?;
+            on_progress(PROCESSED);
+            Ok(cut_out)
+        } else {
+            self.simple_bg_removal(img, on_progress)
         }
     }
 
-    /// Simple background removal using color threshold (MVP fallback)
-    fn simple_bg_removal(&self, img: &DynamicImage) -> Result<RgbaImage, ProcessingError> {
-        let (width, height) = img.dimensions();
-        let rgba = img.to_rgba8();
-        let mut result = RgbaImage::new(width, height);
+    /// U²-Net background removal: the predicted mask becomes the alpha channel
+    #[cfg(feature = "onnx")]
+    fn model_bg_removal(&self, img: &DynamicImage) -> Result<RgbaImage, ProcessingError> {
+        let session = self.session.get_or_init(|| {
+            tracing::info!("Loading U²-Net model from {}", self.model_path);
+            super::u2net::load(&self.model_path).map_err(|e| e.to_string())
+        });
 
-        // Sample corners to determine background color
-        let bg_color = self.estimate_background_color(&rgba);
-
-        for (x, y, pixel) in rgba.enumerate_pixels() {
-            let diff = self.color_distance(pixel, &bg_color);
-            
-            // If pixel is similar to background, make it transparent
-            let alpha = if diff < 50.0 {
-                0
-            } else {
-                255
-            };
+        // Load failures are cached so a broken model is not re-read for every job
+        let session = session
+            .as_ref()
+            .map_err(|e| ProcessingError::ModelLoadFailed(e.clone()))?;
+        let mask = super::u2net::predict_mask(session, img)?;
 
-            result.put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], alpha]));
+        let mut rgba = img.to_rgba8();
+        for (pixel, m) in rgba.pixels_mut().zip(mask.pixels()) {
+            // Keep existing transparency in the source
+            pixel[3] = pixel[3].min(m[0]);
         }
+        Ok(rgba)
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    fn model_bg_removal(&self, _img: &DynamicImage) -> Result<RgbaImage, ProcessingError> {
+        Err(ProcessingError::ModelLoadFailed(
+            "built without the `onnx` feature".to_string(),
+        ))
+    }
+
+    /// Simple background removal using color threshold (fallback when no model is available)
+    fn simple_bg_removal(&self, img: &DynamicImage, on_progress: OnProgress) -> Result<RgbaImage, ProcessingError> {
+        let mut result = img.to_rgba8();
+
+        // Sample corners to determine background color
+        let bg_color = self.estimate_background_color(&result);
+
+        let progress = StageProgress::rows(on_progress, &result);
+        par_rows(&mut result, Some(&progress), |row| {
+            for pixel in row.chunks_exact_mut(4) {
+                let diff = self.color_distance(Rgba::from_slice(pixel), &bg_color);
+
+                // If pixel is similar to background, make it transparent
+                pixel[3] = if diff < 50.0 {
+                    0
+                } else {
+                    255
+                };
+            }
+        });
+        progress.finish();
 
         Ok(result)
     }
@@ -110,7 +462,7 @@ impl ImageProcessor {
         let (width, height) = img.dimensions();
         
         // Sample corners
-        let corners = vec![
+        let corners = [
             img.get_pixel(0, 0),
             img.get_pixel(width - 1, 0),
             img.get_pixel(0, height - 1),
@@ -132,19 +484,20 @@ impl ImageProcessor {
         (r_diff + g_diff + b_diff).sqrt()
     }
 
-    /// Replace background with solid color
+    /// Replace background with solid color. The cut-out stays in memory, so
+    /// concurrent calls never share a file.
     pub fn replace_background(
         &self,
         input_path: &Path,
         output_path: &Path,
         bg_color: [u8; 3],
-    ) -> Result<(), ProcessingError> {
+        trim: Option<f32>,
+        on_progress: OnProgress,
+    ) -> Result<Trimmed, ProcessingError> {
         // First remove background
-        let temp_path = std::env::temp_dir().join("temp_removed.png");
-        self.remove_background(input_path, &temp_path)?;
-
-        // Load transparent image
-        let transparent = image::open(&temp_path)?.to_rgba8();
+        let img = open_upright(input_path, self.max_pixels)?.image;
+        on_progress(DECODED);
+        let transparent = self.cut_out(&img, on_progress)?;
 
         // Create colored background
         let (width, height) = transparent.dimensions();
@@ -155,46 +508,138 @@ impl ImageProcessor {
             *pixel = Rgba([bg_color[0], bg_color[1], bg_color[2], 255]);
         }
 
-        // Composite foreground over background
-        for (x, y, pixel) in transparent.enumerate_pixels() {
-            let alpha = pixel[3] as f32 / 255.0;
-            let bg_pixel = result.get_pixel_mut(x, y);
+        composite_over(&transparent, &mut result);
+
+        let trimmed = Trimmed::plan(&transparent, trim);
+        save_cut_out(trimmed.apply(result), output_path)?;
+        on_progress(100);
+
+        Ok(trimmed)
+    }
 
-            bg_pixel[0] = ((pixel[0] as f32 * alpha) + (bg_pixel[0] as f32 * (1.0 - alpha))) as u8;
-            bg_pixel[1] = ((pixel[1] as f32 * alpha) + (bg_pixel[1] as f32 * (1.0 - alpha))) as u8;
-            bg_pixel[2] = ((pixel[2] as f32 * alpha) + (bg_pixel[2] as f32 * (1.0 - alpha))) as u8;
+    /// Replace the background with another image, scaled to cover the
+    /// foreground's dimensions and center-cropped where the aspect ratios differ
+    pub fn replace_background_with_image(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        background_path: &Path,
+        trim: Option<f32>,
+        on_progress: OnProgress,
+    ) -> Result<Trimmed, ProcessingError> {
+        let img = open_upright(input_path, self.max_pixels)?.image;
+        on_progress(DECODED);
+        let transparent = self.cut_out(&img, on_progress)?;
+
+        let (width, height) = transparent.dimensions();
+        let background = open_upright(background_path, self.max_pixels)?.image;
+        let mut result = background
+            .resize_to_fill(width, height, image::imageops::FilterType::Lanczos3)
+            .to_rgba8();
+        for pixel in result.pixels_mut() {
+            pixel[3] = 255;
         }
+        composite_over(&transparent, &mut result);
 
-        result.save(output_path)?;
+        let trimmed = Trimmed::plan(&transparent, trim);
+        save_cut_out(trimmed.apply(result), output_path)?;
+        on_progress(100);
 
-        // Cleanup
-        std::fs::remove_file(&temp_path).ok();
+        Ok(trimmed)
+    }
 
-        Ok(())
+    /// Keep the subject sharp over a Gaussian-blurred copy of the image. The
+    /// mask's edge is feathered by `FEATHER_SIGMA` so the subject blends in
+    /// rather than looking pasted on.
+    pub fn blur_background(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        sigma: f32,
+        trim: Option<f32>,
+        on_progress: OnProgress,
+    ) -> Result<Trimmed, ProcessingError> {
+        let img = open_upright(input_path, self.max_pixels)?.image;
+        on_progress(DECODED);
+        let cut_out = self.cut_out(&img, on_progress)?;
+        let trimmed = Trimmed::plan(&cut_out, trim);
+
+        let mask = GrayImage::from_fn(cut_out.width(), cut_out.height(), |x, y| Luma([cut_out.get_pixel(x, y)[3]]));
+        let mask = image::imageops::blur(&mask, FEATHER_SIGMA);
+        let background = image::imageops::fast_blur(&img.to_rgb8(), sigma);
+
+        let mut result = cut_out;
+        for ((pixel, bg_pixel), m) in result.pixels_mut().zip(background.pixels()).zip(mask.pixels()) {
+            let alpha = m[0] as f32 / 255.0;
+            for c in 0..3 {
+                pixel[c] = ((pixel[c] as f32 * alpha) + (bg_pixel[c] as f32 * (1.0 - alpha))).round() as u8;
+            }
+            pixel[3] = 255;
+        }
+
+        save_cut_out(trimmed.apply(result), output_path)?;
+        on_progress(100);
+
+        Ok(trimmed)
     }
 
-    /// Convert image format
+    /// Convert image format, optionally resizing and applying a LUT on the way.
+    /// Exif data is only written when `encoding.keep_exif` is set. Animated
+    /// GIFs stay animated when converted to GIF or WebP, ignoring any size
+    /// target; other formats get the first frame.
+    #[allow(clippy::too_many_arguments)]
     pub fn convert_format(
         &self,
         input_path: &Path,
         output_path: &Path,
+        encoding: OutputEncoding,
         width: Option<u32>,
         height: Option<u32>,
-    ) -> Result<(), ProcessingError> {
-        let mut img = image::open(input_path)?;
+        lut_path: Option<&Path>,
+        on_progress: OnProgress,
+    ) -> Result<Converted, ProcessingError> {
+        // Load the LUT first so a bad LUT fails before any decoding work
+        let lut = lut_path.map(Lut::from_file).transpose()?;
+        let transform = |mut img: DynamicImage| {
+            // Resize if dimensions provided
+            if let (Some(w), Some(h)) = (width, height) {
+                img = img.resize_exact(w, h, image::imageops::FilterType::Lanczos3);
+            }
+            match &lut {
+                Some(lut) => DynamicImage::ImageRgba8(lut.apply_to_image(&img)),
+                None => img,
+            }
+        };
 
-        // Resize if dimensions provided
-        if let (Some(w), Some(h)) = (width, height) {
-            img = img.resize_exact(w, h, image::imageops::FilterType::Lanczos3);
+        let mut converted = Converted::default();
+        match Animation::open(input_path, self.max_pixels)? {
+            Some(animation) if animation::is_animated_format(encoding.format) => {
+                animation.encode(output_path, encoding, |frame| {
+                    transform(DynamicImage::ImageRgba8(frame)).into_rgba8()
+                })?;
+            }
+            Some(animation) => {
+                converted = save_image(transform(animation.into_first_frame()), output_path, encoding, None)?;
+                converted.frames_dropped = true;
+            }
+            None => {
+                let Decoded { image, exif } = open_upright(input_path, self.max_pixels)?;
+                on_progress(DECODED);
+                let exif = exif.filter(|_| encoding.keep_exif);
+                let image = transform(image);
+                on_progress(PROCESSED);
+                converted = save_image(image, output_path, encoding, exif)?;
+            }
         }
-
-        img.save(output_path)?;
+        on_progress(100);
         tracing::info!("Image converted: {} -> {}", input_path.display(), output_path.display());
 
-        Ok(())
+        Ok(converted)
     }
 
-    /// Apply color grading
+    /// Apply color grading. An animated GIF is graded frame by frame when
+    /// `output_path` is a GIF or WebP.
+    #[allow(clippy::too_many_arguments)]
     pub fn color_grade(
         &self,
         input_path: &Path,
@@ -203,74 +648,28 @@ impl ImageProcessor {
         saturation: Option<i32>,
         brightness: Option<i32>,
         contrast: Option<i32>,
+        on_progress: OnProgress,
     ) -> Result<(), ProcessingError> {
-        let img = image::open(input_path)?;
-        let mut rgba = img.to_rgba8();
+        let adjustments = Adjustments::new(hue, saturation, brightness, contrast);
+        let grade = |mut rgba: RgbaImage| {
+            adjustments.apply(&mut rgba, None);
+            rgba
+        };
 
-        // Apply adjustments
-        if let Some(b) = brightness {
-            self.adjust_brightness(&mut rgba, b);
-        }
-        if let Some(c) = contrast {
-            self.adjust_contrast(&mut rgba, c);
-        }
-        if let Some(s) = saturation {
-            self.adjust_saturation(&mut rgba, s);
-        }
-        if let Some(h) = hue {
-            self.adjust_hue(&mut rgba, h);
+        if !encode_animated(input_path, output_path, self.max_pixels, grade)? {
+            let mut img = open_upright(input_path, self.max_pixels)?.image.to_rgba8();
+            on_progress(DECODED);
+            let progress = StageProgress::rows(on_progress, &img);
+            adjustments.apply(&mut img, Some(&progress));
+            progress.finish();
+            img.save(output_path)?;
         }
-
-        rgba.save(output_path)?;
+        on_progress(100);
         tracing::info!("Color grading applied: {} -> {}", input_path.display(), output_path.display());
 
         Ok(())
     }
 
-    fn adjust_brightness(&self, img: &mut RgbaImage, amount: i32) {
-        for pixel in img.pixels_mut() {
-            pixel[0] = (pixel[0] as i32 + amount).clamp(0, 255) as u8;
-            pixel[1] = (pixel[1] as i32 + amount).clamp(0, 255) as u8;
-            pixel[2] = (pixel[2] as i32 + amount).clamp(0, 255) as u8;
-        }
-    }
-
-    fn adjust_contrast(&self, img: &mut RgbaImage, amount: i32) {
-        let factor = (259.0 * (amount as f32 + 255.0)) / (255.0 * (259.0 - amount as f32));
-        
-        for pixel in img.pixels_mut() {
-            pixel[0] = (factor * (pixel[0] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
-            pixel[1] = (factor * (pixel[1] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
-            pixel[2] = (factor * (pixel[2] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
-        }
-    }
-
-    fn adjust_saturation(&self, img: &mut RgbaImage, amount: i32) {
-        let factor = (amount as f32 + 100.0) / 100.0;
-        
-        for pixel in img.pixels_mut() {
-            let gray = (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) as u8;
-            
-            pixel[0] = (gray as f32 + factor * (pixel[0] as f32 - gray as f32)).clamp(0.0, 255.0) as u8;
-            pixel[1] = (gray as f32 + factor * (pixel[1] as f32 - gray as f32)).clamp(0.0, 255.0) as u8;
-            pixel[2] = (gray as f32 + factor * (pixel[2] as f32 - gray as f32)).clamp(0.0, 255.0) as u8;
-        }
-    }
-
-    fn adjust_hue(&self, img: &mut RgbaImage, amount: i32) {
-        let hue_shift = amount as f32 / 360.0;
-        
-        for pixel in img.pixels_mut() {
-            let (h, s, v) = Self::rgb_to_hsv(pixel[0], pixel[1], pixel[2]);
-            let new_h = (h + hue_shift) % 1.0;
-            let (r, g, b) = Self::hsv_to_rgb(new_h, s, v);
-            
-            pixel[0] = r;
-            pixel[1] = g;
-            pixel[2] = b;
-        }
-    }
-
     fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
         let r = r as f32 / 255.0;
         let g = g as f32 / 255.0;
@@ -318,59 +717,568 @@ impl ImageProcessor {
     }
 
     /// Apply preset color grade
-    pub fn apply_preset(&self, input_path: &Path, output_path: &Path, preset: &str) -> Result<(), ProcessingError> {
+    pub fn apply_preset(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        preset: &str,
+        on_progress: OnProgress,
+    ) -> Result<(), ProcessingError> {
+        let grade = |hue, saturation, brightness, contrast| {
+            self.color_grade(input_path, output_path, hue, saturation, brightness, contrast, on_progress)
+        };
         match preset {
-            "vintage" => self.color_grade(input_path, output_path, Some(15), Some(-20), Some(-10), Some(10)),
-            "cinematic" => self.color_grade(input_path, output_path, Some(-5), Some(10), Some(-15), Some(20)),
-            "bright" => self.color_grade(input_path, output_path, Some(0), Some(15), Some(30), Some(5)),
+            "vintage" => grade(Some(15), Some(-20), Some(-10), Some(10)),
+            "cinematic" => grade(Some(-5), Some(10), Some(-15), Some(20)),
+            "bright" => grade(Some(0), Some(15), Some(30), Some(5)),
             _ => Err(ProcessingError::InferenceFailed(format!("Unknown preset: {}", preset))),
         }
     }
 
-    /// Apply a .cube LUT to the image. MVP behavior: verify LUT exists and copy input to output (pass-through).
-    pub fn apply_lut(&self, input_path: &Path, output_path: &Path, lut_location: &str) -> Result<(), ProcessingError> {
-        // Load LUT using the new Lut3D module
-        let lut_path = Path::new(lut_location);
-        if !lut_path.exists() {
-            return Err(ProcessingError::IoError(std::io::Error::new(std::io::ErrorKind::NotFound, "LUT file not found")));
+    /// Apply a LUT (.cube 1D/3D or .3dl) to the image. `lut_path` must be a local
+    /// file; callers resolve storage locations first. The output format follows
+    /// the extension of `output_path`, and animated GIFs stay animated as with
+    /// `color_grade`.
+    pub fn apply_lut(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        lut_path: &Path,
+        on_progress: OnProgress,
+    ) -> Result<(), ProcessingError> {
+        let lut = Lut::from_file(lut_path)?;
+        let apply = |frame: RgbaImage| lut.apply_to_image(&DynamicImage::ImageRgba8(frame));
+        if !encode_animated(input_path, output_path, self.max_pixels, apply)? {
+            let mut img = open_upright(input_path, self.max_pixels)?.image.to_rgba8();
+            on_progress(DECODED);
+            let progress = StageProgress::rows(on_progress, &img);
+            par_rows(&mut img, Some(&progress), |row| lut.apply_to_row(row));
+            progress.finish();
+            let encoding = OutputEncoding::new(ImageFormat::from_path(output_path)?);
+            save_image(DynamicImage::ImageRgba8(img), output_path, encoding, None)?;
+        }
+        on_progress(100);
+        tracing::info!("Applied LUT {} to {} -> {}", lut_path.display(), input_path.display(), output_path.display());
+        Ok(())
+    }
+
+    /// Fit a LUT reproducing the grade between two renders of the same shot
+    pub fn generate_lut(&self, source_path: &Path, graded_path: &Path) -> Result<Lut3D, ProcessingError> {
+        let source = open_upright(source_path, self.max_pixels)?.image.to_rgb8();
+        let graded = open_upright(graded_path, self.max_pixels)?.image.to_rgb8();
+        Ok(Lut3D::fit(&source, &graded, lut::FITTED_3D_SIZE)?)
+    }
+}
+
+/// Brightness, contrast, saturation and hue adjustments, in that order,
+/// applied a row at a time so the image is walked once. Brightness and
+/// contrast are pointwise per channel, so together they are one 256-entry
+/// table.
+struct Adjustments {
+    levels: Option<[u8; 256]>,
+    /// Saturation factor, 1.0 is unchanged
+    saturation: Option<f32>,
+    /// Hue shift as a fraction of a turn
+    hue: Option<f32>,
+}
+
+impl Adjustments {
+    fn new(hue: Option<i32>, saturation: Option<i32>, brightness: Option<i32>, contrast: Option<i32>) -> Self {
+        let levels = (brightness.is_some() || contrast.is_some()).then(|| {
+            let factor = contrast.map(|c| (259.0 * (c as f32 + 255.0)) / (255.0 * (259.0 - c as f32)));
+            std::array::from_fn(|v| {
+                let mut v = v as u8;
+                if let Some(amount) = brightness {
+                    v = (v as i32 + amount).clamp(0, 255) as u8;
+                }
+                if let Some(factor) = factor {
+                    v = (factor * (v as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
+                }
+                v
+            })
+        });
+
+        Self {
+            levels,
+            saturation: saturation.map(|s| (s as f32 + 100.0) / 100.0),
+            hue: hue.map(|h| h as f32 / 360.0),
+        }
+    }
+
+    fn apply(&self, img: &mut RgbaImage, progress: Option<&StageProgress>) {
+        if self.levels.is_none() && self.saturation.is_none() && self.hue.is_none() {
+            return;
+        }
+        par_rows(img, progress, |row| self.apply_row(row));
+    }
+
+    /// Each adjustment runs over the whole row before the next, while the row
+    /// is in cache, so the simple loops stay tight enough to vectorize
+    fn apply_row(&self, row: &mut [u8]) {
+        if let Some(levels) = &self.levels {
+            for pixel in row.chunks_exact_mut(4) {
+                for channel in &mut pixel[..3] {
+                    *channel = levels[*channel as usize];
+                }
+            }
         }
 
-        match crate::services::lut::Lut3D::from_cube(lut_path) {
-            Ok(lut) => {
-                let img = image::open(input_path)?;
-                let out_img = lut.apply_to_image(&img);
-                out_img.save(output_path)?;
-                tracing::info!("Applied LUT {} to {} -> {}", lut_location, input_path.display(), output_path.display());
-                Ok(())
+        if let Some(factor) = self.saturation {
+            for pixel in row.chunks_exact_mut(4) {
+                let gray = (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) as u8;
+                for channel in &mut pixel[..3] {
+                    *channel = (gray as f32 + factor * (*channel as f32 - gray as f32)).clamp(0.0, 255.0) as u8;
+                }
             }
-            Err(e) => Err(ProcessingError::InferenceFailed(format!("Failed to load LUT: {}", e))),
+        }
+
+        if let Some(hue_shift) = self.hue {
+            for pixel in row.chunks_exact_mut(4) {
+                let (h, s, v) = ImageProcessor::rgb_to_hsv(pixel[0], pixel[1], pixel[2]);
+                let (r, g, b) = ImageProcessor::hsv_to_rgb((h + hue_shift) % 1.0, s, v);
+                pixel[0] = r;
+                pixel[1] = g;
+                pixel[2] = b;
+            }
+        }
+    }
+}
+
+/// Pass each frame of an animated GIF through `map` into an animated output,
+/// in the format `output_path`'s extension names. `false`, having written
+/// nothing, unless both the input and the output animate.
+fn encode_animated(
+    input_path: &Path,
+    output_path: &Path,
+    max_pixels: u64,
+    map: impl FnMut(RgbaImage) -> RgbaImage,
+) -> Result<bool, ProcessingError> {
+    let format = ImageFormat::from_path(output_path)?;
+    if !animation::is_animated_format(format) {
+        return Ok(false);
+    }
+    match Animation::open(input_path, max_pixels)? {
+        Some(animation) => {
+            animation.encode(output_path, OutputEncoding::new(format), map)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Save with an explicit encoder, dropping alpha for formats that cannot store
+/// it. `exif` is written by the formats that can hold it. With a size target,
+/// lossy formats search for the quality that meets it.
+fn save_image(
+    img: DynamicImage,
+    output_path: &Path,
+    encoding: OutputEncoding,
+    exif: Option<Vec<u8>>,
+) -> Result<Converted, ProcessingError> {
+    if encoding.format == ImageFormat::Avif && img.width().max(img.height()) > AVIF_MAX_EDGE {
+        return Err(ProcessingError::Unsupported(format!(
+            "AVIF output of {}x{} images; AVIF is limited to {} px per side",
+            img.width(),
+            img.height(),
+            AVIF_MAX_EDGE
+        )));
+    }
+
+    let mut converted = Converted::default();
+    let encoded = match encoding.target_bytes.filter(|_| is_lossy(encoding.format)) {
+        Some(target_bytes) => {
+            let sized = encode_to_size(&img, encoding.format, target_bytes, exif.as_deref())?;
+            converted.quality = Some(sized.quality);
+            converted.target_size_missed = sized.missed;
+            sized.bytes
+        }
+        None => encode_image(&img, encoding.format, encoding.quality, exif.as_deref())?,
+    };
+    std::fs::write(output_path, encoded)?;
+    Ok(converted)
+}
+
+/// Formats whose size follows their quality setting
+fn is_lossy(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Avif)
+}
+
+/// Encode `img` in memory. A WebP with a quality is lossy, without one lossless.
+fn encode_image(
+    img: &DynamicImage,
+    format: ImageFormat,
+    quality: Option<u8>,
+    exif: Option<&[u8]>,
+) -> Result<Vec<u8>, ProcessingError> {
+    let mut out = Vec::new();
+    match (format, quality) {
+        (ImageFormat::Jpeg, quality) => {
+            let quality = quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality);
+            set_exif(&mut encoder, exif);
+            // The encoder drops alpha itself
+            img.write_with_encoder(encoder)?;
+        }
+        (ImageFormat::Png, _) => {
+            let mut encoder = image::codecs::png::PngEncoder::new(&mut out);
+            set_exif(&mut encoder, exif);
+            img.write_with_encoder(encoder)?;
+        }
+        (ImageFormat::WebP, None) => {
+            let mut encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut out);
+            set_exif(&mut encoder, exif);
+            img.write_with_encoder(encoder)?;
+        }
+        (ImageFormat::WebP, Some(quality)) => {
+            // image only writes lossless WebP; libwebp handles the lossy case,
+            // though without metadata
+            let rgba = img.to_rgba8();
+            let (width, height) = rgba.dimensions();
+            let encoded = webp::Encoder::from_rgba(rgba.as_raw(), width, height)
+                .encode(quality.clamp(1, 100) as f32);
+            out.extend_from_slice(&encoded);
+        }
+        #[cfg(feature = "avif")]
+        (ImageFormat::Avif, quality) => {
+            let quality = quality.unwrap_or(DEFAULT_AVIF_QUALITY).clamp(1, 100);
+            let encoder = image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut out, AVIF_SPEED, quality);
+            // Fully opaque images are encoded without an alpha plane
+            DynamicImage::ImageRgba8(img.to_rgba8()).write_with_encoder(encoder)?;
+        }
+        #[cfg(not(feature = "avif"))]
+        (ImageFormat::Avif, _) => {
+            return Err(ProcessingError::Unsupported("AVIF output (built without the avif feature)".to_string()));
+        }
+        (format, _) => img.write_to(&mut std::io::Cursor::new(&mut out), format)?,
+    }
+    Ok(out)
+}
+
+/// A lossy encoding chosen for its size, see `encode_to_size`
+struct SizedEncoding {
+    bytes: Vec<u8>,
+    quality: u8,
+    /// Even the lowest quality tried came out larger than the target
+    missed: bool,
+}
+
+/// Encode at the highest quality whose output fits in `target_bytes`,
+/// binary-searching `TARGET_QUALITIES` in at most `TARGET_SEARCH_STEPS`
+/// encodes. When nothing fits, the smallest output, at the lowest quality,
+/// is kept.
+fn encode_to_size(
+    img: &DynamicImage,
+    format: ImageFormat,
+    target_bytes: u64,
+    exif: Option<&[u8]>,
+) -> Result<SizedEncoding, ProcessingError> {
+    let (mut low, mut high) = (*TARGET_QUALITIES.start(), *TARGET_QUALITIES.end());
+    let mut fitting: Option<SizedEncoding> = None;
+    let mut smallest: Option<SizedEncoding> = None;
+    for _ in 0..TARGET_SEARCH_STEPS {
+        if low > high {
+            break;
+        }
+        let quality = low + (high - low) / 2;
+        let bytes = encode_image(img, format, Some(quality), exif)?;
+        if bytes.len() as u64 <= target_bytes {
+            fitting = Some(SizedEncoding { bytes, quality, missed: false });
+            low = quality + 1;
+        } else {
+            // Qualities tried only go down while nothing fits
+            smallest = Some(SizedEncoding { bytes, quality, missed: true });
+            high = quality.saturating_sub(1);
+        }
+    }
+
+    if let Some(fitting) = fitting {
+        return Ok(fitting);
+    }
+    let lowest = *TARGET_QUALITIES.start();
+    match smallest {
+        Some(smallest) if smallest.quality == lowest => Ok(smallest),
+        _ => {
+            let bytes = encode_image(img, format, Some(lowest), exif)?;
+            let missed = bytes.len() as u64 > target_bytes;
+            Ok(SizedEncoding { bytes, quality: lowest, missed })
         }
     }
 }
 
+/// Save a background removal result in the format its extension names.
+/// WebP is lossy with a lossless alpha plane, which keeps the cut-out's edge
+/// clean at a fraction of PNG's size; JPEG drops the alpha.
+fn save_cut_out(img: RgbaImage, output_path: &Path) -> Result<(), ProcessingError> {
+    let format = ImageFormat::from_path(output_path)?;
+    let quality = (format == ImageFormat::WebP).then_some(CUT_OUT_WEBP_QUALITY);
+    let encoding = OutputEncoding { quality, ..OutputEncoding::new(format) };
+    save_image(DynamicImage::ImageRgba8(img), output_path, encoding, None)?;
+    Ok(())
+}
+
+fn set_exif(encoder: &mut impl ImageEncoder, exif: Option<&[u8]>) {
+    if let Some(exif) = exif {
+        // Only called for encoders that support it
+        let _ = encoder.set_exif_metadata(exif.to_vec());
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::GenericImageView;
+
+    const MAX_PIXELS: u64 = 100_000_000;
 
     #[test]
     fn test_processor_creation() {
-        let processor = ImageProcessor::new("./models/u2net.onnx".to_string());
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS);
         assert!(processor.is_ok());
     }
 
     #[test]
     fn test_color_distance() {
-        let processor = ImageProcessor::new("./models/u2net.onnx".to_string()).unwrap();
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
         let black = Rgba([0, 0, 0, 255]);
         let white = Rgba([255, 255, 255, 255]);
         let distance = processor.color_distance(&black, &white);
         assert!(distance > 400.0);
     }
 
+    #[test]
+    fn test_remove_background_falls_back_without_model() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        assert!(!processor.model_available());
+
+        // White canvas with a red square in the middle
+        let mut img = RgbaImage::from_pixel(8, 8, Rgba([255, 255, 255, 255]));
+        for x in 2..6 {
+            for y in 2..6 {
+                img.put_pixel(x, y, Rgba([200, 0, 0, 255]));
+            }
+        }
+        let id = uuid::Uuid::new_v4();
+        let input_path = std::env::temp_dir().join(format!("bg_in_{}.png", id));
+        let output_path = std::env::temp_dir().join(format!("bg_out_{}.png", id));
+        img.save(&input_path).unwrap();
+
+        processor.remove_background(&input_path, &output_path, None, &|_| {}).unwrap();
+        let out = image::open(&output_path).unwrap().to_rgba8();
+        assert_eq!(out.get_pixel(0, 0)[3], 0);
+        assert_eq!(out.get_pixel(4, 4)[3], 255);
+
+        let _ = std::fs::remove_file(input_path);
+        let _ = std::fs::remove_file(output_path);
+    }
+
+    #[test]
+    fn test_webp_cut_out_is_much_smaller_than_png() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        let dir = std::env::temp_dir().join(format!("bg_webp_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.png");
+
+        // Photo-like subject on a white backdrop: shading plus sensor noise,
+        // which PNG compresses poorly
+        let mut seed = 0x2545_f491_u32;
+        let mut noise = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (seed % 24) as u8
+        };
+        RgbaImage::from_fn(256, 256, |x, y| {
+            let (dx, dy) = (x as i32 - 128, y as i32 - 128);
+            if dx * dx + dy * dy > 100 * 100 {
+                return Rgba([255, 255, 255, 255]);
+            }
+            let shade = 120 - (dx + dy) / 4;
+            Rgba([(shade + 40) as u8 + noise(), shade as u8 + noise(), (shade - 40) as u8 + noise(), 255])
+        })
+        .save(&input)
+        .unwrap();
+
+        let size_as = |ext: &str| {
+            let output = dir.join(format!("out.{}", ext));
+            processor.remove_background(&input, &output, None, &|_| {}).unwrap();
+            std::fs::metadata(&output).unwrap().len()
+        };
+        let (png, webp) = (size_as("png"), size_as("webp"));
+        assert!(webp * 3 < png, "webp {} bytes vs png {} bytes", webp, png);
+
+        let out = image::open(dir.join("out.webp")).unwrap().to_rgba8();
+        assert_eq!(out.get_pixel(0, 0)[3], 0);
+        assert_eq!(out.get_pixel(128, 128)[3], 255);
+
+        let jpg = dir.join("out.jpg");
+        processor.replace_background(&input, &jpg, [0, 0, 0], None, &|_| {}).unwrap();
+        assert_eq!(image::open(&jpg).unwrap().color(), image::ColorType::Rgb8);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_subject_bounds() {
+        let mut cut_out = RgbaImage::new(100, 50);
+        for x in 20..40 {
+            for y in 10..20 {
+                cut_out.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+        // Faint pixels don't count
+        cut_out.put_pixel(90, 45, Rgba([0, 0, 0, TRIM_ALPHA_THRESHOLD]));
+
+        assert_eq!(subject_bounds(&cut_out, 0.0), Some(Bounds { x: 20, y: 10, width: 20, height: 10 }));
+        // 10% of 20×10 is 2 and 1 pixels a side
+        assert_eq!(subject_bounds(&cut_out, 10.0), Some(Bounds { x: 18, y: 9, width: 24, height: 12 }));
+        // Padding stops at the edges
+        assert_eq!(subject_bounds(&cut_out, 200.0), Some(Bounds { x: 0, y: 0, width: 80, height: 40 }));
+        assert_eq!(subject_bounds(&RgbaImage::new(10, 10), 10.0), None);
+    }
+
+    #[test]
+    fn test_trimmed_removal_crops_to_the_subject() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        let dir = std::env::temp_dir().join(format!("bg_trim_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.png"), dir.join("out.png"));
+
+        // White canvas with a red square off to one side
+        let mut img = RgbaImage::from_pixel(40, 30, Rgba([255, 255, 255, 255]));
+        for x in 4..14 {
+            for y in 6..16 {
+                img.put_pixel(x, y, Rgba([200, 0, 0, 255]));
+            }
+        }
+        img.save(&input).unwrap();
+        let trimmed = processor.remove_background(&input, &output, Some(0.0), &|_| {}).unwrap();
+        assert_eq!(trimmed, Trimmed::To(Bounds { x: 4, y: 6, width: 10, height: 10 }));
+        assert_eq!(image::image_dimensions(&output).unwrap(), (10, 10));
+
+        // A plain canvas has no subject and keeps its size
+        RgbaImage::from_pixel(40, 30, Rgba([255, 255, 255, 255])).save(&input).unwrap();
+        let trimmed = processor.replace_background(&input, &output, [0, 0, 0], Some(5.0), &|_| {}).unwrap();
+        assert_eq!(trimmed, Trimmed::Empty);
+        assert_eq!(image::image_dimensions(&output).unwrap(), (40, 30));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_concurrent_background_replacements_keep_their_own_output() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        let dir = std::env::temp_dir().join(format!("bg_replace_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // White canvases with a differently coloured square in the middle
+        let cases = [([200, 0, 0], [0, 255, 0]), ([0, 0, 200], [255, 0, 255])];
+        let outputs: Vec<_> = cases
+            .iter()
+            .enumerate()
+            .map(|(i, (square, _))| {
+                let mut img = RgbaImage::from_pixel(32, 32, Rgba([255, 255, 255, 255]));
+                for x in 8..24 {
+                    for y in 8..24 {
+                        img.put_pixel(x, y, Rgba([square[0], square[1], square[2], 255]));
+                    }
+                }
+                let input = dir.join(format!("in_{}.png", i));
+                img.save(&input).unwrap();
+                (input, dir.join(format!("out_{}.png", i)))
+            })
+            .collect();
+
+        std::thread::scope(|scope| {
+            for ((input, output), (_, background)) in outputs.iter().zip(cases) {
+                let processor = &processor;
+                scope.spawn(move || processor.replace_background(input, output, background, None, &|_| {}).unwrap());
+            }
+        });
+
+        for ((_, output), (square, background)) in outputs.iter().zip(cases) {
+            let out = image::open(output).unwrap().to_rgb8();
+            assert_eq!(out.get_pixel(0, 0).0, background);
+            assert_eq!(out.get_pixel(16, 16).0, square);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_replace_background_with_image_covers_and_center_crops() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        let dir = std::env::temp_dir().join(format!("bg_image_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Wide white canvas with a red square in the middle
+        let mut img = RgbaImage::from_pixel(32, 16, Rgba([255, 255, 255, 255]));
+        for x in 12..20 {
+            for y in 4..12 {
+                img.put_pixel(x, y, Rgba([200, 0, 0, 255]));
+            }
+        }
+        // Tall background: black top, green middle, blue bottom. Covering
+        // the wide canvas keeps only the middle band.
+        let background = RgbaImage::from_fn(8, 16, |_, y| match y {
+            0..4 => Rgba([0, 0, 0, 255]),
+            4..12 => Rgba([0, 200, 0, 255]),
+            _ => Rgba([0, 0, 200, 255]),
+        });
+        let (input, background_path, output) = (dir.join("in.png"), dir.join("bg.png"), dir.join("out.png"));
+        img.save(&input).unwrap();
+        background.save(&background_path).unwrap();
+
+        processor.replace_background_with_image(&input, &output, &background_path, None, &|_| {}).unwrap();
+        let out = image::open(&output).unwrap().to_rgba8();
+        assert_eq!(out.dimensions(), (32, 16));
+        for (x, y) in [(0, 0), (31, 0), (0, 15), (31, 15)] {
+            let [r, g, b, a] = out.get_pixel(x, y).0;
+            assert!(r < 10 && g > 190 && b < 10 && a == 255, "({}, {}): {:?}", x, y, [r, g, b, a]);
+        }
+        assert_eq!(out.get_pixel(16, 8).0, [200, 0, 0, 255]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_blur_background_keeps_subject_sharp() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+
+        // A fine checkerboard of near-whites with a blue square in the middle
+        let mut img = RgbaImage::from_fn(32, 32, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 230 };
+            Rgba([v, v, v, 255])
+        });
+        for x in 8..24 {
+            for y in 8..24 {
+                img.put_pixel(x, y, Rgba([0, 0, 200, 255]));
+            }
+        }
+        let id = uuid::Uuid::new_v4();
+        let input_path = std::env::temp_dir().join(format!("bg_blur_in_{}.png", id));
+        let output_path = std::env::temp_dir().join(format!("bg_blur_out_{}.png", id));
+        img.save(&input_path).unwrap();
+
+        processor.blur_background(&input_path, &output_path, 4.0, None, &|_| {}).unwrap();
+        let out = image::open(&output_path).unwrap().to_rgba8();
+
+        // The checkerboard is smoothed out, the subject untouched
+        let corner = out.get_pixel(0, 0);
+        assert!(corner[0] < 255 && corner[0] > 230, "{:?}", corner);
+        assert_eq!(out.get_pixel(16, 16).0, [0, 0, 200, 255]);
+        // The subject's edge fades into the background rather than cutting off
+        let edge = out.get_pixel(8, 16);
+        assert!(edge[0] > 0 && edge[0] < 230, "{:?}", edge);
+        assert!(out.pixels().all(|p| p[3] == 255));
+
+        let _ = std::fs::remove_file(input_path);
+        let _ = std::fs::remove_file(output_path);
+    }
+
     #[test]
     fn test_apply_lut_pass_through() {
         use std::io::Write;
-        let processor = ImageProcessor::new("./models/u2net.onnx".to_string()).unwrap();
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
 
         // Create temp input image
         let input_path = std::env::temp_dir().join("test_input.png");
@@ -392,7 +1300,7 @@ mod tests {
     writeln!(lf, "1 1 1").unwrap();
 
         let output_path = std::env::temp_dir().join("test_output.png");
-        let res = processor.apply_lut(&input_path, &output_path, lut_path.to_str().unwrap());
+        let res = processor.apply_lut(&input_path, &output_path, &lut_path, &|_| {});
         assert!(res.is_ok());
         assert!(output_path.exists());
 
@@ -401,4 +1309,490 @@ mod tests {
         let _ = std::fs::remove_file(output_path);
         let _ = std::fs::remove_file(lut_path);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_convert_with_lut_to_jpeg() {
+        let id = uuid::Uuid::new_v4();
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("convert_in_{}.png", id));
+        let output_path = dir.join(format!("convert_out_{}.jpg", id));
+        let lut_path = dir.join(format!("convert_{}.cube", id));
+
+        // RGBA source: JPEG output must drop alpha rather than fail
+        RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 128])).save(&input_path).unwrap();
+        // Inverting 1D LUT
+        std::fs::write(&lut_path, "LUT_1D_SIZE 2\n1 1 1\n0 0 0\n").unwrap();
+
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
+        processor
+            .convert_format(
+                &input_path,
+                &output_path,
+                OutputEncoding::new(ImageFormat::Jpeg),
+                Some(2),
+                Some(2),
+                Some(&lut_path),
+                &|_| {},
+            )
+            .unwrap();
+
+        let out = image::open(&output_path).unwrap();
+        assert_eq!(out.dimensions(), (2, 2));
+        let p = out.to_rgb8().get_pixel(0, 0).0;
+        assert!(p[0] > 230 && p[2] > 210, "{:?}", p);
+
+        for p in [input_path, output_path, lut_path] {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+
+    #[test]
+    fn test_apply_lut_rejects_corrupt_lut() {
+        let id = uuid::Uuid::new_v4();
+        let lut_path = std::env::temp_dir().join(format!("corrupt_{}.cube", id));
+        std::fs::write(&lut_path, "LUT_3D_SIZE 2\n0 0 0\n0 0 x\n").unwrap();
+
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
+        let input = std::env::temp_dir().join(format!("unused_{}.png", id));
+        let err = processor
+            .apply_lut(&input, &input, &lut_path, &|_| {})
+            .unwrap_err();
+        assert!(matches!(err, ProcessingError::InvalidLut(_)));
+        assert!(err.to_string().contains("line 3"), "{}", err);
+
+        let _ = std::fs::remove_file(lut_path);
+    }
+
+    #[test]
+    fn test_quality_controls_output_size() {
+        let id = uuid::Uuid::new_v4();
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("quality_in_{}.png", id));
+        // Busy pattern so the encoders have detail to throw away
+        RgbaImage::from_fn(96, 96, |x, y| {
+            Rgba([(x * 7 + y * 3) as u8, (x * y) as u8, ((x ^ y) * 4) as u8, 255])
+        })
+        .save(&input_path)
+        .unwrap();
+
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
+        for (format, ext) in [(ImageFormat::Jpeg, "jpg"), (ImageFormat::WebP, "webp")] {
+            let size_at = |quality: u8| {
+                let output_path = dir.join(format!("quality_{}_{}.{}", quality, id, ext));
+                let encoding = OutputEncoding { quality: Some(quality), ..OutputEncoding::new(format) };
+                processor
+                    .convert_format(&input_path, &output_path, encoding, None, None, None, &|_| {})
+                    .unwrap();
+                let size = std::fs::metadata(&output_path).unwrap().len();
+                let _ = std::fs::remove_file(output_path);
+                size
+            };
+            let (low, high) = (size_at(20), size_at(95));
+            assert!(low < high, "{}: q20 {} bytes >= q95 {} bytes", ext, low, high);
+        }
+
+        let _ = std::fs::remove_file(input_path);
+    }
+
+    /// Gradients with grain, so sizes follow the quality closely
+    fn photo_like(size: u32) -> RgbaImage {
+        RgbaImage::from_fn(size, size, |x, y| {
+            let grain = (x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503)) % 32;
+            Rgba([(x + grain) as u8, (y + grain) as u8, ((x + y) / 2 + grain) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn test_size_target_keeps_the_best_quality_that_fits() {
+        let id = uuid::Uuid::new_v4();
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("target_in_{}.png", id));
+        photo_like(192).save(&input_path).unwrap();
+
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
+        for (format, ext) in [(ImageFormat::Jpeg, "jpg"), (ImageFormat::WebP, "webp")] {
+            let output_path = dir.join(format!("target_{}.{}", id, ext));
+            let convert = |encoding: OutputEncoding| {
+                let converted = processor
+                    .convert_format(&input_path, &output_path, encoding, None, None, None, &|_| {})
+                    .unwrap();
+                (converted, std::fs::metadata(&output_path).unwrap().len())
+            };
+            let (_, best) = convert(OutputEncoding { quality: Some(95), ..OutputEncoding::new(format) });
+            let (_, worst) = convert(OutputEncoding { quality: Some(5), ..OutputEncoding::new(format) });
+
+            let target = (best + worst) / 2;
+            let sized = OutputEncoding { target_bytes: Some(target), ..OutputEncoding::new(format) };
+            let (converted, size) = convert(sized);
+            let quality = converted.quality.unwrap();
+            assert!(size <= target && !converted.target_size_missed, "{}: {} bytes for {}", ext, size, target);
+            assert!(quality > 5 && quality < 95, "{}: q{}", ext, quality);
+            // One step up no longer fits, give or take the search's last step
+            let (_, above) = convert(OutputEncoding { quality: Some(quality + 2), ..OutputEncoding::new(format) });
+            assert!(above > target, "{}: q{} is {} bytes, under {}", ext, quality + 2, above, target);
+            assert_eq!(image::open(&output_path).unwrap().dimensions(), (192, 192));
+
+            // Out of reach: the smallest encoding is kept and the miss reported
+            let (converted, size) = convert(OutputEncoding { target_bytes: Some(100), ..OutputEncoding::new(format) });
+            assert!(converted.target_size_missed);
+            assert_eq!((converted.quality, size), (Some(5), worst));
+            assert_eq!(image::open(&output_path).unwrap().dimensions(), (192, 192));
+            let _ = std::fs::remove_file(output_path);
+        }
+
+        // Lossless outputs have no quality to trade
+        let output_path = dir.join(format!("target_{}.png", id));
+        let encoding = OutputEncoding { target_bytes: Some(100), ..OutputEncoding::new(ImageFormat::Png) };
+        let converted =
+            processor.convert_format(&input_path, &output_path, encoding, None, None, None, &|_| {}).unwrap();
+        assert_eq!((converted.quality, converted.target_size_missed), (None, false));
+
+        let _ = std::fs::remove_file(output_path);
+        let _ = std::fs::remove_file(input_path);
+    }
+
+    #[test]
+    fn test_avif_output() {
+        let id = uuid::Uuid::new_v4();
+        let dir = std::env::temp_dir();
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
+
+        // Refused past the size limit before any encoding
+        let wide_path = dir.join(format!("avif_wide_{}.png", id));
+        RgbaImage::new(AVIF_MAX_EDGE + 1, 1).save(&wide_path).unwrap();
+        let output_path = dir.join(format!("avif_{}.avif", id));
+        let encoding = OutputEncoding::new(ImageFormat::Avif);
+        let err = processor.convert_format(&wide_path, &output_path, encoding, None, None, None, &|_| {}).unwrap_err();
+        assert!(matches!(err, ProcessingError::Unsupported(_)), "{:?}", err);
+        let _ = std::fs::remove_file(wide_path);
+
+        let input_path = dir.join(format!("avif_in_{}.png", id));
+        photo_like(64).save(&input_path).unwrap();
+        let converted = processor.convert_format(&input_path, &output_path, encoding, None, None, None, &|_| {});
+        if cfg!(feature = "avif") {
+            converted.unwrap();
+            // image decodes AVIF only with dav1d, so check the container
+            let avif = std::fs::read(&output_path).unwrap();
+            assert_eq!(&avif[4..12], b"ftypavif");
+            let png = std::fs::metadata(&input_path).unwrap().len();
+            assert!((avif.len() as u64) * 2 < png, "avif {} bytes vs png {} bytes", avif.len(), png);
+        } else {
+            assert!(matches!(converted, Err(ProcessingError::Unsupported(_))));
+        }
+
+        let _ = std::fs::remove_file(output_path);
+        let _ = std::fs::remove_file(input_path);
+    }
+
+    /// Minimal little-endian TIFF/Exif chunk holding only an orientation tag
+    fn exif_with_orientation(orientation: u8) -> Vec<u8> {
+        let mut exif = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, orientation, 0, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        exif
+    }
+
+    fn exif_of(path: &Path) -> Option<Vec<u8>> {
+        ImageReader::open(path).unwrap().into_decoder().unwrap().exif_metadata().unwrap()
+    }
+
+    #[test]
+    fn test_exif_orientation_is_applied_and_optionally_kept() {
+        let id = uuid::Uuid::new_v4();
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("orientation_in_{}.jpg", id));
+
+        // Stored sideways: 32x16 with the red half on the left. Orientation 6
+        // means viewers rotate it 90° clockwise, putting the red half on top.
+        let stored = RgbaImage::from_fn(32, 16, |x, _| {
+            if x < 16 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }
+        });
+        let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(
+            std::fs::File::create(&input_path).unwrap(),
+            95,
+        );
+        encoder.set_exif_metadata(exif_with_orientation(6)).unwrap();
+        DynamicImage::ImageRgba8(stored).write_with_encoder(encoder).unwrap();
+
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
+        let convert = |ext: &str, encoding: OutputEncoding| {
+            let output_path = dir.join(format!("orientation_out_{}.{}", id, ext));
+            processor
+                .convert_format(&input_path, &output_path, encoding, None, None, None, &|_| {})
+                .unwrap();
+            output_path
+        };
+
+        // Stripped by default
+        let png = convert("png", OutputEncoding::new(ImageFormat::Png));
+        let out = image::open(&png).unwrap().to_rgb8();
+        assert_eq!(out.dimensions(), (16, 32));
+        let (top, bottom) = (out.get_pixel(8, 4).0, out.get_pixel(8, 28).0);
+        assert!(top[0] > 200 && top[2] < 60, "top {:?}", top);
+        assert!(bottom[2] > 200 && bottom[0] < 60, "bottom {:?}", bottom);
+        assert!(exif_of(&png).is_none());
+
+        // Kept on request, without the orientation that has already been applied
+        let jpg = convert("jpg", OutputEncoding { keep_exif: true, ..OutputEncoding::new(ImageFormat::Jpeg) });
+        assert_eq!(image::open(&jpg).unwrap().dimensions(), (16, 32));
+        let exif = exif_of(&jpg).expect("Exif carried over");
+        assert_eq!(Orientation::from_exif_chunk(&exif), Some(Orientation::NoTransforms));
+
+        for p in [input_path, png, jpg] {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+
+    #[test]
+    fn test_images_over_the_pixel_limit_are_refused_before_decoding() {
+        // 31 KB of PNG declaring a 16000x16000 canvas
+        let bomb = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/huge_canvas.png"));
+        let refused = |e: ProcessingError| matches!(e, ProcessingError::TooManyPixels { pixels: 256_000_000, .. });
+        let data = std::fs::read(bomb).unwrap();
+        assert!(refused(ImageProcessor::thumbnail(&data, 256, MAX_PIXELS).unwrap_err()));
+        assert!(refused(ImageProcessor::analyze(&data, MAX_PIXELS).unwrap_err()));
+
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        let output = std::env::temp_dir().join(format!("bomb_{}.jpg", uuid::Uuid::new_v4()));
+        let encoding = OutputEncoding::new(ImageFormat::Jpeg);
+        let err = processor.convert_format(bomb, &output, encoding, None, None, None, &|_| {}).unwrap_err();
+        assert!(refused(err));
+        assert!(!output.exists());
+
+        // The limit is inclusive
+        let mut png = std::io::Cursor::new(Vec::new());
+        RgbaImage::new(10, 10).write_to(&mut png, ImageFormat::Png).unwrap();
+        assert!(ImageProcessor::thumbnail(png.get_ref(), 256, 100).is_ok());
+        let err = ImageProcessor::thumbnail(png.get_ref(), 256, 99).unwrap_err();
+        assert_eq!(err.to_string(), "Image too large: decodes to 100 pixels, more than the 99 allowed");
+    }
+
+    #[test]
+    fn test_thumbnail_bounds_longest_edge() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        RgbaImage::from_pixel(1024, 512, Rgba([10, 200, 30, 255]))
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+
+        let thumb = ImageProcessor::thumbnail(png.get_ref(), 256, MAX_PIXELS).unwrap();
+        let decoded = image::load_from_memory_with_format(&thumb, ImageFormat::Jpeg).unwrap();
+        assert_eq!(decoded.dimensions(), (256, 128));
+
+        // Small images keep their size
+        let small = ImageProcessor::thumbnail(&thumb, 512, MAX_PIXELS).unwrap();
+        assert_eq!(image::load_from_memory(&small).unwrap().dimensions(), (256, 128));
+    }
+
+    #[test]
+    fn test_animated_gif_keeps_its_frames() {
+        use image::{codecs::gif::GifEncoder, AnimationDecoder, Delay, Frame};
+
+        let id = uuid::Uuid::new_v4();
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("animated_in_{}.gif", id));
+        let mut encoder = GifEncoder::new(std::fs::File::create(&input_path).unwrap());
+        for shade in [40, 120, 200] {
+            let frame = RgbaImage::from_pixel(16, 16, Rgba([shade, shade, shade, 255]));
+            encoder
+                .encode_frame(Frame::from_parts(frame, 0, 0, Delay::from_numer_denom_ms(50, 1)))
+                .unwrap();
+        }
+        drop(encoder);
+
+        let processor = ImageProcessor::new("./models/u2net.onnx".to_string(), MAX_PIXELS).unwrap();
+        let frames_of = |path: &Path| {
+            let file = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+            image::codecs::gif::GifDecoder::new(file).unwrap().into_frames().collect_frames().unwrap()
+        };
+
+        // Resized frame by frame
+        let gif = dir.join(format!("animated_out_{}.gif", id));
+        let converted = processor
+            .convert_format(&input_path, &gif, OutputEncoding::new(ImageFormat::Gif), Some(8), Some(8), None, &|_| {})
+            .unwrap();
+        assert!(!converted.frames_dropped);
+        let frames = frames_of(&gif);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].buffer().dimensions(), (8, 8));
+        assert_eq!(frames[2].delay().numer_denom_ms(), (50, 1));
+
+        // Still formats get the first frame, and say so
+        let png = dir.join(format!("animated_out_{}.png", id));
+        let converted = processor
+            .convert_format(&input_path, &png, OutputEncoding::new(ImageFormat::Png), None, None, None, &|_| {})
+            .unwrap();
+        assert!(converted.frames_dropped);
+        assert_eq!(image::open(&png).unwrap().to_rgb8().get_pixel(0, 0).0, [40, 40, 40]);
+
+        // Graded frame by frame
+        processor.color_grade(&input_path, &gif, None, None, Some(30), None, &|_| {}).unwrap();
+        let frames = frames_of(&gif);
+        assert_eq!(frames.len(), 3);
+        assert!(frames[1].buffer().get_pixel(0, 0)[0] > 140);
+
+        for path in [&input_path, &gif, &png] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    /// Color grading as it was before the adjustments were fused: one full
+    /// pass per adjustment
+    fn graded_in_passes(
+        img: &RgbaImage,
+        hue: Option<i32>,
+        saturation: Option<i32>,
+        brightness: Option<i32>,
+        contrast: Option<i32>,
+    ) -> RgbaImage {
+        let mut img = img.clone();
+        if let Some(amount) = brightness {
+            for pixel in img.pixels_mut() {
+                for c in 0..3 {
+                    pixel[c] = (pixel[c] as i32 + amount).clamp(0, 255) as u8;
+                }
+            }
+        }
+        if let Some(amount) = contrast {
+            let factor = (259.0 * (amount as f32 + 255.0)) / (255.0 * (259.0 - amount as f32));
+            for pixel in img.pixels_mut() {
+                for c in 0..3 {
+                    pixel[c] = (factor * (pixel[c] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+        if let Some(amount) = saturation {
+            let factor = (amount as f32 + 100.0) / 100.0;
+            for pixel in img.pixels_mut() {
+                let gray = (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) as u8;
+                for c in 0..3 {
+                    pixel[c] = (gray as f32 + factor * (pixel[c] as f32 - gray as f32)).clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+        if let Some(amount) = hue {
+            for pixel in img.pixels_mut() {
+                let (h, s, v) = ImageProcessor::rgb_to_hsv(pixel[0], pixel[1], pixel[2]);
+                let (r, g, b) = ImageProcessor::hsv_to_rgb((h + amount as f32 / 360.0) % 1.0, s, v);
+                pixel.0 = [r, g, b, pixel[3]];
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn test_fused_adjustments_match_separate_passes() {
+        // A gradient, noise, and the extremes that clamp
+        let mut seed = 0x2545_f491u32;
+        let fixtures = [
+            RgbaImage::from_fn(256, 64, |x, y| Rgba([x as u8, (y * 4) as u8, (255 - x) as u8, 255])),
+            RgbaImage::from_fn(128, 128, |_, _| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                Rgba(seed.to_le_bytes())
+            }),
+            RgbaImage::from_fn(8, 1, |x, _| {
+                let channel = |bit: u32| if x & bit != 0 { 255 } else { 0 };
+                Rgba([channel(1), channel(2), channel(4), 255])
+            }),
+        ];
+        let settings = [
+            (None, None, Some(40), None),
+            (None, None, None, Some(-60)),
+            (None, Some(-100), None, None),
+            (Some(-180), None, None, None),
+            (Some(15), Some(-20), Some(-10), Some(10)),
+            (Some(90), Some(100), Some(100), Some(100)),
+            (None, None, Some(-100), Some(-100)),
+        ];
+
+        for (i, fixture) in fixtures.iter().enumerate() {
+            for (hue, saturation, brightness, contrast) in settings {
+                let expected = graded_in_passes(fixture, hue, saturation, brightness, contrast);
+                let mut fused = fixture.clone();
+                Adjustments::new(hue, saturation, brightness, contrast).apply(&mut fused, None);
+                for (a, b) in fused.pixels().zip(expected.pixels()) {
+                    assert!(
+                        a.0.iter().zip(b.0).all(|(a, b)| a.abs_diff(b) <= 1),
+                        "fixture {} with {:?}: {:?} != {:?}",
+                        i,
+                        (hue, saturation, brightness, contrast),
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    /// Every progress value `run` reports, in order
+    fn progress_of(run: impl FnOnce(OnProgress) -> Result<(), ProcessingError>) -> Vec<u32> {
+        let seen = Mutex::new(Vec::new());
+        run(&|percent| seen.lock().unwrap().push(percent)).unwrap();
+        seen.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_progress_rises_through_the_rows_to_100() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        let dir = std::env::temp_dir().join(format!("progress_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.png");
+        RgbaImage::from_fn(64, 300, |x, y| Rgba([x as u8, y as u8, 128, 255])).save(&input).unwrap();
+        let output = dir.join("out.png");
+
+        let runs = [
+            progress_of(|on_progress| processor.color_grade(&input, &output, Some(10), None, Some(20), None, on_progress)),
+            progress_of(|on_progress| processor.remove_background(&input, &output, None, on_progress).map(|_| ())),
+            progress_of(|on_progress| processor.apply_preset(&input, &output, "vintage", on_progress)),
+        ];
+        for (i, seen) in runs.iter().enumerate() {
+            assert!(seen.windows(2).all(|pair| pair[0] < pair[1]), "run {}: {:?}", i, seen);
+            assert_eq!(seen.last(), Some(&100), "run {}", i);
+            // Rows report between decoding and saving, not just the milestones
+            assert!(seen.iter().filter(|&&p| p > DECODED && p < PROCESSED).count() > 10, "run {}: {:?}", i, seen);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Timing of the per-pixel work on a 4000x3000 image, on one thread and
+    /// on rayon's full pool. Too slow for the default run:
+    /// `cargo test --release -- --ignored --nocapture test_pixel_loops_scale`
+    #[test]
+    #[ignore]
+    fn test_pixel_loops_scale_across_cores() {
+        let processor = ImageProcessor::new("./models/missing.onnx".to_string(), MAX_PIXELS).unwrap();
+        let mut cube = "LUT_3D_SIZE 17\n".to_string();
+        for b in 0..17 {
+            for g in 0..17 {
+                for r in 0..17 {
+                    cube.push_str(&format!("{} {} {}\n", r as f32 / 16.0, g as f32 / 16.0, b as f32 / 16.0));
+                }
+            }
+        }
+        let (lut, _) = Lut::from_bytes(cube.as_bytes(), Some("cube")).unwrap();
+        let image = RgbaImage::from_fn(4000, 3000, |x, y| Rgba([(x % 256) as u8, (y % 256) as u8, 128, 255]));
+
+        let work = || {
+            let mut img = image.clone();
+            Adjustments::new(Some(30), Some(10), Some(10), Some(10)).apply(&mut img, None);
+            let img = DynamicImage::ImageRgba8(lut.apply_to_image(&DynamicImage::ImageRgba8(img)));
+            processor.simple_bg_removal(&img, &|_| {}).unwrap()
+        };
+        let timed = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let started = std::time::Instant::now();
+            let out = pool.install(work);
+            (started.elapsed(), out)
+        };
+
+        let threads = rayon::current_num_threads();
+        let (single, expected) = timed(1);
+        let (parallel, out) = timed(threads);
+        println!("1 thread: {:?}, {} threads: {:?}", single, threads, parallel);
+        assert!(out == expected, "parallel output differs");
+        if threads > 1 {
+            assert!(parallel < single, "no speedup on {} threads", threads);
+        }
+    }
+}
@@ -1,24 +1,25 @@
 // backend/src/services/queue.rs
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
 use std::sync::Arc;
 use std::collections::HashMap;
 use tokio::sync::Mutex;
 use redis::AsyncCommands;
 use redis::aio::ConnectionManager;
 
+use crate::{db, telemetry};
+
+/// Wake-up signal for the workers. The job itself lives in the database and
+/// is claimed from there, so the message only names it for logging.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobMessage {
     pub job_id: String,
-    pub user_id: String,
-    pub job_type: String,
-    pub media_location: String,
 }
 
 #[derive(Clone)]
 pub struct Queue {
     sender: Sender<JobMessage>,
-    statuses: Arc<Mutex<HashMap<String, JobStatus>>>,
+    statuses: StatusStore,
     // Optional redis connection manager. If present, enqueue will push to redis list
     redis: Option<ConnectionManager>,
 }
@@ -31,17 +32,132 @@ pub enum JobStatus {
     Failed { error: String },
 }
 
+/// Redis list of wake-ups, drained by each instance's poller
+pub const JOB_QUEUE_KEY: &str = "mediaforge:job_queue";
+
+const STATUS_KEY_PREFIX: &str = "mediaforge:job_status:";
+/// Mirrored statuses expire a day after their last update; the jobs table
+/// remains the permanent record
+const STATUS_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+impl JobStatus {
+    /// Flatten into the fields of the Redis status hash
+    fn to_fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::Queued => vec![("state", "queued".to_string())],
+            Self::Processing { progress } => vec![
+                ("state", "processing".to_string()),
+                ("progress", progress.to_string()),
+            ],
+            Self::Completed { result_url } => vec![
+                ("state", "completed".to_string()),
+                ("result_url", result_url.clone()),
+            ],
+            Self::Failed { error } => vec![
+                ("state", "failed".to_string()),
+                ("error", error.clone()),
+            ],
+        }
+    }
+
+    /// Rebuild from a Redis status hash. An empty hash (missing or expired key)
+    /// or an unknown state gives `None`.
+    fn from_fields(fields: &HashMap<String, String>) -> Option<Self> {
+        let field = |name: &str| fields.get(name).cloned().unwrap_or_default();
+        match fields.get("state")?.as_str() {
+            "queued" => Some(Self::Queued),
+            "processing" => Some(Self::Processing {
+                progress: field("progress").parse().unwrap_or(0),
+            }),
+            "completed" => Some(Self::Completed {
+                result_url: field("result_url"),
+            }),
+            "failed" => Some(Self::Failed { error: field("error") }),
+            _ => None,
+        }
+    }
+}
+
+/// Live job statuses. Kept in memory for this process and, when Redis is
+/// configured, mirrored to a hash per job so every API instance sees the
+/// same progress regardless of which one enqueued the job.
+#[derive(Clone)]
+pub struct StatusStore {
+    /// None in a process that serves no status requests
+    local: Option<Arc<Mutex<HashMap<String, JobStatus>>>>,
+    redis: Option<ConnectionManager>,
+}
+
+impl StatusStore {
+    pub fn new(redis: Option<ConnectionManager>) -> Self {
+        Self {
+            local: Some(Arc::new(Mutex::new(HashMap::new()))),
+            redis,
+        }
+    }
+
+    /// Write statuses to Redis only, for a worker process apart from the API
+    pub fn redis_only(redis: ConnectionManager) -> Self {
+        Self { local: None, redis: Some(redis) }
+    }
+
+    /// Record a status locally and write it through to Redis. A Redis failure
+    /// is logged and otherwise ignored; the local copy is still updated.
+    pub async fn set(&self, job_id: &str, status: JobStatus) {
+        if let Some(conn_mgr) = &self.redis {
+            let key = format!("{}{}", STATUS_KEY_PREFIX, job_id);
+            let mut conn = conn_mgr.clone();
+            // Replace the whole hash so fields from an earlier state do not linger
+            let written: redis::RedisResult<()> = redis::pipe()
+                .atomic()
+                .del(&key)
+                .hset_multiple(&key, &status.to_fields())
+                .expire(&key, STATUS_TTL_SECONDS)
+                .query_async(&mut conn)
+                .await;
+            if let Err(e) = written {
+                tracing::warn!("Failed to mirror status of job {} to redis: {:?}", job_id, e);
+            }
+        }
+
+        if let Some(local) = &self.local {
+            local.lock().await.insert(job_id.to_string(), status);
+        }
+    }
+
+    /// Current status, preferring the shared Redis copy and falling back to
+    /// this process's map when Redis is absent, unreachable or has no entry
+    pub async fn get(&self, job_id: &str) -> Option<JobStatus> {
+        if let Some(conn_mgr) = &self.redis {
+            let mut conn = conn_mgr.clone();
+            let key = format!("{}{}", STATUS_KEY_PREFIX, job_id);
+            match conn.hgetall::<_, HashMap<String, String>>(&key).await {
+                Ok(fields) => {
+                    if let Some(status) = JobStatus::from_fields(&fields) {
+                        return Some(status);
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to read status of job {} from redis: {:?}", job_id, e),
+            }
+        }
+
+        match &self.local {
+            Some(local) => local.lock().await.get(job_id).cloned(),
+            None => None,
+        }
+    }
+}
+
 impl Queue {
     /// Create a new in-memory queue. If redis_url is Some, attempt to connect
     /// asynchronously and set up a connection manager; caller should be running
     /// inside a Tokio runtime and await this function.
     pub async fn new(buffer: usize, redis_url: Option<&str>) -> (Self, Receiver<JobMessage>) {
         let (tx, rx) = channel(buffer);
-        let statuses = Arc::new(Mutex::new(HashMap::new()));
 
         let redis_conn = match redis_url {
             Some(url) => match redis::Client::open(url) {
-                Ok(client) => match client.get_tokio_connection_manager().await {
+                Ok(client) => match client.get_connection_manager().await {
                     Ok(cm) => Some(cm),
                     Err(e) => {
                         tracing::warn!("Failed to create redis connection manager: {:?}. Falling back to in-memory queue.", e);
@@ -57,51 +173,186 @@ impl Queue {
         };
 
         (
-            Self { sender: tx, statuses, redis: redis_conn },
+            Self {
+                sender: tx,
+                statuses: StatusStore::new(redis_conn.clone()),
+                redis: redis_conn,
+            },
             rx,
         )
     }
 
-    pub async fn enqueue(&self, job: JobMessage) -> Result<(), ()> {
+    /// Queue a job whose row is ready to be claimed: record it in the job's
+    /// history and announce it to the workers
+    pub async fn enqueue(&self, db_pool: &sqlx::PgPool, job: JobMessage) -> Result<(), ()> {
+        if let Ok(job_id) = uuid::Uuid::parse_str(&job.job_id) {
+            db::JobEvent::record(db_pool, job_id, "queued", None).await;
+        }
+        self.announce(job).await
+    }
+
+    /// Wake a worker for a queued job, through Redis when configured
+    pub async fn announce(&self, job: JobMessage) -> Result<(), ()> {
         // mark queued
-        let mut s = self.statuses.lock().await;
-        s.insert(job.job_id.clone(), JobStatus::Queued);
-        drop(s);
+        self.statuses.set(&job.job_id, JobStatus::Queued).await;
 
         // If we have redis, push to list; otherwise use in-memory channel
-        if let Some(conn_mgr) = &self.redis {
-            let mut conn = conn_mgr.clone();
-            let payload = serde_json::to_string(&job).map_err(|_| ())?;
-            let push_res: Result<(), redis::RedisError> = async {
-                let mut c = conn;
-                c.rpush("mediaforge:job_queue", payload).await.map(|_: i64| ())
+        if self.redis.is_some() {
+            if self.push_redis(&job).await.is_ok() {
+                return Ok(());
             }
-            .await;
+            tracing::warn!("Redis enqueue failed - falling back to local channel");
+            metrics::counter!(telemetry::QUEUE_REDIS_FALLBACKS).increment(1);
+        }
+        self.forward_to_local(job).await
+    }
 
-            match push_res {
-                Ok(_) => Ok(()),
-                Err(e) => {
-                    tracing::warn!("Redis enqueue failed: {:?} - falling back to local channel", e);
-                    self.sender.send(job).await.map_err(|_| ())
-                }
+    /// Announce again the due queued jobs (only `id`, when given) that no
+    /// worker has claimed within `stale_after` of their last announcement,
+    /// most likely because the wake-up was lost in a crash. Jobs claimed in
+    /// the meantime are unaffected, as workers take them from the database.
+    /// Returns the jobs announced.
+    pub async fn reannounce_stalled(
+        &self,
+        db_pool: &sqlx::PgPool,
+        stale_after: std::time::Duration,
+        id: Option<uuid::Uuid>,
+    ) -> Result<Vec<db::Job>, sqlx::Error> {
+        let stalled = db::Job::take_stalled(db_pool, stale_after, id).await?;
+        for job in &stalled {
+            tracing::warn!("Job {} has been queued for over {:?}; announcing it again", job.id, stale_after);
+            metrics::counter!(telemetry::JOBS_STALLED).increment(1);
+            let detail = serde_json::json!({ "stalled_secs": stale_after.as_secs() });
+            db::JobEvent::record(db_pool, job.id, "reannounced", Some(detail)).await;
+            // Idle workers still find it when they poll the table
+            let _ = self.announce(JobMessage { job_id: job.id.to_string() }).await;
+        }
+        Ok(stalled)
+    }
+
+    /// Push a job onto the Redis list. Fails when Redis is not configured.
+    pub async fn push_redis(&self, job: &JobMessage) -> Result<(), ()> {
+        let Some(conn_mgr) = &self.redis else {
+            return Err(());
+        };
+        let payload = serde_json::to_string(job).map_err(|_| ())?;
+        let mut conn = conn_mgr.clone();
+        conn.rpush(JOB_QUEUE_KEY, payload)
+            .await
+            .map(|_: i64| ())
+            .map_err(|e| tracing::warn!("Redis push failed: {:?}", e))
+    }
+
+    /// Wake-ups not yet taken by a worker: the Redis list's length when Redis
+    /// is configured, otherwise the local channel's backlog
+    pub async fn depth(&self) -> usize {
+        if let Some(conn_mgr) = &self.redis {
+            let mut conn = conn_mgr.clone();
+            match conn.llen::<_, usize>(JOB_QUEUE_KEY).await {
+                Ok(len) => return len,
+                Err(e) => tracing::warn!("Failed to read redis queue length: {:?}", e),
             }
-        } else {
-            self.sender.send(job).await.map_err(|_| ())
         }
+        self.sender.max_capacity() - self.sender.capacity()
     }
 
     pub async fn get_status(&self, job_id: &str) -> Option<JobStatus> {
-        let s = self.statuses.lock().await;
-        s.get(job_id).cloned()
+        self.statuses.get(job_id).await
     }
 
-    pub fn get_statuses_handle(&self) -> Arc<Mutex<HashMap<String, JobStatus>>> {
+    pub fn get_statuses_handle(&self) -> StatusStore {
         self.statuses.clone()
     }
 
-    /// Forward a job to the local in-process channel. Used by redis poller to
-    /// insert jobs into the worker channel.
+    /// Shared Redis connection, for other services that keep state there
+    pub fn redis_connection(&self) -> Option<ConnectionManager> {
+        self.redis.clone()
+    }
+
+    /// Wake a local worker. Used by redis poller to insert jobs into the
+    /// worker channel. A full channel means every worker is busy or already
+    /// woken, and they check the database before sleeping again, so the signal
+    /// can be dropped; only a closed channel (no workers) is an error.
     pub async fn forward_to_local(&self, job: JobMessage) -> Result<(), ()> {
-        self.sender.send(job).await.map_err(|_| ())
+        match self.sender.try_send(job) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Closed(_)) => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_fields_round_trip() {
+        for status in [
+            JobStatus::Queued,
+            JobStatus::Processing { progress: 42 },
+            JobStatus::Completed { result_url: "converted_1.png".to_string() },
+            JobStatus::Failed { error: "Asset not found".to_string() },
+        ] {
+            let fields: HashMap<String, String> = status
+                .to_fields()
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect();
+            let restored = JobStatus::from_fields(&fields).unwrap();
+            assert_eq!(format!("{:?}", restored), format!("{:?}", status));
+        }
+
+        // Missing or expired keys come back from HGETALL as an empty hash
+        assert!(JobStatus::from_fields(&HashMap::new()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_depth_counts_pending_wake_ups() {
+        let (queue, mut rx) = Queue::new(8, None).await;
+        assert_eq!(queue.depth().await, 0);
+        for id in ["a", "b"] {
+            queue.announce(JobMessage { job_id: id.to_string() }).await.unwrap();
+        }
+        assert_eq!(queue.depth().await, 2);
+        rx.recv().await.unwrap();
+        assert_eq!(queue.depth().await, 1);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_status_store_without_redis_uses_local_map() {
+        let store = StatusStore::new(None);
+        assert!(store.get("job").await.is_none());
+        store.set("job", JobStatus::Processing { progress: 10 }).await;
+        assert!(matches!(store.get("job").await, Some(JobStatus::Processing { progress: 10 })));
+    }
+
+    /// Two API instances sharing one Redis agree on a job's status
+    #[cfg(feature = "redis-tests")]
+    #[tokio::test]
+    async fn test_status_is_shared_between_instances() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let (first, _rx1) = Queue::new(8, Some(&url)).await;
+        let (second, _rx2) = Queue::new(8, Some(&url)).await;
+        assert!(first.redis.is_some(), "could not connect to redis at {}", url);
+
+        let job_id = uuid::Uuid::new_v4().to_string();
+        first
+            .get_statuses_handle()
+            .set(&job_id, JobStatus::Processing { progress: 55 })
+            .await;
+        assert!(matches!(
+            second.get_status(&job_id).await,
+            Some(JobStatus::Processing { progress: 55 })
+        ));
+
+        // Later states replace earlier ones rather than merging fields
+        first
+            .get_statuses_handle()
+            .set(&job_id, JobStatus::Failed { error: "boom".to_string() })
+            .await;
+        match second.get_status(&job_id).await {
+            Some(JobStatus::Failed { error }) => assert_eq!(error, "boom"),
+            other => panic!("unexpected status {:?}", other),
+        }
+    }
+}
@@ -1,45 +1,426 @@
 use crate::db;
-use crate::error::Result as AppResult;
-use crate::config::Config;
+use crate::config::QuotaConfig;
+use crate::error::{Message, Msg};
+use crate::services::sniff::MediaKind;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-pub async fn check_quota(db_pool: &sqlx::PgPool, config: &Config, user_id: Uuid, tier: &str, job_kind: &str) -> Result<(), String> {
-    // Determine today's jobs count for the user and job_kind
-    let count = db::Job::get_user_jobs_today(db_pool, user_id, Some(job_kind))
-        .await
-        .map_err(|e| format!("DB error: {:?}", e))?;
-
-    let limit = match (tier, job_kind) {
-        ("free", "image") => config.quotas.free_tier_image_daily as i64,
-        ("free", "video") => config.quotas.free_tier_video_daily as i64,
-        ("free", _) => i64::MAX,
-        ("pro", "video") => config.quotas.pro_tier_video_daily as i64,
-        ("pro", _) => i64::MAX,
-        _ => i64::MAX,
+/// Amount used against one limit. A `None` limit is unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Usage {
+    pub used: i64,
+    pub limit: Option<i64>,
+}
+
+impl Usage {
+    fn allows(&self, requested: i64) -> bool {
+        self.limit
+            .is_none_or(|limit| self.used.saturating_add(requested) <= limit)
+    }
+}
+
+/// A user's usage against every limit of their tier
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuotaStatus {
+    pub tier: String,
+    /// Image assets submitted today
+    pub images: Usage,
+    /// Video assets submitted today
+    pub videos: Usage,
+    /// Jobs queued or processing right now
+    pub concurrent: Usage,
+    /// Bytes held by uploads that have neither expired nor been deleted
+    pub storage: Usage,
+    /// Daily counts start over at this time (midnight UTC)
+    pub resets_at: DateTime<Utc>,
+    /// Set when acting for an organization with pooled daily limits:
+    /// `images` and `videos` then count every member's jobs for it, against
+    /// the organization's limit for each kind that has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<Uuid>,
+}
+
+/// Which limit a submission would break
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaViolation {
+    Daily { usage: Usage, requested: i64 },
+    Concurrent { usage: Usage },
+    Storage { usage: Usage, requested: i64 },
+}
+
+impl QuotaViolation {
+    /// What to tell the user, with the numbers as details: `used` and `limit`
+    /// of the limit that was hit, and the `requested` amount where it matters
+    pub fn message(&self) -> Message {
+        match self {
+            Self::Daily { usage, requested } => {
+                let id = if *requested > 1 { Msg::DailyQuotaExceededBy } else { Msg::DailyQuotaExceeded };
+                let message = id.with("used", usage.used).with("limit", usage.limit.unwrap_or(i64::MAX));
+                if *requested > 1 {
+                    message.with("requested", *requested)
+                } else {
+                    message
+                }
+            }
+            Self::Concurrent { usage } => Msg::ConcurrentLimitExceeded
+                .with("used", usage.used)
+                .with("limit", usage.limit.unwrap_or(i64::MAX)),
+            Self::Storage { usage, requested } => Msg::StorageQuotaExceeded
+                .with("used", usage.used)
+                .with("limit", usage.limit.unwrap_or(i64::MAX))
+                .with("requested", *requested),
+        }
+    }
+}
+
+impl QuotaStatus {
+    pub fn daily(&self, kind: MediaKind) -> Usage {
+        match kind {
+            MediaKind::Image => self.images,
+            MediaKind::Video => self.videos,
+        }
+    }
+
+    /// Check that `requested` more assets of `kind` fit in today's quota and
+    /// that a concurrent slot is free
+    pub fn check(&self, kind: MediaKind, requested: i64) -> Result<(), QuotaViolation> {
+        let usage = self.daily(kind);
+        if !usage.allows(requested) {
+            return Err(QuotaViolation::Daily { usage, requested });
+        }
+        self.check_concurrent()
+    }
+
+    /// Check that one more job may run alongside the user's active ones
+    pub fn check_concurrent(&self) -> Result<(), QuotaViolation> {
+        if !self.concurrent.allows(1) {
+            return Err(QuotaViolation::Concurrent { usage: self.concurrent });
+        }
+        Ok(())
+    }
+
+    /// Check that an upload of `bytes` fits in the user's storage quota
+    pub fn check_storage(&self, bytes: i64) -> Result<(), QuotaViolation> {
+        if !self.storage.allows(bytes) {
+            return Err(QuotaViolation::Storage { usage: self.storage, requested: bytes });
+        }
+        Ok(())
+    }
+}
+
+/// Daily asset limit for `kind` shared by an organization's members, if
+/// it is pooled
+fn organization_daily_limit(quotas: &QuotaConfig, kind: MediaKind) -> Option<i64> {
+    let limit = match kind {
+        MediaKind::Image => quotas.org_image_daily,
+        MediaKind::Video => quotas.org_video_daily,
     };
+    (limit > 0).then_some(limit as i64)
+}
+
+/// Daily asset limit for `kind` on `tier`
+fn daily_limit(quotas: &QuotaConfig, tier: &str, kind: MediaKind) -> Option<i64> {
+    match (tier, kind) {
+        ("free", MediaKind::Image) => Some(quotas.free_tier_image_daily as i64),
+        ("free", MediaKind::Video) => Some(quotas.free_tier_video_daily as i64),
+        ("pro", MediaKind::Video) => Some(quotas.pro_tier_video_daily as i64),
+        _ => None,
+    }
+}
+
+fn concurrent_limit(quotas: &QuotaConfig, tier: &str) -> Option<i64> {
+    match tier {
+        "free" => Some(quotas.free_tier_concurrent as i64),
+        "pro" => Some(quotas.pro_tier_concurrent as i64),
+        _ => None,
+    }
+}
+
+fn storage_limit(quotas: &QuotaConfig, tier: &str) -> Option<i64> {
+    match tier {
+        "free" => Some(quotas.free_tier_storage_quota_bytes as i64),
+        "pro" => Some(quotas.pro_tier_storage_quota_bytes as i64),
+        _ => None,
+    }
+}
+
+fn hours(hours: u64) -> Duration {
+    Duration::hours(hours.min(i64::MAX as u64 / 3600) as i64)
+}
+
+/// How long a job's result is kept after it completes
+pub fn result_retention(quotas: &QuotaConfig, tier: &str) -> Duration {
+    match tier {
+        "pro" => hours(quotas.pro_tier_result_retention_hours),
+        _ => hours(quotas.free_tier_result_retention_hours),
+    }
+}
 
-    if count >= limit {
-        return Err(format!("Daily quota exceeded ({}/{}).", count, limit));
+/// Furthest past completion a result's expiry may be pushed, or `None` if
+/// the tier cannot extend retention
+pub fn max_result_retention(quotas: &QuotaConfig, tier: &str) -> Option<Duration> {
+    match tier {
+        "pro" => Some(hours(quotas.pro_tier_max_result_retention_hours)),
+        _ => None,
     }
+}
 
-    Ok(())
+/// Expiry after pushing `expires_at` back by `extra`, capped at `max` past
+/// `completed_at`. `None` when the cap leaves nothing to extend.
+pub fn extended_expiry(
+    expires_at: DateTime<Utc>,
+    completed_at: DateTime<Utc>,
+    extra: Duration,
+    max: Duration,
+) -> Option<DateTime<Utc>> {
+    let extended = (expires_at + extra).min(completed_at + max);
+    (extended > expires_at).then_some(extended)
 }
 
-/// Concurrent jobs check (counts active/running jobs) — enforce concurrent limit
-pub async fn check_concurrent(db_pool: &sqlx::PgPool, config: &Config, user_id: Uuid, tier: &str) -> Result<(), String> {
-    let active = db::Job::get_active_jobs_count(db_pool, user_id)
-        .await
-        .map_err(|e| format!("DB error: {:?}", e))?;
+/// Start of the current daily window
+fn day_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+/// The daily limit on `kind` that applies: the organization's pooled one
+/// when acting for an organization that has one, else the user's own. The
+/// flag says which.
+fn applicable_daily_limit(quotas: &QuotaConfig, owner: db::Owner, tier: &str, kind: MediaKind) -> (Option<i64>, bool) {
+    match owner.organization_id.and_then(|_| organization_daily_limit(quotas, kind)) {
+        Some(limit) => (Some(limit), true),
+        None => (daily_limit(quotas, tier, kind), false),
+    }
+}
 
-    let limit = match tier {
-        "free" => config.quotas.free_tier_concurrent as i64,
-        "pro" => config.quotas.pro_tier_concurrent as i64,
-        _ => i64::MAX,
+/// Today's usage of `kind` against the daily limit that applies, and
+/// whether it is the organization's
+async fn daily_usage(
+    db_pool: &sqlx::PgPool,
+    quotas: &QuotaConfig,
+    owner: db::Owner,
+    tier: &str,
+    kind: MediaKind,
+) -> Result<(Usage, bool), sqlx::Error> {
+    let (limit, pooled) = applicable_daily_limit(quotas, owner, tier, kind);
+    let subject_id = match owner.organization_id {
+        Some(organization_id) if pooled => organization_id,
+        _ => owner.user_id,
     };
+    let used = db::QuotaUsage::today(db_pool, subject_id, kind.as_str()).await?;
+    Ok((Usage { used, limit }, pooled))
+}
+
+/// Count `requested` assets of `kind` against today's quota, failing with
+/// the usage that left no room for them. Unlike checking a `QuotaStatus`,
+/// this holds when submissions race: only as many as fit get through.
+pub async fn reserve(
+    db_pool: &sqlx::PgPool,
+    quotas: &QuotaConfig,
+    owner: db::Owner,
+    tier: &str,
+    kind: MediaKind,
+    requested: i64,
+) -> Result<Result<(), QuotaViolation>, sqlx::Error> {
+    let (limit, pooled) = applicable_daily_limit(quotas, owner, tier, kind);
+    if db::QuotaUsage::reserve(db_pool, owner, kind.as_str(), requested, limit, pooled).await? {
+        return Ok(Ok(()));
+    }
+    let (usage, _) = daily_usage(db_pool, quotas, owner, tier, kind).await?;
+    Ok(Err(QuotaViolation::Daily { usage, requested }))
+}
+
+/// Give back a reservation made today for a job that was never created
+pub async fn release(db_pool: &sqlx::PgPool, owner: db::Owner, kind: MediaKind, requested: i64) {
+    if let Err(e) = db::QuotaUsage::release(db_pool, owner, kind.as_str(), requested).await {
+        tracing::warn!("Failed to release {} {} quota for {}: {:?}", requested, kind.as_str(), owner.user_id, e);
+    }
+}
+
+/// Give a job that failed for good its assets back when
+/// `QUOTA_REFUND_FAILED_JOBS` is set. Retrying the job counts them again.
+pub async fn refund_failed(db_pool: &sqlx::PgPool, quotas: &QuotaConfig, job_id: Uuid) {
+    if !quotas.refund_failed_jobs {
+        return;
+    }
+    match db::Job::refund_quota(db_pool, job_id).await {
+        Ok(true) => tracing::debug!("Refunded the daily quota used by failed job {}", job_id),
+        Ok(false) => {}
+        Err(e) => tracing::warn!("Failed to refund the quota of job {}: {:?}", job_id, e),
+    }
+}
+
+/// Count the user's usage today and right now against their tier's limits,
+/// and the organization's pooled ones when acting for it. Concurrent jobs
+/// and storage are always the user's own.
+pub async fn quota_status(
+    db_pool: &sqlx::PgPool,
+    quotas: &QuotaConfig,
+    owner: db::Owner,
+    tier: &str,
+) -> Result<QuotaStatus, sqlx::Error> {
+    let since = day_start(Utc::now());
+    let (images, images_pooled) = daily_usage(db_pool, quotas, owner, tier, MediaKind::Image).await?;
+    let (videos, videos_pooled) = daily_usage(db_pool, quotas, owner, tier, MediaKind::Video).await?;
+    let active = db::Job::get_active_jobs_count(db_pool, owner.user_id).await?;
+    let stored = db::MediaAsset::storage_used(db_pool, owner.user_id).await?;
+
+    Ok(QuotaStatus {
+        tier: tier.to_string(),
+        images,
+        videos,
+        concurrent: Usage {
+            used: active,
+            limit: concurrent_limit(quotas, tier),
+        },
+        storage: Usage {
+            used: stored,
+            limit: storage_limit(quotas, tier),
+        },
+        resets_at: since + Duration::days(1),
+        organization_id: owner.organization_id.filter(|_| images_pooled || videos_pooled),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(images: i64, active: i64) -> QuotaStatus {
+        QuotaStatus {
+            tier: "free".to_string(),
+            images: Usage { used: images, limit: Some(10) },
+            videos: Usage { used: 0, limit: None },
+            concurrent: Usage { used: active, limit: Some(2) },
+            storage: Usage { used: 3 * 1024 * 1024, limit: Some(4 * 1024 * 1024) },
+            resets_at: day_start(Utc::now()) + Duration::days(1),
+            organization_id: None,
+        }
+    }
+
+    #[test]
+    fn test_check_daily_and_concurrent_limits() {
+        assert!(status(9, 1).check(MediaKind::Image, 1).is_ok());
+
+        let err = status(9, 1).check(MediaKind::Image, 2).unwrap_err();
+        assert_eq!(
+            err.message().to_string(),
+            "Daily quota exceeded (9/10, 2 more requested). Upgrade to Pro for more capacity."
+        );
+        let details = err.message().details();
+        assert_eq!((&details["used"], &details["limit"], &details["requested"]), (&9.into(), &10.into(), &2.into()));
 
-    if active >= limit {
-        return Err(format!("Concurrent job limit exceeded ({}/{}).", active, limit));
+        // Unlimited kinds still need a free concurrent slot
+        assert!(status(10, 0).check(MediaKind::Video, 500).is_ok());
+        let err = status(0, 2).check(MediaKind::Video, 1).unwrap_err();
+        assert!(matches!(err, QuotaViolation::Concurrent { .. }));
+        assert_eq!(err.message().id(), Msg::ConcurrentLimitExceeded);
+        assert_eq!(err.message().details()["used"], 2);
     }
 
-    Ok(())
+    #[test]
+    fn test_check_storage() {
+        assert!(status(0, 0).check_storage(1024 * 1024).is_ok());
+
+        let err = status(0, 0).check_storage(1024 * 1024 + 1).unwrap_err();
+        let message = err.message().to_string();
+        assert!(message.starts_with("Storage quota exceeded (3.0 MB of 4.0 MB used, upload is 1.0 MB)."));
+        assert_eq!(err.message().details()["limit"], 4 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_extended_expiry_is_capped_past_completion() {
+        let completed = DateTime::parse_from_rfc3339("2024-03-01T00:00:00+00:00").unwrap().to_utc();
+        let expires = completed + Duration::days(7);
+        let max = Duration::days(30);
+
+        assert_eq!(extended_expiry(expires, completed, Duration::days(7), max), Some(completed + Duration::days(14)));
+        assert_eq!(extended_expiry(expires, completed, Duration::days(60), max), Some(completed + max));
+        assert_eq!(extended_expiry(completed + max, completed, Duration::hours(1), max), None);
+    }
+
+    #[test]
+    fn test_day_start_is_utc_midnight() {
+        let now = DateTime::parse_from_rfc3339("2024-03-05T23:59:59+00:00").unwrap().to_utc();
+        assert_eq!(day_start(now).to_rfc3339(), "2024-03-05T00:00:00+00:00");
+    }
+
+    #[cfg(feature = "db-tests")]
+    async fn setup(vars: &[(&str, &str)]) -> (sqlx::PgPool, crate::config::Config, db::User) {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+        let mut vars: std::collections::HashMap<_, _> = vars.iter().copied().collect();
+        vars.extend([("DATABASE_URL", url.as_str()), ("JWT_SECRET", "quota-test-secret")]);
+        let config = crate::config::Config::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+        let user = db::User::create(&pool, &format!("{}@quota.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        (pool, config, user)
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_concurrent_reservations_never_exceed_the_daily_limit() {
+        let (pool, config, user) = setup(&[("FREE_TIER_IMAGE_DAILY", "5"), ("ORG_IMAGE_DAILY", "3")]).await;
+        let quotas = &config.quotas;
+        let owner = db::Owner::from(user.id);
+        let reserve_one = |owner| reserve(&pool, quotas, owner, "free", MediaKind::Image, 1);
+
+        let outcomes = futures_util::future::join_all((0..20).map(|_| reserve_one(owner))).await;
+        let granted = outcomes.into_iter().map(Result::unwrap).filter(Result::is_ok).count();
+        assert_eq!(granted, 5);
+        assert_eq!(db::QuotaUsage::today(&pool, user.id, "image").await.unwrap(), 5);
+        let refused = reserve_one(owner).await.unwrap().unwrap_err();
+        assert_eq!(refused, QuotaViolation::Daily { usage: Usage { used: 5, limit: Some(5) }, requested: 1 });
+
+        // Acting for an organization with a pooled limit, only its count is
+        // checked, though the member's own goes up too
+        let organization_id = db::Organization::create(&pool, "Quota race", user.id).await.unwrap().organization_id;
+        let member = db::Owner { user_id: user.id, organization_id: Some(organization_id) };
+        let outcomes = futures_util::future::join_all((0..10).map(|_| reserve_one(member))).await;
+        assert_eq!(outcomes.into_iter().map(Result::unwrap).filter(Result::is_ok).count(), 3);
+        assert_eq!(db::QuotaUsage::today(&pool, organization_id, "image").await.unwrap(), 3);
+        assert_eq!(db::QuotaUsage::today(&pool, user.id, "image").await.unwrap(), 8);
+
+        // More than the whole limit at once never gets a row in
+        let other = db::User::create(&pool, &format!("{}@quota.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let batch = reserve(&pool, quotas, other.id.into(), "free", MediaKind::Image, 6).await.unwrap();
+        assert!(batch.is_err());
+        assert_eq!(db::QuotaUsage::today(&pool, other.id, "image").await.unwrap(), 0);
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_failed_jobs_refund_their_quota_once_when_configured() {
+        let (pool, config, user) = setup(&[("QUOTA_REFUND_FAILED_JOBS", "true")]).await;
+        let owner = db::Owner::from(user.id);
+        let used = || async { db::QuotaUsage::today(&pool, user.id, "image").await.unwrap() };
+
+        reserve(&pool, &config.quotas, owner, "free", MediaKind::Image, 2).await.unwrap().unwrap();
+        let assets = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let job = db::Job::create(&pool, owner, assets, "convert", "image", serde_json::json!({}), 0, None)
+            .await
+            .unwrap();
+        assert_eq!(job.quota_day, Some(Utc::now().date_naive()));
+
+        // Only failed jobs, and only when configured
+        refund_failed(&pool, &config.quotas, job.id).await;
+        assert_eq!(used().await, 2);
+        db::Job::fail(&pool, job.id, "decode failed", "decode_failed").await.unwrap();
+        let kept = QuotaConfig { refund_failed_jobs: false, ..config.quotas.clone() };
+        refund_failed(&pool, &kept, job.id).await;
+        assert_eq!(used().await, 2);
+
+        refund_failed(&pool, &config.quotas, job.id).await;
+        refund_failed(&pool, &config.quotas, job.id).await;
+        assert_eq!(used().await, 0);
+
+        // A retry is counted again
+        let retried = db::Job::retry(&pool, job.id).await.unwrap().unwrap();
+        assert_eq!(retried.quota_day, Some(Utc::now().date_naive()));
+    }
 }
@@ -0,0 +1,199 @@
+// backend/src/services/readiness.rs
+// Readiness probe: checks each dependency concurrently and caches the report
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use super::Storage;
+
+/// Longest a single dependency may take before it counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a report is reused, so aggressive probes don't hammer the database
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CheckResult {
+    /// `ok` or `error`
+    pub status: &'static str,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Readiness {
+    /// `ready` when every check passed, otherwise `unavailable`
+    pub status: &'static str,
+    pub checked_at: DateTime<Utc>,
+    /// Keyed by dependency. Redis only appears when it is configured.
+    pub checks: BTreeMap<&'static str, CheckResult>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.status == "ready"
+    }
+
+    fn from_checks(checks: BTreeMap<&'static str, CheckResult>) -> Self {
+        let ready = checks.values().all(CheckResult::is_ok);
+        Self {
+            status: if ready { "ready" } else { "unavailable" },
+            checked_at: Utc::now(),
+            checks,
+        }
+    }
+}
+
+/// What the probe checks
+pub struct Dependencies<'a> {
+    pub db: &'a sqlx::PgPool,
+    pub redis: Option<ConnectionManager>,
+    pub storage: &'a dyn Storage,
+    pub temp_dir: &'a Path,
+}
+
+/// Runs the checks at most once per `CACHE_TTL`. Probes that arrive while a
+/// check is running wait for it and share its report.
+#[derive(Default)]
+pub struct ReadinessProbe {
+    cached: Mutex<Option<(Instant, Readiness)>>,
+}
+
+impl ReadinessProbe {
+    pub async fn check(&self, deps: Dependencies<'_>) -> Readiness {
+        let mut cached = self.cached.lock().await;
+        if let Some((at, report)) = cached.as_ref() {
+            if at.elapsed() < CACHE_TTL {
+                return report.clone();
+            }
+        }
+
+        let report = check_all(deps).await;
+        *cached = Some((Instant::now(), report.clone()));
+        report
+    }
+}
+
+async fn check_all(deps: Dependencies<'_>) -> Readiness {
+    let database = timed(async {
+        sqlx::query("SELECT 1").execute(deps.db).await.map(|_| ()).map_err(|e| e.to_string())
+    });
+    let redis = async {
+        match deps.redis {
+            Some(mut conn) => Some(
+                timed(async move {
+                    redis::cmd("PING")
+                        .query_async::<String>(&mut conn)
+                        .await
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .await,
+            ),
+            None => None,
+        }
+    };
+    let temp_dir = timed(async {
+        let probe = deps.temp_dir.join(format!(".ready_{}", uuid::Uuid::new_v4()));
+        tokio::fs::write(&probe, b"").await.map_err(|e| e.to_string())?;
+        tokio::fs::remove_file(&probe).await.map_err(|e| e.to_string())
+    });
+    let storage = timed(async { deps.storage.check().await.map_err(|e| e.to_string()) });
+
+    let (database, redis, temp_dir, storage) = tokio::join!(database, redis, temp_dir, storage);
+
+    let mut checks = BTreeMap::from([
+        ("database", database),
+        ("temp_dir", temp_dir),
+        ("storage", storage),
+    ]);
+    if let Some(redis) = redis {
+        checks.insert("redis", redis);
+    }
+    Readiness::from_checks(checks)
+}
+
+/// Run one check under `CHECK_TIMEOUT`, timing it
+async fn timed(check: impl Future<Output = Result<(), String>>) -> CheckResult {
+    let started = Instant::now();
+    let outcome = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(format!("timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    match outcome {
+        Ok(()) => CheckResult { status: "ok", latency_ms, error: None },
+        Err(error) => CheckResult { status: "error", latency_ms, error: Some(error) },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hung_check_times_out() {
+        let result = timed(std::future::pending()).await;
+        assert_eq!(result.status, "error");
+        assert_eq!(result.error.as_deref(), Some("timed out after 2s"));
+
+        let result = timed(async { Ok(()) }).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_checks_each_dependency_and_caches_the_report() {
+        // Nothing listens on port 1, so the database check fails fast
+        let db = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(500))
+            .connect_lazy("postgres://nobody@127.0.0.1:1/none")
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("readiness_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = super::super::LocalStorage::new(&dir);
+        let deps = || Dependencies { db: &db, redis: None, storage: &storage, temp_dir: &dir };
+
+        let probe = ReadinessProbe::default();
+        let first = probe.check(deps()).await;
+        assert!(!first.is_ready());
+        assert_eq!(first.checks["database"].status, "error");
+        assert!(first.checks["temp_dir"].is_ok());
+        assert!(first.checks["storage"].is_ok());
+        assert!(!first.checks.contains_key("redis"));
+        // Probe files are cleaned up
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let second = probe.check(deps()).await;
+        assert_eq!(second.checked_at, first.checked_at);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_one_failed_check_makes_the_report_unavailable() {
+        let ok = CheckResult { status: "ok", latency_ms: 1, error: None };
+        let report = Readiness::from_checks(BTreeMap::from([("database", ok.clone()), ("storage", ok.clone())]));
+        assert!(report.is_ready());
+
+        let down = CheckResult { status: "error", latency_ms: 2000, error: Some("timed out after 2s".to_string()) };
+        let report = Readiness::from_checks(BTreeMap::from([("database", down), ("storage", ok)]));
+        assert!(!report.is_ready());
+        assert_eq!(report.status, "unavailable");
+    }
+}
@@ -0,0 +1,172 @@
+// backend/src/services/relocation.rs
+// One-shot move of local files saved before the per-account layout
+// (`{user_id}/{yyyy}/{mm}/...`) into it, run from the admin API
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db;
+use super::storage::{LocalStorage, StorageError, StorageLocation};
+
+/// Rows listed per query
+const RELOCATE_BATCH: i64 = 500;
+
+/// What a relocation run did
+#[derive(Debug, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RelocationSummary {
+    /// Files moved and their rows updated
+    pub relocated: u64,
+    /// Rows whose file was already gone; left as they are
+    pub missing: u64,
+    /// Files that could not be moved or rows that could not be updated.
+    /// Running again retries them.
+    pub failed: u64,
+}
+
+/// Move every flat or legacy-path local file that a row refers to under its
+/// owner and the month it was stored, then point the rows at the new
+/// location. Each file is handled on its own, so a run that stops part way
+/// leaves everything readable and can simply be repeated. Requests reading a
+/// file while it moves may briefly see it as missing.
+pub async fn relocate_local_objects(
+    db_pool: &sqlx::PgPool,
+    storage: &LocalStorage,
+) -> Result<RelocationSummary, sqlx::Error> {
+    let mut summary = RelocationSummary::default();
+    let mut after = String::new();
+    loop {
+        let objects = db::StoredObject::list_after(db_pool, &after, RELOCATE_BATCH).await?;
+        let Some(last) = objects.last() else {
+            return Ok(summary);
+        };
+        after = last.location.clone();
+
+        for object in objects {
+            let Ok(from) = object.location.parse::<StorageLocation>() else {
+                tracing::warn!("Skipping unreadable storage location {}", object.location);
+                summary.failed += 1;
+                continue;
+            };
+            let Some(to) = LocalStorage::relocated(&from, object.user_id, object.created_at) else {
+                continue;
+            };
+            relocate(db_pool, storage, &from, &to, &mut summary).await;
+        }
+    }
+}
+
+async fn relocate(
+    db_pool: &sqlx::PgPool,
+    storage: &LocalStorage,
+    from: &StorageLocation,
+    to: &StorageLocation,
+    summary: &mut RelocationSummary,
+) {
+    match storage.rename(from, to).await {
+        Ok(()) => {}
+        Err(StorageError::NotFound(_)) => {
+            summary.missing += 1;
+            return;
+        }
+        Err(e) => {
+            tracing::warn!("Failed to move {} to {}: {:?}", from, to, e);
+            summary.failed += 1;
+            return;
+        }
+    }
+
+    match db::StoredObject::relocate(db_pool, &from.to_string(), &to.to_string()).await {
+        Ok(()) => summary.relocated += 1,
+        Err(e) => {
+            tracing::warn!("Failed to record the move of {} to {}: {:?}", from, to, e);
+            // Put the file back where the rows still point
+            if let Err(e) = storage.rename(to, from).await {
+                tracing::error!("Failed to move {} back to {}: {:?}", to, from, e);
+            }
+            summary.failed += 1;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use super::super::Storage;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_flat_and_legacy_files_move_under_their_owner() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let base = std::env::temp_dir().join(format!("relocation_storage_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&base).unwrap();
+        let storage = LocalStorage::new(&base);
+        let user = db::User::create(&pool, &format!("{}@relocation.test", uuid::Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+
+        // As saved before the layout: flat under a scheme, and a full path
+        let flat = format!("{}_a.png", uuid::Uuid::new_v4());
+        std::fs::write(base.join(&flat), b"upload").unwrap();
+        let asset = db::MediaAsset::create(&pool, user.id.into(), "a.png", "png", 6, None).await.unwrap();
+        db::MediaAsset::update_status(&pool, asset.id, "uploaded", Some(&format!("local://{}", flat)))
+            .await
+            .unwrap();
+        let legacy = base.join(format!("{}_grade.cube", uuid::Uuid::new_v4()));
+        std::fs::write(&legacy, b"LUT_3D_SIZE 2").unwrap();
+        let lut =
+            db::LutFile::create(&pool, user.id.into(), "grade.cube", &legacy.to_string_lossy(), 13).await.unwrap();
+        // Already laid out, and a row whose file is gone
+        let current = storage.save_bytes(b"current", user.id, "b.png").await.unwrap().to_string();
+        let current_asset = db::MediaAsset::create(&pool, user.id.into(), "b.png", "png", 7, None).await.unwrap();
+        db::MediaAsset::update_status(&pool, current_asset.id, "uploaded", Some(&current)).await.unwrap();
+        let gone = db::MediaAsset::create(&pool, user.id.into(), "c.png", "png", 1, None).await.unwrap();
+        db::MediaAsset::update_status(&pool, gone.id, "uploaded", Some("local://gone_c.png")).await.unwrap();
+
+        // Queued jobs using the upload as a background, directly or in a pipeline step
+        let flat_location = format!("local://{}", flat);
+        let remove_bg = json!({ "mode": "image", "background_location": flat_location });
+        let pipeline = json!({ "operations": [
+            { "type": "convert", "output_format": "png" },
+            { "type": "remove_bg", "mode": "image", "background_location": flat_location },
+        ] });
+        let mut jobs = Vec::new();
+        for (job_type, parameters) in [("remove_bg", remove_bg), ("pipeline", pipeline)] {
+            let job = db::Job::create(&pool, user.id.into(), vec![], job_type, "image", parameters, 0, None)
+                .await
+                .unwrap();
+            jobs.push(job.id);
+        }
+
+        let summary = relocate_local_objects(&pool, &storage).await.unwrap();
+        assert!(summary.relocated >= 2 && summary.missing >= 1, "{:?}", summary);
+
+        let month = asset.created_at.format("%Y/%m");
+        let moved = db::MediaAsset::find_by_id(&pool, asset.id).await.unwrap().unwrap().result_location.unwrap();
+        assert_eq!(moved, format!("local://{}/{}/{}", user.id, month, flat));
+        assert_eq!(&storage.load_bytes(&moved.parse().unwrap()).await.unwrap()[..], b"upload");
+        assert!(!base.join(&flat).exists());
+        let parameters = |id| {
+            let pool = pool.clone();
+            async move { db::Job::find_by_id(&pool, id).await.unwrap().unwrap().parameters }
+        };
+        assert_eq!(parameters(jobs[0]).await["background_location"], moved);
+        let pipeline = parameters(jobs[1]).await;
+        assert_eq!(pipeline["operations"][1]["background_location"], moved);
+        assert_eq!(pipeline["operations"][0], json!({ "type": "convert", "output_format": "png" }));
+        let lut = db::LutFile::find_for_user(&pool, lut.id, user.id.into()).await.unwrap().unwrap();
+        assert!(lut.location.starts_with(&format!("local://{}/", user.id)), "{}", lut.location);
+        assert!(!legacy.exists());
+        let unchanged = db::MediaAsset::find_by_id(&pool, current_asset.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.result_location.as_deref(), Some(current.as_str()));
+
+        // Nothing is left to move
+        let again = relocate_local_objects(&pool, &storage).await.unwrap();
+        assert_eq!(again.relocated, 0);
+
+        db::User::delete_account(&pool, user.id).await.unwrap();
+        std::fs::remove_dir_all(&base).ok();
+    }
+}
@@ -0,0 +1,211 @@
+// backend/src/services/resumable.rs
+// Part files of resumable uploads. Each upload's bytes so far are kept in
+// `<temp_dir>/resumable/<id>` on the API instance that received them, so
+// with several instances the upload routes need sticky sessions.
+
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// Subdirectory of the temp dir holding part files. The general temp sweep
+/// leaves it alone; part files live as long as their upload.
+pub const PART_DIR: &str = "resumable";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppendError {
+    #[error("the chunk goes past the declared size of {0} bytes")]
+    TooLong(u64),
+    #[error("bytes received earlier for this upload are missing")]
+    Missing,
+    #[error("the chunk could not be read: {0}")]
+    Body(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// What `PartFiles::inspect` read from a finished part file
+#[derive(Debug)]
+pub struct Inspected {
+    /// The first `header_len` bytes, for sniffing
+    pub header: Vec<u8>,
+    /// Hex SHA-256 of the whole file
+    pub sha256: String,
+}
+
+/// Part files under one temp dir, and which uploads a request is busy with
+pub struct PartFiles {
+    dir: PathBuf,
+    busy: Mutex<HashSet<Uuid>>,
+}
+
+/// An upload no other request may write to or complete; released on drop
+pub struct PartLock<'a> {
+    files: &'a PartFiles,
+    id: Uuid,
+}
+
+impl Drop for PartLock<'_> {
+    fn drop(&mut self) {
+        self.files.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl PartFiles {
+    pub fn new(temp_dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: temp_dir.as_ref().join(PART_DIR),
+            busy: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// Take the upload for this request. `None` while another request has it.
+    pub fn lock(&self, id: Uuid) -> Option<PartLock<'_>> {
+        let taken = self.busy.lock().unwrap().insert(id);
+        taken.then(|| PartLock { files: self, id })
+    }
+
+    /// Write `chunk` at `offset`, which is how many bytes the upload has
+    /// recorded. Anything in the file past `offset` is left from a chunk that
+    /// never finished and is overwritten. Returns the new length; on failure
+    /// the file is cut back to `offset`, so the chunk can be sent again.
+    pub async fn append<S, E>(
+        &self,
+        lock: &PartLock<'_>,
+        offset: u64,
+        max_len: u64,
+        chunk: S,
+    ) -> Result<u64, AppendError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path(lock.id))
+            .await?;
+        if file.metadata().await?.len() < offset {
+            return Err(AppendError::Missing);
+        }
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        match write_chunk(&mut file, offset, max_len, chunk).await {
+            Ok(len) => Ok(len),
+            Err(e) => {
+                if let Err(truncate) = file.set_len(offset).await {
+                    tracing::warn!("Failed to cut back part file of upload {}: {:?}", lock.id, truncate);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Read a part file through once
+    pub async fn inspect(&self, id: Uuid, header_len: usize) -> std::io::Result<Inspected> {
+        let mut file = tokio::fs::File::open(self.path(id)).await?;
+        let mut header = Vec::with_capacity(header_len);
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            let take = (header_len - header.len()).min(read);
+            header.extend_from_slice(&buffer[..take]);
+            hasher.update(&buffer[..read]);
+        }
+        Ok(Inspected {
+            header,
+            sha256: hex::encode(hasher.finalize()),
+        })
+    }
+
+    /// Delete the part file, if there is one
+    pub async fn remove(&self, id: Uuid) {
+        match tokio::fs::remove_file(self.path(id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove part file of upload {}: {:?}", id, e),
+        }
+    }
+}
+
+async fn write_chunk<S, E>(
+    file: &mut tokio::fs::File,
+    offset: u64,
+    max_len: u64,
+    mut chunk: S,
+) -> Result<u64, AppendError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let mut len = offset;
+    while let Some(bytes) = chunk.next().await {
+        let bytes = bytes.map_err(|e| AppendError::Body(e.to_string()))?;
+        len += bytes.len() as u64;
+        if len > max_len {
+            return Err(AppendError::TooLong(max_len));
+        }
+        file.write_all(&bytes).await?;
+    }
+    file.flush().await?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(parts: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, String>> + Unpin {
+        futures_util::stream::iter(parts.iter().map(|p| Ok(Bytes::from_static(p))).collect::<Vec<_>>())
+    }
+
+    #[tokio::test]
+    async fn test_append_resumes_and_discards_broken_chunks() {
+        let dir = std::env::temp_dir().join(format!("resumable_test_{}", Uuid::new_v4()));
+        let files = PartFiles::new(&dir);
+        let id = Uuid::new_v4();
+        let lock = files.lock(id).unwrap();
+        assert!(files.lock(id).is_none());
+
+        assert_eq!(files.append(&lock, 0, 10, chunk(&[b"abc", b"d"])).await.unwrap(), 4);
+
+        // A chunk that breaks off leaves nothing behind
+        let broken = futures_util::stream::iter(vec![Ok(Bytes::from_static(b"xy")), Err("reset".to_string())]);
+        assert!(matches!(files.append(&lock, 4, 10, broken).await, Err(AppendError::Body(_))));
+        assert!(matches!(
+            files.append(&lock, 4, 10, chunk(&[b"0123456789"])).await,
+            Err(AppendError::TooLong(10))
+        ));
+        assert_eq!(std::fs::read(files.path(id)).unwrap(), b"abcd");
+
+        assert_eq!(files.append(&lock, 4, 10, chunk(&[b"efgh"])).await.unwrap(), 8);
+        assert!(matches!(files.append(&lock, 9, 10, chunk(&[b"i"])).await, Err(AppendError::Missing)));
+
+        let inspected = files.inspect(id, 3).await.unwrap();
+        assert_eq!(inspected.header, b"abc");
+        assert_eq!(inspected.sha256, hex::encode(Sha256::digest(b"abcdefgh")));
+
+        drop(lock);
+        assert!(files.lock(id).is_some());
+        files.remove(id).await;
+        assert!(!files.path(id).exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
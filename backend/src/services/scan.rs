@@ -0,0 +1,218 @@
+// backend/src/services/scan.rs
+// Malware scanning of uploads: clamd over TCP when configured, nothing otherwise
+
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Bytes sent to clamd per INSTREAM chunk
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Longest clamd reply read; real ones are a line
+const MAX_REPLY_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// `signature` names what was found, e.g. `Eicar-Test-Signature`
+    Infected { signature: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScanError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Scan did not finish within {0:?}")]
+    Timeout(Duration),
+    #[error("Scanner error: {0}")]
+    Scanner(String),
+}
+
+/// Content to scan: bytes in hand, or a file on local disk
+#[derive(Debug, Clone, Copy)]
+pub enum ScanInput<'a> {
+    Bytes(&'a [u8]),
+    File(&'a Path),
+}
+
+#[axum::async_trait]
+pub trait Scanner: Send + Sync {
+    async fn scan(&self, input: ScanInput<'_>) -> Result<ScanVerdict, ScanError>;
+}
+
+/// Passes everything; used when no scanner is configured
+pub struct NoopScanner;
+
+#[axum::async_trait]
+impl Scanner for NoopScanner {
+    async fn scan(&self, _input: ScanInput<'_>) -> Result<ScanVerdict, ScanError> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+/// Streams content to clamd with its `INSTREAM` command, one connection per
+/// scan. Content larger than clamd's `StreamMaxLength` comes back as an error.
+pub struct ClamAvScanner {
+    /// `host:port`
+    address: String,
+    /// For the whole scan, connecting included
+    timeout: Duration,
+}
+
+impl ClamAvScanner {
+    pub fn new(address: impl Into<String>, timeout: Duration) -> Self {
+        Self { address: address.into(), timeout }
+    }
+
+    async fn scan_stream(&self, input: ScanInput<'_>) -> Result<ScanVerdict, ScanError> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        // The `z` prefix asks for NUL-terminated replies
+        stream.write_all(b"zINSTREAM\0").await?;
+        match input {
+            ScanInput::Bytes(bytes) => send_chunks(&mut stream, bytes).await?,
+            ScanInput::File(path) => send_chunks(&mut stream, tokio::fs::File::open(path).await?).await?,
+        }
+        // A zero-length chunk ends the stream
+        stream.write_all(&0u32.to_be_bytes()).await?;
+
+        let mut reply = Vec::new();
+        let mut limited = (&mut stream).take(MAX_REPLY_BYTES);
+        loop {
+            let mut byte = [0u8; 1];
+            if limited.read(&mut byte).await? == 0 || byte[0] == 0 {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        parse_reply(&String::from_utf8_lossy(&reply))
+    }
+}
+
+#[axum::async_trait]
+impl Scanner for ClamAvScanner {
+    async fn scan(&self, input: ScanInput<'_>) -> Result<ScanVerdict, ScanError> {
+        tokio::time::timeout(self.timeout, self.scan_stream(input))
+            .await
+            .map_err(|_| ScanError::Timeout(self.timeout))?
+    }
+}
+
+/// Send `content` as length-prefixed INSTREAM chunks
+async fn send_chunks(stream: &mut TcpStream, mut content: impl AsyncRead + Unpin) -> std::io::Result<()> {
+    let mut buf = vec![0u8; CHUNK_BYTES];
+    loop {
+        let read = content.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+        stream.write_all(&buf[..read]).await?;
+    }
+}
+
+/// clamd answers `stream: OK`, `stream: <signature> FOUND`, or a message
+/// ending in `ERROR`
+fn parse_reply(reply: &str) -> Result<ScanVerdict, ScanError> {
+    let reply = reply.trim();
+    let result = reply.strip_prefix("stream:").map(str::trim).unwrap_or(reply);
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected { signature: signature.trim().to_string() })
+    } else if reply.is_empty() {
+        Err(ScanError::Scanner("clamd closed the connection without a reply".to_string()))
+    } else {
+        Err(ScanError::Scanner(reply.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// The standard antivirus test file, harmless but flagged by every scanner
+    const EICAR: &[u8] =
+        br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    /// A clamd that reads one INSTREAM and flags it if it holds EICAR.
+    /// Returns the address to connect to.
+    async fn fake_clamd(reply_delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut command = [0u8; 10];
+                socket.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+                let mut content = Vec::new();
+                loop {
+                    let mut length = [0u8; 4];
+                    socket.read_exact(&mut length).await.unwrap();
+                    let length = u32::from_be_bytes(length) as usize;
+                    if length == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0u8; length];
+                    socket.read_exact(&mut chunk).await.unwrap();
+                    content.extend_from_slice(&chunk);
+                }
+                tokio::time::sleep(reply_delay).await;
+                let infected = content.windows(EICAR.len()).any(|w| w == EICAR);
+                let reply: &[u8] =
+                    if infected { b"stream: Eicar-Test-Signature FOUND\0" } else { b"stream: OK\0" };
+                let _ = socket.write_all(reply).await;
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_clamav_scanner_flags_eicar() {
+        let address = fake_clamd(Duration::ZERO).await;
+        let scanner = ClamAvScanner::new(address, Duration::from_secs(5));
+
+        let verdict = scanner.scan(ScanInput::Bytes(EICAR)).await.unwrap();
+        assert_eq!(verdict, ScanVerdict::Infected { signature: "Eicar-Test-Signature".to_string() });
+
+        // Files go in chunks; a match straddling two is still found
+        let path = std::env::temp_dir().join(format!("scan_test_{}", uuid::Uuid::new_v4()));
+        let mut content = vec![b'a'; CHUNK_BYTES - 10];
+        content.extend_from_slice(EICAR);
+        std::fs::write(&path, &content).unwrap();
+        let verdict = scanner.scan(ScanInput::File(&path)).await.unwrap();
+        assert!(matches!(verdict, ScanVerdict::Infected { .. }));
+
+        std::fs::write(&path, b"just a picture").unwrap();
+        assert_eq!(scanner.scan(ScanInput::File(&path)).await.unwrap(), ScanVerdict::Clean);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_clamav_scanner_times_out_and_reports_unreachable() {
+        let address = fake_clamd(Duration::from_secs(5)).await;
+        let scanner = ClamAvScanner::new(address, Duration::from_millis(100));
+        assert!(matches!(scanner.scan(ScanInput::Bytes(b"x")).await, Err(ScanError::Timeout(_))));
+
+        // Nothing listens on a port that was just released
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let scanner = ClamAvScanner::new(address, Duration::from_secs(5));
+        assert!(matches!(scanner.scan(ScanInput::Bytes(b"x")).await, Err(ScanError::Io(_))));
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply("stream: OK").unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND\n").unwrap(),
+            ScanVerdict::Infected { signature: "Win.Test.EICAR_HDB-1".to_string() }
+        );
+        let error = parse_reply("INSTREAM size limit exceeded. ERROR").unwrap_err();
+        assert_eq!(error.to_string(), "Scanner error: INSTREAM size limit exceeded. ERROR");
+        assert!(matches!(parse_reply(""), Err(ScanError::Scanner(_))));
+    }
+}
@@ -0,0 +1,123 @@
+// backend/src/services/signed_urls.rs
+// Download URLs handed out with job status, reused across polls until they
+// come close to expiring
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Expired URLs are swept from the cache once it grows past this
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A URL for a job's result that needs no credentials
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedUrl {
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+struct Entry {
+    /// The result the URL was signed for; a retry or a retention change
+    /// makes it stale
+    location: String,
+    result_expires_at: Option<DateTime<Utc>>,
+    signed: SignedUrl,
+}
+
+/// Signed URLs by job, so polling clients get the same URL back instead of
+/// a presign per request. A URL is handed out again while more than
+/// `renew_within` of it is left.
+pub struct SignedUrlCache {
+    renew_within: chrono::Duration,
+    entries: Mutex<HashMap<Uuid, Entry>>,
+}
+
+impl SignedUrlCache {
+    pub fn new(renew_within: Duration) -> Self {
+        Self {
+            renew_within: chrono::Duration::from_std(renew_within).unwrap_or(chrono::Duration::MAX),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The URL cached for the result of `job_id` at `location`, unless it is
+    /// about to expire
+    pub fn get(
+        &self,
+        job_id: Uuid,
+        location: &str,
+        result_expires_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<SignedUrl> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries
+            .get(&job_id)
+            .filter(|entry| entry.location == location && entry.result_expires_at == result_expires_at)?;
+        let expires_at = entry.signed.expires_at;
+        // A URL cut short by the result's own expiry can't be bettered, only used up
+        let renewable = result_expires_at != Some(expires_at);
+        let usable = if renewable { expires_at - now > self.renew_within } else { expires_at > now };
+        usable.then(|| entry.signed.clone())
+    }
+
+    pub fn insert(
+        &self,
+        job_id: Uuid,
+        location: String,
+        result_expires_at: Option<DateTime<Utc>>,
+        signed: SignedUrl,
+        now: DateTime<Utc>,
+    ) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= PRUNE_THRESHOLD {
+            entries.retain(|_, entry| entry.signed.expires_at > now);
+        }
+        entries.insert(job_id, Entry { location, result_expires_at, signed });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed(url: &str, expires_at: DateTime<Utc>) -> SignedUrl {
+        SignedUrl { url: url.to_string(), expires_at }
+    }
+
+    #[test]
+    fn test_urls_are_reused_until_close_to_expiry() {
+        let cache = SignedUrlCache::new(Duration::from_secs(15 * 60));
+        let job_id = Uuid::new_v4();
+        let now = Utc::now();
+        let hour = chrono::Duration::hours(1);
+        assert_eq!(cache.get(job_id, "s3://media/a.png", None, now), None);
+
+        cache.insert(job_id, "s3://media/a.png".to_string(), None, signed("first", now + hour), now);
+        let later = now + chrono::Duration::minutes(40);
+        assert_eq!(cache.get(job_id, "s3://media/a.png", None, later).unwrap().url, "first");
+        // Within the last quarter hour, and for another result, a new one is needed
+        assert_eq!(cache.get(job_id, "s3://media/a.png", None, later + chrono::Duration::minutes(10)), None);
+        assert_eq!(cache.get(job_id, "s3://media/b.png", None, later), None);
+        assert_eq!(cache.get(Uuid::new_v4(), "s3://media/a.png", None, later), None);
+    }
+
+    #[test]
+    fn test_urls_capped_by_the_result_last_until_it_expires() {
+        let cache = SignedUrlCache::new(Duration::from_secs(15 * 60));
+        let job_id = Uuid::new_v4();
+        let now = Utc::now();
+        let result_expires_at = Some(now + chrono::Duration::minutes(5));
+        let url = signed("capped", now + chrono::Duration::minutes(5));
+        cache.insert(job_id, "local://a.png".to_string(), result_expires_at, url, now);
+
+        let soon = now + chrono::Duration::minutes(4);
+        assert_eq!(cache.get(job_id, "local://a.png", result_expires_at, soon).unwrap().url, "capped");
+        assert_eq!(cache.get(job_id, "local://a.png", result_expires_at, soon + chrono::Duration::minutes(1)), None);
+        // Retention was extended, so a longer-lived URL can be signed
+        let extended = Some(now + chrono::Duration::days(3));
+        assert_eq!(cache.get(job_id, "local://a.png", extended, soon), None);
+    }
+}
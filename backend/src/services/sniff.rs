@@ -0,0 +1,221 @@
+// backend/src/services/sniff.rs
+// Magic-byte content sniffing for uploaded media
+
+/// Number of leading bytes needed to identify every supported format
+pub const SNIFF_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
+}
+
+impl MediaKind {
+    /// Value stored in `jobs.media_kind`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Video => "video",
+        }
+    }
+
+    /// The kind `as_str` stored
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "image" => Some(Self::Image),
+            "video" => Some(Self::Video),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SniffedType {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    Heic,
+    Mp4,
+    Mov,
+    Avi,
+    Webm,
+}
+
+impl SniffedType {
+    pub fn kind(self) -> MediaKind {
+        match self {
+            Self::Jpeg | Self::Png | Self::Webp | Self::Gif | Self::Heic => MediaKind::Image,
+            Self::Mp4 | Self::Mov | Self::Avi | Self::Webm => MediaKind::Video,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg => "JPEG",
+            Self::Png => "PNG",
+            Self::Webp => "WEBP",
+            Self::Gif => "GIF",
+            Self::Heic => "HEIC",
+            Self::Mp4 => "MP4",
+            Self::Mov => "MOV",
+            Self::Avi => "AVI",
+            Self::Webm => "WEBM",
+        }
+    }
+
+    /// Whether content of this type may legitimately carry the given extension
+    pub fn matches_extension(self, ext: &str) -> bool {
+        let ext = ext.to_lowercase();
+        match self {
+            Self::Jpeg => ext == "jpg" || ext == "jpeg",
+            Self::Png => ext == "png",
+            Self::Webp => ext == "webp",
+            Self::Gif => ext == "gif",
+            Self::Heic => ext == "heic" || ext == "heif",
+            Self::Mp4 => ext == "mp4" || ext == "mov",
+            Self::Mov => ext == "mov",
+            Self::Avi => ext == "avi",
+            Self::Webm => ext == "webm",
+        }
+    }
+}
+
+/// Identify a supported media type from the leading bytes of a file.
+pub fn sniff(header: &[u8]) -> Option<SniffedType> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some(SniffedType::Jpeg);
+    }
+    if header.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some(SniffedType::Png);
+    }
+    if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        return Some(SniffedType::Gif);
+    }
+    if header.len() >= 12 && &header[0..4] == b"RIFF" {
+        return match &header[8..12] {
+            b"WEBP" => Some(SniffedType::Webp),
+            b"AVI " => Some(SniffedType::Avi),
+            _ => None,
+        };
+    }
+    if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        // EBML container; only the WebM doctype is accepted (not generic Matroska)
+        return contains(header, b"webm").then_some(SniffedType::Webm);
+    }
+    if header.len() >= 12 {
+        return sniff_iso_bmff(header);
+    }
+
+    None
+}
+
+/// ISO base media files (MP4, MOV, HEIC) share the `ftyp` box, so the brand
+/// decides which one we are looking at.
+fn sniff_iso_bmff(header: &[u8]) -> Option<SniffedType> {
+    match &header[4..8] {
+        b"ftyp" => {
+            let major = &header[8..12];
+            if is_heic_brand(major) {
+                return Some(SniffedType::Heic);
+            }
+            if major == b"qt  " {
+                return Some(SniffedType::Mov);
+            }
+            if MP4_BRANDS.contains(&major) {
+                return Some(SniffedType::Mp4);
+            }
+            // Fall back to compatible brands listed after the minor version
+            let box_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            let end = box_len.min(header.len());
+            let compatible = header.get(16..end).unwrap_or(&[]);
+            if compatible.chunks_exact(4).any(is_heic_brand) {
+                Some(SniffedType::Heic)
+            } else if compatible.chunks_exact(4).any(|b| b == b"qt  ") {
+                Some(SniffedType::Mov)
+            } else if compatible.chunks_exact(4).any(|b| MP4_BRANDS.contains(&b)) {
+                Some(SniffedType::Mp4)
+            } else {
+                None
+            }
+        }
+        // Legacy QuickTime files may start straight with a movie/data atom
+        b"moov" | b"mdat" | b"wide" | b"free" | b"skip" | b"pnot" => Some(SniffedType::Mov),
+        _ => None,
+    }
+}
+
+const MP4_BRANDS: &[&[u8]] = &[
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"dash", b"mmp4",
+];
+
+fn is_heic_brand(brand: &[u8]) -> bool {
+    matches!(
+        brand,
+        b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1"
+    )
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(brand: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let len = 16 + compatible.len() * 4;
+        let mut v = (len as u32).to_be_bytes().to_vec();
+        v.extend_from_slice(b"ftyp");
+        v.extend_from_slice(brand);
+        v.extend_from_slice(&[0, 0, 0, 0]);
+        for c in compatible {
+            v.extend_from_slice(*c);
+        }
+        v
+    }
+
+    #[test]
+    fn test_sniff_image_signatures() {
+        assert_eq!(sniff(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0]), Some(SniffedType::Jpeg));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some(SniffedType::Png));
+        assert_eq!(sniff(b"GIF89a\x01\x00"), Some(SniffedType::Gif));
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some(SniffedType::Webp));
+    }
+
+    #[test]
+    fn test_sniff_video_signatures() {
+        assert_eq!(sniff(b"RIFF\x24\0\0\0AVI LIST"), Some(SniffedType::Avi));
+        assert_eq!(sniff(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm"), Some(SniffedType::Webm));
+        assert_eq!(sniff(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x88matroska"), None);
+        assert_eq!(sniff(&ftyp(b"isom", &[b"isom", b"avc1"])), Some(SniffedType::Mp4));
+        assert_eq!(sniff(&ftyp(b"qt  ", &[b"qt  "])), Some(SniffedType::Mov));
+    }
+
+    #[test]
+    fn test_heic_and_mov_distinguished_by_brand() {
+        assert_eq!(sniff(&ftyp(b"heic", &[b"mif1", b"heic"])), Some(SniffedType::Heic));
+        assert_eq!(sniff(&ftyp(b"mif1", &[b"heic"])), Some(SniffedType::Heic));
+        // Unknown major brand, HEIC only in the compatible list
+        assert_eq!(sniff(&ftyp(b"xxxx", &[b"heix"])), Some(SniffedType::Heic));
+        assert_eq!(sniff(&ftyp(b"xxxx", &[b"yyyy"])), None);
+    }
+
+    #[test]
+    fn test_rejects_unknown_content() {
+        assert_eq!(sniff(b"MZ\x90\x00\x03\x00\x00\x00"), None);
+        assert_eq!(sniff(b"#!/bin/sh\necho hi\n"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn test_matches_extension() {
+        assert!(SniffedType::Jpeg.matches_extension("JPEG"));
+        assert!(!SniffedType::Png.matches_extension("jpg"));
+        assert!(SniffedType::Mp4.matches_extension("mov"));
+        assert!(!SniffedType::Mov.matches_extension("mp4"));
+        assert_eq!(SniffedType::Heic.kind(), MediaKind::Image);
+        assert_eq!(SniffedType::Webm.kind(), MediaKind::Video);
+    }
+}
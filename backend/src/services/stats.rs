@@ -0,0 +1,201 @@
+// backend/src/services/stats.rs
+// Usage statistics over a period, of one user or of everyone, aggregated by
+// the database
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db;
+
+/// Jobs of one type submitted over the period
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct JobTypeStats {
+    pub job_type: String,
+    pub jobs: i64,
+    pub completed: i64,
+    pub failed: i64,
+    /// Total size of what the completed ones produced
+    pub output_bytes: i64,
+    /// Mean processing time of the completed ones
+    pub avg_duration_ms: Option<i64>,
+}
+
+/// One UTC day of the period
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DailyStats {
+    pub date: NaiveDate,
+    pub jobs: i64,
+    pub completed: i64,
+    pub failed: i64,
+    pub uploads: i64,
+    pub uploaded_bytes: i64,
+    pub output_bytes: i64,
+}
+
+/// Jobs and uploads over `[from, to)`. Jobs count on the day they were
+/// submitted, whatever became of them later; jobs still queued or processing
+/// count in `jobs` only.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct UsageStats {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub jobs: i64,
+    pub completed: i64,
+    pub failed: i64,
+    pub uploads: i64,
+    pub uploaded_bytes: i64,
+    /// Total size of what completed jobs produced, expired results included
+    pub output_bytes: i64,
+    /// Mean processing time of completed jobs
+    pub avg_duration_ms: Option<i64>,
+    /// Job types used in the period
+    pub by_type: Vec<JobTypeStats>,
+    /// Every UTC day the period touches, oldest first, days without activity
+    /// included
+    pub daily: Vec<DailyStats>,
+}
+
+/// One account's share of the period, see `GET /api/admin/stats`
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct UserStats {
+    pub user_id: Uuid,
+    pub email: String,
+    pub jobs: i64,
+    pub completed: i64,
+    pub failed: i64,
+    pub uploads: i64,
+    pub uploaded_bytes: i64,
+    pub output_bytes: i64,
+}
+
+impl From<db::UserUsage> for UserStats {
+    fn from(usage: db::UserUsage) -> Self {
+        Self {
+            user_id: usage.user_id,
+            email: usage.email,
+            jobs: usage.jobs,
+            completed: usage.completed,
+            failed: usage.failed,
+            uploads: usage.uploads,
+            uploaded_bytes: usage.uploaded_bytes,
+            output_bytes: usage.output_bytes,
+        }
+    }
+}
+
+/// Statistics of `user_id`, or of every user when unset, over `[from, to)`
+pub async fn usage_stats(
+    db_pool: &sqlx::PgPool,
+    user_id: Option<Uuid>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<UsageStats, sqlx::Error> {
+    let jobs = db::Job::usage(db_pool, user_id, from, to).await?;
+    let daily_jobs = db::Job::daily_usage(db_pool, user_id, from, to).await?;
+    let uploads = db::MediaAsset::upload_usage(db_pool, user_id, from, to).await?;
+    Ok(combine(from, to, jobs, daily_jobs, uploads))
+}
+
+/// Put the per-type and per-day rows together. Both daily series cover the
+/// same days in the same order; each set of rows ends with its total.
+fn combine(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    mut jobs: Vec<db::JobUsage>,
+    daily_jobs: Vec<db::DailyJobUsage>,
+    mut uploads: Vec<db::UploadUsage>,
+) -> UsageStats {
+    let job_total = jobs.pop().filter(|total| total.job_type.is_none());
+    let upload_total = uploads.pop().filter(|total| total.day.is_none());
+
+    let daily = daily_jobs
+        .into_iter()
+        .zip(uploads)
+        .map(|(day_jobs, day_uploads)| DailyStats {
+            date: day_jobs.day,
+            jobs: day_jobs.jobs,
+            completed: day_jobs.completed,
+            failed: day_jobs.failed,
+            uploads: day_uploads.uploads,
+            uploaded_bytes: day_uploads.uploaded_bytes,
+            output_bytes: day_jobs.output_bytes,
+        })
+        .collect();
+    let by_type = jobs
+        .into_iter()
+        .filter_map(|usage| {
+            Some(JobTypeStats {
+                job_type: usage.job_type?,
+                jobs: usage.jobs,
+                completed: usage.completed,
+                failed: usage.failed,
+                output_bytes: usage.output_bytes,
+                avg_duration_ms: usage.avg_duration_ms,
+            })
+        })
+        .collect();
+
+    UsageStats {
+        from,
+        to,
+        jobs: job_total.as_ref().map_or(0, |t| t.jobs),
+        completed: job_total.as_ref().map_or(0, |t| t.completed),
+        failed: job_total.as_ref().map_or(0, |t| t.failed),
+        uploads: upload_total.as_ref().map_or(0, |t| t.uploads),
+        uploaded_bytes: upload_total.as_ref().map_or(0, |t| t.uploaded_bytes),
+        output_bytes: job_total.as_ref().map_or(0, |t| t.output_bytes),
+        avg_duration_ms: job_total.and_then(|t| t.avg_duration_ms),
+        by_type,
+        daily,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 3, d).unwrap()
+    }
+
+    #[test]
+    fn test_combine_splits_totals_from_types_and_days() {
+        let from = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2026, 3, 2, 12, 0, 0).unwrap();
+        let usage = |job_type: Option<&str>, jobs, completed, output_bytes, avg_duration_ms| db::JobUsage {
+            job_type: job_type.map(str::to_string),
+            jobs,
+            completed,
+            failed: jobs - completed,
+            output_bytes,
+            avg_duration_ms,
+        };
+        let jobs = vec![
+            usage(Some("convert"), 3, 2, 200, Some(40)),
+            usage(Some("remove_bg"), 1, 0, 0, None),
+            usage(None, 4, 2, 200, Some(40)),
+        ];
+        let daily_jobs = vec![
+            db::DailyJobUsage { day: day(1), jobs: 0, completed: 0, failed: 0, output_bytes: 0 },
+            db::DailyJobUsage { day: day(2), jobs: 4, completed: 2, failed: 2, output_bytes: 200 },
+        ];
+        let uploads = vec![
+            db::UploadUsage { day: Some(day(1)), uploads: 2, uploaded_bytes: 70 },
+            db::UploadUsage { day: Some(day(2)), uploads: 0, uploaded_bytes: 0 },
+            db::UploadUsage { day: None, uploads: 2, uploaded_bytes: 70 },
+        ];
+
+        let stats = combine(from, to, jobs, daily_jobs, uploads);
+        assert_eq!((stats.jobs, stats.completed, stats.failed), (4, 2, 2));
+        assert_eq!((stats.uploads, stats.uploaded_bytes, stats.output_bytes), (2, 70, 200));
+        assert_eq!(stats.avg_duration_ms, Some(40));
+        let types: Vec<_> = stats.by_type.iter().map(|t| t.job_type.as_str()).collect();
+        assert_eq!(types, ["convert", "remove_bg"]);
+        assert_eq!(stats.daily.len(), 2);
+        assert_eq!((stats.daily[0].date, stats.daily[0].jobs, stats.daily[0].uploads), (day(1), 0, 2));
+        assert_eq!((stats.daily[1].date, stats.daily[1].output_bytes), (day(2), 200));
+    }
+}
@@ -1,15 +1,183 @@
-use std::path::{PathBuf};
-use std::fs::File;
-use std::io::Write;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, TryStreamExt};
+use s3::{creds::Credentials, Bucket, Region};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use uuid::Uuid;
 
-#[derive(Debug)]
+use super::byte_range::ByteRange;
+
+#[derive(Debug, thiserror::Error)]
 pub enum StorageError {
-    Io(std::io::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("S3 error: {0}")]
+    S3(#[from] s3::error::S3Error),
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Object not found: {0}")]
+    NotFound(String),
+    #[error("Location is outside storage: {0}")]
+    OutsideStorage(String),
+    #[error("Invalid storage location: {0}")]
+    InvalidLocation(String),
+    #[error("No storage backend is configured for {0}")]
+    NoBackend(String),
+}
+
+/// Where an object is stored, written to the database in its string form:
+/// `local://<path under the storage base>`, `s3://<bucket>/<key>` or
+/// `memory://<name>`. Rows from before schemes existed hold the file's full
+/// path with no scheme; those parse as `LegacyPath` and are read through
+/// local storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageLocation {
+    Local(String),
+    S3 { bucket: String, key: String },
+    Memory(String),
+    LegacyPath(String),
+}
+
+impl StorageLocation {
+    /// Parse a stored location. Only an unknown scheme or a location with
+    /// parts missing fails; anything without a scheme is a legacy path.
+    pub fn parse(location: &str) -> Result<Self, StorageError> {
+        let invalid = || StorageError::InvalidLocation(location.to_string());
+        let Some((scheme, rest)) = location.split_once("://") else {
+            return match location {
+                "" => Err(invalid()),
+                path => Ok(Self::LegacyPath(path.to_string())),
+            };
+        };
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        match scheme {
+            "local" => Ok(Self::Local(rest.to_string())),
+            "memory" => Ok(Self::Memory(rest.to_string())),
+            "s3" => match rest.split_once('/') {
+                Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+                    Ok(Self::S3 { bucket: bucket.to_string(), key: key.to_string() })
+                }
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The last path segment, which ends in the stored file name
+    pub fn file_name(&self) -> &str {
+        let path = match self {
+            Self::Local(path) | Self::Memory(path) | Self::LegacyPath(path) => path,
+            Self::S3 { key, .. } => key,
+        };
+        path.rsplit(['/', '\\']).next().unwrap_or(path)
+    }
+}
+
+impl std::fmt::Display for StorageLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Local(path) => write!(f, "local://{}", path),
+            Self::S3 { bucket, key } => write!(f, "s3://{}/{}", bucket, key),
+            Self::Memory(name) => write!(f, "memory://{}", name),
+            Self::LegacyPath(path) => f.write_str(path),
+        }
+    }
 }
 
+impl std::str::FromStr for StorageLocation {
+    type Err = StorageError;
+
+    fn from_str(location: &str) -> Result<Self, Self::Err> {
+        Self::parse(location)
+    }
+}
+
+/// Longest stored file name suffix, in bytes. With the UUID prefix this stays
+/// well under the 255 byte limit of common filesystems.
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// Reduce a client-supplied file name to one safe path component: directories
+/// are dropped, anything but letters, digits, `.`, `-` and `_` becomes `_`,
+/// and leading dots are removed so the result is never `..` or hidden. Long
+/// names keep their end, and with it the extension.
+pub fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    let cleaned = cleaned.trim_start_matches('.');
+
+    let mut start = cleaned.len().saturating_sub(MAX_FILENAME_BYTES);
+    while !cleaned.is_char_boundary(start) {
+        start += 1;
+    }
+    match &cleaned[start..] {
+        "" => "file".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Where a new object for `owner` goes: `{owner}/{yyyy}/{mm}/{uuid}_{name}`.
+/// One account's files can be found without the database, and no directory
+/// collects more than a month of one account's files.
+fn object_name(owner: Uuid, filename_hint: &str) -> String {
+    layout_name(owner, Utc::now(), &format!("{}_{}", Uuid::new_v4(), sanitize_filename(filename_hint)))
+}
+
+fn layout_name(owner: Uuid, stored_at: DateTime<Utc>, file_name: &str) -> String {
+    format!("{}/{}/{}", owner, stored_at.format("%Y/%m"), file_name)
+}
+
+/// Object contents, read as they are sent rather than buffered up front
+pub type ByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+/// An object store. Each backend serves the location schemes it writes and
+/// answers `NoBackend` for the rest; `StorageRouter` combines them.
+#[axum::async_trait]
 pub trait Storage: Send + Sync {
-    fn save_bytes(&self, bytes: &[u8], filename_hint: &str) -> Result<String, StorageError>;
+    /// Store a new object belonging to the account `owner`
+    async fn save_bytes(&self, bytes: &[u8], owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError>;
+
+    /// Move a file that is already on local disk into storage. The source file is
+    /// consumed: it no longer exists once this returns successfully.
+    async fn save_file(&self, path: &Path, owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError>;
+
+    /// Read back an object previously returned by `save_bytes`.
+    async fn load_bytes(&self, location: &StorageLocation) -> Result<Bytes, StorageError>;
+
+    /// Size of an object in bytes
+    async fn size(&self, location: &StorageLocation) -> Result<u64, StorageError>;
+
+    /// Stream an object, or just `range` of it, for responses too large to
+    /// hold in memory. The range must lie within the object.
+    async fn open_stream(
+        &self,
+        location: &StorageLocation,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream, StorageError>;
+
+    /// A URL clients can fetch the object from directly until `expires_in`
+    /// passes. `None` when the backend cannot serve objects itself.
+    async fn presigned_url(
+        &self,
+        _location: &StorageLocation,
+        _expires_in: Duration,
+    ) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+
+    /// Remove an object. Deleting something that is already gone is not an error.
+    async fn delete(&self, location: &StorageLocation) -> Result<(), StorageError>;
+
+    /// Confirm the backend is reachable and usable, for readiness probes
+    async fn check(&self) -> Result<(), StorageError>;
 }
 
 pub struct LocalStorage {
@@ -20,36 +188,699 @@ impl LocalStorage {
     pub fn new<P: Into<PathBuf>>(base: P) -> Self {
         Self { base_path: base.into() }
     }
+
+    /// The file behind `location`, refused unless it lies inside `base_path`.
+    /// Locations come from the database, so a crafted one such as
+    /// `../../etc/passwd` must never reach the filesystem. `local://` paths
+    /// are relative to the base; legacy ones are full paths.
+    async fn resolve(&self, location: &StorageLocation) -> Result<PathBuf, StorageError> {
+        let outside = || StorageError::OutsideStorage(location.to_string());
+        let base = std::path::absolute(&self.base_path)?;
+        let path = match location {
+            StorageLocation::Local(relative) => {
+                let relative = Path::new(relative);
+                if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+                    return Err(outside());
+                }
+                base.join(relative)
+            }
+            StorageLocation::LegacyPath(path) => {
+                let path = Path::new(path);
+                if path.components().any(|c| c == Component::ParentDir) {
+                    return Err(outside());
+                }
+                std::path::absolute(path)?
+            }
+            other => return Err(StorageError::NoBackend(other.to_string())),
+        };
+        if path == base || !path.starts_with(&base) {
+            return Err(outside());
+        }
+
+        // A symlink inside the base could still point out of it. Missing
+        // files are left for the caller to report as not found.
+        match (tokio::fs::canonicalize(&path).await, tokio::fs::canonicalize(&base).await) {
+            (Ok(real), Ok(real_base)) if !real.starts_with(&real_base) => Err(outside()),
+            _ => Ok(path),
+        }
+    }
+
+    /// Where an object saved before the per-account layout belongs in it:
+    /// under `owner` and the month it was stored, keeping its file name.
+    /// `None` for objects already laid out, or not in local storage.
+    pub fn relocated(location: &StorageLocation, owner: Uuid, stored_at: DateTime<Utc>) -> Option<StorageLocation> {
+        match location {
+            StorageLocation::Local(name) if !name.contains('/') => {}
+            StorageLocation::LegacyPath(_) => {}
+            _ => return None,
+        }
+        Some(StorageLocation::Local(layout_name(owner, stored_at, location.file_name())))
+    }
+
+    /// Move an object to another location in this storage
+    pub async fn rename(&self, from: &StorageLocation, to: &StorageLocation) -> Result<(), StorageError> {
+        let source = self.resolve(from).await?;
+        let dest = self.resolve(to).await?;
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        match tokio::fs::rename(&source, &dest).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(StorageError::NotFound(from.to_string())),
+            result => Ok(result?),
+        }
+    }
 }
 
+#[axum::async_trait]
 impl Storage for LocalStorage {
-    fn save_bytes(&self, bytes: &[u8], filename_hint: &str) -> Result<String, StorageError> {
-        let id = Uuid::new_v4().to_string();
-        let filename = format!("{}_{}", id, filename_hint);
-        let mut path = self.base_path.clone();
-        std::fs::create_dir_all(&path).map_err(StorageError::Io)?;
-        path.push(filename);
-        let mut f = File::create(&path).map_err(StorageError::Io)?;
-        f.write_all(bytes).map_err(StorageError::Io)?;
-        Ok(path.to_string_lossy().to_string())
+    async fn save_bytes(&self, bytes: &[u8], owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        let name = object_name(owner, filename_hint);
+        let dest = self.base_path.join(&name);
+        tokio::fs::create_dir_all(dest.parent().unwrap_or(&self.base_path)).await?;
+        tokio::fs::write(&dest, bytes).await?;
+        Ok(StorageLocation::Local(name))
+    }
+
+    async fn save_file(&self, path: &Path, owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        let name = object_name(owner, filename_hint);
+        let dest = self.base_path.join(&name);
+        tokio::fs::create_dir_all(dest.parent().unwrap_or(&self.base_path)).await?;
+
+        // Rename is free on the same filesystem; fall back to copy when temp_dir
+        // lives on a different mount than the storage base.
+        if tokio::fs::rename(path, &dest).await.is_err() {
+            tokio::fs::copy(path, &dest).await?;
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(StorageLocation::Local(name))
+    }
+
+    async fn load_bytes(&self, location: &StorageLocation) -> Result<Bytes, StorageError> {
+        match tokio::fs::read(self.resolve(location).await?).await {
+            Ok(data) => Ok(Bytes::from(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(StorageError::NotFound(location.to_string()))
+            }
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    async fn size(&self, location: &StorageLocation) -> Result<u64, StorageError> {
+        match tokio::fs::metadata(self.resolve(location).await?).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(StorageError::NotFound(location.to_string()))
+            }
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    async fn open_stream(
+        &self,
+        location: &StorageLocation,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream, StorageError> {
+        let mut file = match tokio::fs::File::open(self.resolve(location).await?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(StorageError::NotFound(location.to_string()))
+            }
+            Err(e) => return Err(StorageError::Io(e)),
+        };
+
+        match range {
+            Some(range) => {
+                file.seek(std::io::SeekFrom::Start(range.start)).await?;
+                Ok(Box::pin(tokio_util::io::ReaderStream::new(file.take(range.size()))))
+            }
+            None => Ok(Box::pin(tokio_util::io::ReaderStream::new(file))),
+        }
+    }
+
+    async fn delete(&self, location: &StorageLocation) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.resolve(location).await?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    /// The base directory accepts a write
+    async fn check(&self) -> Result<(), StorageError> {
+        let probe = self.base_path.join(format!(".ready_{}", Uuid::new_v4()));
+        tokio::fs::write(&probe, b"").await?;
+        tokio::fs::remove_file(&probe).await?;
+        Ok(())
     }
 }
 
-// Placeholder for S3/MinIO implementation
+/// S3 / MinIO backed storage. Objects are addressed path-style so that
+/// self-hosted endpoints without wildcard DNS work out of the box.
 pub struct S3Storage {
-    pub bucket: String,
-    pub endpoint: String,
+    bucket: Box<Bucket>,
+    /// Fetches ranged reads through presigned URLs, which rust-s3 can only
+    /// return fully buffered
+    http: reqwest::Client,
 }
 
 impl S3Storage {
-    pub fn new(bucket: &str, endpoint: &str) -> Self {
-        Self { bucket: bucket.to_string(), endpoint: endpoint.to_string() }
+    pub fn new(
+        bucket: &str,
+        endpoint: &str,
+        region: &str,
+        access_key: Option<&str>,
+        secret_key: Option<&str>,
+    ) -> Result<Self, StorageError> {
+        let region = Region::Custom {
+            region: region.to_string(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        };
+        let credentials = Credentials {
+            access_key: access_key.map(str::to_string),
+            secret_key: secret_key.map(str::to_string),
+            security_token: None,
+            session_token: None,
+            expiration: None,
+        };
+        let bucket = Bucket::new(bucket, region, credentials)?.with_path_style();
+
+        Ok(Self {
+            bucket,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    fn location_for(&self, key: String) -> StorageLocation {
+        StorageLocation::S3 { bucket: self.bucket.name(), key }
+    }
+
+    /// The object key of a location in this bucket
+    fn key_for<'a>(&self, location: &'a StorageLocation) -> Result<&'a str, StorageError> {
+        match location {
+            StorageLocation::S3 { bucket, key } if *bucket == self.bucket.name() => Ok(key),
+            other => Err(StorageError::NoBackend(other.to_string())),
+        }
     }
 }
 
+#[axum::async_trait]
 impl Storage for S3Storage {
-    fn save_bytes(&self, _bytes: &[u8], _filename_hint: &str) -> Result<String, StorageError> {
-        // Not implemented in MVP scaffolding; integrate rusoto/s3 or aws-sdk-s3 later.
-        Err(StorageError::Io(std::io::Error::new(std::io::ErrorKind::Other, "S3 storage not implemented")))
+    async fn save_bytes(&self, bytes: &[u8], owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        let key = object_name(owner, filename_hint);
+        // `fail-on-err` turns non-2xx responses into S3Error::HttpFailWithBody
+        self.bucket.put_object(&key, bytes).await?;
+
+        let location = self.location_for(key);
+        tracing::debug!("Uploaded {} bytes to {}", bytes.len(), location);
+        Ok(location)
+    }
+
+    async fn save_file(&self, path: &Path, owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        let key = object_name(owner, filename_hint);
+        let mut file = tokio::fs::File::open(path).await?;
+        // Streams the file in parts (multipart upload for large objects)
+        self.bucket.put_object_stream(&mut file, &key).await?;
+        tokio::fs::remove_file(path).await?;
+
+        let location = self.location_for(key);
+        tracing::debug!("Uploaded {} to {}", path.display(), location);
+        Ok(location)
+    }
+
+    async fn load_bytes(&self, location: &StorageLocation) -> Result<Bytes, StorageError> {
+        match self.bucket.get_object(self.key_for(location)?).await {
+            Ok(response) => Ok(response.into_bytes()),
+            Err(s3::error::S3Error::HttpFailWithBody(404, _)) => {
+                Err(StorageError::NotFound(location.to_string()))
+            }
+            Err(e) => Err(StorageError::S3(e)),
+        }
+    }
+
+    async fn size(&self, location: &StorageLocation) -> Result<u64, StorageError> {
+        match self.bucket.head_object(self.key_for(location)?).await {
+            Ok((head, _)) => Ok(head.content_length.unwrap_or(0).max(0) as u64),
+            Err(s3::error::S3Error::HttpFailWithBody(404, _)) => {
+                Err(StorageError::NotFound(location.to_string()))
+            }
+            Err(e) => Err(StorageError::S3(e)),
+        }
+    }
+
+    async fn open_stream(
+        &self,
+        location: &StorageLocation,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream, StorageError> {
+        let key = self.key_for(location)?;
+        let Some(range) = range else {
+            return match self.bucket.get_object_stream(key).await {
+                Ok(response) => Ok(Box::pin(response.bytes.map_err(std::io::Error::other))),
+                Err(s3::error::S3Error::HttpFailWithBody(404, _)) => {
+                    Err(StorageError::NotFound(location.to_string()))
+                }
+                Err(e) => Err(StorageError::S3(e)),
+            };
+        };
+
+        let url = self.bucket.presign_get(key, 60, None).await?;
+        let response = self
+            .http
+            .get(url)
+            .header(reqwest::header::RANGE, format!("bytes={}-{}", range.start, range.end))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(StorageError::NotFound(location.to_string()));
+        }
+        let response = response.error_for_status()?;
+        Ok(Box::pin(response.bytes_stream().map_err(std::io::Error::other)))
+    }
+
+    async fn presigned_url(
+        &self,
+        location: &StorageLocation,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StorageError> {
+        // S3 refuses presigned URLs valid for longer than seven days
+        let expires_in = expires_in.as_secs().min(crate::config::MAX_DOWNLOAD_URL_TTL_SECONDS);
+        let expires_in = u32::try_from(expires_in).unwrap_or(u32::MAX);
+        let url = self.bucket.presign_get(self.key_for(location)?, expires_in, None).await?;
+        Ok(Some(url))
+    }
+
+    async fn delete(&self, location: &StorageLocation) -> Result<(), StorageError> {
+        // S3 DELETE is idempotent and returns 204 for missing keys as well
+        self.bucket.delete_object(self.key_for(location)?).await?;
+        Ok(())
+    }
+
+    /// The bucket exists and the credentials can read it. A one-key listing
+    /// stands in for HEAD bucket, which rust-s3 doesn't expose.
+    async fn check(&self) -> Result<(), StorageError> {
+        self.bucket.list_page(String::new(), None, None, None, Some(1)).await?;
+        Ok(())
+    }
+}
+
+/// Objects kept in process memory under `memory://` locations. Nothing
+/// survives a restart, so this is for tests and throwaway setups.
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: std::sync::Mutex<HashMap<String, Bytes>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn name<'a>(&self, location: &'a StorageLocation) -> Result<&'a str, StorageError> {
+        match location {
+            StorageLocation::Memory(name) => Ok(name),
+            other => Err(StorageError::NoBackend(other.to_string())),
+        }
+    }
+
+    fn get(&self, location: &StorageLocation) -> Result<Bytes, StorageError> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(self.name(location)?)
+            .cloned()
+            .ok_or_else(|| StorageError::NotFound(location.to_string()))
+    }
+}
+
+#[axum::async_trait]
+impl Storage for MemoryStorage {
+    async fn save_bytes(&self, bytes: &[u8], owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        let name = object_name(owner, filename_hint);
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(name.clone(), Bytes::copy_from_slice(bytes));
+        Ok(StorageLocation::Memory(name))
+    }
+
+    async fn save_file(&self, path: &Path, owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        let bytes = tokio::fs::read(path).await?;
+        let location = self.save_bytes(&bytes, owner, filename_hint).await?;
+        tokio::fs::remove_file(path).await?;
+        Ok(location)
+    }
+
+    async fn load_bytes(&self, location: &StorageLocation) -> Result<Bytes, StorageError> {
+        self.get(location)
+    }
+
+    async fn size(&self, location: &StorageLocation) -> Result<u64, StorageError> {
+        Ok(self.get(location)?.len() as u64)
+    }
+
+    async fn open_stream(
+        &self,
+        location: &StorageLocation,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream, StorageError> {
+        let mut bytes = self.get(location)?;
+        if let Some(range) = range {
+            bytes = bytes.slice(range.start as usize..=range.end as usize);
+        }
+        Ok(Box::pin(futures_util::stream::once(async move { Ok(bytes) })))
+    }
+
+    async fn delete(&self, location: &StorageLocation) -> Result<(), StorageError> {
+        self.objects.lock().unwrap().remove(self.name(location)?);
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// Local and S3 storage side by side: new objects go to the primary backend
+/// and every location is served by the backend its scheme names, so objects
+/// written before a switch of `STORAGE_MODE` stay readable while they are
+/// moved or expire.
+pub struct StorageRouter {
+    local: Option<LocalStorage>,
+    s3: Option<S3Storage>,
+    primary: Primary,
+}
+
+#[derive(Clone, Copy)]
+enum Primary {
+    Local,
+    S3,
+}
+
+impl StorageRouter {
+    /// Write locally, reading S3 locations through `s3` when given
+    pub fn local(local: LocalStorage, s3: Option<S3Storage>) -> Self {
+        Self { local: Some(local), s3, primary: Primary::Local }
+    }
+
+    /// Write to S3, reading local and legacy locations through `local` when given
+    pub fn s3(s3: S3Storage, local: Option<LocalStorage>) -> Self {
+        Self { local, s3: Some(s3), primary: Primary::S3 }
+    }
+
+    fn primary(&self) -> &dyn Storage {
+        match self.primary {
+            Primary::Local => self.local.as_ref().expect("the primary backend is configured"),
+            Primary::S3 => self.s3.as_ref().expect("the primary backend is configured"),
+        }
+    }
+
+    fn backend(&self, location: &StorageLocation) -> Result<&dyn Storage, StorageError> {
+        let backend: Option<&dyn Storage> = match location {
+            StorageLocation::Local(_) | StorageLocation::LegacyPath(_) => {
+                self.local.as_ref().map(|b| b as &dyn Storage)
+            }
+            StorageLocation::S3 { .. } => self.s3.as_ref().map(|b| b as &dyn Storage),
+            StorageLocation::Memory(_) => None,
+        };
+        backend.ok_or_else(|| StorageError::NoBackend(location.to_string()))
+    }
+}
+
+// Every call a request or job makes to storage is a span of its own
+#[axum::async_trait]
+impl Storage for StorageRouter {
+    #[tracing::instrument(name = "storage.save", skip_all, fields(size = bytes.len()))]
+    async fn save_bytes(&self, bytes: &[u8], owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        self.primary().save_bytes(bytes, owner, filename_hint).await
+    }
+
+    #[tracing::instrument(name = "storage.save", skip_all)]
+    async fn save_file(&self, path: &Path, owner: Uuid, filename_hint: &str) -> Result<StorageLocation, StorageError> {
+        self.primary().save_file(path, owner, filename_hint).await
+    }
+
+    #[tracing::instrument(name = "storage.load", skip_all, fields(location = %location))]
+    async fn load_bytes(&self, location: &StorageLocation) -> Result<Bytes, StorageError> {
+        self.backend(location)?.load_bytes(location).await
+    }
+
+    #[tracing::instrument(name = "storage.size", skip_all, fields(location = %location))]
+    async fn size(&self, location: &StorageLocation) -> Result<u64, StorageError> {
+        self.backend(location)?.size(location).await
+    }
+
+    #[tracing::instrument(name = "storage.open_stream", skip_all, fields(location = %location))]
+    async fn open_stream(
+        &self,
+        location: &StorageLocation,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream, StorageError> {
+        self.backend(location)?.open_stream(location, range).await
+    }
+
+    #[tracing::instrument(name = "storage.presign", skip_all, fields(location = %location))]
+    async fn presigned_url(
+        &self,
+        location: &StorageLocation,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StorageError> {
+        self.backend(location)?.presigned_url(location, expires_in).await
+    }
+
+    #[tracing::instrument(name = "storage.delete", skip_all, fields(location = %location))]
+    async fn delete(&self, location: &StorageLocation) -> Result<(), StorageError> {
+        self.backend(location)?.delete(location).await
+    }
+
+    /// Only the primary backend: the other one holds older objects at most,
+    /// and losing it shouldn't take the service out of rotation
+    #[tracing::instrument(name = "storage.check", skip_all)]
+    async fn check(&self) -> Result<(), StorageError> {
+        self.primary().check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_storage_round_trip() {
+        let base = std::env::temp_dir().join(format!("mf_storage_{}", Uuid::new_v4()));
+        let storage = LocalStorage::new(&base);
+
+        let owner = Uuid::new_v4();
+        let location = storage.save_bytes(b"hello", owner, "hello.txt").await.unwrap();
+        let data = storage.load_bytes(&location).await.unwrap();
+        assert_eq!(&data[..], b"hello");
+
+        // Laid out under the owner and the month
+        let StorageLocation::Local(name) = &location else { panic!("{:?}", location) };
+        let prefix = format!("{}/{}/", owner, Utc::now().format("%Y/%m"));
+        assert!(name.starts_with(&prefix) && name.ends_with("_hello.txt"), "{}", name);
+        assert!(base.join(name).is_file());
+
+        let missing = storage.load_bytes(&StorageLocation::Local("missing.txt".to_string())).await;
+        assert!(matches!(missing, Err(StorageError::NotFound(_))));
+
+        assert_eq!(storage.size(&location).await.unwrap(), 5);
+        let stream = storage.open_stream(&location, None).await.unwrap();
+        let streamed: Vec<Bytes> = stream.try_collect().await.unwrap();
+        assert_eq!(streamed.concat(), b"hello");
+        let window = ByteRange { start: 1, end: 3 };
+        let stream = storage.open_stream(&location, Some(window)).await.unwrap();
+        let streamed: Vec<Bytes> = stream.try_collect().await.unwrap();
+        assert_eq!(streamed.concat(), b"ell");
+
+        storage.delete(&location).await.unwrap();
+        assert!(matches!(storage.load_bytes(&location).await, Err(StorageError::NotFound(_))));
+        assert!(matches!(storage.open_stream(&location, None).await, Err(StorageError::NotFound(_))));
+        assert!(matches!(storage.size(&location).await, Err(StorageError::NotFound(_))));
+        // Deleting twice is a no-op
+        storage.delete(&location).await.unwrap();
+
+        let src = base.join("staged.bin");
+        std::fs::write(&src, b"staged").unwrap();
+        let moved = storage.save_file(&src, owner, "staged.bin").await.unwrap();
+        assert!(!src.exists());
+        assert!(moved.to_string().starts_with(&format!("local://{}", prefix)));
+        assert_eq!(&storage.load_bytes(&moved).await.unwrap()[..], b"staged");
+
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[tokio::test]
+    async fn test_locations_outside_the_base_are_refused() {
+        let root = std::env::temp_dir().join(format!("mf_storage_{}", Uuid::new_v4()));
+        let base = root.join("uploads");
+        std::fs::create_dir_all(&base).unwrap();
+        let secret = root.join("secret.txt");
+        std::fs::write(&secret, b"secret").unwrap();
+        let storage = LocalStorage::new(&base);
+
+        let escapes = [
+            base.join("../secret.txt").to_string_lossy().to_string(),
+            secret.to_string_lossy().to_string(),
+            "/etc/passwd".to_string(),
+            "../../etc/passwd".to_string(),
+            // Bare file names resolve against the working directory
+            "secret.txt".to_string(),
+            base.to_string_lossy().to_string(),
+            "local://../secret.txt".to_string(),
+            "local:///etc/passwd".to_string(),
+            "local://./".to_string(),
+        ];
+        for location in &escapes {
+            let parsed = StorageLocation::parse(location).unwrap();
+            let loaded = storage.load_bytes(&parsed).await;
+            assert!(matches!(loaded, Err(StorageError::OutsideStorage(_))), "{}: {:?}", location, loaded);
+            assert!(matches!(storage.delete(&parsed).await, Err(StorageError::OutsideStorage(_))));
+        }
+        assert!(secret.exists());
+
+        // A symlink planted inside the base doesn't lead out of it
+        #[cfg(unix)]
+        {
+            let link = base.join("link.txt");
+            std::os::unix::fs::symlink(&secret, &link).unwrap();
+            let loaded = storage.open_stream(&StorageLocation::Local("link.txt".to_string()), None).await;
+            assert!(matches!(loaded, Err(StorageError::OutsideStorage(_))));
+        }
+
+        // Hostile upload names are stored inside the base
+        let owner = Uuid::new_v4();
+        let location = storage.save_bytes(b"x", owner, "../../evil.txt").await.unwrap();
+        assert!(matches!(&location, StorageLocation::Local(name) if name.starts_with(&owner.to_string())));
+        assert!(location.file_name().ends_with("_evil.txt") && !location.file_name().contains(".."));
+        assert_eq!(&storage.load_bytes(&location).await.unwrap()[..], b"x");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_round_trip() {
+        let storage = MemoryStorage::new();
+        let location = storage.save_bytes(b"hello", Uuid::new_v4(), "../hello.txt").await.unwrap();
+        assert!(matches!(&location, StorageLocation::Memory(name) if name.ends_with("_hello.txt")));
+        assert_eq!(&storage.load_bytes(&location).await.unwrap()[..], b"hello");
+        assert_eq!(storage.size(&location).await.unwrap(), 5);
+
+        let window = ByteRange { start: 1, end: 3 };
+        let stream = storage.open_stream(&location, Some(window)).await.unwrap();
+        let streamed: Vec<Bytes> = stream.try_collect().await.unwrap();
+        assert_eq!(streamed.concat(), b"ell");
+
+        let staged = std::env::temp_dir().join(format!("mf_memory_{}", Uuid::new_v4()));
+        std::fs::write(&staged, b"staged").unwrap();
+        let moved = storage.save_file(&staged, Uuid::new_v4(), "staged.bin").await.unwrap();
+        assert!(!staged.exists());
+        assert_eq!(&storage.load_bytes(&moved).await.unwrap()[..], b"staged");
+
+        storage.delete(&location).await.unwrap();
+        storage.delete(&location).await.unwrap();
+        assert!(matches!(storage.load_bytes(&location).await, Err(StorageError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_storage_locations_round_trip() {
+        let cases = [
+            ("local://abc_photo.png", StorageLocation::Local("abc_photo.png".to_string())),
+            ("local://nested/abc_photo.png", StorageLocation::Local("nested/abc_photo.png".to_string())),
+            (
+                "s3://mediaforge/abc_photo.png",
+                StorageLocation::S3 { bucket: "mediaforge".to_string(), key: "abc_photo.png".to_string() },
+            ),
+            ("memory://abc_photo.png", StorageLocation::Memory("abc_photo.png".to_string())),
+            (
+                "./data/uploads/abc_photo.png",
+                StorageLocation::LegacyPath("./data/uploads/abc_photo.png".to_string()),
+            ),
+            ("/srv/uploads/abc_photo.png", StorageLocation::LegacyPath("/srv/uploads/abc_photo.png".to_string())),
+        ];
+        for (text, location) in cases {
+            assert_eq!(text.parse::<StorageLocation>().unwrap(), location);
+            assert_eq!(location.to_string(), text);
+            assert_eq!(location.file_name(), "abc_photo.png");
+        }
+
+        for invalid in ["", "ftp://host/file", "local://", "s3://bucket", "s3://bucket/", "s3:///key"] {
+            assert!(
+                matches!(StorageLocation::parse(invalid), Err(StorageError::InvalidLocation(_))),
+                "{:?} parsed",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_reads_legacy_paths_through_local_storage() {
+        let base = std::env::temp_dir().join(format!("mf_storage_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&base).unwrap();
+        // Rows from before schemes hold the full path of the file
+        let legacy = base.join("0000_old.png");
+        std::fs::write(&legacy, b"old").unwrap();
+        let router = StorageRouter::local(LocalStorage::new(&base), None);
+
+        let location: StorageLocation = legacy.to_string_lossy().parse().unwrap();
+        assert!(matches!(location, StorageLocation::LegacyPath(_)));
+        assert_eq!(&router.load_bytes(&location).await.unwrap()[..], b"old");
+        assert_eq!(router.size(&location).await.unwrap(), 3);
+        assert_eq!(location.file_name(), "0000_old.png");
+
+        let saved = router.save_bytes(b"new", Uuid::new_v4(), "new.png").await.unwrap();
+        assert!(saved.to_string().starts_with("local://"));
+        assert_eq!(&router.load_bytes(&saved).await.unwrap()[..], b"new");
+
+        // Without S3 configured its locations have nowhere to go
+        let s3: StorageLocation = "s3://mediaforge/key.png".parse().unwrap();
+        assert!(matches!(router.load_bytes(&s3).await, Err(StorageError::NoBackend(_))));
+
+        router.delete(&location).await.unwrap();
+        assert!(!legacy.exists());
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[tokio::test]
+    async fn test_flat_files_relocate_under_their_owner() {
+        let base = std::env::temp_dir().join(format!("mf_storage_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("0000_a.png"), b"a").unwrap();
+        let storage = LocalStorage::new(&base);
+        let owner = Uuid::new_v4();
+        let stored_at = "2024-03-09T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+
+        let flat = StorageLocation::Local("0000_a.png".to_string());
+        let to = LocalStorage::relocated(&flat, owner, stored_at).unwrap();
+        assert_eq!(to, StorageLocation::Local(format!("{}/2024/03/0000_a.png", owner)));
+        let legacy = StorageLocation::LegacyPath(base.join("0000_a.png").to_string_lossy().to_string());
+        assert_eq!(LocalStorage::relocated(&legacy, owner, stored_at), Some(to.clone()));
+        // Laid out already, or not local
+        assert_eq!(LocalStorage::relocated(&to, owner, stored_at), None);
+        assert_eq!(LocalStorage::relocated(&StorageLocation::Memory("0000_a.png".to_string()), owner, stored_at), None);
+
+        storage.rename(&flat, &to).await.unwrap();
+        assert_eq!(&storage.load_bytes(&to).await.unwrap()[..], b"a");
+        assert!(matches!(storage.rename(&flat, &to).await, Err(StorageError::NotFound(_))));
+        let escape = StorageLocation::Local("../0000_a.png".to_string());
+        assert!(matches!(storage.rename(&to, &escape).await, Err(StorageError::OutsideStorage(_))));
+
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("photo.png"), "photo.png");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\my photo.jpg"), "my_photo.jpg");
+        assert_eq!(sanitize_filename(".."), "file");
+        assert_eq!(sanitize_filename(".env"), "env");
+        assert_eq!(sanitize_filename("a\0b;rm -rf.png"), "a_b_rm_-rf.png");
+        assert_eq!(sanitize_filename("tab\there\nnew\u{7f}.png"), "tab_here_new_.png");
+        assert_eq!(sanitize_filename("café.png"), "café.png");
+
+        let long = format!("{}.jpg", "é".repeat(300));
+        let sanitized = sanitize_filename(&long);
+        assert!(sanitized.len() <= MAX_FILENAME_BYTES);
+        assert!(sanitized.ends_with("é.jpg"));
     }
 }
@@ -0,0 +1,70 @@
+// backend/src/services/u2net.rs
+// U²-Net salient object segmentation via onnxruntime (feature = "onnx")
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage, Luma};
+use ndarray::Array4;
+use ort::session::Session;
+
+use super::processing::ProcessingError;
+
+/// U²-Net expects a fixed 320×320 input
+const INPUT_SIZE: u32 = 320;
+/// ImageNet normalization used when the model was trained
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+pub fn load(model_path: &str) -> Result<Session, ProcessingError> {
+    Session::builder()
+        .and_then(|builder| builder.commit_from_file(model_path))
+        .map_err(|e| ProcessingError::ModelLoadFailed(format!("{}: {}", model_path, e)))
+}
+
+/// Run the model and return a foreground mask (255 = subject) at the
+/// original image resolution.
+pub fn predict_mask(session: &Session, img: &DynamicImage) -> Result<GrayImage, ProcessingError> {
+    let (width, height) = img.dimensions();
+    let size = INPUT_SIZE as usize;
+
+    // Preprocess: resize, scale to 0..1, normalize, NCHW layout
+    let resized = img
+        .resize_exact(INPUT_SIZE, INPUT_SIZE, FilterType::Triangle)
+        .to_rgb8();
+    let mut input = Array4::<f32>::zeros((1, 3, size, size));
+    for (x, y, pixel) in resized.enumerate_pixels() {
+        for c in 0..3 {
+            input[[0, c, y as usize, x as usize]] = (pixel[c] as f32 / 255.0 - MEAN[c]) / STD[c];
+        }
+    }
+
+    let tensor = ort::value::Tensor::from_array(input).map_err(inference_error)?;
+    let inputs = ort::inputs![tensor].map_err(inference_error)?;
+    let outputs = session.run(inputs).map_err(inference_error)?;
+
+    // First output (d0) is the fused saliency map, shape [1, 1, 320, 320]
+    let prediction = outputs[0]
+        .try_extract_tensor::<f32>()
+        .map_err(inference_error)?;
+    let values: Vec<f32> = prediction.iter().copied().collect();
+    if values.len() != size * size {
+        return Err(ProcessingError::InferenceFailed(format!(
+            "Unexpected model output size {} (expected {})",
+            values.len(),
+            size * size
+        )));
+    }
+
+    // Min-max normalize to 0..255
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range = (max - min).max(f32::EPSILON);
+    let mask = GrayImage::from_fn(INPUT_SIZE, INPUT_SIZE, |x, y| {
+        let v = values[(y * INPUT_SIZE + x) as usize];
+        Luma([(((v - min) / range) * 255.0).round() as u8])
+    });
+
+    Ok(image::imageops::resize(&mask, width, height, FilterType::Triangle))
+}
+
+fn inference_error(e: ort::Error) -> ProcessingError {
+    ProcessingError::InferenceFailed(e.to_string())
+}
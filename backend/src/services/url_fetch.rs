@@ -0,0 +1,198 @@
+// backend/src/services/url_fetch.rs
+// Downloads for /api/upload/from-url: https only, never into the internal
+// network, with a deadline covering the whole transfer
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::Stream;
+use reqwest::{StatusCode, Url};
+use tokio::time::Instant;
+
+use super::webhook::is_internal;
+
+/// Redirects followed; each hop is checked like the URL given
+pub const MAX_REDIRECTS: usize = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("url is not a valid URL: {0}")]
+    Invalid(String),
+    #[error("url must use https")]
+    UnsupportedScheme,
+    #[error("url host could not be resolved")]
+    Unresolvable,
+    #[error("url must not point at a private, loopback or link-local address")]
+    PrivateAddress,
+    #[error("url redirected more than {} times", MAX_REDIRECTS)]
+    TooManyRedirects,
+    #[error("the remote server responded with {0}")]
+    Status(StatusCode),
+    #[error("the remote file exceeds the {} MB limit", .0 / (1024 * 1024))]
+    TooLarge(u64),
+    #[error("fetching the url timed out")]
+    TimedOut,
+    #[error("fetching the url failed: {0}")]
+    Request(String),
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Request(err.to_string())
+    }
+}
+
+/// A successful response whose body has not been read yet
+pub struct RemoteFile {
+    /// Where the body comes from, after redirects
+    pub url: Url,
+    response: reqwest::Response,
+    deadline: Instant,
+}
+
+impl RemoteFile {
+    /// The last segment of the URL's path, if it isn't empty
+    pub fn file_name(&self) -> Option<String> {
+        let name = self.url.path_segments()?.next_back()?;
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    /// The body, failing with `TimedOut` once the fetch deadline passes
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes, FetchError>> + Unpin {
+        let deadline = self.deadline;
+        Box::pin(futures_util::stream::unfold(Some(self.response), move |response| async move {
+            let mut response = response?;
+            match tokio::time::timeout_at(deadline, response.chunk()).await {
+                Err(_) => Some((Err(FetchError::TimedOut), None)),
+                Ok(Err(e)) => Some((Err(e.into()), None)),
+                Ok(Ok(Some(chunk))) => Some((Ok(chunk), Some(response))),
+                Ok(Ok(None)) => None,
+            }
+        }))
+    }
+}
+
+/// Request `raw`, following up to `MAX_REDIRECTS` redirects, and return the
+/// response once it is a success. `timeout` covers everything including
+/// reading the body later. A declared length over `max_bytes` is refused
+/// up front; the body is not trusted to match it, so callers still count
+/// bytes as they read.
+pub async fn open(raw: &str, max_bytes: u64, timeout: Duration) -> Result<RemoteFile, FetchError> {
+    let deadline = Instant::now() + timeout;
+    let mut url = Url::parse(raw.trim()).map_err(|e| FetchError::Invalid(e.to_string()))?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let client = tokio::time::timeout_at(deadline, pinned_client(&url))
+            .await
+            .map_err(|_| FetchError::TimedOut)??;
+        let response = tokio::time::timeout_at(deadline, client.get(url.clone()).send())
+            .await
+            .map_err(|_| FetchError::TimedOut)??;
+
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .ok_or(FetchError::Status(status))?;
+            url = url.join(location).map_err(|e| FetchError::Invalid(e.to_string()))?;
+            continue;
+        }
+        if !status.is_success() {
+            return Err(FetchError::Status(status));
+        }
+        if response.content_length().is_some_and(|length| length > max_bytes) {
+            return Err(FetchError::TooLarge(max_bytes));
+        }
+        return Ok(RemoteFile { url, response, deadline });
+    }
+
+    Err(FetchError::TooManyRedirects)
+}
+
+/// Check `url` and build a client that connects only to the addresses that
+/// were checked, so a second DNS answer can't point the request elsewhere
+async fn pinned_client(url: &Url) -> Result<reqwest::Client, FetchError> {
+    let addrs = public_addrs(url).await?;
+    let builder = reqwest::Client::builder()
+        // Redirects are followed by hand so every hop is checked
+        .redirect(reqwest::redirect::Policy::none())
+        // A proxy would do its own resolving
+        .no_proxy();
+    let builder = match url.domain() {
+        Some(domain) => builder.resolve_to_addrs(domain, &addrs),
+        None => builder,
+    };
+    Ok(builder.build()?)
+}
+
+/// Resolve the host of an https `url`, refusing it if any address is internal
+async fn public_addrs(url: &Url) -> Result<Vec<SocketAddr>, FetchError> {
+    if url.scheme() != "https" {
+        return Err(FetchError::UnsupportedScheme);
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| FetchError::Invalid("missing host".to_string()))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    // IPv6 literals keep their brackets in host_str
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| FetchError::Unresolvable)?
+            .collect(),
+    };
+    if addrs.is_empty() {
+        return Err(FetchError::Unresolvable);
+    }
+    if addrs.iter().any(|addr| is_internal(addr.ip())) {
+        return Err(FetchError::PrivateAddress);
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_open_rejects_internal_and_plain_http_urls() {
+        let limit = 1024;
+        let timeout = Duration::from_secs(5);
+        for url in [
+            "https://127.0.0.1/a.png",
+            "https://10.0.0.7/a.png",
+            "https://169.254.169.254/latest/meta-data",
+            "https://[::1]/a.png",
+            "https://[::ffff:192.168.1.1]/a.png",
+            "https://localhost/a.png",
+        ] {
+            assert!(
+                matches!(open(url, limit, timeout).await, Err(FetchError::PrivateAddress)),
+                "{}",
+                url
+            );
+        }
+        for url in ["http://example.com/a.png", "ftp://example.com/a.png", "file:///etc/passwd"] {
+            assert!(
+                matches!(open(url, limit, timeout).await, Err(FetchError::UnsupportedScheme)),
+                "{}",
+                url
+            );
+        }
+        assert!(matches!(open("not a url", limit, timeout).await, Err(FetchError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_too_large_reports_megabytes() {
+        assert_eq!(
+            FetchError::TooLarge(5 * 1024 * 1024).to_string(),
+            "the remote file exceeds the 5 MB limit"
+        );
+    }
+}
@@ -0,0 +1,452 @@
+// backend/src/services/video.rs
+// ffmpeg-driven video processing: frame extraction/reassembly and transcoding
+
+use std::ffi::OsString;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+use super::archive;
+use super::ffmpeg::Ffmpeg;
+use super::processing::ProcessingError;
+
+/// Frame rate used when ffprobe cannot report one
+pub const DEFAULT_FRAME_RATE: f64 = 30.0;
+
+const FRAME_PREFIX: &str = "frame_";
+const FRAME_PATTERN: &str = "frame_%06d.png";
+
+/// Container for processed video output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoOutput {
+    /// VP9 with an alpha plane (yuva420p)
+    WebmAlpha,
+    /// Zip of PNG frames, for requested containers that cannot carry alpha
+    PngSequence,
+}
+
+impl VideoOutput {
+    pub fn for_format(format: Option<&str>) -> Self {
+        match format.map(str::to_lowercase).as_deref() {
+            None | Some("webm") => Self::WebmAlpha,
+            _ => Self::PngSequence,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::WebmAlpha => "webm",
+            Self::PngSequence => "zip",
+        }
+    }
+
+    /// ffmpeg encoders needed to split the source into frames and write this output
+    pub fn encoders(self) -> &'static [&'static str] {
+        match self {
+            Self::WebmAlpha => &["png", "libvpx-vp9", "libopus"],
+            Self::PngSequence => &["png"],
+        }
+    }
+}
+
+/// Containers a video can be converted to
+pub const CONVERT_FORMATS: &[&str] = &["mp4", "webm", "mov", "gif"];
+
+/// Video codecs accepted in `ConvertRequest::video_codec`
+pub const VIDEO_CODECS: &[&str] = &["h264", "hevc", "vp9", "av1", "prores"];
+
+/// Highest CRF any of the supported encoders accepts (libvpx/libaom go to 63)
+pub const MAX_QUALITY: u32 = 63;
+
+/// Requested output of a video conversion
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    pub format: String,
+    pub codec: Option<String>,
+    /// Constant rate factor; lower is better quality
+    pub quality: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Copy container metadata (creation time, GPS, ...) into the output
+    pub keep_metadata: bool,
+}
+
+impl ConvertOptions {
+    /// ffmpeg encoder for the container and requested codec
+    fn encoder(&self) -> Result<&'static str, ProcessingError> {
+        let format = self.format.to_lowercase();
+        let codec = self.codec.as_deref().map(str::to_lowercase);
+        let encoder = match (format.as_str(), codec.as_deref()) {
+            ("gif", None) => "gif",
+            ("mp4" | "mov", None | Some("h264")) => "libx264",
+            ("mp4" | "mov", Some("hevc")) => "libx265",
+            ("mov", Some("prores")) => "prores_ks",
+            ("webm", None | Some("vp9")) => "libvpx-vp9",
+            ("webm" | "mp4", Some("av1")) => "libaom-av1",
+            (f, _) if !CONVERT_FORMATS.contains(&f) => {
+                return Err(ProcessingError::Unsupported(format!(
+                    "videos cannot be converted to {} (supported: {})",
+                    f,
+                    CONVERT_FORMATS.join(", ")
+                )))
+            }
+            (f, Some(c)) => {
+                return Err(ProcessingError::Unsupported(format!("codec {} cannot be stored in {}", c, f)))
+            }
+            (f, None) => return Err(ProcessingError::Unsupported(format!("no encoder for {}", f))),
+        };
+        Ok(encoder)
+    }
+
+    /// ffmpeg audio encoder for the container, `None` when audio is dropped
+    fn audio_encoder(&self) -> Option<&'static str> {
+        match self.format.to_lowercase().as_str() {
+            "gif" => None,
+            "webm" => Some("libopus"),
+            _ => Some("aac"),
+        }
+    }
+
+    /// Every ffmpeg encoder this conversion runs
+    pub fn encoders(&self) -> Result<Vec<&'static str>, ProcessingError> {
+        Ok([Some(self.encoder()?), self.audio_encoder()].into_iter().flatten().collect())
+    }
+
+    /// Check the conversion is possible before any work is queued or started
+    pub fn validate(&self) -> Result<(), ProcessingError> {
+        let encoder = self.encoder()?;
+        if let Some(quality) = self.quality {
+            if encoder == "prores_ks" || encoder == "gif" {
+                return Err(ProcessingError::Unsupported(format!("quality is not supported for {}", encoder)));
+            }
+            if quality > MAX_QUALITY {
+                return Err(ProcessingError::Unsupported(format!(
+                    "quality must be between 0 and {}",
+                    MAX_QUALITY
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Full ffmpeg argument list (after the global flags) for this conversion
+    fn ffmpeg_args(&self, input: &Path, output: &Path) -> Result<Vec<OsString>, ProcessingError> {
+        self.validate()?;
+        let encoder = self.encoder()?;
+        let mut args: Vec<OsString> = vec!["-i".into(), input.into()];
+
+        let mut filters = Vec::new();
+        if encoder == "gif" {
+            filters.push("fps=15".to_string());
+        }
+        if self.width.is_some() || self.height.is_some() {
+            // -2 keeps the aspect ratio while rounding to an even size
+            let dim = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_else(|| "-2".to_string());
+            filters.push(format!("scale={}:{}", dim(self.width), dim(self.height)));
+        }
+        if !filters.is_empty() {
+            args.extend(["-vf".into(), filters.join(",").into()]);
+        }
+
+        args.extend(["-c:v".into(), encoder.into()]);
+        if let Some(crf) = self.quality {
+            args.extend(["-crf".into(), crf.to_string().into()]);
+            if encoder == "libvpx-vp9" || encoder == "libaom-av1" {
+                // Constant quality mode for libvpx/libaom requires a zero bitrate
+                args.extend(["-b:v".into(), "0".into()]);
+            }
+        }
+
+        match self.audio_encoder() {
+            None => args.push("-an".into()),
+            Some(audio) => args.extend(["-c:a".into(), audio.into()]),
+        }
+
+        if !self.keep_metadata {
+            args.extend(["-map_metadata".into(), "-1".into()]);
+        }
+
+        args.extend(["-progress".into(), "pipe:1".into(), "-nostats".into(), output.into()]);
+        Ok(args)
+    }
+}
+
+/// Convert a video with ffmpeg, reporting progress (0..=100) as it encodes.
+/// `duration_seconds` is needed to turn ffmpeg's timestamps into a percentage;
+/// without it only completion is reported.
+pub async fn convert<F, Fut>(
+    ffmpeg: &Ffmpeg,
+    input: &Path,
+    output: &Path,
+    options: &ConvertOptions,
+    duration_seconds: Option<f64>,
+    mut on_progress: F,
+) -> Result<(), ProcessingError>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let args = options.ffmpeg_args(input, output)?;
+    let mut child = ffmpeg
+        .ffmpeg()
+        // -nostdin: never block waiting for interactive input
+        .args(["-nostdin", "-v", "error", "-y"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // A job that times out drops this future; ffmpeg must not run on
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ProcessingError::ToolMissing("ffmpeg"),
+            _ => ProcessingError::IoError(e),
+        })?;
+
+    // Drain stderr concurrently so a chatty ffmpeg cannot fill the pipe and stall
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_task = tokio::spawn(async move {
+        let mut buf = String::new();
+        let _ = stderr.read_to_string(&mut buf).await;
+        buf
+    });
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut lines = BufReader::new(stdout).lines();
+    let mut last = None;
+    while let Some(line) = lines.next_line().await? {
+        if let Some(percent) = parse_progress_line(&line, duration_seconds) {
+            if last != Some(percent) {
+                last = Some(percent);
+                on_progress(percent).await;
+            }
+        }
+    }
+
+    let status = child.wait().await?;
+    let stderr = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        return Err(ProcessingError::IoError(std::io::Error::other(format!(
+            "ffmpeg failed: {}",
+            stderr.trim()
+        ))));
+    }
+    Ok(())
+}
+
+/// Interpret one line of `-progress` output as a completion percentage.
+fn parse_progress_line(line: &str, duration_seconds: Option<f64>) -> Option<u32> {
+    let (key, value) = line.trim().split_once('=')?;
+    match key {
+        "progress" if value == "end" => Some(100),
+        // Despite the name, out_time_ms is also in microseconds
+        "out_time_us" | "out_time_ms" => {
+            let duration = duration_seconds.filter(|d| *d > 0.0)?;
+            let elapsed = value.parse::<f64>().ok()? / 1_000_000.0;
+            Some(((elapsed / duration) * 100.0).clamp(0.0, 99.0) as u32)
+        }
+        _ => None,
+    }
+}
+
+/// Decode every frame of `input` into numbered PNGs inside `dir`, returned in order.
+pub async fn extract_frames(ffmpeg: &Ffmpeg, input: &Path, dir: &Path) -> Result<Vec<PathBuf>, ProcessingError> {
+    tokio::fs::create_dir_all(dir).await?;
+    run_ffmpeg(ffmpeg, vec![
+        "-i".into(),
+        input.into(),
+        // Keep source timing: one PNG per decoded frame, no duplication or drops
+        "-vsync".into(),
+        "passthrough".into(),
+        dir.join(FRAME_PATTERN).into(),
+    ])
+    .await?;
+
+    let mut frames = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with(FRAME_PREFIX) {
+            frames.push(entry.path());
+        }
+    }
+    // Zero-padded names sort in frame order
+    frames.sort();
+    Ok(frames)
+}
+
+/// Reassemble the frames in `dir` into a VP9 WebM with alpha. Audio is copied
+/// over from `audio_source` when it has any.
+pub async fn encode_webm_alpha(
+    ffmpeg: &Ffmpeg,
+    dir: &Path,
+    frame_rate: f64,
+    audio_source: &Path,
+    output: &Path,
+) -> Result<(), ProcessingError> {
+    run_ffmpeg(ffmpeg, vec![
+        "-framerate".into(),
+        frame_rate.to_string().into(),
+        "-i".into(),
+        dir.join(FRAME_PATTERN).into(),
+        "-i".into(),
+        audio_source.into(),
+        "-map".into(),
+        "0:v".into(),
+        "-map".into(),
+        "1:a?".into(),
+        "-c:v".into(),
+        "libvpx-vp9".into(),
+        "-pix_fmt".into(),
+        "yuva420p".into(),
+        // Alt-ref frames are incompatible with alpha in libvpx
+        "-auto-alt-ref".into(),
+        "0".into(),
+        "-c:a".into(),
+        "libopus".into(),
+        "-shortest".into(),
+        output.into(),
+    ])
+    .await
+}
+
+/// Bundle frames into a zip, keeping their file names
+pub fn zip_frames(frames: &[PathBuf], output: &Path) -> Result<(), ProcessingError> {
+    let entries: Vec<(String, PathBuf)> = frames
+        .iter()
+        .map(|frame| {
+            let name = frame
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (name, frame.clone())
+        })
+        .collect();
+    archive::zip_files(&entries, output)?;
+    Ok(())
+}
+
+async fn run_ffmpeg(ffmpeg: &Ffmpeg, args: Vec<OsString>) -> Result<(), ProcessingError> {
+    let output = ffmpeg
+        .ffmpeg()
+        .args(["-v", "error", "-y"])
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ProcessingError::ToolMissing("ffmpeg"),
+            _ => ProcessingError::IoError(e),
+        })?;
+
+    if !output.status.success() {
+        return Err(ProcessingError::IoError(std::io::Error::other(format!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_for_format() {
+        assert_eq!(VideoOutput::for_format(None), VideoOutput::WebmAlpha);
+        assert_eq!(VideoOutput::for_format(Some("WEBM")), VideoOutput::WebmAlpha);
+        // MP4/H.264 has no alpha plane
+        assert_eq!(VideoOutput::for_format(Some("mp4")), VideoOutput::PngSequence);
+        assert_eq!(VideoOutput::PngSequence.extension(), "zip");
+    }
+
+    fn args_for(options: &ConvertOptions) -> Vec<String> {
+        options
+            .ffmpeg_args(Path::new("in.mp4"), Path::new("out"))
+            .unwrap()
+            .into_iter()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_convert_args() {
+        let args = args_for(&ConvertOptions {
+            format: "webm".into(),
+            quality: Some(30),
+            width: Some(640),
+            ..Default::default()
+        });
+        let joined = args.join(" ");
+        assert!(joined.contains("-vf scale=640:-2"), "{}", joined);
+        assert!(joined.contains("-c:v libvpx-vp9 -crf 30 -b:v 0"), "{}", joined);
+        assert!(joined.contains("-c:a libopus"), "{}", joined);
+        assert!(joined.ends_with("-map_metadata -1 -progress pipe:1 -nostats out"), "{}", joined);
+
+        let keep = args_for(&ConvertOptions { format: "mp4".into(), keep_metadata: true, ..Default::default() });
+        assert!(!keep.contains(&"-map_metadata".to_string()), "{:?}", keep);
+
+        let gif = args_for(&ConvertOptions { format: "gif".into(), ..Default::default() }).join(" ");
+        assert!(gif.contains("-vf fps=15 -c:v gif -an"), "{}", gif);
+
+        let prores = ConvertOptions { format: "mov".into(), codec: Some("prores".into()), ..Default::default() };
+        assert_eq!(prores.encoders().unwrap(), ["prores_ks", "aac"]);
+        assert_eq!(ConvertOptions { format: "gif".into(), ..Default::default() }.encoders().unwrap(), ["gif"]);
+    }
+
+    #[test]
+    fn test_unsupported_conversions() {
+        let png = ConvertOptions { format: "png".into(), ..Default::default() };
+        assert!(matches!(png.validate(), Err(ProcessingError::Unsupported(_))));
+
+        let vp9_in_mov = ConvertOptions {
+            format: "mov".into(),
+            codec: Some("vp9".into()),
+            ..Default::default()
+        };
+        assert!(matches!(vp9_in_mov.validate(), Err(ProcessingError::Unsupported(_))));
+
+        let gif_quality = ConvertOptions {
+            format: "gif".into(),
+            quality: Some(20),
+            ..Default::default()
+        };
+        assert!(gif_quality.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_progress_line() {
+        assert_eq!(parse_progress_line("out_time_us=5000000", Some(10.0)), Some(50));
+        assert_eq!(parse_progress_line("out_time_ms=12000000", Some(10.0)), Some(99));
+        assert_eq!(parse_progress_line("out_time_us=5000000", None), None);
+        assert_eq!(parse_progress_line("progress=end", None), Some(100));
+        assert_eq!(parse_progress_line("progress=continue", Some(10.0)), None);
+        assert_eq!(parse_progress_line("frame=12", Some(10.0)), None);
+    }
+
+    #[test]
+    fn test_zip_frames_keeps_order_and_names() {
+        let dir = std::env::temp_dir().join(format!("zip_frames_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let frames: Vec<PathBuf> = (1..=2)
+            .map(|i| {
+                let path = dir.join(format!("frame_{:06}.png", i));
+                image::RgbaImage::new(2, 2).save(&path).unwrap();
+                path
+            })
+            .collect();
+
+        let output = dir.join("frames.zip");
+        zip_frames(&frames, &output).unwrap();
+
+        let archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(archive.len(), 2);
+        assert!(names.contains(&"frame_000001.png") && names.contains(&"frame_000002.png"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,341 @@
+// backend/src/services/webhook.rs
+// Job completion callbacks: HMAC-signed POSTs with retry
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::Serialize;
+use sha2::Sha256;
+
+/// Header carrying `sha256=<hex HMAC of the raw body>`
+pub const SIGNATURE_HEADER: &str = "X-MediaForge-Signature";
+
+const MAX_ATTEMPTS: u32 = 3;
+/// Delay before the second attempt; doubles after each failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookUrlError {
+    #[error("webhook_url is not a valid URL: {0}")]
+    Invalid(String),
+    #[error("webhook_url must use http or https")]
+    UnsupportedScheme,
+    #[error("webhook_url host could not be resolved")]
+    Unresolvable,
+    #[error("webhook_url must not point at a private, loopback or link-local address")]
+    PrivateAddress,
+}
+
+/// Body POSTed to the webhook URL
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload {
+    pub job_id: String,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Set with `error`: `decode_failed`, `unsupported_format`,
+    /// `storage_error`, `timeout`, `worker_lost` or `processing_failed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    pub created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<String>,
+}
+
+/// A webhook URL with the addresses its host resolved to when checked.
+/// Deliveries connect only to those, so a second DNS answer can't point one
+/// at an internal address.
+#[derive(Debug, Clone)]
+pub struct WebhookTarget {
+    pub url: Url,
+    addrs: Vec<SocketAddr>,
+}
+
+/// Outcome of delivering one webhook, including retries
+#[derive(Debug)]
+pub struct Delivery {
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+impl Delivery {
+    pub fn delivered(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Check that a webhook URL is http(s) and that its host does not resolve to
+/// an internal address. Checked at submit time and again before delivery,
+/// since DNS can change in between.
+pub async fn validate_url(raw: &str) -> Result<WebhookTarget, WebhookUrlError> {
+    let url = Url::parse(raw).map_err(|e| WebhookUrlError::Invalid(e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(WebhookUrlError::UnsupportedScheme);
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| WebhookUrlError::Invalid("missing host".to_string()))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    // IPv6 literals keep their brackets in host_str
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| WebhookUrlError::Unresolvable)?
+            .collect(),
+    };
+    if addrs.is_empty() {
+        return Err(WebhookUrlError::Unresolvable);
+    }
+    if addrs.iter().any(|addr| is_internal(addr.ip())) {
+        return Err(WebhookUrlError::PrivateAddress);
+    }
+
+    Ok(WebhookTarget { url, addrs })
+}
+
+/// Addresses no user-supplied URL may reach: private, loopback, link-local
+/// and the like
+pub(crate) fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_internal_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_internal_v4(v4),
+            None => is_internal_v6(v6),
+        },
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        || a == 0
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// `sha256=<hex>` HMAC of `body` under `secret`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Sends signed webhook payloads, retrying failures with exponential backoff
+pub struct WebhookSender {
+    secret: String,
+    backoff: Duration,
+}
+
+impl WebhookSender {
+    pub fn new(secret: String) -> Self {
+        Self {
+            secret,
+            backoff: INITIAL_BACKOFF,
+        }
+    }
+
+    /// A client that connects only to the target's checked addresses
+    fn pinned_client(target: &WebhookTarget) -> reqwest::Result<reqwest::Client> {
+        let builder = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            // A redirect could point the request somewhere validate_url never saw
+            .redirect(reqwest::redirect::Policy::none())
+            // A proxy would do its own resolving
+            .no_proxy();
+        let builder = match target.url.domain() {
+            Some(domain) => builder.resolve_to_addrs(domain, &target.addrs),
+            None => builder,
+        };
+        builder.build()
+    }
+
+    /// POST the payload, up to three attempts. Any 2xx response counts as delivered.
+    pub async fn send(&self, target: &WebhookTarget, payload: &WebhookPayload) -> Delivery {
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body,
+            Err(e) => {
+                return Delivery {
+                    attempts: 0,
+                    error: Some(format!("Failed to encode payload: {}", e)),
+                }
+            }
+        };
+        let signature = sign(&self.secret, &body);
+        let client = match Self::pinned_client(target) {
+            Ok(client) => client,
+            Err(e) => {
+                return Delivery {
+                    attempts: 0,
+                    error: Some(format!("Failed to build HTTP client: {}", e)),
+                }
+            }
+        };
+
+        let mut delay = self.backoff;
+        let mut last_error = String::new();
+        for attempt in 1..=MAX_ATTEMPTS {
+            let response = client
+                .post(target.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+
+            match response {
+                Ok(r) if r.status().is_success() => {
+                    return Delivery {
+                        attempts: attempt,
+                        error: None,
+                    }
+                }
+                Ok(r) => last_error = format!("Receiver responded with {}", r.status()),
+                Err(e) => last_error = format!("Request failed: {}", e),
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        Delivery {
+            attempts: MAX_ATTEMPTS,
+            error: Some(last_error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_validate_url_rejects_internal_targets() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.10:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:10.0.0.1]/hook",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+        ] {
+            assert!(
+                matches!(validate_url(url).await, Err(WebhookUrlError::PrivateAddress)),
+                "{}",
+                url
+            );
+        }
+
+        assert!(matches!(
+            validate_url("ftp://203.0.113.5/x").await,
+            Err(WebhookUrlError::UnsupportedScheme)
+        ));
+        assert!(matches!(validate_url("not a url").await, Err(WebhookUrlError::Invalid(_))));
+        let target = validate_url("https://93.184.216.34/hooks/job").await.unwrap();
+        assert_eq!(target.addrs, ["93.184.216.34:443".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_until_success() {
+        use axum::{http::StatusCode, routing::post, Router};
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: axum::http::HeaderMap, body: axum::body::Bytes| {
+                let counter = counter.clone();
+                async move {
+                    assert_eq!(
+                        headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap(),
+                        sign("secret", &body)
+                    );
+                    // Fail the first attempt
+                    if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::NO_CONTENT
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut sender = WebhookSender::new("secret".to_string());
+        sender.backoff = Duration::from_millis(10);
+        let payload = WebhookPayload {
+            job_id: "job".to_string(),
+            status: "completed".to_string(),
+            result_url: Some("result.png".to_string()),
+            error: None,
+            error_code: None,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            completed_at: None,
+        };
+
+        // The receiver is local, which validate_url would refuse
+        let target = |path: &str| WebhookTarget {
+            url: Url::parse(&format!("http://{}{}", addr, path)).unwrap(),
+            addrs: vec![addr],
+        };
+        let delivery = sender.send(&target("/hook"), &payload).await;
+        assert!(delivery.delivered());
+        assert_eq!(delivery.attempts, 2);
+
+        // Deliveries go to the checked addresses, not to a fresh lookup
+        let pinned = WebhookTarget {
+            url: Url::parse(&format!("http://hooks.invalid:{}/hook", addr.port())).unwrap(),
+            addrs: vec![addr],
+        };
+        assert!(sender.send(&pinned, &payload).await.delivered());
+
+        // A receiver that never succeeds exhausts all attempts
+        let delivery = sender.send(&target("/missing"), &payload).await;
+        assert!(!delivery.delivered());
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+    }
+}
@@ -2,369 +2,2593 @@
 // Background job worker with database integration
 
 use tokio::sync::mpsc::Receiver;
+use std::collections::HashSet;
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::path::{Path, PathBuf};
 use tokio::sync::Mutex;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+use redis::aio::MultiplexedConnection;
 use uuid::Uuid;
 
-use crate::{db, config};
-use super::queue::{JobMessage, JobStatus};
-use super::processing::ImageProcessor;
+use crate::{db, config, telemetry};
+use super::queue::{JobMessage, JobStatus, Queue, StatusStore, JOB_QUEUE_KEY};
+use super::animation;
+use super::archive;
+use super::ffmpeg::Ffmpeg;
+use super::formats;
+use super::probe;
+use super::quota;
+use super::sniff::MediaKind;
+use super::processing::{ImageProcessor, OnProgress, Trimmed, DEFAULT_BLUR_SIGMA, OutputEncoding, ProcessingError};
+use super::video::{self, VideoOutput};
+use super::job_params::{
+    ColorGradeParams, ConvertParams, InvalidParams, JobParams, LutGenerateParams, PipelineParams, PipelineStep,
+    RemoveBgParams,
+};
+use super::lut::LutError;
+use super::storage::{self, StorageError, StorageLocation};
+use super::webhook::{self, Delivery, WebhookPayload, WebhookSender};
 use super::Storage;
 
+/// Everything a worker needs to run jobs
+struct WorkerContext {
+    storage: Arc<dyn Storage>,
+    db_pool: sqlx::PgPool,
+    statuses: StatusStore,
+    processor: Arc<ImageProcessor>,
+    ffmpeg: Arc<Ffmpeg>,
+    config: config::Config,
+    webhooks: Option<Arc<WebhookSender>>,
+    /// For announcing stalled jobs again
+    queue: Arc<Queue>,
+    /// Jobs this process is running, kept alive by the heartbeat
+    running: std::sync::Mutex<HashSet<Uuid>>,
+}
+
+/// How long an idle worker waits for a wake-up before checking the table anyway
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the jobs in hand are marked alive and dead workers' jobs looked for
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// A `processing` job without a heartbeat for this long lost its worker
+const HEARTBEAT_STALE_AFTER: Duration = Duration::from_secs(120);
+
+/// Where idle workers hear about new jobs
+pub enum JobSource {
+    /// This process's queue channel, which the Redis poller forwards to
+    Local(Receiver<JobMessage>),
+    /// The Redis list every API instance pushes to, read directly
+    Redis(redis::Client),
+}
+
+/// A `JobSource` shared by the workers. Only one idle worker waits on it at
+/// a time.
+enum Wake {
+    Local(Mutex<Receiver<JobMessage>>),
+    Redis {
+        client: redis::Client,
+        conn: Mutex<Option<MultiplexedConnection>>,
+    },
+}
+
+impl From<JobSource> for Wake {
+    fn from(source: JobSource) -> Self {
+        match source {
+            JobSource::Local(receiver) => Self::Local(Mutex::new(receiver)),
+            JobSource::Redis(client) => Self::Redis { client, conn: Mutex::new(None) },
+        }
+    }
+}
+
+impl Wake {
+    /// Wait up to `IDLE_POLL_INTERVAL` for a wake-up. False once no more
+    /// can arrive.
+    async fn wait(&self) -> bool {
+        match self {
+            Self::Local(receiver) => {
+                let mut receiver = receiver.lock().await;
+                tokio::select! {
+                    message = receiver.recv() => message.is_some(),
+                    _ = tokio::time::sleep(IDLE_POLL_INTERVAL) => true,
+                }
+            }
+            Self::Redis { client, conn } => {
+                let mut conn = conn.lock().await;
+                if let Err(e) = Self::pop_redis(client, &mut conn).await {
+                    tracing::error!("Redis BRPOP error: {:?}", e);
+                    // Reconnect on the next wait, after backing off briefly
+                    *conn = None;
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+                true
+            }
+        }
+    }
+
+    /// Block on the Redis list, holding a connection of our own: BRPOP ties
+    /// it up until a message arrives or the timeout passes
+    async fn pop_redis(
+        client: &redis::Client,
+        conn: &mut Option<MultiplexedConnection>,
+    ) -> redis::RedisResult<()> {
+        let conn = match conn {
+            Some(conn) => conn,
+            None => conn.insert(client.get_multiplexed_async_connection().await?),
+        };
+        let popped: Option<(String, String)> = redis::cmd("BRPOP")
+            .arg(JOB_QUEUE_KEY)
+            .arg(IDLE_POLL_INTERVAL.as_secs())
+            .query_async(conn)
+            .await?;
+        if let Some((_list, payload)) = popped {
+            match serde_json::from_str::<JobMessage>(&payload) {
+                Ok(job) => tracing::debug!("Woken by redis for job {}", job.job_id),
+                Err(_) => tracing::warn!("Failed to deserialize job payload from redis"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Delay before the first automatic retry; doubles with each further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(600);
+
+/// Kind of failure recorded on a failed job as `error_code`, for clients to
+/// branch on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorCode {
+    /// The input could not be read as an image or video
+    DecodeFailed,
+    /// The input or requested output format is not supported
+    UnsupportedFormat,
+    /// Storage, database or disk trouble
+    StorageError,
+    Timeout,
+    /// Anything else, such as bad parameters or a missing asset
+    ProcessingFailed,
+}
+
+impl ErrorCode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::DecodeFailed => "decode_failed",
+            Self::UnsupportedFormat => "unsupported_format",
+            Self::StorageError => "storage_error",
+            Self::Timeout => "timeout",
+            Self::ProcessingFailed => "processing_failed",
+        }
+    }
+}
+
+/// Why a job failed, and whether running it again could help
+#[derive(Debug, Clone, PartialEq)]
+enum JobError {
+    /// Storage, database or disk trouble that may clear up by itself
+    Transient(String),
+    /// Bad input or parameters; another attempt would fail the same way
+    Permanent(ErrorCode, String),
+}
+
+impl JobError {
+    fn is_transient(&self) -> bool {
+        matches!(self, Self::Transient(_))
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Self::Transient(message) | Self::Permanent(_, message) => message,
+        }
+    }
+
+    fn code(&self) -> ErrorCode {
+        match self {
+            Self::Transient(_) => ErrorCode::StorageError,
+            Self::Permanent(code, _) => *code,
+        }
+    }
+
+    /// Prefix the message, keeping the classification
+    fn context(self, prefix: &str) -> Self {
+        match self {
+            Self::Transient(message) => Self::Transient(format!("{}: {}", prefix, message)),
+            Self::Permanent(code, message) => Self::Permanent(code, format!("{}: {}", prefix, message)),
+        }
+    }
+
+    /// A missing, refused or unreadable location stays that way; anything
+    /// else from storage may be a blip
+    fn storage(e: &StorageError, message: String) -> Self {
+        match e {
+            StorageError::NotFound(_)
+            | StorageError::OutsideStorage(_)
+            | StorageError::InvalidLocation(_)
+            | StorageError::NoBackend(_) => Self::Permanent(ErrorCode::StorageError, message),
+            _ => Self::Transient(message),
+        }
+    }
+
+    /// Real I/O failures are worth retrying. ffmpeg rejecting its input is
+    /// reported as `ErrorKind::Other` and, like every other processing
+    /// error, would fail again.
+    fn processing(e: &ProcessingError, message: String) -> Self {
+        let code = match e {
+            ProcessingError::IoError(io) if io.kind() != std::io::ErrorKind::Other => {
+                return Self::Transient(message);
+            }
+            // The rest of the I/O errors are ffmpeg rejecting its input
+            ProcessingError::ImageLoadFailed(_)
+            | ProcessingError::DecodeFailed(_)
+            | ProcessingError::IoError(_)
+            | ProcessingError::TooManyPixels { .. } => ErrorCode::DecodeFailed,
+            ProcessingError::Unsupported(_) => ErrorCode::UnsupportedFormat,
+            _ => ErrorCode::ProcessingFailed,
+        };
+        Self::Permanent(code, message)
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl From<String> for JobError {
+    fn from(message: String) -> Self {
+        Self::Permanent(ErrorCode::ProcessingFailed, message)
+    }
+}
+
+impl From<&str> for JobError {
+    fn from(message: &str) -> Self {
+        Self::Permanent(ErrorCode::ProcessingFailed, message.to_string())
+    }
+}
+
+impl From<InvalidParams> for JobError {
+    fn from(e: InvalidParams) -> Self {
+        Self::Permanent(ErrorCode::ProcessingFailed, e.to_string())
+    }
+}
+
+/// Wait before retrying a job that has run `attempts` times
+fn retry_delay(attempts: i32) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    RETRY_BASE_DELAY.saturating_mul(1 << doublings).min(RETRY_MAX_DELAY)
+}
+
+/// Spawn `WORKER_CONCURRENCY` workers. Jobs are claimed from the database;
+/// messages from `source` only tell idle workers to look. Once `shutdown` is
+/// cancelled each worker finishes the job in hand and exits, leaving unclaimed
+/// jobs `queued`; await the returned handle to wait for that.
+///
+/// Alongside the workers a heartbeat keeps this process's jobs marked alive
+/// and requeues jobs whose worker has stopped, in this process or another.
+#[allow(clippy::too_many_arguments)]
 pub fn start_worker(
-    mut rx: Receiver<JobMessage>,
+    source: JobSource,
     storage: Arc<dyn Storage>,
     db_pool: sqlx::PgPool,
-    statuses: Arc<Mutex<HashMap<String, JobStatus>>>,
+    queue: Arc<Queue>,
+    statuses: StatusStore,
+    ffmpeg: Arc<Ffmpeg>,
     config: config::Config,
-) {
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
-        let processor = ImageProcessor::new(config.processing.model_path.clone())
+        // One processor (and model session) shared by every worker
+        let processor = ImageProcessor::new(config.processing.model_path.clone(), config.processing.max_image_pixels)
+            .map(Arc::new)
             .expect("Failed to initialize image processor");
 
-        tracing::info!("Worker started and ready to process jobs");
+        let webhooks = config
+            .webhook_secret
+            .clone()
+            .map(|secret| Arc::new(WebhookSender::new(secret)));
+
+        let concurrency = config.processing.worker_concurrency.max(1);
+        let ctx = Arc::new(WorkerContext {
+            storage,
+            db_pool,
+            statuses,
+            processor,
+            ffmpeg,
+            config,
+            webhooks,
+            queue,
+            running: Default::default(),
+        });
+        let wake = Arc::new(Wake::from(source));
+        let heartbeat = tokio::spawn(heartbeat(ctx.clone()));
+
+        let mut workers = JoinSet::new();
+        for _ in 0..concurrency {
+            let ctx = ctx.clone();
+            let wake = wake.clone();
+            let shutdown = shutdown.clone();
+            workers.spawn(async move {
+                run_until_shutdown(
+                    &wake,
+                    &shutdown,
+                    || claim_next(&ctx.db_pool),
+                    |job| {
+                        // Worker log lines carry the ID of the request that created the job,
+                        // and its exported spans continue that request's trace
+                        let span = tracing::info_span!(
+                            "job",
+                            job_id = %job.id,
+                            job_type = %job.job_type,
+                            request_id = job.request_id(),
+                            otel.kind = "consumer",
+                        );
+                        if let Some(trace_context) = job.trace_context() {
+                            crate::otel::set_job_parent(&span, trace_context);
+                        }
+                        run_job(job, &ctx).instrument(span)
+                    },
+                )
+                .await;
+            });
+        }
 
-        while let Some(job) = rx.recv().await {
-            tracing::info!("Worker processing job {} (type: {})", job.job_id, job.job_type);
+        tracing::info!("{} worker(s) started and ready to process jobs", concurrency);
 
-            // Update status to processing
-            {
-                let mut s = statuses.lock().await;
-                s.insert(job.job_id.clone(), JobStatus::Processing { progress: 0 });
+        while let Some(result) = workers.join_next().await {
+            if let Err(e) = result {
+                tracing::error!("Worker task failed: {:?}", e);
             }
+        }
+        // Every job in hand has finished
+        heartbeat.abort();
 
-            let job_uuid = match Uuid::parse_str(&job.job_id) {
-                Ok(id) => id,
-                Err(e) => {
-                    tracing::error!("Invalid job UUID {}: {}", job.job_id, e);
-                    continue;
-                }
-            };
+        if shutdown.is_cancelled() {
+            tracing::info!("Workers stopped after shutdown");
+        } else {
+            tracing::info!("Worker exiting - channel closed");
+        }
+    })
+}
 
-            // Update database
-            if let Err(e) = db::Job::update_progress(&db_pool, job_uuid, "processing", 0).await {
-                tracing::error!("Failed to update job status: {:?}", e);
+/// Every `HEARTBEAT_INTERVAL`, mark the jobs this process is running as alive,
+/// recover any whose worker has gone quiet and announce again queued jobs no
+/// worker has taken. Runs until aborted.
+async fn heartbeat(ctx: Arc<WorkerContext>) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let running: Vec<Uuid> = ctx.running.lock().unwrap().iter().copied().collect();
+        if !running.is_empty() {
+            if let Err(e) = db::Job::heartbeat(&ctx.db_pool, &running).await {
+                tracing::warn!("Failed to record heartbeat for {} job(s): {:?}", running.len(), e);
             }
+        }
 
-            // Process job based on type
-            let result = match job.job_type.as_str() {
-                "remove_bg" => {
-                    process_background_removal(
-                        &job,
-                        &db_pool,
-                        &storage,
-                        &processor,
-                        &statuses,
-                    ).await
-                }
-                "convert" => {
-                    process_conversion(
-                        &job,
-                        &db_pool,
-                        &storage,
-                        &processor,
-                        &statuses,
-                    ).await
-                }
-                "color_grade" => {
-                    process_color_grade(
-                        &job,
-                        &db_pool,
-                        &storage,
-                        &processor,
-                        &statuses,
-                    ).await
-                }
-                _ => {
-                    tracing::error!("Unknown job type: {}", job.job_type);
-                    Err("Unknown job type".to_string())
-                }
-            };
+        match recover_jobs(&ctx).await {
+            Ok(0) => {}
+            Ok(recovered) => tracing::warn!("Recovered {} job(s) whose worker stopped", recovered),
+            Err(e) => tracing::error!("Failed to recover abandoned jobs: {:?}", e),
+        }
+
+        let stale_after = ctx.config.processing.queued_stale_after();
+        if let Err(e) = ctx.queue.reannounce_stalled(&ctx.db_pool, stale_after, None).await {
+            tracing::error!("Failed to look for stalled jobs: {:?}", e);
+        }
+    }
+}
 
-            // Update final status
-            match result {
-                Ok(result_location) => {
-                    let mut s = statuses.lock().await;
-                    s.insert(
-                        job.job_id.clone(),
-                        JobStatus::Completed {
-                            result_url: result_location.clone(),
-                        },
-                    );
-                    drop(s);
-
-                    if let Err(e) = db::Job::complete(&db_pool, job_uuid, &result_location).await {
-                        tracing::error!("Failed to mark job as complete: {:?}", e);
-                    }
-
-                    tracing::info!("Job {} completed successfully", job.job_id);
+/// Claim and run jobs until `shutdown` is cancelled or the wake-up source
+/// closes. Queued work is swept first, so jobs left over from before a restart
+/// start without waiting for a new enqueue. A claimed job always runs to
+/// completion; anything not yet claimed stays queued for the next run.
+async fn run_until_shutdown<T, C, CF, H, HF>(
+    wake: &Wake,
+    shutdown: &CancellationToken,
+    mut claim: C,
+    mut handle: H,
+) where
+    C: FnMut() -> CF,
+    CF: Future<Output = Option<T>>,
+    H: FnMut(T) -> HF,
+    HF: Future<Output = ()>,
+{
+    while !shutdown.is_cancelled() {
+        if let Some(job) = claim().await {
+            handle(job).await;
+            continue;
+        }
+
+        // Nothing queued. Only one idle worker waits for a wake-up at a time;
+        // it stops waiting before claiming so the others can look too.
+        tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => break,
+            open = wake.wait() => {
+                if !open {
+                    return;
                 }
-                Err(error) => {
-                    let mut s = statuses.lock().await;
-                    s.insert(
-                        job.job_id.clone(),
-                        JobStatus::Failed {
-                            error: error.clone(),
-                        },
-                    );
-                    drop(s);
-
-                    if let Err(e) = db::Job::fail(&db_pool, job_uuid, &error).await {
-                        tracing::error!("Failed to mark job as failed: {:?}", e);
-                    }
-
-                    tracing::error!("Job {} failed: {}", job.job_id, error);
+            }
+        }
+    }
+}
+
+async fn claim_next(db_pool: &sqlx::PgPool) -> Option<db::Job> {
+    match db::Job::claim_next(db_pool).await {
+        Ok(job) => job,
+        Err(e) => {
+            tracing::error!("Failed to claim next job: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Put `processing` jobs whose worker has stopped sending heartbeats back to
+/// `queued`, or fail those out of attempts and send their webhook. Jobs held
+/// by a live worker, in any process, are left alone.
+async fn recover_jobs(ctx: &WorkerContext) -> Result<usize, sqlx::Error> {
+    let recovered = db::Job::reset_stale(&ctx.db_pool, HEARTBEAT_STALE_AFTER).await?;
+    for job in &recovered {
+        let detail = serde_json::json!({ "attempt": job.attempts, "requeued": job.status == "queued" });
+        db::JobEvent::record(&ctx.db_pool, job.id, "worker_lost", Some(detail)).await;
+    }
+    for job in recovered.iter().filter(|job| job.status == "failed") {
+        let error = job.error_message.clone().unwrap_or_default();
+        tracing::error!("Job {} failed after {} attempt(s): {}", job.id, job.attempts, error);
+        ctx.statuses.set(&job.id.to_string(), JobStatus::Failed { error }).await;
+        quota::refund_failed(&ctx.db_pool, &ctx.config.quotas, job.id).await;
+        tokio::spawn(notify_webhook(ctx.db_pool.clone(), ctx.webhooks.clone(), job.id));
+    }
+    Ok(recovered.len())
+}
+
+/// How a run of a job ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Completed,
+    /// Failed transiently and went back to the queue
+    Retried,
+    Failed,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::Retried => "retried",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Run a claimed job, recording it in the job metrics
+async fn run_job(job: db::Job, ctx: &WorkerContext) {
+    let tier = match db::User::find_by_id(&ctx.db_pool, job.user_id).await {
+        Ok(Some(user)) => user.subscription_tier,
+        _ => "unknown".to_string(),
+    };
+    let retention = quota::result_retention(&ctx.config.quotas, &tier);
+    let [job_type, tier] = telemetry::job_labels(&job.job_type, &tier);
+
+    let job_id = job.id;
+    ctx.running.lock().unwrap().insert(job_id);
+    metrics::gauge!(telemetry::JOBS_IN_FLIGHT).increment(1.0);
+    let started = Instant::now();
+    let outcome = process_job(job, retention, ctx).await;
+    metrics::gauge!(telemetry::JOBS_IN_FLIGHT).decrement(1.0);
+    ctx.running.lock().unwrap().remove(&job_id);
+
+    metrics::histogram!(
+        telemetry::JOB_DURATION,
+        &[job_type.clone(), tier.clone(), ("outcome", outcome.as_str().to_string())]
+    )
+    .record(started.elapsed().as_secs_f64());
+    let counter = match outcome {
+        Outcome::Completed => telemetry::JOBS_COMPLETED,
+        Outcome::Retried => telemetry::JOBS_RETRIED,
+        Outcome::Failed => telemetry::JOBS_FAILED,
+    };
+    metrics::counter!(counter, &[job_type, tier]).increment(1);
+}
+
+/// Run a job and record its outcome. A result is kept for `retention`.
+async fn process_job(job: db::Job, retention: chrono::Duration, ctx: &WorkerContext) -> Outcome {
+    let job_id = job.id.to_string();
+    tracing::info!("Worker processing job {} (type: {})", job_id, job.job_type);
+
+    // The row was moved to `processing` when the job was claimed
+    let detail = serde_json::json!({ "attempt": job.attempts, "max_attempts": job.max_attempts });
+    db::JobEvent::record(&ctx.db_pool, job.id, "started", Some(detail)).await;
+    let reporter = ProgressReporter::new(&ctx.statuses, &ctx.db_pool, job.id);
+    reporter.report(0).await;
+
+    let timeout = ctx.config.processing.job_timeout(&job.job_type);
+    let started = Instant::now();
+    let processing = dispatch(&job, ctx, &reporter).instrument(tracing::info_span!("process"));
+    let result = with_timeout(timeout, &temp_dir(&ctx.config), &job_id, processing).await;
+    let duration = started.elapsed();
+
+    // Update final status
+    let outcome = match result {
+        Ok(saved) => {
+            let outputs: Vec<db::JobOutput> =
+                saved.iter().enumerate().map(|(index, output)| output.record(job.id, index)).collect();
+            ctx.statuses
+                .set(
+                    &job_id,
+                    JobStatus::Completed {
+                        result_url: outputs[0].location.clone(),
+                    },
+                )
+                .await;
+
+            let result = saved[0].result(duration);
+            match db::Job::complete(&ctx.db_pool, job.id, &result, &outputs, retention).await {
+                Ok(true) => {
+                    let detail = serde_json::json!({ "outputs": outputs.len(), "duration_ms": result.duration_ms });
+                    db::JobEvent::record(&ctx.db_pool, job.id, "completed", Some(detail)).await;
                 }
+                // The account was deleted while the job ran; nothing refers to the outputs
+                Ok(false) => delete_outputs(ctx.storage.as_ref(), &saved).await,
+                Err(e) => tracing::error!("Failed to mark job as complete: {:?}", e),
             }
+
+            tracing::info!("Job {} completed successfully", job_id);
+            Outcome::Completed
+        }
+        Err(error) if error.is_transient() && job.attempts < job.max_attempts => {
+            let delay = retry_delay(job.attempts);
+            ctx.statuses.set(&job_id, JobStatus::Queued).await;
+
+            // No wake-up needed: idle workers poll and will claim it once the delay is up
+            let requeued = db::Job::requeue(&ctx.db_pool, job.id, delay, error.message(), error.code().as_str()).await;
+            if let Err(e) = requeued {
+                tracing::error!("Failed to requeue job: {:?}", e);
+            }
+            let detail = serde_json::json!({
+                "attempt": job.attempts,
+                "delay_secs": delay.as_secs(),
+                "error": error.message(),
+                "error_code": error.code().as_str(),
+            });
+            db::JobEvent::record(&ctx.db_pool, job.id, "retry_scheduled", Some(detail)).await;
+
+            tracing::warn!(
+                "Job {} failed on attempt {}/{}, retrying in {:?}: {}",
+                job_id,
+                job.attempts,
+                job.max_attempts,
+                delay,
+                error
+            );
+            // Not finished yet, so no webhook
+            return Outcome::Retried;
+        }
+        Err(error) => {
+            let code = error.code();
+            let error = error.to_string();
+            ctx.statuses
+                .set(
+                    &job_id,
+                    JobStatus::Failed {
+                        error: error.clone(),
+                    },
+                )
+                .await;
+
+            if let Err(e) = db::Job::fail(&ctx.db_pool, job.id, &error, code.as_str()).await {
+                tracing::error!("Failed to mark job as failed: {:?}", e);
+            }
+            let detail = serde_json::json!({ "attempt": job.attempts, "error": error, "error_code": code.as_str() });
+            db::JobEvent::record(&ctx.db_pool, job.id, "failed", Some(detail)).await;
+            quota::refund_failed(&ctx.db_pool, &ctx.config.quotas, job.id).await;
+
+            tracing::error!("Job {} failed: {}", job_id, error);
+            Outcome::Failed
+        }
+    };
+
+    // Deliver the completion webhook without holding up the next job
+    tokio::spawn(notify_webhook(ctx.db_pool.clone(), ctx.webhooks.clone(), job.id).in_current_span());
+    outcome
+}
+
+/// Process a job according to its type, returning its outputs, primary first
+async fn dispatch(
+    job: &db::Job,
+    ctx: &WorkerContext,
+    reporter: &ProgressReporter<'_>,
+) -> Result<Vec<SavedOutput>, JobError> {
+    match job.job_type.as_str() {
+        "remove_bg" => {
+            process_background_removal(
+                job,
+                &ctx.db_pool,
+                &ctx.storage,
+                &ctx.processor,
+                &ctx.ffmpeg,
+                reporter,
+                &ctx.config,
+            ).await.map(|saved| vec![saved])
+        }
+        "convert" => {
+            process_conversion(
+                job,
+                &ctx.db_pool,
+                &ctx.storage,
+                &ctx.processor,
+                &ctx.ffmpeg,
+                reporter,
+                &ctx.config,
+            ).await
+        }
+        "color_grade" => {
+            process_color_grade(
+                job,
+                &ctx.db_pool,
+                &ctx.storage,
+                &ctx.processor,
+                &ctx.ffmpeg,
+                reporter,
+                &ctx.config,
+            ).await.map(|saved| vec![saved])
+        }
+        "analyze" => {
+            process_analysis(
+                job,
+                &ctx.db_pool,
+                &ctx.storage,
+                &ctx.processor,
+                &ctx.ffmpeg,
+                reporter,
+                &ctx.config,
+            ).await.map(|saved| vec![saved])
+        }
+        "lut_generate" => {
+            process_lut_generate(
+                job,
+                &ctx.db_pool,
+                &ctx.storage,
+                &ctx.processor,
+                &ctx.ffmpeg,
+                reporter,
+                &ctx.config,
+            ).await.map(|saved| vec![saved])
         }
+        "pipeline" => {
+            process_pipeline(
+                job,
+                &ctx.db_pool,
+                &ctx.storage,
+                &ctx.processor,
+                &ctx.ffmpeg,
+                reporter,
+                &ctx.config,
+            ).await.map(|saved| vec![saved])
+        }
+        _ => {
+            tracing::error!("Unknown job type: {}", job.job_type);
+            Err(JobError::from("Unknown job type"))
+        }
+    }
+}
+
+/// Run a job's work, failing it once it has taken `limit`. Dropping the work
+/// kills any ffmpeg it started and removes the temp files it holds; whatever
+/// else it left in `temp_dir` is found by the job ID in its name.
+async fn with_timeout<T>(
+    limit: Duration,
+    temp_dir: &Path,
+    job_id: &str,
+    work: impl Future<Output = Result<T, JobError>>,
+) -> Result<T, JobError> {
+    match tokio::time::timeout(limit, work).await {
+        Ok(result) => result,
+        Err(_) => {
+            remove_job_temp_files(temp_dir, job_id).await;
+            Err(JobError::Permanent(ErrorCode::Timeout, format!("processing timed out after {:?}", limit)))
+        }
+    }
+}
+
+/// Remove the files and directories in `temp_dir` named after `job_id`
+async fn remove_job_temp_files(temp_dir: &Path, job_id: &str) {
+    let Ok(mut entries) = tokio::fs::read_dir(temp_dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry.file_name().to_string_lossy().contains(job_id) {
+            continue;
+        }
+        let path = entry.path();
+        let removed = match entry.file_type().await {
+            Ok(file_type) if file_type.is_dir() => tokio::fs::remove_dir_all(&path).await,
+            _ => tokio::fs::remove_file(&path).await,
+        };
+        if let Err(e) = removed {
+            tracing::warn!("Failed to remove temp file {}: {:?}", path.display(), e);
+        }
+    }
+}
 
-        tracing::info!("Worker exiting - channel closed");
+/// Run image work on the blocking pool like `blocking`, passing the progress
+/// it reports on to `reporter` through `span` while it runs
+async fn blocking_with_progress<T, F>(
+    processor: &Arc<ImageProcessor>,
+    reporter: &ProgressReporter<'_>,
+    span: ProgressSpan,
+    work: F,
+) -> Result<T, ProcessingError>
+where
+    T: Send + 'static,
+    F: FnOnce(&ImageProcessor, OnProgress) -> Result<T, ProcessingError> + Send + 'static,
+{
+    // Only the latest value matters, so a slow status store skips the rest
+    let (sender, mut receiver) = tokio::sync::watch::channel(0);
+    let work = blocking(processor, move |processor| {
+        work(processor, &|percent| {
+            sender.send_replace(percent);
+        })
     });
+    tokio::pin!(work);
+    loop {
+        tokio::select! {
+            result = &mut work => return result,
+            Ok(()) = receiver.changed() => {
+                let percent = *receiver.borrow_and_update();
+                reporter.report(span.at(percent)).await;
+            }
+        }
+    }
+}
+
+/// Run image work on the blocking pool. A timeout only fires at an `.await`,
+/// so work done inline would hold the worker until it finished. Work whose
+/// job timed out runs on unobserved; an output it writes afterwards is left
+/// to the cleanup task's temp file sweep.
+async fn blocking<T, F>(processor: &Arc<ImageProcessor>, work: F) -> Result<T, ProcessingError>
+where
+    T: Send + 'static,
+    F: FnOnce(&ImageProcessor) -> Result<T, ProcessingError> + Send + 'static,
+{
+    let processor = processor.clone();
+    tokio::task::spawn_blocking(move || work(&processor))
+        .await
+        .unwrap_or_else(|e| Err(ProcessingError::IoError(std::io::Error::other(format!("processing task failed: {}", e)))))
 }
 
 async fn process_background_removal(
-    job: &JobMessage,
+    job: &db::Job,
     db_pool: &sqlx::PgPool,
     storage: &Arc<dyn Storage>,
-    processor: &ImageProcessor,
-    statuses: &Arc<Mutex<HashMap<String, JobStatus>>>,
-) -> Result<String, String> {
-    // Get job details from database
-    let job_uuid = Uuid::parse_str(&job.job_id).map_err(|e| e.to_string())?;
-    let job_record = db::Job::find_by_id(db_pool, job_uuid)
-        .await
-        .map_err(|e| format!("Failed to fetch job: {:?}", e))?
-        .ok_or("Job not found")?;
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    config: &config::Config,
+) -> Result<SavedOutput, JobError> {
+    let job_id = job.id.to_string();
+    let params = RemoveBgParams::from_value(&job.parameters)?;
 
     // Get media asset IDs
-    let asset_ids: Vec<String> = serde_json::from_value(job_record.media_asset_ids)
+    let asset_ids: Vec<String> = serde_json::from_value(job.media_asset_ids.clone())
         .map_err(|e| format!("Invalid asset IDs: {}", e))?;
 
     if asset_ids.is_empty() {
-        return Err("No assets in job".to_string());
+        return Err("No assets in job".into());
     }
 
     let asset_id = Uuid::parse_str(&asset_ids[0]).map_err(|e| e.to_string())?;
 
     // Get asset location from database
     let asset = sqlx::query_as::<_, db::MediaAsset>(
-        "SELECT * FROM media_assets WHERE id = $1"
+        "SELECT * FROM media_assets WHERE id = $1 AND deleted_at IS NULL"
     )
     .bind(asset_id)
     .fetch_optional(db_pool)
     .await
-    .map_err(|e| format!("Failed to fetch asset: {:?}", e))?
+    .map_err(|e| JobError::Transient(format!("Failed to fetch asset: {:?}", e)))?
     .ok_or("Asset not found")?;
 
-    let input_path = std::path::PathBuf::from(&asset.result_location.unwrap_or(asset.original_filename.clone()));
-    let output_filename = format!("processed_{}.png", job.job_id);
-    let output_path = std::env::temp_dir().join(&output_filename);
+    let input_location = stored_location(&asset)?;
+    let input = fetch_input(storage, &input_location, &temp_dir(config), &job_id).await?;
+
+    let output_stem = format!("processed_{}", job_id);
+    let output = TempFile(
+        remove_background_step(
+            &job_id,
+            db_pool,
+            &params,
+            &input,
+            &output_stem,
+            storage,
+            processor,
+            ffmpeg,
+            reporter,
+            config,
+            ProgressSpan::FULL,
+        )
+        .await?,
+    );
 
-    // Update progress
-    update_progress(statuses, &job.job_id, 20).await;
+    let saved = save_output(storage, ffmpeg, job.user_id, &output).await;
 
-    // Check if we should replace background
-    let replace_color: Option<[u8; 3]> = job_record
-        .parameters
-        .get("replace_color")
-        .and_then(|v| serde_json::from_value(v.clone()).ok());
+    reporter.report(100).await;
 
-    // Process image or video
-    let lower = input_path.to_string_lossy().to_lowercase();
-    let is_video = lower.ends_with(".mp4") || lower.ends_with(".mov") || lower.ends_with(".avi") || lower.ends_with(".webm");
+    saved
+}
+
+/// What a remove_bg job puts behind the subject
+#[derive(Debug, Clone, PartialEq)]
+enum BackgroundMode {
+    Transparent,
+    Color([u8; 3]),
+    /// The image itself, blurred with this sigma
+    Blur(f32),
+    /// Another image, staged at this path
+    Image(PathBuf),
+}
+
+impl BackgroundMode {
+    /// `background` is the staged background image of the `image` mode.
+    /// Jobs queued before `mode` existed only have `replace_color`. A
+    /// `flatten_color` turns a transparent result for JPEG into a colored one.
+    fn from_params(params: &RemoveBgParams, background: Option<&Path>) -> Self {
+        if let Some(background) = background {
+            return Self::Image(background.to_path_buf());
+        }
+        match (params.mode.as_deref(), params.replace_color) {
+            (Some("blur"), _) => Self::Blur(params.blur_sigma.unwrap_or(DEFAULT_BLUR_SIGMA)),
+            (Some("transparent"), _) | (_, None) => params.flatten_color.map_or(Self::Transparent, Self::Color),
+            (_, Some(color)) => Self::Color(color),
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        match self {
+            Self::Transparent => "Background removal",
+            Self::Color(_) | Self::Image(_) => "Background replacement",
+            Self::Blur(_) => "Background blur",
+        }
+    }
+
+    fn apply(
+        &self,
+        processor: &ImageProcessor,
+        input: &Path,
+        output: &Path,
+        trim: Option<f32>,
+        on_progress: OnProgress,
+    ) -> Result<Trimmed, ProcessingError> {
+        match self {
+            Self::Transparent => processor.remove_background(input, output, trim, on_progress),
+            Self::Color(color) => processor.replace_background(input, output, *color, trim, on_progress),
+            Self::Blur(sigma) => processor.blur_background(input, output, *sigma, trim, on_progress),
+            Self::Image(background) => {
+                processor.replace_background_with_image(input, output, background, trim, on_progress)
+            }
+        }
+    }
+}
+
+/// Remove (or replace) the background of a staged input. Images produce
+/// `<temp_dir>/<output_stem>.png`, or `.webp` / `.jpg` as requested; videos
+/// a WebM or a zip of PNG frames.
+#[allow(clippy::too_many_arguments)]
+async fn remove_background_step(
+    job_id: &str,
+    db_pool: &sqlx::PgPool,
+    params: &RemoveBgParams,
+    input_path: &Path,
+    output_stem: &str,
+    storage: &Arc<dyn Storage>,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    config: &config::Config,
+    progress: ProgressSpan,
+) -> Result<PathBuf, JobError> {
+    let temp_dir = temp_dir(config);
+    let background = match params.background_location.as_deref() {
+        Some(location) => Some(
+            fetch_input(storage, &parse_location(location)?, &temp_dir, job_id)
+                .await
+                .map_err(|e| e.context("Background image"))?,
+        ),
+        None => None,
+    };
+    let mode = BackgroundMode::from_params(params, background.as_deref());
+
+    let is_video = is_video_path(input_path);
+
+    let output_format = params.output_format.as_deref();
+    let video_output = VideoOutput::for_format(output_format);
+    let extension = if is_video { video_output.extension() } else { cut_out_extension(output_format) };
+    let output_path = temp_dir.join(format!("{}.{}", output_stem, extension));
 
+    // Process image or video
     if is_video {
-        // For MVP, extract first frame and remove background on it
-        processor
-            .remove_background_from_video(&input_path, &output_path)
-            .map_err(|e| format!("Background removal failed (video): {:?}", e))?;
+        remove_video_background(
+            job_id,
+            processor,
+            ffmpeg,
+            reporter,
+            input_path,
+            &output_path,
+            &temp_dir,
+            video_output,
+            mode,
+            config.processing.max_video_duration_seconds,
+            progress,
+        )
+        .await?;
     } else {
-        if let Some(color) = replace_color {
-            processor
-                .replace_background(&input_path, &output_path, color)
-                .map_err(|e| format!("Background replacement failed: {:?}", e))?;
+        reporter.report(progress.at(20)).await;
+        let (input, output) = (input_path.to_path_buf(), output_path.clone());
+        let span = progress.within(20, 80);
+        let action = mode.action();
+        let trim = params.trim.then(|| params.trim_padding.unwrap_or(0.0));
+        let trimmed = blocking_with_progress(processor, reporter, span, move |processor, on_progress| {
+            mode.apply(processor, &input, &output, trim, on_progress)
+        })
+        .await
+        .map_err(|e| JobError::processing(&e, format!("{} failed: {:?}", action, e)))?;
+        note_trimmed(db_pool, job_id, trimmed).await;
+        // Videos already reported per-frame progress up to 90%
+        reporter.report(progress.at(80)).await;
+    }
+
+    Ok(output_path)
+}
+
+/// Extension of an image background removal result: PNG unless WebP or
+/// JPEG was asked for
+fn cut_out_extension(output_format: Option<&str>) -> &'static str {
+    match output_format.map(str::to_lowercase).as_deref() {
+        Some("webp") => "webp",
+        Some("jpg" | "jpeg") => "jpg",
+        _ => "png",
+    }
+}
+
+/// Per-frame background removal: split the video into PNG frames with ffmpeg,
+/// process each frame, then reassemble. Progress runs 10% → 90% of the span over the frames.
+#[allow(clippy::too_many_arguments)]
+async fn remove_video_background(
+    job_id: &str,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    input_path: &Path,
+    output_path: &Path,
+    temp_dir: &Path,
+    output: VideoOutput,
+    mode: BackgroundMode,
+    max_duration_seconds: u32,
+    progress: ProgressSpan,
+) -> Result<(), JobError> {
+    ffmpeg.require().map_err(|e| e.to_string())?;
+
+    let info = probe::probe_video(ffmpeg, input_path)
+        .await
+        .map_err(|e| format!("Could not read video: {}", e))?
+        .unwrap_or_default();
+    if let Some(duration) = info.duration_seconds {
+        if duration > max_duration_seconds as f64 {
+            return Err(format!(
+                "Video too long: {:.1}s (max {}s)",
+                duration, max_duration_seconds
+            )
+            .into());
+        }
+    }
+
+    let frames_dir = temp_dir.join(format!("frames_{}", job_id));
+    let result = async {
+        let frames = video::extract_frames(ffmpeg, input_path, &frames_dir)
+            .await
+            .map_err(|e| JobError::processing(&e, format!("Frame extraction failed: {}", e)))?;
+        if frames.is_empty() {
+            return Err("Video contains no frames".into());
+        }
+        reporter.report(progress.at(10)).await;
+
+        let total = frames.len();
+        let action = mode.action();
+        for (i, frame) in frames.iter().enumerate() {
+            let frame = frame.clone();
+            let mode = mode.clone();
+            blocking(processor, move |processor| mode.apply(processor, &frame, &frame, None, &|_| {}))
+                .await
+                .map_err(|e| {
+                    JobError::processing(&e, format!("{} failed on frame {}/{}: {}", action, i + 1, total, e))
+                })?;
+
+            reporter.report(progress.at(10 + (80 * (i + 1) / total) as u32)).await;
+        }
+
+        match output {
+            VideoOutput::WebmAlpha => {
+                let frame_rate = info.frame_rate.unwrap_or(video::DEFAULT_FRAME_RATE);
+                video::encode_webm_alpha(ffmpeg, &frames_dir, frame_rate, input_path, output_path).await
+            }
+            VideoOutput::PngSequence => video::zip_frames(&frames, output_path),
+        }
+        .map_err(|e| JobError::processing(&e, format!("Failed to assemble video: {}", e)))
+    }
+    .await;
+
+    tokio::fs::remove_dir_all(&frames_dir).await.ok();
+    result
+}
+
+/// An output named after the upload it came from, with the converted
+/// extension. The upload's name is the client's, so only its safe characters
+/// are kept, as for stored objects.
+fn batch_output_name(original: &str, output: &Path) -> String {
+    let stem = Path::new(original).file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    let ext = output.extension().and_then(|s| s.to_str()).unwrap_or_default();
+    format!("{}.{}", storage::sanitize_filename(stem), ext)
+}
+
+async fn process_conversion(
+    job: &db::Job,
+    db_pool: &sqlx::PgPool,
+    storage: &Arc<dyn Storage>,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    config: &config::Config,
+) -> Result<Vec<SavedOutput>, JobError> {
+    let job_id = job.id.to_string();
+    let temp_dir = temp_dir(config);
+
+    let asset_ids: Vec<String> = serde_json::from_value(job.media_asset_ids.clone())
+        .map_err(|e| format!("Invalid asset IDs: {}", e))?;
+    let params = ConvertParams::from_value(&job.parameters)?;
+
+    if asset_ids.is_empty() {
+        return Err("No assets in job".into());
+    }
+
+    if let [asset_id] = asset_ids.as_slice() {
+        let asset = load_asset(db_pool, asset_id).await?;
+        let output_stem = format!("converted_{}", job_id);
+        let output = TempFile(
+            convert_asset(
+                &job_id,
+                db_pool,
+                &params,
+                &asset,
+                &output_stem,
+                &temp_dir,
+                storage,
+                processor,
+                ffmpeg,
+                reporter,
+                ProgressSpan::FULL,
+            )
+            .await?,
+        );
+
+        let saved = save_output(storage, ffmpeg, job.user_id, &output).await?;
+        reporter.report(100).await;
+        return Ok(vec![saved]);
+    }
+
+    // Batch: convert each asset in turn, keep going past failures and keep
+    // whatever succeeded, one output per asset. Assets share the 5..90% range in equal slices.
+    let total = asset_ids.len().max(1) as u32;
+    let mut results = serde_json::Map::new();
+    let mut outputs: Vec<(String, PathBuf)> = Vec::new();
+    let mut any_transient = false;
+    let mut codes = Vec::new();
+
+    for (i, asset_id) in asset_ids.iter().enumerate() {
+        let i = i as u32;
+        let span = ProgressSpan {
+            start: 5 + 85 * i / total,
+            end: 5 + 85 * (i + 1) / total,
+        };
+
+        let converted = match load_asset(db_pool, asset_id).await {
+            Ok(asset) => {
+                let output_stem = format!("converted_{}_{}", job_id, asset.id);
+                convert_asset(
+                    &job_id,
+                    db_pool,
+                    &params,
+                    &asset,
+                    &output_stem,
+                    &temp_dir,
+                    storage,
+                    processor,
+                    ffmpeg,
+                    reporter,
+                    span,
+                )
+                .await
+                .map(|path| (asset.original_filename, path))
+            }
+            Err(e) => Err(e),
+        };
+
+        match converted {
+            Ok(output) => {
+                results.insert(asset_id.clone(), serde_json::json!({ "status": "completed" }));
+                outputs.push(output);
+            }
+            Err(error) => {
+                tracing::warn!("Job {}: asset {} failed: {}", job_id, asset_id, error);
+                any_transient |= error.is_transient();
+                codes.push(error.code());
+                results.insert(
+                    asset_id.clone(),
+                    serde_json::json!({
+                        "status": "failed",
+                        "error": error.message(),
+                        "error_code": error.code().as_str(),
+                    }),
+                );
+            }
+        }
+        reporter.report(span.end).await;
+    }
+
+    if let Err(e) =
+        db::Job::set_parameter(db_pool, job.id, "asset_results", serde_json::Value::Object(results)).await
+    {
+        tracing::error!("Failed to record per-asset results for job {}: {:?}", job_id, e);
+    }
+
+    if outputs.is_empty() {
+        let message = format!("Conversion failed for all {} assets", asset_ids.len());
+        // Worth another go if any of them might succeed next time
+        return Err(if any_transient {
+            JobError::Transient(message)
         } else {
-            processor
-                .remove_background(&input_path, &output_path)
-                .map_err(|e| format!("Background removal failed: {:?}", e))?;
+            // Every asset failing the same way fails the job that way
+            let same = codes.windows(2).all(|pair| pair[0] == pair[1]);
+            let code = if same { codes[0] } else { ErrorCode::ProcessingFailed };
+            JobError::Permanent(code, message)
+        });
+    }
+
+    let names: Vec<String> = outputs.iter().map(|(original, path)| batch_output_name(original, path)).collect();
+    let names = archive::unique_entry_names(names.iter().map(String::as_str));
+
+    let mut saved = Vec::with_capacity(outputs.len());
+    let mut failed = None;
+    for (filename, (_, path)) in names.into_iter().zip(&outputs) {
+        if failed.is_none() {
+            match save_output(storage, ffmpeg, job.user_id, path).await {
+                Ok(output) => saved.push(SavedOutput { filename, ..output }),
+                Err(e) => failed = Some(e),
+            }
         }
+        std::fs::remove_file(path).ok();
+    }
+    if let Some(error) = failed {
+        // Nothing refers to the outputs saved so far
+        delete_outputs(storage.as_ref(), &saved).await;
+        return Err(error);
     }
 
-    update_progress(statuses, &job.job_id, 80).await;
+    reporter.report(100).await;
+    Ok(saved)
+}
 
-    // Save result to storage
-    let result_bytes = std::fs::read(&output_path)
-        .map_err(|e| format!("Failed to read result: {}", e))?;
+/// Slice of the overall job progress that one asset's work maps onto
+#[derive(Debug, Clone, Copy)]
+struct ProgressSpan {
+    start: u32,
+    end: u32,
+}
 
-    let result_location = storage
-        .save_bytes(&result_bytes, &output_filename)
-        .map_err(|e| format!("Failed to save result: {:?}", e))?;
+impl ProgressSpan {
+    const FULL: Self = Self { start: 0, end: 100 };
 
-    // Cleanup temp file
-    std::fs::remove_file(&output_path).ok();
+    /// Overall progress for `percent` of the way through this span
+    fn at(self, percent: u32) -> u32 {
+        self.start + (self.end - self.start) * percent.min(100) / 100
+    }
 
-    update_progress(statuses, &job.job_id, 100).await;
+    /// The part of this span from `start`% to `end`% of the way through
+    fn within(self, start: u32, end: u32) -> Self {
+        Self { start: self.at(start), end: self.at(end) }
+    }
+}
 
-    Ok(result_location)
+/// Convert one asset according to the job parameters, leaving the result at
+/// `<temp_dir>/<output_stem>.<format>`. The input is staged in `temp_dir` and
+/// removed afterwards.
+#[allow(clippy::too_many_arguments)]
+async fn convert_asset(
+    job_id: &str,
+    db_pool: &sqlx::PgPool,
+    params: &ConvertParams,
+    asset: &db::MediaAsset,
+    output_stem: &str,
+    temp_dir: &Path,
+    storage: &Arc<dyn Storage>,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    progress: ProgressSpan,
+) -> Result<PathBuf, JobError> {
+    let input_location = stored_location(asset)?;
+    let input = fetch_input(storage, &input_location, temp_dir, job_id).await?;
+
+    convert_step(
+        job_id,
+        db_pool,
+        params,
+        &input,
+        output_stem,
+        temp_dir,
+        storage,
+        processor,
+        ffmpeg,
+        reporter,
+        progress,
+    )
+    .await
 }
 
-async fn process_conversion(
-    job: &JobMessage,
+/// Convert a staged input to `<temp_dir>/<output_stem>.<format>`. LUTs staged
+/// along the way are cleaned up whether or not the conversion succeeds. An
+/// animated input flattened to a still image is noted on the job as
+/// `frames_dropped`.
+#[allow(clippy::too_many_arguments)]
+async fn convert_step(
+    job_id: &str,
     db_pool: &sqlx::PgPool,
+    params: &ConvertParams,
+    input_path: &Path,
+    output_stem: &str,
+    temp_dir: &Path,
     storage: &Arc<dyn Storage>,
-    processor: &ImageProcessor,
-    statuses: &Arc<Mutex<HashMap<String, JobStatus>>>,
-) -> Result<String, String> {
-    let job_uuid = Uuid::parse_str(&job.job_id).map_err(|e| e.to_string())?;
-    let job_record = db::Job::find_by_id(db_pool, job_uuid)
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    progress: ProgressSpan,
+) -> Result<PathBuf, JobError> {
+    let kind = if is_video_path(input_path) {
+        MediaKind::Video
+    } else {
+        MediaKind::Image
+    };
+
+    // Get conversion parameters. The route validates too; this guards jobs
+    // queued before validation existed, since the format becomes a file extension.
+    let output_format = formats::validate_output_format(params.output_format.as_deref().unwrap_or("png"), kind)
+    .map_err(|e| format!("Conversion failed: {}", e))?;
+    // Images are encoded explicitly rather than inferred from the file extension
+    let image_format = match kind {
+        MediaKind::Image => Some(
+            formats::image_format(&output_format).map_err(|e| format!("Conversion failed: {}", e))?,
+        ),
+        MediaKind::Video => None,
+    };
+
+    let (width, height) = (params.width, params.height);
+
+    let output_path = temp_dir.join(format!("{}.{}", output_stem, output_format));
+    let lut = job_lut(db_pool, params.lut_id, params.lut_location.as_deref()).await?;
+
+    if let Some(image_format) = image_format {
+        let lut_path = match &lut {
+            Some(lut) => Some(fetch_lut(storage, lut, temp_dir, job_id).await?),
+            None => None,
+        };
+
+        reporter.report(progress.at(30)).await;
+
+        // Convert image
+        let encoding = OutputEncoding {
+            format: image_format,
+            quality: params.quality.map(|v| v.clamp(1, 100) as u8),
+            keep_exif: !params.strip_metadata,
+            target_bytes: params.target_size_kb.map(|kb| u64::from(kb) * 1024),
+        };
+        let (input, output, lut_file) = (input_path.to_path_buf(), output_path.clone(), lut_path.clone());
+        let span = progress.within(30, 80);
+        let processed = blocking_with_progress(processor, reporter, span, move |processor, on_progress| {
+            processor.convert_format(&input, &output, encoding, width, height, lut_file.as_deref(), on_progress)
+        })
+        .await
+        .map_err(|e| match (e, &lut) {
+                (ProcessingError::InvalidLut(e), Some(lut)) => lut_error(lut, &e).into(),
+                (e, _) => JobError::processing(&e, format!("Conversion failed: {:?}", e)),
+            });
+        if let Some(path) = &lut_path {
+            std::fs::remove_file(path).ok();
+        }
+        let converted = processed?;
+        if converted.frames_dropped {
+            note_frames_dropped(db_pool, job_id).await;
+        }
+        if let Some(quality) = converted.quality {
+            note_size_target(db_pool, job_id, quality, converted.target_size_missed).await;
+        }
+        reporter.report(progress.at(80)).await;
+    } else {
+        let options = video::ConvertOptions {
+            format: output_format.clone(),
+            codec: params.video_codec.clone(),
+            quality: params.quality,
+            width,
+            height,
+            keep_metadata: !params.strip_metadata,
+        };
+
+        let processed = if lut.is_some() {
+            Err("Conversion failed: LUTs can only be applied to images".into())
+        } else {
+            convert_video(ffmpeg, reporter, input_path, &output_path, &options, progress).await
+        };
+        if processed.is_err() {
+            std::fs::remove_file(&output_path).ok();
+        }
+        processed?;
+    }
+
+    Ok(output_path)
+}
+
+/// Transcode with ffmpeg. Progress follows the encode from 30% to 90% of the span.
+async fn convert_video(
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    input_path: &Path,
+    output_path: &Path,
+    options: &video::ConvertOptions,
+    progress: ProgressSpan,
+) -> Result<(), JobError> {
+    // Reject impossible conversions before touching ffmpeg
+    options.validate().map_err(|e| format!("Conversion failed: {}", e))?;
+    ffmpeg.require().map_err(|e| e.to_string())?;
+    reporter.report(progress.at(30)).await;
+
+    let duration = probe::probe_video(ffmpeg, input_path)
         .await
-        .map_err(|e| format!("Failed to fetch job: {:?}", e))?
-        .ok_or("Job not found")?;
+        .ok()
+        .flatten()
+        .and_then(|info| info.duration_seconds);
 
-    let asset_ids: Vec<String> = serde_json::from_value(job_record.media_asset_ids)
+    video::convert(ffmpeg, input_path, output_path, options, duration, |percent| {
+        reporter.report(progress.at(30 + percent * 60 / 100))
+    })
+    .await
+    .map_err(|e| JobError::processing(&e, format!("Conversion failed: {}", e)))
+}
+
+/// A result uploaded to storage
+#[derive(Debug)]
+struct SavedOutput {
+    location: StorageLocation,
+    /// Hex SHA-256 of the result, served as its `ETag`
+    etag: String,
+    /// Name to download it under
+    filename: String,
+    size: u64,
+    /// File extension, lowercased
+    format: String,
+    /// Displayed size of an image or video output
+    dimensions: Option<(u32, u32)>,
+}
+
+impl SavedOutput {
+    /// The row recording this as output `index` of `job_id`
+    fn record(&self, job_id: Uuid, index: usize) -> db::JobOutput {
+        db::JobOutput {
+            job_id,
+            output_index: index as i32,
+            location: self.location.to_string(),
+            filename: self.filename.clone(),
+            size_bytes: self.size as i64,
+            content_type: formats::content_type(&self.filename).to_string(),
+            etag: self.etag.clone(),
+        }
+    }
+
+    /// The metadata kept on the job row when this is its primary output
+    fn result(&self, duration: Duration) -> db::JobResult {
+        db::JobResult {
+            location: self.location.to_string(),
+            width: self.dimensions.map(|(width, _)| width),
+            height: self.dimensions.map(|(_, height)| height),
+            size_bytes: self.size as i64,
+            format: self.format.clone(),
+            duration_ms: duration.as_millis() as i64,
+        }
+    }
+}
+
+/// Delete outputs that no job row refers to
+async fn delete_outputs(storage: &dyn Storage, outputs: &[SavedOutput]) {
+    for output in outputs {
+        if let Err(e) = storage.delete(&output.location).await {
+            tracing::warn!("Failed to delete unused output {}: {:?}", output.location, e);
+        }
+    }
+}
+
+/// Upload a finished output under its temp file name, hashing it while the
+/// bytes are in hand and probing its dimensions
+#[tracing::instrument(name = "store_result", skip_all)]
+async fn save_output(
+    storage: &Arc<dyn Storage>,
+    ffmpeg: &Ffmpeg,
+    owner: Uuid,
+    output_path: &Path,
+) -> Result<SavedOutput, JobError> {
+    let result_bytes = std::fs::read(output_path)
+        .map_err(|e| JobError::Transient(format!("Failed to read result: {}", e)))?;
+    let output_filename = output_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let location = storage
+        .save_bytes(&result_bytes, owner, &output_filename)
+        .await
+        .map_err(|e| JobError::storage(&e, format!("Failed to save result: {:?}", e)))?;
+
+    Ok(SavedOutput {
+        location,
+        etag: hex::encode(Sha256::digest(&result_bytes)),
+        filename: output_filename,
+        size: result_bytes.len() as u64,
+        format: output_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default(),
+        dimensions: output_dimensions(ffmpeg, output_path).await,
+    })
+}
+
+/// Width and height of an image or video output; `None` for anything else,
+/// such as a LUT or a zip of frames, or when ffprobe is missing
+async fn output_dimensions(ffmpeg: &Ffmpeg, path: &Path) -> Option<(u32, u32)> {
+    let info = if is_video_path(path) {
+        probe::probe_video(ffmpeg, path).await.ok().flatten()?
+    } else {
+        probe::probe_image(path).ok()?
+    };
+    info.width.zip(info.height)
+}
+
+/// An input asset; one deleted since the job was submitted, say before a
+/// manual retry, counts as gone
+async fn load_asset(db_pool: &sqlx::PgPool, asset_id: &str) -> Result<db::MediaAsset, JobError> {
+    let asset_id = Uuid::parse_str(asset_id).map_err(|e| e.to_string())?;
+    db::MediaAsset::find_by_id(db_pool, asset_id)
+        .await
+        .map_err(|e| JobError::Transient(format!("Failed to fetch asset: {:?}", e)))?
+        .filter(|asset| asset.deleted_at.is_none())
+        .ok_or_else(|| JobError::from("Asset not found"))
+}
+
+async fn process_color_grade(
+    job: &db::Job,
+    db_pool: &sqlx::PgPool,
+    storage: &Arc<dyn Storage>,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    config: &config::Config,
+) -> Result<SavedOutput, JobError> {
+    let job_id = job.id.to_string();
+    let params = ColorGradeParams::from_value(&job.parameters)?;
+
+    let asset_ids: Vec<String> = serde_json::from_value(job.media_asset_ids.clone())
         .map_err(|e| format!("Invalid asset IDs: {}", e))?;
 
     let asset_id = Uuid::parse_str(&asset_ids[0]).map_err(|e| e.to_string())?;
 
     let asset = sqlx::query_as::<_, db::MediaAsset>(
-        "SELECT * FROM media_assets WHERE id = $1"
+        "SELECT * FROM media_assets WHERE id = $1 AND deleted_at IS NULL"
     )
     .bind(asset_id)
     .fetch_optional(db_pool)
     .await
-    .map_err(|e| format!("Failed to fetch asset: {:?}", e))?
+    .map_err(|e| JobError::Transient(format!("Failed to fetch asset: {:?}", e)))?
     .ok_or("Asset not found")?;
 
-    let input_path = std::path::PathBuf::from(&asset.result_location.unwrap_or(asset.original_filename.clone()));
+    let input_location = stored_location(&asset)?;
+    let temp_dir = temp_dir(config);
+    let input = fetch_input(storage, &input_location, &temp_dir, &job_id).await?;
+
+    let output_stem = format!("graded_{}", job_id);
+    let output = TempFile(
+        color_grade_step(
+            &job_id,
+            db_pool,
+            &params,
+            &input,
+            &output_stem,
+            &temp_dir,
+            storage,
+            processor,
+            reporter,
+            ProgressSpan::FULL,
+        )
+        .await?,
+    );
 
-    // Get conversion parameters
-    let output_format: String = job_record
-        .parameters
-        .get("output_format")
-        .and_then(|v| v.as_str())
-        .unwrap_or("png")
-        .to_string();
+    let saved = save_output(storage, ffmpeg, job.user_id, &output).await;
 
-    let width: Option<u32> = job_record
-        .parameters
-        .get("width")
-        .and_then(|v| v.as_u64())
-        .map(|v| v as u32);
+    reporter.report(100).await;
 
-    let height: Option<u32> = job_record
-        .parameters
-        .get("height")
-        .and_then(|v| v.as_u64())
-        .map(|v| v as u32);
+    saved
+}
 
-    let output_filename = format!("converted_{}.{}", job.job_id, output_format);
-    let output_path = std::env::temp_dir().join(&output_filename);
+/// Analyze an image too large for `/api/analyze` to do in the request. The
+/// analysis is cached on the asset and is also the job's JSON result.
+async fn process_analysis(
+    job: &db::Job,
+    db_pool: &sqlx::PgPool,
+    storage: &Arc<dyn Storage>,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    config: &config::Config,
+) -> Result<SavedOutput, JobError> {
+    let job_id = job.id.to_string();
+
+    let asset_ids: Vec<String> = serde_json::from_value(job.media_asset_ids.clone())
+        .map_err(|e| format!("Invalid asset IDs: {}", e))?;
+    let asset = load_asset(db_pool, asset_ids.first().ok_or("No assets in job")?).await?;
 
-    update_progress(statuses, &job.job_id, 30).await;
+    let input_location = stored_location(&asset)?;
+    let data = storage
+        .load_bytes(&input_location)
+        .await
+        .map_err(|e| JobError::storage(&e, format!("Failed to load input: {}", e)))?;
+    reporter.report(20).await;
 
-    // Convert image
-    processor
-        .convert_format(&input_path, &output_path, width, height)
-        .map_err(|e| format!("Conversion failed: {:?}", e))?;
+    let analysis = blocking(processor, move |processor| ImageProcessor::analyze(&data, processor.max_pixels()))
+        .await
+        .map_err(|e| JobError::processing(&e, format!("Analysis failed: {}", e)))?;
+    let analysis = serde_json::to_value(&analysis).map_err(|e| e.to_string())?;
+    reporter.report(80).await;
 
-    update_progress(statuses, &job.job_id, 80).await;
+    db::MediaAsset::store_analysis(db_pool, asset.id, &analysis)
+        .await
+        .map_err(|e| JobError::Transient(format!("Failed to store analysis: {:?}", e)))?;
 
-    // Save result
-    let result_bytes = std::fs::read(&output_path)
-        .map_err(|e| format!("Failed to read result: {}", e))?;
+    let output = TempFile(temp_dir(config).join(format!("analysis_{}.json", job_id)));
+    tokio::fs::write(&*output, analysis.to_string())
+        .await
+        .map_err(|e| JobError::Transient(format!("Failed to write analysis: {}", e)))?;
+    let saved = save_output(storage, ffmpeg, job.user_id, &output).await;
+
+    reporter.report(100).await;
+
+    saved
+}
 
-    let result_location = storage
-        .save_bytes(&result_bytes, &output_filename)
-        .map_err(|e| format!("Failed to save result: {:?}", e))?;
+/// Fit a LUT to a source/graded pair of the same shot. The .cube file is the
+/// job's result, and a copy joins the user's LUT library under the
+/// requested name.
+async fn process_lut_generate(
+    job: &db::Job,
+    db_pool: &sqlx::PgPool,
+    storage: &Arc<dyn Storage>,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    config: &config::Config,
+) -> Result<SavedOutput, JobError> {
+    let job_id = job.id.to_string();
+    let params = LutGenerateParams::from_value(&job.parameters)?;
+
+    let asset_ids: Vec<String> = serde_json::from_value(job.media_asset_ids.clone())
+        .map_err(|e| format!("Invalid asset IDs: {}", e))?;
+    let [source_id, graded_id] = asset_ids.as_slice() else {
+        return Err("LUT generation needs a source and a graded asset".into());
+    };
+    let source = load_asset(db_pool, source_id).await.map_err(|e| e.context("Source image"))?;
+    let graded = load_asset(db_pool, graded_id).await.map_err(|e| e.context("Graded image"))?;
+
+    let temp_dir = temp_dir(config);
+    let source_input = fetch_input(storage, &stored_location(&source)?, &temp_dir, &job_id)
+        .await
+        .map_err(|e| e.context("Source image"))?;
+    let graded_input = fetch_input(storage, &stored_location(&graded)?, &temp_dir, &job_id)
+        .await
+        .map_err(|e| e.context("Graded image"))?;
+    reporter.report(20).await;
 
-    std::fs::remove_file(&output_path).ok();
+    let name = params
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("{}.cube", file_stem(&graded.original_filename)));
+    let title = file_stem(&name).to_string();
 
-    update_progress(statuses, &job.job_id, 100).await;
+    let (source_path, graded_path) = (source_input.to_path_buf(), graded_input.to_path_buf());
+    let lut = blocking(processor, move |processor| processor.generate_lut(&source_path, &graded_path))
+        .await
+        .map_err(|e| JobError::processing(&e, format!("LUT generation failed: {}", e)))?;
+    let cube = lut.with_title(title).to_cube_string();
+    reporter.report(80).await;
 
-    Ok(result_location)
+    let output = TempFile(temp_dir.join(format!("lut_{}.cube", job_id)));
+    tokio::fs::write(&*output, &cube)
+        .await
+        .map_err(|e| JobError::Transient(format!("Failed to write LUT: {}", e)))?;
+    let saved = save_output(storage, ffmpeg, job.user_id, &output).await?;
+
+    // A retry after the library copy was made must not add a second one
+    if params.generated_lut_id.is_none() {
+        let location = storage
+            .save_bytes(cube.as_bytes(), job.user_id, &name)
+            .await
+            .map_err(|e| JobError::storage(&e, format!("Failed to save LUT: {:?}", e)))?;
+        let created = db::LutFile::create(db_pool, job.owner(), &name, &location.to_string(), cube.len() as i64).await;
+        let lut = match created {
+            Ok(lut) => lut,
+            Err(e) => {
+                storage.delete(&location).await.ok();
+                return Err(JobError::Transient(format!("Failed to add LUT to the library: {:?}", e)));
+            }
+        };
+        let lut_id = serde_json::json!(lut.id.to_string());
+        if let Err(e) = db::Job::set_parameter(db_pool, job.id, "generated_lut_id", lut_id).await {
+            tracing::warn!("Failed to record the generated LUT on job {}: {:?}", job_id, e);
+        }
+    }
+
+    reporter.report(100).await;
+
+    Ok(saved)
 }
 
-async fn process_color_grade(
-    job: &JobMessage,
+fn file_stem(filename: &str) -> &str {
+    Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or(filename)
+}
+
+/// Record on the job that its result lost an animated input's later frames
+async fn note_frames_dropped(db_pool: &sqlx::PgPool, job_id: &str) {
+    let Ok(id) = Uuid::parse_str(job_id) else {
+        return;
+    };
+    if let Err(e) = db::Job::set_parameter(db_pool, id, "frames_dropped", serde_json::Value::Bool(true)).await {
+        tracing::error!("Failed to note dropped frames for job {}: {:?}", job_id, e);
+    }
+}
+
+/// Record on the job the quality a size target settled on, and whether even
+/// the lowest quality missed it
+async fn note_size_target(db_pool: &sqlx::PgPool, job_id: &str, quality: u8, missed: bool) {
+    let Ok(id) = Uuid::parse_str(job_id) else {
+        return;
+    };
+    let mut noted = db::Job::set_parameter(db_pool, id, "target_quality", serde_json::json!(quality)).await;
+    if noted.is_ok() && missed {
+        noted = db::Job::set_parameter(db_pool, id, "target_size_missed", serde_json::Value::Bool(true)).await;
+    }
+    if let Err(e) = noted {
+        tracing::error!("Failed to note the size target outcome for job {}: {:?}", job_id, e);
+    }
+}
+
+/// Record on the job what trimming a background removal did: the size it
+/// cropped to, or that there was no subject to crop to
+async fn note_trimmed(db_pool: &sqlx::PgPool, job_id: &str, trimmed: Trimmed) {
+    let (key, value) = match trimmed {
+        Trimmed::Off => return,
+        Trimmed::Empty => ("trim_empty", serde_json::Value::Bool(true)),
+        Trimmed::To(bounds) => (
+            "trimmed_size",
+            serde_json::json!({ "width": bounds.width, "height": bounds.height }),
+        ),
+    };
+    let Ok(id) = Uuid::parse_str(job_id) else {
+        return;
+    };
+    if let Err(e) = db::Job::set_parameter(db_pool, id, key, value).await {
+        tracing::error!("Failed to note trimming for job {}: {:?}", job_id, e);
+    }
+}
+
+/// Apply a LUT, preset or manual adjustments to a staged image, writing
+/// `<temp_dir>/<output_stem>.png`, or `.gif` for an animated GIF so every frame
+/// is kept
+#[allow(clippy::too_many_arguments)]
+async fn color_grade_step(
+    job_id: &str,
     db_pool: &sqlx::PgPool,
+    params: &ColorGradeParams,
+    input_path: &Path,
+    output_stem: &str,
+    temp_dir: &Path,
     storage: &Arc<dyn Storage>,
-    processor: &ImageProcessor,
-    statuses: &Arc<Mutex<HashMap<String, JobStatus>>>,
-) -> Result<String, String> {
-    let job_uuid = Uuid::parse_str(&job.job_id).map_err(|e| e.to_string())?;
-    let job_record = db::Job::find_by_id(db_pool, job_uuid)
+    processor: &Arc<ImageProcessor>,
+    reporter: &ProgressReporter<'_>,
+    progress: ProgressSpan,
+) -> Result<PathBuf, JobError> {
+    let input = input_path.to_path_buf();
+    let animated = blocking(processor, move |processor| animation::is_animated(&input, processor.max_pixels()))
+        .await
+        .map_err(|e| JobError::processing(&e, format!("Color grading failed: {:?}", e)))?;
+    let extension = if animated { "gif" } else { "png" };
+    let output_path = temp_dir.join(format!("{}.{}", output_stem, extension));
+
+    reporter.report(progress.at(20)).await;
+    let span = progress.within(20, 80);
+
+    // Check for preset or manual adjustments
+    if let Some(lut) = job_lut(db_pool, params.lut_id, params.lut_location.as_deref()).await? {
+        // Apply LUT (if present)
+        let lut_path = fetch_lut(storage, &lut, temp_dir, job_id).await?;
+        let (input, output, lut_file) = (input_path.to_path_buf(), output_path.clone(), lut_path.clone());
+        let applied = blocking_with_progress(processor, reporter, span, move |processor, on_progress| {
+            processor.apply_lut(&input, &output, &lut_file, on_progress)
+        })
+        .await
+        .map_err(|e| match e {
+            ProcessingError::InvalidLut(e) => lut_error(&lut, &e).into(),
+            e => JobError::processing(&e, format!("LUT application failed: {:?}", e)),
+        });
+        std::fs::remove_file(&lut_path).ok();
+        applied
+    } else if let Some(preset) = &params.preset {
+        let (input, output, preset) = (input_path.to_path_buf(), output_path.clone(), preset.to_string());
+        blocking_with_progress(processor, reporter, span, move |processor, on_progress| {
+            processor.apply_preset(&input, &output, &preset, on_progress)
+        })
+        .await
+        .map_err(|e| JobError::processing(&e, format!("Preset application failed: {:?}", e)))
+    } else {
+        let (hue, saturation, brightness, contrast) = (params.hue, params.saturation, params.brightness, params.contrast);
+        let (input, output) = (input_path.to_path_buf(), output_path.clone());
+        blocking_with_progress(processor, reporter, span, move |processor, on_progress| {
+            processor.color_grade(&input, &output, hue, saturation, brightness, contrast, on_progress)
+        })
         .await
-        .map_err(|e| format!("Failed to fetch job: {:?}", e))?
-        .ok_or("Job not found")?;
+        .map_err(|e| JobError::processing(&e, format!("Color grading failed: {:?}", e)))
+    }?;
 
-    let asset_ids: Vec<String> = serde_json::from_value(job_record.media_asset_ids)
+    reporter.report(progress.at(80)).await;
+
+    Ok(output_path)
+}
+
+/// Run the job's `operations` in order on one asset, feeding each step's
+/// output into the next. Steps share 0..90% equally; the last step's output
+/// is the result.
+async fn process_pipeline(
+    job: &db::Job,
+    db_pool: &sqlx::PgPool,
+    storage: &Arc<dyn Storage>,
+    processor: &Arc<ImageProcessor>,
+    ffmpeg: &Ffmpeg,
+    reporter: &ProgressReporter<'_>,
+    config: &config::Config,
+) -> Result<SavedOutput, JobError> {
+    let job_id = job.id.to_string();
+
+    let asset_ids: Vec<String> = serde_json::from_value(job.media_asset_ids.clone())
         .map_err(|e| format!("Invalid asset IDs: {}", e))?;
+    let asset_id = asset_ids.first().ok_or("No assets in job")?;
+    let asset = load_asset(db_pool, asset_id).await?;
 
-    let asset_id = Uuid::parse_str(&asset_ids[0]).map_err(|e| e.to_string())?;
+    let operations = PipelineParams::from_value(&job.parameters)?.operations;
+    if operations.is_empty() {
+        return Err("Pipeline has no operations".into());
+    }
 
-    let asset = sqlx::query_as::<_, db::MediaAsset>(
-        "SELECT * FROM media_assets WHERE id = $1"
+    // Each step's input is either the staged original or the previous
+    // output, removed once replaced
+    let input_location = stored_location(&asset)?;
+    let temp_dir = temp_dir(config);
+    let mut current = fetch_input(storage, &input_location, &temp_dir, &job_id).await?;
+
+    let total = operations.len() as u32;
+    for (i, operation) in operations.iter().enumerate() {
+        let step = i as u32;
+        let span = ProgressSpan {
+            start: 90 * step / total,
+            end: 90 * (step + 1) / total,
+        };
+        let output_stem = if step + 1 == total {
+            format!("pipeline_{}", job_id)
+        } else {
+            format!("pipeline_{}_step{}", job_id, step + 1)
+        };
+
+        let output = match operation {
+            PipelineStep::RemoveBg(params) => {
+                remove_background_step(
+                    &job_id,
+                    db_pool,
+                    params,
+                    &current,
+                    &output_stem,
+                    storage,
+                    processor,
+                    ffmpeg,
+                    reporter,
+                    config,
+                    span,
+                )
+                .await
+            }
+            PipelineStep::ColorGrade(params) => {
+                color_grade_step(
+                    &job_id,
+                    db_pool,
+                    params,
+                    &current,
+                    &output_stem,
+                    &temp_dir,
+                    storage,
+                    processor,
+                    reporter,
+                    span,
+                )
+                .await
+            }
+            PipelineStep::Convert(params) => {
+                convert_step(
+                    &job_id,
+                    db_pool,
+                    params,
+                    &current,
+                    &output_stem,
+                    &temp_dir,
+                    storage,
+                    processor,
+                    ffmpeg,
+                    reporter,
+                    span,
+                )
+                .await
+            }
+        };
+
+        let context = format!("Step {} ({})", step + 1, operation.name());
+        current = TempFile(output.map_err(|e| e.context(&context))?);
+        reporter.report(span.end).await;
+    }
+
+    let saved = save_output(storage, ffmpeg, job.user_id, &current).await;
+
+    reporter.report(100).await;
+
+    saved
+}
+
+/// POST the job's final state to its `webhook_url`, if it was submitted with
+/// one, and record the outcome on the job
+async fn notify_webhook(db_pool: sqlx::PgPool, webhooks: Option<Arc<WebhookSender>>, job_id: Uuid) {
+    let job = match db::Job::find_by_id(&db_pool, job_id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!("Failed to load job {} for webhook: {:?}", job_id, e);
+            return;
+        }
+    };
+    let Some(url) = job.parameters.get("webhook_url").and_then(|v| v.as_str()) else {
+        return;
+    };
+
+    let delivery = match (&webhooks, webhook::validate_url(url).await) {
+        (None, _) => Delivery {
+            attempts: 0,
+            error: Some("Webhooks are not configured".to_string()),
+        },
+        (_, Err(e)) => Delivery {
+            attempts: 0,
+            error: Some(e.to_string()),
+        },
+        (Some(sender), Ok(target)) => {
+            let payload = WebhookPayload {
+                job_id: job.id.to_string(),
+                status: job.status.clone(),
+                result_url: job.result_location.clone(),
+                error: job.error_message.clone().filter(|_| job.status == "failed"),
+                error_code: job.error_code.clone().filter(|_| job.status == "failed"),
+                created_at: job.created_at.to_rfc3339(),
+                completed_at: job.completed_at.map(|t| t.to_rfc3339()),
+            };
+            sender.send(&target, &payload).await
+        }
+    };
+
+    match &delivery.error {
+        None => tracing::info!(
+            "Webhook for job {} delivered after {} attempt(s)",
+            job_id,
+            delivery.attempts
+        ),
+        Some(e) => tracing::warn!("Webhook for job {} failed: {}", job_id, e),
+    }
+    if let Err(e) = db::Job::record_webhook(
+        &db_pool,
+        job_id,
+        delivery.delivered(),
+        delivery.attempts as i32,
+        delivery.error.as_deref(),
     )
-    .bind(asset_id)
-    .fetch_optional(db_pool)
     .await
-    .map_err(|e| format!("Failed to fetch asset: {:?}", e))?
-    .ok_or("Asset not found")?;
+    {
+        tracing::error!("Failed to record webhook delivery for job {}: {:?}", job_id, e);
+    }
+}
 
-    let input_path = std::path::PathBuf::from(&asset.result_location.unwrap_or(asset.original_filename.clone()));
+fn is_video_path(path: &Path) -> bool {
+    let lower = path.to_string_lossy().to_lowercase();
+    lower.ends_with(".mp4") || lower.ends_with(".mov") || lower.ends_with(".avi") || lower.ends_with(".webm")
+}
 
-    let output_filename = format!("graded_{}.png", job.job_id);
-    let output_path = std::env::temp_dir().join(&output_filename);
+/// Where an asset's upload is stored. The original file name is only a label
+/// chosen by the client and is never read from disk.
+fn stored_location(asset: &db::MediaAsset) -> Result<StorageLocation, JobError> {
+    parse_location(asset.result_location.as_deref().ok_or("Asset has no stored content")?)
+}
 
-    update_progress(statuses, &job.job_id, 20).await;
+fn parse_location(location: &str) -> Result<StorageLocation, JobError> {
+    location.parse().map_err(|e: StorageError| JobError::storage(&e, e.to_string()))
+}
 
-    // Check for preset or manual adjustments
-    if let Some(lut_loc) = job_record.parameters.get("lut_location").and_then(|v| v.as_str()) {
-        // Apply LUT (if present)
-        processor
-            .apply_lut(&input_path, &output_path, lut_loc)
-            .map_err(|e| format!("LUT application failed: {:?}", e))?;
-    } else if let Some(preset) = job_record.parameters.get("preset").and_then(|v| v.as_str()) {
-        processor
-            .apply_preset(&input_path, &output_path, preset)
-            .map_err(|e| format!("Preset application failed: {:?}", e))?;
-    } else {
-        let hue = job_record.parameters.get("hue").and_then(|v| v.as_i64()).map(|v| v as i32);
-        let saturation = job_record.parameters.get("saturation").and_then(|v| v.as_i64()).map(|v| v as i32);
-        let brightness = job_record.parameters.get("brightness").and_then(|v| v.as_i64()).map(|v| v as i32);
-        let contrast = job_record.parameters.get("contrast").and_then(|v| v.as_i64()).map(|v| v as i32);
+/// The configured temp dir, which the cleanup task sweeps for leftovers
+fn temp_dir(config: &config::Config) -> PathBuf {
+    PathBuf::from(&config.processing.temp_dir)
+}
+
+/// A staged file, removed when dropped so every way out of a job cleans it
+/// up, errors included. Removing a file that has already gone is a no-op.
+struct TempFile(PathBuf);
+
+impl std::ops::Deref for TempFile {
+    type Target = Path;
 
-        processor
-            .color_grade(&input_path, &output_path, hue, saturation, brightness, contrast)
-            .map_err(|e| format!("Color grading failed: {:?}", e))?;
+    fn deref(&self) -> &Path {
+        &self.0
     }
+}
 
-    update_progress(statuses, &job.job_id, 80).await;
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
 
-    // Save result
-    let result_bytes = std::fs::read(&output_path)
-        .map_err(|e| format!("Failed to read result: {}", e))?;
+/// Materialize a stored input in `temp_dir` through `Storage`, so the
+/// path-based processors can read it whatever the backend. The original file
+/// name is kept as a suffix so format detection by extension still works.
+#[tracing::instrument(skip_all)]
+async fn fetch_input(
+    storage: &Arc<dyn Storage>,
+    location: &StorageLocation,
+    temp_dir: &Path,
+    job_id: &str,
+) -> Result<TempFile, JobError> {
+    let data = storage
+        .load_bytes(location)
+        .await
+        .map_err(|e| JobError::storage(&e, format!("Failed to load input: {}", e)))?;
 
-    let result_location = storage
-        .save_bytes(&result_bytes, &output_filename)
-        .map_err(|e| format!("Failed to save result: {:?}", e))?;
+    // Unique per fetch: a batch may hold several uploads with the same name
+    let name = location.file_name();
+    let staged = TempFile(temp_dir.join(format!("input_{}_{}_{}", job_id, Uuid::new_v4().simple(), name)));
+    tokio::fs::write(&*staged, &data)
+        .await
+        .map_err(|e| JobError::Transient(format!("Failed to stage input: {}", e)))?;
 
-    std::fs::remove_file(&output_path).ok();
+    Ok(staged)
+}
 
-    update_progress(statuses, &job.job_id, 100).await;
+/// The library LUT a job or step names by `lut_id`, if any. A
+/// `lut_location` comes from a job queued before LUTs were stored per user.
+async fn job_lut(
+    db_pool: &sqlx::PgPool,
+    lut_id: Option<Uuid>,
+    lut_location: Option<&str>,
+) -> Result<Option<db::LutFile>, JobError> {
+    let Some(lut_id) = lut_id else {
+        if lut_location.is_some() {
+            return Err("LUTs are now referenced by id; upload the LUT again and resubmit with lut_id".into());
+        }
+        return Ok(None);
+    };
 
-    Ok(result_location)
+    let lut = db::LutFile::find_by_id(db_pool, lut_id)
+        .await
+        .map_err(|e| JobError::Transient(format!("Failed to fetch LUT: {:?}", e)))?
+        .ok_or("LUT not found; it may have been deleted")?;
+    Ok(Some(lut))
 }
 
-async fn update_progress(
-    statuses: &Arc<Mutex<HashMap<String, JobStatus>>>,
+/// Stage a LUT from storage next to the job input. The stored name keeps its
+/// extension, which `Lut::from_file` uses to pick a parser.
+async fn fetch_lut(
+    storage: &Arc<dyn Storage>,
+    lut: &db::LutFile,
+    temp_dir: &Path,
     job_id: &str,
-    progress: u32,
-) {
-    let mut s = statuses.lock().await;
-    s.insert(
-        job_id.to_string(),
-        JobStatus::Processing { progress },
-    );
-}
\ No newline at end of file
+) -> Result<PathBuf, JobError> {
+    let data = match storage.load_bytes(&parse_location(&lut.location)?).await {
+        Ok(data) => data,
+        Err(StorageError::NotFound(_)) => return Err(format!("LUT '{}' not found", lut.name).into()),
+        Err(e) => {
+            return Err(JobError::Transient(format!("Failed to load LUT '{}': {}", lut.name, e)))
+        }
+    };
+
+    let name = lut.location.rsplit('/').next().unwrap_or("lut.cube");
+    let path = temp_dir.join(format!("lut_{}_{}", job_id, name));
+    tokio::fs::write(&path, &data)
+        .await
+        .map_err(|e| JobError::Transient(format!("Failed to stage LUT: {}", e)))?;
+
+    Ok(path)
+}
+
+fn lut_error(lut: &db::LutFile, e: &LutError) -> String {
+    format!("LUT '{}' is invalid: {}", lut.name, e)
+}
+
+/// Points of progress between writes to the jobs table
+const DB_PROGRESS_STEP: u32 = 5;
+/// Progress is recorded in the job's history on passing each multiple of this
+const EVENT_PROGRESS_STEP: u32 = 25;
+
+/// A running job's progress. Every change goes to the status store; the jobs
+/// table, which `/api/jobs/:job_id` reads, is written each time progress has
+/// moved on by `DB_PROGRESS_STEP`, and a `progress` event recorded on passing
+/// a milestone. Progress never goes backwards.
+struct ProgressReporter<'a> {
+    statuses: &'a StatusStore,
+    db_pool: &'a sqlx::PgPool,
+    job_id: Uuid,
+    reported: AtomicU32,
+    written: AtomicU32,
+}
+
+impl<'a> ProgressReporter<'a> {
+    fn new(statuses: &'a StatusStore, db_pool: &'a sqlx::PgPool, job_id: Uuid) -> Self {
+        Self { statuses, db_pool, job_id, reported: AtomicU32::new(0), written: AtomicU32::new(0) }
+    }
+
+    async fn report(&self, progress: u32) {
+        let progress = progress.min(100);
+        if progress < self.reported.fetch_max(progress, Ordering::Relaxed) {
+            return;
+        }
+        tracing::debug!("Job {} progress {}%", self.job_id, progress);
+        self.statuses.set(&self.job_id.to_string(), JobStatus::Processing { progress }).await;
+
+        let written = self.written.load(Ordering::Relaxed);
+        if progress >= written + DB_PROGRESS_STEP {
+            self.written.store(progress, Ordering::Relaxed);
+            if let Err(e) = db::Job::update_progress(self.db_pool, self.job_id, progress as i32).await {
+                tracing::warn!("Failed to record progress for job {}: {:?}", self.job_id, e);
+            }
+            if progress / EVENT_PROGRESS_STEP > written / EVENT_PROGRESS_STEP {
+                let detail = serde_json::json!({ "progress": progress });
+                db::JobEvent::record(self.db_pool, self.job_id, "progress", Some(detail)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Stand-in for the jobs table: claiming pops the oldest queued id
+    fn table(ids: &[&str]) -> Mutex<VecDeque<String>> {
+        Mutex::new(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    fn wake_channel() -> (tokio::sync::mpsc::Sender<JobMessage>, Wake) {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        (tx, JobSource::Local(rx).into())
+    }
+
+    #[test]
+    fn test_job_error_classification() {
+        let missing = StorageError::NotFound("a.png".to_string());
+        assert!(!JobError::storage(&missing, "gone".to_string()).is_transient());
+        let outside = StorageError::OutsideStorage("../../etc/passwd".to_string());
+        assert!(!JobError::storage(&outside, "refused".to_string()).is_transient());
+        let io = StorageError::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert!(JobError::storage(&io, "blip".to_string()).is_transient());
+
+        // ffmpeg rejecting the input comes back as ErrorKind::Other
+        let ffmpeg = ProcessingError::IoError(std::io::Error::other("ffmpeg failed: invalid data"));
+        assert!(!JobError::processing(&ffmpeg, "bad".to_string()).is_transient());
+        let disk = ProcessingError::IoError(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        assert!(JobError::processing(&disk, "slow".to_string()).is_transient());
+        let format = ProcessingError::Unsupported("no encoder for xyz".to_string());
+        assert!(!JobError::processing(&format, "nope".to_string()).is_transient());
+
+        // Codes for clients to branch on
+        let code = |error: JobError| error.code().as_str();
+        assert_eq!(code(JobError::processing(&format, "nope".to_string())), "unsupported_format");
+        assert_eq!(code(JobError::processing(&ffmpeg, "bad".to_string())), "decode_failed");
+        let truncated = ProcessingError::DecodeFailed("truncated PNG".to_string());
+        assert_eq!(code(JobError::processing(&truncated, "bad".to_string())), "decode_failed");
+        assert_eq!(code(JobError::processing(&disk, "slow".to_string())), "storage_error");
+        assert_eq!(code(JobError::storage(&missing, "gone".to_string())), "storage_error");
+        assert_eq!(code(JobError::from("Asset not found")), "processing_failed");
+
+        // Pipeline step context keeps the classification
+        let step = JobError::Transient("Failed to save result".to_string()).context("Step 2 (convert)");
+        assert_eq!(step, JobError::Transient("Step 2 (convert): Failed to save result".to_string()));
+        let step = JobError::processing(&format, "nope".to_string()).context("Step 1 (convert)");
+        assert_eq!(step.code(), ErrorCode::UnsupportedFormat);
+        assert!(!JobError::from("Asset not found").is_transient());
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(3), RETRY_BASE_DELAY * 4);
+        assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(40), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_background_mode_from_params() {
+        let params = |parameters| RemoveBgParams::from_value(&parameters).unwrap();
+        let mode = |parameters| BackgroundMode::from_params(&params(parameters), None);
+        assert_eq!(mode(serde_json::json!({})), BackgroundMode::Transparent);
+        // Jobs queued before modes existed
+        assert_eq!(mode(serde_json::json!({ "replace_color": [1, 2, 3] })), BackgroundMode::Color([1, 2, 3]));
+        assert_eq!(
+            mode(serde_json::json!({ "mode": "color", "replace_color": [1, 2, 3] })),
+            BackgroundMode::Color([1, 2, 3])
+        );
+        assert_eq!(mode(serde_json::json!({ "mode": "blur", "blur_sigma": 4.0 })), BackgroundMode::Blur(4.0));
+        assert_eq!(mode(serde_json::json!({ "mode": "blur" })), BackgroundMode::Blur(DEFAULT_BLUR_SIGMA));
+        assert_eq!(
+            mode(serde_json::json!({ "mode": "transparent", "flatten_color": [9, 9, 9] })),
+            BackgroundMode::Color([9, 9, 9])
+        );
+
+        let staged = Path::new("/tmp/input_job_bg.png");
+        assert_eq!(
+            BackgroundMode::from_params(&params(serde_json::json!({ "mode": "image" })), Some(staged)),
+            BackgroundMode::Image(staged.to_path_buf())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_output_hashes_the_result() {
+        let base = std::env::temp_dir().join(format!("save_output_test_{}", Uuid::new_v4()));
+        let storage: Arc<dyn Storage> = Arc::new(super::super::LocalStorage::new(base.join("store")));
+        let ffmpeg = Ffmpeg::detect("ffmpeg").await;
+        std::fs::create_dir_all(&base).unwrap();
+        let output_path = base.join("out.png");
+        std::fs::write(&output_path, b"hello").unwrap();
+
+        let saved = save_output(&storage, &ffmpeg, Uuid::new_v4(), &output_path).await.unwrap();
+        assert_eq!(&storage.load_bytes(&saved.location).await.unwrap()[..], b"hello");
+        assert_eq!(saved.etag, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        let record = saved.record(Uuid::nil(), 1);
+        assert_eq!((record.output_index, record.filename.as_str(), record.size_bytes), (1, "out.png", 5));
+        assert_eq!(record.content_type, "image/png");
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[tokio::test]
+    async fn test_saved_output_describes_the_file() {
+        let base = std::env::temp_dir().join(format!("save_output_test_{}", Uuid::new_v4()));
+        let storage: Arc<dyn Storage> = Arc::new(super::super::LocalStorage::new(base.join("store")));
+        let ffmpeg = Ffmpeg::detect("ffmpeg").await;
+        std::fs::create_dir_all(&base).unwrap();
+        let image_path = base.join("out.PNG");
+        image::RgbaImage::new(3, 2).save_with_format(&image_path, image::ImageFormat::Png).unwrap();
+
+        let saved = save_output(&storage, &ffmpeg, Uuid::new_v4(), &image_path).await.unwrap();
+        let result = saved.result(Duration::from_millis(1250));
+        assert_eq!((result.width, result.height), (Some(3), Some(2)));
+        assert_eq!(result.size_bytes as u64, std::fs::metadata(&image_path).unwrap().len());
+        assert_eq!((result.format.as_str(), result.duration_ms), ("png", 1250));
+        assert_eq!(result.location, saved.location.to_string());
+
+        // Nothing to measure in a LUT
+        let lut_path = base.join("grade.cube");
+        std::fs::write(&lut_path, b"LUT_3D_SIZE 2").unwrap();
+        let result = save_output(&storage, &ffmpeg, Uuid::new_v4(), &lut_path).await.unwrap().result(Duration::ZERO);
+        assert_eq!((result.width, result.height, result.format.as_str()), (None, None, "cube"));
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[tokio::test]
+    async fn test_inputs_are_staged_in_the_temp_dir_and_removed_on_drop() {
+        let base = std::env::temp_dir().join(format!("fetch_input_test_{}", Uuid::new_v4()));
+        let temp_dir = base.join("temp");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(super::super::LocalStorage::new(base.join("store")));
+        let location = storage.save_bytes(b"pixels", Uuid::new_v4(), "a.png").await.unwrap();
+
+        let first = fetch_input(&storage, &location, &temp_dir, "job").await.unwrap();
+        let second = fetch_input(&storage, &location, &temp_dir, "job").await.unwrap();
+        assert_eq!(first.parent(), Some(temp_dir.as_path()));
+        assert_ne!(*first, *second);
+        assert!(first.to_string_lossy().ends_with("_a.png"));
+        assert_eq!(std::fs::read(&*first).unwrap(), b"pixels");
+
+        let path = first.to_path_buf();
+        drop(first);
+        assert!(!path.exists());
+        drop(second);
+        assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+
+        let missing = StorageLocation::Local("gone.png".to_string());
+        let err = fetch_input(&storage, &missing, &temp_dir, "job").await.err().unwrap();
+        assert!(!err.is_transient());
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_job_is_abandoned_and_its_temp_files_removed() {
+        let dir = std::env::temp_dir().join(format!("timeout_test_{}", Uuid::new_v4()));
+        let job_id = Uuid::new_v4().to_string();
+        std::fs::create_dir_all(dir.join(format!("frames_{}", job_id))).unwrap();
+        std::fs::write(dir.join(format!("frames_{}", job_id)).join("frame_0001.png"), b"x").unwrap();
+        std::fs::write(dir.join(format!("lut_{}_warm.cube", job_id)), b"x").unwrap();
+        std::fs::write(dir.join("input_other_job.png"), b"x").unwrap();
+
+        // Image work that never finishes on its own
+        let processor = Arc::new(ImageProcessor::new("./models/missing.onnx".to_string(), u64::MAX).unwrap());
+        let (release, stuck) = std::sync::mpsc::channel::<()>();
+        let staged = TempFile(dir.join(format!("input_{}_a.png", job_id)));
+        std::fs::write(&*staged, b"x").unwrap();
+        let work = async move {
+            let _staged = staged;
+            blocking(&processor, move |_| Ok(stuck.recv().ok()))
+                .await
+                .map_err(|e| JobError::processing(&e, e.to_string()))
+        };
+
+        let started = Instant::now();
+        let result = with_timeout(Duration::from_millis(100), &dir, &job_id, work).await;
+        assert_eq!(
+            result,
+            Err(JobError::Permanent(ErrorCode::Timeout, "processing timed out after 100ms".to_string()))
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let left: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(left, ["input_other_job.png"]);
+
+        release.send(()).ok();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_mid_job_completes_it_and_leaves_the_rest_queued() {
+        let queued = table(&["a", "b", "c"]);
+        let (_tx, wake) = wake_channel();
+        let shutdown = CancellationToken::new();
+        let completed = Mutex::new(Vec::new());
+
+        run_until_shutdown(
+            &wake,
+            &shutdown,
+            || async { queued.lock().await.pop_front() },
+            |job| {
+                let (shutdown, completed) = (&shutdown, &completed);
+                async move {
+                    // Shutdown arrives while the first job is still running
+                    shutdown.cancel();
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    completed.lock().await.push(job);
+                }
+            },
+        )
+        .await;
+
+        // Every job is either finished or still claimable, never orphaned
+        assert_eq!(*completed.lock().await, ["a"]);
+        assert_eq!(*queued.lock().await, ["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_sweeps_queued_jobs_before_waiting() {
+        let queued = table(&["a", "b"]);
+        let (tx, wake) = wake_channel();
+        drop(tx);
+
+        let mut seen = Vec::new();
+        run_until_shutdown(
+            &wake,
+            &CancellationToken::new(),
+            || async { queued.lock().await.pop_front() },
+            |job| {
+                seen.push(job);
+                async {}
+            },
+        )
+        .await;
+
+        // No wake-up was ever sent; both jobs still ran before the closed channel stopped the loop
+        assert_eq!(seen, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_wake_up_picks_up_new_jobs() {
+        let queued = table(&[]);
+        let (tx, wake) = wake_channel();
+        let shutdown = CancellationToken::new();
+        let seen = Mutex::new(Vec::new());
+
+        let enqueue = async {
+            queued.lock().await.push_back("late".to_string());
+            tx.send(JobMessage { job_id: "late".to_string() }).await.unwrap();
+        };
+        let worker = run_until_shutdown(
+            &wake,
+            &shutdown,
+            || async { queued.lock().await.pop_front() },
+            |job| {
+                let (shutdown, seen) = (&shutdown, &seen);
+                async move {
+                    seen.lock().await.push(job);
+                    shutdown.cancel();
+                }
+            },
+        );
+        tokio::time::timeout(Duration::from_secs(1), async { tokio::join!(worker, enqueue) })
+            .await
+            .expect("worker should wake without waiting for the poll interval");
+
+        assert_eq!(*seen.lock().await, ["late"]);
+    }
+
+    /// A worker process apart from the API hears about jobs straight from Redis
+    #[cfg(feature = "redis-tests")]
+    #[tokio::test]
+    async fn test_redis_push_wakes_an_external_worker() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let (queue, _rx) = super::super::Queue::new(8, Some(&url)).await;
+        let wake = Wake::from(JobSource::Redis(redis::Client::open(url.as_str()).unwrap()));
+        let queued = table(&[]);
+        let shutdown = CancellationToken::new();
+        let seen = Mutex::new(Vec::new());
+
+        let worker = run_until_shutdown(
+            &wake,
+            &shutdown,
+            || async { queued.lock().await.pop_front() },
+            |job| {
+                let (shutdown, seen) = (&shutdown, &seen);
+                async move {
+                    seen.lock().await.push(job);
+                    shutdown.cancel();
+                }
+            },
+        );
+        let enqueue = async {
+            // Let the worker find the table empty and start waiting
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            queued.lock().await.push_back("late".to_string());
+            queue.push_redis(&JobMessage { job_id: "late".to_string() }).await.unwrap();
+        };
+        tokio::time::timeout(Duration::from_secs(2), async { tokio::join!(worker, enqueue) })
+            .await
+            .expect("worker should wake without waiting for the poll interval");
+
+        assert_eq!(*seen.lock().await, ["late"]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_job_does_not_block_other_workers() {
+        let queued = table(&["slow", "fast-1", "fast-2"]);
+        let (_tx, wake) = wake_channel();
+        let shutdown = CancellationToken::new();
+        let finished = Mutex::new(Vec::new());
+        let claim = || async { queued.lock().await.pop_front() };
+        let run = |job: String| {
+            let (shutdown, finished) = (&shutdown, &finished);
+            async move {
+                if job == "slow" {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let mut finished = finished.lock().await;
+                finished.push(job);
+                if finished.len() == 3 {
+                    shutdown.cancel();
+                }
+            }
+        };
+
+        tokio::join!(
+            run_until_shutdown(&wake, &shutdown, claim, run),
+            run_until_shutdown(&wake, &shutdown, claim, run),
+        );
+
+        // Jobs complete out of order while the slow one is still running
+        assert_eq!(*finished.lock().await, ["fast-1", "fast-2", "slow"]);
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_progress_is_written_to_the_job_every_five_percent() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let user = db::User::create(&pool, &format!("{}@progress.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let job = db::Job::create(&pool, user.id.into(), vec![], "convert", "image", serde_json::json!({}), 0, None)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE jobs SET status = 'processing', heartbeat_at = NOW() WHERE id = $1")
+            .bind(job.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let progress_percent = || async {
+            db::Job::find_by_id(&pool, job.id).await.unwrap().unwrap().progress_percent
+        };
+
+        let statuses = StatusStore::new(None);
+        let reporter = ProgressReporter::new(&statuses, &pool, job.id);
+        let mut written = Vec::new();
+        for progress in [3, 5, 7, 9, 12, 4] {
+            reporter.report(progress).await;
+            written.push(progress_percent().await);
+        }
+        assert_eq!(written, [0, 5, 5, 5, 12, 12]);
+        // The status store sees every change but never goes backwards
+        assert!(matches!(
+            statuses.get(&job.id.to_string()).await,
+            Some(JobStatus::Processing { progress: 12 })
+        ));
+
+        // A finished job keeps its final progress
+        db::Job::fail(&pool, job.id, "done with it", "processing_failed").await.unwrap();
+        reporter.report(50).await;
+        assert_eq!(progress_percent().await, 12);
+    }
+
+    #[test]
+    fn test_batch_output_names_keep_only_safe_characters() {
+        let output = Path::new("/tmp/converted_1.jpg");
+        assert_eq!(batch_output_name("holiday.png", output), "holiday.jpg");
+        assert_eq!(batch_output_name("say \"hi\"; x.png", output), "say__hi___x.jpg");
+        assert_eq!(batch_output_name("../../etc/passwd.png", output), "passwd.jpg");
+        assert_eq!(batch_output_name("..\\evil.png", output), "evil.jpg");
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_conversion_without_assets_fails_permanently() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+        let vars = std::collections::HashMap::from([
+            ("DATABASE_URL", url.as_str()),
+            ("JWT_SECRET", "worker-test-secret"),
+            ("REDIS_URL", ""),
+        ]);
+        let config = config::Config::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+
+        let user = db::User::create(&pool, &format!("{}@empty.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let parameters = serde_json::json!({ "output_format": "png" });
+        let job = db::Job::create(&pool, user.id.into(), vec![], "convert", "image", parameters, 0, None)
+            .await
+            .unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(super::super::MemoryStorage::new());
+        let processor = Arc::new(ImageProcessor::new(String::new(), config.processing.max_image_pixels).unwrap());
+        let statuses = StatusStore::new(None);
+        let reporter = ProgressReporter::new(&statuses, &pool, job.id);
+
+        let ffmpeg = Ffmpeg::detect("ffmpeg").await;
+        let result = process_conversion(&job, &pool, &storage, &processor, &ffmpeg, &reporter, &config).await;
+        match result {
+            Err(JobError::Permanent(code, message)) => {
+                assert_eq!((code, message.as_str()), (ErrorCode::ProcessingFailed, "No assets in job"))
+            }
+            other => panic!("expected a permanent failure, got {:?}", other.map(|outputs| outputs.len())),
+        }
+        db::User::delete_account(&pool, user.id).await.unwrap();
+    }
+
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_events_trace_a_job_from_queued_to_completed_or_failed() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let pool = db::create_pool(&url).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let temp_dir = std::env::temp_dir().join(format!("worker_events_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir_str = temp_dir.to_string_lossy().to_string();
+        let vars = std::collections::HashMap::from([
+            ("DATABASE_URL", url.as_str()),
+            ("JWT_SECRET", "worker-test-secret"),
+            ("REDIS_URL", ""),
+            ("TEMP_DIR", temp_dir_str.as_str()),
+        ]);
+        let config = config::Config::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+        let (queue, _wake_ups) = Queue::new(8, None).await;
+        let ctx = WorkerContext {
+            storage: Arc::new(super::super::MemoryStorage::new()),
+            db_pool: pool.clone(),
+            statuses: StatusStore::new(None),
+            processor: Arc::new(ImageProcessor::new(String::new(), config.processing.max_image_pixels).unwrap()),
+            ffmpeg: Arc::new(Ffmpeg::detect("ffmpeg").await),
+            config,
+            webhooks: None,
+            queue: Arc::new(queue),
+            running: Default::default(),
+        };
+
+        let user = db::User::create(&pool, &format!("{}@events.test", Uuid::new_v4()), "hash", "free")
+            .await
+            .unwrap();
+        let mut png = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let location = ctx.storage.save_bytes(&png, user.id, "input.png").await.unwrap().to_string();
+        let asset = db::MediaAsset::create(&pool, user.id.into(), "input.png", "png", png.len() as i64, None)
+            .await
+            .unwrap();
+        db::MediaAsset::update_status(&pool, asset.id, "uploaded", Some(&location)).await.unwrap();
+
+        // A missing asset is a permanent failure, so the job fails on its first attempt
+        let missing = Uuid::new_v4();
+        let mut timelines = Vec::new();
+        for asset_id in [asset.id, missing] {
+            let parameters = serde_json::json!({});
+            let job = db::Job::create(&pool, user.id.into(), vec![asset_id], "analyze", "image", parameters, 0, None)
+                .await
+                .unwrap();
+            ctx.queue.enqueue(&pool, JobMessage { job_id: job.id.to_string() }).await.unwrap();
+            // Claimed directly, as `claim_next` could take other tests' jobs
+            let job = sqlx::query_as::<_, db::Job>(
+                "UPDATE jobs SET status = 'processing', attempts = attempts + 1 WHERE id = $1 RETURNING *"
+            )
+            .bind(job.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            process_job(job.clone(), chrono::Duration::hours(1), &ctx).await;
+
+            let (events, total) = db::JobEvent::list(&pool, job.id, 50, 0).await.unwrap();
+            assert_eq!(total as usize, events.len());
+            timelines.push(events);
+        }
+
+        let types = |events: &[db::JobEvent]| events.iter().map(|e| e.event_type.clone()).collect::<Vec<_>>();
+        let [completed, failed] = <[_; 2]>::try_from(timelines).unwrap();
+        assert_eq!(types(&completed), ["queued", "started", "progress", "progress", "completed"]);
+        let progress: Vec<_> = completed.iter().filter_map(|e| e.detail.as_ref()?.get("progress").cloned()).collect();
+        assert_eq!(progress, [serde_json::json!(80), serde_json::json!(100)]);
+        assert_eq!(completed[1].detail.as_ref().unwrap()["attempt"], 1);
+        assert!(completed.windows(2).all(|pair| pair[0].created_at <= pair[1].created_at));
+
+        assert_eq!(types(&failed), ["queued", "started", "failed"]);
+        let detail = failed[2].detail.as_ref().unwrap();
+        assert_eq!(detail["error"], "Asset not found");
+        assert_eq!(detail["error_code"], "processing_failed");
+
+        db::User::delete_account(&pool, user.id).await.unwrap();
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
@@ -0,0 +1,137 @@
+// backend/src/telemetry.rs
+// Prometheus metrics: recorder setup, metric names and HTTP request tracking
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+/// Jobs accepted by the API, by `job_type` and `tier`
+pub const JOBS_ENQUEUED: &str = "mediaforge_jobs_enqueued_total";
+/// Jobs finished successfully, by `job_type` and `tier`
+pub const JOBS_COMPLETED: &str = "mediaforge_jobs_completed_total";
+/// Jobs that failed for good, by `job_type` and `tier`
+pub const JOBS_FAILED: &str = "mediaforge_jobs_failed_total";
+/// Runs that failed transiently and were put back in the queue
+pub const JOBS_RETRIED: &str = "mediaforge_jobs_retried_total";
+/// Queued jobs announced again after waiting past `QUEUED_STALE_AFTER_SECONDS`
+pub const JOBS_STALLED: &str = "mediaforge_jobs_stalled_total";
+/// Time from claiming a job to its outcome, by `job_type`, `tier` and `outcome`
+pub const JOB_DURATION: &str = "mediaforge_job_duration_seconds";
+/// Jobs this process is running right now
+pub const JOBS_IN_FLIGHT: &str = "mediaforge_jobs_in_flight";
+/// Wake-ups waiting for a worker: the Redis list length, or the local channel's
+pub const QUEUE_DEPTH: &str = "mediaforge_queue_depth";
+/// Enqueues that fell back to the local channel because Redis failed
+pub const QUEUE_REDIS_FALLBACKS: &str = "mediaforge_queue_redis_fallbacks_total";
+/// Size of accepted uploads
+pub const UPLOAD_SIZE: &str = "mediaforge_upload_size_bytes";
+/// Requests by `method`, route `path` and `status`
+pub const HTTP_REQUESTS: &str = "mediaforge_http_requests_total";
+/// Time to produce response headers, by `method` and route `path`
+pub const HTTP_DURATION: &str = "mediaforge_http_request_duration_seconds";
+
+const HTTP_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const JOB_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+const SIZE_BUCKETS: &[f64] = &[
+    64.0 * 1024.0,
+    256.0 * 1024.0,
+    1024.0 * 1024.0,
+    4.0 * 1024.0 * 1024.0,
+    16.0 * 1024.0 * 1024.0,
+    64.0 * 1024.0 * 1024.0,
+    256.0 * 1024.0 * 1024.0,
+    1024.0 * 1024.0 * 1024.0,
+];
+
+/// How often histogram samples are folded into their buckets
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+fn builder() -> PrometheusBuilder {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(HTTP_DURATION.to_string()), HTTP_BUCKETS)
+        .and_then(|b| b.set_buckets_for_metric(Matcher::Full(JOB_DURATION.to_string()), JOB_BUCKETS))
+        .and_then(|b| b.set_buckets_for_metric(Matcher::Full(UPLOAD_SIZE.to_string()), SIZE_BUCKETS))
+        .expect("bucket lists are not empty")
+}
+
+/// Install the global recorder. Must be called inside the Tokio runtime:
+/// without the exporter's own HTTP listener, upkeep runs on a spawned task.
+pub fn install() -> anyhow::Result<PrometheusHandle> {
+    let handle = builder().install_recorder()?;
+
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(UPKEEP_INTERVAL).await;
+            upkeep.run_upkeep();
+        }
+    });
+
+    Ok(handle)
+}
+
+/// `job_type` and `tier` labels shared by the job metrics
+pub fn job_labels(job_type: &str, tier: &str) -> [(&'static str, String); 2] {
+    [("job_type", job_type.to_string()), ("tier", tier.to_string())]
+}
+
+/// Count and time each request under its route template, so `/api/jobs/:job_id`
+/// is one series however many jobs there are
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    crate::request_id::record_route(request.method(), &path);
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let labels = [("method", method), ("path", path)];
+    metrics::histogram!(HTTP_DURATION, &labels).record(started.elapsed().as_secs_f64());
+    let [method, path] = labels;
+    metrics::counter!(
+        HTTP_REQUESTS,
+        &[method, path, ("status", response.status().as_u16().to_string())]
+    )
+    .increment(1);
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_http_requests_are_labelled_by_route_template() {
+        let recorder = builder().build_recorder();
+        let handle = recorder.handle();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let app = Router::new()
+            .route("/items/:id", get(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn(track_http));
+        for id in ["1", "2"] {
+            let request = Request::builder().uri(format!("/items/{}", id)).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let rendered = handle.render();
+        assert!(
+            rendered.contains(r#"mediaforge_http_requests_total{method="GET",path="/items/:id",status="200"} 2"#),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains(r#"mediaforge_http_request_duration_seconds_bucket{method="GET",path="/items/:id",le="0.005"}"#));
+    }
+}
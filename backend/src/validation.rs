@@ -0,0 +1,162 @@
+// backend/src/validation.rs
+// Field-level request validation: collects every problem with a request so
+// forms can show them next to the fields they belong to
+
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::ops::RangeInclusive;
+use utoipa::ToSchema;
+
+use crate::error::{AppError, Message, Msg, Result};
+
+/// One invalid field. `code` is stable for clients to branch on; `message`
+/// is for people, in the request's language.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FieldError {
+    /// JSON path of the field, e.g. `password` or `operations[1].hue`
+    pub field: String,
+    pub code: &'static str,
+    #[schema(value_type = String)]
+    pub message: Message,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, code: &'static str, message: impl Into<Message>) -> Self {
+        Self {
+            field: field.into(),
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The same error for a field nested under `parent`
+    pub fn within(mut self, parent: &str) -> Self {
+        self.field = format!("{}.{}", parent, self.field);
+        self
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Codes used in `FieldError::code`
+pub mod code {
+    pub const INVALID_FORMAT: &str = "invalid_format";
+    pub const TOO_SHORT: &str = "too_short";
+    pub const OUT_OF_RANGE: &str = "out_of_range";
+    pub const INVALID_CHOICE: &str = "invalid_choice";
+    pub const NOT_APPLICABLE: &str = "not_applicable";
+    pub const REQUIRED: &str = "required";
+}
+
+/// Collects field errors; `finish` turns them into `AppError::Validation`
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, error: FieldError) -> &mut Self {
+        self.errors.push(error);
+        self
+    }
+
+    /// Loose sanity check; the address is confirmed by use, not by parsing
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        if !value.contains('@') || value.len() < 5 {
+            self.push(FieldError::new(field, code::INVALID_FORMAT, Msg::InvalidEmail));
+        }
+        self
+    }
+
+    pub fn min_length(&mut self, field: &str, value: &str, min: usize) -> &mut Self {
+        if value.chars().count() < min {
+            self.push(FieldError::new(field, code::TOO_SHORT, Msg::TooShort.with("min", min)));
+        }
+        self
+    }
+
+    /// Checks an optional number; absent values are fine
+    pub fn range<T>(&mut self, field: &str, value: Option<T>, range: RangeInclusive<T>) -> &mut Self
+    where
+        T: PartialOrd + Copy + Into<Value>,
+    {
+        if let Some(value) = value {
+            if !range.contains(&value) {
+                let (min, max) = range.into_inner();
+                self.push(FieldError::new(
+                    field,
+                    code::OUT_OF_RANGE,
+                    Msg::OutOfRange.with("min", min).with("max", max).with("value", value),
+                ));
+            }
+        }
+        self
+    }
+
+    /// Checks an optional string against a list of choices, ignoring case
+    pub fn one_of(&mut self, field: &str, value: Option<&str>, choices: &[&str]) -> &mut Self {
+        if let Some(value) = value {
+            if !choices.contains(&value.to_lowercase().as_str()) {
+                self.push(FieldError::new(
+                    field,
+                    code::INVALID_CHOICE,
+                    Msg::InvalidChoice.with("value", value).with("choices", choices),
+                ));
+            }
+        }
+        self
+    }
+
+    /// `Err(AppError::Validation)` with everything collected so far, if anything
+    pub fn finish(&mut self) -> Result<()> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(result: Result<()>) -> Vec<FieldError> {
+        match result {
+            Err(AppError::Validation(errors)) => errors,
+            other => panic!("expected validation errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validator_collects_every_failure() {
+        let errors = errors(
+            Validator::new()
+                .email("email", "nope")
+                .min_length("password", "short", 8)
+                .range("hue", Some(200), -180..=180)
+                .range("contrast", None::<i32>, -100..=100)
+                .one_of("codec", Some("H264"), &["h264", "vp9"])
+                .finish(),
+        );
+        let fields: Vec<_> = errors.iter().map(|e| (e.field.as_str(), e.code)).collect();
+        assert_eq!(
+            fields,
+            [("email", code::INVALID_FORMAT), ("password", code::TOO_SHORT), ("hue", code::OUT_OF_RANGE)]
+        );
+        assert_eq!(errors[2].message.to_string(), "Must be between -180 and 180, got 200");
+        assert_eq!(errors[2].message.details()["max"], 180);
+        assert_eq!(errors[2].clone().within("operations[1]").field, "operations[1].hue");
+
+        assert!(Validator::new().email("email", "a@b.io").finish().is_ok());
+    }
+}
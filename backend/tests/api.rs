@@ -0,0 +1,1275 @@
+// backend/tests/api.rs
+// End-to-end requests through the real router. Needs a Postgres database at
+// DATABASE_URL; each test works in a schema of its own.
+#![cfg(feature = "db-tests")]
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use common::{TestApp, PASSWORD_RESET_SUBJECT, VERIFICATION_SUBJECT};
+use media_processor_server::db;
+use media_processor_server::services::queue::JobStatus;
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn test_register_then_login() {
+    let app = TestApp::new().await;
+    let credentials = json!({ "email": "someone@api.test", "password": "password1" });
+
+    let registered = app.post_json("/api/auth/register", None, credentials.clone()).await;
+    assert_eq!(registered.status, StatusCode::OK, "{}", registered.body);
+    assert_eq!(registered.body["user"]["tier"], "free");
+    assert_eq!(registered.body["user"]["email_verified"], false);
+
+    let again = app.post_json("/api/auth/register", None, credentials.clone()).await;
+    assert_eq!(again.status, StatusCode::CONFLICT);
+
+    let login = app.post_json("/api/auth/login", None, credentials).await;
+    assert_eq!(login.status, StatusCode::OK, "{}", login.body);
+    let token = login.body["token"].as_str().unwrap();
+    assert_eq!(app.get("/api/quota", token).await.status, StatusCode::OK);
+
+    let wrong = json!({ "email": "someone@api.test", "password": "password2" });
+    let rejected = app.post_json("/api/auth/login", None, wrong).await;
+    assert_eq!(rejected.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(rejected.body["error"]["code"], "UNAUTHORIZED");
+
+    assert_eq!(app.get("/api/quota", "not-a-token").await.status, StatusCode::UNAUTHORIZED);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_jobs_wait_for_email_verification() {
+    let app = TestApp::with_config(&[("RESEND_VERIFICATION_RATE_LIMIT", "1")]).await;
+    let (email, token) = app.register_unverified().await;
+    let first_link = app.emailed_token(&email, VERIFICATION_SUBJECT).await;
+    let asset_id = app.upload_png(&token).await;
+    let convert = json!({ "asset_id": asset_id, "output_format": "jpeg" });
+
+    let refused = app.post_json("/api/convert", Some(&token), convert.clone()).await;
+    assert_eq!(refused.status, StatusCode::FORBIDDEN, "{}", refused.body);
+    assert_eq!(refused.body["error"]["code"], "EMAIL_NOT_VERIFIED");
+
+    // A new link replaces the first, and asking again too soon is throttled
+    let resent = app.post_json("/api/auth/resend-verification", Some(&token), json!({})).await;
+    assert_eq!(resent.status, StatusCode::NO_CONTENT, "{}", resent.body);
+    let again = app.post_json("/api/auth/resend-verification", Some(&token), json!({})).await;
+    assert_eq!(again.status, StatusCode::TOO_MANY_REQUESTS);
+    for _ in 0..50 {
+        if app.mailer.sent().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let second_link = app.emailed_token(&email, VERIFICATION_SUBJECT).await;
+    assert_ne!(second_link, first_link);
+    assert_eq!(app.verify_email(&first_link).await.status, StatusCode::BAD_REQUEST);
+
+    let verified = app.verify_email(&second_link).await;
+    assert_eq!(verified.status, StatusCode::OK, "{}", verified.body);
+    assert_eq!(verified.body["email_verified"], true);
+    // Links are single use
+    assert_eq!(app.verify_email(&second_link).await.status, StatusCode::BAD_REQUEST);
+
+    let queued = app.post_json("/api/convert", Some(&token), convert).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    let resent = app.post_json("/api/auth/resend-verification", Some(&token), json!({})).await;
+    assert_eq!(resent.status, StatusCode::CONFLICT);
+    app.finish().await;
+}
+
+/// Ask for a reset link for `email` and return its token
+async fn request_reset(app: &TestApp, email: &str) -> String {
+    let requested = app.post_json("/api/auth/forgot-password", None, json!({ "email": email })).await;
+    assert_eq!(requested.status, StatusCode::OK, "{}", requested.body);
+    app.emailed_token(email, PASSWORD_RESET_SUBJECT).await
+}
+
+fn login(email: &str, password: &str) -> serde_json::Value {
+    json!({ "email": email, "password": password })
+}
+
+#[tokio::test]
+async fn test_password_reset_link_works_once() {
+    let app = TestApp::new().await;
+    let (email, _) = app.register_unverified().await;
+    let token = request_reset(&app, &email).await;
+
+    // A password the policy rejects doesn't use up the link
+    let short = app
+        .post_json("/api/auth/reset-password", None, json!({ "token": token, "new_password": "short" }))
+        .await;
+    assert_eq!(short.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let reset = json!({ "token": token, "new_password": "new-password" });
+    let changed = app.post_json("/api/auth/reset-password", None, reset.clone()).await;
+    assert_eq!(changed.status, StatusCode::NO_CONTENT, "{}", changed.body);
+    let old = app.post_json("/api/auth/login", None, login(&email, "password1")).await;
+    assert_eq!(old.status, StatusCode::UNAUTHORIZED);
+    let new = app.post_json("/api/auth/login", None, login(&email, "new-password")).await;
+    assert_eq!(new.status, StatusCode::OK);
+
+    let reused = app.post_json("/api/auth/reset-password", None, reset).await;
+    assert_eq!(reused.status, StatusCode::BAD_REQUEST);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_expired_reset_link_is_refused() {
+    let app = TestApp::new().await;
+    let (email, _) = app.register_unverified().await;
+    let token = request_reset(&app, &email).await;
+    sqlx::query("UPDATE password_resets SET expires_at = NOW() - INTERVAL '1 minute'")
+        .execute(&app.state.db)
+        .await
+        .unwrap();
+
+    let reset = json!({ "token": token, "new_password": "new-password" });
+    let expired = app.post_json("/api/auth/reset-password", None, reset).await;
+    assert_eq!(expired.status, StatusCode::BAD_REQUEST);
+    let unchanged = app.post_json("/api/auth/login", None, login(&email, "password1")).await;
+    assert_eq!(unchanged.status, StatusCode::OK);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_forgot_password_does_not_reveal_accounts() {
+    let app = TestApp::with_config(&[("FORGOT_PASSWORD_RATE_LIMIT", "2")]).await;
+    let (email, _) = app.register_unverified().await;
+
+    let known = app.post_json("/api/auth/forgot-password", None, json!({ "email": email })).await;
+    let unknown = app
+        .post_json("/api/auth/forgot-password", None, json!({ "email": "nobody@api.test" }))
+        .await;
+    assert_eq!((known.status, unknown.status), (StatusCode::OK, StatusCode::OK));
+    assert_eq!(known.body, unknown.body);
+    app.emailed_token(&email, PASSWORD_RESET_SUBJECT).await;
+    assert!(app.mailer.sent().iter().all(|m| m.to != "nobody@api.test"));
+
+    // The limit applies per address whether or not an account uses the email
+    let throttled = app
+        .post_json("/api/auth/forgot-password", None, json!({ "email": "someone-else@api.test" }))
+        .await;
+    assert_eq!(throttled.status, StatusCode::TOO_MANY_REQUESTS);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_deleting_an_account_removes_its_data() {
+    let mut app = TestApp::new().await;
+    app.complete_jobs_with(b"converted");
+    let (email, token) = app.register_unverified().await;
+    app.verify_email(&app.emailed_token(&email, VERIFICATION_SUBJECT).await).await;
+    let other = app.register().await;
+    let other_asset = app.upload_png(&other).await;
+
+    let uploaded = app.upload(&token, "photo.png", &common::png(16, 16)).await;
+    let asset_id = uploaded.body["asset_id"].as_str().unwrap();
+    let queued = app
+        .post_json("/api/convert", Some(&token), json!({ "asset_id": asset_id, "output_format": "jpeg" }))
+        .await;
+    let job_uri = format!("/api/jobs/{}", queued.body["job_id"].as_str().unwrap());
+    let mut job = app.get(&job_uri, &token).await;
+    for _ in 0..50 {
+        if job.body["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = app.get(&job_uri, &token).await;
+    }
+    assert_eq!(job.body["status"], "completed", "{}", job.body);
+    let locations: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT location FROM users u, LATERAL (
+            SELECT result_location FROM media_assets WHERE user_id = u.id
+            UNION SELECT thumbnail_location FROM media_assets WHERE user_id = u.id
+            UNION SELECT result_location FROM jobs WHERE user_id = u.id
+        ) AS owned (location)
+        WHERE u.email = $1 AND location IS NOT NULL
+        "#,
+    )
+    .bind(&email)
+    .fetch_all(&app.state.db)
+    .await
+    .unwrap();
+    assert!(locations.len() >= 2, "{:?}", locations);
+
+    let wrong = app.delete_json("/api/auth/account", &token, json!({ "password": "password2" })).await;
+    assert_eq!(wrong.status, StatusCode::UNAUTHORIZED);
+    let deleted = app.delete_json("/api/auth/account", &token, json!({ "password": "password1" })).await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT, "{}", deleted.body);
+
+    // The token dies with the account, and the other user's upload is untouched
+    assert_eq!(app.get("/api/quota", &token).await.status, StatusCode::UNAUTHORIZED);
+    let relogin = app.post_json("/api/auth/login", None, login(&email, "password1")).await;
+    assert_eq!(relogin.status, StatusCode::UNAUTHORIZED);
+    for location in &locations {
+        assert!(app.state.storage.load_bytes(&location.parse().unwrap()).await.is_err(), "{} is still stored", location);
+    }
+    assert_eq!(app.get(&format!("/api/assets/{}", other_asset), &other).await.status, StatusCode::OK);
+    let pending: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pending_deletions")
+        .fetch_one(&app.state.db)
+        .await
+        .unwrap();
+    assert_eq!(pending, 0);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_upload_stores_the_file() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+
+    let png = common::png(16, 16);
+    let uploaded = app.upload(&token, "photo.png", &png).await;
+    assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.body);
+    assert_eq!((uploaded.body["width"].as_i64(), uploaded.body["height"].as_i64()), (Some(16), Some(16)));
+    let location = uploaded.body["location"].as_str().unwrap();
+    assert_eq!(&app.state.storage.load_bytes(&location.parse().unwrap()).await.unwrap()[..], &png[..]);
+
+    let asset_id = uploaded.body["asset_id"].as_str().unwrap();
+    let asset = app.get(&format!("/api/assets/{}", asset_id), &token).await;
+    assert_eq!(asset.body["filename"], "photo.png");
+
+    // Content that isn't what the name says is refused
+    let mislabeled = app.upload(&token, "photo.png", b"plain text").await;
+    assert_eq!(mislabeled.status, StatusCode::BAD_REQUEST);
+
+    // So is a small file declaring a canvas that would take gigabytes to decode
+    let bomb = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/huge_canvas.png")).unwrap();
+    let refused = app.upload(&token, "huge.png", &bomb).await;
+    assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", refused.body);
+    assert_eq!(refused.body["error"]["reason"], "image_too_many_pixels");
+    assert_eq!(refused.body["error"]["details"]["pixels"], 256_000_000);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_heic_over_the_pixel_limit_is_refused_at_upload() {
+    // The fixture is 64x32
+    let app = TestApp::with_config(&[("MAX_IMAGE_PIXELS", "2047")]).await;
+    let token = app.register().await;
+
+    let heic = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/small.heic")).unwrap();
+    let refused = app.upload(&token, "photo.heic", &heic).await;
+    assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", refused.body);
+    let expected = if media_processor_server::services::heic::available() {
+        "image_too_many_pixels"
+    } else {
+        "heic_unsupported"
+    };
+    assert_eq!(refused.body["error"]["reason"], expected);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_upload_flagged_by_the_scanner_is_refused() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+
+    // A real image carrying the test signature passes every other check
+    let mut png = common::png(16, 16);
+    png.extend_from_slice(common::EICAR);
+    let uploaded = app.upload(&token, "photo.png", &png).await;
+    assert_eq!(uploaded.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", uploaded.body);
+    assert_eq!(uploaded.body["error"]["code"], "MALWARE_DETECTED");
+    assert!(uploaded.body["error"]["message"].as_str().unwrap().contains("Eicar-Test-Signature"));
+
+    let assets = app.get("/api/assets", &token).await;
+    assert_eq!(assets.body["total"], 0);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_resumable_upload_in_chunks() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let png = common::png(64, 64);
+    let (first, rest) = png.split_at(png.len() / 2);
+
+    let init = json!({ "filename": "photo.png", "size": png.len() });
+    let started = app.post_json("/api/upload/init", Some(&token), init).await;
+    assert_eq!(started.status, StatusCode::OK, "{}", started.body);
+    let uri = format!("/api/upload/{}", started.body["upload_id"].as_str().unwrap());
+    let complete_uri = format!("{}/complete", uri);
+
+    let sent = app.patch_chunk(&uri, &token, 0, first).await;
+    assert_eq!(sent.status, StatusCode::OK, "{}", sent.body);
+    assert_eq!(sent.body["received_bytes"], first.len());
+    // Chunks must follow on from what has arrived
+    assert_eq!(app.patch_chunk(&uri, &token, 0, first).await.status, StatusCode::CONFLICT);
+    let early = app.post_json(&complete_uri, Some(&token), json!({})).await;
+    assert_eq!(early.status, StatusCode::CONFLICT);
+
+    // A resuming client asks where to carry on from
+    let offset = app.get(&uri, &token).await.body["received_bytes"].as_u64().unwrap();
+    let sent = app.patch_chunk(&uri, &token, offset, rest).await;
+    assert_eq!(sent.body["received_bytes"], png.len());
+    let overflow = app.patch_chunk(&uri, &token, png.len() as u64, b"x").await;
+    assert_eq!(overflow.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let sha256 = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&png));
+    let completed = app.post_json(&complete_uri, Some(&token), json!({ "sha256": sha256 })).await;
+    assert_eq!(completed.status, StatusCode::OK, "{}", completed.body);
+    assert_eq!((completed.body["width"].as_i64(), completed.body["height"].as_i64()), (Some(64), Some(64)));
+    let location = completed.body["location"].as_str().unwrap();
+    assert_eq!(&app.state.storage.load_bytes(&location.parse().unwrap()).await.unwrap()[..], &png[..]);
+    // The upload is used up
+    assert_eq!(app.get(&uri, &token).await.status, StatusCode::NOT_FOUND);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_resumable_upload_checks_the_assembled_file() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let png = common::png(16, 16);
+    let start = |filename: &'static str| {
+        let app = &app;
+        let token = &token;
+        let size = png.len();
+        async move {
+            let started = app
+                .post_json("/api/upload/init", Some(token), json!({ "filename": filename, "size": size }))
+                .await;
+            format!("/api/upload/{}", started.body["upload_id"].as_str().unwrap())
+        }
+    };
+
+    let uri = start("photo.png").await;
+    app.patch_chunk(&uri, &token, 0, &png).await;
+    let mismatch = app
+        .post_json(&format!("{}/complete", uri), Some(&token), json!({ "sha256": "00".repeat(32) }))
+        .await;
+    assert_eq!(mismatch.status, StatusCode::BAD_REQUEST);
+    assert_eq!(app.get(&uri, &token).await.status, StatusCode::NOT_FOUND);
+    assert!(!app.state.part_files.path(uri.rsplit('/').next().unwrap().parse().unwrap()).exists());
+
+    // Content that isn't what the name says is refused, as for regular uploads
+    let uri = start("clip.mp4").await;
+    app.patch_chunk(&uri, &token, 0, &png).await;
+    let mislabeled = app.post_json(&format!("{}/complete", uri), Some(&token), json!({})).await;
+    assert_eq!(mislabeled.status, StatusCode::BAD_REQUEST, "{}", mislabeled.body);
+
+    let too_big = json!({ "filename": "photo.png", "size": 1024 * 1024 * 1024 });
+    let refused = app.post_json("/api/upload/init", Some(&token), too_big).await;
+    assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_upload_from_url_refuses_unsafe_urls() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+
+    for url in ["http://example.com/a.png", "https://127.0.0.1/a.png", "https://169.254.169.254/a.png"] {
+        let refused = app.post_json("/api/upload/from-url", Some(&token), json!({ "url": url })).await;
+        assert_eq!(refused.status, StatusCode::BAD_REQUEST, "{}", url);
+    }
+    // A bad name is refused before anything is fetched
+    let named = json!({ "url": "https://127.0.0.1/a.png", "filename": "notes.txt" });
+    let refused = app.post_json("/api/upload/from-url", Some(&token), named).await;
+    assert_eq!(refused.status, StatusCode::BAD_REQUEST);
+    assert!(refused.body["error"]["message"].as_str().unwrap().starts_with("Unsupported file type"));
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_convert_runs_to_a_downloadable_result() {
+    let mut app = TestApp::new().await;
+    app.complete_jobs_with(b"converted");
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+
+    let queued = app
+        .post_json("/api/convert", Some(&token), json!({ "asset_id": asset_id, "output_format": "jpeg" }))
+        .await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    let job_id = queued.body["job_id"].as_str().unwrap().to_string();
+
+    let mut status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    for _ in 0..50 {
+        if status.body["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    }
+    assert_eq!(status.body["status"], "completed", "{}", status.body);
+    assert_eq!(status.body["job_type"], "convert");
+    assert_eq!(status.body["asset_ids"], json!([asset_id]));
+
+    let download = app.get(&format!("/api/download/{}", job_id), &token).await;
+    assert_eq!(download.status, StatusCode::OK);
+    assert_eq!(download.body, "converted");
+
+    // The status carries a link needing no credentials, the same one on every poll
+    let download_url = status.body["download_url"].as_str().unwrap().to_string();
+    assert!(download_url.starts_with("/api/files/"), "{}", download_url);
+    assert!(status.body["download_url_expires_at"].is_string());
+    let polled = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    assert_eq!(polled.body["download_url"], download_url.as_str());
+    let fetched = app.send(Request::get(&download_url).body(Body::empty()).unwrap()).await;
+    assert_eq!(fetched.status, StatusCode::OK);
+    assert_eq!(fetched.body, "converted");
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_downloads_of_outputs_named_after_uploads_quote_the_name() {
+    let mut app = TestApp::new().await;
+    // Batch outputs are named after the uploads they came from
+    app.complete_jobs_with_outputs(vec![("say \"hi\"; x.jpg", b"first"), ("beach.jpg", b"second")]);
+    let token = app.register().await;
+    // Escaped for the multipart header it is sent in
+    let uploaded = app.upload(&token, r#"say \"hi\"; x.png"#, &common::png(16, 16)).await;
+    assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.body);
+    let asset = app.get(&format!("/api/assets/{}", uploaded.body["asset_id"].as_str().unwrap()), &token).await;
+    assert_eq!(asset.body["filename"], "say \"hi\"; x.png");
+
+    let convert = json!({ "asset_id": uploaded.body["asset_id"], "output_format": "jpeg" });
+    let queued = app.post_json("/api/convert", Some(&token), convert).await;
+    let job_id = queued.body["job_id"].as_str().unwrap().to_string();
+    let uri = format!("/api/download/{}?output=0", job_id);
+    let mut download = app.get(&uri, &token).await;
+    for _ in 0..50 {
+        if download.status == StatusCode::OK {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        download = app.get(&uri, &token).await;
+    }
+    assert_eq!(download.status, StatusCode::OK);
+    assert_eq!(download.body, "first");
+    assert_eq!(
+        download.headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"say _hi__ x.jpg\"; filename*=UTF-8''say%20%22hi%22%3B%20x.jpg"
+    );
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_multi_output_jobs_download_one_output_or_a_zip() {
+    let mut app = TestApp::new().await;
+    app.complete_jobs_with_outputs(vec![("holiday.jpg", b"first"), ("beach.jpg", b"second one")]);
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+
+    let convert = json!({ "asset_id": asset_id, "output_format": "jpeg" });
+    let queued = app.post_json("/api/convert", Some(&token), convert).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    let job_id = queued.body["job_id"].as_str().unwrap().to_string();
+
+    let mut status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    for _ in 0..50 {
+        if status.body["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    }
+    assert_eq!(status.body["status"], "completed", "{}", status.body);
+    let outputs: Vec<_> = status.body["outputs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| (o["index"].as_i64().unwrap(), o["filename"].as_str().unwrap(), o["size"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(outputs, [(0, "holiday.jpg", 5), (1, "beach.jpg", 10)]);
+    let listed = app.get("/api/jobs", &token).await;
+    assert_eq!(listed.body["jobs"][0]["outputs"][1]["filename"], "beach.jpg");
+
+    let second = app.get(&format!("/api/download/{}?output=1", job_id), &token).await;
+    assert_eq!(second.status, StatusCode::OK);
+    assert_eq!(second.body, "second one");
+    assert_eq!(
+        second.headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"beach.jpg\"; filename*=UTF-8''beach.jpg"
+    );
+
+    // Without a pick, several outputs come as one zip
+    for uri in [format!("/api/download/{}", job_id), format!("/api/download/{}?format=zip", job_id)] {
+        let zipped = app.get(&uri, &token).await;
+        assert_eq!(zipped.status, StatusCode::OK, "{}", uri);
+        assert_eq!(zipped.headers[header::CONTENT_TYPE], "application/zip");
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zipped.bytes.to_vec())).unwrap();
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("beach.jpg").unwrap(), &mut contents).unwrap();
+        assert_eq!(contents, "second one");
+        assert_eq!(archive.len(), 2);
+    }
+
+    let missing = app.get(&format!("/api/download/{}?output=2", job_id), &token).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    for query in ["format=tar", "output=-1", "output=0&format=zip"] {
+        let refused = app.get(&format!("/api/download/{}?{}", job_id, query), &token).await;
+        assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", query);
+    }
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_completed_jobs_describe_their_result() {
+    let mut app = TestApp::new().await;
+    let result: &'static [u8] = Box::leak(common::png(3, 2).into_boxed_slice());
+    app.complete_jobs_with_outputs(vec![("result.png", result)]);
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+
+    let convert = json!({ "asset_id": asset_id, "output_format": "png" });
+    let queued = app.post_json("/api/convert", Some(&token), convert).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    let job_id = queued.body["job_id"].as_str().unwrap().to_string();
+
+    let mut status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    for _ in 0..50 {
+        if status.body["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    }
+    assert_eq!(status.body["status"], "completed", "{}", status.body);
+    assert!(status.body["result_url"].is_string());
+
+    // The metadata matches the file the download serves
+    let downloaded = app.get(&format!("/api/download/{}", job_id), &token).await;
+    assert_eq!(downloaded.status, StatusCode::OK);
+    let img = image::load_from_memory(&downloaded.bytes).unwrap();
+    let described = &status.body["result"];
+    assert_eq!(described["width"], img.width());
+    assert_eq!(described["height"], img.height());
+    assert_eq!(described["size_bytes"], downloaded.bytes.len());
+    assert_eq!(described["format"], "png");
+    assert!(described["duration_ms"].is_i64());
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_deleted_assets_take_no_jobs_until_restored() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let used = app.get("/api/quota", &token).await.body["storage"]["used"].clone();
+    assert_ne!(used, 0);
+
+    let deleted = app.delete_json(&format!("/api/assets/{}", asset_id), &token, json!({})).await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.get("/api/assets", &token).await.body["total"], 0);
+    assert_eq!(app.get(&format!("/api/assets/{}", asset_id), &token).await.status, StatusCode::NOT_FOUND);
+    // The space is free again at once
+    assert_eq!(app.get("/api/quota", &token).await.body["storage"]["used"], 0);
+
+    let convert = json!({ "asset_id": asset_id, "output_format": "jpeg" });
+    let refused = app.post_json("/api/convert", Some(&token), convert.clone()).await;
+    assert_eq!(refused.status, StatusCode::NOT_FOUND, "{}", refused.body);
+
+    let other = app.register().await;
+    let stolen = app.post_json(&format!("/api/assets/{}/restore", asset_id), Some(&other), json!({})).await;
+    assert_eq!(stolen.status, StatusCode::FORBIDDEN);
+
+    let restored = app.post_json(&format!("/api/assets/{}/restore", asset_id), Some(&token), json!({})).await;
+    assert_eq!(restored.status, StatusCode::OK, "{}", restored.body);
+    assert_eq!(restored.body["id"], asset_id.as_str());
+    assert_eq!(app.get("/api/quota", &token).await.body["storage"]["used"], used);
+    let again = app.post_json(&format!("/api/assets/{}/restore", asset_id), Some(&token), json!({})).await;
+    assert_eq!(again.status, StatusCode::CONFLICT);
+
+    let queued = app.post_json("/api/convert", Some(&token), convert).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_jobs_whose_wake_up_was_lost_are_announced_again() {
+    let mut app = TestApp::with_config(&[("QUEUED_STALE_AFTER_SECONDS", "60")]).await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let queued = app
+        .post_json("/api/convert", Some(&token), json!({ "asset_id": asset_id, "output_format": "jpeg" }))
+        .await;
+    let job_id = queued.body["job_id"].as_str().unwrap().to_string();
+    app.lose_wake_ups();
+    app.complete_jobs_with(b"converted");
+
+    // Recently queued: nothing is wrong yet
+    let status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    assert_eq!(status.body["status"], "queued");
+    assert!(status.body.get("stalled").is_none(), "{}", status.body);
+
+    sqlx::query("UPDATE jobs SET enqueued_at = NOW() - INTERVAL '2 minutes' WHERE id = $1")
+        .bind(Uuid::parse_str(&job_id).unwrap())
+        .execute(&app.state.db)
+        .await
+        .unwrap();
+    let status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    assert_eq!(status.body["stalled"], true, "{}", status.body);
+
+    let mut status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    for _ in 0..50 {
+        if status.body["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        status = app.get(&format!("/api/jobs/{}", job_id), &token).await;
+    }
+    assert_eq!(status.body["status"], "completed", "{}", status.body);
+    assert!(status.body.get("stalled").is_none());
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_scheduled_jobs_wait_and_count_toward_todays_quota() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let tonight = chrono::Utc::now() + chrono::Duration::hours(8);
+
+    let high = json!({ "asset_id": asset_id, "output_format": "jpeg", "priority": "high" });
+    let refused = app.post_json("/api/convert", Some(&token), high).await;
+    assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", refused.body);
+
+    let convert = json!({ "asset_id": asset_id, "output_format": "jpeg", "priority": "low", "run_after": tonight.to_rfc3339() });
+    let queued = app.post_json("/api/convert", Some(&token), convert).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+
+    let status = app.get(&format!("/api/jobs/{}", queued.body["job_id"].as_str().unwrap()), &token).await;
+    assert_eq!(status.body["status"], "queued");
+    let scheduled_for = chrono::DateTime::parse_from_rfc3339(status.body["scheduled_for"].as_str().unwrap()).unwrap();
+    assert_eq!(scheduled_for.timestamp_millis(), tonight.timestamp_millis());
+    assert_eq!(app.get("/api/quota", &token).await.body["images"]["used"], 1);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_remove_bg_background_must_be_the_callers_image() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let other = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let background_id = app.upload_png(&token).await;
+    let others_background = app.upload_png(&other).await;
+
+    // An MP4 header is enough for the upload to be taken as a video
+    let mut mp4 = vec![0, 0, 0, 20];
+    mp4.extend_from_slice(b"ftypisom\0\0\0\0isom");
+    let video = app.upload(&token, "clip.mp4", &mp4).await;
+    assert_eq!(video.status, StatusCode::OK, "{}", video.body);
+    let video_id = video.body["asset_id"].as_str().unwrap();
+
+    let remove_bg = |background: &str| json!({ "asset_id": asset_id, "background_asset_id": background });
+    let stolen = app.post_json("/api/remove-bg", Some(&token), remove_bg(&others_background)).await;
+    assert_eq!(stolen.status, StatusCode::FORBIDDEN);
+    let moving = app.post_json("/api/remove-bg", Some(&token), remove_bg(video_id)).await;
+    assert_eq!(moving.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", moving.body);
+    let both = json!({ "asset_id": asset_id, "background_asset_id": background_id, "replace_color": [0, 0, 0] });
+    let refused = app.post_json("/api/remove-bg", Some(&token), both).await;
+    assert_eq!(refused.status, StatusCode::BAD_REQUEST);
+
+    let queued = app.post_json("/api/remove-bg", Some(&token), remove_bg(&background_id)).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    let (parameters, background): (serde_json::Value, String) = sqlx::query_as(
+        "SELECT j.parameters, a.result_location FROM jobs j, media_assets a WHERE j.id = $1::uuid AND a.id = $2::uuid",
+    )
+    .bind(queued.body["job_id"].as_str().unwrap())
+    .bind(&background_id)
+    .fetch_one(&app.state.db)
+    .await
+    .unwrap();
+    assert_eq!(parameters["mode"], "image");
+    assert_eq!(parameters["background_location"], background);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_video_work_is_refused_without_ffmpeg() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+
+    // Public, like the health check
+    let request = Request::get("/api/capabilities").body(Body::empty()).unwrap();
+    let capabilities = app.send(request).await;
+    assert_eq!(capabilities.status, StatusCode::OK, "{}", capabilities.body);
+    assert_eq!(capabilities.body["video"], app.state.ffmpeg.available());
+    assert!(capabilities.body["image_formats"]["output"].as_array().unwrap().contains(&json!("png")));
+    if app.state.ffmpeg.available() {
+        return app.finish().await;
+    }
+    assert_eq!(capabilities.body["video_formats"]["operations"], json!([]));
+    assert_eq!(capabilities.body["video_codecs"], json!([]));
+
+    let mut mp4 = vec![0, 0, 0, 20];
+    mp4.extend_from_slice(b"ftypisom\0\0\0\0isom");
+    let video = app.upload(&token, "clip.mp4", &mp4).await;
+    assert_eq!(video.status, StatusCode::OK, "{}", video.body);
+    let video_id = &video.body["asset_id"];
+
+    let convert = app.post_json("/api/convert", Some(&token), json!({ "asset_id": video_id, "output_format": "mp4" }));
+    let remove_bg = app.post_json("/api/remove-bg", Some(&token), json!({ "asset_id": video_id }));
+    for refused in [convert.await, remove_bg.await] {
+        assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", refused.body);
+        assert_eq!(refused.body["error"]["reason"], "video_unavailable");
+    }
+    let jobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM jobs").fetch_one(&app.state.db).await.unwrap();
+    assert_eq!(jobs, 0);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_analyze_small_images_in_the_request() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+
+    let analyze = json!({ "asset_id": asset_id });
+    let first = app.post_json("/api/analyze", Some(&token), analyze.clone()).await;
+    assert_eq!(first.status, StatusCode::OK, "{}", first.body);
+    assert_eq!(first.body["status"], "completed");
+    let analysis = &first.body["analysis"];
+    assert_eq!(analysis["histogram"]["red"].as_array().unwrap().len(), 256);
+    assert!(!analysis["dominant_colors"].as_array().unwrap().is_empty());
+
+    let cached = app.post_json("/api/analyze", Some(&token), analyze).await;
+    assert_eq!(cached.body, first.body);
+
+    let mut mp4 = vec![0, 0, 0, 20];
+    mp4.extend_from_slice(b"ftypisom\0\0\0\0isom");
+    let video = app.upload(&token, "clip.mp4", &mp4).await;
+    let video = json!({ "asset_id": video.body["asset_id"] });
+    let refused = app.post_json("/api/analyze", Some(&token), video).await;
+    assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", refused.body);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_analyze_queues_large_images() {
+    let app = TestApp::with_config(&[("ANALYZE_SYNC_MAX_MB", "0")]).await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+
+    let queued = app.post_json("/api/analyze", Some(&token), json!({ "asset_id": asset_id })).await;
+    assert_eq!(queued.status, StatusCode::ACCEPTED, "{}", queued.body);
+    assert_eq!(queued.body["status"], "queued");
+    assert!(queued.body.get("analysis").is_none());
+    let job = app.get(&format!("/api/jobs/{}", queued.body["job_id"].as_str().unwrap()), &token).await;
+    assert_eq!(job.status, StatusCode::OK, "{}", job.body);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_generate_lut_queues_a_job_for_two_images() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let source_id = app.upload_png(&token).await;
+    let graded_id = app.upload_png(&token).await;
+
+    let mut mp4 = vec![0, 0, 0, 20];
+    mp4.extend_from_slice(b"ftypisom\0\0\0\0isom");
+    let video = app.upload(&token, "clip.mp4", &mp4).await;
+    let from_video = json!({ "source_asset_id": video.body["asset_id"], "graded_asset_id": graded_id });
+    let refused = app.post_json("/api/luts/generate", Some(&token), from_video).await;
+    assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", refused.body);
+
+    let bad_name = json!({ "source_asset_id": source_id, "graded_asset_id": graded_id, "name": "a/b" });
+    let refused = app.post_json("/api/luts/generate", Some(&token), bad_name).await;
+    assert_eq!(refused.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", refused.body);
+    assert_eq!(refused.body["error"]["errors"][0]["field"], "name");
+
+    let request = json!({ "source_asset_id": source_id, "graded_asset_id": graded_id, "name": "Teal night" });
+    let queued = app.post_json("/api/luts/generate", Some(&token), request).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    let job = app.get(&format!("/api/jobs/{}", queued.body["job_id"].as_str().unwrap()), &token).await;
+    assert_eq!(job.body["job_type"], "lut_generate");
+    assert_eq!(job.body["asset_ids"], json!([source_id, graded_id]));
+    let (name,): (String,) = sqlx::query_as("SELECT parameters->>'name' FROM jobs WHERE id = $1::uuid")
+        .bind(queued.body["job_id"].as_str().unwrap())
+        .fetch_one(&app.state.db)
+        .await
+        .unwrap();
+    assert_eq!(name, "Teal night.cube");
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_other_users_assets_and_jobs_are_forbidden() {
+    let app = TestApp::new().await;
+    let owner = app.register().await;
+    let other = app.register().await;
+    let asset_id = app.upload_png(&owner).await;
+
+    let asset = app.get(&format!("/api/assets/{}", asset_id), &other).await;
+    assert_eq!(asset.status, StatusCode::FORBIDDEN);
+    assert_eq!(asset.body["error"]["code"], "FORBIDDEN");
+
+    let convert = json!({ "asset_id": asset_id, "output_format": "jpeg" });
+    let stolen = app.post_json("/api/convert", Some(&other), convert.clone()).await;
+    assert_eq!(stolen.status, StatusCode::FORBIDDEN);
+
+    let queued = app.post_json("/api/convert", Some(&owner), convert).await;
+    let job_id = queued.body["job_id"].as_str().unwrap();
+    let uris = [
+        format!("/api/jobs/{}", job_id),
+        format!("/api/jobs/{}/events", job_id),
+        format!("/api/download/{}", job_id),
+    ];
+    for uri in uris {
+        assert_eq!(app.get(&uri, &other).await.status, StatusCode::FORBIDDEN, "{}", uri);
+    }
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_job_status_falls_back_to_the_live_status_map() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+
+    // A job with a row is read from the table, under either route
+    let queued = app
+        .post_json("/api/convert", Some(&token), json!({ "asset_id": asset_id, "output_format": "jpeg" }))
+        .await;
+    let job_id = queued.body["job_id"].as_str().unwrap();
+    for uri in [format!("/api/jobs/{}", job_id), format!("/api/status/{}", job_id)] {
+        let status = app.get(&uri, &token).await;
+        assert_eq!(status.status, StatusCode::OK, "{}", uri);
+        assert_eq!(status.body["job_type"], "convert");
+        assert_eq!(status.body["asset_ids"], json!([asset_id]));
+    }
+
+    // IDs only the status map knows, whether legacy or without a row yet
+    let statuses = app.state.queue.get_statuses_handle();
+    let rowless = Uuid::new_v4().to_string();
+    statuses.set("legacy-job-1", JobStatus::Processing { progress: 30 }).await;
+    let rowless_completed = Uuid::new_v4().to_string();
+    statuses.set(&rowless, JobStatus::Failed { error: "boom".to_string() }).await;
+    statuses.set(&rowless_completed, JobStatus::Completed { result_url: "users/x/result.png".to_string() }).await;
+    statuses.set("legacy-job-3", JobStatus::Completed { result_url: "legacy/result.png".to_string() }).await;
+    let legacy = app.get("/api/status/legacy-job-1", &token).await;
+    assert_eq!(legacy.status, StatusCode::OK, "{}", legacy.body);
+    assert_eq!((legacy.body["status"].as_str(), legacy.body["progress"].as_u64()), (Some("processing"), Some(30)));
+    assert_eq!(legacy.body["job_id"], "legacy-job-1");
+    assert!(legacy.body["created_at"].is_string());
+    let legacy = app.get("/api/status/legacy-job-3", &token).await;
+    assert_eq!(legacy.body["result_url"], "legacy/result.png");
+
+    // A UUID without a row may be a deleted job of anyone's: only how far it got
+    let failed = app.get(&format!("/api/jobs/{}", rowless), &token).await;
+    assert_eq!(failed.status, StatusCode::OK, "{}", failed.body);
+    assert_eq!(failed.body["status"], "failed");
+    assert!(failed.body.get("error").is_none(), "{}", failed.body);
+    let completed = app.get(&format!("/api/status/{}", rowless_completed), &token).await;
+    assert_eq!(completed.body["status"], "completed");
+    assert_eq!(completed.body["progress"], 100);
+    assert!(completed.body.get("result_url").is_none(), "{}", completed.body);
+
+    // Known to neither
+    for id in ["legacy-job-2".to_string(), Uuid::new_v4().to_string()] {
+        let missing = app.get(&format!("/api/status/{}", id), &token).await;
+        assert_eq!(missing.status, StatusCode::NOT_FOUND, "{}", id);
+        assert_eq!(missing.body["error"]["reason"], "job_not_found");
+    }
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_job_events_list_the_history_oldest_first() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let queued = app
+        .post_json("/api/convert", Some(&token), json!({ "asset_id": asset_id, "output_format": "jpeg" }))
+        .await;
+    let job_id = queued.body["job_id"].as_str().unwrap().to_string();
+    let events_uri = format!("/api/jobs/{}/events", job_id);
+
+    let events = app.get(&events_uri, &token).await;
+    assert_eq!(events.status, StatusCode::OK, "{}", events.body);
+    assert_eq!(events.body["total"], 1);
+    assert_eq!(events.body["events"][0]["event_type"], "queued");
+    assert!(events.body["events"][0]["created_at"].is_string());
+
+    // What a worker would record on a run that fails for good; a retry queues it again
+    let id = Uuid::parse_str(&job_id).unwrap();
+    db::JobEvent::record(&app.state.db, id, "started", Some(json!({ "attempt": 1 }))).await;
+    db::Job::fail(&app.state.db, id, "Asset not found", "processing_failed").await.unwrap();
+    db::JobEvent::record(&app.state.db, id, "failed", Some(json!({ "error_code": "processing_failed" }))).await;
+    let retried = app.post_json(&format!("/api/jobs/{}/retry", job_id), Some(&token), json!({})).await;
+    assert_eq!(retried.status, StatusCode::OK, "{}", retried.body);
+
+    let events = app.get(&events_uri, &token).await;
+    let types: Vec<_> = events.body["events"].as_array().unwrap().iter().map(|e| e["event_type"].clone()).collect();
+    assert_eq!(types, [json!("queued"), json!("started"), json!("failed"), json!("queued")]);
+    let page = app.get(&format!("{}?limit=2&offset=2", events_uri), &token).await;
+    assert_eq!([&page.body["total"], &page.body["limit"], &page.body["offset"]], [4, 2, 2]);
+    assert_eq!(page.body["events"][0]["event_type"], "failed");
+    assert_eq!(page.body["events"][0]["detail"]["error_code"], "processing_failed");
+    assert_eq!(page.body["events"][1]["event_type"], "queued");
+
+    let missing = app.get(&format!("/api/jobs/{}/events", Uuid::new_v4()), &token).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_idempotent_upload_is_replayed_and_leaves_no_spooled_files() {
+    let app = TestApp::new().await;
+    let token = app.register().await;
+    let png = common::png(16, 16);
+    let upload = |boundary: &str| {
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"photo.png\"\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(&png);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        Request::post("/api/upload")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .header("Idempotency-Key", "upload-1")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let first = app.send(upload("first-attempt")).await;
+    assert_eq!(first.status, StatusCode::OK, "{}", first.body);
+    // Clients pick a new boundary for every attempt
+    let retried = app.send(upload("second-attempt")).await;
+    assert_eq!(retried.status, StatusCode::OK, "{}", retried.body);
+    assert_eq!(retried.body["asset_id"], first.body["asset_id"]);
+    assert_eq!(retried.headers["idempotent-replayed"], "true");
+
+    let spooled = std::fs::read_dir(&app.state.config.processing.temp_dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("idempotent_"))
+        .count();
+    assert_eq!(spooled, 0);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_idempotency_key_replays_the_first_job() {
+    let mut app = TestApp::with_config(&[("FREE_TIER_IMAGE_DAILY", "1"), ("FREE_TIER_CONCURRENT", "5")]).await;
+    app.complete_jobs_with(b"converted");
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let convert = |key: &str, format: &str| {
+        Request::post("/api/convert")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header("Idempotency-Key", key)
+            .body(Body::from(json!({ "asset_id": asset_id, "output_format": format }).to_string()))
+            .unwrap()
+    };
+
+    // Sent twice at once, only one runs: the other waits on the key and then
+    // either replays it or, still in flight, is told so
+    let (first, twin) = tokio::join!(app.send(convert("retry-1", "jpeg")), app.send(convert("retry-1", "jpeg")));
+    let ran = |r: &common::TestResponse| r.status == StatusCode::OK && !r.headers.contains_key("idempotent-replayed");
+    let (first, twin) = if ran(&first) { (first, twin) } else { (twin, first) };
+    assert_eq!(first.status, StatusCode::OK, "{}", first.body);
+    match twin.status {
+        StatusCode::OK => assert_eq!(twin.body["job_id"], first.body["job_id"]),
+        status => assert_eq!(status, StatusCode::CONFLICT, "{}", twin.body),
+    }
+
+    // The retry is answered from the first response, without using quota
+    let retried = app.send(convert("retry-1", "jpeg")).await;
+    assert_eq!(retried.status, StatusCode::OK, "{}", retried.body);
+    assert_eq!(retried.body["job_id"], first.body["job_id"]);
+    assert_eq!(retried.headers["idempotent-replayed"], "true");
+
+    let changed = app.send(convert("retry-1", "webp")).await;
+    assert_eq!(changed.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", changed.body);
+
+    // A new key is a new request, which the daily quota now refuses; failed
+    // requests aren't remembered, so it runs again when retried
+    assert_eq!(app.send(convert("retry-2", "jpeg")).await.status, StatusCode::TOO_MANY_REQUESTS);
+    let refused = app.send(convert("retry-2", "jpeg")).await;
+    assert_eq!(refused.body["error"]["code"], "QUOTA_EXCEEDED");
+    assert!(refused.headers.get("idempotent-replayed").is_none());
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_processing_requests_are_throttled_per_user() {
+    let app = TestApp::with_config(&[("REQUEST_RATE_LIMIT_BURST", "2"), ("REQUEST_RATE_LIMIT_PER_MINUTE", "1")]).await;
+    let token = app.register().await;
+    app.upload_png(&token).await;
+    let analyze = json!({ "asset_id": Uuid::new_v4() });
+    // The upload took one token, and a refused request still takes the other
+    assert_eq!(app.post_json("/api/analyze", Some(&token), analyze.clone()).await.status, StatusCode::NOT_FOUND);
+
+    let throttled = app.post_json("/api/analyze", Some(&token), analyze.clone()).await;
+    assert_eq!(throttled.status, StatusCode::TOO_MANY_REQUESTS, "{}", throttled.body);
+    assert_eq!(throttled.body["error"]["code"], "QUOTA_EXCEEDED");
+    let retry_after: u64 = throttled.headers[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after), "{}", retry_after);
+
+    // Reading isn't throttled, and other users have their own bucket
+    assert_eq!(app.get("/api/assets", &token).await.status, StatusCode::OK);
+    let other = app.register().await;
+    assert_eq!(app.post_json("/api/analyze", Some(&other), analyze).await.status, StatusCode::NOT_FOUND);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_daily_image_quota_is_enforced() {
+    let mut app = TestApp::with_config(&[("FREE_TIER_IMAGE_DAILY", "1"), ("FREE_TIER_CONCURRENT", "5")]).await;
+    app.complete_jobs_with(b"converted");
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let convert = json!({ "asset_id": asset_id, "output_format": "jpeg" });
+
+    let first = app.post_json("/api/convert", Some(&token), convert.clone()).await;
+    assert_eq!(first.status, StatusCode::OK, "{}", first.body);
+
+    let second = app.post_json("/api/convert", Some(&token), convert).await;
+    assert_eq!(second.status, StatusCode::TOO_MANY_REQUESTS, "{}", second.body);
+    assert_eq!(second.body["error"]["code"], "QUOTA_EXCEEDED");
+    assert_eq!(second.body["error"]["quota"]["images"]["used"], 1);
+    assert_eq!(second.body["error"]["quota"]["images"]["limit"], 1);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_simultaneous_submissions_never_exceed_the_daily_quota() {
+    let app = TestApp::with_config(&[
+        ("FREE_TIER_IMAGE_DAILY", "3"),
+        ("FREE_TIER_CONCURRENT", "100"),
+        ("REQUEST_RATE_LIMIT_BURST", "100"),
+    ])
+    .await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let convert = json!({ "asset_id": asset_id, "output_format": "jpeg" });
+
+    let submissions = (0..12).map(|_| app.post_json("/api/convert", Some(&token), convert.clone()));
+    let responses = futures_util::future::join_all(submissions).await;
+    let accepted = responses.iter().filter(|r| r.status == StatusCode::OK).count();
+    assert_eq!(accepted, 3);
+    for refused in responses.iter().filter(|r| r.status != StatusCode::OK) {
+        assert_eq!(refused.status, StatusCode::TOO_MANY_REQUESTS, "{}", refused.body);
+        assert_eq!(refused.body["error"]["quota"]["images"]["used"], 3);
+    }
+    let jobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM jobs").fetch_one(&app.state.db).await.unwrap();
+    assert_eq!(jobs, 3);
+    assert_eq!(app.get("/api/quota", &token).await.body["images"]["used"], 3);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_usage_statistics_for_users_and_admins() {
+    let mut app = TestApp::new().await;
+    app.complete_jobs_with(b"converted");
+    let token = app.register().await;
+    let other = app.register().await;
+    app.upload_png(&other).await;
+    let asset_id = app.upload_png(&token).await;
+
+    let queued = app
+        .post_json("/api/convert", Some(&token), json!({ "asset_id": asset_id, "output_format": "jpeg" }))
+        .await;
+    let job_uri = format!("/api/jobs/{}", queued.body["job_id"].as_str().unwrap());
+    let mut job = app.get(&job_uri, &token).await;
+    for _ in 0..50 {
+        if job.body["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = app.get(&job_uri, &token).await;
+    }
+    assert_eq!(job.body["status"], "completed", "{}", job.body);
+
+    // The last 30 days by default, one entry per UTC day they touch
+    let stats = app.get("/api/stats", &token).await;
+    assert_eq!(stats.status, StatusCode::OK, "{}", stats.body);
+    assert_eq!((stats.body["jobs"].as_i64(), stats.body["completed"].as_i64()), (Some(1), Some(1)));
+    assert_eq!(stats.body["uploads"], 1);
+    assert_eq!(stats.body["uploaded_bytes"].as_i64(), Some(common::png(16, 16).len() as i64));
+    assert_eq!(stats.body["output_bytes"], "converted".len());
+    assert_eq!(stats.body["by_type"][0]["job_type"], "convert");
+    let daily = stats.body["daily"].as_array().unwrap();
+    assert_eq!(daily.len(), 31);
+    assert_eq!(daily.last().unwrap()["date"], chrono::Utc::now().date_naive().to_string());
+    assert_eq!(daily.last().unwrap()["jobs"], 1);
+
+    let inverted = app.get("/api/stats?from=2026-02-01T00:00:00Z&to=2026-01-01T00:00:00Z", &token).await;
+    assert_eq!(inverted.status, StatusCode::BAD_REQUEST);
+    let too_long = app.get("/api/stats?from=2024-01-01T00:00:00Z&to=2026-01-01T00:00:00Z", &token).await;
+    assert_eq!(too_long.status, StatusCode::BAD_REQUEST);
+    let quiet = app.get("/api/stats?from=2020-01-01T00:00:00Z&to=2020-01-03T00:00:00Z", &token).await;
+    assert_eq!((quiet.body["jobs"].as_i64(), quiet.body["daily"].as_array().map(Vec::len)), (Some(0), Some(2)));
+
+    assert_eq!(app.get("/api/admin/stats", &token).await.status, StatusCode::FORBIDDEN);
+    sqlx::query("UPDATE users SET is_admin = TRUE").execute(&app.state.db).await.unwrap();
+    let everyone = app.get("/api/admin/stats", &token).await;
+    assert_eq!(everyone.status, StatusCode::OK, "{}", everyone.body);
+    assert_eq!((everyone.body["jobs"].as_i64(), everyone.body["uploads"].as_i64()), (Some(1), Some(2)));
+    assert!(everyone.body.get("users").is_none());
+    let by_user = app.get("/api/admin/stats?by_user=true", &token).await;
+    let users = by_user.body["users"].as_array().unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!((users[0]["jobs"].as_i64(), users[1]["jobs"].as_i64()), (Some(1), Some(0)));
+    assert_eq!(users[1]["uploads"], 1);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_organization_members_share_assets_and_a_pooled_quota() {
+    let mut app = TestApp::with_config(&[("ORG_IMAGE_DAILY", "1"), ("FREE_TIER_CONCURRENT", "5")]).await;
+    app.complete_jobs_with(b"converted");
+    let owner = app.register().await;
+    let (member_email, member) = app.register_unverified().await;
+    let outsider = app.register().await;
+
+    let created = app.post_json("/api/organizations", Some(&owner), json!({ "name": " Studio " })).await;
+    assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+    assert_eq!((&created.body["name"], &created.body["role"]), (&json!("Studio"), &json!("owner")));
+    let org_id = created.body["id"].as_str().unwrap().to_string();
+    let invites = format!("/api/organizations/{}/invites", org_id);
+    let invite = json!({ "email": member_email });
+    assert_eq!(app.post_json(&invites, Some(&outsider), invite.clone()).await.status, StatusCode::NOT_FOUND);
+    let invited = app.post_json(&invites, Some(&owner), invite.clone()).await;
+    assert_eq!(invited.status, StatusCode::OK, "{}", invited.body);
+
+    // Accepting needs the invited address confirmed
+    let invite_token = app.emailed_token(&member_email, "Join Studio").await;
+    let accept = json!({ "token": invite_token });
+    let unconfirmed = app.post_json("/api/organizations/invites/accept", Some(&member), accept.clone()).await;
+    assert_eq!(unconfirmed.status, StatusCode::FORBIDDEN, "{}", unconfirmed.body);
+    let verified = app.verify_email(&app.emailed_token(&member_email, VERIFICATION_SUBJECT).await).await;
+    assert_eq!(verified.status, StatusCode::OK, "{}", verified.body);
+    let stolen = app.post_json("/api/organizations/invites/accept", Some(&outsider), accept.clone()).await;
+    assert_eq!(stolen.status, StatusCode::BAD_REQUEST, "{}", stolen.body);
+    let joined = app.post_json("/api/organizations/invites/accept", Some(&member), accept).await;
+    assert_eq!(joined.status, StatusCode::OK, "{}", joined.body);
+    assert_eq!(joined.body["role"], "member");
+    assert_eq!(app.post_json(&invites, Some(&owner), invite.clone()).await.status, StatusCode::CONFLICT);
+    assert_eq!(app.post_json(&invites, Some(&member), invite).await.status, StatusCode::FORBIDDEN);
+    let members = app.get(&format!("/api/organizations/{}/members", org_id), &member).await;
+    assert_eq!(members.body.as_array().unwrap().len(), 2, "{}", members.body);
+
+    // The owner uploads for the organization with a switched token
+    let switch = json!({ "organization_id": org_id });
+    let switched = app.post_json("/api/auth/switch-organization", Some(&owner), switch.clone()).await;
+    assert_eq!(switched.status, StatusCode::OK, "{}", switched.body);
+    assert_eq!(switched.body["user"]["organization_id"], org_id.as_str());
+    let owner_for_org = switched.body["token"].as_str().unwrap().to_string();
+    let shared = app.upload_png(&owner_for_org).await;
+    let personal = app.upload_png(&owner).await;
+    let refused = app.post_json("/api/auth/switch-organization", Some(&outsider), switch).await;
+    assert_eq!(refused.status, StatusCode::NOT_FOUND);
+
+    // The member reaches it only while acting for the organization, and
+    // never the owner's personal uploads
+    let for_org = |method: &str, uri: String, org: &str, body: Option<serde_json::Value>| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", member))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-Organization-Id", org)
+            .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+            .unwrap()
+    };
+    let shared_uri = format!("/api/assets/{}", shared);
+    assert_eq!(app.get(&shared_uri, &member).await.status, StatusCode::FORBIDDEN);
+    let asset = app.send(for_org("GET", shared_uri, &org_id, None)).await;
+    assert_eq!(asset.status, StatusCode::OK, "{}", asset.body);
+    assert_eq!(asset.body["organization_id"], org_id.as_str());
+    let listed = app.send(for_org("GET", "/api/assets".to_string(), &org_id, None)).await;
+    assert_eq!(listed.body["total"], 1, "{}", listed.body);
+    let other = app.send(for_org("GET", format!("/api/assets/{}", personal), &org_id, None)).await;
+    assert_eq!(other.status, StatusCode::FORBIDDEN);
+    let not_member = app.send(for_org("GET", "/api/assets".to_string(), &Uuid::new_v4().to_string(), None)).await;
+    assert_eq!(not_member.status, StatusCode::FORBIDDEN);
+    let malformed = app.send(for_org("GET", "/api/assets".to_string(), "studio", None)).await;
+    assert_eq!(malformed.status, StatusCode::BAD_REQUEST);
+
+    // One image a day between them
+    let convert = json!({ "asset_id": shared, "output_format": "jpeg" });
+    let queued = app.send(for_org("POST", "/api/convert".to_string(), &org_id, Some(convert.clone()))).await;
+    assert_eq!(queued.status, StatusCode::OK, "{}", queued.body);
+    let job = app.get(&format!("/api/jobs/{}", queued.body["job_id"].as_str().unwrap()), &owner_for_org).await;
+    assert_eq!(job.status, StatusCode::OK, "{}", job.body);
+    let over = app.post_json("/api/convert", Some(&owner_for_org), convert).await;
+    assert_eq!(over.status, StatusCode::TOO_MANY_REQUESTS, "{}", over.body);
+    assert_eq!(over.body["error"]["quota"]["organization_id"], org_id.as_str());
+    // Personal work still counts against the owner's own limit
+    let personal_convert = json!({ "asset_id": personal, "output_format": "jpeg" });
+    assert_eq!(app.post_json("/api/convert", Some(&owner), personal_convert).await.status, StatusCode::OK);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_rs256_tokens_and_tokens_from_a_retired_secret_are_accepted() {
+    let fixture = |name: &str| format!("{}/tests/fixtures/jwt/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let (private, public) = (fixture("rsa_current.pem"), fixture("rsa_current.pub.pem"));
+    let app = TestApp::with_config(&[
+        ("JWT_ALGORITHM", "RS256"),
+        ("JWT_PRIVATE_KEY_PATH", &private),
+        ("JWT_PUBLIC_KEY_PATH", &public),
+        ("JWT_PREVIOUS_SECRETS", "retired-secret"),
+    ])
+    .await;
+    let (email, _) = app.register_unverified().await;
+    let login = app.post_json("/api/auth/login", None, json!({ "email": email, "password": "password1" })).await;
+    let token = login.body["token"].as_str().unwrap();
+    let header = jsonwebtoken::decode_header(token).unwrap();
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::RS256);
+    assert!(header.kid.is_some());
+    assert_eq!(app.get("/api/quota", token).await.status, StatusCode::OK);
+
+    // Issued under HS256 before the move, without a key id
+    let claims = json!({
+        "sub": login.body["user"]["id"],
+        "email": email,
+        "tier": "free",
+        "iat": chrono::Utc::now().timestamp(),
+        "exp": chrono::Utc::now().timestamp() + 60,
+    });
+    let sign = |secret: &str| {
+        let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+        jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+    };
+    assert_eq!(app.get("/api/quota", &sign("retired-secret")).await.status, StatusCode::OK);
+    assert_eq!(app.get("/api/quota", &sign("integration-test-secret")).await.status, StatusCode::UNAUTHORIZED);
+    app.finish().await;
+}
+
+#[tokio::test]
+async fn test_errors_are_written_in_the_requested_language() {
+    let app = TestApp::with_config(&[("FREE_TIER_IMAGE_DAILY", "1"), ("FREE_TIER_CONCURRENT", "5")]).await;
+    let token = app.register().await;
+    let asset_id = app.upload_png(&token).await;
+    let convert = |language: &str| {
+        Request::post("/api/convert")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::ACCEPT_LANGUAGE, language)
+            .body(Body::from(json!({ "asset_id": asset_id, "output_format": "jpg" }).to_string()))
+            .unwrap()
+    };
+    assert_eq!(app.send(convert("es")).await.status, StatusCode::OK);
+
+    let es = app.send(convert("es-MX, en;q=0.8")).await;
+    assert_eq!(es.status, StatusCode::TOO_MANY_REQUESTS, "{}", es.body);
+    assert_eq!(es.headers[header::CONTENT_LANGUAGE], "es");
+    assert_eq!(es.body["error"]["code"], "QUOTA_EXCEEDED");
+    assert_eq!(es.body["error"]["reason"], "daily_quota_exceeded");
+    assert_eq!(es.body["error"]["details"], json!({ "used": 1, "limit": 1 }));
+    assert!(es.body["error"]["message"].as_str().unwrap().starts_with("Cuota diaria superada (1/1)"));
+
+    // Languages without a catalog fall back to English; nothing else changes
+    let de = app.send(convert("de")).await;
+    assert_eq!(de.headers[header::CONTENT_LANGUAGE], "en");
+    assert!(de.body["error"]["message"].as_str().unwrap().starts_with("Daily quota exceeded (1/1)"));
+    for key in ["code", "reason", "details", "quota"] {
+        assert_eq!(de.body["error"][key], es.body["error"][key]);
+    }
+    app.finish().await;
+}
@@ -0,0 +1,306 @@
+// backend/tests/common/mod.rs
+// Harness for the HTTP integration tests: the real router over a throwaway
+// Postgres schema, in-memory storage, a local job queue and a stand-in
+// malware scanner
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::connect_info::MockConnectInfo;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::Router;
+use media_processor_server::config::Config;
+use media_processor_server::services::ffmpeg::Ffmpeg;
+use media_processor_server::services::scan::{ScanError, ScanInput, ScanVerdict};
+use media_processor_server::services::{JobMessage, MemoryMailer, MemoryStorage, Queue, Scanner};
+use media_processor_server::{build_router, db, AppState, Resources};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use serde_json::{json, Value};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use tokio::sync::mpsc::Receiver;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+/// Subjects of the emails the app sends
+pub const VERIFICATION_SUBJECT: &str = "Confirm your";
+pub const PASSWORD_RESET_SUBJECT: &str = "Reset your";
+
+/// The standard antivirus test file, harmless but flagged by every scanner
+pub const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// Flags content holding `EICAR`, as clamd would
+pub struct EicarScanner;
+
+#[axum::async_trait]
+impl Scanner for EicarScanner {
+    async fn scan(&self, input: ScanInput<'_>) -> Result<ScanVerdict, ScanError> {
+        let content = match input {
+            ScanInput::Bytes(bytes) => bytes.to_vec(),
+            ScanInput::File(path) => tokio::fs::read(path).await?,
+        };
+        if content.windows(EICAR.len()).any(|w| w == EICAR) {
+            Ok(ScanVerdict::Infected { signature: "Eicar-Test-Signature".to_string() })
+        } else {
+            Ok(ScanVerdict::Clean)
+        }
+    }
+}
+
+pub struct TestApp {
+    pub state: AppState,
+    /// Every email the app has sent
+    pub mailer: Arc<MemoryMailer>,
+    router: Router,
+    jobs: Option<Receiver<JobMessage>>,
+    schema: String,
+    temp_dir: std::path::PathBuf,
+}
+
+/// A response with its body read
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+    /// The body as sent, for responses that aren't text
+    pub bytes: axum::body::Bytes,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::with_config(&[]).await
+    }
+
+    /// An app whose configuration has `overrides` on top of the test defaults
+    pub async fn with_config(overrides: &[(&str, &str)]) -> Self {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for db-tests");
+        let temp_dir = std::env::temp_dir().join(format!("mf_api_test_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+
+        let temp_dir_str = temp_dir.to_string_lossy().to_string();
+        let mut vars: HashMap<&str, &str> = HashMap::from([
+            ("DATABASE_URL", database_url.as_str()),
+            ("JWT_SECRET", "integration-test-secret"),
+            // Empty disables Redis: jobs go through the in-process channel
+            ("REDIS_URL", ""),
+            ("TEMP_DIR", temp_dir_str.as_str()),
+        ]);
+        vars.extend(overrides.iter().copied());
+        let config = Config::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+
+        // Every app gets its own schema, so counts and quotas start from zero
+        let schema = format!("test_{}", Uuid::new_v4().simple());
+        let admin = PgPoolOptions::new().max_connections(1).connect(&database_url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&admin).await.unwrap();
+        admin.close().await;
+        let options = PgConnectOptions::from_str(&database_url)
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        let pool = PgPoolOptions::new().max_connections(5).connect_with(options).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let (queue, jobs) = Queue::new(16, None).await;
+        let mailer = Arc::new(MemoryMailer::new());
+        let resources = Resources {
+            db: pool,
+            storage: Arc::new(MemoryStorage::new()),
+            queue: Arc::new(queue),
+            mailer: mailer.clone(),
+            scanner: Arc::new(EicarScanner),
+            ffmpeg: Arc::new(Ffmpeg::detect("ffmpeg").await),
+        };
+        // A recorder that isn't installed globally, so apps don't clash
+        let state = AppState::new(config, resources, PrometheusBuilder::new().build_recorder().handle());
+        let router = build_router(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+
+        Self {
+            state,
+            mailer,
+            router,
+            jobs: Some(jobs),
+            schema,
+            temp_dir,
+        }
+    }
+
+    /// Throw away the wake-ups sent so far, as a crash between queueing a job
+    /// and a worker hearing of it would
+    pub fn lose_wake_ups(&mut self) {
+        let jobs = self.jobs.as_mut().expect("jobs are already being consumed");
+        while jobs.try_recv().is_ok() {}
+    }
+
+    /// Stand in for the worker: every queued job completes at once, its
+    /// result being `output`
+    pub fn complete_jobs_with(&mut self, output: &'static [u8]) {
+        self.complete_jobs_with_outputs(vec![("result.bin", output)]);
+    }
+
+    /// Like `complete_jobs_with`, every job producing all of `outputs` as
+    /// `(filename, content)`, the first being the primary one. The job's
+    /// result metadata describes the primary one as the worker would.
+    pub fn complete_jobs_with_outputs(&mut self, outputs: Vec<(&'static str, &'static [u8])>) {
+        let mut jobs = self.jobs.take().expect("jobs are already being consumed");
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            while let Some(message) = jobs.recv().await {
+                let job_id = Uuid::parse_str(&message.job_id).unwrap();
+                let job = db::Job::find_by_id(&state.db, job_id).await.unwrap().unwrap();
+                let mut saved = Vec::new();
+                for (index, (filename, content)) in outputs.iter().enumerate() {
+                    let location = state.storage.save_bytes(content, job.user_id, filename).await.unwrap();
+                    saved.push(db::JobOutput {
+                        job_id,
+                        output_index: index as i32,
+                        location: location.to_string(),
+                        filename: filename.to_string(),
+                        size_bytes: content.len() as i64,
+                        content_type: "application/octet-stream".to_string(),
+                        etag: format!("etag{}", index),
+                    });
+                }
+                let (filename, content) = outputs[0];
+                let dimensions = image::load_from_memory(content).ok().map(|img| (img.width(), img.height()));
+                let result = db::JobResult {
+                    location: saved[0].location.clone(),
+                    width: dimensions.map(|(width, _)| width),
+                    height: dimensions.map(|(_, height)| height),
+                    size_bytes: content.len() as i64,
+                    format: filename.rsplit_once('.').map(|(_, ext)| ext.to_string()).unwrap_or_default(),
+                    duration_ms: 0,
+                };
+                db::Job::complete(&state.db, job_id, &result, &saved, chrono::Duration::hours(1)).await.unwrap();
+            }
+        });
+    }
+
+    pub async fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into()))
+        };
+        TestResponse { status, headers, body, bytes }
+    }
+
+    pub async fn get(&self, uri: &str, token: &str) -> TestResponse {
+        let request = Request::get(uri).header(header::AUTHORIZATION, format!("Bearer {}", token));
+        self.send(request.body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn post_json(&self, uri: &str, token: Option<&str>, body: Value) -> TestResponse {
+        let mut request = Request::post(uri).header(header::CONTENT_TYPE, "application/json");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        self.send(request.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    /// Send one chunk of a resumable upload
+    pub async fn patch_chunk(&self, uri: &str, token: &str, offset: u64, bytes: &[u8]) -> TestResponse {
+        let request = Request::patch(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header("Upload-Offset", offset.to_string());
+        self.send(request.body(Body::from(bytes.to_vec())).unwrap()).await
+    }
+
+    pub async fn delete_json(&self, uri: &str, token: &str, body: Value) -> TestResponse {
+        let request = Request::delete(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token));
+        self.send(request.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    /// Register a fresh account, confirm its email and return its token
+    pub async fn register(&self) -> String {
+        let (email, token) = self.register_unverified().await;
+        let verified = self.verify_email(&self.emailed_token(&email, VERIFICATION_SUBJECT).await).await;
+        assert_eq!(verified.status, StatusCode::OK, "{}", verified.body);
+        token
+    }
+
+    /// Register a fresh account, leaving the verification email unanswered.
+    /// Returns the address and the token.
+    pub async fn register_unverified(&self) -> (String, String) {
+        let email = format!("{}@api.test", Uuid::new_v4());
+        let response = self
+            .post_json("/api/auth/register", None, json!({ "email": email, "password": "password1" }))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        (email, response.body["token"].as_str().unwrap().to_string())
+    }
+
+    /// The token from the link in the latest email to `email` whose subject
+    /// starts with `subject`. Mail goes out in the background, so this waits
+    /// briefly for it.
+    pub async fn emailed_token(&self, email: &str, subject: &str) -> String {
+        for _ in 0..50 {
+            let sent = self.mailer.sent();
+            if let Some(message) = sent.iter().rev().find(|m| m.to == email && m.subject.starts_with(subject)) {
+                let link = message.body.split_whitespace().find(|w| w.contains("token=")).unwrap();
+                return link.rsplit("token=").next().unwrap().to_string();
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("no '{}' email was sent to {}", subject, email);
+    }
+
+    /// Follow a verification link
+    pub async fn verify_email(&self, token: &str) -> TestResponse {
+        let request = Request::get(format!("/api/auth/verify?token={}", token));
+        self.send(request.body(Body::empty()).unwrap()).await
+    }
+
+    /// Upload `bytes` as `filename` through `/api/upload`
+    pub async fn upload(&self, token: &str, filename: &str, bytes: &[u8]) -> TestResponse {
+        let boundary = "mediaforge-test-boundary";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let request = Request::post("/api/upload")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .body(Body::from(body))
+            .unwrap();
+        self.send(request).await
+    }
+
+    /// Upload a small PNG and return the asset ID
+    pub async fn upload_png(&self, token: &str) -> String {
+        let response = self.upload(token, "photo.png", &png(16, 16)).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        response.body["asset_id"].as_str().unwrap().to_string()
+    }
+
+    /// Drop the schema and temp files. Skipped when a test fails, leaving
+    /// them to look at.
+    pub async fn finish(self) {
+        let database_url = std::env::var("DATABASE_URL").unwrap();
+        self.state.db.close().await;
+        let admin = PgPoolOptions::new().max_connections(1).connect(&database_url).await.unwrap();
+        sqlx::query(&format!("DROP SCHEMA {} CASCADE", self.schema))
+            .execute(&admin)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&self.temp_dir).ok();
+    }
+}
+
+/// A `width` by `height` PNG
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+    bytes.into_inner()
+}